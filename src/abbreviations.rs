@@ -0,0 +1,90 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use log::warn;
+
+// Expansions available before the user configures any
+const DEFAULT_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("teh", "the"),
+    ("adn", "and"),
+    ("recieve", "receive"),
+    ("seperate", "separate"),
+    ("impd", "impl Default for"),
+];
+
+/// Words that expand as they are typed, once followed by Space or Tab. They are shared by
+/// every tab and stored in `abbreviations.toml` in the config directory, one expansion per
+/// abbreviation; an empty expansion turns a default off:
+///
+/// ```toml
+/// teh = "the"
+/// fnm = "fn main() {"
+/// adn = ""
+/// ```
+#[derive(Debug, Clone)]
+pub struct Abbreviations {
+    expansions: HashMap<String, String>,
+}
+
+impl Default for Abbreviations {
+    fn default() -> Self {
+        let expansions = DEFAULT_ABBREVIATIONS
+            .iter()
+            .map(|(abbreviation, expansion)| (abbreviation.to_string(), expansion.to_string()))
+            .collect();
+        Self { expansions }
+    }
+}
+
+impl Abbreviations {
+    fn abbreviations_file() -> PathBuf {
+        glib::user_config_dir().join("rustedit").join("abbreviations.toml")
+    }
+
+    /// The defaults, overridden by the config file where it has an entry
+    pub fn load() -> Self {
+        let path = Self::abbreviations_file();
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        let entries: BTreeMap<String, String> = match toml::from_str(&content) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Ignoring unreadable abbreviations {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+
+        let mut abbreviations = Self::default();
+        for (abbreviation, expansion) in entries {
+            if !abbreviation.chars().all(is_word_char) {
+                warn!("Ignoring abbreviation '{}': it may only hold letters, digits and '_'", abbreviation);
+                continue;
+            }
+            if expansion.is_empty() {
+                abbreviations.expansions.remove(&abbreviation);
+            } else {
+                abbreviations.expansions.insert(abbreviation, expansion);
+            }
+        }
+        abbreviations
+    }
+
+    /// The expansion of the word `text` ends with, and that word's length in characters.
+    /// Only whole words count, so `steh` does not expand the `teh` at its end.
+    pub fn expansion_at_end(&self, text: &str) -> Option<(&str, usize)> {
+        let word_start = text
+            .char_indices()
+            .rev()
+            .take_while(|(_, ch)| is_word_char(*ch))
+            .last()
+            .map(|(index, _)| index)?;
+        let word = &text[word_start..];
+        let expansion = self.expansions.get(word)?;
+        Some((expansion, word.chars().count()))
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+/// Which pair of brackets a problem is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BracketKind {
+    Parenthesis,
+    Brace,
+    Square,
+}
+
+impl BracketKind {
+    fn of(ch: char) -> Option<(BracketKind, bool)> {
+        match ch {
+            '(' => Some((BracketKind::Parenthesis, true)),
+            ')' => Some((BracketKind::Parenthesis, false)),
+            '{' => Some((BracketKind::Brace, true)),
+            '}' => Some((BracketKind::Brace, false)),
+            '[' => Some((BracketKind::Square, true)),
+            ']' => Some((BracketKind::Square, false)),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BracketKind::Parenthesis => "parenthesis",
+            BracketKind::Brace => "brace",
+            BracketKind::Square => "square bracket",
+        }
+    }
+}
+
+/// An opening bracket that is never closed, or a closing one with nothing to close
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BracketProblem {
+    /// 0-based line
+    pub line: usize,
+    /// 0-based column, in characters
+    pub column: usize,
+    pub bracket: char,
+    pub kind: BracketKind,
+    pub unclosed: bool,
+}
+
+impl BracketProblem {
+    pub fn description(&self) -> String {
+        let what = if self.unclosed { "Unclosed" } else { "Unmatched closing" };
+        format!("{} {} '{}'", what, self.kind.label(), self.bracket)
+    }
+}
+
+/// The character closing a pair opened by `open`: a bracket's partner, or the same quote
+pub fn closing_partner(open: char) -> Option<char> {
+    match open {
+        '(' => Some(')'),
+        '{' => Some('}'),
+        '[' => Some(']'),
+        '"' | '\'' | '`' => Some(open),
+        _ => None,
+    }
+}
+
+/// Find unbalanced brackets in source text, ignoring any inside strings,
+/// character literals and comments. A closing bracket that skips over open
+/// ones (as in `( [ )`) closes its partner and reports the skipped ones.
+pub fn find_unmatched_brackets(text: &str) -> Vec<BracketProblem> {
+    scan(text).1
+}
+
+/// Where each bracket of a text is closed, by (line, column) as in `BracketProblem`
+#[derive(Debug, Clone, Default)]
+pub struct BracketPairs {
+    partners: HashMap<Position, Option<Position>>,
+    // Matched pairs as (opening, closing), in text order
+    pairs: Vec<(Position, Position)>,
+}
+
+impl BracketPairs {
+    /// Scan `text` the way `find_unmatched_brackets` does
+    pub fn new(text: &str) -> Self {
+        let (pairs, problems) = scan(text);
+        let mut partners = HashMap::new();
+        for &(open, close) in &pairs {
+            partners.insert(open, Some(close));
+            partners.insert(close, Some(open));
+        }
+        for problem in problems {
+            partners.insert((problem.line, problem.column), None);
+        }
+        Self { partners, pairs }
+    }
+
+    /// The partner of the bracket at `position`: `Some(None)` when it has none, and `None`
+    /// when there is no bracket there outside strings and comments
+    pub fn partner(&self, position: (usize, usize)) -> Option<Option<(usize, usize)>> {
+        self.partners.get(&position).copied()
+    }
+
+    /// The matched pairs whose brackets are both outside the characters from `start` up to
+    /// `end`, innermost first
+    pub fn enclosing(&self, start: (usize, usize), end: (usize, usize)) -> Vec<((usize, usize), (usize, usize))> {
+        let mut enclosing: Vec<_> = self.pairs.iter().copied().filter(|&(open, close)| open < start && close >= end).collect();
+        enclosing.sort_by_key(|&(open, _)| std::cmp::Reverse(open));
+        enclosing
+    }
+}
+
+// Line and column of a bracket
+type Position = (usize, usize);
+
+// Matched pairs as (opening, closing) positions, and the problems, both in text order
+fn scan(text: &str) -> (Vec<(Position, Position)>, Vec<BracketProblem>) {
+    let mut pairs = Vec::new();
+    let mut problems = Vec::new();
+    let mut open: Vec<(usize, usize, char, BracketKind)> = Vec::new();
+
+    for (line, column, ch) in CodeChars::new(text) {
+        let Some((kind, opening)) = BracketKind::of(ch) else {
+            continue;
+        };
+        if opening {
+            open.push((line, column, ch, kind));
+            continue;
+        }
+
+        match open.iter().rposition(|&(_, _, _, open_kind)| open_kind == kind) {
+            Some(partner) => {
+                // Drop the partner along with any brackets opened after it
+                let mut dropped = open.drain(partner..);
+                if let Some((open_line, open_column, _, _)) = dropped.next() {
+                    pairs.push(((open_line, open_column), (line, column)));
+                }
+                for (line, column, bracket, kind) in dropped {
+                    problems.push(BracketProblem { line, column, bracket, kind, unclosed: true });
+                }
+            }
+            None => problems.push(BracketProblem { line, column, bracket: ch, kind, unclosed: false }),
+        }
+    }
+
+    for (line, column, bracket, kind) in open {
+        problems.push(BracketProblem { line, column, bracket, kind, unclosed: true });
+    }
+    pairs.sort();
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    (pairs, problems)
+}
+
+/// The characters of `text` that are code rather than literal or comment,
+/// with their 0-based line and character column
+struct CodeChars<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> CodeChars<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { chars: text.chars().peekable(), line: 0, column: 0 }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+
+    fn skip_line_comment(&mut self) {
+        while self.chars.peek().is_some_and(|&ch| ch != '\n') {
+            self.bump();
+        }
+    }
+
+    // Block comments nest in Rust
+    fn skip_block_comment(&mut self) {
+        let mut depth = 1;
+        while let Some(ch) = self.bump() {
+            match ch {
+                '*' if self.chars.peek() == Some(&'/') => {
+                    self.bump();
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                '/' if self.chars.peek() == Some(&'*') => {
+                    self.bump();
+                    depth += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn skip_string(&mut self) {
+        while let Some(ch) = self.bump() {
+            match ch {
+                '\\' => {
+                    self.bump();
+                }
+                '"' => return,
+                _ => {}
+            }
+        }
+    }
+
+    // After `r`: `#`s then a quote, closed by a quote and as many `#`s
+    fn skip_raw_string(&mut self) -> bool {
+        let mut lookahead = self.chars.clone();
+        let mut hashes = 0;
+        while lookahead.peek() == Some(&'#') {
+            lookahead.next();
+            hashes += 1;
+        }
+        if lookahead.peek() != Some(&'"') {
+            return false;
+        }
+        for _ in 0..=hashes {
+            self.bump();
+        }
+
+        while let Some(ch) = self.bump() {
+            if ch == '"' {
+                let mut closing = 0;
+                while closing < hashes && self.chars.peek() == Some(&'#') {
+                    self.bump();
+                    closing += 1;
+                }
+                if closing == hashes {
+                    return true;
+                }
+            }
+        }
+        true
+    }
+
+    // Char literals like '{' or '\n'; lifetimes like 'a are left alone
+    fn skip_char_literal(&mut self) {
+        let mut lookahead = self.chars.clone();
+        let length = match lookahead.next() {
+            Some('\\') => {
+                // Escapes: '\n', '\'', '\u{7FFF}'
+                let mut length = 1;
+                loop {
+                    match lookahead.next() {
+                        Some('\'') if length > 1 => break Some(length + 1),
+                        Some('\n') | None => break None,
+                        Some(_) => length += 1,
+                    }
+                }
+            }
+            Some(_) if lookahead.next() == Some('\'') => Some(2),
+            _ => None,
+        };
+        for _ in 0..length.unwrap_or(0) {
+            self.bump();
+        }
+    }
+}
+
+impl Iterator for CodeChars<'_> {
+    type Item = (usize, usize, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, column) = (self.line, self.column);
+            let ch = self.bump()?;
+            match ch {
+                '/' if self.chars.peek() == Some(&'/') => self.skip_line_comment(),
+                '/' if self.chars.peek() == Some(&'*') => {
+                    self.bump();
+                    self.skip_block_comment();
+                }
+                '"' => self.skip_string(),
+                'r' if self.skip_raw_string() => {}
+                '\'' => self.skip_char_literal(),
+                _ => return Some((line, column, ch)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_nested_brackets() {
+        let pairs = BracketPairs::new("fn f(a: [u8; 2]) { g(h()) }");
+        assert_eq!(pairs.partner((0, 4)), Some(Some((0, 15))));
+        assert_eq!(pairs.partner((0, 8)), Some(Some((0, 14))));
+        assert_eq!(pairs.partner((0, 22)), Some(Some((0, 23))));
+        assert_eq!(pairs.partner((0, 24)), Some(Some((0, 20))));
+        assert_eq!(pairs.partner((0, 26)), Some(Some((0, 17))));
+        assert_eq!(pairs.partner((0, 0)), None);
+    }
+
+    #[test]
+    fn ignores_brackets_in_strings_and_comments() {
+        let pairs = BracketPairs::new("let s = \"(\"; // )\n/* [ */ '{'");
+        assert_eq!(pairs.partner((0, 9)), None);
+        assert_eq!(pairs.partner((0, 16)), None);
+        assert_eq!(pairs.partner((1, 3)), None);
+        assert_eq!(pairs.partner((1, 9)), None);
+    }
+
+    #[test]
+    fn brackets_without_partners() {
+        let pairs = BracketPairs::new("a)\n(b");
+        assert_eq!(pairs.partner((0, 1)), Some(None));
+        assert_eq!(pairs.partner((1, 0)), Some(None));
+    }
+}
@@ -0,0 +1,80 @@
+/// Comment out `lines`, or uncomment them if every line with text is already commented.
+/// Each line gets `open` at the indentation of the least indented line, followed by a
+/// space, and `close` at its end for languages that only have block comments.
+pub fn toggle_line_comments(lines: &str, open: &str, close: Option<&str>) -> String {
+    let is_blank = |line: &str| line.trim().is_empty();
+    let is_commented = |line: &str| {
+        let line = line.trim_start_matches([' ', '\t']).trim_end();
+        line.starts_with(open) && close.is_none_or(|close| line.ends_with(close))
+    };
+    let commented = lines.split('\n').filter(|line| !is_blank(line)).all(is_commented);
+    if commented {
+        return lines
+            .split('\n')
+            .map(|line| if is_blank(line) { line.to_string() } else { uncomment_line(line, open, close) })
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+
+    let indent = lines
+        .split('\n')
+        .filter(|line| !is_blank(line))
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .split('\n')
+        .map(|line| {
+            if is_blank(line) {
+                return line.to_string();
+            }
+            let (indentation, rest) = line.split_at(indent);
+            match close {
+                Some(close) => format!("{}{} {} {}", indentation, open, rest.trim_end(), close),
+                None => format!("{}{} {}", indentation, open, rest),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Wrap `text` in one block comment, or unwrap it if it already is one. Whitespace around
+/// `text` stays outside the comment.
+pub fn toggle_block_comment(text: &str, open: &str, close: &str) -> String {
+    let start = text.len() - text.trim_start().len();
+    let end = text.trim_end().len();
+    let (before, inner, after) = (&text[..start], &text[start..end], &text[end..]);
+    if inner.is_empty() {
+        return format!("{}{} {}{}", before, open, close, after);
+    }
+
+    let unwrapped = inner
+        .strip_prefix(open)
+        .and_then(|inner| inner.strip_suffix(close))
+        .filter(|_| inner.len() >= open.len() + close.len());
+    match unwrapped {
+        Some(content) => {
+            let content = content.strip_prefix(' ').unwrap_or(content);
+            let content = content.strip_suffix(' ').unwrap_or(content);
+            format!("{}{}{}", before, content, after)
+        }
+        None => format!("{}{} {} {}{}", before, open, inner, close, after),
+    }
+}
+
+// Remove the comment markers of one line, with the space after `open` and before `close`
+fn uncomment_line(line: &str, open: &str, close: Option<&str>) -> String {
+    let rest = line.trim_start_matches([' ', '\t']);
+    let indentation = &line[..line.len() - rest.len()];
+    let rest = rest.trim_end();
+    let rest = &rest[open.len()..];
+    let rest = rest.strip_prefix(' ').unwrap_or(rest);
+    let rest = match close {
+        Some(close) => {
+            let rest = rest.strip_suffix(close).unwrap_or(rest);
+            rest.strip_suffix(' ').unwrap_or(rest)
+        }
+        None => rest,
+    };
+    format!("{}{}", indentation, rest)
+}
@@ -0,0 +1,315 @@
+use gtk::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
+use std::rc::Rc;
+
+// Most rows the popup shows without scrolling
+const MAX_VISIBLE_ROWS: i32 = 10;
+// Width of the documentation beside the list, in pixels
+const DOCUMENTATION_WIDTH: i32 = 360;
+
+/// What a completion is, shown as a colored letter before its label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompletionKind {
+    /// Paths, dictionary words and anything else that needs no badge
+    #[default]
+    Plain,
+    Function,
+    Method,
+    Field,
+    Variable,
+    Constant,
+    Type,
+    Module,
+    Keyword,
+    Snippet,
+    /// A word found elsewhere in the buffer
+    Word,
+}
+
+impl CompletionKind {
+    /// The kind of a `CompletionItemKind` number of the language server protocol
+    pub fn from_lsp(kind: u64) -> Self {
+        match kind {
+            2 => CompletionKind::Method,
+            3 | 4 => CompletionKind::Function,
+            5 | 10 => CompletionKind::Field,
+            6 | 18 => CompletionKind::Variable,
+            12 | 20 | 21 => CompletionKind::Constant,
+            7 | 8 | 13 | 22 | 25 => CompletionKind::Type,
+            9 => CompletionKind::Module,
+            14 => CompletionKind::Keyword,
+            15 => CompletionKind::Snippet,
+            _ => CompletionKind::Plain,
+        }
+    }
+
+    // Letter of the badge and its style class
+    fn badge(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            CompletionKind::Plain => None,
+            CompletionKind::Function => Some(("f", "function")),
+            CompletionKind::Method => Some(("m", "function")),
+            CompletionKind::Field => Some(("p", "field")),
+            CompletionKind::Variable => Some(("v", "variable")),
+            CompletionKind::Constant => Some(("c", "variable")),
+            CompletionKind::Type => Some(("T", "type")),
+            CompletionKind::Module => Some(("M", "type")),
+            CompletionKind::Keyword => Some(("k", "keyword")),
+            CompletionKind::Snippet => Some(("s", "keyword")),
+            CompletionKind::Word => Some(("w", "word")),
+        }
+    }
+}
+
+/// One completion candidate: `label` is listed, `insert_text` replaces the typed prefix
+#[derive(Default)]
+pub struct CompletionItem {
+    pub label: String,
+    pub detail: String,
+    pub insert_text: String,
+    pub kind: CompletionKind,
+    /// Shown beside the list while the item is selected
+    pub documentation: String,
+    /// Characters of the buffer `insert_text` replaces, when that is not the typed prefix
+    pub replace: Option<Range<usize>>,
+    /// Changes elsewhere that come with the item, such as an import, as character ranges
+    /// of the buffer and their new text
+    pub extra_edits: Vec<(Range<usize>, String)>,
+}
+
+/// A completion list shown under the cursor while focus stays in the text view.
+/// Up/Down pick a row, Tab or Enter accept it and Escape dismisses the popup. Enter can be
+/// left to the text view, for lists shown while typing prose.
+pub struct CompletionPopup {
+    text_view: gtk::TextView,
+    popover: gtk::Popover,
+    scrolled: gtk::ScrolledWindow,
+    list: gtk::ListBox,
+    documentation: gtk::Label,
+    documentation_scrolled: gtk::ScrolledWindow,
+    items: RefCell<Vec<CompletionItem>>,
+    // Characters before the cursor that the accepted item replaces
+    replace_chars: Cell<usize>,
+    return_accepts: Cell<bool>,
+}
+
+impl CompletionPopup {
+    pub fn new(text_view: &gtk::TextView) -> Rc<Self> {
+        let popover = gtk::Popover::new();
+        popover.set_parent(text_view);
+        popover.set_autohide(false);
+        popover.set_has_arrow(false);
+        popover.set_position(gtk::PositionType::Bottom);
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::Single);
+        list.set_can_focus(false);
+
+        let scrolled = gtk::ScrolledWindow::new();
+        scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        scrolled.set_propagate_natural_height(true);
+        scrolled.set_propagate_natural_width(true);
+        scrolled.set_child(Some(&list));
+
+        let documentation = gtk::Label::new(None);
+        documentation.set_wrap(true);
+        documentation.set_wrap_mode(pango::WrapMode::WordChar);
+        documentation.set_xalign(0.0);
+        documentation.set_yalign(0.0);
+        documentation.set_selectable(false);
+        documentation.set_css_classes(&["completion-documentation"]);
+        let documentation_scrolled = gtk::ScrolledWindow::new();
+        documentation_scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        documentation_scrolled.set_propagate_natural_height(true);
+        documentation_scrolled.set_min_content_width(DOCUMENTATION_WIDTH);
+        documentation_scrolled.set_max_content_width(DOCUMENTATION_WIDTH);
+        documentation_scrolled.set_child(Some(&documentation));
+        documentation_scrolled.set_visible(false);
+
+        let content = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        content.append(&scrolled);
+        content.append(&documentation_scrolled);
+        popover.set_child(Some(&content));
+
+        let popup = Rc::new(Self {
+            text_view: text_view.clone(),
+            popover,
+            scrolled,
+            list,
+            documentation,
+            documentation_scrolled,
+            items: RefCell::new(Vec::new()),
+            replace_chars: Cell::new(0),
+            return_accepts: Cell::new(true),
+        });
+
+        let popup_ref = Rc::downgrade(&popup);
+        popup.list.connect_row_selected(move |_, row| {
+            if let Some(popup) = popup_ref.upgrade() {
+                popup.show_documentation(row.map(|row| row.index() as usize));
+            }
+        });
+
+        let popup_ref = Rc::downgrade(&popup);
+        popup.list.connect_row_activated(move |_, row| {
+            if let Some(popup) = popup_ref.upgrade() {
+                popup.accept(row.index() as usize);
+            }
+        });
+
+        // Capture phase, so the text view doesn't move its cursor on Up/Down first
+        let key_controller = gtk::EventControllerKey::new();
+        key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+        let popup_ref = Rc::downgrade(&popup);
+        key_controller.connect_key_pressed(move |_, key, _, _| {
+            match popup_ref.upgrade() {
+                Some(popup) if popup.is_visible() => popup.handle_key(key),
+                _ => glib::Propagation::Proceed,
+            }
+        });
+        text_view.add_controller(key_controller);
+
+        popup
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.popover.is_visible()
+    }
+
+    /// Show `items` at the cursor, replacing the list shown so far
+    pub fn show(&self, items: Vec<CompletionItem>, replace_chars: usize) {
+        if items.is_empty() {
+            self.hide();
+            return;
+        }
+
+        while let Some(child) = self.list.first_child() {
+            self.list.remove(&child);
+        }
+        *self.items.borrow_mut() = items;
+        self.replace_chars.set(replace_chars);
+        for item in self.items.borrow().iter() {
+            let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+            if let Some((letter, class)) = item.kind.badge() {
+                let badge = gtk::Label::new(Some(letter));
+                badge.set_css_classes(&["completion-kind", class]);
+                row_box.append(&badge);
+            }
+            let label = gtk::Label::new(Some(&item.label));
+            label.set_halign(gtk::Align::Start);
+            label.set_hexpand(true);
+            // Signatures can be long
+            let detail = gtk::Label::new(Some(&item.detail));
+            detail.set_css_classes(&["dim-label", "shortcut-label"]);
+            detail.set_max_width_chars(40);
+            detail.set_ellipsize(pango::EllipsizeMode::End);
+            row_box.append(&label);
+            row_box.append(&detail);
+            self.list.append(&row_box);
+        }
+        if let Some(first) = self.list.row_at_index(0) {
+            self.list.select_row(Some(&first));
+        }
+
+        // Cap the height at MAX_VISIBLE_ROWS; longer lists scroll
+        let row_height = self.list.row_at_index(0)
+            .map(|row| row.preferred_size().1.height())
+            .filter(|height| *height > 0)
+            .unwrap_or(24);
+        self.scrolled.set_max_content_height(row_height * MAX_VISIBLE_ROWS);
+        self.scrolled.vadjustment().set_value(0.0);
+
+        let buffer = self.text_view.buffer();
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let location = self.text_view.iter_location(&cursor);
+        let (x, y) = self.text_view.buffer_to_window_coords(gtk::TextWindowType::Widget, location.x(), location.y());
+        self.popover.set_pointing_to(Some(&gtk::gdk::Rectangle::new(x, y, 1, location.height())));
+        self.popover.popup();
+    }
+
+    /// Whether Enter accepts the selected item, besides Tab
+    pub fn set_return_accepts(&self, return_accepts: bool) {
+        self.return_accepts.set(return_accepts);
+    }
+
+    pub fn hide(&self) {
+        if self.popover.is_visible() {
+            self.popover.popdown();
+        }
+    }
+
+    fn handle_key(&self, key: gtk::gdk::Key) -> glib::Propagation {
+        match key {
+            gtk::gdk::Key::Down | gtk::gdk::Key::Up => {
+                let step = if key == gtk::gdk::Key::Down { 1 } else { -1 };
+                let current = self.list.selected_row().map(|row| row.index()).unwrap_or(0);
+                if let Some(row) = self.list.row_at_index((current + step).max(0)) {
+                    self.list.select_row(Some(&row));
+                    self.scroll_to_row(&row);
+                }
+            }
+            gtk::gdk::Key::Return | gtk::gdk::Key::KP_Enter if !self.return_accepts.get() => {
+                self.hide();
+                return glib::Propagation::Proceed;
+            }
+            gtk::gdk::Key::Tab | gtk::gdk::Key::Return | gtk::gdk::Key::KP_Enter => {
+                let index = self.list.selected_row().map(|row| row.index()).unwrap_or(0);
+                self.accept(index as usize);
+            }
+            gtk::gdk::Key::Escape => self.hide(),
+            _ => return glib::Propagation::Proceed,
+        }
+        glib::Propagation::Stop
+    }
+
+    // Documentation of the item at `index`, if it has any
+    fn show_documentation(&self, index: Option<usize>) {
+        let items = self.items.borrow();
+        let documentation = index.and_then(|index| items.get(index)).map_or("", |item| item.documentation.trim());
+        self.documentation.set_text(documentation);
+        self.documentation_scrolled.set_visible(!documentation.is_empty());
+        self.documentation_scrolled.vadjustment().set_value(0.0);
+    }
+
+    fn scroll_to_row(&self, row: &gtk::ListBoxRow) {
+        let adj = self.scrolled.vadjustment();
+        if let Some(bounds) = row.compute_bounds(&self.list) {
+            let (top, bottom) = (bounds.y() as f64, (bounds.y() + bounds.height()) as f64);
+            if top < adj.value() {
+                adj.set_value(top);
+            } else if bottom > adj.value() + adj.page_size() {
+                adj.set_value(bottom - adj.page_size());
+            }
+        }
+    }
+
+    // Replace the typed prefix, or the range the item gives, with the chosen item and make
+    // the changes that come with it, all as one undo step
+    fn accept(&self, index: usize) {
+        let buffer = self.text_view.buffer();
+        let cursor = buffer.iter_at_mark(&buffer.get_insert()).offset() as usize;
+        let mut edits = match self.items.borrow().get(index) {
+            Some(item) => {
+                let replace = item.replace.clone().unwrap_or(cursor.saturating_sub(self.replace_chars.get())..cursor);
+                let mut edits = item.extra_edits.clone();
+                edits.push((replace, item.insert_text.clone()));
+                edits
+            }
+            None => return,
+        };
+        self.hide();
+
+        // From the end back, so the offsets of the edits still to come stay put
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        buffer.begin_user_action();
+        for (range, text) in edits {
+            let mut start = buffer.iter_at_offset(range.start as i32);
+            let mut end = buffer.iter_at_offset(range.end as i32);
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &text);
+        }
+        buffer.end_user_action();
+        self.text_view.grab_focus();
+    }
+}
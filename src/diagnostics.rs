@@ -0,0 +1,278 @@
+use anyhow::{bail, Result};
+use gio::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn css_class(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// 0-based line of the primary span
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+    pub fixes: Vec<Fix>,
+}
+
+/// A compiler suggestion that can be applied to the file it was reported for
+#[derive(Debug, Clone)]
+pub struct Fix {
+    pub title: String,
+    pub edits: Vec<Edit>,
+}
+
+/// Replace the text between two 0-based (line, character column) positions
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub replacement: String,
+}
+
+/// Diagnostics from the last check, keyed by canonical file path
+#[derive(Debug, Default)]
+pub struct DiagnosticsStore {
+    by_file: HashMap<PathBuf, Vec<Diagnostic>>,
+}
+
+impl DiagnosticsStore {
+    pub fn replace_all(&mut self, diagnostics: HashMap<PathBuf, Vec<Diagnostic>>) {
+        self.by_file = diagnostics;
+    }
+
+    pub fn for_file(&self, path: &Path) -> &[Diagnostic] {
+        let key = canonical(path);
+        self.by_file.get(&key).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// The most severe diagnostic of each line (the first one reported among equals)
+    pub fn first_per_line(&self, path: &Path) -> Vec<&Diagnostic> {
+        let mut per_line: HashMap<usize, &Diagnostic> = HashMap::new();
+        for diagnostic in self.for_file(path) {
+            per_line
+                .entry(diagnostic.line)
+                .and_modify(|current| {
+                    if diagnostic.severity < current.severity {
+                        *current = diagnostic;
+                    }
+                })
+                .or_insert(diagnostic);
+        }
+        let mut lines: Vec<&Diagnostic> = per_line.into_values().collect();
+        lines.sort_by_key(|diagnostic| diagnostic.line);
+        lines
+    }
+
+    /// Every fix offered by diagnostics on `line`
+    pub fn fixes_on_line(&self, path: &Path, line: usize) -> Vec<Fix> {
+        self.for_file(path)
+            .iter()
+            .filter(|diagnostic| diagnostic.line == line)
+            .flat_map(|diagnostic| diagnostic.fixes.iter().cloned())
+            .collect()
+    }
+}
+
+// Lines of code shown before and after the one a report is about
+const REPORT_CONTEXT_LINES: usize = 2;
+
+/// A problem at 0-based `line` and character `column` of `text` as a Markdown code block
+/// for bug reports and chat: `location:line:column: message`, then the lines around it
+/// with numbers, the line itself marked and a caret under the column
+pub fn format_report(location: &str, line: usize, column: usize, message: &str, text: &str) -> String {
+    let mut report = format!("```\n{}:{}:{}: {}\n", location, line + 1, column + 1, message);
+    let lines: Vec<&str> = text.lines().collect();
+    if line < lines.len() {
+        let first = line.saturating_sub(REPORT_CONTEXT_LINES);
+        let last = (line + REPORT_CONTEXT_LINES).min(lines.len() - 1);
+        let width = (last + 1).to_string().len();
+        for (index, content) in lines.iter().enumerate().take(last + 1).skip(first) {
+            let marker = if index == line { '>' } else { ' ' };
+            report.push_str(format!("{} {:>width$} | {}", marker, index + 1, content).trim_end());
+            report.push('\n');
+            if index == line {
+                // Tabs stay tabs so the caret lines up however wide they are shown
+                let indent: String = content.chars().take(column).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+                report.push_str(&format!("  {:>width$} | {}^\n", "", indent));
+            }
+        }
+    }
+    report.push_str("```\n");
+    report
+}
+
+/// The directory `cargo check` should run in for `path`: the enclosing workspace
+/// if there is one, otherwise the nearest package
+pub fn find_cargo_root(path: &Path) -> Option<PathBuf> {
+    let mut nearest = None;
+    for dir in path.ancestors().skip(1) {
+        let manifest = dir.join("Cargo.toml");
+        if let Ok(content) = fs::read_to_string(&manifest) {
+            if content.contains("[workspace]") {
+                return Some(dir.to_path_buf());
+            }
+            if nearest.is_none() {
+                nearest = Some(dir.to_path_buf());
+            }
+        }
+    }
+    nearest
+}
+
+/// Run `cargo check` in `root` and collect its diagnostics per file
+pub fn run_cargo_check(root: &Path, cancellable: &gio::Cancellable) -> Result<HashMap<PathBuf, Vec<Diagnostic>>> {
+    let mut child = Command::new("cargo")
+        .args(["check", "--message-format=json"])
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read on threads of their own, so cargo filling one pipe does not stall
+    let read_all = |stream: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            if let Some(mut stream) = stream {
+                let _ = stream.read_to_end(&mut bytes);
+            }
+            bytes
+        })
+    };
+    let stdout = read_all(child.stdout.take().map(|stream| Box::new(stream) as Box<dyn Read + Send>));
+    let stderr = read_all(child.stderr.take().map(|stream| Box::new(stream) as Box<dyn Read + Send>));
+
+    // Cancelling stops cargo, so a check that hangs does not hold up the next one
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancellable.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("cargo check was stopped");
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    let (stdout, stderr) = (stdout.join().unwrap_or_default(), stderr.join().unwrap_or_default());
+    if stdout.is_empty() && !status.success() {
+        bail!("cargo check failed: {}", String::from_utf8_lossy(&stderr).trim());
+    }
+    Ok(parse_cargo_messages(root, &String::from_utf8_lossy(&stdout)))
+}
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    message: String,
+    level: String,
+    spans: Vec<Span>,
+    children: Vec<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    column_end: usize,
+    is_primary: bool,
+    suggested_replacement: Option<String>,
+}
+
+impl Span {
+    fn to_edit(&self) -> Option<Edit> {
+        let replacement = self.suggested_replacement.clone()?;
+        Some(Edit {
+            start: (self.line_start.saturating_sub(1), self.column_start.saturating_sub(1)),
+            end: (self.line_end.saturating_sub(1), self.column_end.saturating_sub(1)),
+            replacement,
+        })
+    }
+}
+
+// Suggestions live in the child messages ("help: consider ...") as spans with a replacement
+fn collect_fixes(message: &CompilerMessage, file_name: &str) -> Vec<Fix> {
+    message.children.iter()
+        .filter_map(|child| {
+            let edits: Vec<Edit> = child.spans.iter()
+                // Fixes are applied to the open file, so skip ones that touch other files
+                .filter(|span| span.file_name == file_name)
+                .filter_map(Span::to_edit)
+                .collect();
+            if edits.is_empty() || edits.len() != child.spans.len() {
+                return None;
+            }
+            let title = match edits.as_slice() {
+                [edit] if edit.replacement.is_empty() => format!("{} (remove)", child.message),
+                [edit] if !edit.replacement.contains('\n') => format!("{}: `{}`", child.message, edit.replacement),
+                _ => child.message.clone(),
+            };
+            Some(Fix { title, edits })
+        })
+        .collect()
+}
+
+fn parse_cargo_messages(root: &Path, output: &str) -> HashMap<PathBuf, Vec<Diagnostic>> {
+    let mut diagnostics: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+    for line in output.lines() {
+        let Ok(cargo_message) = serde_json::from_str::<CargoMessage>(line) else {
+            continue;
+        };
+        if cargo_message.reason != "compiler-message" {
+            continue;
+        }
+        let Some(message) = cargo_message.message else {
+            continue;
+        };
+        // Summaries like "aborting due to previous error" have no location
+        let Some(span) = message.spans.iter().find(|span| span.is_primary) else {
+            continue;
+        };
+
+        let severity = match message.level.as_str() {
+            level if level.starts_with("error") => Severity::Error,
+            "warning" => Severity::Warning,
+            _ => Severity::Note,
+        };
+        let file = canonical(&root.join(&span.file_name));
+        let fixes = collect_fixes(&message, &span.file_name);
+        diagnostics.entry(file).or_default().push(Diagnostic {
+            line: span.line_start.saturating_sub(1),
+            severity,
+            message: message.message,
+            fixes,
+        });
+    }
+    diagnostics
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
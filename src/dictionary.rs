@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use log::warn;
+
+/// Dictionary used until another is chosen
+pub const DEFAULT_DICTIONARY: &str = "en";
+
+// Shortest typed prefix that gets suggestions
+const MIN_PREFIX_CHARS: usize = 3;
+
+const ENGLISH: &str = include_str!("dictionaries/en.txt");
+
+/// Words of a natural language with how often they are used, for completing prose.
+///
+/// Besides the built-in English list, every `<name>.txt` file in the `dictionaries`
+/// directory of the config directory is a dictionary, replacing a built-in one of the
+/// same name. Each line holds a word and optionally its count; words without a count are
+/// taken to be listed most frequent first. Lines starting with `#` are comments.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    // Most frequent first
+    words: Vec<String>,
+}
+
+impl Dictionary {
+    fn dictionaries_dir() -> PathBuf {
+        glib::user_config_dir().join("rustedit").join("dictionaries")
+    }
+
+    /// Names of the dictionaries that can be chosen, built-in first
+    pub fn names() -> Vec<String> {
+        let mut names = vec![DEFAULT_DICTIONARY.to_string()];
+        if let Ok(entries) = fs::read_dir(Self::dictionaries_dir()) {
+            let mut user_names: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+                .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+                .filter(|name| name != DEFAULT_DICTIONARY)
+                .collect();
+            user_names.sort();
+            names.extend(user_names);
+        }
+        names
+    }
+
+    /// The dictionary called `name`; an unknown name gives an empty one
+    pub fn load(name: &str) -> Self {
+        let path = Self::dictionaries_dir().join(format!("{}.txt", name));
+        match fs::read_to_string(&path) {
+            Ok(content) => Self::parse(&content),
+            Err(_) if name == DEFAULT_DICTIONARY => Self::parse(ENGLISH),
+            Err(e) => {
+                warn!("Ignoring unreadable dictionary {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    fn parse(content: &str) -> Self {
+        let mut counted: Vec<(String, u64)> = Vec::new();
+        for (rank, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let Some(word) = fields.next() else {
+                continue;
+            };
+            // Without counts, earlier lines rank higher
+            let count = fields.next()
+                .and_then(|count| count.parse().ok())
+                .unwrap_or(u64::MAX - rank as u64);
+            counted.push((word.to_string(), count));
+        }
+        counted.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+        let mut seen = HashSet::new();
+        let words = counted
+            .into_iter()
+            .map(|(word, _)| word)
+            .filter(|word| seen.insert(word.clone()))
+            .collect();
+        Self { words }
+    }
+
+    /// The most frequent words starting with `prefix`, ignoring case, spelled with the
+    /// prefix's capital if it starts with one
+    pub fn complete(&self, prefix: &str, limit: usize) -> Vec<String> {
+        if prefix.chars().count() < MIN_PREFIX_CHARS {
+            return Vec::new();
+        }
+        let lower = prefix.to_lowercase();
+        let capitalized = prefix.chars().next().is_some_and(char::is_uppercase);
+        self.words
+            .iter()
+            .filter(|word| word.len() > lower.len() && word.to_lowercase().starts_with(&lower))
+            .take(limit)
+            .map(|word| {
+                if !capitalized {
+                    return word.clone();
+                }
+                let mut chars = word.chars();
+                chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+            })
+            .collect()
+    }
+}
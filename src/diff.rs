@@ -0,0 +1,189 @@
+use std::ops::Range;
+
+// Above this many comparison cells the LCS table gets too big, so the differing
+// middle section is reported as a plain replacement instead
+const MAX_LCS_CELLS: usize = 16_000_000;
+
+/// How a line relates to the two compared texts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    Equal,
+    Removed,
+    Added,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffLine<'a> {
+    pub kind: DiffKind,
+    pub text: &'a str,
+}
+
+/// Line-based diff of `old` against `new`
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    let mut old_index = 0;
+    let mut new_index = 0;
+    let mut result = Vec::new();
+    for kind in diff_sequences(&old_lines, &new_lines) {
+        let text = match kind {
+            DiffKind::Equal => {
+                new_index += 1;
+                old_index += 1;
+                old_lines[old_index - 1]
+            }
+            DiffKind::Removed => {
+                old_index += 1;
+                old_lines[old_index - 1]
+            }
+            DiffKind::Added => {
+                new_index += 1;
+                new_lines[new_index - 1]
+            }
+        };
+        result.push(DiffLine { kind, text });
+    }
+    result
+}
+
+/// Byte ranges that changed between two versions of a line, as (removed from `old`, added in `new`)
+pub fn diff_inline(old: &str, new: &str) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let old_chars: Vec<(usize, char)> = old.char_indices().collect();
+    let new_chars: Vec<(usize, char)> = new.char_indices().collect();
+    let old_plain: Vec<char> = old_chars.iter().map(|(_, ch)| *ch).collect();
+    let new_plain: Vec<char> = new_chars.iter().map(|(_, ch)| *ch).collect();
+
+    let mut removed: Vec<Range<usize>> = Vec::new();
+    let mut added: Vec<Range<usize>> = Vec::new();
+    let mut old_index = 0;
+    let mut new_index = 0;
+    for kind in diff_sequences(&old_plain, &new_plain) {
+        match kind {
+            DiffKind::Equal => {
+                old_index += 1;
+                new_index += 1;
+            }
+            DiffKind::Removed => {
+                let (start, ch) = old_chars[old_index];
+                push_range(&mut removed, start..start + ch.len_utf8());
+                old_index += 1;
+            }
+            DiffKind::Added => {
+                let (start, ch) = new_chars[new_index];
+                push_range(&mut added, start..start + ch.len_utf8());
+                new_index += 1;
+            }
+        }
+    }
+    (removed, added)
+}
+
+fn push_range(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    match ranges.last_mut() {
+        Some(last) if last.end == range.start => last.end = range.end,
+        _ => ranges.push(range),
+    }
+}
+
+// Edit script turning `old` into `new`, one entry per element, based on the longest common subsequence
+fn diff_sequences<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffKind> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops = vec![DiffKind::Equal; prefix];
+    if (old_mid.len() + 1) * (new_mid.len() + 1) > MAX_LCS_CELLS {
+        ops.extend(std::iter::repeat_n(DiffKind::Removed, old_mid.len()));
+        ops.extend(std::iter::repeat_n(DiffKind::Added, new_mid.len()));
+    } else {
+        ops.extend(lcs_script(old_mid, new_mid));
+    }
+    ops.extend(std::iter::repeat_n(DiffKind::Equal, suffix));
+    ops
+}
+
+fn lcs_script<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffKind> {
+    let width = new.len() + 1;
+    // lengths[i * width + j] = LCS length of old[i..] and new[j..]
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            ops.push(DiffKind::Equal);
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            ops.push(DiffKind::Removed);
+            i += 1;
+        } else {
+            ops.push(DiffKind::Added);
+            j += 1;
+        }
+    }
+    ops.extend(std::iter::repeat_n(DiffKind::Removed, old.len() - i));
+    ops.extend(std::iter::repeat_n(DiffKind::Added, new.len() - j));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DiffKind::{Added, Equal, Removed};
+
+    fn lines<'a>(old: &'a str, new: &'a str) -> Vec<(DiffKind, &'a str)> {
+        diff_lines(old, new).into_iter().map(|line| (line.kind, line.text)).collect()
+    }
+
+    #[test]
+    fn finds_inserted_lines() {
+        assert_eq!(lines("a\nc\n", "a\nb\nc\n"), [(Equal, "a"), (Added, "b"), (Equal, "c")]);
+        assert_eq!(lines("", "a\n"), [(Added, "a")]);
+    }
+
+    #[test]
+    fn finds_deleted_lines() {
+        assert_eq!(lines("a\nb\nc", "a\nc"), [(Equal, "a"), (Removed, "b"), (Equal, "c")]);
+        assert_eq!(lines("a\nb", "b"), [(Removed, "a"), (Equal, "b")]);
+    }
+
+    #[test]
+    fn replaced_lines_are_removed_then_added() {
+        assert_eq!(lines("a\nb\nc", "a\nx\ny\nc"), [
+            (Equal, "a"), (Removed, "b"), (Added, "x"), (Added, "y"), (Equal, "c"),
+        ]);
+    }
+
+    #[test]
+    fn inline_ranges_cover_the_changed_characters() {
+        let inline = |old, new| {
+            let bounds = |ranges: Vec<Range<usize>>| {
+                ranges.into_iter().map(|range| (range.start, range.end)).collect::<Vec<_>>()
+            };
+            let (removed, added) = diff_inline(old, new);
+            (bounds(removed), bounds(added))
+        };
+        assert_eq!(inline("let x = 1;", "let y = 10;"), (vec![(4, 5)], vec![(4, 5), (9, 10)]));
+        assert_eq!(inline("same", "same"), (vec![], vec![]));
+        // Byte ranges, so a multi-byte character is covered whole
+        assert_eq!(inline("café", "cafe"), (vec![(3, 5)], vec![(3, 4)]));
+    }
+}
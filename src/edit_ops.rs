@@ -0,0 +1,164 @@
+use std::ops::Range;
+
+/// A change planned on a text: `range` is replaced by `text`, after which `selection` is
+/// selected. Both ranges are byte offsets, `range` into the text before the change and
+/// `selection` into the text after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+    pub selection: Range<usize>,
+}
+
+impl Edit {
+    /// The text once the change is made
+    pub fn apply(&self, text: &str) -> String {
+        format!("{}{}{}", &text[..self.range.start], self.text, &text[self.range.end..])
+    }
+}
+
+/// Copy the selection to just after it and select the copy, or without a selection copy
+/// the cursor's line below it, keeping the cursor's column
+pub fn duplicate(text: &str, selection: Range<usize>) -> Edit {
+    if !selection.is_empty() {
+        let copy = text[selection.clone()].to_string();
+        let end = selection.end;
+        return Edit {
+            range: end..end,
+            selection: end..end + copy.len(),
+            text: copy,
+        };
+    }
+
+    let lines = line_span(text, selection.clone());
+    let copy = format!("\n{}", &text[lines.clone()]);
+    let cursor = selection.start + copy.len();
+    Edit {
+        range: lines.end..lines.end,
+        text: copy,
+        selection: cursor..cursor,
+    }
+}
+
+/// Swap the lines the selection touches with the line above them, or below them when
+/// `down`, keeping the same text selected. `None` when there is no line to swap with.
+pub fn move_lines(text: &str, selection: Range<usize>, down: bool) -> Option<Edit> {
+    let lines = line_span(text, selection.clone());
+    if down {
+        if lines.end == text.len() {
+            return None;
+        }
+        let next_end = text[lines.end + 1..].find('\n').map_or(text.len(), |index| lines.end + 1 + index);
+        let next = &text[lines.end + 1..next_end];
+        let shift = next.len() + 1;
+        Some(Edit {
+            range: lines.start..next_end,
+            text: format!("{}\n{}", next, &text[lines.clone()]),
+            selection: selection.start + shift..selection.end + shift,
+        })
+    } else {
+        if lines.start == 0 {
+            return None;
+        }
+        let previous_start = text[..lines.start - 1].rfind('\n').map_or(0, |index| index + 1);
+        let previous = &text[previous_start..lines.start - 1];
+        let shift = previous.len() + 1;
+        Some(Edit {
+            range: previous_start..lines.end,
+            text: format!("{}\n{}", &text[lines.clone()], previous),
+            selection: selection.start - shift..selection.end - shift,
+        })
+    }
+}
+
+/// Byte ranges of the spaces and tabs ending each line
+pub fn trailing_whitespace(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for line in text.split('\n') {
+        let end = start + line.len();
+        let kept = line.trim_end_matches([' ', '\t']).len();
+        if start + kept < end {
+            ranges.push(start + kept..end);
+        }
+        start = end + 1;
+    }
+    ranges
+}
+
+// Whole lines the selection touches, without the final line break. A selection ending at
+// the start of a line leaves that line out.
+fn line_span(text: &str, selection: Range<usize>) -> Range<usize> {
+    let mut last = selection.end;
+    if last > selection.start && text[..last].ends_with('\n') {
+        last -= 1;
+    }
+    let start = text[..selection.start].rfind('\n').map_or(0, |index| index + 1);
+    let end = text[last..].find('\n').map_or(text.len(), |index| last + index);
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_copies_the_cursor_line_below_it() {
+        let text = "one\ntwo\nthree";
+        let edit = duplicate(text, 5..5);
+        assert_eq!(edit.apply(text), "one\ntwo\ntwo\nthree");
+        assert_eq!(edit.selection, 9..9);
+    }
+
+    #[test]
+    fn duplicate_copies_the_last_line_without_a_line_break() {
+        let text = "one\ntwo";
+        let edit = duplicate(text, 7..7);
+        assert_eq!(edit.apply(text), "one\ntwo\ntwo");
+        assert_eq!(edit.selection, 11..11);
+    }
+
+    #[test]
+    fn duplicate_copies_the_selection_after_it() {
+        let text = "let x = 1;";
+        let edit = duplicate(text, 4..5);
+        assert_eq!(edit.apply(text), "let xx = 1;");
+        assert_eq!(edit.selection, 5..6);
+    }
+
+    #[test]
+    fn move_lines_swaps_with_the_line_above() {
+        let text = "one\ntwo\nthree";
+        let edit = move_lines(text, 5..6, false).unwrap();
+        assert_eq!(edit.apply(text), "two\none\nthree");
+        assert_eq!(edit.selection, 1..2);
+    }
+
+    #[test]
+    fn move_lines_swaps_with_the_line_below() {
+        let text = "one\ntwo\nthree";
+        let edit = move_lines(text, 0..6, true).unwrap();
+        assert_eq!(edit.apply(text), "three\none\ntwo");
+        assert_eq!(edit.selection, 6..12);
+    }
+
+    #[test]
+    fn move_lines_ignores_a_line_the_selection_only_reaches_the_start_of() {
+        let text = "one\ntwo\nthree";
+        let edit = move_lines(text, 0..4, true).unwrap();
+        assert_eq!(edit.apply(text), "two\none\nthree");
+    }
+
+    #[test]
+    fn trailing_whitespace_finds_the_blanks_ending_each_line() {
+        let text = "one  \n\t\ntwo\nthree \t";
+        assert_eq!(trailing_whitespace(text), vec![3..5, 6..7, 17..19]);
+    }
+
+    #[test]
+    fn move_lines_stops_at_either_end() {
+        let text = "one\ntwo";
+        assert_eq!(move_lines(text, 1..1, false), None);
+        assert_eq!(move_lines(text, 5..5, true), None);
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::{bail, Result};
+use encoding_rs::{DecoderResult, Encoding};
+use std::path::Path;
+use crate::file_access::FileAccess;
+
+/// Encodings offered for reopening and saving, most common first
+pub const CHOICES: &[&Encoding] = &[
+    encoding_rs::UTF_8,
+    encoding_rs::UTF_16LE,
+    encoding_rs::UTF_16BE,
+    encoding_rs::WINDOWS_1252,
+    encoding_rs::ISO_8859_2,
+    encoding_rs::ISO_8859_15,
+    encoding_rs::WINDOWS_1250,
+    encoding_rs::WINDOWS_1251,
+    encoding_rs::KOI8_R,
+    encoding_rs::SHIFT_JIS,
+    encoding_rs::EUC_JP,
+    encoding_rs::GBK,
+    encoding_rs::BIG5,
+    encoding_rs::EUC_KR,
+];
+
+/// How a file's bytes were turned into text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+    /// Valid in the encoding that was found or asked for
+    Exact,
+    /// Not valid UTF-8, so a legacy 8-bit encoding was assumed
+    Guessed,
+    /// Invalid sequences were replaced with U+FFFD
+    Lossy,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedText {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+    pub detection: Detection,
+}
+
+/// Read `path` and decode it, detecting the encoding
+pub fn read_file(path: &Path, cancellable: Option<&gio::Cancellable>) -> Result<DecodedText> {
+    Ok(decode(&FileAccess::current().read(path, cancellable)?))
+}
+
+/// Decode `bytes`: a byte order mark wins, then BOM-less UTF-16, then UTF-8, then Windows-1252
+pub fn decode(bytes: &[u8]) -> DecodedText {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return decode_as(&bytes[bom_length..], encoding, true);
+    }
+
+    // Checked before UTF-8, since ASCII text in UTF-16 is also valid UTF-8
+    if let Some(encoding) = sniff_utf16(bytes) {
+        return decode_as(bytes, encoding, false);
+    }
+
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return DecodedText {
+            text: text.to_string(),
+            encoding: encoding_rs::UTF_8,
+            bom: false,
+            detection: Detection::Exact,
+        };
+    }
+
+    // Every byte is defined in Windows-1252, so this always succeeds but may be the wrong guess
+    let mut decoded = decode_as(bytes, encoding_rs::WINDOWS_1252, false);
+    decoded.detection = Detection::Guessed;
+    decoded
+}
+
+/// Decode `bytes` as `encoding`, for reopening a file with a chosen encoding
+pub fn decode_with(bytes: &[u8], encoding: &'static Encoding) -> DecodedText {
+    match Encoding::for_bom(bytes) {
+        Some((bom_encoding, bom_length)) if bom_encoding == encoding => decode_as(&bytes[bom_length..], encoding, true),
+        _ => decode_as(bytes, encoding, false),
+    }
+}
+
+/// Decode bytes appended to a file in `encoding`, returning the text and how many bytes it
+/// took. A character cut off at the end is left for the next read, so UTF-16 is only ever
+/// read in whole code units.
+pub fn decode_appended(bytes: &[u8], encoding: &'static Encoding) -> (String, usize) {
+    // A decoder told more input may follow only fails on bytes that are invalid, not on a
+    // character that is merely incomplete
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text = String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(bytes.len()).unwrap_or(0));
+    let (result, _) = decoder.decode_to_string_without_replacement(bytes, &mut text, false);
+    if result == DecoderResult::InputEmpty {
+        // No encoding offered here has characters longer than four bytes
+        for cut in 0..=bytes.len().min(3) {
+            let used = bytes.len() - cut;
+            if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(&bytes[..used]) {
+                return (text.into_owned(), used);
+            }
+        }
+    }
+
+    // Invalid, not merely incomplete: replace what cannot be decoded
+    let used = if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+        bytes.len() & !1
+    } else {
+        bytes.len()
+    };
+    (encoding.decode_without_bom_handling(&bytes[..used]).0.into_owned(), used)
+}
+
+fn decode_as(bytes: &[u8], encoding: &'static Encoding, bom: bool) -> DecodedText {
+    let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+    DecodedText {
+        text: text.into_owned(),
+        encoding,
+        bom,
+        detection: if had_errors { Detection::Lossy } else { Detection::Exact },
+    }
+}
+
+// Text that is mostly ASCII stored as UTF-16 has a NUL in every other byte
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(4096) & !1];
+    if sample.is_empty() {
+        return None;
+    }
+    let pairs = sample.len() / 2;
+    let even_nuls = sample.iter().step_by(2).filter(|&&byte| byte == 0).count();
+    let odd_nuls = sample.iter().skip(1).step_by(2).filter(|&&byte| byte == 0).count();
+
+    if odd_nuls * 10 >= pairs * 7 && even_nuls * 10 < pairs {
+        Some(encoding_rs::UTF_16LE)
+    } else if even_nuls * 10 >= pairs * 7 && odd_nuls * 10 < pairs {
+        Some(encoding_rs::UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Encode `text` for saving, failing if the encoding cannot represent every character
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>> {
+    // encoding_rs only decodes UTF-16, so it is written by hand
+    let utf16 = |to_bytes: fn(u16) -> [u8; 2], bom_bytes: [u8; 2]| {
+        let mut bytes = if bom { bom_bytes.to_vec() } else { Vec::new() };
+        bytes.extend(text.encode_utf16().flat_map(to_bytes));
+        bytes
+    };
+    if encoding == encoding_rs::UTF_16LE {
+        return Ok(utf16(u16::to_le_bytes, [0xFF, 0xFE]));
+    }
+    if encoding == encoding_rs::UTF_16BE {
+        return Ok(utf16(u16::to_be_bytes, [0xFE, 0xFF]));
+    }
+
+    let mut bytes = if bom && encoding == encoding_rs::UTF_8 {
+        vec![0xEF, 0xBB, 0xBF]
+    } else {
+        Vec::new()
+    };
+    let (encoded, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        bail!("{} cannot represent every character in this file", encoding.name());
+    }
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appended_text_leaves_a_cut_off_character_for_the_next_read() {
+        assert_eq!(decode_appended("ab\u{e9}".as_bytes(), encoding_rs::UTF_8), ("ab\u{e9}".to_string(), 4));
+        assert_eq!(decode_appended(&"ab\u{e9}".as_bytes()[..3], encoding_rs::UTF_8), ("ab".to_string(), 2));
+        // Half a code unit, then half a surrogate pair
+        assert_eq!(decode_appended(&[b'a', 0, b'b'], encoding_rs::UTF_16LE), ("a".to_string(), 2));
+        assert_eq!(decode_appended(&[b'a', 0, 0x3D, 0xD8], encoding_rs::UTF_16LE), ("a".to_string(), 2));
+        assert_eq!(decode_appended(&[0, b'a', 0xD8, 0x3D, 0xDE, 0x00], encoding_rs::UTF_16BE), ("a\u{1F600}".to_string(), 6));
+        assert_eq!(decode_appended(&[b'a', 0xE9], encoding_rs::WINDOWS_1252), ("a\u{e9}".to_string(), 2));
+    }
+
+    #[test]
+    fn invalid_appended_bytes_are_replaced() {
+        assert_eq!(decode_appended(&[b'a', 0xFF, b'b'], encoding_rs::UTF_8), ("a\u{FFFD}b".to_string(), 3));
+        // An unpaired low surrogate, then an odd byte left for the next read
+        assert_eq!(decode_appended(&[0x00, 0xDC, b'a', 0, b'b'], encoding_rs::UTF_16LE), ("\u{FFFD}a".to_string(), 4));
+    }
+}
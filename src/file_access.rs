@@ -0,0 +1,195 @@
+use anyhow::{anyhow, bail, Result};
+use gio::prelude::*;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// How the files the user opens and saves are reached.
+///
+/// Outside a sandbox that is the file system itself. Inside Flatpak, files chosen in a file
+/// dialog come through the document portal as paths under `/run/user/<uid>/doc`, read and
+/// written through GIO. The portal's grant for such a path can lapse between runs, in which
+/// case the user has to pick the file again.
+///
+/// Reads and writes block, so the editor runs them on worker threads. Cancelling a read
+/// stops it between chunks; a write can only be stopped before it starts, except through
+/// the portal, where GIO replaces the file only once the new content is complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAccess {
+    Direct,
+    Portal,
+}
+
+impl FileAccess {
+    /// The backend for this run, decided once
+    pub fn current() -> Self {
+        static CURRENT: OnceLock<FileAccess> = OnceLock::new();
+        *CURRENT.get_or_init(|| {
+            if Path::new("/.flatpak-info").exists() {
+                Self::Portal
+            } else {
+                Self::Direct
+            }
+        })
+    }
+
+    pub fn is_sandboxed(self) -> bool {
+        self == Self::Portal
+    }
+
+    pub fn read(self, path: &Path, cancellable: Option<&gio::Cancellable>) -> Result<Vec<u8>> {
+        match self {
+            Self::Direct => {
+                let mut file = fs::File::open(path)?;
+                let mut bytes = Vec::with_capacity(file.metadata().map_or(0, |metadata| metadata.len() as usize));
+                let mut chunk = vec![0; READ_CHUNK];
+                loop {
+                    check_cancelled(cancellable)?;
+                    match file.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(read) => bytes.extend_from_slice(&chunk[..read]),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Ok(bytes)
+            }
+            Self::Portal => {
+                let (bytes, _) = gio::File::for_path(path).load_contents(cancellable).map_err(io_error)?;
+                Ok(bytes.to_vec())
+            }
+        }
+    }
+
+    /// The bytes of `path` from `offset` to the end, for following a file that grows
+    pub fn read_from(self, path: &Path, offset: u64) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        match self {
+            Self::Direct => {
+                let mut file = fs::File::open(path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_to_end(&mut bytes)?;
+            }
+            Self::Portal => {
+                let stream = gio::File::for_path(path).read(gio::Cancellable::NONE).map_err(io_error)?;
+                stream.seek(offset as i64, glib::SeekType::Set, gio::Cancellable::NONE).map_err(io_error)?;
+                loop {
+                    let chunk = stream.read_bytes(READ_CHUNK, gio::Cancellable::NONE).map_err(io_error)?;
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    bytes.extend_from_slice(&chunk);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Length of the file at `path` in bytes
+    pub fn size(self, path: &Path) -> Result<u64> {
+        match self {
+            Self::Direct => Ok(fs::metadata(path)?.len()),
+            Self::Portal => {
+                let info = gio::File::for_path(path)
+                    .query_info(gio::FILE_ATTRIBUTE_STANDARD_SIZE, gio::FileQueryInfoFlags::NONE, gio::Cancellable::NONE)
+                    .map_err(io_error)?;
+                Ok(info.size().max(0) as u64)
+            }
+        }
+    }
+
+    pub fn write(self, path: &Path, bytes: &[u8], cancellable: Option<&gio::Cancellable>) -> Result<()> {
+        match self {
+            Self::Direct => {
+                check_cancelled(cancellable)?;
+                fs::write(path, bytes)?;
+            }
+            Self::Portal => {
+                gio::File::for_path(path)
+                    .replace_contents(bytes, None, false, gio::FileCreateFlags::NONE, cancellable)
+                    .map_err(io_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the user may write to the file at `path`, as far as the file system says
+    pub fn can_write(self, path: &Path) -> bool {
+        gio::File::for_path(path)
+            .query_info(gio::FILE_ATTRIBUTE_ACCESS_CAN_WRITE, gio::FileQueryInfoFlags::NONE, gio::Cancellable::NONE)
+            .map_or(true, |info| info.boolean(gio::FILE_ATTRIBUTE_ACCESS_CAN_WRITE))
+    }
+
+    /// Whether a file the user may not write can be saved as administrator instead, through
+    /// polkit's `pkexec`. Never inside a sandbox, which has no way out to it.
+    pub fn can_write_as_administrator(self) -> bool {
+        !self.is_sandboxed()
+            && std::env::var_os("PATH")
+                .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join("pkexec").is_file()))
+    }
+
+    /// Whether `path` has to be picked again before it can be opened: a file the sandbox
+    /// can no longer see. Outside a sandbox a missing file is just missing.
+    pub fn needs_grant(self, path: &Path) -> bool {
+        self.is_sandboxed() && fs::metadata(path).is_err()
+    }
+}
+
+/// Write `bytes` to `path` as root: `pkexec` asks the user to authenticate, then `tee`
+/// replaces the file's content, keeping its owner and permissions. Blocks until the user
+/// has answered, so it runs on a worker thread like any other write.
+pub fn write_as_administrator(path: &Path, bytes: &[u8], cancellable: Option<&gio::Cancellable>) -> Result<()> {
+    check_cancelled(cancellable)?;
+    let mut child = Command::new("pkexec")
+        .arg("tee")
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Could not start pkexec: {}", e))?;
+    // A failed write shows in tee's exit status, which says more than the broken pipe
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(bytes);
+    }
+    let output = child.wait_with_output()?;
+    match output.status.code() {
+        Some(0) => Ok(()),
+        // pkexec's own exit codes, for a dismissed dialog and a refused authentication
+        Some(126) => Err(io::Error::new(io::ErrorKind::Interrupted, "authentication was dismissed").into()),
+        Some(127) => Err(io::Error::new(io::ErrorKind::PermissionDenied, "not authorized to save as administrator").into()),
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            match stderr.trim() {
+                "" => bail!("pkexec failed ({})", output.status),
+                message => bail!("{}", message),
+            }
+        }
+    }
+}
+
+// Bytes read at a time, between checks for cancellation
+const READ_CHUNK: usize = 1 << 20;
+
+fn check_cancelled(cancellable: Option<&gio::Cancellable>) -> io::Result<()> {
+    match cancellable {
+        Some(cancellable) => cancellable.set_error_if_cancelled().map_err(io_error),
+        None => Ok(()),
+    }
+}
+
+// GIO's errors as the I/O errors the rest of the editor explains to the user
+fn io_error(error: glib::Error) -> io::Error {
+    let kind = match error.kind::<gio::IOErrorEnum>() {
+        Some(gio::IOErrorEnum::NotFound) => io::ErrorKind::NotFound,
+        Some(gio::IOErrorEnum::PermissionDenied) => io::ErrorKind::PermissionDenied,
+        Some(gio::IOErrorEnum::NoSpace) => io::ErrorKind::StorageFull,
+        Some(gio::IOErrorEnum::ReadOnly) => io::ErrorKind::ReadOnlyFilesystem,
+        Some(gio::IOErrorEnum::IsDirectory) => io::ErrorKind::IsADirectory,
+        Some(gio::IOErrorEnum::Cancelled) => io::ErrorKind::Interrupted,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, error.message().to_string())
+}
@@ -0,0 +1,213 @@
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexBuilder};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Files larger than this are skipped; they are rarely what a project search is after
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Longest line preview kept per match, in characters
+const MAX_PREVIEW_CHARS: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub pattern: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+}
+
+/// One matching line; `line` and `column` are 0-based, `column` counts characters
+#[derive(Debug, Clone)]
+pub struct LineMatch {
+    pub line: usize,
+    pub column: usize,
+    pub preview: String,
+}
+
+/// All matches found in one file
+#[derive(Debug, Clone)]
+pub struct FileMatches {
+    pub path: PathBuf,
+    pub matches: Vec<LineMatch>,
+}
+
+/// Compile the search pattern, escaping it unless regex mode is on
+pub fn build_matcher(options: &SearchOptions) -> Result<Regex> {
+    let pattern = if options.regex {
+        options.pattern.clone()
+    } else {
+        regex::escape(&options.pattern)
+    };
+    Ok(RegexBuilder::new(&pattern)
+        .case_insensitive(!options.case_sensitive)
+        .build()?)
+}
+
+/// Patterns from one `.gitignore`, matched against paths relative to its directory
+struct IgnoreRules {
+    base: PathBuf,
+    globs: GlobSet,
+    dir_only: GlobSet,
+}
+
+impl IgnoreRules {
+    fn load(dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(dir.join(".gitignore")).ok()?;
+        let mut globs = GlobSetBuilder::new();
+        let mut dir_only = GlobSetBuilder::new();
+
+        for line in content.lines() {
+            let line = line.trim();
+            // Negated patterns are not supported; skipping them only means searching more
+            if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+                continue;
+            }
+
+            let (line, only_dirs) = match line.strip_suffix('/') {
+                Some(stripped) => (stripped, true),
+                None => (line, false),
+            };
+            // Patterns without an inner slash match at any depth
+            let pattern = match line.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if line.contains('/') => line.to_string(),
+                None => format!("**/{}", line),
+            };
+
+            let Ok(glob) = Glob::new(&pattern) else {
+                continue;
+            };
+            if only_dirs {
+                dir_only.add(glob);
+            } else {
+                globs.add(glob);
+            }
+        }
+
+        Some(Self {
+            base: dir.to_path_buf(),
+            globs: globs.build().ok()?,
+            dir_only: dir_only.build().ok()?,
+        })
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        self.globs.is_match(relative) || (is_dir && self.dir_only.is_match(relative))
+    }
+}
+
+/// Search every text file under `root`, skipping `.git` and anything `.gitignore` excludes.
+/// `on_file` receives each file with matches as soon as it is searched and returns
+/// `false` to stop the search early.
+pub fn search_directory<F>(root: &Path, matcher: &Regex, mut on_file: F)
+where
+    F: FnMut(FileMatches) -> bool,
+{
+    walk_directory(root, |path| match search_file(path, matcher) {
+        Some(found) => on_file(found),
+        None => true,
+    });
+}
+
+/// Visit every file under `root` in name order, skipping `.git` and anything `.gitignore`
+/// excludes. `on_file` returns `false` to stop the walk early.
+pub fn walk_directory<F>(root: &Path, mut on_file: F)
+where
+    F: FnMut(&Path) -> bool,
+{
+    let mut rules = Vec::new();
+    walk_recursive(root, &mut rules, &mut on_file);
+}
+
+fn walk_recursive<F>(dir: &Path, rules: &mut Vec<IgnoreRules>, on_file: &mut F) -> bool
+where
+    F: FnMut(&Path) -> bool,
+{
+    let Ok(entries) = fs::read_dir(dir) else {
+        return true;
+    };
+    let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let pushed_rules = match IgnoreRules::load(dir) {
+        Some(dir_rules) => {
+            rules.push(dir_rules);
+            true
+        }
+        None => false,
+    };
+
+    let mut keep_going = true;
+    for entry in entries {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        // Symlinks are skipped so that link cycles cannot trap the walk
+        if file_type.is_symlink() || entry.file_name() == ".git" {
+            continue;
+        }
+        let is_dir = file_type.is_dir();
+        if rules.iter().any(|rules| rules.is_ignored(&path, is_dir)) {
+            continue;
+        }
+
+        keep_going = if is_dir { walk_recursive(&path, rules, on_file) } else { on_file(&path) };
+        if !keep_going {
+            break;
+        }
+    }
+
+    if pushed_rules {
+        rules.pop();
+    }
+    keep_going
+}
+
+/// The root of the git repository holding `path`: the nearest directory above it with a
+/// `.git` entry, which is a file rather than a directory in worktrees and submodules
+pub fn find_git_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors().skip(1).find(|dir| dir.join(".git").exists()).map(Path::to_path_buf)
+}
+
+fn search_file(path: &Path, matcher: &Regex) -> Option<FileMatches> {
+    let mut file = fs::File::open(path).ok()?;
+    if file.metadata().ok()?.len() > MAX_FILE_SIZE {
+        return None;
+    }
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).ok()?;
+    // A NUL byte near the start means a binary file
+    if bytes.iter().take(8192).any(|&byte| byte == 0) {
+        return None;
+    }
+    let content = String::from_utf8(bytes).ok()?;
+
+    let matches: Vec<LineMatch> = content
+        .lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let found = matcher.find(text)?;
+            Some(LineMatch {
+                line,
+                column: text[..found.start()].chars().count(),
+                preview: text.trim().chars().take(MAX_PREVIEW_CHARS).collect(),
+            })
+        })
+        .collect();
+
+    if matches.is_empty() {
+        None
+    } else {
+        Some(FileMatches {
+            path: path.to_path_buf(),
+            matches,
+        })
+    }
+}
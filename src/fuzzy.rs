@@ -0,0 +1,53 @@
+/// Score how well `pattern` matches `candidate` as a case-insensitive subsequence.
+/// Returns `None` when the pattern characters do not all appear in order; higher
+/// scores mean tighter matches (consecutive runs, word starts, early positions).
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+
+    let mut score = 0i64;
+    let mut pattern_index = 0;
+    let mut previous_match: Option<usize> = None;
+    let mut previous_char: Option<char> = None;
+
+    for (index, ch) in candidate.chars().enumerate() {
+        if pattern_index == pattern.len() {
+            break;
+        }
+        let lower = ch.to_lowercase().next().unwrap_or(ch);
+        if lower == pattern[pattern_index] {
+            score += 1;
+            if previous_match.is_some_and(|previous| previous + 1 == index) {
+                score += 5;
+            }
+            if is_word_start(previous_char, ch) {
+                score += 8;
+            }
+            if previous_match.is_none() {
+                // Prefer matches that start early in the candidate
+                score -= index.min(10) as i64;
+            }
+            previous_match = Some(index);
+            pattern_index += 1;
+        }
+        previous_char = Some(ch);
+    }
+
+    if pattern_index < pattern.len() {
+        return None;
+    }
+
+    // Among equal matches, shorter candidates are more specific
+    Some(score * 100 - candidate.chars().count().min(99) as i64)
+}
+
+fn is_word_start(previous: Option<char>, current: char) -> bool {
+    match previous {
+        None => true,
+        Some(previous) => {
+            !previous.is_alphanumeric() || (previous.is_lowercase() && current.is_uppercase())
+        }
+    }
+}
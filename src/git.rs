@@ -0,0 +1,169 @@
+use crate::diff::{diff_lines, DiffKind};
+use crate::line_ending;
+use git2::{ErrorCode, Repository, StatusOptions};
+use std::ops::Range;
+use std::path::Path;
+
+/// Where the repository holding a file stands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoStatus {
+    /// Checked out branch, or the short commit id when HEAD is detached
+    pub branch: String,
+    /// Whether anything differs from HEAD, untracked files included
+    pub dirty: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HunkKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// A run of lines that differs from the committed text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub kind: HunkKind,
+    /// 0-based lines of the current text; empty for deletions, which sit before `lines.start`
+    pub lines: Range<usize>,
+    /// The committed lines the hunk replaces
+    pub old_lines: Vec<String>,
+}
+
+// The repository around `path` and the path relative to its working directory
+fn open(path: &Path) -> Option<(Repository, std::path::PathBuf)> {
+    let path = path.canonicalize().ok()?;
+    let repo = Repository::discover(path.parent()?).ok()?;
+    let workdir = repo.workdir()?.canonicalize().ok()?;
+    let relative = path.strip_prefix(&workdir).ok()?.to_path_buf();
+    Some((repo, relative))
+}
+
+/// Text of `path` as committed at HEAD, with its line endings normalized. `None` when the
+/// file is not in a repository, not committed yet, or not UTF-8.
+pub fn head_text(path: &Path) -> Option<String> {
+    let (repo, relative) = open(path)?;
+    let tree = repo.head().ok()?.peel_to_tree().ok()?;
+    let blob = tree.get_path(&relative).ok()?.to_object(&repo).ok()?.peel_to_blob().ok()?;
+    let text = std::str::from_utf8(blob.content()).ok()?;
+    Some(line_ending::normalize(text))
+}
+
+/// Branch and dirty state of the repository holding `path`, if it is in one
+pub fn repo_status(path: &Path) -> Option<RepoStatus> {
+    let (repo, _) = open(path)?;
+    let branch = match repo.head() {
+        Ok(head) if head.is_branch() => head.shorthand()?.to_string(),
+        Ok(head) => head.target()?.to_string().chars().take(7).collect(),
+        // A new repository's branch has no commits yet, so HEAD only names it
+        Err(e) if e.code() == ErrorCode::UnbornBranch => {
+            let head = repo.find_reference("HEAD").ok()?;
+            let target = head.symbolic_target()?;
+            target.strip_prefix("refs/heads/").unwrap_or(target).to_string()
+        }
+        Err(_) => return None,
+    };
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    let dirty = repo.statuses(Some(&mut options)).is_ok_and(|statuses| !statuses.is_empty());
+    Some(RepoStatus { branch, dirty })
+}
+
+/// The runs of lines of `text` that differ from `head`
+pub fn hunks(head: &str, text: &str) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    let mut line = 0;
+    let mut start = 0;
+    let mut old_lines = Vec::new();
+    let mut finish = |line: usize, start: usize, old_lines: &mut Vec<String>| {
+        let kind = match (start == line, old_lines.is_empty()) {
+            (true, true) => return,
+            (true, false) => HunkKind::Deleted,
+            (false, true) => HunkKind::Added,
+            (false, false) => HunkKind::Modified,
+        };
+        hunks.push(Hunk { kind, lines: start..line, old_lines: std::mem::take(old_lines) });
+    };
+    for diff_line in diff_lines(head, text) {
+        match diff_line.kind {
+            DiffKind::Equal => {
+                finish(line, start, &mut old_lines);
+                line += 1;
+                start = line;
+            }
+            DiffKind::Removed => old_lines.push(diff_line.text.to_string()),
+            DiffKind::Added => line += 1,
+        }
+    }
+    finish(line, start, &mut old_lines);
+    hunks
+}
+
+/// The edit that puts the committed lines of `hunk` back into `text`: the byte range to
+/// replace and its replacement
+pub fn revert_hunk(text: &str, hunk: &Hunk) -> (Range<usize>, String) {
+    let line_start = |line: usize| {
+        text.split_inclusive('\n').take(line).map(str::len).sum::<usize>()
+    };
+    let (mut start, end) = (line_start(hunk.lines.start), line_start(hunk.lines.end));
+    let mut replacement: String = hunk.old_lines.iter().map(|line| format!("{}\n", line)).collect();
+
+    // The last line has no line break to replace or to put after the committed lines
+    if end == text.len() && !text.is_empty() && !text.ends_with('\n') {
+        if start == text.len() {
+            replacement.pop();
+            replacement.insert(0, '\n');
+        } else if replacement.is_empty() {
+            start = start.saturating_sub(1);
+        } else {
+            replacement.pop();
+        }
+    }
+    (start..end, replacement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revert(text: &str, hunk: &Hunk) -> String {
+        let (range, replacement) = revert_hunk(text, hunk);
+        let mut text = text.to_string();
+        text.replace_range(range, &replacement);
+        text
+    }
+
+    #[test]
+    fn finds_added_modified_and_deleted_lines() {
+        let head = "one\ntwo\nthree\nfour\nfive\n";
+        let text = "one\nnew\ntwo\nTHREE\nfive\n";
+        assert_eq!(hunks(head, text), [
+            Hunk { kind: HunkKind::Added, lines: 1..2, old_lines: vec![] },
+            Hunk { kind: HunkKind::Modified, lines: 3..4, old_lines: vec!["three".to_string(), "four".to_string()] },
+        ]);
+        assert_eq!(hunks(head, "one\nfive\n"), [
+            Hunk { kind: HunkKind::Deleted, lines: 1..1, old_lines: vec!["two".to_string(), "three".to_string(), "four".to_string()] },
+        ]);
+        assert_eq!(hunks(head, head), []);
+    }
+
+    #[test]
+    fn reverting_every_hunk_gives_the_committed_text() {
+        let head = "one\ntwo\nthree\nfour\nfive\n";
+        for text in ["one\nnew\ntwo\nTHREE\nfive\n", "one\nfive\n", "zero\none\ntwo\nthree\nfour\nfive\nsix\n"] {
+            let mut reverted = text.to_string();
+            // Last first, so the lines of the others stay where they are
+            for hunk in hunks(head, text).iter().rev() {
+                reverted = revert(&reverted, hunk);
+            }
+            assert_eq!(reverted, head);
+        }
+    }
+
+    #[test]
+    fn reverts_at_an_unterminated_last_line() {
+        assert_eq!(revert("one\ntwo", &hunks("one\nold", "one\ntwo")[0]), "one\nold");
+        assert_eq!(revert("one\ntwo", &hunks("one", "one\ntwo")[0]), "one");
+        assert_eq!(revert("one", &hunks("one\ntwo", "one")[0]), "one\ntwo");
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Texts shorter than this are highlighted faster than their cache entry is read
+pub const MIN_TEXT_LEN: usize = 256 * 1024;
+// Entries kept; the least recently written beyond these are removed
+const MAX_ENTRIES: usize = 32;
+
+/// Syntax tags of one text, as the editor applied them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    language: String,
+    /// Length of the text in bytes, a check against hash collisions
+    len: usize,
+    /// Tag names with the character ranges they cover
+    spans: Vec<(String, Vec<Range<i32>>)>,
+}
+
+fn cache_dir() -> PathBuf {
+    glib::user_cache_dir().join("rustedit").join("highlight")
+}
+
+fn cache_file(hash: u64) -> PathBuf {
+    cache_dir().join(format!("{:016x}.json", hash))
+}
+
+/// The tags stored for a text with `hash` and `len` bytes highlighted as `language`, if any
+pub fn load(hash: u64, len: usize, language: &str) -> Option<Vec<(String, Vec<Range<i32>>)>> {
+    let content = fs::read_to_string(cache_file(hash)).ok()?;
+    match serde_json::from_str::<CacheEntry>(&content) {
+        Ok(entry) if entry.language == language && entry.len == len => Some(entry.spans),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Ignoring unreadable highlight cache entry {:016x}: {}", hash, e);
+            None
+        }
+    }
+}
+
+/// Store the tags of a text with `hash` and `len` bytes highlighted as `language`, then
+/// drop the oldest entries beyond the limit
+pub fn store(hash: u64, len: usize, language: &str, spans: Vec<(String, Vec<Range<i32>>)>) -> Result<()> {
+    let dir = cache_dir();
+    fs::create_dir_all(&dir)?;
+
+    // Write next to the target and rename, so a reader never sees half an entry
+    let path = cache_file(hash);
+    let temp_path = path.with_extension("tmp");
+    let entry = CacheEntry { language: language.to_string(), len, spans };
+    fs::write(&temp_path, serde_json::to_string(&entry)?)?;
+    fs::rename(&temp_path, &path)?;
+
+    let mut entries: Vec<(std::time::SystemTime, PathBuf)> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .collect();
+    entries.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, path) in entries.into_iter().skip(MAX_ENTRIES) {
+        let _ = fs::remove_file(path);
+    }
+    Ok(())
+}
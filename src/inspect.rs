@@ -0,0 +1,33 @@
+/// Most characters spelled out; longer texts are only counted past this
+pub const MAX_INSPECTED_CHARS: usize = 64;
+
+/// How a text is encoded, character by character, for finding stray encodings and
+/// invisible characters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inspection {
+    pub chars: usize,
+    pub utf8_len: usize,
+    pub utf16_len: usize,
+    /// `U+` code points, space separated
+    pub code_points: String,
+    /// UTF-8 bytes in hex, space separated
+    pub utf8: String,
+    /// UTF-16 code units in hex, space separated
+    pub utf16: String,
+}
+
+/// Inspect `text`, spelling out its first `MAX_INSPECTED_CHARS` characters and marking the
+/// rest with an ellipsis
+pub fn inspect(text: &str) -> Inspection {
+    let shown: String = text.chars().take(MAX_INSPECTED_CHARS).collect();
+    let ellipsis = if shown.len() < text.len() { " …" } else { "" };
+    let join = |parts: Vec<String>| format!("{}{}", parts.join(" "), ellipsis);
+    Inspection {
+        chars: text.chars().count(),
+        utf8_len: text.len(),
+        utf16_len: text.encode_utf16().count(),
+        code_points: join(shown.chars().map(|c| format!("U+{:04X}", c as u32)).collect()),
+        utf8: join(shown.bytes().map(|byte| format!("{:02X}", byte)).collect()),
+        utf16: join(shown.encode_utf16().map(|unit| format!("{:04X}", unit)).collect()),
+    }
+}
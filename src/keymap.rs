@@ -0,0 +1,508 @@
+use anyhow::Result;
+use glib::translate::IntoGlib;
+use gtk::gdk;
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+/// Editor commands that can be bound to keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    NewTab,
+    Open,
+    QuickOpen,
+    Save,
+    SaveAs,
+    Revert,
+    Print,
+    CloseTab,
+    Quit,
+    Undo,
+    Redo,
+    DuplicateLine,
+    MoveLinesUp,
+    MoveLinesDown,
+    SelectLine,
+    SelectParagraph,
+    ExpandSelection,
+    ShowCompletions,
+    PasteFromHistory,
+    SwapWithClipboard,
+    FormatTable,
+    RunSelection,
+    ToggleReadOnly,
+    Find,
+    FindInFiles,
+    Replace,
+    GoToSymbol,
+    GoToLine,
+    TabOverview,
+    QuickFix,
+    NextSymbol,
+    PreviousSymbol,
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    FocusOtherPane,
+    JumpToBracket,
+    ToggleLineComment,
+    ToggleBlockComment,
+    CaretBack,
+    CaretForward,
+    ToggleBookmark,
+    NextBookmark,
+    PreviousBookmark,
+    NextChange,
+    PreviousChange,
+    MoveTabLeft,
+    MoveTabRight,
+    MoveTabToPosition,
+}
+
+impl Action {
+    pub const ALL: [Action; 49] = [
+        Action::NewTab,
+        Action::Open,
+        Action::QuickOpen,
+        Action::Save,
+        Action::SaveAs,
+        Action::Revert,
+        Action::Print,
+        Action::CloseTab,
+        Action::Quit,
+        Action::Undo,
+        Action::Redo,
+        Action::DuplicateLine,
+        Action::MoveLinesUp,
+        Action::MoveLinesDown,
+        Action::SelectLine,
+        Action::SelectParagraph,
+        Action::ExpandSelection,
+        Action::ShowCompletions,
+        Action::PasteFromHistory,
+        Action::SwapWithClipboard,
+        Action::FormatTable,
+        Action::RunSelection,
+        Action::ToggleReadOnly,
+        Action::Find,
+        Action::FindInFiles,
+        Action::Replace,
+        Action::GoToSymbol,
+        Action::GoToLine,
+        Action::TabOverview,
+        Action::QuickFix,
+        Action::NextSymbol,
+        Action::PreviousSymbol,
+        Action::JumpToBracket,
+        Action::ToggleLineComment,
+        Action::ToggleBlockComment,
+        Action::CaretBack,
+        Action::CaretForward,
+        Action::ToggleBookmark,
+        Action::NextBookmark,
+        Action::PreviousBookmark,
+        Action::NextChange,
+        Action::PreviousChange,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::ResetZoom,
+        Action::FocusOtherPane,
+        Action::MoveTabLeft,
+        Action::MoveTabRight,
+        Action::MoveTabToPosition,
+    ];
+
+    /// Key of the action in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::NewTab => "new_tab",
+            Action::Open => "open",
+            Action::QuickOpen => "quick_open",
+            Action::Save => "save",
+            Action::SaveAs => "save_as",
+            Action::Revert => "revert",
+            Action::Print => "print",
+            Action::CloseTab => "close_tab",
+            Action::Quit => "quit",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::DuplicateLine => "duplicate_line",
+            Action::MoveLinesUp => "move_lines_up",
+            Action::MoveLinesDown => "move_lines_down",
+            Action::SelectLine => "select_line",
+            Action::SelectParagraph => "select_paragraph",
+            Action::ExpandSelection => "expand_selection",
+            Action::ShowCompletions => "show_completions",
+            Action::PasteFromHistory => "paste_from_history",
+            Action::SwapWithClipboard => "swap_with_clipboard",
+            Action::FormatTable => "format_table",
+            Action::RunSelection => "run_selection",
+            Action::ToggleReadOnly => "toggle_read_only",
+            Action::Find => "find",
+            Action::FindInFiles => "find_in_files",
+            Action::Replace => "replace",
+            Action::GoToSymbol => "go_to_symbol",
+            Action::GoToLine => "go_to_line",
+            Action::TabOverview => "tab_overview",
+            Action::QuickFix => "quick_fix",
+            Action::NextSymbol => "next_symbol",
+            Action::PreviousSymbol => "previous_symbol",
+            Action::ZoomIn => "zoom_in",
+            Action::ZoomOut => "zoom_out",
+            Action::ResetZoom => "reset_zoom",
+            Action::FocusOtherPane => "focus_other_pane",
+            Action::JumpToBracket => "jump_to_bracket",
+            Action::ToggleLineComment => "toggle_line_comment",
+            Action::ToggleBlockComment => "toggle_block_comment",
+            Action::CaretBack => "caret_back",
+            Action::CaretForward => "caret_forward",
+            Action::ToggleBookmark => "toggle_bookmark",
+            Action::NextBookmark => "next_bookmark",
+            Action::PreviousBookmark => "previous_bookmark",
+            Action::NextChange => "next_change",
+            Action::PreviousChange => "previous_change",
+            Action::MoveTabLeft => "move_tab_left",
+            Action::MoveTabRight => "move_tab_right",
+            Action::MoveTabToPosition => "move_tab_to_position",
+        }
+    }
+
+    /// Name shown in the shortcuts editor
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::NewTab => "New File",
+            Action::Open => "Open File",
+            Action::QuickOpen => "Quick Open",
+            Action::Save => "Save",
+            Action::SaveAs => "Save As",
+            Action::Revert => "Revert to Saved",
+            Action::Print => "Print",
+            Action::CloseTab => "Close File",
+            Action::Quit => "Quit",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::DuplicateLine => "Duplicate Line or Selection",
+            Action::MoveLinesUp => "Move Lines Up",
+            Action::MoveLinesDown => "Move Lines Down",
+            Action::SelectLine => "Select Line",
+            Action::SelectParagraph => "Select Paragraph",
+            Action::ExpandSelection => "Expand Selection",
+            Action::ShowCompletions => "Show Completions",
+            Action::PasteFromHistory => "Paste from History",
+            Action::SwapWithClipboard => "Swap with Clipboard",
+            Action::FormatTable => "Format Markdown Table",
+            Action::RunSelection => "Run Selection as Rust",
+            Action::ToggleReadOnly => "Toggle Read-Only",
+            Action::Find => "Find",
+            Action::FindInFiles => "Find in Files",
+            Action::Replace => "Replace",
+            Action::GoToSymbol => "Go to Symbol",
+            Action::GoToLine => "Go to Line",
+            Action::TabOverview => "Switch Tab",
+            Action::QuickFix => "Quick Fix",
+            Action::NextSymbol => "Next Function",
+            Action::PreviousSymbol => "Previous Function",
+            Action::ZoomIn => "Zoom In",
+            Action::ZoomOut => "Zoom Out",
+            Action::ResetZoom => "Reset Zoom",
+            Action::FocusOtherPane => "Switch Split Pane",
+            Action::JumpToBracket => "Jump to Matching Bracket",
+            Action::ToggleLineComment => "Toggle Line Comment",
+            Action::ToggleBlockComment => "Toggle Block Comment",
+            Action::CaretBack => "Previous Caret Position",
+            Action::CaretForward => "Next Caret Position",
+            Action::ToggleBookmark => "Toggle Bookmark",
+            Action::NextBookmark => "Next Bookmark",
+            Action::PreviousBookmark => "Previous Bookmark",
+            Action::NextChange => "Next Change",
+            Action::PreviousChange => "Previous Change",
+            Action::MoveTabLeft => "Move Tab Left",
+            Action::MoveTabRight => "Move Tab Right",
+            Action::MoveTabToPosition => "Move Tab to Position",
+        }
+    }
+
+    /// Heading the action is listed under
+    pub fn section(self) -> &'static str {
+        match self {
+            Action::NewTab | Action::Open | Action::QuickOpen | Action::Save | Action::SaveAs | Action::Revert | Action::Print | Action::CloseTab | Action::Quit => "File Operations",
+            Action::ZoomIn | Action::ZoomOut | Action::ResetZoom | Action::FocusOtherPane => "View Operations",
+            Action::MoveTabLeft | Action::MoveTabRight | Action::MoveTabToPosition => "Tab Operations",
+            _ => "Edit Operations",
+        }
+    }
+
+    /// Whether the action goes before the text view's own handling of its keys, since its
+    /// default keys are ones the text view uses too
+    pub fn overrides_text_view(self) -> bool {
+        matches!(
+            self,
+            Action::MoveTabLeft
+                | Action::MoveTabRight
+                | Action::ToggleLineComment
+                | Action::ToggleBlockComment
+                | Action::PasteFromHistory
+                | Action::RunSelection
+                | Action::ShowCompletions
+        )
+    }
+
+    /// Whether the action changes the text, so it does nothing in a read-only tab
+    pub fn edits_text(self) -> bool {
+        matches!(
+            self,
+            Action::Undo
+                | Action::Redo
+                | Action::DuplicateLine
+                | Action::MoveLinesUp
+                | Action::MoveLinesDown
+                | Action::PasteFromHistory
+                | Action::SwapWithClipboard
+                | Action::FormatTable
+                | Action::QuickFix
+                | Action::ToggleLineComment
+                | Action::ToggleBlockComment
+                | Action::ShowCompletions
+        )
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    // Shift is listed separately for plus and question, since they need Shift on some layouts
+    // and not others
+    fn default_accelerators(self) -> &'static [&'static str] {
+        match self {
+            Action::NewTab => &["<Control>t"],
+            Action::Open => &["<Control>o"],
+            // Print has Ctrl+P, so finding a file takes the other key editors commonly use
+            Action::QuickOpen => &["<Control>e"],
+            Action::Save => &["<Control>s"],
+            Action::SaveAs => &["<Control><Shift>s"],
+            Action::Revert => &["<Control><Alt>r"],
+            Action::Print => &["<Control>p"],
+            Action::CloseTab => &["<Control>w"],
+            Action::Quit => &["<Control>q"],
+            Action::Undo => &["<Control>z"],
+            Action::Redo => &["<Control>y"],
+            Action::DuplicateLine => &["<Control><Shift>d"],
+            Action::MoveLinesUp => &["<Alt>Up"],
+            Action::MoveLinesDown => &["<Alt>Down"],
+            Action::SelectLine => &["<Control>l"],
+            Action::SelectParagraph => &["<Control><Shift>l"],
+            Action::ExpandSelection => &["<Control><Shift>space"],
+            Action::ShowCompletions => &["<Control>space"],
+            Action::PasteFromHistory => &["<Control><Shift>v"],
+            Action::SwapWithClipboard => &["<Control><Alt>v"],
+            Action::FormatTable => &["<Control><Alt>t"],
+            Action::RunSelection => &["<Control><Shift>Return"],
+            Action::ToggleReadOnly => &["<Control><Shift>r"],
+            Action::Find => &["<Control>f"],
+            Action::FindInFiles => &["<Control><Shift>f"],
+            Action::Replace => &["<Control>h"],
+            Action::GoToSymbol => &["<Control><Shift>o", "<Control>r"],
+            Action::GoToLine => &["<Control>g"],
+            Action::TabOverview => &["<Control>k"],
+            Action::QuickFix => &["<Control>period"],
+            Action::NextSymbol => &["<Alt>Page_Down"],
+            Action::PreviousSymbol => &["<Alt>Page_Up"],
+            Action::ZoomIn => &["<Control>plus", "<Control><Shift>plus", "<Control>equal"],
+            Action::ZoomOut => &["<Control>minus"],
+            Action::ResetZoom => &["<Control>0"],
+            Action::FocusOtherPane => &["F6", "<Shift>F6"],
+            Action::JumpToBracket => &["<Control>m"],
+            Action::ToggleLineComment => &["<Control>slash"],
+            Action::ToggleBlockComment => &["<Control><Shift>slash", "<Control><Shift>question"],
+            Action::CaretBack => &["<Control><Alt>minus"],
+            Action::CaretForward => &["<Control><Alt>equal"],
+            Action::ToggleBookmark => &["<Control>F2"],
+            Action::NextBookmark => &["F2"],
+            Action::PreviousBookmark => &["<Shift>F2"],
+            Action::NextChange => &["<Alt>F5"],
+            Action::PreviousChange => &["<Alt><Shift>F5"],
+            Action::MoveTabLeft => &["<Control><Shift>Page_Up"],
+            Action::MoveTabRight => &["<Control><Shift>Page_Down"],
+            Action::MoveTabToPosition => &["<Control><Shift>k"],
+        }
+    }
+}
+
+/// A key with the modifiers that have to be held with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    key: gdk::Key,
+    modifiers: gdk::ModifierType,
+}
+
+impl KeyBinding {
+    const MODIFIERS: gdk::ModifierType = gdk::ModifierType::CONTROL_MASK
+        .union(gdk::ModifierType::SHIFT_MASK)
+        .union(gdk::ModifierType::ALT_MASK);
+
+    /// The binding for a key press. Letters are compared in lower case, since Shift is
+    /// already part of the modifiers.
+    pub fn new(key: gdk::Key, modifiers: gdk::ModifierType) -> Self {
+        Self {
+            key: key.to_lower(),
+            modifiers: modifiers & Self::MODIFIERS,
+        }
+    }
+
+    /// Parse a GTK accelerator such as `<Control><Shift>s`
+    pub fn parse(accelerator: &str) -> Option<Self> {
+        let (key, modifiers) = gtk::accelerator_parse(accelerator)?;
+        Some(Self::new(key, modifiers))
+    }
+
+    /// Form written to the config file
+    pub fn accelerator(&self) -> String {
+        gtk::accelerator_name(self.key, self.modifiers).to_string()
+    }
+
+    /// Form shown to the user, such as `Ctrl+Shift+S`
+    pub fn label(&self) -> String {
+        gtk::accelerator_get_label(self.key, self.modifiers).to_string()
+    }
+
+    /// Whether the binding would not get in the way of typing. Function keys work alone;
+    /// anything else needs Ctrl or Alt.
+    pub fn is_usable(&self) -> bool {
+        let function_key = (gdk::Key::F1.into_glib()..=gdk::Key::F35.into_glib()).contains(&self.key.into_glib());
+        function_key || self.modifiers.intersects(gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::ALT_MASK)
+    }
+}
+
+/// Which keys trigger which actions. Bindings that differ from the defaults are stored in
+/// `keybindings.toml` in the config directory, one list of GTK accelerators per action:
+///
+/// ```toml
+/// save = ["<Control>s"]
+/// zoom_in = ["<Control>plus", "<Control>equal"]
+/// find_in_files = []
+/// ```
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<Action, Vec<KeyBinding>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = Action::ALL
+            .into_iter()
+            .map(|action| (action, Self::default_bindings(action)))
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    fn keymap_file() -> PathBuf {
+        glib::user_config_dir().join("rustedit").join("keybindings.toml")
+    }
+
+    fn default_bindings(action: Action) -> Vec<KeyBinding> {
+        action
+            .default_accelerators()
+            .iter()
+            .filter_map(|accelerator| KeyBinding::parse(accelerator))
+            .collect()
+    }
+
+    /// The defaults, overridden by the config file where it has an entry
+    pub fn load() -> Self {
+        let path = Self::keymap_file();
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        let entries: BTreeMap<String, Vec<String>> = match toml::from_str(&content) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Ignoring unreadable key bindings {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+
+        let mut keymap = Self::default();
+        for (name, accelerators) in entries {
+            let Some(action) = Action::from_name(&name) else {
+                warn!("Ignoring key bindings for unknown action '{}'", name);
+                continue;
+            };
+            let mut bindings = Vec::new();
+            for accelerator in accelerators {
+                match KeyBinding::parse(&accelerator) {
+                    Some(binding) => bindings.push(binding),
+                    None => warn!("Ignoring unknown shortcut '{}' for {}", accelerator, name),
+                }
+            }
+            keymap.bindings.insert(action, bindings);
+        }
+
+        // A key bound twice only triggers the action listed first
+        for action in Action::ALL {
+            for binding in keymap.bindings(action) {
+                if let Some(other) = keymap.action_for(binding) {
+                    if other != action {
+                        warn!("{} is bound to both {} and {}", binding.label(), other.name(), action.name());
+                    }
+                }
+            }
+        }
+        keymap
+    }
+
+    /// Write the bindings that differ from the defaults
+    pub fn save(&self) -> Result<()> {
+        let changed: BTreeMap<&str, Vec<String>> = Action::ALL
+            .into_iter()
+            .filter(|&action| self.bindings(action) != Self::default_bindings(action).as_slice())
+            .map(|action| (action.name(), self.bindings(action).iter().map(KeyBinding::accelerator).collect()))
+            .collect();
+
+        let path = Self::keymap_file();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, toml::to_string(&changed)?)?;
+        Ok(())
+    }
+
+    pub fn bindings(&self, action: Action) -> &[KeyBinding] {
+        self.bindings.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Replace every binding of `action`
+    pub fn set(&mut self, action: Action, bindings: Vec<KeyBinding>) {
+        self.bindings.insert(action, bindings);
+    }
+
+    /// Remove `binding` from whichever action has it
+    pub fn unbind(&mut self, binding: &KeyBinding) {
+        for bindings in self.bindings.values_mut() {
+            bindings.retain(|bound| bound != binding);
+        }
+    }
+
+    /// The action a key press triggers, if any
+    pub fn action_for(&self, binding: &KeyBinding) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|&action| self.bindings(action).contains(binding))
+    }
+
+    /// First shortcut of `action`, for hints next to menu items
+    pub fn primary_label(&self, action: Action) -> String {
+        self.bindings(action).first().map(KeyBinding::label).unwrap_or_default()
+    }
+
+    /// Shortcuts of `action` joined for display, empty when it has none
+    pub fn label(&self, action: Action) -> String {
+        self.bindings(action)
+            .iter()
+            .map(KeyBinding::label)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
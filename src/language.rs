@@ -0,0 +1,255 @@
+use std::path::Path;
+
+/// A language the editor knows about, used for file filters and highlighting
+#[derive(Debug)]
+pub struct Language {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Interpreter names recognised in a `#!` line, matched as prefixes (`python` matches `python3`)
+    pub shebangs: &'static [&'static str],
+    pub keywords: &'static [&'static str],
+    pub types: &'static [&'static str],
+    pub line_comment: Option<&'static str>,
+    pub block_comment: Option<(&'static str, &'static str)>,
+    pub string_quotes: &'static [char],
+    /// Whether the Rust-oriented bracket and semicolon checks make sense for this language
+    pub syntax_checks: bool,
+    /// Whether files in this language are mostly prose, where typing aids for text apply
+    pub prose: bool,
+}
+
+/// Name shown for buffers without a detected language
+pub const PLAIN_TEXT: &str = "Plain Text";
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "const", "continue", "default", "do", "else", "enum", "extern",
+    "for", "goto", "if", "inline", "register", "restrict", "return", "sizeof", "static",
+    "struct", "switch", "typedef", "union", "volatile", "while", "NULL", "true", "false",
+];
+
+const C_TYPES: &[&str] = &[
+    "bool", "char", "double", "float", "int", "long", "short", "signed", "unsigned", "void",
+    "size_t", "int8_t", "int16_t", "int32_t", "int64_t", "uint8_t", "uint16_t", "uint32_t",
+    "uint64_t",
+];
+
+const JS_KEYWORDS: &[&str] = &[
+    "async", "await", "break", "case", "catch", "class", "const", "continue", "debugger",
+    "default", "delete", "do", "else", "export", "extends", "false", "finally", "for",
+    "from", "function", "if", "import", "in", "instanceof", "let", "new", "null", "of",
+    "return", "static", "super", "switch", "this", "throw", "true", "try", "typeof",
+    "undefined", "var", "void", "while", "yield", "interface", "type", "enum", "implements",
+    "private", "protected", "public", "readonly", "abstract", "declare", "namespace",
+];
+
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        name: "Rust",
+        extensions: &["rs"],
+        shebangs: &[],
+        keywords: &[
+            "as", "break", "const", "continue", "crate", "else", "enum", "extern",
+            "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+            "super", "trait", "true", "type", "unsafe", "use", "where", "while", "async",
+            "await", "dyn", "abstract", "become", "box", "do", "final", "macro", "override",
+            "priv", "typeof", "unsized", "virtual", "yield",
+        ],
+        types: &[
+            "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize",
+            "u8", "u16", "u32", "u64", "u128", "usize", "str", "String", "Vec",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"'],
+        syntax_checks: true,
+        prose: false,
+    },
+    Language {
+        name: "C",
+        extensions: &["c", "h"],
+        shebangs: &[],
+        keywords: C_KEYWORDS,
+        types: C_TYPES,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"', '\''],
+        syntax_checks: false,
+        prose: false,
+    },
+    Language {
+        name: "C++",
+        extensions: &["cpp", "cc", "cxx", "hpp", "hh", "hxx"],
+        shebangs: &[],
+        keywords: &[
+            "auto", "break", "case", "catch", "class", "const", "constexpr", "continue",
+            "default", "delete", "do", "else", "enum", "explicit", "extern", "false", "for",
+            "friend", "if", "inline", "namespace", "new", "noexcept", "nullptr", "operator",
+            "override", "private", "protected", "public", "return", "sizeof", "static",
+            "struct", "switch", "template", "this", "throw", "true", "try", "typedef",
+            "typename", "union", "using", "virtual", "volatile", "while",
+        ],
+        types: &[
+            "bool", "char", "double", "float", "int", "long", "short", "signed", "unsigned",
+            "void", "size_t", "string", "vector", "map", "unique_ptr", "shared_ptr",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"', '\''],
+        syntax_checks: false,
+        prose: false,
+    },
+    Language {
+        name: "Python",
+        extensions: &["py", "pyw", "pyi"],
+        shebangs: &["python"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def",
+            "del", "elif", "else", "except", "False", "finally", "for", "from", "global",
+            "if", "import", "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass",
+            "raise", "return", "True", "try", "while", "with", "yield",
+        ],
+        types: &[
+            "bool", "bytes", "dict", "float", "int", "list", "object", "set", "str", "tuple",
+        ],
+        line_comment: Some("#"),
+        block_comment: None,
+        string_quotes: &['"', '\''],
+        syntax_checks: false,
+        prose: false,
+    },
+    Language {
+        name: "JavaScript",
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        shebangs: &["node"],
+        keywords: JS_KEYWORDS,
+        types: &["Array", "Boolean", "Map", "Number", "Object", "Promise", "Set", "String"],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"', '\'', '`'],
+        syntax_checks: false,
+        prose: false,
+    },
+    Language {
+        name: "TypeScript",
+        extensions: &["ts", "mts", "cts", "tsx"],
+        shebangs: &["deno", "ts-node"],
+        keywords: JS_KEYWORDS,
+        types: &[
+            "any", "boolean", "never", "number", "object", "string", "symbol", "unknown",
+            "Array", "Map", "Promise", "Record", "Set",
+        ],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"', '\'', '`'],
+        syntax_checks: false,
+        prose: false,
+    },
+    Language {
+        name: "TOML",
+        extensions: &["toml"],
+        shebangs: &[],
+        keywords: &["true", "false"],
+        types: &[],
+        line_comment: Some("#"),
+        block_comment: None,
+        string_quotes: &['"', '\''],
+        syntax_checks: false,
+        prose: false,
+    },
+    Language {
+        name: "JSON",
+        extensions: &["json"],
+        shebangs: &[],
+        keywords: &["true", "false", "null"],
+        types: &[],
+        line_comment: None,
+        block_comment: None,
+        string_quotes: &['"'],
+        syntax_checks: false,
+        prose: false,
+    },
+    Language {
+        name: "YAML",
+        extensions: &["yaml", "yml"],
+        shebangs: &[],
+        keywords: &["true", "false", "null", "yes", "no"],
+        types: &[],
+        line_comment: Some("#"),
+        block_comment: None,
+        string_quotes: &['"', '\''],
+        syntax_checks: false,
+        prose: false,
+    },
+    Language {
+        name: "Markdown",
+        extensions: &["md", "markdown"],
+        shebangs: &[],
+        keywords: &[],
+        types: &[],
+        line_comment: None,
+        block_comment: Some(("<!--", "-->")),
+        string_quotes: &['`'],
+        syntax_checks: false,
+        prose: true,
+    },
+    Language {
+        name: "Shell",
+        extensions: &["sh", "bash", "zsh"],
+        shebangs: &["sh", "bash", "zsh", "dash", "ksh"],
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function",
+            "if", "in", "local", "return", "then", "until", "while",
+        ],
+        types: &[],
+        line_comment: Some("#"),
+        block_comment: None,
+        string_quotes: &['"', '\''],
+        syntax_checks: false,
+        prose: false,
+    },
+];
+
+/// Whether text in `language` is prose. Buffers without a language hold plain text, which is.
+pub fn is_prose(language: Option<&Language>) -> bool {
+    language.is_none_or(|language| language.prose)
+}
+
+/// Look a language up by its display name; `PLAIN_TEXT` and unknown names give `None`
+pub fn by_name(name: &str) -> Option<&'static Language> {
+    LANGUAGES.iter().find(|language| language.name == name)
+}
+
+/// Detect a language from the file extension, falling back to a `#!` first line
+pub fn detect(path: Option<&Path>, first_line: &str) -> Option<&'static Language> {
+    let by_extension = path
+        .and_then(|path| path.extension())
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .and_then(|extension| {
+            LANGUAGES
+                .iter()
+                .find(|language| language.extensions.contains(&extension.as_str()))
+        });
+
+    by_extension.or_else(|| detect_shebang(first_line))
+}
+
+fn detect_shebang(first_line: &str) -> Option<&'static Language> {
+    let command = first_line.strip_prefix("#!")?.trim();
+    let mut words = command.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+
+    // `#!/usr/bin/env python3` names the interpreter in the next argument
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+
+    LANGUAGES.iter().find(|language| {
+        language.shebangs.iter().any(|shebang| {
+            program
+                .strip_prefix(shebang)
+                .is_some_and(|rest| rest.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        })
+    })
+}
@@ -0,0 +1,406 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long to wait for a response before giving up on a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Semantic token types and modifiers the editor understands
+const TOKEN_TYPES: &[&str] = &[
+    "namespace", "type", "class", "enum", "interface", "struct", "typeParameter",
+    "parameter", "variable", "property", "enumMember", "function", "method",
+    "macro", "keyword", "comment", "string", "number", "operator",
+];
+const TOKEN_MODIFIERS: &[&str] = &["declaration", "definition", "readonly", "static", "mutable"];
+
+/// A semantic token, positioned in UTF-16 code units as LSP specifies by default
+#[derive(Debug, Clone)]
+pub struct SemanticToken {
+    pub line: u32,
+    pub start: u32,
+    pub length: u32,
+    pub token_type: String,
+}
+
+/// A change to a document: the text between two positions, given as 0-based lines and
+/// UTF-16 code units within them, becomes `new_text`
+#[derive(Debug, Clone)]
+pub struct TextEdit {
+    pub start: (u32, u32),
+    pub end: (u32, u32),
+    pub new_text: String,
+}
+
+/// A completion the server offers at a position
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub label: String,
+    /// `CompletionItemKind` number of the protocol
+    pub kind: Option<u64>,
+    pub detail: String,
+    pub documentation: String,
+    /// Text the list is filtered by, the label unless the server says otherwise
+    pub filter_text: String,
+    /// Text the list is sorted by among equal matches
+    pub sort_text: String,
+    /// What the item inserts; replaces the word at the position unless `edit` says where
+    pub insert_text: String,
+    pub edit: Option<TextEdit>,
+    /// Changes elsewhere that come with the item, such as an import
+    pub additional_edits: Vec<TextEdit>,
+}
+
+type Pending = Arc<Mutex<HashMap<i64, Sender<Result<Value>>>>>;
+
+/// A language server speaking JSON-RPC over the child process's stdin/stdout
+pub struct LspClient {
+    child: Mutex<Child>,
+    stdin: Arc<Mutex<ChildStdin>>,
+    pending: Pending,
+    next_id: AtomicI64,
+    // Version of every document we have opened on the server
+    documents: Mutex<HashMap<PathBuf, i32>>,
+    token_legend: Vec<String>,
+}
+
+impl LspClient {
+    /// Start `command` for the workspace at `root` and complete the initialize handshake
+    pub fn start(command: &str, root: &Path) -> Result<Self> {
+        let mut child = Command::new(command)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("cannot start {}", command))?;
+        let stdin = Arc::new(Mutex::new(child.stdin.take().context("language server has no stdin")?));
+        let stdout = child.stdout.take().context("language server has no stdout")?;
+
+        let pending = Pending::default();
+        spawn_reader(stdout, stdin.clone(), pending.clone());
+
+        let mut client = Self {
+            child: Mutex::new(child),
+            stdin,
+            pending,
+            next_id: AtomicI64::new(1),
+            documents: Mutex::new(HashMap::new()),
+            token_legend: Vec::new(),
+        };
+
+        let result = client.request("initialize", json!({
+            "processId": std::process::id(),
+            "rootUri": path_to_uri(root)?,
+            "capabilities": {
+                "textDocument": {
+                    "completion": {
+                        "completionItem": {
+                            "snippetSupport": false,
+                            "documentationFormat": ["plaintext", "markdown"],
+                        },
+                    },
+                    "semanticTokens": {
+                        "requests": { "full": true },
+                        "tokenTypes": TOKEN_TYPES,
+                        "tokenModifiers": TOKEN_MODIFIERS,
+                        "formats": ["relative"],
+                    },
+                },
+            },
+        }))?;
+        client.token_legend = result
+            .pointer("/capabilities/semanticTokensProvider/legend/tokenTypes")
+            .and_then(Value::as_array)
+            .map(|types| types.iter().filter_map(|name| name.as_str().map(String::from)).collect())
+            .unwrap_or_default();
+
+        client.notify("initialized", json!({}))?;
+        Ok(client)
+    }
+
+    /// Send a request and block until its result arrives
+    pub fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel();
+        self.pending.lock().map_err(|_| anyhow!("language server state poisoned"))?.insert(id, sender);
+
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+
+        match receiver.recv_timeout(REQUEST_TIMEOUT) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Ok(mut pending) = self.pending.lock() {
+                    pending.remove(&id);
+                }
+                bail!("{} timed out", method)
+            }
+            Err(RecvTimeoutError::Disconnected) => bail!("language server exited"),
+        }
+    }
+
+    pub fn notify(&self, method: &str, params: Value) -> Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn send(&self, message: &Value) -> Result<()> {
+        write_message(&self.stdin, message)
+    }
+
+    /// Give the server the current text of `path`, opening the document on first use
+    pub fn sync_document(&self, path: &Path, language_id: &str, text: &str) -> Result<()> {
+        let uri = path_to_uri(path)?;
+        let mut documents = self.documents.lock().map_err(|_| anyhow!("language server state poisoned"))?;
+        match documents.get_mut(path) {
+            Some(version) => {
+                *version += 1;
+                self.notify("textDocument/didChange", json!({
+                    "textDocument": { "uri": uri, "version": *version },
+                    "contentChanges": [{ "text": text }],
+                }))
+            }
+            None => {
+                documents.insert(path.to_path_buf(), 1);
+                self.notify("textDocument/didOpen", json!({
+                    "textDocument": { "uri": uri, "languageId": language_id, "version": 1, "text": text },
+                }))
+            }
+        }
+    }
+
+    /// Semantic tokens for the whole of a synced document
+    pub fn semantic_tokens(&self, path: &Path) -> Result<Vec<SemanticToken>> {
+        let result = self.request("textDocument/semanticTokens/full", json!({
+            "textDocument": { "uri": path_to_uri(path)? },
+        }))?;
+        let data: Vec<u32> = result
+            .get("data")
+            .and_then(Value::as_array)
+            .map(|data| data.iter().filter_map(Value::as_u64).map(|n| n as u32).collect())
+            .unwrap_or_default();
+        Ok(decode_semantic_tokens(&data, &self.token_legend))
+    }
+
+    /// Completions at 0-based `line` and UTF-16 `character` of a synced document
+    pub fn completion(&self, path: &Path, line: u32, character: u32) -> Result<Vec<Completion>> {
+        let result = self.request("textDocument/completion", json!({
+            "textDocument": { "uri": path_to_uri(path)? },
+            "position": { "line": line, "character": character },
+        }))?;
+        // Either a plain list of items or a `CompletionList` holding them
+        let items = result.get("items").unwrap_or(&result);
+        Ok(items.as_array().map(|items| items.iter().filter_map(parse_completion).collect()).unwrap_or_default())
+    }
+}
+
+impl Drop for LspClient {
+    fn drop(&mut self) {
+        // Don't wait for a clean shutdown; the server may be busy indexing
+        let _ = self.notify("exit", Value::Null);
+        if let Ok(mut child) = self.child.lock() {
+            let _ = child.kill();
+        }
+    }
+}
+
+/// Character offset within `line` of a position given in UTF-16 code units
+pub fn utf16_to_char_offset(line: &str, utf16_offset: u32) -> usize {
+    let mut units = 0;
+    for (index, ch) in line.chars().enumerate() {
+        if units >= utf16_offset as usize {
+            return index;
+        }
+        units += ch.len_utf16();
+    }
+    line.chars().count()
+}
+
+/// Character offset in `text` of a position given as a 0-based line and UTF-16 code units
+pub fn position_to_char_offset(text: &str, (line, character): (u32, u32)) -> usize {
+    let mut offset = 0;
+    for (index, content) in text.split('\n').enumerate() {
+        if index == line as usize {
+            return offset + utf16_to_char_offset(content, character);
+        }
+        offset += content.chars().count() + 1;
+    }
+    text.chars().count()
+}
+
+/// Position of character `char_offset` of `line` in UTF-16 code units
+pub fn char_to_utf16_offset(line: &str, char_offset: usize) -> u32 {
+    line.chars().take(char_offset).map(char::len_utf16).sum::<usize>() as u32
+}
+
+fn parse_text_edit(edit: &Value) -> Option<TextEdit> {
+    // An `InsertReplaceEdit` has two ranges; replacing keeps the rest of the word from doubling up
+    let range = edit.get("range").or_else(|| edit.get("replace"))?;
+    let position = |key: &str| -> Option<(u32, u32)> {
+        let position = range.get(key)?;
+        Some((position.get("line")?.as_u64()? as u32, position.get("character")?.as_u64()? as u32))
+    };
+    Some(TextEdit {
+        start: position("start")?,
+        end: position("end")?,
+        new_text: edit.get("newText")?.as_str()?.to_string(),
+    })
+}
+
+fn parse_completion(item: &Value) -> Option<Completion> {
+    let text = |key: &str| item.get(key).and_then(Value::as_str).map(String::from);
+    let label = text("label")?;
+    // Servers may send snippets all the same; their placeholders become plain text
+    let is_snippet = item.get("insertTextFormat").and_then(Value::as_u64) == Some(2);
+    let plain = |text: String| if is_snippet { snippet_to_plain(&text) } else { text };
+    let edit = item.get("textEdit").and_then(parse_text_edit).map(|edit| TextEdit { new_text: plain(edit.new_text), ..edit });
+    let documentation = match item.get("documentation") {
+        Some(Value::String(documentation)) => documentation.clone(),
+        Some(content) => content.get("value").and_then(Value::as_str).unwrap_or_default().to_string(),
+        None => String::new(),
+    };
+    Some(Completion {
+        kind: item.get("kind").and_then(Value::as_u64),
+        detail: text("detail").unwrap_or_default(),
+        documentation,
+        filter_text: text("filterText").unwrap_or_else(|| label.clone()),
+        sort_text: text("sortText").unwrap_or_else(|| label.clone()),
+        insert_text: plain(text("insertText").unwrap_or_else(|| label.clone())),
+        edit,
+        additional_edits: item.get("additionalTextEdits")
+            .and_then(Value::as_array)
+            .map(|edits| edits.iter().filter_map(parse_text_edit).collect())
+            .unwrap_or_default(),
+        label,
+    })
+}
+
+// Snippet syntax as the text it stands for: tab stops such as `$1` go, placeholders such as
+// `${1:name}` leave their default text and `\$` becomes `$`
+fn snippet_to_plain(snippet: &str) -> String {
+    let mut plain = String::new();
+    let mut open_placeholders = 0;
+    let mut chars = snippet.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => plain.extend(chars.next()),
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+                chars.next_if_eq(&':');
+                open_placeholders += 1;
+            }
+            '}' if open_placeholders > 0 => open_placeholders -= 1,
+            c => plain.push(c),
+        }
+    }
+    plain
+}
+
+// Tokens arrive as groups of five numbers, each relative to the previous token
+fn decode_semantic_tokens(data: &[u32], legend: &[String]) -> Vec<SemanticToken> {
+    let mut tokens = Vec::with_capacity(data.len() / 5);
+    let (mut line, mut start) = (0, 0);
+    for chunk in data.chunks_exact(5) {
+        let &[delta_line, delta_start, length, token_type, _modifiers] = chunk else {
+            continue;
+        };
+        if delta_line > 0 {
+            line += delta_line;
+            start = delta_start;
+        } else {
+            start += delta_start;
+        }
+        if let Some(token_type) = legend.get(token_type as usize) {
+            tokens.push(SemanticToken { line, start, length, token_type: token_type.clone() });
+        }
+    }
+    tokens
+}
+
+// Route responses to the requests waiting for them until the server goes away
+fn spawn_reader(stdout: ChildStdout, stdin: Arc<Mutex<ChildStdin>>, pending: Pending) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        loop {
+            let message = match read_message(&mut reader) {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Language server stream error: {}", e);
+                    break;
+                }
+            };
+
+            match (message.get("id"), message.get("method")) {
+                // Requests from the server: none are supported, but each needs an answer
+                (Some(id), Some(_)) => {
+                    let _ = write_message(&stdin, &json!({ "jsonrpc": "2.0", "id": id, "result": null }));
+                }
+                (Some(id), None) => {
+                    let sender = id.as_i64().and_then(|id| pending.lock().ok()?.remove(&id));
+                    if let Some(sender) = sender {
+                        let result = match message.get("error") {
+                            Some(error) => Err(anyhow!(
+                                "{}",
+                                error.get("message").and_then(Value::as_str).unwrap_or("request failed")
+                            )),
+                            None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                        };
+                        let _ = sender.send(result);
+                    }
+                }
+                (None, Some(method)) => debug!("Ignoring language server notification {}", method),
+                (None, None) => {}
+            }
+        }
+
+        // Dropping the senders fails every request still waiting
+        if let Ok(mut pending) = pending.lock() {
+            pending.clear();
+        }
+    });
+}
+
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            content_length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let mut body = vec![0; content_length.context("message without Content-Length")?];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(stdin: &Mutex<ChildStdin>, message: &Value) -> Result<()> {
+    let body = serde_json::to_string(message)?;
+    let mut stdin = stdin.lock().map_err(|_| anyhow!("language server stdin poisoned"))?;
+    write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    stdin.flush()?;
+    Ok(())
+}
+
+fn path_to_uri(path: &Path) -> Result<String> {
+    Ok(glib::filename_to_uri(path, None)?.to_string())
+}
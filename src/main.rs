@@ -1,58 +1,90 @@
+mod abbreviations;
+mod brackets;
+mod comments;
+mod completion;
+mod diagnostics;
+mod dictionary;
+mod diff;
+mod edit_ops;
+mod encoding;
+mod file_access;
+mod fuzzy;
+mod git;
+mod highlight_cache;
+mod inspect;
+mod find_in_files;
+mod keymap;
+mod language;
+mod line_ending;
+mod lsp;
+mod markdown;
+mod picker;
+mod printing;
+mod prose;
+mod recovery;
+mod search;
+mod settings;
+mod settings_bundle;
+mod snippet;
+mod startup;
+mod state;
+mod symbols;
 mod text_buffer;
+mod theme;
+mod typography;
+mod word_index;
 
 use anyhow::Result;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use log::{info, error, debug, warn};
 use gtk::prelude::*;
 use gtk::{TextBuffer, TextTag, TextTagTable};
 use gtk::glib;
 use std::env;
 use std::fs;
-use text_buffer::TextBuffer as EditorBuffer;
+use std::io::Read;
+use text_buffer::{BlockSelection, LineOperation, TextBuffer as EditorBuffer};
+use settings::{Indentation, SaveCleanup, Settings};
+use settings_bundle::SettingsBundle;
+use file_access::FileAccess;
+use startup::StartupProfile;
+use state::{FilePosition, PersistentState, ScrollbarMode};
+use abbreviations::Abbreviations;
+use brackets::BracketProblem;
+use completion::{CompletionItem, CompletionKind, CompletionPopup};
+use diagnostics::{DiagnosticsStore, Fix};
+use dictionary::Dictionary;
+use encoding::{DecodedText, Detection};
+use encoding_rs::Encoding;
+use keymap::{Action, KeyBinding, Keymap};
+use line_ending::LineEnding;
+use lsp::{LspClient, SemanticToken};
+use word_index::WordIndex;
+use ropey::Rope;
+use std::collections::{HashSet, VecDeque};
 use pangocairo;
 use pango;
 use std::collections::HashMap;
+use std::hash::Hasher;
 use std::path::{Path, PathBuf};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::ops::Range;
 use gtk::{ApplicationWindow, TextView, Button, Box as GtkBox, Label, Entry};
 use gtk::gdk::Key;
 use gtk::gdk::Display;
 use gtk::gio::SimpleAction;
 
-struct RecentFilesManager {
-    recent_files: Vec<PathBuf>,
-    max_files: usize,
-}
-
-impl RecentFilesManager {
-    fn new(max_files: usize) -> Self {
-        Self {
-            recent_files: Vec::new(),
-            max_files,
-        }
-    }
-
-    fn add_file(&mut self, path: PathBuf) {
-        // Remove if already exists
-        self.recent_files.retain(|p| p != &path);
-        
-        // Add to front
-        self.recent_files.insert(0, path);
-        
-        // Trim if too many
-        if self.recent_files.len() > self.max_files {
-            self.recent_files.truncate(self.max_files);
-        }
-    }
-    
-    fn get_recent_files(&self) -> &[PathBuf] {
-        &self.recent_files
-    }
-}
+// Source of the per-tab ids that name recovery files
+static NEXT_RECOVERY_ID: AtomicUsize = AtomicUsize::new(0);
 
+// State of the shown tab, shared by the main thread's handlers as Rc<RefCell<EditorState>>.
+// A borrow has to end before anything that can emit a signal, such as changing the view's
+// buffer, since the handlers of those signals borrow the state too; one that finds it
+// already borrowed skips its work rather than panicking. Key, click and drag handlers run
+// straight from the main loop with nothing borrowed, so they borrow it outright.
 struct EditorState {
     current_file: Option<PathBuf>,
     is_modified: bool,
@@ -60,13 +92,102 @@ struct EditorState {
     selection_start: Option<usize>,
     selection_end: Option<usize>,
     zoom_level: f64,
-    recent_files: RecentFilesManager,
     tab_name: String,
     active_tab_id: usize,
-    undo_stack: Vec<String>,
-    redo_stack: Vec<String>,
+    recovery_id: usize,
+    // Texts before each undo step, kept as ropes since cloning one only shares its chunks
+    undo_stack: Vec<Rope>,
+    redo_stack: Vec<Rope>,
+    // Undo or redo is putting its text into the view, which must not start undo steps
+    restoring_history: bool,
     last_saved_text: Option<String>,
+    last_saved_hash: u64,
     timeout_id: Option<glib::SourceId>,
+    large_file_mode: bool,
+    // Opened with its long lines wrapped, which holds whatever the Word Wrap setting says
+    wrap_long_lines: bool,
+    // Editing is turned off, for a file the user may not write or on request
+    read_only: bool,
+    syntax_highlighting: bool,
+    language_override: Option<String>,
+    encoding: &'static Encoding,
+    write_bom: bool,
+    line_ending: LineEnding,
+    saved_line_ending: LineEnding,
+    following: bool,
+    follow_offset: u64,
+    workspace_root: Option<PathBuf>,
+    persistent: PersistentState,
+    settings: Settings,
+    diagnostics: DiagnosticsStore,
+    diagnostics_running: bool,
+    diagnostics_pending: bool,
+    language_server: LanguageServer,
+    caret_history: CaretHistory,
+}
+
+// Longest title an Untitled tab takes from its first line
+const DRAFT_TITLE_CHARS: usize = 30;
+
+// Lines the caret has to move at once for its old position to go into the caret history
+const CARET_JUMP_LINES: i32 = 10;
+// Most positions kept in each direction of the caret history
+const MAX_CARET_HISTORY: usize = 50;
+
+// Positions the caret jumped away from in one tab, to step back and forth through with
+// Ctrl+Alt+- and Ctrl+Alt+=. Marks keep each position on its text as the tab is edited.
+#[derive(Default)]
+struct CaretHistory {
+    back: Vec<gtk::TextMark>,
+    forward: Vec<gtk::TextMark>,
+    // Where the caret was last seen
+    last: Option<gtk::TextMark>,
+}
+
+impl CaretHistory {
+    // Note that the caret moved to `cursor`, recording where it came from on a jump
+    fn caret_moved(&mut self, buffer: &gtk::TextBuffer, cursor: &gtk::TextIter) {
+        let last = self.last.as_ref().filter(|last| last.buffer().as_ref() == Some(buffer));
+        let Some(last) = last else {
+            self.last = Some(buffer.create_mark(None, cursor, true));
+            return;
+        };
+        let from = buffer.iter_at_mark(last);
+        if (from.line() - cursor.line()).abs() >= CARET_JUMP_LINES {
+            self.back.push(buffer.create_mark(None, &from, true));
+            if self.back.len() > MAX_CARET_HISTORY {
+                let oldest = self.back.remove(0);
+                buffer.delete_mark(&oldest);
+            }
+            for mark in self.forward.drain(..) {
+                buffer.delete_mark(&mark);
+            }
+        }
+        buffer.move_mark(last, cursor);
+    }
+
+    // Take the previous position, or the next one when `forward`, leaving the caret's
+    // current one to come back to
+    fn step(&mut self, buffer: &gtk::TextBuffer, forward: bool) -> Option<gtk::TextIter> {
+        let (from, to) = if forward { (&mut self.forward, &mut self.back) } else { (&mut self.back, &mut self.forward) };
+        let mark = from.pop().filter(|mark| mark.buffer().as_ref() == Some(buffer))?;
+        let target = buffer.iter_at_mark(&mark);
+        buffer.delete_mark(&mark);
+        to.push(buffer.create_mark(None, &buffer.iter_at_mark(&buffer.get_insert()), true));
+        // Moving there is not a jump of its own
+        if let Some(last) = &self.last {
+            buffer.move_mark(last, &target);
+        }
+        Some(target)
+    }
+}
+
+// Lifecycle of the language server, started lazily for the first Rust file
+enum LanguageServer {
+    NotStarted,
+    Starting,
+    Running(Arc<LspClient>),
+    Unavailable,
 }
 
 impl EditorState {
@@ -78,39 +199,142 @@ impl EditorState {
             selection_start: None,
             selection_end: None,
             zoom_level: 1.0,
-            recent_files: RecentFilesManager::new(10),
             tab_name: "Untitled".to_string(),
             active_tab_id: 0,
+            recovery_id: NEXT_RECOVERY_ID.fetch_add(1, Ordering::Relaxed),
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
+            restoring_history: false,
             last_saved_text: None,
+            last_saved_hash: content_hash([""]),
             timeout_id: None,
+            large_file_mode: false,
+            wrap_long_lines: false,
+            read_only: false,
+            syntax_highlighting: true,
+            language_override: None,
+            encoding: encoding_rs::UTF_8,
+            write_bom: false,
+            line_ending: LineEnding::Lf,
+            saved_line_ending: LineEnding::Lf,
+            following: false,
+            follow_offset: 0,
+            // Treat the launch directory as the workspace unless we were started from $HOME or /
+            workspace_root: env::current_dir()
+                .ok()
+                .filter(|dir| *dir != glib::home_dir() && dir.parent().is_some()),
+            persistent: PersistentState::load(),
+            settings: Settings::load(),
+            diagnostics: DiagnosticsStore::default(),
+            diagnostics_running: false,
+            diagnostics_pending: false,
+            language_server: LanguageServer::NotStarted,
+            caret_history: CaretHistory::default(),
         }
     }
 
-    fn open_file(&mut self, path: &PathBuf) -> Result<String> {
-        let content = fs::read_to_string(path)?;
-        self.current_file = Some(path.clone());
+    // Move everything that belongs to the editor as a whole, rather than to one tab,
+    // over to the state of the tab being switched to
+    fn hand_over_app_state(&mut self, incoming: &mut EditorState) {
+        std::mem::swap(&mut self.persistent, &mut incoming.persistent);
+        std::mem::swap(&mut self.settings, &mut incoming.settings);
+        std::mem::swap(&mut self.language_server, &mut incoming.language_server);
+        std::mem::swap(&mut self.diagnostics, &mut incoming.diagnostics);
+        std::mem::swap(&mut self.diagnostics_running, &mut incoming.diagnostics_running);
+        std::mem::swap(&mut self.diagnostics_pending, &mut incoming.diagnostics_pending);
+        std::mem::swap(&mut self.workspace_root, &mut incoming.workspace_root);
+        std::mem::swap(&mut self.active_tab_id, &mut incoming.active_tab_id);
+        std::mem::swap(&mut self.timeout_id, &mut incoming.timeout_id);
+    }
+
+    // Take over content that was already read from `path`
+    fn load_file_content(&mut self, path: &Path, content: &str) {
+        self.current_file = Some(path.to_path_buf());
         self.is_modified = false;
-        self.text_buffer.set_text(&content);
-        self.recent_files.add_file(path.clone());
+        self.following = false;
+        self.read_only = !FileAccess::current().can_write(path);
+        self.text_buffer.set_text(content);
+        self.remember_recent_file(path);
+        self.remember_directory(path);
         self.update_tab_name();
         self.undo_stack.clear();
         self.redo_stack.clear();
         self.mark_saved();
-        Ok(content)
     }
 
-    fn save_file(&mut self, path: &PathBuf) -> Result<()> {
-        fs::write(path, self.text_buffer.text())?;
-        self.current_file = Some(path.clone());
+    fn save_file(&mut self, path: &Path) -> Result<()> {
+        FileAccess::current().write(path, &self.encode_text(self.text_buffer.text())?, None)?;
+        self.current_file = Some(path.to_path_buf());
         self.is_modified = false;
-        self.recent_files.add_file(path.clone());
+        self.remember_recent_file(path);
+        self.remember_directory(path);
         self.update_tab_name();
         self.mark_saved();
         Ok(())
     }
 
+    // `text` as it is written to the tab's file, in its line ending style and encoding
+    fn encode_text(&self, text: &str) -> Result<Vec<u8>> {
+        encoding::encode(&self.line_ending.apply(text), self.encoding, self.write_bom)
+    }
+
+    // Directory file dialogs should start in: the current file's folder, then the
+    // workspace root, then wherever the user last opened or saved something
+    fn dialog_directory(&self) -> Option<PathBuf> {
+        let current_dir = self.current_file
+            .as_ref()
+            .and_then(|path| path.parent())
+            .map(Path::to_path_buf);
+
+        [current_dir, self.workspace_root.clone(), self.persistent.last_directory.clone()]
+            .into_iter()
+            .flatten()
+            .find(|dir| dir.is_dir())
+    }
+
+    fn remember_filter(&mut self, name: &str) {
+        if self.persistent.last_filter.as_deref() == Some(name) {
+            return;
+        }
+        self.persistent.last_filter = Some(name.to_string());
+        if let Err(e) = self.persistent.save() {
+            warn!("Failed to save editor state: {}", e);
+        }
+    }
+
+    fn remember_directory(&mut self, path: &Path) {
+        if let Some(dir) = path.parent() {
+            if self.persistent.last_directory.as_deref() == Some(dir) {
+                return;
+            }
+            self.persistent.last_directory = Some(dir.to_path_buf());
+            if let Err(e) = self.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+    }
+
+    // Keep the language picked for the tab's file, so it is used again when the file is opened
+    fn remember_language_override(&mut self, language: Option<String>) {
+        let Some(path) = self.current_file.clone() else {
+            return;
+        };
+        match language {
+            Some(language) => self.persistent.language_overrides.insert(path, language),
+            None => self.persistent.language_overrides.remove(&path),
+        };
+        if let Err(e) = self.persistent.save() {
+            warn!("Failed to save editor state: {}", e);
+        }
+    }
+
+    fn remember_recent_file(&mut self, path: &Path) {
+        self.persistent.add_recent_file(path);
+        if let Err(e) = self.persistent.save() {
+            warn!("Failed to save editor state: {}", e);
+        }
+    }
+
     fn insert_text(&mut self, text: &str) {
         self.text_buffer.insert(text);
         self.is_modified = true;
@@ -187,8 +411,48 @@ impl EditorState {
         }
     }
 
-    fn push_to_undo_stack(&mut self, text: &str) {
-        self.undo_stack.push(text.to_string());
+    // Title for the tab's label. Until it is saved, an Untitled tab is named after its
+    // first line with text, so scratch tabs can be told apart.
+    fn tab_title(&self) -> String {
+        if self.current_file.is_some() || !self.tab_name.starts_with("Untitled") {
+            return self.tab_name.clone();
+        }
+        let Some(line) = self.text_buffer.text().lines().map(str::trim).find(|line| !line.is_empty()) else {
+            return self.tab_name.clone();
+        };
+        if line.chars().count() <= DRAFT_TITLE_CHARS {
+            return line.to_string();
+        }
+        let mut title: String = line.chars().take(DRAFT_TITLE_CHARS - 1).collect();
+        title.push('…');
+        title
+    }
+
+    // Tooltip of the tab: its file's path, then how the text is saved and highlighted
+    fn tab_tooltip(&self) -> String {
+        let location = self.current_file.as_ref().map_or_else(|| "Not saved to a file".to_string(), |path| path.display().to_string());
+        // The size on disk, or of the text for a tab without a file
+        let size = self.current_file.as_ref()
+            .and_then(|path| fs::metadata(path).ok())
+            .map_or(self.text_buffer.text().len() as u64, |metadata| metadata.len());
+        let mut tooltip = format!(
+            "{}\n{}, {} lines\n{} · {} · {} · {}",
+            location,
+            format_size(size),
+            self.text_buffer.line_count(),
+            self.language().map_or(language::PLAIN_TEXT, |language| language.name),
+            encoding_label(self.encoding, self.write_bom),
+            self.line_ending.label(),
+            self.indentation().label()
+        );
+        if self.read_only {
+            tooltip.push_str("\nRead-only");
+        }
+        tooltip
+    }
+
+    fn push_to_undo_stack(&mut self, text: Rope) {
+        self.undo_stack.push(text);
         if self.undo_stack.len() > 100 {
             // Limit the size of the undo stack
             self.undo_stack.remove(0);
@@ -199,11 +463,7 @@ impl EditorState {
 
     fn undo(&mut self) -> Option<String> {
         if let Some(current_text) = self.undo_stack.pop() {
-            let previous_text = if self.undo_stack.is_empty() {
-                String::new()
-            } else {
-                self.undo_stack.last().unwrap().clone()
-            };
+            let previous_text = self.undo_stack.last().map(Rope::to_string).unwrap_or_default();
             self.redo_stack.push(current_text);
             Some(previous_text)
         } else {
@@ -213,98 +473,570 @@ impl EditorState {
 
     fn redo(&mut self) -> Option<String> {
         if let Some(next_text) = self.redo_stack.pop() {
-            self.undo_stack.push(next_text.clone());
-            Some(next_text)
+            let text = next_text.to_string();
+            self.undo_stack.push(next_text);
+            Some(text)
         } else {
             None
         }
     }
 
+    // Language picked in the status bar, otherwise the one detected from the file
+    fn language(&self) -> Option<&'static language::Language> {
+        match &self.language_override {
+            Some(name) => language::by_name(name),
+            None => self.detected_language(),
+        }
+    }
+
+    // How Tab indents in this tab's language
+    fn indentation(&self) -> Indentation {
+        self.settings.indentation(self.language().map_or(language::PLAIN_TEXT, |language| language.name))
+    }
+
+    // Give the mirror the word characters of this tab's language, for word selection and motion
+    fn sync_word_chars(&mut self) {
+        let chars = self.settings.word_chars(self.language().map_or(language::PLAIN_TEXT, |language| language.name)).to_string();
+        self.text_buffer.set_extra_word_chars(&chars);
+    }
+
+    // How this tab's text is tidied when saved
+    fn save_cleanup(&self) -> SaveCleanup {
+        self.settings.save_cleanup(self.language().map_or(language::PLAIN_TEXT, |language| language.name))
+    }
+
+    // Theme to draw the window in while this tab is shown
+    fn theme_name(&self) -> &str {
+        self.settings.theme_for(self.language().map_or(language::PLAIN_TEXT, |language| language.name))
+    }
+
+    fn detected_language(&self) -> Option<&'static language::Language> {
+        language::detect(self.current_file.as_deref(), &self.text_buffer.line_content(0))
+    }
+
+    // Compares lengths before any text, so that checking after every keystroke stays cheap
     fn is_modified_from_last_save(&self) -> bool {
-        if let Some(last_saved) = &self.last_saved_text {
-            last_saved != self.text_buffer.text()
-        } else {
-            self.text_buffer.text().len() > 0
+        // A different hash settles it; a matching one is confirmed against the saved text
+        let saved = content_hash(self.text_buffer.rope().chunks()) == self.last_saved_hash
+            && self.text_buffer.content_eq(self.last_saved_text.as_deref().unwrap_or_default());
+        !saved || self.line_ending != self.saved_line_ending
+    }
+
+    // Bring the mirrored text in line with the view's buffer after an edit. Unless it starts
+    // a new undo step, the edit is undone together with the one before it.
+    fn mirror_text(&mut self, text: &str, new_undo_step: bool) {
+        // Only push to undo stack if content actually changed
+        if self.text_buffer.text() != text {
+            // Store current text before modifying it
+            if new_undo_step && !self.restoring_history {
+                self.push_to_undo_stack(self.text_buffer.rope().clone());
+            }
+            self.text_buffer.set_text(text);
+        }
+        self.refresh_modified();
+    }
+
+    // Make the edit noted for the view's buffer in the mirror as well, which keeps typing from
+    // copying the whole text. Without one, or with the mirror out of step with the buffer
+    // before it, the whole text is copied after all.
+    fn mirror_buffer_edit(&mut self, buffer: &gtk::TextBuffer, edit: Option<BufferEdit>, new_undo_step: bool) {
+        let char_count = buffer.char_count() as usize;
+        let edit = edit.filter(|edit| match edit {
+            BufferEdit::Insert(_, text) => self.text_buffer.char_count() + text.chars().count() == char_count,
+            BufferEdit::Delete(range) => self.text_buffer.char_count() == char_count + range.len(),
+        });
+        let Some(edit) = edit else {
+            self.mirror_text(buffer.text(&buffer.start_iter(), &buffer.end_iter(), false).as_str(), new_undo_step);
+            return;
+        };
+        if new_undo_step && !self.restoring_history {
+            self.push_to_undo_stack(self.text_buffer.rope().clone());
+        }
+        match edit {
+            BufferEdit::Insert(at, text) => self.text_buffer.insert_at_char(at, &text),
+            BufferEdit::Delete(range) => self.text_buffer.remove_chars(range),
         }
+        self.refresh_modified();
+    }
+
+    // Clears the modified flag once edits (or undos) bring the text back to the saved version
+    fn refresh_modified(&mut self) {
+        self.is_modified = self.is_modified_from_last_save();
     }
 
     fn mark_saved(&mut self) {
         self.is_modified = false;
         self.last_saved_text = Some(self.text_buffer.text().to_string());
+        self.last_saved_hash = content_hash(self.text_buffer.rope().chunks());
+        self.saved_line_ending = self.line_ending;
+    }
+
+    // Take `text`, written with `line_ending`, as the saved version. Edits made while it was
+    // being written keep the tab modified.
+    fn mark_written(&mut self, text: &str, line_ending: LineEnding) {
+        self.last_saved_text = Some(text.to_string());
+        self.last_saved_hash = content_hash([text]);
+        self.saved_line_ending = line_ending;
+        self.is_modified = self.is_modified_from_last_save();
+    }
+
+    // Keep a recovery copy while the tab has unsaved changes, and drop it once it has none
+    fn update_recovery_file(&self) {
+        if !self.is_modified {
+            recovery::remove(self.recovery_id);
+            return;
+        }
+        let file = recovery::RecoveryFile {
+            path: self.current_file.clone(),
+            tab_name: self.tab_name.clone(),
+            text: self.text_buffer.text().to_string(),
+        };
+        if let Err(e) = recovery::write(self.recovery_id, &file) {
+            warn!("Failed to write recovery file for {}: {}", self.tab_name, e);
+        }
+    }
+}
+
+// Hash of text given in pieces, such as the chunks of a rope; how it is split does not
+// change the hash
+fn content_hash<'a>(pieces: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for piece in pieces {
+        hasher.write(piece.as_bytes());
     }
+    hasher.finish()
+}
+
+// An edit the view made to its buffer, in characters, noted before the buffer makes it
+enum BufferEdit {
+    Insert(usize, String),
+    Delete(Range<usize>),
+}
+
+// Note the edits of `buffer` as it is about to make them, for its changed signal to mirror.
+// The buffer cannot be touched before it makes the change, hence the two steps.
+fn connect_pending_edits(buffer: &gtk::TextBuffer) -> Rc<RefCell<Option<BufferEdit>>> {
+    let pending = Rc::new(RefCell::new(None));
+    let pending_ref = pending.clone();
+    buffer.connect_insert_text(move |_, location, text| {
+        *pending_ref.borrow_mut() = Some(BufferEdit::Insert(location.offset() as usize, text.to_string()));
+    });
+    let pending_ref = pending.clone();
+    buffer.connect_delete_range(move |_, start, end| {
+        *pending_ref.borrow_mut() = Some(BufferEdit::Delete(start.offset() as usize..end.offset() as usize));
+    });
+    pending
+}
+
+// A tab's buffer, its label and button in the tab bar, and its editor state while another tab is active
+struct Tab {
+    buffer: gtk::TextBuffer,
+    label: gtk::Label,
+    // Lock shown beside the label while the tab is read-only
+    lock: gtk::Image,
+    button: gtk::Button,
+    parked_state: Option<EditorState>,
 }
 
-// Define a TabInfo struct to track tab data
-struct TabInfo {
-    id: usize,
+// What the tab overview shows for one tab
+struct TabSummary {
     name: String,
+    file: Option<PathBuf>,
+    modified: bool,
     buffer: gtk::TextBuffer,
-    file_path: Option<PathBuf>,
-    is_modified: bool,
+    button: gtk::Button,
+}
+
+// Gives every tab its own EditorState. The active tab's state lives in the shared
+// Rc<RefCell<EditorState>> that all handlers already use, so save/open/undo and the
+// status bar act on the active tab; the other tabs' states are parked until switched to.
+struct TabManager {
+    state: Rc<RefCell<EditorState>>,
+    tabs: Vec<Tab>,
+    active: usize,
 }
 
-impl TabInfo {
-    fn new(id: usize, buffer: gtk::TextBuffer) -> Self {
+impl TabManager {
+    fn new(state: Rc<RefCell<EditorState>>) -> Self {
         Self {
-            id,
-            name: format!("Untitled {}", id),
-            buffer,
-            file_path: None,
-            is_modified: false,
+            state,
+            tabs: Vec::new(),
+            active: 0,
         }
     }
-    
-    fn update_name(&mut self) {
-        if let Some(path) = &self.file_path {
-            if let Some(file_name) = path.file_name() {
-                self.name = file_name.to_string_lossy().to_string();
+
+    // The first tab takes over the shared state, later tabs start with a fresh one
+    fn add_tab(&mut self, buffer: &gtk::TextBuffer, label: &gtk::Label, lock: &gtk::Image, button: &gtk::Button) {
+        let parked_state = if self.tabs.is_empty() {
+            None
+        } else {
+            let mut state = EditorState::new();
+            state.tab_name = label.text().to_string();
+            Some(state)
+        };
+
+        self.tabs.push(Tab {
+            buffer: buffer.clone(),
+            label: label.clone(),
+            lock: lock.clone(),
+            button: button.clone(),
+            parked_state,
+        });
+    }
+
+    fn remove_tab(&mut self, buffer: &gtk::TextBuffer) {
+        let Some(index) = self.tabs.iter().position(|tab| tab.buffer == *buffer) else {
+            return;
+        };
+
+        // The view has already switched away from a closed active tab, so its state is parked
+        if index != self.active {
+            if let Some(state) = &self.tabs[index].parked_state {
+                recovery::remove(state.recovery_id);
+                if let (Some(path), Ok(mut shown)) = (&state.current_file, self.state.try_borrow_mut()) {
+                    shown.persistent.remember_file_position(path, file_position(&self.tabs[index].buffer));
+                    if let Err(e) = shown.persistent.save() {
+                        warn!("Failed to save editor state: {}", e);
+                    }
+                }
+            }
+            self.tabs.remove(index);
+            if index < self.active {
+                self.active -= 1;
             }
+        }
+    }
+
+    // Park the active tab's state and bring in the state of the tab owning `buffer`
+    fn activate(&mut self, buffer: &gtk::TextBuffer) {
+        let Some(index) = self.tabs.iter().position(|tab| tab.buffer == *buffer) else {
+            return;
+        };
+        if index == self.active {
+            return;
+        }
+
+        let Some(mut incoming) = self.tabs[index].parked_state.take() else {
+            return;
+        };
+
+        match self.state.try_borrow_mut() {
+            Ok(mut state) => {
+                state.hand_over_app_state(&mut incoming);
+                let outgoing = std::mem::replace(&mut *state, incoming);
+                self.tabs[self.active].parked_state = Some(outgoing);
+                self.active = index;
+            }
+            Err(_) => self.tabs[index].parked_state = Some(incoming),
+        }
+    }
+
+    // Name, file and modified flag of every tab, in tab order
+    fn summaries(&self) -> Vec<TabSummary> {
+        let active_state = self.state.try_borrow().ok();
+        self.tabs.iter().enumerate().filter_map(|(index, tab)| {
+            let state = if index == self.active {
+                active_state.as_deref()?
+            } else {
+                tab.parked_state.as_ref()?
+            };
+            Some(TabSummary {
+                name: state.tab_title(),
+                file: state.current_file.clone(),
+                modified: state.is_modified,
+                buffer: tab.buffer.clone(),
+                button: tab.button.clone(),
+            })
+        }).collect()
+    }
+
+    // Refresh the recovery copies of all tabs, or drop them when autosave is off
+    fn update_recovery_files(&self) {
+        let active_state = self.state.try_borrow().ok();
+        let enabled = active_state.as_ref().is_some_and(|state| state.settings.autosave);
+        for (index, tab) in self.tabs.iter().enumerate() {
+            let state = if index == self.active {
+                active_state.as_deref()
+            } else {
+                tab.parked_state.as_ref()
+            };
+            match state {
+                Some(state) if enabled => state.update_recovery_file(),
+                Some(state) => recovery::remove(state.recovery_id),
+                None => {}
+            }
+        }
+    }
+
+    // Files shown in the tabs with each tab's zoom level, in tab order
+    fn open_files(&self) -> Vec<(PathBuf, f64)> {
+        self.tabs.iter().enumerate().filter_map(|(index, tab)| {
+            if index == self.active {
+                let state = self.state.try_borrow().ok()?;
+                Some((state.current_file.clone()?, state.zoom_level))
+            } else {
+                let state = tab.parked_state.as_ref()?;
+                Some((state.current_file.clone()?, state.zoom_level))
+            }
+        }).collect()
+    }
+
+    // File shown in the tab owning `buffer`, whether or not that tab is active
+    fn file_for(&self, buffer: &gtk::TextBuffer) -> Option<PathBuf> {
+        let index = self.tabs.iter().position(|tab| tab.buffer == *buffer)?;
+        if index == self.active {
+            self.state.try_borrow().ok()?.current_file.clone()
+        } else {
+            self.tabs[index].parked_state.as_ref()?.current_file.clone()
+        }
+    }
+
+    // Tooltip of the tab owning `buffer`, made from its state when it is asked for
+    fn tooltip_for(&self, buffer: &gtk::TextBuffer) -> Option<String> {
+        let index = self.index_of(buffer)?;
+        if index == self.active {
+            Some(self.state.try_borrow().ok()?.tab_tooltip())
+        } else {
+            Some(self.tabs[index].parked_state.as_ref()?.tab_tooltip())
+        }
+    }
+
+    // Whether the tab owning `buffer` has unsaved changes
+    fn is_modified(&self, buffer: &gtk::TextBuffer) -> bool {
+        let Some(index) = self.tabs.iter().position(|tab| tab.buffer == *buffer) else {
+            return false;
+        };
+        if index == self.active {
+            self.state.try_borrow().map(|state| state.is_modified).unwrap_or(false)
         } else {
-            self.name = format!("Untitled {}", self.id);
+            self.tabs[index].parked_state.as_ref().is_some_and(|state| state.is_modified)
+        }
+    }
+
+    // Mirror an edit made in the split pane into the state of a tab that is not the shown
+    // one. Returns the language to highlight the buffer in, unless highlighting is off.
+    fn mirror_parked_edit(&mut self, buffer: &gtk::TextBuffer, edit: Option<BufferEdit>) -> Option<Option<&'static language::Language>> {
+        let index = self.tabs.iter().position(|tab| tab.buffer == *buffer)?;
+        if index == self.active {
+            return None;
+        }
+        let tab = &mut self.tabs[index];
+        let state = tab.parked_state.as_mut()?;
+        state.mirror_buffer_edit(buffer, edit, true);
+        let modified_marker = if state.is_modified { "*" } else { "" };
+        tab.label.set_text(&format!("{}{}", modified_marker, state.tab_title()));
+        (state.syntax_highlighting && !state.large_file_mode).then(|| state.language())
+    }
+
+    // Move the tab owning `buffer` to `index` in the tab order, or to the end if `index` is
+    // past it. Returns the tab bar button of the tab now in front of it, if any.
+    fn move_tab(&mut self, buffer: &gtk::TextBuffer, index: usize) -> Option<Option<gtk::Button>> {
+        let from = self.tabs.iter().position(|tab| tab.buffer == *buffer)?;
+        let to = index.min(self.tabs.len() - 1);
+        let active_buffer = self.tabs[self.active].buffer.clone();
+        let tab = self.tabs.remove(from);
+        self.tabs.insert(to, tab);
+        self.active = self.tabs.iter().position(|tab| tab.buffer == active_buffer).unwrap_or(to);
+        Some(to.checked_sub(1).map(|previous| self.tabs[previous].button.clone()))
+    }
+
+    // Change the state of the tab owning `buffer`, whether it is the shown one or parked.
+    // A parked tab's label is refreshed here, the shown tab's by the label timer.
+    fn update_state(&mut self, buffer: &gtk::TextBuffer, update: impl FnOnce(&mut EditorState)) {
+        let Some(index) = self.index_of(buffer) else {
+            return;
+        };
+        if index == self.active {
+            if let Ok(mut state) = self.state.try_borrow_mut() {
+                update(&mut state);
+            }
+            return;
+        }
+        let tab = &mut self.tabs[index];
+        if let Some(state) = tab.parked_state.as_mut() {
+            update(state);
+            let modified_marker = if state.is_modified { "*" } else { "" };
+            tab.label.set_text(&format!("{}{}", modified_marker, state.tab_title()));
+        }
+    }
+
+    // Position of the tab owning `buffer` in the tab order
+    fn index_of(&self, buffer: &gtk::TextBuffer) -> Option<usize> {
+        self.tabs.iter().position(|tab| tab.buffer == *buffer)
+    }
+
+    // Show or hide the lock of the tab owning `buffer`
+    fn show_lock(&self, buffer: &gtk::TextBuffer, locked: bool) {
+        if let Some(tab) = self.tabs.iter().find(|tab| tab.buffer == *buffer) {
+            tab.lock.set_visible(locked);
         }
     }
+
+    // Tab bar button of the tab owning `buffer`, if it is still open
+    fn button_for(&self, buffer: &gtk::TextBuffer) -> Option<gtk::Button> {
+        self.tabs.iter().find(|tab| tab.buffer == *buffer).map(|tab| tab.button.clone())
+    }
+
+    fn active_buffer(&self) -> Option<gtk::TextBuffer> {
+        self.tabs.get(self.active).map(|tab| tab.buffer.clone())
+    }
+
+    fn active_label(&self) -> Option<gtk::Label> {
+        self.tabs.get(self.active).map(|tab| tab.label.clone())
+    }
+}
+
+thread_local! {
+    // The window's tabs, for file reads and writes that finish after another tab was shown
+    static TABS: RefCell<Option<Rc<RefCell<TabManager>>>> = const { RefCell::new(None) };
+    // Buffers of tabs a file is being read into
+    static LOADING_BUFFERS: RefCell<Vec<gtk::TextBuffer>> = const { RefCell::new(Vec::new()) };
+}
+
+// Show the tab owning `buffer` unless it is the shown one. False if it has been closed.
+fn show_tab_of(text_view: &gtk::TextView, buffer: &gtk::TextBuffer) -> bool {
+    if text_view.buffer() == *buffer {
+        return true;
+    }
+    let button = TABS.with(|tabs| tabs.borrow().as_ref().and_then(|tabs| tabs.borrow().button_for(buffer)));
+    let Some(button) = button else {
+        return false;
+    };
+    button.emit_clicked();
+    text_view.buffer() == *buffer
+}
+
+fn update_tab_state(buffer: &gtk::TextBuffer, update: impl FnOnce(&mut EditorState)) {
+    TABS.with(|tabs| {
+        if let Some(tabs) = tabs.borrow().as_ref() {
+            tabs.borrow_mut().update_state(buffer, update);
+        }
+    });
+}
+
+// Whether a file can go into the shown tab instead of a new one: it has no file, no text,
+// and no file on its way in
+fn shown_tab_is_empty(tab_bar: &TabBar) -> bool {
+    let loading = LOADING_BUFFERS.with(|loading| loading.borrow().contains(&tab_bar.text_view.buffer()));
+    !loading && tab_bar.editor_state.try_borrow()
+        .map(|state| state.current_file.is_none() && state.text_buffer.text().is_empty())
+        .unwrap_or(false)
+}
+
+thread_local! {
+    static TAG_TABLE: RefCell<Option<TextTagTable>> = const { RefCell::new(None) };
+}
+
+// Tags are the same for every buffer, so one table is built on first use and shared
+fn shared_tag_table() -> TextTagTable {
+    TAG_TABLE.with(|cell| cell.borrow_mut().get_or_insert_with(create_tag_table).clone())
 }
 
 fn create_tag_table() -> TextTagTable {
     let tag_table = TextTagTable::new();
     
-    // Create syntax highlighting tags with dark mode friendly colors
+    // Create syntax highlighting tags; their colors come from the theme
     let keyword_tag = TextTag::builder()
         .name("keyword")
-        .foreground("#569CD6")  // Light blue for keywords
         .build();
     
     let function_tag = TextTag::builder()
         .name("function")
-        .foreground("#DCDCAA")  // Light yellow for functions
         .build();
     
     let type_tag = TextTag::builder()
         .name("type")
-        .foreground("#4EC9B0")  // Teal for types
         .build();
     
     let string_tag = TextTag::builder()
         .name("string")
-        .foreground("#CE9178")  // Rust/brown for strings
         .build();
     
     let number_tag = TextTag::builder()
         .name("number")
-        .foreground("#B5CEA8")  // Light green for numbers
         .build();
     
     let comment_tag = TextTag::builder()
         .name("comment")
-        .foreground("#6A9955")  // Green for comments
         .build();
     
     let error_tag = TextTag::builder()
         .name("error")
-        .foreground("#F44747")  // Bright red for errors
         .underline(pango::Underline::Error)
         .build();
     
+    // Diff view tags: whole changed lines plus stronger intra-line changes
+    let diff_header_tag = TextTag::builder()
+        .name("diff-header")
+        .weight(700)
+        .build();
+    
+    let diff_added_tag = TextTag::builder()
+        .name("diff-added")
+        .build();
+    
+    let diff_removed_tag = TextTag::builder()
+        .name("diff-removed")
+        .build();
+    
+    // Side-by-side diffs color each column's cell rather than the whole line
+    let diff_added_cell_tag = TextTag::builder()
+        .name("diff-added-cell")
+        .build();
+    
+    let diff_removed_cell_tag = TextTag::builder()
+        .name("diff-removed-cell")
+        .build();
+    
+    let diff_added_inline_tag = TextTag::builder()
+        .name("diff-added-inline")
+        .build();
+    
+    let diff_removed_inline_tag = TextTag::builder()
+        .name("diff-removed-inline")
+        .build();
+    
+    // Semantic tags from the language server; added last so they win over the syntactic ones
+    let semantic_parameter_tag = TextTag::builder()
+        .name("semantic-parameter")
+        .style(pango::Style::Italic)
+        .build();
+    
+    let semantic_variable_tag = TextTag::builder()
+        .name("semantic-variable")
+        .build();
+    
+    let semantic_field_tag = TextTag::builder()
+        .name("semantic-field")
+        .build();
+    
+    // The bracket pair around the cursor, or a bracket at the cursor without a partner
+    let bracket_match_tag = TextTag::builder()
+        .name("bracket-match")
+        .weight(700)
+        .build();
+    
+    let bracket_unmatched_tag = TextTag::builder()
+        .name("bracket-unmatched")
+        .weight(700)
+        .underline(pango::Underline::Error)
+        .build();
+    
+    let trailing_whitespace_tag = TextTag::builder()
+        .name("trailing-whitespace")
+        .build();
+    
+    // Words repeated right after themselves, found by the prose analysis
+    let repeated_word_tag = TextTag::builder()
+        .name("repeated-word")
+        .underline(pango::Underline::Error)
+        .build();
+    
+    // Focus mode dimming; added after every colored tag so it wins over them
+    let focus_dim_tag = TextTag::builder()
+        .name("focus-dim")
+        .build();
+    
     // Add tags to the table
     tag_table.add(&keyword_tag);
     tag_table.add(&function_tag);
@@ -313,10 +1045,70 @@ fn create_tag_table() -> TextTagTable {
     tag_table.add(&number_tag);
     tag_table.add(&comment_tag);
     tag_table.add(&error_tag);
+    tag_table.add(&diff_header_tag);
+    tag_table.add(&diff_added_tag);
+    tag_table.add(&diff_removed_tag);
+    tag_table.add(&diff_added_cell_tag);
+    tag_table.add(&diff_removed_cell_tag);
+    tag_table.add(&diff_added_inline_tag);
+    tag_table.add(&diff_removed_inline_tag);
+    tag_table.add(&semantic_parameter_tag);
+    tag_table.add(&semantic_variable_tag);
+    tag_table.add(&semantic_field_tag);
+    tag_table.add(&bracket_match_tag);
+    tag_table.add(&bracket_unmatched_tag);
+    tag_table.add(&trailing_whitespace_tag);
+    tag_table.add(&repeated_word_tag);
+    tag_table.add(&focus_dim_tag);
     
+    CURRENT_THEME.with(|theme| color_tags(&tag_table, &theme.borrow()));
     tag_table
 }
 
+// Give the tags of `tag_table` the colors of `theme`
+fn color_tags(tag_table: &TextTagTable, theme: &theme::Theme) {
+    for (name, color) in [
+        ("keyword", &theme.keyword),
+        ("function", &theme.function),
+        ("type", &theme.type_name),
+        ("string", &theme.string),
+        ("number", &theme.number),
+        ("comment", &theme.comment),
+        ("error", &theme.error),
+        ("diff-header", &theme.keyword),
+        ("semantic-parameter", &theme.parameter),
+        ("semantic-variable", &theme.variable),
+        ("semantic-field", &theme.field),
+        ("bracket-unmatched", &theme.error),
+        ("focus-dim", &theme.focus_dim),
+    ] {
+        if let Some(tag) = tag_table.lookup(name) {
+            tag.set_foreground(Some(color));
+        }
+    }
+    for (name, color) in [("diff-added", &theme.diff_added), ("diff-removed", &theme.diff_removed)] {
+        if let Some(tag) = tag_table.lookup(name) {
+            tag.set_paragraph_background(Some(color));
+        }
+    }
+    for (name, color) in [
+        ("diff-added-cell", &theme.diff_added),
+        ("diff-removed-cell", &theme.diff_removed),
+        ("diff-added-inline", &theme.diff_added_inline),
+        ("diff-removed-inline", &theme.diff_removed_inline),
+        ("line-highlight", &theme.current_line),
+        ("bracket-match", &theme.bracket_match),
+        ("trailing-whitespace", &theme.trailing_whitespace),
+    ] {
+        if let Some(tag) = tag_table.lookup(name) {
+            tag.set_background(Some(color));
+        }
+    }
+    if let Some(tag) = tag_table.lookup("repeated-word") {
+        tag.set_underline_rgba(gtk::gdk::RGBA::parse(theme.diagnostic_warning.as_str()).ok().as_ref());
+    }
+}
+
 fn create_tab_transition<W: IsA<gtk::Widget>>(widget: &W) {
     let provider = gtk::CssProvider::new();
     provider.load_from_data(
@@ -330,1634 +1122,10499 @@ fn create_tab_transition<W: IsA<gtk::Widget>>(widget: &W) {
     widget.add_css_class("tab-transition");
 }
 
-fn create_menu_bar(window: &gtk::ApplicationWindow, buffer: &gtk::TextBuffer, editor_state: Arc<Mutex<EditorState>>, status_label: gtk::Label, text_view: &gtk::TextView) -> (gtk::Box, gtk::Button, gtk::Button, gtk::Button, gtk::Button, gtk::Button, gtk::Box, gtk::Button, gtk::Button, gtk::CheckButton) {
-    // Create the main vertical container for menu and tabs
-    let main_container = gtk::Box::new(gtk::Orientation::Vertical, 0);
-    main_container.set_css_classes(&["main-menu-container"]);
+// Save the shown tab to its file, asking for a name when it has none, then run `on_saved`.
+// Nothing runs when the dialog is cancelled; when writing fails the user is told why and
+// can retry or pick another file.
+fn save_shown_tab<F: Fn() + 'static>(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, on_saved: F) {
+    let current_file = match state.try_borrow() {
+        Ok(state) => state.current_file.clone(),
+        Err(_) => return,
+    };
+    let Some(path) = current_file else {
+        save_shown_tab_as(window, text_view, state, on_saved);
+        return;
+    };
+
+    let (window_ref, text_view_ref, state_ref, failed_path) = (window.clone(), text_view.clone(), state.clone(), path.clone());
+    write_tab(&text_view.buffer(), state, &path, move |written| match written {
+        Ok(_) => on_saved(),
+        Err(e) => show_save_error(&window_ref, &text_view_ref, &state_ref, &failed_path, &e, Rc::new(on_saved)),
+    });
+}
+
+// Ask for a file name and save the shown tab there, then run `on_saved`
+fn save_shown_tab_as<F: Fn() + 'static>(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, on_saved: F) {
+    let dialog = gtk::FileChooserNative::builder()
+        .title("Save File As")
+        .action(gtk::FileChooserAction::Save)
+        .accept_label("Save")
+        .cancel_label("Cancel")
+        .transient_for(window)
+        .modal(true)
+        .build();
+        
+    // Set current filename and folder if available
+    if let Ok(state) = state.try_borrow() {
+        if let Some(path) = &state.current_file {
+            if let Some(name) = path.file_name() {
+                dialog.set_current_name(&name.to_string_lossy());
+            }
+        }
+        install_file_filters(&dialog, &state);
+        set_dialog_folder(&dialog, &state);
+    }
     
-    // Create the menu bar (horizontal)
-    let menu_bar = gtk::Box::new(gtk::Orientation::Horizontal, 0);
-    menu_bar.set_css_classes(&["menu-bar"]);
-    
-    // Create a more modern File button with icon
-    let file_menu_button = gtk::MenuButton::new();
-    file_menu_button.set_label("File");
-    file_menu_button.set_css_classes(&["menu-button"]);
-    file_menu_button.set_has_frame(false);
-    file_menu_button.set_focus_on_click(false);
-    menu_bar.append(&file_menu_button);
-    
-    // Create File popup menu
-    let menu = gtk::PopoverMenu::from_model(None::<&gtk::gio::MenuModel>);
-    let menu_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
-    menu_box.set_margin_top(2);
-    menu_box.set_margin_bottom(2);
-    menu_box.set_margin_start(2);
-    menu_box.set_margin_end(2);
-    
-    // New file button with keyboard shortcut hint
-    let new_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let new_btn_label = gtk::Label::new(Some("New file"));
-    new_btn_label.set_halign(gtk::Align::Start);
-    new_btn_label.set_hexpand(true);
-    let new_shortcut = gtk::Label::new(Some("Ctrl+T"));
-    new_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
-    
-    new_button.append(&new_btn_label);
-    new_button.append(&new_shortcut);
-    
-    let new_button_wrapper = gtk::Button::new();
-    new_button_wrapper.set_child(Some(&new_button));
-    new_button_wrapper.set_has_frame(false);
-    new_button_wrapper.set_hexpand(true);
-    
-    let buffer_ref = buffer.clone();
-    let state_ref = editor_state.clone();
-    let status_label_ref = status_label.clone();
-    new_button_wrapper.connect_clicked(move |_| {
-        buffer_ref.set_text("");
-        if let Ok(mut state) = state_ref.lock() {
-            state.text_buffer.set_text("");
-            state.current_file = None;
-            state.is_modified = false;
-            state.update_tab_name();
-            status_label_ref.set_text("Line: 1 Col: 1");
+    let window = window.clone();
+    let buffer = text_view.buffer();
+    let text_view = text_view.clone();
+    let state = state.clone();
+    let on_saved: Rc<dyn Fn()> = Rc::new(on_saved);
+    dialog.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            remember_dialog_filter(dialog, &state);
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                let (window, buffer_ref, text_view, state_ref, saved_path, on_saved) =
+                    (window.clone(), buffer.clone(), text_view.clone(), state.clone(), path.clone(), on_saved.clone());
+                write_tab(&buffer, &state, &path, move |written| match written {
+                    Ok(_) => {
+                        update_tab_state(&buffer_ref, |state| {
+                            state.current_file = Some(saved_path.clone());
+                            state.remember_directory(&saved_path);
+                            state.remember_recent_file(&saved_path);
+                            state.update_tab_name();
+                        });
+                        if text_view.buffer() == buffer_ref {
+                            watch_file(&buffer_ref, &text_view, &state_ref, &saved_path);
+                        }
+                        on_saved();
+                    },
+                    Err(e) => show_save_error(&window, &text_view, &state_ref, &saved_path, &e, on_saved),
+                });
+            }
         }
+        dialog.destroy();
     });
-    menu_box.append(&new_button_wrapper);
-    
-    // Open file button with keyboard shortcut hint
-    let open_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let open_btn_label = gtk::Label::new(Some("Open file..."));
-    open_btn_label.set_halign(gtk::Align::Start);
-    open_btn_label.set_hexpand(true);
-    let open_shortcut = gtk::Label::new(Some("Ctrl+O"));
-    open_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
-    
-    open_button.append(&open_btn_label);
-    open_button.append(&open_shortcut);
     
-    let open_button_wrapper = gtk::Button::new();
-    open_button_wrapper.set_child(Some(&open_button));
-    open_button_wrapper.set_has_frame(false);
-    open_button_wrapper.set_hexpand(true);
-    
-    let window_ref = window.clone();
-    let buffer_ref = buffer.clone();
-    let state_ref = editor_state.clone();
-    let status_label_ref = status_label.clone();
-    open_button_wrapper.connect_clicked(move |_| {
-        let dialog = gtk::FileChooserNative::builder()
-            .title("Open File")
-            .action(gtk::FileChooserAction::Open)
-            .accept_label("Open")
-            .cancel_label("Cancel")
-            .transient_for(&window_ref)
-            .modal(true)
-            .build();
-            
-        let filter_text = gtk::FileFilter::new();
-        filter_text.add_mime_type("text/plain");
-        filter_text.set_name(Some("Text files"));
+    dialog.show();
+}
 
-        let filter_rust = gtk::FileFilter::new();
-        filter_rust.add_pattern("*.rs");
-        filter_rust.set_name(Some("Rust files"));
+// Write the tab of `buffer` to `path`, first trimming trailing whitespace and adding a
+// final line break where its language's settings ask for that. Every way of saving comes
+// through here. The file is written in the background; once it is, the tab is marked
+// saved and `done` gets the outcome. Nothing happens when the tab's state is busy, and
+// `done` is not called when the user cancels.
+fn write_tab(buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>, path: &Path, done: impl FnOnce(Result<()>) + 'static) {
+    write_tab_with(buffer, state, path, |path, bytes, cancellable| FileAccess::current().write(path, bytes, Some(cancellable)), done);
+}
 
-        let filter_all = gtk::FileFilter::new();
-        filter_all.add_pattern("*");
-        filter_all.set_name(Some("All files"));
+// Like `write_tab`, writing the file as root through pkexec for a file the user may not write
+fn write_tab_as_administrator(buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>, path: &Path, done: impl FnOnce(Result<()>) + 'static) {
+    write_tab_with(buffer, state, path, |path, bytes, cancellable| file_access::write_as_administrator(path, bytes, Some(cancellable)), done);
+}
 
-        dialog.add_filter(&filter_text);
-        dialog.add_filter(&filter_rust);
-        dialog.add_filter(&filter_all);
-        
-        let buffer = buffer_ref.clone();
-        let state = state_ref.clone();
-        let status_label = status_label_ref.clone();
-        dialog.connect_response(move |dialog, response| {
-            if response == gtk::ResponseType::Accept {
-                if let Some(file) = dialog.file() {
-                    if let Some(path) = file.path() {
-                        match fs::read_to_string(&path) {
-                            Ok(content) => {
-                                buffer.set_text(&content);
-                                if let Ok(mut state) = state.lock() {
-                                    if let Err(e) = state.open_file(&path) {
-                                        error!("Failed to open file: {}", e);
-                                    } else {
-                                        state.update_tab_name();
-                                        status_label.set_text(&format!("Line: {} Col: {}", 
-                                            state.get_cursor_line(), 
-                                            state.get_cursor_column()));
-                                    }
-                                }
-                            },
-                            Err(e) => {
-                                error!("Failed to read file: {}", e);
-                            }
-                        }
-                    }
-                }
+fn write_tab_with(
+    buffer: &gtk::TextBuffer,
+    state: &Rc<RefCell<EditorState>>,
+    path: &Path,
+    write: fn(&Path, &[u8], &gio::Cancellable) -> Result<()>,
+    done: impl FnOnce(Result<()>) + 'static,
+) {
+    let Ok(cleanup) = state.try_borrow().map(|state| state.save_cleanup()) else {
+        return;
+    };
+    // Tidied in the buffer too, so it matches the file and the change can be undone
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+    let trailing = if cleanup.trim_trailing_whitespace { edit_ops::trailing_whitespace(&text) } else { Vec::new() };
+    let add_newline = cleanup.ensure_final_newline && !text.is_empty() && !text.ends_with('\n');
+    if !trailing.is_empty() || add_newline {
+        buffer.begin_user_action();
+        if add_newline {
+            // Keep a cursor at the end of the text before the new line break
+            let marks: Vec<gtk::TextMark> = [buffer.get_insert(), buffer.selection_bound()]
+                .into_iter()
+                .filter(|mark| buffer.iter_at_mark(mark).is_end())
+                .collect();
+            buffer.insert(&mut buffer.end_iter(), "\n");
+            let mut before = buffer.end_iter();
+            before.backward_char();
+            for mark in &marks {
+                buffer.move_mark(mark, &before);
             }
-            dialog.destroy();
-        });
-        
-        dialog.show();
-    });
-    menu_box.append(&open_button_wrapper);
-    
-    // Open recent menu item
-    let open_recent_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let recent_btn_label = gtk::Label::new(Some("Open recent file"));
-    recent_btn_label.set_halign(gtk::Align::Start);
-    recent_btn_label.set_hexpand(true);
-    
-    open_recent_button.append(&recent_btn_label);
-    
-    let open_recent_wrapper = gtk::Button::new();
-    open_recent_wrapper.set_child(Some(&open_recent_button));
-    open_recent_wrapper.set_has_frame(false);
-    open_recent_wrapper.set_hexpand(true);
-    
-    let buffer_ref = buffer.clone();
-    let state_ref = editor_state.clone();
-    let status_label_ref = status_label.clone();
-    
-    open_recent_wrapper.connect_clicked(move |button| {
-        // Create a popover for recent files
-        let recent_popover = gtk::Popover::new();
-        recent_popover.set_parent(button);
-        
-        let recent_box = gtk::Box::new(gtk::Orientation::Vertical, 4);
-        recent_box.set_margin_top(4);
-        recent_box.set_margin_bottom(4);
-        recent_box.set_margin_start(4);
-        recent_box.set_margin_end(4);
-        
-        let recent_files = {
-            if let Ok(state) = state_ref.lock() {
-                state.recent_files.get_recent_files().to_vec()
-            } else {
-                Vec::new()
+        }
+        // From the end, so the offsets before each range stay valid
+        for range in char_ranges(&text, &trailing).into_iter().rev() {
+            buffer.delete(&mut buffer.iter_at_offset(range.start), &mut buffer.iter_at_offset(range.end));
+        }
+        buffer.end_user_action();
+    }
+
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false).to_string();
+    let (encoded, line_ending) = match state.try_borrow() {
+        Ok(state) => (state.encode_text(&text), state.line_ending),
+        Err(_) => return,
+    };
+    let bytes = match encoded {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            done(Err(e));
+            return;
+        }
+    };
+
+    let buffer = buffer.clone();
+    let write_path = path.to_path_buf();
+    run_file_job(
+        format!("Saving {}…", display_file_name(path)),
+        move |cancellable| write(&write_path, &bytes, cancellable),
+        move |written| {
+            let Some(written) = written else {
+                return;
+            };
+            if written.is_ok() {
+                update_tab_state(&buffer, |state| state.mark_written(&text, line_ending));
             }
-        };
-        
-        if recent_files.is_empty() {
-            let no_recent_label = gtk::Label::new(Some("No recent files"));
-            recent_box.append(&no_recent_label);
-        } else {
-            for path in recent_files {
-                let file_name = path.file_name()
-                    .and_then(|f| f.to_str())
-                    .unwrap_or("Unknown");
-                
-                let file_button = gtk::Button::with_label(file_name);
-                file_button.set_has_frame(false);
-                file_button.set_hexpand(true);
-                file_button.set_halign(gtk::Align::Start);
-                file_button.set_tooltip_text(Some(&path.to_string_lossy()));
-                
-                let buffer = buffer_ref.clone();
-                let state = state_ref.clone();
-                let status_label = status_label_ref.clone();
-                let path_clone = path.clone();
-                let popover_ref = recent_popover.clone();
-                
-                file_button.connect_clicked(move |_| {
-                    match fs::read_to_string(&path_clone) {
-                        Ok(content) => {
-                            buffer.set_text(&content);
-                            if let Ok(mut state) = state.lock() {
-                                if let Err(e) = state.open_file(&path_clone) {
-                                    error!("Failed to open file: {}", e);
-                                } else {
-                                    state.update_tab_name();
-                                    status_label.set_text(&format!("Line: {} Col: {}", 
-                                        state.get_cursor_line(), 
-                                        state.get_cursor_column()));
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            error!("Failed to read file: {}", e);
-                        }
-                    }
-                    popover_ref.popdown();
-                });
-                
-                recent_box.append(&file_button);
+            done(written);
+        },
+    );
+}
+
+// Tell the user why saving to `path` failed, offering to try again or to save elsewhere,
+// and to save as administrator when the file is not the user's to write
+fn show_save_error(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, path: &Path, error: &anyhow::Error, on_saved: Rc<dyn Fn()>) {
+    let retry = {
+        let window = window.clone();
+        let text_view = text_view.clone();
+        let state = state.clone();
+        let on_saved = on_saved.clone();
+        Rc::new(move || {
+            let on_saved = on_saved.clone();
+            save_shown_tab(&window, &text_view, &state, move || on_saved());
+        })
+    };
+    let save_as = {
+        let window = window.clone();
+        let text_view = text_view.clone();
+        let state = state.clone();
+        let on_saved = on_saved.clone();
+        Rc::new(move || {
+            let on_saved = on_saved.clone();
+            save_shown_tab_as(&window, &text_view, &state, move || on_saved());
+        })
+    };
+    let permission_denied = error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied);
+    let as_administrator = (permission_denied && FileAccess::current().can_write_as_administrator()).then(|| {
+        let window = window.clone();
+        let text_view = text_view.clone();
+        let state = state.clone();
+        let path = path.to_path_buf();
+        Rc::new(move || save_shown_tab_as_administrator(&window, &text_view, &state, &path, on_saved.clone())) as Rc<dyn Fn()>
+    });
+    show_file_error(window, &format!("Could not save \"{}\"", display_file_name(path)), error, Some(retry), Some(save_as), as_administrator);
+}
+
+// Save the shown tab to `path` as root, once the user has authenticated, then run
+// `on_saved`. Nothing happens when the user dismisses the authentication dialog.
+fn save_shown_tab_as_administrator(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, path: &Path, on_saved: Rc<dyn Fn()>) {
+    let (window_ref, text_view_ref, state_ref, failed_path) = (window.clone(), text_view.clone(), state.clone(), path.to_path_buf());
+    write_tab_as_administrator(&text_view.buffer(), state, path, move |written| match written {
+        Ok(_) => on_saved(),
+        Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::Interrupted) => {}
+        Err(e) => show_save_error(&window_ref, &text_view_ref, &state_ref, &failed_path, &e, on_saved),
+    });
+}
+
+// Before the shown tab is closed, offer to save its unsaved changes. `on_continue` runs
+// once the tab is saved or the changes are discarded, and not at all when cancelled.
+fn confirm_close_tab<F: Fn() + 'static>(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, on_continue: F) {
+    let modified_tab = match state.try_borrow() {
+        Ok(state) => state.is_modified.then(|| state.tab_name.clone()),
+        Err(_) => return,
+    };
+    let (Some(tab_name), Some(window)) = (modified_tab, text_view.root().and_downcast::<gtk::ApplicationWindow>()) else {
+        on_continue();
+        return;
+    };
+
+    let dialog = gtk::MessageDialog::new(
+        Some(&window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Warning,
+        gtk::ButtonsType::None,
+        format!("Save changes to \"{}\" before closing?", tab_name),
+    );
+    dialog.set_secondary_text(Some("Your changes will be lost if you close without saving."));
+    dialog.add_buttons(&[
+        ("Close Without Saving", gtk::ResponseType::Reject),
+        ("Cancel", gtk::ResponseType::Cancel),
+        ("Save", gtk::ResponseType::Accept),
+    ]);
+    dialog.set_default_response(gtk::ResponseType::Accept);
+
+    let text_view = text_view.clone();
+    let state = state.clone();
+    let on_continue = Rc::new(on_continue);
+    dialog.connect_response(move |dialog, response| {
+        dialog.destroy();
+        match response {
+            gtk::ResponseType::Accept => {
+                let on_continue = on_continue.clone();
+                save_shown_tab(&window, &text_view, &state, move || on_continue());
             }
+            gtk::ResponseType::Reject => on_continue(),
+            _ => {}
         }
-        
-        recent_popover.set_child(Some(&recent_box));
-        recent_popover.popup();
     });
-    menu_box.append(&open_recent_wrapper);
-    
-    // Add separator
-    let separator1 = gtk::Separator::new(gtk::Orientation::Horizontal);
-    separator1.set_margin_top(2);
-    separator1.set_margin_bottom(2);
-    menu_box.append(&separator1);
-    
-    // Save file button with keyboard shortcut hint
-    let save_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let save_btn_label = gtk::Label::new(Some("Save"));
-    save_btn_label.set_halign(gtk::Align::Start);
-    save_btn_label.set_hexpand(true);
-    let save_shortcut = gtk::Label::new(Some("Ctrl+S"));
-    save_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
-    
-    save_button.append(&save_btn_label);
-    save_button.append(&save_shortcut);
-    
-    let save_button_wrapper = gtk::Button::new();
-    save_button_wrapper.set_child(Some(&save_button));
-    save_button_wrapper.set_has_frame(false);
-    save_button_wrapper.set_hexpand(true);
-    
-    let window_ref = window.clone();
-    let buffer_ref = buffer.clone();
-    let state_ref = editor_state.clone();
-    save_button_wrapper.connect_clicked(move |_| {
-        let should_show_dialog = {
-            if let Ok(state) = state_ref.lock() {
-                state.current_file.is_none()
-            } else {
-                true
+    dialog.show();
+}
+
+// Show each of `tabs` in turn and confirm closing it, running `on_done` after the last one.
+// Cancelling any of the prompts stops there.
+fn confirm_close_tabs(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, mut tabs: Vec<gtk::Button>, on_done: Rc<dyn Fn()>) {
+    if tabs.is_empty() {
+        on_done();
+        return;
+    }
+    tabs.remove(0).emit_clicked();
+
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    confirm_close_tab(text_view, state, move || {
+        confirm_close_tabs(&text_view_ref, &state_ref, tabs.clone(), on_done.clone());
+    });
+}
+
+// Empty the shown tab and forget its file, which is how the first tab is closed
+fn clear_shown_tab(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let position = file_position(&text_view.buffer());
+    text_view.buffer().set_text("");
+    if let Ok(mut state) = state.try_borrow_mut() {
+        if let Some(path) = state.current_file.clone() {
+            state.persistent.remember_file_position(&path, position);
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
             }
-        };
-        
-        if should_show_dialog {
-            let dialog = gtk::FileChooserNative::builder()
-                .title("Save File")
-                .action(gtk::FileChooserAction::Save)
-                .accept_label("Save")
-                .cancel_label("Cancel")
-                .transient_for(&window_ref)
-                .modal(true)
-                .build();
-                
-            let filter_text = gtk::FileFilter::new();
-            filter_text.add_mime_type("text/plain");
-            filter_text.set_name(Some("Text files"));
+        }
+        state.text_buffer.set_text("");
+        state.current_file = None;
+        state.encoding = encoding_rs::UTF_8;
+        state.write_bom = false;
+        state.line_ending = LineEnding::Lf;
+        state.read_only = false;
+        state.wrap_long_lines = false;
+        state.mark_saved();
+        state.update_tab_name();
+    }
+    show_read_only(text_view, state);
+    show_wrap_mode(text_view, state);
+}
 
-            let filter_rust = gtk::FileFilter::new();
-            filter_rust.add_pattern("*.rs");
-            filter_rust.set_name(Some("Rust files"));
+// Build one filter per known language, bracketed by the generic text/all filters,
+// and preselect whichever filter the user picked last time
+fn install_file_filters(dialog: &gtk::FileChooserNative, state: &EditorState) {
+    let filter_text = gtk::FileFilter::new();
+    filter_text.add_mime_type("text/plain");
+    filter_text.set_name(Some("Text files"));
+    dialog.add_filter(&filter_text);
 
-            let filter_all = gtk::FileFilter::new();
-            filter_all.add_pattern("*");
-            filter_all.set_name(Some("All files"));
+    for language in language::LANGUAGES {
+        let filter = gtk::FileFilter::new();
+        for extension in language.extensions {
+            filter.add_pattern(&format!("*.{}", extension));
+        }
+        filter.set_name(Some(&format!("{} files", language.name)));
+        dialog.add_filter(&filter);
+    }
 
-            dialog.add_filter(&filter_text);
-            dialog.add_filter(&filter_rust);
-            dialog.add_filter(&filter_all);
-            
-            let buffer = buffer_ref.clone();
-            let state = state_ref.clone();
-            dialog.connect_response(move |dialog, response| {
-                if response == gtk::ResponseType::Accept {
-                    if let Some(file) = dialog.file() {
-                        if let Some(path) = file.path() {
-                            let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
-                            match fs::write(&path, text.as_str()) {
-                                Ok(_) => {
-                                    if let Ok(mut state) = state.lock() {
-                                        state.current_file = Some(path.clone());
-                                        state.is_modified = false;
-                                        state.recent_files.add_file(path);
-                                        state.update_tab_name();
-                                    }
-                                },
-                                Err(e) => {
-                                    error!("Failed to save file: {}", e);
-                                }
-                            }
-                        }
-                    }
-                }
-                dialog.destroy();
-            });
-            
-            dialog.show();
-        } else {
-            // Save to existing file
-            if let Ok(mut state) = state_ref.lock() {
-                if let Some(path) = &state.current_file {
-                    let text = buffer_ref.text(&buffer_ref.start_iter(), &buffer_ref.end_iter(), false);
-                    match fs::write(path, text.as_str()) {
-                        Ok(_) => {
-                            state.is_modified = false;
-                        },
-                        Err(e) => {
-                            error!("Failed to save file: {}", e);
-                        }
-                    }
+    let filter_all = gtk::FileFilter::new();
+    filter_all.add_pattern("*");
+    filter_all.set_name(Some("All files"));
+    dialog.add_filter(&filter_all);
+
+    if let Some(last_filter) = &state.persistent.last_filter {
+        let filters = dialog.filters();
+        for i in 0..filters.n_items() {
+            if let Some(filter) = filters.item(i).and_downcast::<gtk::FileFilter>() {
+                if filter.name().as_deref() == Some(last_filter.as_str()) {
+                    dialog.set_filter(&filter);
+                    break;
                 }
             }
         }
-    });
-    menu_box.append(&save_button_wrapper);
-    
-    // Save As button with keyboard shortcut hint
-    let save_as_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let save_as_btn_label = gtk::Label::new(Some("Save as..."));
-    save_as_btn_label.set_halign(gtk::Align::Start);
-    save_as_btn_label.set_hexpand(true);
-    let save_as_shortcut = gtk::Label::new(Some("Ctrl+Shift+S"));
-    save_as_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
-    
-    save_as_button.append(&save_as_btn_label);
-    save_as_button.append(&save_as_shortcut);
-    
-    let save_as_button_wrapper = gtk::Button::new();
-    save_as_button_wrapper.set_child(Some(&save_as_button));
-    save_as_button_wrapper.set_has_frame(false);
-    save_as_button_wrapper.set_hexpand(true);
-    
-    let window_ref = window.clone();
-    let buffer_ref = buffer.clone();
-    let state_ref = editor_state.clone();
-    save_as_button_wrapper.connect_clicked(move |_| {
-        let dialog = gtk::FileChooserNative::builder()
-            .title("Save File As")
-            .action(gtk::FileChooserAction::Save)
-            .accept_label("Save")
-            .cancel_label("Cancel")
-            .transient_for(&window_ref)
-            .modal(true)
-            .build();
-            
-        let filter_text = gtk::FileFilter::new();
-        filter_text.add_mime_type("text/plain");
-        filter_text.set_name(Some("Text files"));
-
-        let filter_rust = gtk::FileFilter::new();
-        filter_rust.add_pattern("*.rs");
-        filter_rust.set_name(Some("Rust files"));
-
-        let filter_all = gtk::FileFilter::new();
-        filter_all.add_pattern("*");
-        filter_all.set_name(Some("All files"));
-
-        dialog.add_filter(&filter_text);
-        dialog.add_filter(&filter_rust);
-        dialog.add_filter(&filter_all);
-        
-        // Set current filename if available
-        if let Ok(state) = state_ref.lock() {
-            if let Some(path) = &state.current_file {
-                if let Some(name) = path.file_name() {
-                    dialog.set_current_name(&name.to_string_lossy());
-                }
-            }
+    }
+}
+
+fn remember_dialog_filter(dialog: &gtk::FileChooserNative, state: &Rc<RefCell<EditorState>>) {
+    if let Some(name) = dialog.filter().and_then(|filter| filter.name()) {
+        if let Ok(mut state) = state.try_borrow_mut() {
+            state.remember_filter(&name);
         }
-        
-        let buffer = buffer_ref.clone();
-        let state = state_ref.clone();
-        dialog.connect_response(move |dialog, response| {
-            if response == gtk::ResponseType::Accept {
-                if let Some(file) = dialog.file() {
-                    if let Some(path) = file.path() {
-                        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
-                        match fs::write(&path, text.as_str()) {
-                            Ok(_) => {
-                                if let Ok(mut state) = state.lock() {
-                                    state.current_file = Some(path.clone());
-                                    state.is_modified = false;
-                                    state.recent_files.add_file(path);
-                                    state.update_tab_name();
-                                }
-                            },
-                            Err(e) => {
-                                error!("Failed to save file: {}", e);
-                            }
-                        }
-                    }
-                }
-            }
-            dialog.destroy();
-        });
-        
-        dialog.show();
-    });
-    menu_box.append(&save_as_button_wrapper);
-    
-    // Add separator
-    let separator2 = gtk::Separator::new(gtk::Orientation::Horizontal);
-    separator2.set_margin_top(2);
-    separator2.set_margin_bottom(2);
-    menu_box.append(&separator2);
-    
-    // Close file button with keyboard shortcut hint
-    let close_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let close_btn_label = gtk::Label::new(Some("Close file"));
-    close_btn_label.set_halign(gtk::Align::Start);
-    close_btn_label.set_hexpand(true);
-    let close_shortcut = gtk::Label::new(Some("Ctrl+W"));
-    close_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
-    
-    close_button.append(&close_btn_label);
-    close_button.append(&close_shortcut);
-    
-    let close_button_wrapper = gtk::Button::new();
-    close_button_wrapper.set_child(Some(&close_button));
-    close_button_wrapper.set_has_frame(false);
-    close_button_wrapper.set_hexpand(true);
-    
-    let buffer_ref = buffer.clone();
-    let state_ref = editor_state.clone();
-    close_button_wrapper.connect_clicked(move |_| {
-        buffer_ref.set_text("");
-        if let Ok(mut state) = state_ref.lock() {
-            state.text_buffer.set_text("");
-            state.current_file = None;
-            state.is_modified = false;
-            state.update_tab_name();
+    }
+}
+
+fn set_dialog_folder(dialog: &gtk::FileChooserNative, state: &EditorState) {
+    if let Some(dir) = state.dialog_directory() {
+        if let Err(e) = dialog.set_current_folder(Some(&gio::File::for_path(&dir))) {
+            warn!("Failed to set dialog folder to {}: {}", dir.display(), e);
         }
-    });
-    menu_box.append(&close_button_wrapper);
-    
-    // Add separator before quit
-    let separator3 = gtk::Separator::new(gtk::Orientation::Horizontal);
-    separator3.set_margin_top(2);
-    separator3.set_margin_bottom(2);
-    menu_box.append(&separator3);
-    
-    // Quit button with keyboard shortcut hint
-    let quit_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let quit_btn_label = gtk::Label::new(Some("Quit"));
-    quit_btn_label.set_halign(gtk::Align::Start);
-    quit_btn_label.set_hexpand(true);
-    let quit_shortcut = gtk::Label::new(Some("Ctrl+Q"));
-    quit_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
-    
-    quit_button.append(&quit_btn_label);
-    quit_button.append(&quit_shortcut);
-    
-    let quit_button_wrapper = gtk::Button::new();
-    quit_button_wrapper.set_child(Some(&quit_button));
-    quit_button_wrapper.set_has_frame(false);
-    quit_button_wrapper.set_hexpand(true);
-    
-    let app_window = window.clone();
-    quit_button_wrapper.connect_clicked(move |_| {
-        app_window.close();
-    });
-    menu_box.append(&quit_button_wrapper);
-    
-    menu.set_child(Some(&menu_box));
-    file_menu_button.set_popover(Some(&menu));
-    
-    // Add Edit menu button next to File
-    let edit_menu_button = gtk::MenuButton::new();
-    edit_menu_button.set_label("Edit");
-    edit_menu_button.set_css_classes(&["menu-button"]);
-    edit_menu_button.set_has_frame(false);
-    edit_menu_button.set_focus_on_click(false);
-    menu_bar.append(&edit_menu_button);
+    }
+}
 
-    // Create Edit popup menu
-    let edit_menu = gtk::PopoverMenu::from_model(None::<&gtk::gio::MenuModel>);
-    let edit_menu_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
-    edit_menu_box.set_margin_top(2);
-    edit_menu_box.set_margin_bottom(2);
-    edit_menu_box.set_margin_start(2);
-    edit_menu_box.set_margin_end(2);
+// Lines longer than this (minified JS/JSON and the like) make the TextView lay out
+// megabytes of glyphs in one go, so we ask the user how to open such files
+const LONG_LINE_THRESHOLD: usize = 20_000;
+const LONG_LINE_CHUNK: usize = 1_000;
 
-    // Undo button with keyboard shortcut hint
-    let undo_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let undo_btn_label = gtk::Label::new(Some("Undo"));
-    undo_btn_label.set_halign(gtk::Align::Start);
-    undo_btn_label.set_hexpand(true);
-    let undo_shortcut = gtk::Label::new(Some("Ctrl+Z"));
-    undo_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
-    
-    undo_button.append(&undo_btn_label);
-    undo_button.append(&undo_shortcut);
-    
-    let undo_button_wrapper = gtk::Button::new();
-    undo_button_wrapper.set_child(Some(&undo_button));
-    undo_button_wrapper.set_has_frame(false);
-    undo_button_wrapper.set_hexpand(true);
-    
-    let buffer_ref = buffer.clone();
-    let state_ref = editor_state.clone();
-    undo_button_wrapper.connect_clicked(move |_| {
-        if let Ok(mut state) = state_ref.lock() {
-            if let Some(previous_text) = state.undo() {
-                buffer_ref.set_text(&previous_text);
-                state.text_buffer.set_text(&previous_text);
-            }
-        }
-    });
-    edit_menu_box.append(&undo_button_wrapper);
+#[derive(Clone, Copy, PartialEq)]
+enum LongLineMode {
+    Normal,
+    SoftWrap,
+    Chunked,
+    LargeFile,
+}
 
-    // Redo button with keyboard shortcut hint
-    let redo_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let redo_btn_label = gtk::Label::new(Some("Redo"));
-    redo_btn_label.set_halign(gtk::Align::Start);
-    redo_btn_label.set_hexpand(true);
-    let redo_shortcut = gtk::Label::new(Some("Ctrl+Y"));
-    redo_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
-    
-    redo_button.append(&redo_btn_label);
-    redo_button.append(&redo_shortcut);
-    
-    let redo_button_wrapper = gtk::Button::new();
-    redo_button_wrapper.set_child(Some(&redo_button));
-    redo_button_wrapper.set_has_frame(false);
-    redo_button_wrapper.set_hexpand(true);
-    
-    let buffer_ref = buffer.clone();
-    let state_ref = editor_state.clone();
-    redo_button_wrapper.connect_clicked(move |_| {
-        if let Ok(mut state) = state_ref.lock() {
-            if let Some(next_text) = state.redo() {
-                buffer_ref.set_text(&next_text);
-                state.text_buffer.set_text(&next_text);
+fn longest_line_length(content: &str) -> usize {
+    content.lines().map(|line| line.len()).max().unwrap_or(0)
+}
+
+// Break every line longer than `max_chars` into several lines of at most `max_chars` characters
+fn chunk_long_lines(content: &str, max_chars: usize) -> String {
+    let mut result = String::with_capacity(content.len() + content.len() / max_chars);
+    for line in content.split_inclusive('\n') {
+        let mut count = 0;
+        for ch in line.chars() {
+            if count == max_chars && ch != '\n' && ch != '\r' {
+                result.push('\n');
+                count = 0;
             }
+            result.push(ch);
+            count += 1;
         }
-    });
-    edit_menu_box.append(&redo_button_wrapper);
+    }
+    result
+}
 
-    // Add separator
-    let separator_edit = gtk::Separator::new(gtk::Orientation::Horizontal);
-    separator_edit.set_margin_top(2);
-    separator_edit.set_margin_bottom(2);
-    edit_menu_box.append(&separator_edit);
+fn open_path_in_view(window: &gtk::ApplicationWindow, buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label, path: PathBuf) {
+    open_path_in_view_then(window, buffer, text_view, state, status_label, path, || {});
+}
 
-    // Find button
-    let find_button = gtk::Button::with_label("Find...");
-    find_button.set_has_frame(false);
-    find_button.set_hexpand(true);
-    find_button.set_halign(gtk::Align::Start);
-    edit_menu_box.append(&find_button);
+// Read `path` in the background and open it in the tab of `buffer`, showing that tab again
+// if another was shown meanwhile. `loaded` runs once the file is in, or once the user is
+// asked how to open one with very long lines.
+fn open_path_in_view_then(window: &gtk::ApplicationWindow, buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label, path: PathBuf, loaded: impl FnOnce() + 'static) {
+    LOADING_BUFFERS.with(|loading| loading.borrow_mut().push(buffer.clone()));
+    let (window, buffer, text_view, state, status_label) =
+        (window.clone(), buffer.clone(), text_view.clone(), state.clone(), status_label.clone());
+    let read_path = path.clone();
+    run_file_job(
+        format!("Opening {}…", display_file_name(&path)),
+        move |cancellable| encoding::read_file(&read_path, Some(cancellable)),
+        move |read| {
+            LOADING_BUFFERS.with(|loading| loading.borrow_mut().retain(|loading| *loading != buffer));
+            // Cancelled, or the tab was closed while the file was read
+            let Some(read) = read.filter(|_| show_tab_of(&text_view, &buffer)) else {
+                return;
+            };
+            match read {
+                Ok(decoded) => {
+                    open_decoded_in_view(&window, &buffer, &text_view, &state, &status_label, path, decoded);
+                    loaded();
+                }
+                Err(e) => {
+                    let (window_ref, retry_path) = (window.clone(), path.clone());
+                    let retry = Rc::new(move || {
+                        open_path_in_view(&window_ref, &buffer, &text_view, &state, &status_label, retry_path.clone());
+                    });
+                    show_open_error(&window, &path, &e, retry);
+                }
+            }
+        },
+    );
+}
 
-    // Replace button
-    let replace_button = gtk::Button::with_label("Replace...");
-    replace_button.set_has_frame(false);
-    replace_button.set_hexpand(true);
-    replace_button.set_halign(gtk::Align::Start);
-    edit_menu_box.append(&replace_button);
+// Read `path` again as `encoding`, replacing the tab's content
+fn reopen_with_encoding(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label, path: PathBuf, encoding: &'static Encoding) {
+    let (window, buffer, text_view, state, status_label) =
+        (window.clone(), text_view.buffer(), text_view.clone(), state.clone(), status_label.clone());
+    let read_path = path.clone();
+    run_file_job(
+        format!("Opening {}…", display_file_name(&path)),
+        move |cancellable| FileAccess::current().read(&read_path, Some(cancellable)),
+        move |read| {
+            let Some(read) = read.filter(|_| show_tab_of(&text_view, &buffer)) else {
+                return;
+            };
+            match read {
+                Ok(bytes) => {
+                    let decoded = encoding::decode_with(&bytes, encoding);
+                    open_decoded_in_view(&window, &buffer, &text_view, &state, &status_label, path, decoded);
+                }
+                Err(e) => {
+                    let (window_ref, retry_path) = (window.clone(), path.clone());
+                    let retry = Rc::new(move || {
+                        reopen_with_encoding(&window_ref, &text_view, &state, &status_label, retry_path.clone(), encoding);
+                    });
+                    show_open_error(&window, &path, &e, retry);
+                }
+            }
+        },
+    );
+}
 
-    edit_menu.set_child(Some(&edit_menu_box));
-    edit_menu_button.set_popover(Some(&edit_menu));
-    
-    // Add View menu button after Edit
-    let view_menu_button = gtk::MenuButton::new();
-    view_menu_button.set_label("View");
-    view_menu_button.set_css_classes(&["menu-button"]);
-    view_menu_button.set_has_frame(false);
-    view_menu_button.set_focus_on_click(false);
-    menu_bar.append(&view_menu_button);
+// Tell the user why `path` could not be read, offering to try again
+fn show_open_error(window: &gtk::ApplicationWindow, path: &Path, error: &anyhow::Error, retry: Rc<dyn Fn()>) {
+    show_file_error(window, &format!("Could not open \"{}\"", display_file_name(path)), error, Some(retry), None, None);
+}
 
-    // Create View popup menu
-    let view_menu = gtk::PopoverMenu::from_model(None::<&gtk::gio::MenuModel>);
-    let view_menu_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
-    view_menu_box.set_margin_top(2);
-    view_menu_box.set_margin_bottom(2);
-    view_menu_box.set_margin_start(2);
-    view_menu_box.set_margin_end(2);
+fn open_decoded_in_view(window: &gtk::ApplicationWindow, buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label, path: PathBuf, decoded: DecodedText) {
+    let longest_line = longest_line_length(&decoded.text);
+    if longest_line < LONG_LINE_THRESHOLD {
+        load_content_into_view(buffer, text_view, state, status_label, &path, decoded, LongLineMode::Normal);
+        return;
+    }
 
-    // Word Wrap toggle
-    let word_wrap_button = gtk::CheckButton::with_label("Word Wrap");
-    word_wrap_button.set_active(false);
-    view_menu_box.append(&word_wrap_button);
+    let file_name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string());
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Warning,
+        gtk::ButtonsType::None,
+        format!("\"{}\" contains a line with {} characters", file_name, longest_line),
+    );
+    dialog.set_secondary_text(Some(
+        "Very long lines can freeze the editor. Soft-wrap keeps the file intact, \
+         splitting inserts line breaks that will be saved, and large-file mode \
+         disables syntax highlighting.",
+    ));
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel),
+        ("Large-file mode", gtk::ResponseType::Other(LongLineMode::LargeFile as u16)),
+        ("Split lines", gtk::ResponseType::Other(LongLineMode::Chunked as u16)),
+        ("Soft-wrap", gtk::ResponseType::Other(LongLineMode::SoftWrap as u16)),
+    ]);
+    dialog.set_default_response(gtk::ResponseType::Other(LongLineMode::SoftWrap as u16));
 
-    // Show Line Numbers toggle
-    let show_line_numbers_button = gtk::CheckButton::with_label("Show Line Numbers");
-    show_line_numbers_button.set_active(true);
-    view_menu_box.append(&show_line_numbers_button);
+    let buffer = buffer.clone();
+    let text_view = text_view.clone();
+    let state = state.clone();
+    let status_label = status_label.clone();
+    let decoded = RefCell::new(Some(decoded));
+    dialog.connect_response(move |dialog, response| {
+        dialog.destroy();
+        let mode = match response {
+            gtk::ResponseType::Other(id) if id == LongLineMode::SoftWrap as u16 => LongLineMode::SoftWrap,
+            gtk::ResponseType::Other(id) if id == LongLineMode::Chunked as u16 => LongLineMode::Chunked,
+            gtk::ResponseType::Other(id) if id == LongLineMode::LargeFile as u16 => LongLineMode::LargeFile,
+            _ => return,
+        };
+        if let Some(decoded) = decoded.borrow_mut().take() {
+            load_content_into_view(&buffer, &text_view, &state, &status_label, &path, decoded, mode);
+        }
+    });
+    dialog.show();
+}
 
-    // Add separator
-    let separator_view1 = gtk::Separator::new(gtk::Orientation::Horizontal);
-    separator_view1.set_margin_top(2);
-    separator_view1.set_margin_bottom(2);
-    view_menu_box.append(&separator_view1);
+fn load_content_into_view(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label, path: &Path, decoded: DecodedText, mode: LongLineMode) {
+    // Reading the tab's own file again, as in another encoding, keeps the cursor and
+    // selection where they were
+    let reloading = state.try_borrow().is_ok_and(|state| state.current_file.as_deref() == Some(path));
+    // Another file gets back the cursor and bookmarks it was left with
+    let remembered = if reloading {
+        None
+    } else {
+        state.try_borrow().ok().and_then(|state| state.persistent.file_positions.get(path).cloned())
+    };
+    let selection = if reloading {
+        Some(selection_positions(buffer))
+    } else {
+        remembered.as_ref().map(|position| [position.cursor; 2])
+    };
+    // Its bookmarks stay on their lines too
+    let bookmarks = if reloading { bookmark_lines(buffer) } else { remembered.map(|position| position.bookmarks).unwrap_or_default() };
 
-    // Zoom In button with keyboard shortcut hint
-    let zoom_in_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let zoom_in_label = gtk::Label::new(Some("Zoom In"));
-    zoom_in_label.set_halign(gtk::Align::Start);
-    zoom_in_label.set_hexpand(true);
-    let zoom_in_shortcut = gtk::Label::new(Some("Ctrl++"));
-    zoom_in_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
+    // Must be set before the text goes in, since inserting triggers highlighting
+    if let Ok(mut state) = state.try_borrow_mut() {
+        state.large_file_mode = mode == LongLineMode::LargeFile;
+        state.wrap_long_lines = matches!(mode, LongLineMode::SoftWrap | LongLineMode::LargeFile);
+        state.current_file = Some(path.to_path_buf());
+        state.language_override = state.persistent.language_overrides.get(path).cloned();
+        state.encoding = decoded.encoding;
+        state.write_bom = decoded.bom;
+        state.line_ending = LineEnding::detect(&decoded.text);
+    }
+    show_decoding_warning(path, &decoded);
+    let content = line_ending::normalize(&decoded.text);
 
-    zoom_in_button.append(&zoom_in_label);
-    zoom_in_button.append(&zoom_in_shortcut);
+    show_wrap_mode(text_view, state);
 
-    let zoom_in_wrapper = gtk::Button::new();
-    zoom_in_wrapper.set_child(Some(&zoom_in_button));
-    zoom_in_wrapper.set_has_frame(false);
-    zoom_in_wrapper.set_hexpand(true);
+    let display_text = if mode == LongLineMode::Chunked {
+        chunk_long_lines(&content, LONG_LINE_CHUNK)
+    } else {
+        content.clone()
+    };
+    buffer.set_text(&display_text);
+    set_bookmark_lines(buffer, &bookmarks);
+    if let Some(selection) = selection {
+        restore_selection(buffer, selection);
+        // Scroll to it once the new text has been laid out
+        let (text_view, buffer) = (text_view.clone(), buffer.clone());
+        glib::idle_add_local_once(move || text_view.scroll_mark_onscreen(&buffer.get_insert()));
+    }
 
-    let state_ref = editor_state.clone();
-    let text_view_ref = text_view.clone();
-    zoom_in_wrapper.connect_clicked(move |_| {
-        if let Ok(mut state) = state_ref.lock() {
-            state.zoom_in();
-            apply_zoom(&text_view_ref, state.zoom_level);
+    if let Ok(mut state) = state.try_borrow_mut() {
+        state.load_file_content(path, &content);
+        if mode == LongLineMode::Chunked {
+            // The buffer no longer matches the file on disk
+            state.text_buffer.set_text(&display_text);
+            state.is_modified = true;
         }
-    });
-    view_menu_box.append(&zoom_in_wrapper);
+        status_label.set_text(&format!("Line: {} Col: {}", 
+            state.get_cursor_line(), 
+            state.get_cursor_column()));
+    }
+    show_read_only(text_view, state);
 
-    // Zoom Out button with keyboard shortcut hint
-    let zoom_out_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let zoom_out_label = gtk::Label::new(Some("Zoom Out"));
-    zoom_out_label.set_halign(gtk::Align::Start);
-    zoom_out_label.set_hexpand(true);
-    let zoom_out_shortcut = gtk::Label::new(Some("Ctrl+-"));
-    zoom_out_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
+    watch_file(buffer, text_view, state, path);
+    show_inline_diagnostics(buffer, text_view, state);
+    request_diagnostics(buffer, text_view, state, path);
+    refresh_git(buffer, text_view, Some(path));
+}
 
-    zoom_out_button.append(&zoom_out_label);
-    zoom_out_button.append(&zoom_out_shortcut);
+thread_local! {
+    // Monitor for the file shown in the editor; replacing it stops watching the old file
+    static FILE_MONITOR: RefCell<Option<gio::FileMonitor>> = const { RefCell::new(None) };
+}
 
-    let zoom_out_wrapper = gtk::Button::new();
-    zoom_out_wrapper.set_child(Some(&zoom_out_button));
-    zoom_out_wrapper.set_has_frame(false);
-    zoom_out_wrapper.set_hexpand(true);
+fn watch_file(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, path: &Path) {
+    let monitor = match gio::File::for_path(path).monitor_file(gio::FileMonitorFlags::NONE, gio::Cancellable::NONE) {
+        Ok(monitor) => monitor,
+        Err(e) => {
+            warn!("Cannot watch {} for changes: {}", path.display(), e);
+            return;
+        }
+    };
 
-    let state_ref = editor_state.clone();
-    let text_view_ref = text_view.clone();
-    zoom_out_wrapper.connect_clicked(move |_| {
-        if let Ok(mut state) = state_ref.lock() {
-            state.zoom_out();
-            apply_zoom(&text_view_ref, state.zoom_level);
+    let buffer = buffer.clone();
+    let text_view = text_view.clone();
+    let state = state.clone();
+    let path = path.to_path_buf();
+    monitor.connect_changed(move |_, _, _, event| {
+        let following = state.try_borrow().map(|state| state.following).unwrap_or(false);
+        if following {
+            // Stream every write while following, like `tail -f`
+            if matches!(event, gio::FileMonitorEvent::Changed | gio::FileMonitorEvent::Created) {
+                append_followed_data(&buffer, &text_view, &state, &path);
+            }
+        } else if matches!(event, gio::FileMonitorEvent::ChangesDoneHint | gio::FileMonitorEvent::Created) {
+            // Tools either rewrite the file in place or replace it with a new one
+            reload_if_unmodified(&buffer, &text_view, &state, &path);
+            if event == gio::FileMonitorEvent::ChangesDoneHint {
+                // Saves from the editor land here too, so this re-checks after every save
+                request_diagnostics(&buffer, &text_view, &state, &path);
+                refresh_git(&buffer, &text_view, Some(&path));
+            }
         }
     });
-    view_menu_box.append(&zoom_out_wrapper);
 
-    // Reset Zoom button with keyboard shortcut hint
-    let reset_zoom_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let reset_zoom_label = gtk::Label::new(Some("Reset Zoom"));
-    reset_zoom_label.set_halign(gtk::Align::Start);
-    reset_zoom_label.set_hexpand(true);
-    let reset_zoom_shortcut = gtk::Label::new(Some("Ctrl+0"));
-    reset_zoom_shortcut.set_css_classes(&["dim-label", "shortcut-label"]);
+    FILE_MONITOR.with(|current| *current.borrow_mut() = Some(monitor));
+}
 
-    reset_zoom_button.append(&reset_zoom_label);
-    reset_zoom_button.append(&reset_zoom_shortcut);
+thread_local! {
+    // Inline diagnostic labels, each anchored to a mark at the start of its line
+    static INLINE_DIAGNOSTICS: RefCell<Vec<(gtk::TextMark, gtk::Label)>> = const { RefCell::new(Vec::new()) };
+}
 
-    let reset_zoom_wrapper = gtk::Button::new();
-    reset_zoom_wrapper.set_child(Some(&reset_zoom_button));
-    reset_zoom_wrapper.set_has_frame(false);
-    reset_zoom_wrapper.set_hexpand(true);
+// Space between the end of a line and its inline diagnostic, in pixels
+const INLINE_DIAGNOSTIC_GAP: i32 = 24;
+// Longest a `cargo check` may run before it is stopped
+const CARGO_CHECK_BUDGET: Duration = Duration::from_secs(300);
 
-    let state_ref = editor_state.clone();
-    let text_view_ref = text_view.clone();
-    reset_zoom_wrapper.connect_clicked(move |_| {
-        if let Ok(mut state) = state_ref.lock() {
-            state.reset_zoom();
-            apply_zoom(&text_view_ref, state.zoom_level);
+// Run `cargo check` for the crate containing `path` in the background
+fn request_diagnostics(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, path: &Path) {
+    if path.extension().and_then(|ext| ext.to_str()) != Some("rs") {
+        return;
+    }
+    let Some(root) = diagnostics::find_cargo_root(path) else {
+        return;
+    };
+
+    if let Ok(mut state) = state.try_borrow_mut() {
+        // Only one check at a time; a save during a check queues one more run
+        if state.diagnostics_running {
+            state.diagnostics_pending = true;
+            return;
+        }
+        state.diagnostics_running = true;
+    }
+
+    let buffer = buffer.clone();
+    let text_view = text_view.clone();
+    let state = state.clone();
+    let path = path.to_path_buf();
+    run_budgeted_job(
+        format!("Checking {}…", display_file_name(&root)),
+        CARGO_CHECK_BUDGET,
+        move |cancellable| diagnostics::run_cargo_check(&root, cancellable),
+        move |result| {
+            let mut rerun = false;
+            if let Ok(mut state) = state.try_borrow_mut() {
+                state.diagnostics_running = false;
+                rerun = std::mem::take(&mut state.diagnostics_pending);
+                match result {
+                    Some(Ok(diagnostics)) => state.diagnostics.replace_all(diagnostics),
+                    Some(Err(e)) => warn!("Diagnostics unavailable: {}", e),
+                    None => info!("cargo check was cancelled"),
+                }
+            }
+            show_inline_diagnostics(&buffer, &text_view, &state);
+
+            if rerun {
+                request_diagnostics(&buffer, &text_view, &state, &path);
+            }
+        },
+    );
+}
+
+// Show the first diagnostic of each line as dimmed text after the line's content
+fn show_inline_diagnostics(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    INLINE_DIAGNOSTICS.with(|labels| {
+        for (mark, label) in labels.borrow_mut().drain(..) {
+            text_view.remove(&label);
+            if let Some(mark_buffer) = mark.buffer() {
+                mark_buffer.delete_mark(&mark);
+            }
         }
     });
-    view_menu_box.append(&reset_zoom_wrapper);
 
-    view_menu.set_child(Some(&view_menu_box));
-    view_menu_button.set_popover(Some(&view_menu));
+    let lines: Vec<(usize, &'static str, String)> = match state.try_borrow() {
+        Ok(state) if state.persistent.inline_diagnostics => match &state.current_file {
+            Some(path) => state.diagnostics.first_per_line(path)
+                .into_iter()
+                .map(|diagnostic| {
+                    let message = diagnostic.message.lines().next().unwrap_or_default();
+                    (diagnostic.line, diagnostic.severity.css_class(), format!("■ {}", message))
+                })
+                .collect(),
+            None => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
 
-    // Connect word wrap toggle
-    let text_view_ref = text_view.clone();
-    word_wrap_button.connect_toggled(move |button| {
-        if button.is_active() {
-            text_view_ref.set_wrap_mode(gtk::WrapMode::Word);
-        } else {
-            text_view_ref.set_wrap_mode(gtk::WrapMode::None);
+    INLINE_DIAGNOSTICS.with(|labels| {
+        let mut labels = labels.borrow_mut();
+        for (line, severity_class, message) in lines {
+            let Some(line_start) = buffer.iter_at_line(line as i32) else {
+                continue;
+            };
+            let mark = buffer.create_mark(None, &line_start, true);
+            let label = gtk::Label::new(Some(&message));
+            load_on_demand_css();
+            label.set_css_classes(&["inline-diagnostic", severity_class]);
+            label.set_can_target(false);
+            text_view.add_overlay(&label, 0, 0);
+            labels.push((mark, label));
         }
     });
 
-    // Add Help menu button
-    let help_menu_button = gtk::MenuButton::new();
-    help_menu_button.set_label("Help");
-    help_menu_button.set_css_classes(&["menu-button"]);
-    help_menu_button.set_has_frame(false);
-    help_menu_button.set_focus_on_click(false);
-    menu_bar.append(&help_menu_button);
+    // Lightbulbs for quick fixes are drawn in the line number gutter
+    queue_gutter_redraw(text_view, true);
 
-    // Create Help popup menu
-    let help_menu = gtk::PopoverMenu::from_model(None::<&gtk::gio::MenuModel>);
-    let help_menu_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
-    help_menu_box.set_margin_top(2);
-    help_menu_box.set_margin_bottom(2);
-    help_menu_box.set_margin_start(2);
-    help_menu_box.set_margin_end(2);
+    // Line geometry is only valid once the view has laid out the text
+    let text_view = text_view.clone();
+    glib::idle_add_local_once(move || layout_inline_diagnostics(&text_view));
+}
 
-    // Keyboard Shortcuts button
-    let shortcuts_button = gtk::Button::with_label("Keyboard Shortcuts");
-    shortcuts_button.set_has_frame(false);
-    shortcuts_button.set_hexpand(true);
-    shortcuts_button.set_halign(gtk::Align::Start);
+thread_local! {
+    // The line number gutter, redrawn when the set of quick fixes changes
+    static LINE_NUMBERS: RefCell<Option<gtk::DrawingArea>> = const { RefCell::new(None) };
+    // A gutter redraw waiting for the next frame clock tick, and whether it has to
+    // happen even if the view did not move
+    static GUTTER_REDRAW: Cell<Option<bool>> = const { Cell::new(None) };
+    // What the gutter showed when it was last painted, see gutter_view_key
+    static GUTTER_PAINTED: Cell<Option<(i64, i64, i32, i32)>> = const { Cell::new(None) };
+}
 
-    let window_ref = window.clone();
-    shortcuts_button.connect_clicked(move |_| {
-        // Create a dialog with keyboard shortcuts
-        let dialog = gtk::Dialog::with_buttons(
-            Some("Keyboard Shortcuts"),
-            Some(&window_ref),
-            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
-            &[("Close", gtk::ResponseType::Close)],
-        );
-        dialog.set_default_width(400);
-        dialog.set_default_height(500);
-        
-        let content_area = dialog.content_area();
-        content_area.set_margin_top(10);
-        content_area.set_margin_bottom(10);
-        content_area.set_margin_start(10);
-        content_area.set_margin_end(10);
-        
-        let shortcuts_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
-        
-        // File Operations shortcuts
-        let file_label = gtk::Label::new(Some("File Operations"));
-        file_label.set_halign(gtk::Align::Start);
-        file_label.set_css_classes(&["heading"]);
-        shortcuts_box.append(&file_label);
-        
-        let shortcuts = [
-            ("New File", "Ctrl+T"),
-            ("Open File", "Ctrl+O"),
-            ("Save", "Ctrl+S"),
-            ("Save As", "Ctrl+Shift+S"),
-            ("Close File", "Ctrl+W"),
-            ("Quit", "Ctrl+Q"),
-        ];
-        
-        let file_grid = gtk::Grid::new();
-        file_grid.set_column_spacing(20);
-        file_grid.set_row_spacing(5);
-        file_grid.set_margin_start(10);
-        
-        for (i, (action, shortcut)) in shortcuts.iter().enumerate() {
-            let action_label = gtk::Label::new(Some(action));
-            action_label.set_halign(gtk::Align::Start);
-            
-            let shortcut_label = gtk::Label::new(Some(shortcut));
-            shortcut_label.set_halign(gtk::Align::Start);
-            
-            file_grid.attach(&action_label, 0, i as i32, 1, 1);
-            file_grid.attach(&shortcut_label, 1, i as i32, 1, 1);
-        }
-        
-        shortcuts_box.append(&file_grid);
-        
-        // Edit Operations shortcuts
-        let edit_label = gtk::Label::new(Some("Edit Operations"));
-        edit_label.set_halign(gtk::Align::Start);
-        edit_label.set_css_classes(&["heading"]);
-        edit_label.set_margin_top(10);
-        shortcuts_box.append(&edit_label);
-        
-        let edit_shortcuts = [
-            ("Undo", "Ctrl+Z"),
-            ("Redo", "Ctrl+Y"),
-            ("Find", "Ctrl+F"),
-            ("Replace", "Ctrl+H"),
-        ];
-        
-        let edit_grid = gtk::Grid::new();
-        edit_grid.set_column_spacing(20);
-        edit_grid.set_row_spacing(5);
-        edit_grid.set_margin_start(10);
-        
-        for (i, (action, shortcut)) in edit_shortcuts.iter().enumerate() {
-            let action_label = gtk::Label::new(Some(action));
-            action_label.set_halign(gtk::Align::Start);
-            
-            let shortcut_label = gtk::Label::new(Some(shortcut));
-            shortcut_label.set_halign(gtk::Align::Start);
-            
-            edit_grid.attach(&action_label, 0, i as i32, 1, 1);
-            edit_grid.attach(&shortcut_label, 1, i as i32, 1, 1);
+// What the gutter's contents depend on, apart from quick fixes: the scroll offset and
+// height of the laid out text in pixels, the line count and the cursor's line
+fn gutter_view_key(text_view: &gtk::TextView) -> (i64, i64, i32, i32) {
+    let (scroll_pos, text_height) = text_view.vadjustment()
+        .map(|vadj| (vadj.value(), vadj.upper()))
+        .unwrap_or_default();
+    let buffer = text_view.buffer();
+    let cursor_line = buffer.iter_at_mark(&buffer.get_insert()).line();
+    (scroll_pos.round() as i64, text_height.round() as i64, buffer.line_count(), cursor_line)
+}
+
+// Ask for a gutter repaint. Requests are batched into at most one per frame clock tick,
+// and unless `force` is set the repaint is skipped when nothing in gutter_view_key changed
+// since the last one. GTK 4 always repaints a drawing area as a whole,
+// so skipping is what keeps scrolling and typing cheap.
+fn queue_gutter_redraw(text_view: &gtk::TextView, force: bool) {
+    let already_queued = GUTTER_REDRAW.with(|redraw| {
+        let queued = redraw.get();
+        redraw.set(Some(queued.unwrap_or(false) || force));
+        queued.is_some()
+    });
+    if already_queued {
+        return;
+    }
+
+    let Some(gutter) = LINE_NUMBERS.with(|gutter| gutter.borrow().clone()) else {
+        GUTTER_REDRAW.with(|redraw| redraw.set(None));
+        return;
+    };
+    let text_view = text_view.clone();
+    gutter.add_tick_callback(move |gutter, _| {
+        let force = GUTTER_REDRAW.with(|redraw| redraw.take()).unwrap_or(false);
+        if force || GUTTER_PAINTED.with(Cell::get) != Some(gutter_view_key(&text_view)) {
+            gutter.queue_draw();
         }
-        
-        shortcuts_box.append(&edit_grid);
-        
-        // View Operations shortcuts
-        let view_label = gtk::Label::new(Some("View Operations"));
-        view_label.set_halign(gtk::Align::Start);
-        view_label.set_css_classes(&["heading"]);
-        view_label.set_margin_top(10);
-        shortcuts_box.append(&view_label);
-        
-        let view_shortcuts = [
-            ("Zoom In", "Ctrl++"),
-            ("Zoom Out", "Ctrl+-"),
-            ("Reset Zoom", "Ctrl+0"),
-        ];
-        
-        let view_grid = gtk::Grid::new();
-        view_grid.set_column_spacing(20);
-        view_grid.set_row_spacing(5);
-        view_grid.set_margin_start(10);
-        
-        for (i, (action, shortcut)) in view_shortcuts.iter().enumerate() {
-            let action_label = gtk::Label::new(Some(action));
-            action_label.set_halign(gtk::Align::Start);
-            
-            let shortcut_label = gtk::Label::new(Some(shortcut));
-            shortcut_label.set_halign(gtk::Align::Start);
-            
-            view_grid.attach(&action_label, 0, i as i32, 1, 1);
-            view_grid.attach(&shortcut_label, 1, i as i32, 1, 1);
+        glib::ControlFlow::Break
+    });
+}
+
+// Space around the numbers and lightbulbs in the line number gutter, in pixels
+const GUTTER_PADDING: i32 = 4;
+
+// Fixes on `line` of the open file. Suggestions refer to the file as it was last
+// checked, so none are offered while there are unsaved edits.
+fn quick_fixes_for_line(state: &EditorState, line: usize) -> Vec<Fix> {
+    match &state.current_file {
+        Some(path) if !state.is_modified => state.diagnostics.fixes_on_line(path, line),
+        _ => Vec::new(),
+    }
+}
+
+// Lines of the open file that have at least one quick fix
+fn quick_fix_lines(state: &EditorState) -> HashSet<usize> {
+    match &state.current_file {
+        Some(path) if !state.is_modified => state.diagnostics.for_file(path)
+            .iter()
+            .filter(|diagnostic| !diagnostic.fixes.is_empty())
+            .map(|diagnostic| diagnostic.line)
+            .collect(),
+        _ => HashSet::new(),
+    }
+}
+
+// Offer the quick fixes for `line` in a popover pointing at `rect` inside `parent`
+fn show_quick_fixes<W: IsA<gtk::Widget>>(parent: &W, rect: &gtk::gdk::Rectangle, buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>, line: usize) -> bool {
+    let fixes = match state.try_borrow() {
+        Ok(state) => quick_fixes_for_line(&state, line),
+        Err(_) => return false,
+    };
+    if fixes.is_empty() {
+        return false;
+    }
+
+    let popover = gtk::Popover::new();
+    popover.set_parent(parent);
+    popover.set_pointing_to(Some(rect));
+    popover.set_position(gtk::PositionType::Bottom);
+
+    let fixes_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    for fix in fixes {
+        let fix_label = gtk::Label::new(Some(&fix.title));
+        fix_label.set_halign(gtk::Align::Start);
+        let fix_button = gtk::Button::new();
+        fix_button.set_child(Some(&fix_label));
+        fix_button.set_has_frame(false);
+        fix_button.set_css_classes(&["menu-item"]);
+
+        let buffer_ref = buffer.clone();
+        let popover_ref = popover.clone();
+        fix_button.connect_clicked(move |_| {
+            apply_fix(&buffer_ref, &fix);
+            popover_ref.popdown();
+        });
+        fixes_box.append(&fix_button);
+    }
+    popover.set_child(Some(&fixes_box));
+
+    popover.connect_closed(|popover| popover.unparent());
+    popover.popup();
+    true
+}
+
+// Apply all edits of a fix as one undoable change
+fn apply_fix(buffer: &gtk::TextBuffer, fix: &Fix) {
+    // Edit from the end of the file backwards so earlier positions stay valid
+    let mut edits: Vec<_> = fix.edits.iter().collect();
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.start));
+
+    buffer.begin_user_action();
+    for edit in edits {
+        let start = buffer.iter_at_line_offset(edit.start.0 as i32, edit.start.1 as i32);
+        let end = buffer.iter_at_line_offset(edit.end.0 as i32, edit.end.1 as i32);
+        let (Some(mut start), Some(mut end)) = (start, end) else {
+            warn!("Skipping quick fix edit outside the buffer");
+            continue;
+        };
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &edit.replacement);
+    }
+    buffer.end_user_action();
+}
+
+// Ctrl+. - show the quick fixes for the cursor's line below the cursor
+fn show_quick_fixes_at_cursor(text_view: &gtk::TextView, buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>) -> bool {
+    // Diagnostics only exist for the first tab's file
+    if text_view.buffer() != *buffer {
+        return false;
+    }
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let location = text_view.iter_location(&cursor);
+    let (x, y) = text_view.buffer_to_window_coords(gtk::TextWindowType::Widget, location.x(), location.y());
+    let rect = gtk::gdk::Rectangle::new(x, y, location.width().max(1), location.height());
+    show_quick_fixes(text_view, &rect, buffer, state, cursor.line() as usize)
+}
+
+// How long typing has to pause before the git markers in the gutter are worked out again
+const GIT_DIFF_DELAY: Duration = Duration::from_millis(300);
+// Width of the bars git markers draw at the gutter's left edge, in pixels
+const GIT_MARKER_WIDTH: f64 = 3.0;
+
+// Committed text of a tab's file and how the tab's text differs from it
+struct GitDiff {
+    buffer: gtk::TextBuffer,
+    head: String,
+    hunks: Vec<git::Hunk>,
+}
+
+thread_local! {
+    // Tabs whose file is committed in a git repository
+    static GIT_DIFFS: RefCell<Vec<GitDiff>> = const { RefCell::new(Vec::new()) };
+    // Pending update of the git markers, restarted by every edit
+    static GIT_DIFF_TIMEOUT: RefCell<Option<glib::SourceId>> = const { RefCell::new(None) };
+    // Branch of the shown file's repository in the status bar
+    static GIT_STATUS_LABEL: RefCell<Option<gtk::Label>> = const { RefCell::new(None) };
+}
+
+fn create_git_status_label() -> gtk::Label {
+    let label = gtk::Label::new(None);
+    label.set_css_classes(&["status-label"]);
+    label.set_visible(false);
+    GIT_STATUS_LABEL.with(|cell| *cell.borrow_mut() = Some(label.clone()));
+    label
+}
+
+fn show_git_status(status: Option<&git::RepoStatus>) {
+    let Some(label) = GIT_STATUS_LABEL.with(|cell| cell.borrow().clone()) else {
+        return;
+    };
+    let Some(status) = status else {
+        label.set_visible(false);
+        return;
+    };
+    let (marker, tooltip) = if status.dirty { (" ●", "with uncommitted changes") } else { ("", "no uncommitted changes") };
+    label.set_text(&format!("⎇ {}{}", status.branch, marker));
+    label.set_tooltip_text(Some(&format!("Branch {}, {}", status.branch, tooltip)));
+    label.set_visible(true);
+}
+
+// Read the committed text of `buffer`'s file and the state of its repository in the
+// background, then mark the changes in the gutter. `None` is a tab without a file.
+fn refresh_git(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, path: Option<&Path>) {
+    // Closed tabs are forgotten
+    if let Some(summaries) = TABS.with(|tabs| tabs.borrow().as_ref().and_then(|tabs| tabs.try_borrow().ok().map(|tabs| tabs.summaries()))) {
+        GIT_DIFFS.with(|diffs| diffs.borrow_mut().retain(|diff| summaries.iter().any(|tab| tab.buffer == diff.buffer)));
+    }
+    let Some(path) = path.map(Path::to_path_buf) else {
+        GIT_DIFFS.with(|diffs| diffs.borrow_mut().retain(|diff| diff.buffer != *buffer));
+        if text_view.buffer() == *buffer {
+            show_git_status(None);
+            queue_gutter_redraw(text_view, true);
         }
-        
-        shortcuts_box.append(&view_grid);
-        
-        let scrolled_window = gtk::ScrolledWindow::new();
-        scrolled_window.set_child(Some(&shortcuts_box));
-        scrolled_window.set_vexpand(true);
-        
-        content_area.append(&scrolled_window);
-        
-        dialog.connect_response(|dialog, _| {
-            dialog.destroy();
+        return;
+    };
+
+    let buffer = buffer.clone();
+    let text_view = text_view.clone();
+    glib::spawn_future_local(async move {
+        let Ok((head, status)) = gio::spawn_blocking(move || (git::head_text(&path), git::repo_status(&path))).await else {
+            return;
+        };
+        if text_view.buffer() == buffer {
+            show_git_status(status.as_ref());
+        }
+        let known = GIT_DIFFS.with(|diffs| diffs.borrow().iter().any(|diff| diff.buffer == buffer));
+        match head {
+            Some(head) if known => GIT_DIFFS.with(|diffs| {
+                if let Some(diff) = diffs.borrow_mut().iter_mut().find(|diff| diff.buffer == buffer) {
+                    diff.head = head;
+                }
+            }),
+            Some(head) => {
+                connect_git_diff(&buffer, &text_view);
+                GIT_DIFFS.with(|diffs| diffs.borrow_mut().push(GitDiff { buffer: buffer.clone(), head, hunks: Vec::new() }));
+            }
+            None => GIT_DIFFS.with(|diffs| diffs.borrow_mut().retain(|diff| diff.buffer != buffer)),
+        }
+        update_git_diff(&buffer, &text_view);
+    });
+}
+
+// Work the git markers out again once typing in `buffer` pauses
+fn connect_git_diff(buffer: &gtk::TextBuffer, text_view: &gtk::TextView) {
+    let text_view = text_view.clone();
+    buffer.connect_changed(move |buffer| {
+        let buffer = buffer.clone();
+        let text_view = text_view.clone();
+        let source_id = glib::timeout_add_local_once(GIT_DIFF_DELAY, move || {
+            GIT_DIFF_TIMEOUT.with(|timeout| timeout.borrow_mut().take());
+            update_git_diff(&buffer, &text_view);
+        });
+        GIT_DIFF_TIMEOUT.with(|timeout| {
+            if let Some(previous) = timeout.borrow_mut().replace(source_id) {
+                previous.remove();
+            }
         });
-        
-        dialog.show();
     });
-    help_menu_box.append(&shortcuts_button);
+}
 
-    // About button
-    let about_button = gtk::Button::with_label("About RustEdit");
-    about_button.set_has_frame(false);
-    about_button.set_hexpand(true);
-    about_button.set_halign(gtk::Align::Start);
+fn update_git_diff(buffer: &gtk::TextBuffer, text_view: &gtk::TextView) {
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+    GIT_DIFFS.with(|diffs| {
+        if let Some(diff) = diffs.borrow_mut().iter_mut().find(|diff| diff.buffer == *buffer) {
+            diff.hunks = git::hunks(&diff.head, &text);
+        }
+    });
+    if text_view.buffer() == *buffer {
+        queue_gutter_redraw(text_view, true);
+    }
+}
 
-    let window_ref = window.clone();
-    about_button.connect_clicked(move |_| {
-        let dialog = gtk::AboutDialog::new();
-        dialog.set_modal(true);
-        dialog.set_transient_for(Some(&window_ref));
-        dialog.set_program_name(Some("RustEdit"));
-        dialog.set_version(Some("0.1.0"));
-        dialog.set_comments(Some("A lightweight text editor inspired by COSMIC Edit"));
-        dialog.set_copyright(Some("© 2023 RustEdit Developers"));
-        dialog.set_license_type(gtk::License::Gpl30);
-        
-        dialog.show();
+fn git_hunks(buffer: &gtk::TextBuffer) -> Vec<git::Hunk> {
+    GIT_DIFFS.with(|diffs| {
+        diffs.borrow().iter().find(|diff| diff.buffer == *buffer).map(|diff| diff.hunks.clone()).unwrap_or_default()
+    })
+}
+
+// The hunk marked beside `line`; a deletion's marker sits above the line after it, or
+// below the last line when the deletion is at the end
+fn git_hunk_at_line(buffer: &gtk::TextBuffer, line: usize) -> Option<git::Hunk> {
+    let line_count = buffer.line_count() as usize;
+    git_hunks(buffer).into_iter().find(|hunk| match hunk.kind {
+        git::HunkKind::Deleted => hunk.lines.start == line || (hunk.lines.start > line && line + 1 == line_count),
+        git::HunkKind::Added | git::HunkKind::Modified => hunk.lines.contains(&line),
+    })
+}
+
+// Offer to put back the committed lines of the hunk beside `line`, in a popover pointing
+// at `rect` inside `parent`
+fn show_git_hunk_menu<W: IsA<gtk::Widget>>(parent: &W, rect: &gtk::gdk::Rectangle, buffer: &gtk::TextBuffer, line: usize) {
+    let Some(hunk) = git_hunk_at_line(buffer, line) else {
+        return;
+    };
+
+    let popover = gtk::Popover::new();
+    popover.set_parent(parent);
+    popover.set_pointing_to(Some(rect));
+    popover.set_position(gtk::PositionType::Right);
+
+    let revert_label = gtk::Label::new(Some("Revert hunk"));
+    revert_label.set_halign(gtk::Align::Start);
+    let revert_button = gtk::Button::new();
+    revert_button.set_child(Some(&revert_label));
+    revert_button.set_has_frame(false);
+    revert_button.set_css_classes(&["menu-item"]);
+    let buffer = buffer.clone();
+    let popover_ref = popover.clone();
+    revert_button.connect_clicked(move |_| {
+        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+        let (range, replacement) = git::revert_hunk(&text, &hunk);
+        let mut start = iter_at_byte(&buffer, &text, range.start);
+        let mut end = iter_at_byte(&buffer, &text, range.end);
+        buffer.begin_user_action();
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &replacement);
+        buffer.end_user_action();
+        popover_ref.popdown();
     });
-    help_menu_box.append(&about_button);
+    popover.set_child(Some(&revert_button));
 
-    help_menu.set_child(Some(&help_menu_box));
-    help_menu_button.set_popover(Some(&help_menu));
-    
-    // Create a separator between menu bars and tabs
-    let separator = gtk::Separator::new(gtk::Orientation::Horizontal);
-    separator.set_css_classes(&["menu-separator"]);
-    
-    // Add the menu bar to the main container
-    main_container.append(&menu_bar);
-    main_container.append(&separator);
-    
-    // Create a new separate row for tabs (horizontal box)
-    let tabs_row = gtk::Box::new(gtk::Orientation::Horizontal, 0);
-    tabs_row.set_css_classes(&["tabs-row"]);
-    
-    // Add modern tab bar container
-    let tabs_container = gtk::Box::new(gtk::Orientation::Horizontal, 0);
-    tabs_container.set_hexpand(true);
-    tabs_container.set_css_classes(&["tab-bar"]);
-    
-    // Create tabs box and store tab buttons in a Vec for tracking
-    let tabs_box = gtk::Box::new(gtk::Orientation::Horizontal, 2);
-    tabs_box.set_hexpand(true);
-    tabs_box.set_css_classes(&["tabs-box"]);
-    
-    // Create tab button with modern styling
-    let tab_button = gtk::Box::new(gtk::Orientation::Horizontal, 6);
-    tab_button.set_css_classes(&["tab-button"]);
-    
-    // Get the tab name
-    let tab_name = {
-        if let Ok(state) = editor_state.lock() {
-            state.tab_name.clone()
-        } else {
-            "Untitled".to_string()
+    popover.connect_closed(|popover| popover.unparent());
+    popover.popup();
+}
+
+thread_local! {
+    // Pending semantic token refresh, restarted by every edit
+    static SEMANTIC_TOKENS_TIMEOUT: RefCell<Option<glib::SourceId>> = const { RefCell::new(None) };
+}
+
+// Refresh semantic highlighting once typing pauses
+fn schedule_semantic_tokens(buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>) {
+    let buffer = buffer.clone();
+    let state = state.clone();
+    let source_id = glib::timeout_add_local_once(Duration::from_millis(500), move || {
+        SEMANTIC_TOKENS_TIMEOUT.with(|timeout| timeout.borrow_mut().take());
+        request_semantic_tokens(&buffer, &state);
+    });
+    SEMANTIC_TOKENS_TIMEOUT.with(|timeout| {
+        if let Some(previous) = timeout.borrow_mut().replace(source_id) {
+            previous.remove();
+        }
+    });
+}
+
+fn request_semantic_tokens(buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>) {
+    let (path, text, client) = match state.try_borrow_mut() {
+        Ok(mut state) => {
+            let path = match &state.current_file {
+                Some(path) if path.extension().and_then(|ext| ext.to_str()) == Some("rs") && !state.large_file_mode => path.clone(),
+                _ => return,
+            };
+            let client = match &state.language_server {
+                LanguageServer::Running(client) => Some(client.clone()),
+                LanguageServer::NotStarted => None,
+                LanguageServer::Starting | LanguageServer::Unavailable => return,
+            };
+            if client.is_none() {
+                state.language_server = LanguageServer::Starting;
+            }
+            (path, state.text_buffer.text().to_string(), client)
         }
+        Err(_) => return,
     };
-    
-    // Create a label for the tab
-    let tab_label = gtk::Label::new(Some(&tab_name));
-    tab_label.set_css_classes(&["tab-label"]);
-    tab_label.set_ellipsize(pango::EllipsizeMode::End);
-    tab_label.set_width_chars(15);
-    tab_label.set_max_width_chars(15);
-    
-    // Create a close button for the tab
-    let close_icon = gtk::Button::new();
-    close_icon.set_css_classes(&["tab-close-button"]);
-    close_icon.set_icon_name("window-close-symbolic");
-    close_icon.set_tooltip_text(Some("Close tab"));
-    
-    // Add elements to tab button
-    tab_button.append(&tab_label);
+
+    let buffer = buffer.clone();
+    let state = state.clone();
+    glib::spawn_future_local(async move {
+        let starting = client.is_none();
+        let root = diagnostics::find_cargo_root(&path)
+            .or_else(|| path.parent().map(Path::to_path_buf))
+            .unwrap_or_default();
+        let text_for_server = text.clone();
+        let result = gio::spawn_blocking(move || -> Result<(Arc<LspClient>, Vec<SemanticToken>)> {
+            let client = match client {
+                Some(client) => client,
+                None => Arc::new(LspClient::start("rust-analyzer", &root)?),
+            };
+            client.sync_document(&path, "rust", &text_for_server)?;
+            let tokens = client.semantic_tokens(&path)?;
+            Ok((client, tokens))
+        }).await;
+
+        let tokens = match result {
+            Ok(Ok((client, tokens))) => {
+                if let Ok(mut state) = state.try_borrow_mut() {
+                    state.language_server = LanguageServer::Running(client);
+                }
+                tokens
+            }
+            Ok(Err(e)) if starting => {
+                // Without a server we keep the syntactic highlighting only
+                warn!("Semantic highlighting disabled: {}", e);
+                if let Ok(mut state) = state.try_borrow_mut() {
+                    state.language_server = LanguageServer::Unavailable;
+                }
+                return;
+            }
+            Ok(Err(e)) => {
+                debug!("Semantic tokens unavailable: {}", e);
+                return;
+            }
+            Err(_) => {
+                error!("Language server thread panicked");
+                return;
+            }
+        };
+
+        // Tokens for text that has since been edited would land in the wrong place
+        if buffer.text(&buffer.start_iter(), &buffer.end_iter(), false) == text.as_str() {
+            apply_semantic_tokens(&buffer, &text, &tokens);
+        }
+    });
+}
+
+// Layer parameter/variable/field tags over the syntactic highlighting
+fn apply_semantic_tokens(buffer: &gtk::TextBuffer, text: &str, tokens: &[SemanticToken]) {
+    let (start, end) = buffer.bounds();
+    for tag in ["semantic-parameter", "semantic-variable", "semantic-field"] {
+        buffer.remove_tag_by_name(tag, &start, &end);
+    }
+
+    let lines: Vec<&str> = text.split('\n').collect();
+    for token in tokens {
+        let tag = match token.token_type.as_str() {
+            "parameter" => "semantic-parameter",
+            "variable" => "semantic-variable",
+            "property" => "semantic-field",
+            _ => continue,
+        };
+        let Some(line) = lines.get(token.line as usize) else {
+            continue;
+        };
+        let start_offset = lsp::utf16_to_char_offset(line, token.start);
+        let end_offset = lsp::utf16_to_char_offset(line, token.start + token.length);
+        let start = buffer.iter_at_line_offset(token.line as i32, start_offset as i32);
+        let end = buffer.iter_at_line_offset(token.line as i32, end_offset as i32);
+        if let (Some(start), Some(end)) = (start, end) {
+            buffer.apply_tag_by_name(tag, &start, &end);
+        }
+    }
+}
+
+thread_local! {
+    // The path being previewed on hover and the popover showing it
+    static HOVER_PREVIEW: RefCell<Option<(PathBuf, gtk::Popover)>> = const { RefCell::new(None) };
+    // Pending hover check, restarted whenever the pointer moves
+    static HOVER_TIMEOUT: RefCell<Option<glib::SourceId>> = const { RefCell::new(None) };
+}
+
+const HOVER_PREVIEW_LINES: usize = 12;
+const HOVER_PREVIEW_LINE_CHARS: usize = 100;
+const HOVER_THUMBNAIL_SIZE: i32 = 160;
+
+// Characters that end a path written in prose, code or a string literal
+fn is_path_char(ch: char) -> bool {
+    !ch.is_whitespace() && !matches!(ch, '"' | '\'' | '`' | '(' | ')' | '[' | ']' | '{' | '}' | '<' | '>' | ',' | ';' | '|')
+}
+
+// The path-like word around `offset` (in characters) of `line`, with its character range
+fn path_at(line: &str, offset: usize) -> Option<(Range<usize>, String)> {
+    let chars: Vec<char> = line.chars().collect();
+    if !chars.get(offset).copied().is_some_and(is_path_char) {
+        return None;
+    }
+
+    let start = chars[..offset].iter().rposition(|&ch| !is_path_char(ch)).map_or(0, |index| index + 1);
+    let mut end = chars[offset..].iter().position(|&ch| !is_path_char(ch)).map_or(chars.len(), |index| offset + index);
+    // Drop sentence punctuation and `:line` suffixes like "src/main.rs:42"
+    while end > start && matches!(chars[end - 1], '.' | ':') {
+        end -= 1;
+    }
+    let word: String = chars[start..end].iter().collect();
+    let word = match word.rsplit_once(':') {
+        Some((path, line_number)) if !line_number.is_empty() && line_number.chars().all(|ch| ch.is_ascii_digit()) => path.to_string(),
+        _ => word,
+    };
+
+    if !word.contains('/') && !word.contains('.') {
+        return None;
+    }
+    Some((start..start + word.chars().count(), word))
+}
+
+// Resolve a path as written in the buffer against the open file's folder and the workspace
+fn resolve_hover_path(state: &Rc<RefCell<EditorState>>, word: &str) -> Option<PathBuf> {
+    let candidate = match word.strip_prefix("~/") {
+        Some(rest) => glib::home_dir().join(rest),
+        None => PathBuf::from(word),
+    };
+    if candidate.is_absolute() {
+        return candidate.is_file().then_some(candidate);
+    }
+
+    let bases = match state.try_borrow() {
+        Ok(state) => [
+            state.current_file.as_deref().and_then(Path::parent).map(Path::to_path_buf),
+            state.workspace_root.clone(),
+        ],
+        Err(_) => return None,
+    };
+    bases.into_iter().flatten().map(|base| base.join(&candidate)).find(|path| path.is_file())
+}
+
+fn schedule_hover_preview(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, x: f64, y: f64) {
+    let text_view = text_view.clone();
+    let state = state.clone();
+    let source_id = glib::timeout_add_local_once(Duration::from_millis(400), move || {
+        HOVER_TIMEOUT.with(|timeout| timeout.borrow_mut().take());
+        update_hover_preview(&text_view, &state, x, y);
+    });
+    HOVER_TIMEOUT.with(|timeout| {
+        if let Some(previous) = timeout.borrow_mut().replace(source_id) {
+            previous.remove();
+        }
+    });
+}
+
+fn cancel_hover_preview() {
+    HOVER_TIMEOUT.with(|timeout| {
+        if let Some(source_id) = timeout.borrow_mut().take() {
+            source_id.remove();
+        }
+    });
+    HOVER_PREVIEW.with(|preview| {
+        if let Some((_, popover)) = preview.borrow_mut().take() {
+            popover.popdown();
+        }
+    });
+}
+
+// Show a preview for the path under the pointer, or hide the current one
+fn update_hover_preview(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, x: f64, y: f64) {
+    let (buffer_x, buffer_y) = text_view.window_to_buffer_coords(gtk::TextWindowType::Widget, x as i32, y as i32);
+    let Some(iter) = text_view.iter_at_location(buffer_x, buffer_y) else {
+        cancel_hover_preview();
+        return;
+    };
+
+    let buffer = text_view.buffer();
+    let mut line_start = iter;
+    line_start.set_line_offset(0);
+    let mut line_end = iter;
+    if !line_end.ends_line() {
+        line_end.forward_to_line_end();
+    }
+    let line = buffer.text(&line_start, &line_end, false);
+
+    let hovered = path_at(&line, iter.line_offset() as usize)
+        .and_then(|(range, word)| Some((range, resolve_hover_path(state, &word)?)));
+    let Some((range, path)) = hovered else {
+        cancel_hover_preview();
+        return;
+    };
+
+    let already_shown = HOVER_PREVIEW.with(|preview| {
+        preview.borrow().as_ref().is_some_and(|(shown, _)| *shown == path)
+    });
+    if already_shown {
+        return;
+    }
+    cancel_hover_preview();
+
+    let Some(content) = build_hover_preview(&path) else {
+        return;
+    };
+
+    // Point at the hovered path itself
+    let mut word_start = line_start;
+    word_start.set_line_offset(range.start as i32);
+    let mut word_end = line_start;
+    word_end.set_line_offset(range.end as i32);
+    let start_location = text_view.iter_location(&word_start);
+    let end_location = text_view.iter_location(&word_end);
+    let (rect_x, rect_y) = text_view.buffer_to_window_coords(gtk::TextWindowType::Widget, start_location.x(), start_location.y());
+    let rect = gtk::gdk::Rectangle::new(
+        rect_x,
+        rect_y,
+        (end_location.x() - start_location.x()).max(1),
+        start_location.height(),
+    );
+
+    let popover = gtk::Popover::new();
+    popover.set_parent(text_view);
+    popover.set_autohide(false);
+    popover.set_can_target(false);
+    popover.set_pointing_to(Some(&rect));
+    popover.set_position(gtk::PositionType::Bottom);
+    popover.set_child(Some(&content));
+    popover.connect_closed(|popover| popover.unparent());
+    popover.popup();
+
+    HOVER_PREVIEW.with(|preview| *preview.borrow_mut() = Some((path, popover)));
+}
+
+// A thumbnail for images, the first lines for text files, nothing for other binaries
+fn build_hover_preview(path: &Path) -> Option<gtk::Widget> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    if matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "bmp" | "svg" | "webp" | "ico") {
+        let picture = gtk::Picture::for_filename(path);
+        picture.set_can_shrink(true);
+        picture.set_keep_aspect_ratio(true);
+        picture.set_size_request(HOVER_THUMBNAIL_SIZE, HOVER_THUMBNAIL_SIZE);
+        return Some(picture.upcast());
+    }
+
+    // Only the head of the file is read, so previewing huge files stays cheap
+    let mut head = Vec::new();
+    fs::File::open(path).ok()?.take(4096).read_to_end(&mut head).ok()?;
+    if head.contains(&0) {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(&head);
+    let preview: Vec<String> = text.lines()
+        .take(HOVER_PREVIEW_LINES)
+        .map(|line| line.chars().take(HOVER_PREVIEW_LINE_CHARS).collect())
+        .collect();
+    let label = gtk::Label::new(Some(&preview.join("\n")));
+    label.set_xalign(0.0);
+    load_on_demand_css();
+    label.set_css_classes(&["hover-preview"]);
+    Some(label.upcast())
+}
+
+// Most directory entries offered by path completion
+const MAX_PATH_COMPLETIONS: usize = 50;
+
+// The typed part of the string literal the cursor is in, if it looks like a path
+fn string_path_before_cursor(line_before_cursor: &str) -> Option<&str> {
+    let mut string_start = None;
+    let mut escaped = false;
+    for (index, ch) in line_before_cursor.char_indices() {
+        match ch {
+            '\\' if string_start.is_some() => {
+                escaped = !escaped;
+                continue;
+            }
+            '"' if !escaped => {
+                string_start = match string_start {
+                    Some(_) => None,
+                    None => Some(index + 1),
+                };
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    let typed = &line_before_cursor[string_start?..];
+    (typed.contains('/') && !typed.contains("://")).then_some(typed)
+}
+
+// Directory entries completing the last component of `typed`, directories first
+fn path_completions(state: &Rc<RefCell<EditorState>>, typed: &str) -> Vec<CompletionItem> {
+    let Some((dir_part, partial)) = typed.rsplit_once('/') else {
+        return Vec::new();
+    };
+    let dir_part = format!("{}/", dir_part);
+    let dir = match dir_part.strip_prefix("~/") {
+        Some(rest) => glib::home_dir().join(rest),
+        None => PathBuf::from(&dir_part),
+    };
+
+    // Relative paths are tried against the open file's folder, then the workspace
+    let dir = if dir.is_absolute() {
+        dir
+    } else {
+        let bases = match state.try_borrow() {
+            Ok(state) => [
+                state.current_file.as_deref().and_then(Path::parent).map(Path::to_path_buf),
+                state.workspace_root.clone(),
+            ],
+            Err(_) => return Vec::new(),
+        };
+        match bases.into_iter().flatten().map(|base| base.join(&dir)).find(|path| path.is_dir()) {
+            Some(dir) => dir,
+            None => return Vec::new(),
+        }
+    };
+
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<(bool, String)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            // Hidden entries only once the user starts typing a dot
+            if !name.starts_with(partial) || (name.starts_with('.') && !partial.starts_with('.')) {
+                return None;
+            }
+            Some((entry.path().is_dir(), name))
+        })
+        .collect();
+    names.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    // Nothing left to complete once a file name is typed out in full
+    if let [(false, name)] = names.as_slice() {
+        if name == partial {
+            return Vec::new();
+        }
+    }
+
+    names.into_iter()
+        .take(MAX_PATH_COMPLETIONS)
+        .map(|(is_dir, name)| {
+            let label = if is_dir { format!("{}/", name) } else { name };
+            CompletionItem {
+                insert_text: label.clone(),
+                label,
+                detail: if is_dir { "folder" } else { "file" }.to_string(),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+thread_local! {
+    // Dictionary of prose completion, loaded when first needed and when another is chosen
+    static DICTIONARY: RefCell<Option<(String, Dictionary)>> = const { RefCell::new(None) };
+}
+
+// Offer directory entries while the cursor is in a path-like string literal, completions
+// for the code being typed, and with prose completion on, dictionary words for the word
+// being typed in prose. `explicit` is set when the user asked for completions with Ctrl+Space.
+fn update_completion(completion: &Rc<CompletionPopup>, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, explicit: bool) {
+    let buffer = text_view.buffer();
+    if !text_view.has_focus() || buffer.has_selection() {
+        completion.hide();
+        return;
+    }
+
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut line_start = cursor;
+    line_start.set_line_offset(0);
+    let before_cursor = buffer.text(&line_start, &cursor, false);
+
+    if let Some(typed) = string_path_before_cursor(&before_cursor) {
+        let partial_chars = typed.rsplit('/').next().unwrap_or_default().chars().count();
+        completion.set_return_accepts(true);
+        completion.show(path_completions(state, typed), partial_chars);
+        return;
+    }
+
+    let code_completion = state.try_borrow().ok()
+        .filter(|state| !language::is_prose(state.language()))
+        .map(|state| state.settings.code_completion && !state.large_file_mode);
+    if let Some(enabled) = code_completion {
+        if enabled || explicit {
+            complete_code(completion, text_view, state, &before_cursor, explicit);
+        } else {
+            completion.hide();
+        }
+        return;
+    }
+
+    let dictionary_name = state.try_borrow().ok()
+        .filter(|state| state.settings.prose_completion && language::is_prose(state.language()))
+        .map(|state| state.settings.dictionary.clone());
+    let Some(dictionary_name) = dictionary_name else {
+        completion.hide();
+        return;
+    };
+    // Only at the end of a word, so typing inside one is left alone
+    let is_word_char = |ch: char| ch.is_alphanumeric() || ch == '\'';
+    if cursor.char().is_alphanumeric() {
+        completion.hide();
+        return;
+    }
+    let word_start = before_cursor
+        .char_indices()
+        .rev()
+        .take_while(|(_, ch)| is_word_char(*ch))
+        .last()
+        .map_or(before_cursor.len(), |(index, _)| index);
+    let typed = &before_cursor[word_start..];
+
+    let words = DICTIONARY.with(|dictionary| {
+        let mut dictionary = dictionary.borrow_mut();
+        if dictionary.as_ref().is_none_or(|(name, _)| *name != dictionary_name) {
+            *dictionary = Some((dictionary_name.clone(), Dictionary::load(&dictionary_name)));
+        }
+        dictionary.as_ref().map(|(_, dictionary)| dictionary.complete(typed, 8)).unwrap_or_default()
+    });
+    let items = words.into_iter()
+        .map(|word| CompletionItem {
+            label: word.clone(),
+            insert_text: word,
+            ..Default::default()
+        })
+        .collect();
+    completion.set_return_accepts(false);
+    completion.show(items, typed.chars().count());
+}
+
+// Characters typed before code completions pop up on their own, so short words are left alone
+const MIN_CODE_COMPLETION_CHARS: usize = 3;
+// Most code completions listed
+const MAX_CODE_COMPLETIONS: usize = 50;
+
+thread_local! {
+    // Counts code completion requests, so the language server's answer to an older one is dropped
+    static COMPLETION_GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+// Complete the identifier before the cursor from the language server, or from the words of
+// the open tabs without one. They pop up after a few characters, or right after `.` or `::`
+// when the server is there to complete members; Ctrl+Space asks for them whatever is typed.
+fn complete_code(completion: &Rc<CompletionPopup>, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, before_cursor: &str, explicit: bool) {
+    let generation = COMPLETION_GENERATION.with(|generation| {
+        generation.set(generation.get() + 1);
+        generation.get()
+    });
+    let buffer = text_view.buffer();
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let Ok(state_ref) = state.try_borrow() else {
+        return;
+    };
+    if !mirror_in_step(&state_ref, &buffer) {
+        return;
+    }
+
+    let extra_word_chars = state_ref.text_buffer.extra_word_chars();
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || extra_word_chars.contains(c);
+    let typed_start = before_cursor.char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map_or(before_cursor.len(), |(index, _)| index);
+    let typed = before_cursor[typed_start..].to_string();
+    let typed_chars = typed.chars().count();
+    // Typing inside a word is left alone
+    if !explicit && is_word_char(cursor.char()) {
+        completion.hide();
+        return;
+    }
+
+    // A list that is open keeps up with every character typed
+    let min_chars = if completion.is_visible() { 1 } else { MIN_CODE_COMPLETION_CHARS };
+    let after_member_access = typed.is_empty() && (before_cursor.ends_with('.') || before_cursor.ends_with("::"));
+    let server = match (&state_ref.language_server, &state_ref.current_file) {
+        (LanguageServer::Running(client), Some(path)) if path.extension().and_then(|ext| ext.to_str()) == Some("rs") => Some((client.clone(), path.clone())),
+        _ => None,
+    };
+    if !(explicit || typed_chars >= min_chars || (after_member_access && server.is_some())) {
+        completion.hide();
+        return;
+    }
+
+    let words = word_completions(&buffer, &typed);
+    let Some((client, path)) = server else {
+        completion.set_return_accepts(true);
+        completion.show(words, typed_chars);
+        return;
+    };
+
+    let text = state_ref.text_buffer.text().to_string();
+    drop(state_ref);
+    let line = cursor.line() as u32;
+    let line_text = text.split('\n').nth(line as usize).unwrap_or_default();
+    let character = lsp::char_to_utf16_offset(line_text, cursor.line_offset() as usize);
+    let (requested_buffer, cursor_offset) = (buffer.clone(), cursor.offset());
+    let completion = completion.clone();
+    let text_view = text_view.clone();
+    glib::spawn_future_local(async move {
+        let server_text = text.clone();
+        let result = gio::spawn_blocking(move || -> Result<Vec<lsp::Completion>> {
+            client.sync_document(&path, "rust", &server_text)?;
+            client.completion(&path, line, character)
+        }).await;
+
+        // Typing on or moving the cursor meanwhile asked for completions anew
+        let current = COMPLETION_GENERATION.with(Cell::get) == generation;
+        let buffer = text_view.buffer();
+        if !current || buffer != requested_buffer || buffer.iter_at_mark(&buffer.get_insert()).offset() != cursor_offset {
+            return;
+        }
+        let completions = match result {
+            Ok(Ok(completions)) => completions,
+            Ok(Err(e)) => {
+                debug!("Completions unavailable: {}", e);
+                Vec::new()
+            }
+            Err(_) => {
+                error!("Language server thread panicked");
+                Vec::new()
+            }
+        };
+        // Words stand in when the server has nothing to offer
+        let items = server_completion_items(completions, &text, &typed);
+        completion.set_return_accepts(true);
+        if items.is_empty() && !after_member_access {
+            completion.show(words, typed_chars);
+        } else {
+            completion.show(items, typed_chars);
+        }
+    });
+}
+
+// Buffers with more characters than this are left out of word completion
+const MAX_WORD_INDEX_CHARS: i32 = 4_000_000;
+
+thread_local! {
+    // Words of the open tabs' buffers, indexed when first completed from
+    static WORD_INDEXES: RefCell<Vec<(gtk::TextBuffer, WordIndex)>> = const { RefCell::new(Vec::new()) };
+}
+
+// Index the words of `buffer` and keep the index up to date from now on: the lines an edit
+// touches are taken out of it before the edit and put back once it is made
+fn index_words(buffer: &gtk::TextBuffer) {
+    // The lines an edit is about to change, as the first and how many there are once it is made
+    let pending: Rc<Cell<Option<(i32, i32)>>> = Rc::new(Cell::new(None));
+    let pending_ref = pending.clone();
+    buffer.connect_insert_text(move |buf, location, text| {
+        let line = location.line();
+        let old = lines_text(buf, line, line);
+        update_word_index(buf, |index| index.remove(&old));
+        pending_ref.set(Some((line, text.matches('\n').count() as i32 + 1)));
+    });
+    let pending_ref = pending.clone();
+    buffer.connect_delete_range(move |buf, start, end| {
+        let old = lines_text(buf, start.line(), end.line());
+        update_word_index(buf, |index| index.remove(&old));
+        pending_ref.set(Some((start.line(), 1)));
+    });
+    buffer.connect_changed(move |buf| {
+        if let Some((first, lines)) = pending.take() {
+            let new = lines_text(buf, first, first + lines - 1);
+            update_word_index(buf, |index| index.add(&new));
+        }
+    });
+
+    let index = WordIndex::new(&buffer.text(&buffer.start_iter(), &buffer.end_iter(), true));
+    WORD_INDEXES.with(|indexes| indexes.borrow_mut().push((buffer.clone(), index)));
+}
+
+fn update_word_index(buffer: &gtk::TextBuffer, apply: impl FnOnce(&mut WordIndex)) {
+    WORD_INDEXES.with(|indexes| {
+        if let Some((_, index)) = indexes.borrow_mut().iter_mut().find(|(indexed, _)| indexed == buffer) {
+            apply(index);
+        }
+    });
+}
+
+// Text of lines `first` to `last` of `buffer`, without the last one's line break
+fn lines_text(buffer: &gtk::TextBuffer, first: i32, last: i32) -> String {
+    let start = buffer.iter_at_line(first).unwrap_or_else(|| buffer.end_iter());
+    let mut end = buffer.iter_at_line(last).unwrap_or_else(|| buffer.end_iter());
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    buffer.text(&start, &end, true).to_string()
+}
+
+// Words of the open tabs that complete `typed`: those of `shown` first, then the other tabs'
+// with the name of the tab they are from, each tab's most frequent first
+fn word_completions(shown: &gtk::TextBuffer, typed: &str) -> Vec<CompletionItem> {
+    let Some(summaries) = TABS.with(|tabs| tabs.borrow().as_ref().map(|tabs| tabs.borrow().summaries())) else {
+        return Vec::new();
+    };
+    WORD_INDEXES.with(|indexes| indexes.borrow_mut().retain(|(buffer, _)| summaries.iter().any(|tab| tab.buffer == *buffer)));
+    for tab in &summaries {
+        let indexed = WORD_INDEXES.with(|indexes| indexes.borrow().iter().any(|(buffer, _)| *buffer == tab.buffer));
+        if !indexed && tab.buffer.char_count() <= MAX_WORD_INDEX_CHARS {
+            index_words(&tab.buffer);
+        }
+    }
+
+    let ordered = summaries.iter().filter(|tab| tab.buffer == *shown).chain(summaries.iter().filter(|tab| tab.buffer != *shown));
+    let mut items = WORD_INDEXES.with(|indexes| {
+        let indexes = indexes.borrow();
+        let mut seen = HashSet::new();
+        let mut items = Vec::new();
+        for tab in ordered {
+            let Some((_, index)) = indexes.iter().find(|(buffer, _)| *buffer == tab.buffer) else {
+                continue;
+            };
+            for (word, _) in index.complete(typed) {
+                if seen.insert(word) {
+                    items.push(CompletionItem {
+                        label: word.to_string(),
+                        detail: if tab.buffer == *shown { String::new() } else { tab.name.clone() },
+                        insert_text: word.to_string(),
+                        kind: CompletionKind::Word,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+        items
+    });
+    items.truncate(MAX_CODE_COMPLETIONS);
+    items
+}
+
+// The language server's completions that match `typed`, best first, with their edits as
+// character ranges of `text`
+fn server_completion_items(completions: Vec<lsp::Completion>, text: &str, typed: &str) -> Vec<CompletionItem> {
+    let mut matches: Vec<(i64, lsp::Completion)> = completions.into_iter()
+        .filter_map(|completion| Some((fuzzy::fuzzy_score(typed, &completion.filter_text)?, completion)))
+        .collect();
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.sort_text.cmp(&b.1.sort_text)));
+
+    let char_range = |edit: &lsp::TextEdit| lsp::position_to_char_offset(text, edit.start)..lsp::position_to_char_offset(text, edit.end);
+    matches.into_iter()
+        .take(MAX_CODE_COMPLETIONS)
+        .map(|(_, completion)| CompletionItem {
+            replace: completion.edit.as_ref().map(char_range),
+            extra_edits: completion.additional_edits.iter().map(|edit| (char_range(edit), edit.new_text.clone())).collect(),
+            insert_text: completion.edit.map_or(completion.insert_text, |edit| edit.new_text),
+            kind: completion.kind.map_or(CompletionKind::Plain, CompletionKind::from_lsp),
+            label: completion.label,
+            detail: completion.detail,
+            documentation: completion.documentation,
+        })
+        .collect()
+}
+
+thread_local! {
+    // Typed abbreviations, shared by every tab and pane
+    static ABBREVIATIONS: Abbreviations = Abbreviations::load();
+}
+
+// Expand an abbreviation before the cursor when Space or Tab follows it. Space is still
+// typed after the expansion; Tab only expands. While `completion` is open, Tab is its own.
+fn connect_abbreviations(text_view: &gtk::TextView, completion: Option<Rc<CompletionPopup>>) {
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let typed_space = key == Key::space;
+        if !(typed_space || key == Key::Tab)
+            || modifiers.intersects(gtk::gdk::ModifierType::CONTROL_MASK | gtk::gdk::ModifierType::ALT_MASK | gtk::gdk::ModifierType::SHIFT_MASK)
+            || completion.as_ref().is_some_and(|completion| completion.is_visible())
+        {
+            return glib::Propagation::Proceed;
+        }
+        let buffer = text_view_ref.buffer();
+        if buffer.has_selection() || !text_view_ref.is_editable() {
+            return glib::Propagation::Proceed;
+        }
+
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let mut line_start = cursor;
+        line_start.set_line_offset(0);
+        let before_cursor = buffer.text(&line_start, &cursor, false);
+        let Some((expansion, word_chars)) = ABBREVIATIONS.with(|abbreviations| {
+            abbreviations.expansion_at_end(&before_cursor).map(|(expansion, chars)| (expansion.to_string(), chars))
+        }) else {
+            return glib::Propagation::Proceed;
+        };
+
+        let mut end = cursor;
+        let mut start = cursor;
+        start.backward_chars(word_chars as i32);
+        buffer.begin_user_action();
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &expansion);
+        buffer.end_user_action();
+        if typed_space {
+            glib::Propagation::Proceed
+        } else {
+            glib::Propagation::Stop
+        }
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Smart typography for prose: replace quotes, double dashes and the first letter of a
+// sentence as they are typed, when turned on and the shown tab is Markdown or plain text
+fn connect_smart_typography(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let Some(typed) = key.to_unicode() else {
+            return glib::Propagation::Proceed;
+        };
+        if modifiers.intersects(gtk::gdk::ModifierType::CONTROL_MASK | gtk::gdk::ModifierType::ALT_MASK) {
+            return glib::Propagation::Proceed;
+        }
+        let Some(language) = state_ref.try_borrow().ok()
+            .filter(|state| state.settings.smart_typography)
+            .map(|state| state.language())
+            .filter(|language| language::is_prose(*language))
+        else {
+            return glib::Propagation::Proceed;
+        };
+        let buffer = text_view_ref.buffer();
+        if buffer.has_selection() || text_view_ref.overwrites() || !text_view_ref.is_editable() {
+            return glib::Propagation::Proceed;
+        }
+
+        // The line before is enough to tell where a sentence or paragraph starts
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let mut context_start = cursor;
+        context_start.backward_line();
+        context_start.set_line_offset(0);
+        let before = buffer.text(&context_start, &cursor, false);
+        let Some(replacement) = typography::replacement(&before, typed) else {
+            return glib::Propagation::Proceed;
+        };
+        if language.is_some_and(|language| language.name == "Markdown") && typography::in_markdown_code(&buffer.text(&buffer.start_iter(), &cursor, false)) {
+            return glib::Propagation::Proceed;
+        }
+
+        let mut end = cursor;
+        let mut start = cursor;
+        start.backward_chars(replacement.delete_before as i32);
+        buffer.begin_user_action();
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &replacement.insert);
+        buffer.end_user_action();
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Enter in a Markdown list item or quote starts the next one with the same prefix,
+// counting ordered lists on; on an empty item it takes the prefix off instead. Shift+Enter
+// and Enter in code blocks break the line as usual.
+fn connect_markdown_lists(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        if !(key == Key::Return || key == Key::KP_Enter)
+            || modifiers.intersects(gtk::gdk::ModifierType::CONTROL_MASK | gtk::gdk::ModifierType::ALT_MASK | gtk::gdk::ModifierType::SHIFT_MASK)
+            || !text_view_ref.is_editable()
+        {
+            return glib::Propagation::Proceed;
+        }
+        let is_markdown = state_ref.try_borrow().is_ok_and(|state| state.language().is_some_and(|language| language.name == "Markdown"));
+        let buffer = text_view_ref.buffer();
+        if !is_markdown || buffer.has_selection() {
+            return glib::Propagation::Proceed;
+        }
+
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let mut line_start = cursor;
+        line_start.set_line_offset(0);
+        let mut line_end = cursor;
+        if !line_end.ends_line() {
+            line_end.forward_to_line_end();
+        }
+        let before_cursor = buffer.text(&line_start, &cursor, false);
+        let line = format!("{}{}", before_cursor, buffer.text(&cursor, &line_end, false));
+        let Some(enter) = markdown::list_enter(&line, before_cursor.len()) else {
+            return glib::Propagation::Proceed;
+        };
+        if typography::in_markdown_code(&buffer.text(&buffer.start_iter(), &cursor, false)) {
+            return glib::Propagation::Proceed;
+        }
+
+        buffer.begin_user_action();
+        match enter {
+            markdown::ListEnter::End { prefix_len } => {
+                let mut prefix_end = line_start;
+                prefix_end.forward_chars(line[..prefix_len].chars().count() as i32);
+                buffer.delete(&mut line_start, &mut prefix_end);
+            }
+            markdown::ListEnter::Continue { prefix } => {
+                let mut cursor = cursor;
+                buffer.insert(&mut cursor, &format!("\n{}", prefix));
+                // Later items of an ordered list count on from the new one
+                let mut following_start = cursor;
+                if following_start.forward_line() {
+                    let following = buffer.text(&following_start, &buffer.end_iter(), false);
+                    let changes = markdown::renumber(&prefix, &following);
+                    let ranges: Vec<Range<usize>> = changes.iter().map(|(range, _)| range.clone()).collect();
+                    let first_offset = following_start.offset();
+                    // From the end, so the offsets before each change stay valid
+                    for (range, (_, number)) in char_ranges(&following, &ranges).into_iter().zip(&changes).rev() {
+                        let mut start = buffer.iter_at_offset(first_offset + range.start);
+                        let mut end = buffer.iter_at_offset(first_offset + range.end);
+                        buffer.delete(&mut start, &mut end);
+                        buffer.insert(&mut start, number);
+                    }
+                }
+            }
+        }
+        buffer.end_user_action();
+        text_view_ref.scroll_mark_onscreen(&buffer.get_insert());
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Line up the pipes of the Markdown table holding the cursor and keep the cursor in its
+// cell, or with `step` move it to the next cell, or the previous one for `Some(false)`.
+// Stepping past the last cell adds a row. False when the cursor is not in a table.
+fn format_table_at_cursor(text_view: &gtk::TextView, step: Option<bool>) -> bool {
+    let buffer = text_view.buffer();
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+    let lines: Vec<&str> = text.split('\n').collect();
+    let cursor_line = cursor.line() as usize;
+    let Some(range) = markdown::table_lines(&lines, cursor_line) else {
+        return false;
+    };
+    let Some(mut table) = markdown::Table::parse(&lines[range.clone()]) else {
+        return false;
+    };
+
+    // Rows count the table's lines, so the delimiter row is row 1
+    let line = lines[cursor_line];
+    let column: usize = line.chars().take(cursor.line_offset() as usize).map(char::len_utf8).sum();
+    let mut row = cursor_line - range.start;
+    let mut cell = markdown::cell_at(line, column).min(table.columns() - 1);
+    match step {
+        Some(true) if cell + 1 < table.columns() => cell += 1,
+        Some(true) => {
+            cell = 0;
+            row = (row + 1).max(2);
+            if row >= table.line_count() {
+                table.push_row();
+            }
+        }
+        Some(false) if cell > 0 => cell -= 1,
+        Some(false) if row > 0 => {
+            row = if row <= 2 { 0 } else { row - 1 };
+            cell = table.columns() - 1;
+        }
+        _ => {}
+    }
+
+    let formatted = table.format();
+    let mut start = buffer.iter_at_line(range.start as i32).unwrap_or_else(|| buffer.start_iter());
+    let mut end = buffer.iter_at_line((range.end - 1) as i32).unwrap_or_else(|| buffer.end_iter());
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    let new_text = formatted.join("\n");
+    buffer.begin_user_action();
+    if buffer.text(&start, &end, false) != new_text {
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &new_text);
+    }
+    let target = &formatted[row];
+    if let Some(mut place) = buffer.iter_at_line((range.start + row) as i32) {
+        place.forward_chars(target[..markdown::cell_start(target, cell)].chars().count() as i32);
+        buffer.place_cursor(&place);
+    }
+    buffer.end_user_action();
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+    true
+}
+
+// Tab and Shift+Tab in a Markdown table move between its cells, lining the table up
+fn connect_markdown_tables(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let backward = key == Key::ISO_Left_Tab || key == Key::Tab && modifiers.contains(gtk::gdk::ModifierType::SHIFT_MASK);
+        if !(backward || key == Key::Tab)
+            || modifiers.intersects(gtk::gdk::ModifierType::CONTROL_MASK | gtk::gdk::ModifierType::ALT_MASK)
+            || !text_view_ref.is_editable()
+        {
+            return glib::Propagation::Proceed;
+        }
+        let is_markdown = state_ref.try_borrow().is_ok_and(|state| state.language().is_some_and(|language| language.name == "Markdown"));
+        let buffer = text_view_ref.buffer();
+        if !is_markdown || buffer.has_selection() {
+            return glib::Propagation::Proceed;
+        }
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        if typography::in_markdown_code(&buffer.text(&buffer.start_iter(), &cursor, false)) {
+            return glib::Propagation::Proceed;
+        }
+        if format_table_at_cursor(&text_view_ref, Some(!backward)) {
+            glib::Propagation::Stop
+        } else {
+            glib::Propagation::Proceed
+        }
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Edit > Format Markdown Table
+fn format_markdown_table(text_view: &gtk::TextView) {
+    if !format_table_at_cursor(text_view, None) {
+        show_warning_bar("Put the cursor in a Markdown table to format it.");
+    }
+}
+
+// Move each inline diagnostic to the current end of its line
+fn layout_inline_diagnostics(text_view: &gtk::TextView) {
+    let buffer = text_view.buffer();
+    INLINE_DIAGNOSTICS.with(|labels| {
+        for (mark, label) in labels.borrow().iter() {
+            // Hide them while another tab's buffer is in the view
+            let visible = mark.buffer().as_ref() == Some(&buffer);
+            label.set_visible(visible);
+            if !visible {
+                continue;
+            }
+
+            let mut line_end = buffer.iter_at_mark(mark);
+            if !line_end.ends_line() {
+                line_end.forward_to_line_end();
+            }
+            let location = text_view.iter_location(&line_end);
+            text_view.move_overlay(label, location.x() + location.width() + INLINE_DIAGNOSTIC_GAP, location.y());
+        }
+    });
+}
+
+// Autosave for when the window or tab loses focus: only modified tabs that already have a file
+fn save_on_focus_loss(buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>) {
+    let path = match state.try_borrow() {
+        Ok(state) if state.persistent.save_on_focus_loss && state.is_modified => state.current_file.clone(),
+        _ => None,
+    };
+    let Some(path) = path else {
+        return;
+    };
+
+    let failed_path = path.clone();
+    write_tab(buffer, state, &path, move |written| {
+        if let Err(e) = written {
+            error!("Failed to autosave {}: {}", failed_path.display(), e);
+            show_warning_bar(&format!("Could not save {}: {}", failed_path.display(), describe_file_error(&e)));
+        }
+    });
+}
+
+fn reload_if_unmodified(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, path: &Path) {
+    let can_reload = |state: &Rc<RefCell<EditorState>>, path: &Path| match state.try_borrow() {
+        Ok(state) if state.persistent.auto_reload && !state.is_modified && state.current_file.as_deref() == Some(path) => Some(state.encoding),
+        _ => None,
+    };
+    let Some(file_encoding) = can_reload(state, path) else {
+        return;
+    };
+
+    let (buffer, text_view, state, path) = (buffer.clone(), text_view.clone(), state.clone(), path.to_path_buf());
+    let read_path = path.clone();
+    run_file_job(
+        format!("Reloading {}…", display_file_name(&path)),
+        move |cancellable| FileAccess::current().read(&read_path, Some(cancellable)),
+        move |read| {
+            let Some(read) = read else {
+                return;
+            };
+            // Only while the tab is still shown and unedited
+            if text_view.buffer() != buffer || can_reload(&state, &path).is_none() {
+                return;
+            }
+            // Keep the encoding the file was opened with
+            match read {
+                Ok(bytes) => reload_content(&buffer, &text_view, &state, &path, &encoding::decode_with(&bytes, file_encoding).text),
+                Err(e) => debug!("Skipping reload of {}: {}", path.display(), e),
+            }
+        },
+    );
+}
+
+// File > Revert: read the shown tab's file again, keeping the cursor and scroll position.
+// Unsaved changes are only dropped once the user confirms.
+fn revert_shown_tab(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let Ok((file, modified)) = state.try_borrow().map(|state| (state.current_file.clone(), state.is_modified)) else {
+        return;
+    };
+    let Some(path) = file else {
+        show_warning_bar("This tab has no file to revert to.");
+        return;
+    };
+    if !modified {
+        reread_shown_tab(window, text_view, state, &path);
+        return;
+    }
+
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Warning,
+        gtk::ButtonsType::None,
+        format!("Revert \"{}\" to the saved file?", display_file_name(&path)),
+    );
+    dialog.set_secondary_text(Some("Your unsaved changes will be lost."));
+    dialog.add_buttons(&[("Cancel", gtk::ResponseType::Cancel), ("Revert", gtk::ResponseType::Accept)]);
+    dialog.set_default_response(gtk::ResponseType::Cancel);
+
+    let (window, text_view, state) = (window.clone(), text_view.clone(), state.clone());
+    dialog.connect_response(move |dialog, response| {
+        dialog.destroy();
+        if response == gtk::ResponseType::Accept {
+            reread_shown_tab(&window, &text_view, &state, &path);
+        }
+    });
+    dialog.show();
+}
+
+// Read `path` into the shown tab in the background, in the encoding it was opened with
+fn reread_shown_tab(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, path: &Path) {
+    let Ok(file_encoding) = state.try_borrow().map(|state| state.encoding) else {
+        return;
+    };
+    let (window, text_view, state, path) = (window.clone(), text_view.clone(), state.clone(), path.to_path_buf());
+    let buffer = text_view.buffer();
+    let read_path = path.clone();
+    run_file_job(
+        format!("Reverting {}…", display_file_name(&path)),
+        move |cancellable| FileAccess::current().read(&read_path, Some(cancellable)),
+        move |read| {
+            let Some(read) = read else {
+                return;
+            };
+            if !show_tab_of(&text_view, &buffer) || state.try_borrow().map_or(true, |state| state.current_file.as_deref() != Some(path.as_path())) {
+                return;
+            }
+            match read {
+                Ok(bytes) => {
+                    let text = encoding::decode_with(&bytes, file_encoding).text;
+                    reload_content(&buffer, &text_view, &state, &path, &text);
+                    // Text that already matched the file was left alone, line endings and all
+                    if let Ok(mut state) = state.try_borrow_mut() {
+                        state.line_ending = LineEnding::detect(&text);
+                        state.mark_saved();
+                    }
+                }
+                Err(e) => {
+                    let retry = {
+                        let (window, text_view, state, path) = (window.clone(), text_view.clone(), state.clone(), path.clone());
+                        Rc::new(move || reread_shown_tab(&window, &text_view, &state, &path)) as Rc<dyn Fn()>
+                    };
+                    show_file_error(&window, &format!("Could not revert \"{}\"", display_file_name(&path)), &e, Some(retry), None, None);
+                }
+            }
+        },
+    );
+}
+
+// File > Print: the shown tab with its highlighting and line numbers, headed by its file's
+// path. With `preview` the pages are shown in a preview window first.
+fn print_shown_tab(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, preview: bool) {
+    let Ok((title, settings)) = state.try_borrow().map(|state| {
+        let title = state.current_file.as_ref().map_or_else(|| state.tab_name.clone(), |path| path.display().to_string());
+        (title, state.settings.clone())
+    }) else {
+        return;
+    };
+    // Printing runs a main loop of its own, in which the theme may change
+    let theme = CURRENT_THEME.with(|theme| theme.borrow().clone());
+    if let Err(e) = printing::print_buffer(window, &text_view.buffer(), &title, &settings, &theme, preview) {
+        error!("Could not print {}: {}", title, e);
+        show_warning_bar(&format!("Could not print: {}", e));
+    }
+}
+
+// Replace the tab's text with `raw_content` read again from `path`, keeping the cursor and
+// scroll position
+fn reload_content(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, path: &Path, raw_content: &str) {
+    let content = line_ending::normalize(raw_content);
+
+    // Our own saves trigger the monitor too
+    let unchanged = state.try_borrow().map(|state| state.text_buffer.text() == content).unwrap_or(true);
+    if unchanged {
+        return;
+    }
+
+    info!("Reloading {} from disk", path.display());
+    let selection = selection_positions(buffer);
+    let bookmarks = bookmark_lines(buffer);
+    let scroll_value = text_view.vadjustment().map(|adj| adj.value());
+
+    buffer.set_text(&content);
+    set_bookmark_lines(buffer, &bookmarks);
+    if let Ok(mut state) = state.try_borrow_mut() {
+        state.line_ending = LineEnding::detect(raw_content);
+        state.mark_saved();
+    }
+
+    restore_selection(buffer, selection);
+    // The new text has not been laid out yet, so restore the scroll offset once idle
+    if let (Some(adj), Some(value)) = (text_view.vadjustment(), scroll_value) {
+        glib::idle_add_local_once(move || adj.set_value(value));
+    }
+}
+
+fn set_following(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, following: bool) {
+    let path = {
+        if let Ok(mut state) = state.try_borrow_mut() {
+            state.following = following;
+            // Continue from the end of what was loaded, as it is stored with its encoding and
+            // byte order mark, catching up on anything written since
+            let loaded = state.last_saved_text.as_ref().map(|text| state.encode_text(text));
+            state.follow_offset = match (loaded, &state.current_file) {
+                (Some(Ok(bytes)), _) => bytes.len() as u64,
+                // Text that does not encode back was read with replacements; follow from the end
+                (Some(Err(_)), Some(path)) => FileAccess::current().size(path).unwrap_or(0),
+                _ => 0,
+            };
+            state.current_file.clone()
+        } else {
+            None
+        }
+    };
+
+    if let (true, Some(path)) = (following, path) {
+        append_followed_data(buffer, text_view, state, &path);
+    }
+}
+
+// Append whatever was written to the followed file since the last read and keep the view at the bottom
+fn append_followed_data(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, path: &Path) {
+    let (offset, was_modified, encoding) = {
+        if let Ok(state) = state.try_borrow() {
+            if !state.following || state.current_file.as_deref() != Some(path) {
+                return;
+            }
+            (state.follow_offset, state.is_modified, state.encoding)
+        } else {
+            return;
+        }
+    };
+
+    let file_len = match FileAccess::current().size(path) {
+        Ok(len) => len,
+        Err(e) => {
+            debug!("Cannot follow {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    // A shorter file means it was truncated or rotated: start over from the beginning
+    let (offset, replace) = if file_len < offset { (0, true) } else { (offset, false) };
+    if file_len == offset && !replace {
+        return;
+    }
+
+    let data = match FileAccess::current().read_from(path, offset) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Failed to read appended data from {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    // A file read from its start may begin with a byte order mark, which is not text
+    let bom = match Encoding::for_bom(&data) {
+        Some((_, length)) if offset == 0 => length,
+        _ => 0,
+    };
+    // Leave a character cut off at the end for the next read
+    let (text, consumed) = encoding::decode_appended(&data[bom..], encoding);
+    let consumed = bom + consumed;
+    let text = line_ending::normalize(&text);
+
+    if replace {
+        buffer.set_text(&text);
+    } else {
+        buffer.insert(&mut buffer.end_iter(), &text);
+    }
+
+    if let Ok(mut state) = state.try_borrow_mut() {
+        state.follow_offset = offset + consumed as u64;
+        if !was_modified {
+            state.mark_saved();
+        }
+    }
+
+    buffer.place_cursor(&buffer.end_iter());
+    text_view.scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 1.0);
+}
+
+// Symbols of the visible buffer, found the way its language's files are read
+fn shown_symbols(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) -> Vec<symbols::Symbol> {
+    let buffer = text_view.buffer();
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+    let language = state.try_borrow().ok().and_then(|state| state.language()).map(|language| language.name);
+    symbols::extract_symbols_for(&text, language)
+}
+
+// Move the cursor to the next/previous function or top-level item in the visible buffer
+fn jump_to_symbol(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, forward: bool) {
+    let buffer = text_view.buffer();
+    let current_line = buffer.iter_at_mark(&buffer.get_insert()).line() as usize;
+    
+    let mut targets = shown_symbols(text_view, state)
+        .into_iter()
+        .filter(|symbol| symbol.depth == 0 || matches!(symbol.kind, symbols::SymbolKind::Function | symbols::SymbolKind::Heading));
+    let target = if forward {
+        targets.find(|symbol| symbol.line > current_line)
+    } else {
+        targets.rev().find(|symbol| symbol.line < current_line)
+    };
+    
+    if let Some(symbol) = target {
+        place_cursor_at_line_column(&buffer, symbol.line as i32, symbol.column as i32);
+        text_view.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.3);
+    }
+}
+
+// Ctrl+K: fuzzy-filtered list of all open tabs, switching to the one picked
+fn show_tab_overview(text_view: &gtk::TextView, tabs: &Rc<RefCell<TabManager>>) {
+    let summaries = tabs.borrow().summaries();
+    
+    let items = summaries.iter()
+        .map(|tab| picker::PickerItem {
+            label: if tab.modified { format!("● {}", tab.name) } else { tab.name.clone() },
+            detail: tab.file.as_ref()
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            rank: 0,
+        })
+        .collect();
+    
+    let text_view_ref = text_view.clone();
+    picker::show_fuzzy_picker(text_view, "Switch to tab...", items, move |index| {
+        summaries[index].button.emit_clicked();
+        text_view_ref.grab_focus();
+    });
+}
+
+// Move the shown tab to the position `to` gives for its current one, in the tab bar as well
+// as in the tab order
+fn move_shown_tab(text_view: &gtk::TextView, tabs: &Rc<RefCell<TabManager>>, to: impl FnOnce(usize) -> usize) {
+    let buffer = text_view.buffer();
+    let Some(index) = tabs.borrow().index_of(&buffer) else {
+        return;
+    };
+    let Some(previous) = tabs.borrow_mut().move_tab(&buffer, to(index)) else {
+        return;
+    };
+    let Some(button) = tabs.borrow().button_for(&buffer) else {
+        return;
+    };
+    if let Some(tabs_box) = button.parent().and_downcast::<gtk::Box>() {
+        tabs_box.reorder_child_after(&button, previous.as_ref());
+    }
+}
+
+// Files quick open lists at most; the walk of a very large tree stops there
+const MAX_QUICK_OPEN_FILES: usize = 50_000;
+// Added to the match score of recently opened files, so they come before other close matches
+const RECENT_FILE_RANK: i64 = 1000;
+
+// The folder quick open lists: the workspace when the shown file is in it or there is none,
+// otherwise the git repository holding the file, or else the file's folder
+fn quick_open_root(state: &EditorState) -> PathBuf {
+    let workspace = state.workspace_root.clone()
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_else(glib::home_dir);
+    match &state.current_file {
+        Some(file) if !file.starts_with(&workspace) => find_in_files::find_git_root(file)
+            .or_else(|| file.parent().map(Path::to_path_buf))
+            .unwrap_or(workspace),
+        _ => workspace,
+    }
+}
+
+// Ctrl+E: fuzzy-find a file of the project and open it. Recently opened files are listed
+// right away and ranked higher; the rest stream in as a worker thread walks the folder.
+fn show_quick_open(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let Ok((root, recent_files)) = state.try_borrow().map(|state| (quick_open_root(&state), state.persistent.recent_files())) else {
+        return;
+    };
+    let relative = {
+        let root = root.clone();
+        move |path: &Path| path.strip_prefix(&root).unwrap_or(path).to_string_lossy().to_string()
+    };
+
+    let recent: Vec<PathBuf> = recent_files.into_iter()
+        .map(|recent| recent.path)
+        .filter(|path| path.starts_with(&root) && path.is_file())
+        .collect();
+    let items = recent.iter()
+        .enumerate()
+        .map(|(index, path)| picker::PickerItem {
+            label: relative(path),
+            detail: "recent".to_string(),
+            rank: RECENT_FILE_RANK - index as i64,
+        })
+        .collect();
+    let listed: HashSet<PathBuf> = recent.iter().cloned().collect();
+    let paths = Rc::new(RefCell::new(recent));
+
+    let root_name = root.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| root.to_string_lossy().to_string());
+    let text_view_ref = text_view.clone();
+    let paths_ref = paths.clone();
+    let picker = picker::show_fuzzy_picker(text_view, &format!("Open file in {}...", root_name), items, move |index| {
+        if let Some(path) = paths_ref.borrow().get(index).cloned() {
+            open_handed_over_files(vec![path]);
+        }
+        text_view_ref.grab_focus();
+    });
+
+    // Files are found on a worker thread and sent back in batches as the walk goes
+    let (sender, receiver) = mpsc::channel::<Vec<PathBuf>>();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let worker_cancelled = cancelled.clone();
+    let walk_root = root.clone();
+    std::thread::spawn(move || {
+        let mut batch = Vec::new();
+        let mut total = 0;
+        find_in_files::walk_directory(&walk_root, |path| {
+            if !listed.contains(path) {
+                batch.push(path.to_path_buf());
+                total += 1;
+            }
+            if batch.len() >= 200 && sender.send(std::mem::take(&mut batch)).is_err() {
+                return false;
+            }
+            !worker_cancelled.load(Ordering::Relaxed) && total < MAX_QUICK_OPEN_FILES
+        });
+        let _ = sender.send(batch);
+    });
+
+    glib::timeout_add_local(Duration::from_millis(50), move || {
+        if !picker.is_open() {
+            cancelled.store(true, Ordering::Relaxed);
+            return glib::ControlFlow::Break;
+        }
+        let mut found = Vec::new();
+        let finished = loop {
+            match receiver.try_recv() {
+                Ok(batch) => found.extend(batch),
+                Err(mpsc::TryRecvError::Empty) => break false,
+                Err(mpsc::TryRecvError::Disconnected) => break true,
+            }
+        };
+        let items = found.iter()
+            .map(|path| picker::PickerItem { label: relative(path), detail: String::new(), rank: 0 })
+            .collect();
+        paths.borrow_mut().extend(found);
+        picker.extend(items);
+        if finished { glib::ControlFlow::Break } else { glib::ControlFlow::Continue }
+    });
+}
+
+// Pick a position for the shown tab from the list of tabs
+fn show_tab_position_picker(text_view: &gtk::TextView, tabs: &Rc<RefCell<TabManager>>) {
+    let summaries = tabs.borrow().summaries();
+    let items = summaries.iter()
+        .enumerate()
+        .map(|(index, tab)| picker::PickerItem {
+            label: format!("Position {}", index + 1),
+            detail: tab.name.clone(),
+            rank: 0,
+        })
+        .collect();
+
+    let text_view_ref = text_view.clone();
+    let tabs = tabs.clone();
+    picker::show_fuzzy_picker(text_view, "Move tab to position...", items, move |index| {
+        move_shown_tab(&text_view_ref, &tabs, |_| index);
+        text_view_ref.grab_focus();
+    });
+}
+
+// Ctrl+Shift+O or Ctrl+R: fuzzy-search the symbols of the visible buffer and jump to the chosen one
+fn show_symbol_search(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let symbols = shown_symbols(text_view, state);
+    
+    let items = symbols.iter()
+        .map(|symbol| picker::PickerItem {
+            label: symbol.name.clone(),
+            detail: format!("{}  line {}", symbol.kind.label(), symbol.line + 1),
+            rank: 0,
+        })
+        .collect();
+    
+    let text_view_ref = text_view.clone();
+    picker::show_fuzzy_picker(text_view, "Go to symbol...", items, move |index| {
+        let symbol = &symbols[index];
+        let buffer = text_view_ref.buffer();
+        place_cursor_at_line_column(&buffer, symbol.line as i32, symbol.column as i32);
+        text_view_ref.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.3);
+        text_view_ref.grab_focus();
+    });
+}
+
+// Ctrl+G: ask for `line` or `line:column`, both 1-based, and center the view there
+fn show_go_to_line(text_view: &gtk::TextView) {
+    let popover = gtk::Popover::new();
+    popover.set_parent(text_view);
+    popover.set_position(gtk::PositionType::Bottom);
+    popover.set_pointing_to(Some(&gtk::gdk::Rectangle::new(text_view.width() / 2, 0, 1, 1)));
+    popover.connect_closed(|popover| popover.unparent());
+
+    let buffer = text_view.buffer();
+    let entry = gtk::Entry::new();
+    entry.set_placeholder_text(Some(&format!("Line (1-{}) or line:column", buffer.line_count())));
+    entry.set_width_chars(28);
+    entry.set_margin_top(4);
+    entry.set_margin_bottom(4);
+    entry.set_margin_start(4);
+    entry.set_margin_end(4);
+    popover.set_child(Some(&entry));
+
+    let text_view_ref = text_view.clone();
+    let popover_ref = popover.clone();
+    entry.connect_activate(move |entry| {
+        let Some((line, column)) = parse_line_column(&entry.text()) else {
+            entry.add_css_class("error");
+            return;
+        };
+        popover_ref.popdown();
+        let buffer = text_view_ref.buffer();
+        // Past the last line goes to the last line, not the end of the text
+        let line = line.min(buffer.line_count() as usize).max(1) - 1;
+        place_cursor_at_line_column(&buffer, line as i32, column.saturating_sub(1) as i32);
+        text_view_ref.scroll_to_mark(&buffer.get_insert(), 0.0, true, 0.0, 0.5);
+        text_view_ref.grab_focus();
+    });
+    entry.connect_changed(|entry| entry.remove_css_class("error"));
+
+    popover.popup();
+    entry.grab_focus();
+}
+
+// `120` or `120:45`; a missing column is the start of the line
+fn parse_line_column(text: &str) -> Option<(usize, usize)> {
+    let text = text.trim();
+    let (line, column) = match text.split_once(':') {
+        Some((line, column)) => (line.trim(), column.trim().parse().ok()?),
+        None => (text, 1),
+    };
+    Some((line.parse().ok()?, column))
+}
+
+// Place the cursor at a 0-based line/column, clamping both to the buffer contents
+fn place_cursor_at_line_column(buffer: &gtk::TextBuffer, line: i32, column: i32) {
+    buffer.place_cursor(&iter_at_line_column(buffer, line, column));
+}
+
+// The position nearest to `line` and `column`: the end of a shorter line, or the end of
+// the text when there are fewer lines
+fn iter_at_line_column(buffer: &gtk::TextBuffer, line: i32, column: i32) -> gtk::TextIter {
+    let mut iter = buffer.iter_at_line(line).unwrap_or_else(|| buffer.end_iter());
+    let mut line_end = iter;
+    if !line_end.ends_line() {
+        line_end.forward_to_line_end();
+    }
+    iter.set_line_offset(column.min(line_end.line_offset()));
+    iter
+}
+
+// Line and column of the cursor and of the other end of the selection, to put them back
+// with `restore_selection` once the text has been replaced
+fn selection_positions(buffer: &gtk::TextBuffer) -> [(i32, i32); 2] {
+    [buffer.get_insert(), buffer.selection_bound()].map(|mark| {
+        let iter = buffer.iter_at_mark(&mark);
+        (iter.line(), iter.line_offset())
+    })
+}
+
+fn restore_selection(buffer: &gtk::TextBuffer, [(line, column), (bound_line, bound_column)]: [(i32, i32); 2]) {
+    buffer.select_range(&iter_at_line_column(buffer, line, column), &iter_at_line_column(buffer, bound_line, bound_column));
+}
+
+// Where the cursor and bookmarks of `buffer` are, to be remembered for its file
+fn file_position(buffer: &gtk::TextBuffer) -> FilePosition {
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    FilePosition { cursor: (cursor.line(), cursor.line_offset()), bookmarks: bookmark_lines(buffer), last_used: 0 }
+}
+
+// Ask for a single existing file and hand it to `on_chosen`
+fn choose_file<F: Fn(PathBuf) + 'static>(window: &gtk::ApplicationWindow, state: &Rc<RefCell<EditorState>>, title: &str, on_chosen: F) {
+    let dialog = gtk::FileChooserNative::builder()
+        .title(title)
+        .action(gtk::FileChooserAction::Open)
+        .accept_label("Select")
+        .cancel_label("Cancel")
+        .transient_for(window)
+        .modal(true)
+        .build();
+    
+    if let Ok(state) = state.try_borrow() {
+        install_file_filters(&dialog, &state);
+        set_dialog_folder(&dialog, &state);
+    }
+    
+    let state = state.clone();
+    dialog.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            remember_dialog_filter(dialog, &state);
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                on_chosen(path);
+            }
+        }
+        dialog.destroy();
+    });
+    
+    dialog.show();
+}
+
+// File -> Save session: name the open files and workspace so they can be reopened together
+fn save_session_dialog(window: &gtk::ApplicationWindow, tab_bar: &TabBar) {
+    let dialog = gtk::Dialog::with_buttons(
+        Some("Save Session"),
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &[
+            ("Save", gtk::ResponseType::Accept),
+            ("Cancel", gtk::ResponseType::Cancel),
+        ],
+    );
+    dialog.set_default_width(350);
+    dialog.set_default_response(gtk::ResponseType::Accept);
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(6);
+    grid.set_margin_start(10);
+    grid.set_margin_end(10);
+    grid.set_margin_top(10);
+    grid.set_margin_bottom(10);
+
+    let name_label = gtk::Label::new(Some("Session name:"));
+    name_label.set_halign(gtk::Align::Start);
+    let name_entry = gtk::Entry::new();
+    name_entry.set_hexpand(true);
+    name_entry.set_activates_default(true);
+
+    // Suggest the workspace folder's name
+    let workspace_root = tab_bar.editor_state.try_borrow().ok().and_then(|state| state.workspace_root.clone());
+    if let Some(name) = workspace_root.as_ref().and_then(|root| root.file_name()) {
+        name_entry.set_text(&name.to_string_lossy());
+    }
+
+    grid.attach(&name_label, 0, 0, 1, 1);
+    grid.attach(&name_entry, 1, 0, 1, 1);
+    dialog.content_area().append(&grid);
+
+    let tab_bar = tab_bar.clone();
+    dialog.connect_response(move |dialog, response| {
+        let name = name_entry.text().trim().to_string();
+        if response == gtk::ResponseType::Accept && !name.is_empty() {
+            let open_files = tab_bar.tabs.borrow().open_files();
+            let zoom_levels = open_files.iter()
+                .filter(|(_, zoom_level)| (zoom_level - 1.0).abs() > f64::EPSILON)
+                .cloned()
+                .collect();
+            if let Ok(mut state) = tab_bar.editor_state.try_borrow_mut() {
+                let session = state::Session {
+                    name,
+                    workspace_root: state.workspace_root.clone(),
+                    files: open_files.into_iter().map(|(path, _)| path).collect(),
+                    zoom_levels,
+                };
+                state.persistent.remember_session(session);
+                if let Err(e) = state.persistent.save() {
+                    warn!("Failed to save editor state: {}", e);
+                }
+            }
+        }
+        dialog.destroy();
+    });
+    dialog.show();
+}
+
+// Edit -> Open selection: a URL opens in the browser, an existing file in a tab of its own
+// and any other text is searched for across the workspace
+fn open_selection(window: &gtk::ApplicationWindow, tab_bar: &TabBar, status_label: &gtk::Label) {
+    let buffer = tab_bar.text_view.buffer();
+    let Some((start, end)) = buffer.selection_bounds() else {
+        return;
+    };
+    let selection = buffer.text(&start, &end, false);
+    let selection = selection.trim();
+    if selection.is_empty() {
+        return;
+    }
+
+    if ["http://", "https://", "ftp://", "file://", "mailto:"].iter().any(|scheme| selection.starts_with(scheme)) {
+        if let Err(e) = gio::AppInfo::launch_default_for_uri(selection, None::<&gio::AppLaunchContext>) {
+            warn!("Failed to open {}: {}", selection, e);
+        }
+        return;
+    }
+
+    if let Some(path) = resolve_hover_path(&tab_bar.editor_state, selection) {
+        let title = path.file_name().map(|name| name.to_string_lossy().to_string());
+        let buffer = open_new_tab(tab_bar, title.as_deref());
+        open_path_in_view(window, &buffer, &tab_bar.text_view, &tab_bar.editor_state, status_label, path);
+        return;
+    }
+
+    show_find_in_files();
+    FIND_IN_FILES_PANEL.with(|panel| {
+        if let Some(panel) = panel.borrow().as_ref() {
+            panel.entry.set_text(selection);
+            panel.entry.emit_activate();
+        }
+    });
+}
+
+// Edit -> Open selection in new tab: start an Untitled tab holding the selected text
+fn open_selection_in_new_tab(tab_bar: &TabBar) {
+    let buffer = tab_bar.text_view.buffer();
+    let Some((start, end)) = buffer.selection_bounds() else {
+        return;
+    };
+    let selection = buffer.text(&start, &end, false);
+    let new_buffer = open_new_tab(tab_bar, None);
+    new_buffer.set_text(&selection);
+    new_buffer.place_cursor(&new_buffer.start_iter());
+    tab_bar.text_view.grab_focus();
+}
+
+// Reopen a session's files, reusing the shown tab while it is still empty
+fn load_session(window: &gtk::ApplicationWindow, tab_bar: &TabBar, status_label: &gtk::Label, name: &str) {
+    let session = match tab_bar.editor_state.try_borrow_mut() {
+        Ok(mut state) => {
+            let Some(session) = state.persistent.sessions.iter().find(|session| session.name == name).cloned() else {
+                return;
+            };
+            state.persistent.remember_session(session.clone());
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+            state.workspace_root = session.workspace_root.clone();
+            session
+        }
+        Err(_) => return,
+    };
+
+    let mut lapsed = Vec::new();
+    for path in session.files {
+        let zoom_level = session.zoom_levels.get(&path).copied().unwrap_or(1.0);
+        if FileAccess::current().needs_grant(&path) {
+            lapsed.push(path);
+            continue;
+        }
+        if !path.is_file() {
+            warn!("Skipping missing session file {}", path.display());
+            continue;
+        }
+
+        let buffer = if shown_tab_is_empty(tab_bar) {
+            tab_bar.text_view.buffer()
+        } else {
+            let title = path.file_name().map(|name| name.to_string_lossy().to_string());
+            open_new_tab(tab_bar, title.as_deref())
+        };
+        open_path_in_view(window, &buffer, &tab_bar.text_view, &tab_bar.editor_state, status_label, path);
+
+        // The tab just opened is the shown one, so it owns the shared state
+        if let Ok(mut state) = tab_bar.editor_state.try_borrow_mut() {
+            state.zoom_level = zoom_level;
+            apply_zoom(&tab_bar.text_view, &state.settings, zoom_level);
+        }
+    }
+    pick_lapsed_files(window, tab_bar, status_label, lapsed);
+}
+
+// Show the tab of `path` if it is open already, else open it in the shown tab while that
+// is empty, or in a new one
+fn show_path_in_tab(window: &gtk::ApplicationWindow, tab_bar: &TabBar, status_label: &gtk::Label, path: PathBuf) {
+    let open_tab = tab_bar.tabs.borrow().summaries()
+        .into_iter()
+        .find(|summary| summary.file.as_deref() == Some(path.as_path()))
+        .map(|summary| summary.button);
+    if let Some(button) = open_tab {
+        button.emit_clicked();
+        return;
+    }
+
+    let buffer = if shown_tab_is_empty(tab_bar) {
+        tab_bar.text_view.buffer()
+    } else {
+        let title = path.file_name().map(|name| name.to_string_lossy().to_string());
+        open_new_tab(tab_bar, title.as_deref())
+    };
+    open_path_in_view(window, &buffer, &tab_bar.text_view, &tab_bar.editor_state, status_label, path);
+}
+
+type FileHandoff = Box<dyn Fn(Vec<PathBuf>)>;
+
+thread_local! {
+    // Opens files given on the command line, or handed over by a second launch, in the window
+    static FILE_HANDOFF: RefCell<Option<FileHandoff>> = const { RefCell::new(None) };
+}
+
+// Open `paths` in the window and raise it
+fn open_handed_over_files(paths: Vec<PathBuf>) {
+    FILE_HANDOFF.with(|handoff| {
+        if let Some(open) = handoff.borrow().as_ref() {
+            open(paths);
+        }
+    });
+}
+
+// Inside a sandbox, have the user pick each file the session's access to has lapsed, one
+// dialog after another, and open what they pick
+fn pick_lapsed_files(window: &gtk::ApplicationWindow, tab_bar: &TabBar, status_label: &gtk::Label, mut paths: Vec<PathBuf>) {
+    if paths.is_empty() {
+        return;
+    }
+    let path = paths.remove(0);
+    let dialog = gtk::FileChooserNative::builder()
+        .title(format!("Find \"{}\" Again", display_file_name(&path)))
+        .action(gtk::FileChooserAction::Open)
+        .accept_label("Open")
+        .cancel_label("Skip")
+        .transient_for(window)
+        .modal(true)
+        .build();
+
+    let window = window.clone();
+    let tab_bar = tab_bar.clone();
+    let status_label = status_label.clone();
+    dialog.connect_response(move |dialog, response| {
+        let picked = dialog.file().and_then(|file| file.path()).filter(|_| response == gtk::ResponseType::Accept);
+        dialog.destroy();
+        if let Some(picked) = picked {
+            show_path_in_tab(&window, &tab_bar, &status_label, picked);
+        }
+        pick_lapsed_files(&window, &tab_bar, &status_label, paths.clone());
+    });
+    dialog.show();
+}
+
+// Popover listing saved sessions, most recently used first
+fn show_sessions_popover(button: &gtk::Button, window: &gtk::ApplicationWindow, tab_bar: &TabBar, status_label: &gtk::Label) {
+    let popover = gtk::Popover::new();
+    popover.set_parent(button);
+    popover.connect_closed(|popover| popover.unparent());
+
+    let sessions_box = gtk::Box::new(gtk::Orientation::Vertical, 4);
+    sessions_box.set_margin_top(4);
+    sessions_box.set_margin_bottom(4);
+    sessions_box.set_margin_start(4);
+    sessions_box.set_margin_end(4);
+
+    let sessions = tab_bar.editor_state.try_borrow()
+        .map(|state| state.persistent.sessions.clone())
+        .unwrap_or_default();
+
+    if sessions.is_empty() {
+        sessions_box.append(&gtk::Label::new(Some("No saved sessions")));
+    }
+    for session in sessions {
+        let session_button = gtk::Button::with_label(&session.name);
+        session_button.set_has_frame(false);
+        session_button.set_hexpand(true);
+        session_button.set_halign(gtk::Align::Start);
+        let workspace = session.workspace_root
+            .map(|root| root.to_string_lossy().to_string())
+            .unwrap_or_else(|| "no workspace".to_string());
+        session_button.set_tooltip_text(Some(&format!("{} ({} files)", workspace, session.files.len())));
+
+        let window = window.clone();
+        let tab_bar = tab_bar.clone();
+        let status_label = status_label.clone();
+        let popover_ref = popover.clone();
+        session_button.connect_clicked(move |_| {
+            popover_ref.popdown();
+            load_session(&window, &tab_bar, &status_label, &session.name);
+        });
+        sessions_box.append(&session_button);
+    }
+
+    popover.set_child(Some(&sessions_box));
+    popover.popup();
+}
+
+// Startup prompt offering the saved sessions, when enabled in the File menu
+fn prompt_for_session(window: &gtk::ApplicationWindow, tab_bar: &TabBar, status_label: &gtk::Label) {
+    let sessions = match tab_bar.editor_state.try_borrow() {
+        Ok(state) if state.persistent.prompt_for_session => state.persistent.sessions.clone(),
+        _ => return,
+    };
+    if sessions.is_empty() {
+        return;
+    }
+
+    let dialog = gtk::Dialog::with_buttons(
+        Some("Open Session"),
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &[("Start Empty", gtk::ResponseType::Cancel)],
+    );
+    dialog.set_default_width(350);
+
+    let list = gtk::ListBox::new();
+    list.set_margin_start(10);
+    list.set_margin_end(10);
+    list.set_margin_top(10);
+    list.set_margin_bottom(10);
+    for session in &sessions {
+        let label = gtk::Label::new(Some(&session.name));
+        label.set_halign(gtk::Align::Start);
+        list.append(&label);
+    }
+    dialog.content_area().append(&list);
+
+    let window = window.clone();
+    let tab_bar = tab_bar.clone();
+    let status_label = status_label.clone();
+    let dialog_ref = dialog.clone();
+    list.connect_row_activated(move |_, row| {
+        dialog_ref.destroy();
+        if let Some(session) = sessions.get(row.index() as usize) {
+            load_session(&window, &tab_bar, &status_label, &session.name);
+        }
+    });
+    dialog.connect_response(|dialog, _| dialog.destroy());
+    dialog.show();
+}
+
+// Shortest autosave interval accepted, so a bad setting cannot keep the disk busy
+const MIN_AUTOSAVE_INTERVAL_SECS: u32 = 5;
+
+// Refresh the recovery copies every autosave interval; the interval is read again each time
+// so changing it takes effect after the next write
+fn schedule_autosave(tabs: &Rc<RefCell<TabManager>>) {
+    let interval = tabs.borrow().state.try_borrow()
+        .map(|state| state.settings.autosave_interval_secs)
+        .unwrap_or(30)
+        .max(MIN_AUTOSAVE_INTERVAL_SECS);
+
+    let tabs = tabs.clone();
+    glib::timeout_add_local_once(Duration::from_secs(interval as u64), move || {
+        tabs.borrow().update_recovery_files();
+        schedule_autosave(&tabs);
+    });
+}
+
+// Startup prompt offering to restore tabs that had unsaved changes when an earlier run crashed
+fn offer_recovery(window: &gtk::ApplicationWindow, tab_bar: &TabBar) {
+    let leftovers = recovery::leftovers();
+    if leftovers.is_empty() {
+        return;
+    }
+
+    let names: Vec<String> = leftovers.iter()
+        .map(|(_, file)| format!("• {}", file.tab_name))
+        .collect();
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Question,
+        gtk::ButtonsType::None,
+        "Recover unsaved changes?",
+    );
+    dialog.set_secondary_text(Some(&format!(
+        "The editor did not shut down cleanly. Unsaved changes to these tabs were kept:\n{}",
+        names.join("\n"),
+    )));
+    dialog.add_buttons(&[
+        ("Discard", gtk::ResponseType::Reject),
+        ("Not Now", gtk::ResponseType::Cancel),
+        ("Restore", gtk::ResponseType::Accept),
+    ]);
+    dialog.set_default_response(gtk::ResponseType::Accept);
+
+    let tab_bar = tab_bar.clone();
+    dialog.connect_response(move |dialog, response| {
+        dialog.destroy();
+        match response {
+            gtk::ResponseType::Accept => {
+                for (path, file) in &leftovers {
+                    restore_recovered_tab(&tab_bar, file);
+                    recovery::discard(path);
+                }
+            }
+            gtk::ResponseType::Reject => {
+                for (path, _) in &leftovers {
+                    recovery::discard(path);
+                }
+            }
+            // Keep the copies to be offered again next time
+            _ => {}
+        }
+    });
+    dialog.show();
+}
+
+// Show recovered text in a tab, reusing the shown tab while it is still empty
+fn restore_recovered_tab(tab_bar: &TabBar, file: &recovery::RecoveryFile) {
+    let buffer = if shown_tab_is_empty(tab_bar) {
+        tab_bar.text_view.buffer()
+    } else {
+        open_new_tab(tab_bar, Some(&file.tab_name))
+    };
+
+    // Set before the text goes in, so highlighting picks the right language
+    if let Ok(mut state) = tab_bar.editor_state.try_borrow_mut() {
+        state.current_file = file.path.clone();
+        state.tab_name = file.tab_name.clone();
+        state.language_override = file.path.as_ref().and_then(|path| state.persistent.language_overrides.get(path).cloned());
+    }
+    buffer.set_text(&file.text);
+    if let Ok(mut state) = tab_bar.editor_state.try_borrow_mut() {
+        // The recovered text was never saved
+        state.is_modified = true;
+    }
+}
+
+fn compare_files(tab_bar: &TabBar, old_path: &Path, new_path: &Path) {
+    let (tab_bar, old_path, new_path) = (tab_bar.clone(), old_path.to_path_buf(), new_path.to_path_buf());
+    let paths = (old_path.clone(), new_path.clone());
+    run_file_job(
+        format!("Comparing {}…", display_file_name(&old_path)),
+        move |cancellable| {
+            let (old_path, new_path) = paths;
+            (encoding::read_file(&old_path, Some(cancellable)), encoding::read_file(&new_path, Some(cancellable)))
+        },
+        move |read| {
+            let Some((old_read, new_read)) = read else {
+                return;
+            };
+            let text = |path: &Path, read: Result<DecodedText>| {
+                read.map(|decoded| decoded.text)
+                    .map_err(|e| {
+                        error!("Failed to read {} for comparison: {}", path.display(), e);
+                        show_warning_bar(&format!("Could not compare {}: {}", path.display(), describe_file_error(&e)));
+                    })
+                    .ok()
+            };
+            if let (Some(old_text), Some(new_text)) = (text(&old_path, old_read), text(&new_path, new_read)) {
+                let title = format!("{} ↔ {}", display_file_name(&old_path), display_file_name(&new_path));
+                show_comparison(&tab_bar, &title, &old_path.to_string_lossy(), &new_path.to_string_lossy(), &old_text, &new_text);
+            }
+        },
+    );
+}
+
+// Compare the shown tab's text with its file as last saved
+fn compare_with_saved(tab_bar: &TabBar) {
+    let Some(path) = tab_bar.editor_state.try_borrow().ok().and_then(|state| state.current_file.clone()) else {
+        show_warning_bar("This tab has not been saved yet, so there is nothing to compare it with.");
+        return;
+    };
+    let buffer = tab_bar.text_view.buffer();
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false).to_string();
+    let tab_bar = tab_bar.clone();
+    let saved_path = path.clone();
+    run_file_job(
+        format!("Comparing {}…", display_file_name(&path)),
+        move |cancellable| encoding::read_file(&saved_path, Some(cancellable)),
+        move |read| {
+            match read {
+                Some(Ok(decoded)) => {
+                    let title = format!("{} (saved ↔ edited)", display_file_name(&path));
+                    let name = path.to_string_lossy();
+                    let saved = line_ending::normalize(&decoded.text);
+                    show_comparison(&tab_bar, &title, &format!("{} (saved)", name), &format!("{} (edited)", name), &saved, &text);
+                }
+                Some(Err(e)) => {
+                    error!("Failed to read {} for comparison: {}", path.display(), e);
+                    show_warning_bar(&format!("Could not compare {}: {}", path.display(), describe_file_error(&e)));
+                }
+                None => {}
+            }
+        },
+    );
+}
+
+// Texts of a comparison tab, kept so it can be shown again in the other layout
+struct DiffTab {
+    buffer: gtk::TextBuffer,
+    old_text: String,
+    new_text: String,
+    old_name: String,
+    new_name: String,
+    side_by_side: bool,
+    /// First line of each change in the tab, for stepping through them
+    changes: Vec<i32>,
+}
+
+impl DiffTab {
+    fn show(&mut self, side_by_side: bool, tab_width: u32) {
+        self.side_by_side = side_by_side;
+        self.changes = if side_by_side {
+            show_side_by_side_diff(&self.buffer, &self.old_text, &self.new_text, &self.old_name, &self.new_name, tab_width)
+        } else {
+            show_diff(&self.buffer, &self.old_text, &self.new_text, &self.old_name, &self.new_name)
+        };
+    }
+}
+
+thread_local! {
+    // Open comparison tabs
+    static DIFF_TABS: RefCell<Vec<DiffTab>> = const { RefCell::new(Vec::new()) };
+}
+
+// Open a tab with the diff of two texts, in the layout of the View menu
+fn show_comparison(tab_bar: &TabBar, title: &str, old_name: &str, new_name: &str, old_text: &str, new_text: &str) {
+    let buffer = open_new_tab(tab_bar, Some(title));
+    let (side_by_side, tab_width) = match tab_bar.editor_state.try_borrow_mut() {
+        Ok(mut state) => {
+            state.syntax_highlighting = false;
+            (state.persistent.side_by_side_diff, state.settings.tab_width)
+        }
+        Err(_) => (false, Settings::default().tab_width),
+    };
+    let mut diff_tab = DiffTab {
+        buffer,
+        old_text: old_text.to_string(),
+        new_text: new_text.to_string(),
+        old_name: old_name.to_string(),
+        new_name: new_name.to_string(),
+        side_by_side,
+        changes: Vec::new(),
+    };
+    diff_tab.show(side_by_side, tab_width);
+    DIFF_TABS.with(|tabs| tabs.borrow_mut().push(diff_tab));
+}
+
+// Show the comparison in the shown tab again if it was laid out the other way. Closed
+// comparison tabs are forgotten.
+fn update_diff_layout(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    if let Some(summaries) = TABS.with(|tabs| tabs.borrow().as_ref().and_then(|tabs| tabs.try_borrow().ok().map(|tabs| tabs.summaries()))) {
+        DIFF_TABS.with(|tabs| tabs.borrow_mut().retain(|tab| summaries.iter().any(|summary| summary.buffer == tab.buffer)));
+    }
+    let Ok((side_by_side, tab_width)) = state.try_borrow().map(|state| (state.persistent.side_by_side_diff, state.settings.tab_width)) else {
+        return;
+    };
+    let buffer = text_view.buffer();
+    DIFF_TABS.with(|tabs| {
+        if let Some(tab) = tabs.borrow_mut().iter_mut().find(|tab| tab.buffer == buffer && tab.side_by_side != side_by_side) {
+            tab.show(side_by_side, tab_width);
+        }
+    });
+}
+
+// Alt+F5 and Shift+Alt+F5: go to the next or previous change of a comparison tab, or of the
+// shown file against its last commit
+fn step_change(text_view: &gtk::TextView, forward: bool) {
+    let buffer = text_view.buffer();
+    let changes = DIFF_TABS.with(|tabs| tabs.borrow().iter().find(|tab| tab.buffer == buffer).map(|tab| tab.changes.clone()));
+    let lines = changes.unwrap_or_else(|| {
+        // A deletion at the end is marked on the last line
+        git_hunks(&buffer).iter().map(|hunk| (hunk.lines.start as i32).min(buffer.line_count() - 1)).collect()
+    });
+    step_to_line(text_view, &lines, forward);
+}
+
+// Render a unified diff of two texts into `buffer`, with intra-line highlighting
+// for lines that were changed rather than purely added or removed. Gives the first
+// line of each change.
+fn show_diff(buffer: &gtk::TextBuffer, old_text: &str, new_text: &str, old_name: &str, new_name: &str) -> Vec<i32> {
+    buffer.set_text("");
+    
+    let insert_line = |text: &str, tag: &str| -> i32 {
+        let line_start = buffer.end_iter().offset();
+        let mut end = buffer.end_iter();
+        buffer.insert_with_tags_by_name(&mut end, &format!("{}\n", text), &[tag]);
+        line_start
+    };
+    let tag_ranges = |line_start: i32, line: &str, ranges: &[Range<usize>], tag: &str| {
+        for range in ranges {
+            // Ranges are bytes within the line; the buffer counts characters after the 2-char prefix
+            let start = line_start + 2 + line[..range.start].chars().count() as i32;
+            let end = start + line[range.clone()].chars().count() as i32;
+            buffer.apply_tag_by_name(tag, &buffer.iter_at_offset(start), &buffer.iter_at_offset(end));
+        }
+    };
+    
+    insert_line(&format!("--- {}", old_name), "diff-header");
+    insert_line(&format!("+++ {}", new_name), "diff-header");
+    
+    let lines = diff::diff_lines(old_text, new_text);
+    let mut changes = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].kind == diff::DiffKind::Equal {
+            let mut end = buffer.end_iter();
+            buffer.insert(&mut end, &format!("  {}\n", lines[i].text));
+            i += 1;
+            continue;
+        }
+        changes.push(buffer.end_iter().line());
+        
+        // Collect a block of removals followed by additions and pair them up line by line
+        let removed_start = i;
+        while i < lines.len() && lines[i].kind == diff::DiffKind::Removed {
+            i += 1;
+        }
+        let added_start = i;
+        while i < lines.len() && lines[i].kind == diff::DiffKind::Added {
+            i += 1;
+        }
+        let removed = &lines[removed_start..added_start];
+        let added = &lines[added_start..i];
+        
+        let removed_offsets: Vec<i32> = removed.iter()
+            .map(|line| insert_line(&format!("- {}", line.text), "diff-removed"))
+            .collect();
+        let added_offsets: Vec<i32> = added.iter()
+            .map(|line| insert_line(&format!("+ {}", line.text), "diff-added"))
+            .collect();
+        
+        for (pair, (old_line, new_line)) in removed.iter().zip(added).enumerate() {
+            let (removed_ranges, added_ranges) = diff::diff_inline(old_line.text, new_line.text);
+            tag_ranges(removed_offsets[pair], old_line.text, &removed_ranges, "diff-removed-inline");
+            tag_ranges(added_offsets[pair], new_line.text, &added_ranges, "diff-added-inline");
+        }
+    }
+    
+    if lines.iter().all(|line| line.kind == diff::DiffKind::Equal) {
+        insert_line("(files are identical)", "diff-header");
+    }
+    
+    buffer.place_cursor(&buffer.start_iter());
+    changes
+}
+
+// Separator between the columns of a side-by-side diff
+const DIFF_COLUMN_SEPARATOR: &str = " │ ";
+
+// `line` with its tabs turned into spaces up to the next multiple of `tab_width`, so the
+// columns of a side-by-side diff line up
+fn expand_tabs(line: &str, tab_width: u32) -> String {
+    let tab_width = tab_width.max(1) as usize;
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = tab_width - column % tab_width;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+    expanded
+}
+
+// Render a diff of two texts into `buffer` as two columns, the old text on the left and
+// the new on the right, changed lines across from the lines they replace. Gives the first
+// line of each change.
+fn show_side_by_side_diff(buffer: &gtk::TextBuffer, old_text: &str, new_text: &str, old_name: &str, new_name: &str, tab_width: u32) -> Vec<i32> {
+    // Rows of (old line, new line), each missing on the side where lines were only added or
+    // removed, and whether the row is part of a change
+    let lines = diff::diff_lines(old_text, new_text);
+    let mut rows: Vec<(Option<String>, Option<String>, bool)> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].kind == diff::DiffKind::Equal {
+            let line = expand_tabs(lines[i].text, tab_width);
+            rows.push((Some(line.clone()), Some(line), false));
+            i += 1;
+            continue;
+        }
+        let removed_start = i;
+        while i < lines.len() && lines[i].kind == diff::DiffKind::Removed {
+            i += 1;
+        }
+        let added_start = i;
+        while i < lines.len() && lines[i].kind == diff::DiffKind::Added {
+            i += 1;
+        }
+        let (removed, added) = (&lines[removed_start..added_start], &lines[added_start..i]);
+        for row in 0..removed.len().max(added.len()) {
+            let old = removed.get(row).map(|line| expand_tabs(line.text, tab_width));
+            let new = added.get(row).map(|line| expand_tabs(line.text, tab_width));
+            rows.push((old, new, true));
+        }
+    }
+
+    let old_header = format!("--- {}", old_name);
+    let new_header = format!("+++ {}", new_name);
+    let old_width = rows.iter()
+        .filter_map(|(old, _, _)| old.as_ref())
+        .map(|old| old.chars().count())
+        .chain([old_header.chars().count()])
+        .max()
+        .unwrap_or(0);
+
+    buffer.set_text("");
+    // Insert one row, tagging the old cell padded to the width of its column and the new one
+    // up to the end of the line
+    let insert_row = |old: &str, new: &str, tags: (Option<&str>, Option<&str>)| -> (i32, i32) {
+        let row_start = buffer.end_iter().offset();
+        let mut end = buffer.end_iter();
+        buffer.insert(&mut end, &format!("{:width$}{}{}\n", old, DIFF_COLUMN_SEPARATOR, new, width = old_width));
+        let new_start = row_start + (old_width + DIFF_COLUMN_SEPARATOR.chars().count()) as i32;
+        for (tag, start, len) in [(tags.0, row_start, old_width), (tags.1, new_start, new.chars().count())] {
+            if let Some(tag) = tag {
+                buffer.apply_tag_by_name(tag, &buffer.iter_at_offset(start), &buffer.iter_at_offset(start + len as i32));
+            }
+        }
+        (row_start, new_start)
+    };
+    let tag_ranges = |cell_start: i32, line: &str, ranges: &[Range<usize>], tag: &str| {
+        for range in ranges {
+            let start = cell_start + line[..range.start].chars().count() as i32;
+            let end = start + line[range.clone()].chars().count() as i32;
+            buffer.apply_tag_by_name(tag, &buffer.iter_at_offset(start), &buffer.iter_at_offset(end));
+        }
+    };
+
+    insert_row(&old_header, &new_header, (Some("diff-header"), Some("diff-header")));
+    let mut changes = Vec::new();
+    let mut in_change = false;
+    for (old, new, changed) in &rows {
+        if *changed && !in_change {
+            changes.push(buffer.end_iter().line());
+        }
+        in_change = *changed;
+        let tags = if *changed {
+            (old.as_ref().map(|_| "diff-removed-cell"), new.as_ref().map(|_| "diff-added-cell"))
+        } else {
+            (None, None)
+        };
+        let (old_start, new_start) = insert_row(old.as_deref().unwrap_or_default(), new.as_deref().unwrap_or_default(), tags);
+        if let (true, Some(old), Some(new)) = (*changed, old, new) {
+            let (removed_ranges, added_ranges) = diff::diff_inline(old, new);
+            tag_ranges(old_start, old, &removed_ranges, "diff-removed-inline");
+            tag_ranges(new_start, new, &added_ranges, "diff-added-inline");
+        }
+    }
+
+    if rows.iter().all(|(_, _, changed)| !changed) {
+        let mut end = buffer.end_iter();
+        buffer.insert_with_tags_by_name(&mut end, "(files are identical)\n", &["diff-header"]);
+    }
+
+    buffer.place_cursor(&buffer.start_iter());
+    changes
+}
+
+// Widgets a tab needs in order to add itself to the tab bar
+#[derive(Clone)]
+struct TabBar {
+    tabs_box: gtk::Box,
+    new_tab_button: gtk::Button,
+    first_tab: gtk::Button,
+    first_buffer: gtk::TextBuffer,
+    text_view: gtk::TextView,
+    editor_state: Rc<RefCell<EditorState>>,
+    tabs: Rc<RefCell<TabManager>>,
+}
+
+// Create a new tab after the existing ones, switch to it and return its buffer
+fn open_new_tab(tab_bar: &TabBar, title: Option<&str>) -> gtk::TextBuffer {
+    let tabs_box_ref = tab_bar.tabs_box.clone();
+    let new_tab_button_ref = tab_bar.new_tab_button.clone();
+    let editor_state_ref = tab_bar.editor_state.clone();
+    let text_view_ref = tab_bar.text_view.clone();
+    let tab_button_wrapper_ref = tab_bar.first_tab.clone();
+    let buffer_for_new_tab = tab_bar.first_buffer.clone();
+    
+    // Create a new buffer with syntax highlighting
+    let tag_table = shared_tag_table();
+    let new_buffer = TextBuffer::new(Some(&tag_table));
+    
+    // Generate tab ID
+    let tab_id = {
+        if let Ok(mut state) = editor_state_ref.try_borrow_mut() {
+            state.active_tab_id += 1;
+            state.active_tab_id
+        } else {
+            0
+        }
+    };
+    
+    // Create new tab with initial opacity of 0
+    let new_tab_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    new_tab_box.set_css_classes(&["tab-button"]);
+    new_tab_box.set_opacity(0.0);
+    create_tab_transition(&new_tab_box);
+    
+    let tab_title = title.map(str::to_string).unwrap_or_else(|| format!("Untitled {}", tab_id));
+    let new_tab_label = gtk::Label::new(Some(&tab_title));
+    new_tab_label.set_css_classes(&["tab-label"]);
+    new_tab_label.set_ellipsize(pango::EllipsizeMode::End);
+    new_tab_label.set_width_chars(15);
+    new_tab_label.set_max_width_chars(15);
+    
+    let new_tab_lock = gtk::Image::from_icon_name("changes-prevent-symbolic");
+    new_tab_lock.set_tooltip_text(Some("Read-only"));
+    new_tab_lock.set_visible(false);
+    
+    let new_close_icon = gtk::Button::new();
+    new_close_icon.set_css_classes(&["tab-close-button"]);
+    new_close_icon.set_icon_name("window-close-symbolic");
+    new_close_icon.set_tooltip_text(Some("Close tab"));
+    
+    new_tab_box.append(&new_tab_lock);
+    new_tab_box.append(&new_tab_label);
+    new_tab_box.append(&new_close_icon);
+    
+    let new_tab_wrapper = gtk::Button::new();
+    new_tab_wrapper.set_css_classes(&["tab-button-wrapper"]);
+    new_tab_wrapper.set_has_frame(false);
+    new_tab_wrapper.set_child(Some(&new_tab_box));
+    tab_bar.tabs.borrow_mut().add_tab(&new_buffer, &new_tab_label, &new_tab_lock, &new_tab_wrapper);
+    connect_tab_tooltip(&new_tab_wrapper, &new_buffer, &tab_bar.tabs);
+    
+    // Add the tab to the box first
+    tabs_box_ref.remove(&new_tab_button_ref);
+    tabs_box_ref.append(&new_tab_wrapper);
+    tabs_box_ref.append(&new_tab_button_ref);
+    
+    // Use a timeout to trigger the fade-in
+    glib::timeout_add_local(Duration::from_millis(50), move || {
+        new_tab_box.set_opacity(1.0);
+        glib::ControlFlow::Break
+    });
+    
+    // Connect close button - we need a fresh buffer for each tab
+    let tabs_box_ref_clone = tabs_box_ref.clone();
+    let new_tab_wrapper_clone = new_tab_wrapper.clone();
+    let text_view_ref_clone = text_view_ref.clone();
+    // Create a fresh buffer clone specific to this closure
+    let buffer_for_close = buffer_for_new_tab.clone();
+    let tab_button_wrapper_ref_clone = tab_button_wrapper_ref.clone();
+    
+    // CRITICAL: Create separate click controller for close button to ensure clicks are captured
+    let click_controller = gtk::GestureClick::new();
+    click_controller.set_button(1); // Left mouse button
+    click_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    new_close_icon.add_controller(click_controller.clone());
+    
+    let tabs_box_ref_clone = tabs_box_ref.clone();
+    let new_tab_wrapper_clone = new_tab_wrapper.clone();
+    let text_view_ref_clone = text_view_ref.clone();
+    let buffer_for_close = buffer_for_new_tab.clone();
+    let tab_button_wrapper_ref_clone = tab_button_wrapper_ref.clone();
+    let tabs_ref = tab_bar.tabs.clone();
+    let closed_buffer = new_buffer.clone();
+    let state_for_close = tab_bar.editor_state.clone();
+    
+    click_controller.connect_pressed(move |gesture, _, _, _| {
+        debug!("Tab X button clicked");
+        gesture.set_state(gtk::EventSequenceState::Claimed);
+        
+        let modified = tabs_ref.borrow().is_modified(&closed_buffer);
+        let tab_wrapper = new_tab_wrapper_clone.clone();
+        let text_view = text_view_ref_clone.clone();
+        
+        let tabs_box_ref_clone = tabs_box_ref_clone.clone();
+        let new_tab_wrapper_clone = new_tab_wrapper_clone.clone();
+        let text_view_ref_clone = text_view_ref_clone.clone();
+        let buffer_for_close = buffer_for_close.clone();
+        let tab_button_wrapper_ref_clone = tab_button_wrapper_ref_clone.clone();
+        let tabs_ref = tabs_ref.clone();
+        let closed_buffer = closed_buffer.clone();
+        let close_tab = move || {
+            // Check if this is the active tab
+            let is_active = new_tab_wrapper_clone.css_classes().iter().any(|class| class == "active");
+            debug!("Is active tab: {}", is_active);
+            
+            // Create fade-out transition
+            create_tab_transition(&new_tab_wrapper_clone);
+            
+            // Start the fade-out
+            new_tab_wrapper_clone.set_opacity(0.0);
+            
+            // Clone all the necessary variables for the inner closure
+            let tabs_box_ref_inner = tabs_box_ref_clone.clone();
+            let new_tab_wrapper_inner = new_tab_wrapper_clone.clone();
+            let text_view_ref_inner = text_view_ref_clone.clone();
+            let buffer_for_close_inner = buffer_for_close.clone();
+            let tab_button_wrapper_ref_inner = tab_button_wrapper_ref_clone.clone();
+            let is_active_inner = is_active;
+            let tabs_inner = tabs_ref.clone();
+            let closed_buffer_inner = closed_buffer.clone();
+            
+            glib::timeout_add_local(Duration::from_millis(150), move || {
+                // Remove the tab after animation completes
+                tabs_box_ref_inner.remove(&new_tab_wrapper_inner);
+                
+                // Check if the tab was actually removed
+                if new_tab_wrapper_inner.parent().is_some() {
+                    warn!("Tab wasn't removed properly, it still has a parent");
+                } else {
+                    debug!("Tab was successfully removed");
+                }
+                
+                // If this was the active tab, switch back to the first tab
+                if is_active_inner {
+                    debug!("Switching back to first tab since active tab was closed");
+                    text_view_ref_inner.set_buffer(Some(&buffer_for_close_inner));
+                    tab_button_wrapper_ref_inner.set_css_classes(&["tab-button-wrapper", "active"]);
+                }
+                
+                // Drop the closed tab's editor state
+                tabs_inner.borrow_mut().remove_tab(&closed_buffer_inner);
+                
+                glib::ControlFlow::Break
+            });
+        };
+        
+        // Unsaved changes are offered for saving first, with the tab shown so the
+        // prompt is about the visible tab
+        if !modified {
+            close_tab();
+            return;
+        }
+        tab_wrapper.emit_clicked();
+        confirm_close_tab(&text_view, &state_for_close, close_tab);
+    });
+    
+    // Connect tab button to switch to this tab
+    let new_buffer_clone = new_buffer.clone();
+    let text_view_ref_clone = text_view_ref.clone();
+    let tab_button_wrapper_clone = tab_button_wrapper_ref.clone();
+    
+    new_tab_wrapper.connect_clicked(move |clicked_button| {
+        // Set all tabs to inactive (simplified approach)
+        if let Some(parent) = clicked_button.parent() {
+            if let Some(box_parent) = parent.downcast_ref::<gtk::Box>() {
+                // Find all buttons in the tabs box and set them to inactive
+                let n_children = box_parent.first_child()
+                    .map(|_| {
+                        let mut count = 0;
+                        let mut child = box_parent.first_child();
+                        while let Some(widget) = child {
+                            count += 1;
+                            child = widget.next_sibling();
+                        }
+                        count
+                    })
+                    .unwrap_or(0);
+
+                let mut child = box_parent.first_child();
+                for _ in 0..n_children {
+                    if let Some(widget) = child.clone() {
+                        if let Some(button) = widget.downcast_ref::<gtk::Button>() {
+                            // Don't compare pointers, just set all to inactive
+                            button.set_css_classes(&["tab-button-wrapper"]);
+                        }
+                        child = widget.next_sibling();
+                    }
+                }
+            }
+        }
+        
+        // Set this tab as active
+        clicked_button.set_css_classes(&["tab-button-wrapper", "active"]);
+        // Set old tab to inactive
+        tab_button_wrapper_clone.set_css_classes(&["tab-button-wrapper"]);
+        
+        // Set this tab as active
+        clicked_button.set_css_classes(&["tab-button-wrapper", "active"]);
+        
+        // Switch to this tab's buffer
+        text_view_ref_clone.set_buffer(Some(&new_buffer_clone));
+    });
+    
+    // Add right-click context menu for the new tab
+    let right_click = gtk::GestureClick::new();
+    right_click.set_button(3); // Right mouse button
+    
+    let new_tab_wrapper_ref = new_tab_wrapper.clone();
+    let tabs_box_ref_clone = tabs_box_ref.clone();
+    let text_view_ref_clone = text_view_ref.clone();
+    // Create separate buffer clones to avoid lifetime issues
+    let buffer_for_menu = buffer_for_new_tab.clone();
+    let tab_button_wrapper_ref_clone = tab_button_wrapper_ref.clone();
+    let new_buffer_for_menu = new_buffer.clone();
+    let tabs_for_menu = tab_bar.tabs.clone();
+    let editor_state_for_menu = tab_bar.editor_state.clone();
+    
+    right_click.connect_pressed(move |_, _, _, _| {
+        let popover = gtk::Popover::new();
+        popover.set_parent(&new_tab_wrapper_ref);
+        
+        let box_container = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        box_container.set_margin_top(5);
+        box_container.set_margin_bottom(5);
+        box_container.set_margin_start(5);
+        box_container.set_margin_end(5);
+        
+        // Close tab option
+        let close_item = gtk::Button::new();
+        close_item.set_label("Close Tab");
+        close_item.set_css_classes(&["menu-item"]);
+        close_item.set_has_frame(false);
+        
+        // Create fresh clones for this inner closure
+        let tabs_box_for_close = tabs_box_ref_clone.clone();
+        let new_tab_wrapper_for_close = new_tab_wrapper_ref.clone();
+        let text_view_for_close = text_view_ref_clone.clone();
+        let buffer_for_close = buffer_for_menu.clone();
+        let tab_button_wrapper_for_close = tab_button_wrapper_ref_clone.clone();
+        let popover_for_close = popover.clone();
+        let tabs_for_close = tabs_for_menu.clone();
+        let closed_buffer = new_buffer_for_menu.clone();
+        
+        let close_item_clone = close_item.clone();
+        close_item.connect_clicked(move |_| {
+            // Check if this is the active tab
+            let is_active = new_tab_wrapper_for_close.css_classes().iter().any(|class| class == "active");
+            
+            // Remove this tab
+            tabs_box_for_close.remove(&new_tab_wrapper_for_close);
+            
+            // If this was the active tab, switch back to the first tab
+            if is_active {
+                text_view_for_close.set_buffer(Some(&buffer_for_close));
+                tab_button_wrapper_for_close.set_css_classes(&["tab-button-wrapper", "active"]);
+            }
+            tabs_for_close.borrow_mut().remove_tab(&closed_buffer);
+            
+            // Close the popover
+            popover_for_close.popdown();
+        });
+        
+        // Clear tab content option
+        let clear_item = gtk::Button::new();
+        clear_item.set_label("Clear Content");
+        clear_item.set_css_classes(&["menu-item"]);
+        clear_item.set_has_frame(false);
+        
+        // Create fresh clone for this inner closure
+        let new_buffer_clear = new_buffer_for_menu.clone();
+        let popover_clear = popover.clone();
+        
+        let clear_item_clone = clear_item.clone();
+        clear_item.connect_clicked(move |_| {
+            new_buffer_clear.set_text("");
+            popover_clear.popdown();
+        });
+        
+        box_container.append(&close_item_clone);
+        box_container.append(&clear_item_clone);
+        
+        if let Some(favorite_item) = favorite_menu_item(&tabs_for_menu, &editor_state_for_menu, &new_buffer_for_menu, &popover) {
+            box_container.append(&favorite_item);
+        }
+        
+        popover.set_child(Some(&box_container));
+        popover.popup();
+    });
+    
+    new_tab_wrapper.add_controller(right_click);
+    
+    // Move the + button to the end
+    tabs_box_ref.remove(&new_tab_button_ref);
+    tabs_box_ref.append(&new_tab_wrapper);
+    tabs_box_ref.append(&new_tab_button_ref);
+    
+    // Simulate a click on the new tab to activate it
+    new_tab_wrapper.emit_clicked();
+    
+    new_buffer
+}
+
+thread_local! {
+    // Key bindings, shared by the window's key handler and the shortcuts editor
+    static KEYMAP: RefCell<Keymap> = RefCell::new(Keymap::load());
+    // Shortcut hints next to menu items, relabeled when a binding changes
+    static SHORTCUT_HINTS: RefCell<Vec<(Action, gtk::Label)>> = const { RefCell::new(Vec::new()) };
+}
+
+// Dimmed label showing the shortcut of `action` beside a menu item
+fn shortcut_hint(action: Action) -> gtk::Label {
+    let label = gtk::Label::new(Some(&KEYMAP.with(|keymap| keymap.borrow().primary_label(action))));
+    label.set_css_classes(&["dim-label", "shortcut-label"]);
+    SHORTCUT_HINTS.with(|hints| hints.borrow_mut().push((action, label.clone())));
+    label
+}
+
+fn refresh_shortcut_hints() {
+    KEYMAP.with(|keymap| {
+        let keymap = keymap.borrow();
+        SHORTCUT_HINTS.with(|hints| {
+            for (action, label) in hints.borrow().iter() {
+                label.set_text(&keymap.primary_label(*action));
+            }
+        });
+    });
+}
+
+// Keys that only modify others, ignored while waiting for a new shortcut
+fn is_modifier_key(key: Key) -> bool {
+    matches!(
+        key,
+        Key::Shift_L | Key::Shift_R | Key::Control_L | Key::Control_R | Key::Alt_L | Key::Alt_R
+            | Key::Meta_L | Key::Meta_R | Key::Super_L | Key::Super_R | Key::Hyper_L | Key::Hyper_R
+            | Key::ISO_Level3_Shift | Key::Caps_Lock | Key::Num_Lock
+    )
+}
+
+// Apply `change` to the keymap, then store it and show it everywhere. Showing it reads
+// the keymap again, so it is only borrowed mutably while `change` runs.
+fn change_keymap(change: impl FnOnce(&mut Keymap), buttons: &[(Action, gtk::Button)]) {
+    KEYMAP.with(|keymap| change(&mut keymap.borrow_mut()));
+    KEYMAP.with(|keymap| {
+        let keymap = keymap.borrow();
+        if let Err(e) = keymap.save() {
+            warn!("Failed to save key bindings: {}", e);
+        }
+        show_bindings(&keymap, buttons);
+    });
+}
+
+fn show_bindings(keymap: &Keymap, buttons: &[(Action, gtk::Button)]) {
+    for (action, button) in buttons {
+        let label = keymap.label(*action);
+        button.set_label(if label.is_empty() { "Disabled" } else { &label });
+    }
+    refresh_shortcut_hints();
+}
+
+// Dialog listing every shortcut. Clicking one waits for a new key combination;
+// Escape cancels and Backspace disables the shortcut.
+fn show_shortcuts_editor(window: &gtk::ApplicationWindow) {
+    let dialog = gtk::Dialog::with_buttons(
+        Some("Keyboard Shortcuts"),
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &[("Reset to Defaults", gtk::ResponseType::Reject), ("Close", gtk::ResponseType::Close)],
+    );
+    dialog.set_default_width(400);
+    dialog.set_default_height(500);
+
+    let content_area = dialog.content_area();
+    content_area.set_margin_top(10);
+    content_area.set_margin_bottom(10);
+    content_area.set_margin_start(10);
+    content_area.set_margin_end(10);
+
+    let shortcuts_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    let capturing: Rc<Cell<Option<Action>>> = Rc::new(Cell::new(None));
+    let buttons: Rc<RefCell<Vec<(Action, gtk::Button)>>> = Rc::new(RefCell::new(Vec::new()));
+    let hint = gtk::Label::new(Some("Click a shortcut to change it"));
+    hint.set_css_classes(&["dim-label"]);
+    hint.set_halign(gtk::Align::Start);
+
+    let mut section = "";
+    let mut grid = gtk::Grid::new();
+    let mut row = 0;
+    for action in Action::ALL {
+        if action.section() != section {
+            section = action.section();
+            let heading = gtk::Label::new(Some(section));
+            heading.set_halign(gtk::Align::Start);
+            heading.set_css_classes(&["heading"]);
+            heading.set_margin_top(10);
+            shortcuts_box.append(&heading);
+
+            grid = gtk::Grid::new();
+            grid.set_column_spacing(20);
+            grid.set_row_spacing(5);
+            grid.set_margin_start(10);
+            shortcuts_box.append(&grid);
+            row = 0;
+        }
+
+        let action_label = gtk::Label::new(Some(action.label()));
+        action_label.set_halign(gtk::Align::Start);
+        action_label.set_hexpand(true);
+        let shortcut_button = gtk::Button::new();
+        shortcut_button.set_halign(gtk::Align::End);
+
+        let capturing_ref = capturing.clone();
+        let buttons_ref = buttons.clone();
+        let hint_ref = hint.clone();
+        shortcut_button.connect_clicked(move |button| {
+            // Only one shortcut waits for keys at a time
+            if capturing_ref.get().is_some() {
+                KEYMAP.with(|keymap| show_bindings(&keymap.borrow(), &buttons_ref.borrow()));
+            }
+            capturing_ref.set(Some(action));
+            button.set_label("Press a shortcut…");
+            hint_ref.set_text("Escape cancels, Backspace disables the shortcut");
+        });
+
+        grid.attach(&action_label, 0, row, 1, 1);
+        grid.attach(&shortcut_button, 1, row, 1, 1);
+        buttons.borrow_mut().push((action, shortcut_button));
+        row += 1;
+    }
+    KEYMAP.with(|keymap| show_bindings(&keymap.borrow(), &buttons.borrow()));
+
+    let scrolled_window = gtk::ScrolledWindow::new();
+    scrolled_window.set_child(Some(&shortcuts_box));
+    scrolled_window.set_vexpand(true);
+    content_area.append(&scrolled_window);
+    content_area.append(&hint);
+
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let dialog_ref = dialog.clone();
+    let buttons_ref = buttons.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let Some(action) = capturing.get() else {
+            return glib::Propagation::Proceed;
+        };
+        if is_modifier_key(key) {
+            return glib::Propagation::Stop;
+        }
+
+        let binding = KeyBinding::new(key, modifiers);
+        let new_bindings = match key {
+            Key::Escape => None,
+            Key::BackSpace if !binding.is_usable() => Some(Vec::new()),
+            _ if !binding.is_usable() => {
+                hint.set_text("Shortcuts need Ctrl or Alt, except for function keys");
+                return glib::Propagation::Stop;
+            }
+            _ => Some(vec![binding]),
+        };
+        capturing.set(None);
+        hint.set_text("Click a shortcut to change it");
+
+        let Some(new_bindings) = new_bindings else {
+            KEYMAP.with(|keymap| show_bindings(&keymap.borrow(), &buttons_ref.borrow()));
+            return glib::Propagation::Stop;
+        };
+        let conflict = KEYMAP.with(|keymap| keymap.borrow().action_for(&binding))
+            .filter(|&other| other != action && !new_bindings.is_empty());
+        let Some(other) = conflict else {
+            change_keymap(|keymap| keymap.set(action, new_bindings), &buttons_ref.borrow());
+            return glib::Propagation::Stop;
+        };
+
+        // Taking a shortcut from another action has to be confirmed
+        let message = gtk::MessageDialog::new(
+            Some(&dialog_ref),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            format!("{} is already used by {}", binding.label(), other.label()),
+        );
+        message.set_secondary_text(Some(&format!("Use it for {} instead? {} will lose this shortcut.", action.label(), other.label())));
+        message.add_buttons(&[
+            ("Cancel", gtk::ResponseType::Cancel),
+            ("Reassign", gtk::ResponseType::Accept),
+        ]);
+        message.set_default_response(gtk::ResponseType::Accept);
+        let buttons = buttons_ref.clone();
+        message.connect_response(move |message, response| {
+            message.destroy();
+            if response == gtk::ResponseType::Accept {
+                change_keymap(|keymap| {
+                    keymap.unbind(&binding);
+                    keymap.set(action, new_bindings.clone());
+                }, &buttons.borrow());
+            } else {
+                KEYMAP.with(|keymap| show_bindings(&keymap.borrow(), &buttons.borrow()));
+            }
+        });
+        message.show();
+        glib::Propagation::Stop
+    });
+    dialog.add_controller(key_controller);
+
+    dialog.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Reject {
+            change_keymap(|keymap| *keymap = Keymap::default(), &buttons.borrow());
+            return;
+        }
+        dialog.destroy();
+    });
+
+    dialog.show();
+}
+
+// View menu toggles that the Preferences dialog mirrors
+struct ViewToggles {
+    word_wrap: gtk::CheckButton,
+    show_line_numbers: gtk::CheckButton,
+    autosave: gtk::CheckButton,
+    autosave_interval: gtk::SpinButton,
+}
+
+// Change the settings, store them and show the result in `text_view`
+fn change_font_settings(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, change: impl FnOnce(&mut Settings)) {
+    if let Ok(mut state) = state.try_borrow_mut() {
+        change(&mut state.settings);
+        if let Err(e) = state.settings.save() {
+            warn!("Failed to save settings: {}", e);
+        }
+        apply_zoom(text_view, &state.settings, state.zoom_level);
+    }
+}
+
+// Change the settings, store them and show the wrap column they give
+fn change_wrap_settings(state: &Rc<RefCell<EditorState>>, change: impl FnOnce(&mut Settings)) {
+    if let Ok(mut state) = state.try_borrow_mut() {
+        change(&mut state.settings);
+        if let Err(e) = state.settings.save() {
+            warn!("Failed to save settings: {}", e);
+        }
+        update_wrap_columns(&state.settings, state.zoom_level);
+    }
+}
+
+// Switch for one of the whitespace settings, stored by `set`
+fn whitespace_switch(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, active: bool, set: fn(&mut Settings, bool)) -> gtk::Switch {
+    let switch = gtk::Switch::new();
+    switch.set_active(active);
+    let text_view = text_view.clone();
+    let state = state.clone();
+    switch.connect_active_notify(move |switch| {
+        if let Ok(mut state) = state.try_borrow_mut() {
+            set(&mut state.settings, switch.is_active());
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+        highlight_trailing_whitespace(&text_view.buffer(), &state);
+    });
+    switch
+}
+
+// Preferences -> Export: write the whole configuration to a file of the user's choosing
+fn export_settings_bundle(window: &gtk::ApplicationWindow) {
+    let dialog = gtk::FileChooserNative::builder()
+        .title("Export Settings")
+        .action(gtk::FileChooserAction::Save)
+        .accept_label("Export")
+        .cancel_label("Cancel")
+        .transient_for(window)
+        .modal(true)
+        .build();
+    dialog.set_current_name("rustedit-settings.json");
+
+    let window = window.clone();
+    dialog.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                match SettingsBundle::export(&path) {
+                    Ok(count) => info!("Exported {} configuration files to {}", count, path.display()),
+                    Err(e) => show_file_error(&window, &format!("Could not export settings to {}", path.display()), &e, None, None, None),
+                }
+            }
+        }
+        dialog.destroy();
+    });
+    dialog.show();
+}
+
+// Preferences -> Import: take over a configuration exported with the above, then reload
+// what can be reloaded in place
+fn import_settings_bundle(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let dialog = gtk::FileChooserNative::builder()
+        .title("Import Settings")
+        .action(gtk::FileChooserAction::Open)
+        .accept_label("Import")
+        .cancel_label("Cancel")
+        .transient_for(window)
+        .modal(true)
+        .build();
+    let filter = gtk::FileFilter::new();
+    filter.set_name(Some("Settings bundles"));
+    filter.add_pattern("*.json");
+    dialog.add_filter(&filter);
+
+    let window = window.clone();
+    let text_view = text_view.clone();
+    let state = state.clone();
+    dialog.connect_response(move |dialog, response| {
+        let path = dialog.file().and_then(|file| file.path());
+        dialog.destroy();
+        let Some(path) = path.filter(|_| response == gtk::ResponseType::Accept) else {
+            return;
+        };
+        if let Err(e) = SettingsBundle::read(&path).and_then(|bundle| bundle.install()) {
+            show_file_error(&window, &format!("Could not import settings from {}", path.display()), &e, None, None, None);
+            return;
+        }
+
+        if let Ok(mut state) = state.try_borrow_mut() {
+            state.settings = Settings::load();
+            apply_zoom(&text_view, &state.settings, state.zoom_level);
+        }
+        // The theme files may have changed even where the name did not
+        CURRENT_THEME_NAME.with(|current| current.borrow_mut().clear());
+        apply_language_theme(&state);
+        KEYMAP.with(|keymap| *keymap.borrow_mut() = Keymap::load());
+        refresh_shortcut_hints();
+
+        let message = gtk::MessageDialog::new(
+            Some(&window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Info,
+            gtk::ButtonsType::Close,
+            "Settings imported",
+        );
+        message.set_secondary_text(Some("Abbreviations and dictionaries from the import are used after restarting the editor."));
+        message.connect_response(|message, _| message.destroy());
+        message.show();
+    });
+    dialog.show();
+}
+
+// Dialog for the settings in settings.toml; every change applies right away
+fn show_preferences(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, view_toggles: &ViewToggles) {
+    let Some(settings) = state.try_borrow().ok().map(|state| state.settings.clone()) else {
+        return;
+    };
+
+    let dialog = gtk::Dialog::with_buttons(
+        Some("Preferences"),
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &[("Close", gtk::ResponseType::Close)],
+    );
+    dialog.set_default_width(400);
+
+    let content_area = dialog.content_area();
+    content_area.set_margin_top(10);
+    content_area.set_margin_bottom(10);
+    content_area.set_margin_start(10);
+    content_area.set_margin_end(10);
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(20);
+    let mut row = 0;
+    let mut add_row = |label: &str, widget: &gtk::Widget| {
+        let label = gtk::Label::new(Some(label));
+        label.set_halign(gtk::Align::Start);
+        label.set_hexpand(true);
+        widget.set_halign(gtk::Align::End);
+        grid.attach(&label, 0, row, 1, 1);
+        grid.attach(widget, 1, row, 1, 1);
+        row += 1;
+    };
+
+    // Only the family is picked here, since the size has its own row
+    let font_button = gtk::FontButton::with_font(&settings.font_family);
+    font_button.set_level(gtk::FontChooserLevel::FAMILY);
+    font_button.set_use_size(false);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    font_button.connect_font_set(move |button| {
+        let Some(family) = button.font_desc().and_then(|font| font.family()) else {
+            return;
+        };
+        change_font_settings(&text_view_ref, &state_ref, |settings| settings.font_family = family.to_string());
+    });
+    add_row("Font", font_button.upcast_ref());
+
+    let font_size = gtk::SpinButton::with_range(6.0, 72.0, 1.0);
+    font_size.set_value(settings.font_size as f64);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    font_size.connect_value_changed(move |spin| {
+        change_font_settings(&text_view_ref, &state_ref, |settings| settings.font_size = spin.value_as_int() as u32);
+    });
+    add_row("Font size (pixels)", font_size.upcast_ref());
+
+    let tab_width = gtk::SpinButton::with_range(1.0, 16.0, 1.0);
+    tab_width.set_value(settings.tab_width as f64);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    tab_width.connect_value_changed(move |spin| {
+        change_font_settings(&text_view_ref, &state_ref, |settings| settings.tab_width = spin.value_as_int() as u32);
+    });
+    add_row("Tab width", tab_width.upcast_ref());
+
+    let theme_names = theme::Theme::names();
+    let theme_selector = gtk::DropDown::from_strings(&theme_names.iter().map(String::as_str).collect::<Vec<_>>());
+    if let Some(index) = theme_names.iter().position(|name| *name == settings.theme) {
+        theme_selector.set_selected(index as u32);
+    }
+    let state_ref = state.clone();
+    theme_selector.connect_selected_notify(move |selector| {
+        let Some(name) = theme_names.get(selector.selected() as usize) else {
+            return;
+        };
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.theme = name.to_string();
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+        apply_language_theme(&state_ref);
+    });
+    add_row("Theme", theme_selector.upcast_ref());
+
+    // Override for the shown tab's language, kept in its `[languages]` entry
+    let language = state.try_borrow().ok()
+        .and_then(|state| state.language())
+        .map_or(language::PLAIN_TEXT, |language| language.name);
+    let mut language_theme_choices = vec!["Same as Theme".to_string()];
+    language_theme_choices.extend(theme::Theme::names());
+    let language_theme_selector = gtk::DropDown::from_strings(&language_theme_choices.iter().map(String::as_str).collect::<Vec<_>>());
+    let language_theme = settings.languages.get(language).and_then(|settings| settings.theme.as_deref());
+    if let Some(index) = language_theme.and_then(|theme| language_theme_choices.iter().position(|name| name == theme)) {
+        language_theme_selector.set_selected(index as u32);
+    }
+    let state_ref = state.clone();
+    language_theme_selector.connect_selected_notify(move |selector| {
+        let theme = match selector.selected() {
+            0 => None,
+            index => language_theme_choices.get(index as usize).cloned(),
+        };
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.languages.entry(language.to_string()).or_default().theme = theme;
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+        apply_language_theme(&state_ref);
+    });
+    add_row(&format!("Theme for {}", language), language_theme_selector.upcast_ref());
+
+    for (label, toggle) in [
+        ("Word wrap", &view_toggles.word_wrap),
+        ("Show line numbers", &view_toggles.show_line_numbers),
+        ("Autosave recovery copies", &view_toggles.autosave),
+    ] {
+        let switch = gtk::Switch::new();
+        toggle.bind_property("active", &switch, "active").bidirectional().sync_create().build();
+        add_row(label, switch.upcast_ref());
+    }
+
+    let autosave_interval = gtk::SpinButton::with_range(MIN_AUTOSAVE_INTERVAL_SECS as f64, 600.0, 5.0);
+    view_toggles.autosave_interval.bind_property("value", &autosave_interval, "value").bidirectional().sync_create().build();
+    view_toggles.autosave.bind_property("active", &autosave_interval, "sensitive").sync_create().build();
+    add_row("Autosave every (seconds)", autosave_interval.upcast_ref());
+
+    let wrap_column = gtk::SpinButton::with_range(0.0, 400.0, 1.0);
+    wrap_column.set_value(settings.wrap_column as f64);
+    wrap_column.set_tooltip_text(Some("0 wraps at the edge of the window"));
+    let state_ref = state.clone();
+    wrap_column.connect_value_changed(move |spin| {
+        change_wrap_settings(&state_ref, |settings| settings.wrap_column = spin.value_as_int() as u32);
+    });
+    add_row("Wrap at column", wrap_column.upcast_ref());
+
+    let right_margin = gtk::Switch::new();
+    right_margin.set_active(settings.show_right_margin);
+    let state_ref = state.clone();
+    right_margin.connect_active_notify(move |switch| {
+        change_wrap_settings(&state_ref, |settings| settings.show_right_margin = switch.is_active());
+    });
+    add_row("Show a line at the wrap column", right_margin.upcast_ref());
+
+    let smart_typography = gtk::Switch::new();
+    smart_typography.set_active(settings.smart_typography);
+    let state_ref = state.clone();
+    smart_typography.connect_active_notify(move |switch| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.smart_typography = switch.is_active();
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+    });
+    add_row("Smart quotes and capitals in prose", smart_typography.upcast_ref());
+
+    let code_completion = gtk::Switch::new();
+    code_completion.set_active(settings.code_completion);
+    let state_ref = state.clone();
+    code_completion.connect_active_notify(move |switch| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.code_completion = switch.is_active();
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+    });
+    add_row("Suggest completions while typing code", code_completion.upcast_ref());
+
+    let prose_completion = gtk::Switch::new();
+    prose_completion.set_active(settings.prose_completion);
+    let state_ref = state.clone();
+    prose_completion.connect_active_notify(move |switch| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.prose_completion = switch.is_active();
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+    });
+    add_row("Suggest words in prose", prose_completion.upcast_ref());
+
+    let external_clipboard = gtk::Switch::new();
+    external_clipboard.set_active(settings.clipboard_history_external);
+    let state_ref = state.clone();
+    external_clipboard.connect_active_notify(move |switch| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.clipboard_history_external = switch.is_active();
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+    });
+    add_row("Keep copies from other apps in paste history", external_clipboard.upcast_ref());
+
+    let dictionary_names = Dictionary::names();
+    let dictionary_selector = gtk::DropDown::from_strings(&dictionary_names.iter().map(String::as_str).collect::<Vec<_>>());
+    if let Some(index) = dictionary_names.iter().position(|name| *name == settings.dictionary) {
+        dictionary_selector.set_selected(index as u32);
+    }
+    prose_completion.bind_property("active", &dictionary_selector, "sensitive").sync_create().build();
+    let state_ref = state.clone();
+    dictionary_selector.connect_selected_notify(move |selector| {
+        let Some(name) = dictionary_names.get(selector.selected() as usize) else {
+            return;
+        };
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.dictionary = name.to_string();
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+    });
+    add_row("Dictionary", dictionary_selector.upcast_ref());
+
+    let show_trailing_whitespace = whitespace_switch(text_view, state, settings.show_trailing_whitespace, |settings, active| {
+        settings.show_trailing_whitespace = active;
+    });
+    add_row("Show trailing whitespace", show_trailing_whitespace.upcast_ref());
+    // Languages can override these two in their `[languages]` entry
+    let trim_trailing_whitespace = whitespace_switch(text_view, state, settings.trim_trailing_whitespace, |settings, active| {
+        settings.trim_trailing_whitespace = active;
+    });
+    add_row("Trim trailing whitespace on save", trim_trailing_whitespace.upcast_ref());
+    let ensure_final_newline = whitespace_switch(text_view, state, settings.ensure_final_newline, |settings, active| {
+        settings.ensure_final_newline = active;
+    });
+    add_row("End files with a line break on save", ensure_final_newline.upcast_ref());
+
+    let shortcuts_button = gtk::Button::with_label("Keyboard Shortcuts");
+    let window_ref = window.clone();
+    shortcuts_button.connect_clicked(move |_| show_shortcuts_editor(&window_ref));
+    add_row("Key bindings", shortcuts_button.upcast_ref());
+
+    // Everything in the config directory, carried to another machine in one file
+    let bundle_buttons = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let export_button = gtk::Button::with_label("Export…");
+    let window_ref = window.clone();
+    export_button.connect_clicked(move |_| export_settings_bundle(&window_ref));
+    bundle_buttons.append(&export_button);
+    let import_button = gtk::Button::with_label("Import…");
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    let dialog_ref = dialog.clone();
+    import_button.connect_clicked(move |_| {
+        // Reopening shows the imported values
+        dialog_ref.destroy();
+        import_settings_bundle(&window_ref, &text_view_ref, &state_ref);
+    });
+    bundle_buttons.append(&import_button);
+    add_row("Settings, key bindings and themes", bundle_buttons.upcast_ref());
+
+    content_area.append(&grid);
+    dialog.connect_response(|dialog, _| dialog.destroy());
+    dialog.show();
+}
+
+fn create_menu_bar(window: &gtk::ApplicationWindow, buffer: &gtk::TextBuffer, editor_state: Rc<RefCell<EditorState>>, tabs: &Rc<RefCell<TabManager>>, status_label: gtk::Label, text_view: &gtk::TextView) -> (gtk::Box, gtk::Button, gtk::Button, gtk::Button, gtk::Button, gtk::Button, gtk::Box, gtk::Button, gtk::Button, gtk::CheckButton) {
+    // Create the main vertical container for menu and tabs
+    let main_container = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    main_container.set_css_classes(&["main-menu-container"]);
+    
+    // Create the menu bar (horizontal)
+    let menu_bar = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    menu_bar.set_css_classes(&["menu-bar"]);
+    
+    // Create a more modern File button with icon
+    let file_menu_button = gtk::MenuButton::new();
+    file_menu_button.set_label("File");
+    file_menu_button.set_css_classes(&["menu-button"]);
+    file_menu_button.set_has_frame(false);
+    file_menu_button.set_focus_on_click(false);
+    menu_bar.append(&file_menu_button);
+    
+    // Create File popup menu
+    let menu = gtk::PopoverMenu::from_model(None::<&gtk::gio::MenuModel>);
+    let menu_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    menu_box.set_margin_top(2);
+    menu_box.set_margin_bottom(2);
+    menu_box.set_margin_start(2);
+    menu_box.set_margin_end(2);
+    
+    // New file button with keyboard shortcut hint
+    let new_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let new_btn_label = gtk::Label::new(Some("New file"));
+    new_btn_label.set_halign(gtk::Align::Start);
+    new_btn_label.set_hexpand(true);
+    let new_shortcut = shortcut_hint(Action::NewTab);
+    
+    new_button.append(&new_btn_label);
+    new_button.append(&new_shortcut);
+    
+    let new_button_wrapper = gtk::Button::new();
+    new_button_wrapper.set_child(Some(&new_button));
+    new_button_wrapper.set_has_frame(false);
+    new_button_wrapper.set_hexpand(true);
+    
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    let status_label_ref = status_label.clone();
+    new_button_wrapper.connect_clicked(move |_| {
+        text_view_ref.buffer().set_text("");
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.text_buffer.set_text("");
+            state.current_file = None;
+            state.encoding = encoding_rs::UTF_8;
+            state.write_bom = false;
+            state.line_ending = LineEnding::Lf;
+            state.mark_saved();
+            state.update_tab_name();
+            status_label_ref.set_text("Line: 1 Col: 1");
+        }
+    });
+    menu_box.append(&new_button_wrapper);
+    
+    // Open file button with keyboard shortcut hint
+    let open_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let open_btn_label = gtk::Label::new(Some("Open file..."));
+    open_btn_label.set_halign(gtk::Align::Start);
+    open_btn_label.set_hexpand(true);
+    let open_shortcut = shortcut_hint(Action::Open);
+    
+    open_button.append(&open_btn_label);
+    open_button.append(&open_shortcut);
+    
+    let open_button_wrapper = gtk::Button::new();
+    open_button_wrapper.set_child(Some(&open_button));
+    open_button_wrapper.set_has_frame(false);
+    open_button_wrapper.set_hexpand(true);
+    
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    let status_label_ref = status_label.clone();
+    open_button_wrapper.connect_clicked(move |_| {
+        let dialog = gtk::FileChooserNative::builder()
+            .title("Open File")
+            .action(gtk::FileChooserAction::Open)
+            .accept_label("Open")
+            .cancel_label("Cancel")
+            .transient_for(&window_ref)
+            .modal(true)
+            .build();
+            
+        // Start in the most relevant directory instead of $HOME
+        if let Ok(state) = state_ref.try_borrow() {
+            install_file_filters(&dialog, &state);
+            set_dialog_folder(&dialog, &state);
+        }
+        
+        let window = window_ref.clone();
+        let buffer = text_view_ref.buffer();
+        let text_view = text_view_ref.clone();
+        let state = state_ref.clone();
+        let status_label = status_label_ref.clone();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk::ResponseType::Accept {
+                remember_dialog_filter(dialog, &state);
+                if let Some(file) = dialog.file() {
+                    if let Some(path) = file.path() {
+                        open_path_in_view(&window, &buffer, &text_view, &state, &status_label, path);
+                    }
+                }
+            }
+            dialog.destroy();
+        });
+        
+        dialog.show();
+    });
+    menu_box.append(&open_button_wrapper);
+    
+    // Quick open menu item, finding a file of the project by name
+    let quick_open_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let quick_open_label = gtk::Label::new(Some("Quick open…"));
+    quick_open_label.set_halign(gtk::Align::Start);
+    quick_open_label.set_hexpand(true);
+    quick_open_button.append(&quick_open_label);
+    quick_open_button.append(&shortcut_hint(Action::QuickOpen));
+    
+    let quick_open_wrapper = gtk::Button::new();
+    quick_open_wrapper.set_child(Some(&quick_open_button));
+    quick_open_wrapper.set_has_frame(false);
+    quick_open_wrapper.set_hexpand(true);
+    
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    quick_open_wrapper.connect_clicked(move |_| {
+        show_quick_open(&text_view_ref, &state_ref);
+    });
+    menu_box.append(&quick_open_wrapper);
+    
+    // Open recent menu item
+    let open_recent_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let recent_btn_label = gtk::Label::new(Some("Open recent file"));
+    recent_btn_label.set_halign(gtk::Align::Start);
+    recent_btn_label.set_hexpand(true);
+    
+    open_recent_button.append(&recent_btn_label);
+    
+    let open_recent_wrapper = gtk::Button::new();
+    open_recent_wrapper.set_child(Some(&open_recent_button));
+    open_recent_wrapper.set_has_frame(false);
+    open_recent_wrapper.set_hexpand(true);
+    
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    let status_label_ref = status_label.clone();
+    
+    open_recent_wrapper.connect_clicked(move |button| {
+        // Create a popover for recent files
+        let recent_popover = gtk::Popover::new();
+        recent_popover.set_parent(button);
+        recent_popover.connect_closed(|popover| popover.unparent());
+        
+        let recent_box = gtk::Box::new(gtk::Orientation::Vertical, 4);
+        recent_box.set_margin_top(4);
+        recent_box.set_margin_bottom(4);
+        recent_box.set_margin_start(4);
+        recent_box.set_margin_end(4);
+        
+        let recent_files = state_ref.try_borrow()
+            .map(|state| state.persistent.recent_files())
+            .unwrap_or_default();
+        
+        if recent_files.is_empty() {
+            let no_recent_label = gtk::Label::new(Some("No recent files"));
+            recent_box.append(&no_recent_label);
+        } else {
+            let mut entries = Vec::new();
+            for recent in &recent_files {
+                let path = &recent.path;
+                let file_name = path.file_name()
+                    .and_then(|f| f.to_str())
+                    .unwrap_or("Unknown");
+                let last_used = glib::DateTime::from_unix_local(recent.last_used as i64)
+                    .and_then(|time| time.format("%x %H:%M"))
+                    .map(|time| format!("\nLast opened {}", time))
+                    .unwrap_or_default();
+                
+                let row = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+                let file_button = gtk::Button::with_label(file_name);
+                file_button.set_has_frame(false);
+                file_button.set_hexpand(true);
+                file_button.set_halign(gtk::Align::Start);
+                file_button.set_tooltip_text(Some(&format!("{}{}", path.to_string_lossy(), last_used)));
+                row.append(&file_button);
+                
+                let pin_button = gtk::ToggleButton::new();
+                pin_button.set_icon_name("view-pin-symbolic");
+                pin_button.set_has_frame(false);
+                pin_button.set_active(recent.pinned);
+                pin_button.set_tooltip_text(Some("Keep at the top of the list"));
+                row.append(&pin_button);
+                
+                // Only offered once the file turns out to be missing
+                let remove_button = gtk::Button::from_icon_name("list-remove-symbolic");
+                remove_button.set_has_frame(false);
+                remove_button.set_tooltip_text(Some("Remove from list"));
+                remove_button.set_visible(false);
+                row.append(&remove_button);
+                
+                let window = window_ref.clone();
+                let text_view = text_view_ref.clone();
+                let state = state_ref.clone();
+                let status_label = status_label_ref.clone();
+                let path_clone = path.clone();
+                let popover_ref = recent_popover.clone();
+                
+                file_button.connect_clicked(move |_| {
+                    popover_ref.popdown();
+                    open_path_in_view(&window, &text_view.buffer(), &text_view, &state, &status_label, path_clone.clone());
+                });
+                
+                let state = state_ref.clone();
+                let path_clone = path.clone();
+                pin_button.connect_toggled(move |_| {
+                    if let Ok(mut state) = state.try_borrow_mut() {
+                        state.persistent.toggle_pinned(&path_clone);
+                        if let Err(e) = state.persistent.save() {
+                            warn!("Failed to save editor state: {}", e);
+                        }
+                    }
+                });
+                
+                let state = state_ref.clone();
+                let path_clone = path.clone();
+                let recent_box_ref = recent_box.clone();
+                let row_ref = row.clone();
+                remove_button.connect_clicked(move |_| {
+                    if let Ok(mut state) = state.try_borrow_mut() {
+                        state.persistent.remove_recent_file(&path_clone);
+                        if let Err(e) = state.persistent.save() {
+                            warn!("Failed to save editor state: {}", e);
+                        }
+                    }
+                    recent_box_ref.remove(&row_ref);
+                });
+                
+                recent_box.append(&row);
+                entries.push((file_button, remove_button, path.clone()));
+            }
+            
+            recent_box.append(&gtk::Separator::new(gtk::Orientation::Horizontal));
+            let clear_button = gtk::Button::with_label("Clear Recent Files");
+            clear_button.set_has_frame(false);
+            clear_button.set_halign(gtk::Align::Start);
+            clear_button.set_tooltip_text(Some("Forget the files that are not pinned"));
+            let state = state_ref.clone();
+            let popover_ref = recent_popover.clone();
+            clear_button.connect_clicked(move |_| {
+                popover_ref.popdown();
+                if let Ok(mut state) = state.try_borrow_mut() {
+                    state.persistent.clear_recent_files();
+                    if let Err(e) = state.persistent.save() {
+                        warn!("Failed to save editor state: {}", e);
+                    }
+                }
+            });
+            recent_box.append(&clear_button);
+            
+            // Checking for the files can block on slow or unmounted drives, so it happens off
+            // the UI thread and the list is updated when the answers arrive
+            let paths: Vec<PathBuf> = recent_files.into_iter().map(|recent| recent.path).collect();
+            glib::spawn_future_local(async move {
+                let Ok(exists) = gio::spawn_blocking(move || {
+                    paths.iter().map(|path| path.is_file()).collect::<Vec<bool>>()
+                }).await else {
+                    return;
+                };
+                for ((file_button, remove_button, path), exists) in entries.into_iter().zip(exists) {
+                    if !exists {
+                        file_button.set_sensitive(false);
+                        file_button.set_tooltip_text(Some(&format!("{} (file not found)", path.to_string_lossy())));
+                        remove_button.set_visible(true);
+                    }
+                }
+            });
+        }
+        
+        recent_popover.set_child(Some(&recent_box));
+        recent_popover.popup();
+    });
+    menu_box.append(&open_recent_wrapper);
+    
+    // Favorites menu item, listing the starred files
+    let favorites_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let favorites_btn_label = gtk::Label::new(Some("Favorites"));
+    favorites_btn_label.set_halign(gtk::Align::Start);
+    favorites_btn_label.set_hexpand(true);
+    
+    favorites_button.append(&favorites_btn_label);
+    
+    let favorites_wrapper = gtk::Button::new();
+    favorites_wrapper.set_child(Some(&favorites_button));
+    favorites_wrapper.set_has_frame(false);
+    favorites_wrapper.set_hexpand(true);
+    
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    let status_label_ref = status_label.clone();
+    
+    favorites_wrapper.connect_clicked(move |button| {
+        let favorites_popover = gtk::Popover::new();
+        favorites_popover.set_parent(button);
+        favorites_popover.connect_closed(|popover| popover.unparent());
+        
+        let favorites_box = gtk::Box::new(gtk::Orientation::Vertical, 4);
+        favorites_box.set_margin_top(4);
+        favorites_box.set_margin_bottom(4);
+        favorites_box.set_margin_start(4);
+        favorites_box.set_margin_end(4);
+        
+        let favorites = state_ref.try_borrow()
+            .map(|state| state.persistent.favorites.clone())
+            .unwrap_or_default();
+        
+        if favorites.is_empty() {
+            favorites_box.append(&gtk::Label::new(Some("No favorites yet")));
+        }
+        for path in favorites {
+            let file_name = path.file_name()
+                .and_then(|f| f.to_str())
+                .unwrap_or("Unknown");
+            
+            let file_button = gtk::Button::with_label(file_name);
+            file_button.set_has_frame(false);
+            file_button.set_hexpand(true);
+            file_button.set_halign(gtk::Align::Start);
+            file_button.set_tooltip_text(Some(&path.to_string_lossy()));
+            
+            let window = window_ref.clone();
+            let text_view = text_view_ref.clone();
+            let state = state_ref.clone();
+            let status_label = status_label_ref.clone();
+            let popover_ref = favorites_popover.clone();
+            
+            file_button.connect_clicked(move |_| {
+                popover_ref.popdown();
+                open_path_in_view(&window, &text_view.buffer(), &text_view, &state, &status_label, path.clone());
+            });
+            
+            favorites_box.append(&file_button);
+        }
+        
+        favorites_popover.set_child(Some(&favorites_box));
+        favorites_popover.popup();
+    });
+    menu_box.append(&favorites_wrapper);
+    
+    // Compare two files; connected once the tab bar exists
+    let compare_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let compare_btn_label = gtk::Label::new(Some("Compare files..."));
+    compare_btn_label.set_halign(gtk::Align::Start);
+    compare_btn_label.set_hexpand(true);
+    
+    compare_button.append(&compare_btn_label);
+    
+    let compare_button_wrapper = gtk::Button::new();
+    compare_button_wrapper.set_child(Some(&compare_button));
+    compare_button_wrapper.set_has_frame(false);
+    compare_button_wrapper.set_hexpand(true);
+    menu_box.append(&compare_button_wrapper);
+    
+    let compare_saved_label = gtk::Label::new(Some("Compare with saved"));
+    compare_saved_label.set_halign(gtk::Align::Start);
+    compare_saved_label.set_hexpand(true);
+    
+    let compare_saved_wrapper = gtk::Button::new();
+    compare_saved_wrapper.set_child(Some(&compare_saved_label));
+    compare_saved_wrapper.set_has_frame(false);
+    compare_saved_wrapper.set_hexpand(true);
+    menu_box.append(&compare_saved_wrapper);
+    
+    // Session items; connected once the tab bar exists
+    let save_session_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let save_session_label = gtk::Label::new(Some("Save session..."));
+    save_session_label.set_halign(gtk::Align::Start);
+    save_session_label.set_hexpand(true);
+    save_session_button.append(&save_session_label);
+    
+    let save_session_wrapper = gtk::Button::new();
+    save_session_wrapper.set_child(Some(&save_session_button));
+    save_session_wrapper.set_has_frame(false);
+    save_session_wrapper.set_hexpand(true);
+    menu_box.append(&save_session_wrapper);
+    
+    let recent_sessions_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let recent_sessions_label = gtk::Label::new(Some("Recent sessions"));
+    recent_sessions_label.set_halign(gtk::Align::Start);
+    recent_sessions_label.set_hexpand(true);
+    recent_sessions_button.append(&recent_sessions_label);
+    
+    let recent_sessions_wrapper = gtk::Button::new();
+    recent_sessions_wrapper.set_child(Some(&recent_sessions_button));
+    recent_sessions_wrapper.set_has_frame(false);
+    recent_sessions_wrapper.set_hexpand(true);
+    menu_box.append(&recent_sessions_wrapper);
+    
+    // Session prompt toggle, remembered across launches
+    let prompt_for_session_button = gtk::CheckButton::with_label("Ask for Session at Startup");
+    if let Ok(state) = editor_state.try_borrow() {
+        prompt_for_session_button.set_active(state.persistent.prompt_for_session);
+    }
+    let state_ref = editor_state.clone();
+    prompt_for_session_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.prompt_for_session = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+    });
+    menu_box.append(&prompt_for_session_button);
+    
+    // Add separator
+    let separator1 = gtk::Separator::new(gtk::Orientation::Horizontal);
+    separator1.set_margin_top(2);
+    separator1.set_margin_bottom(2);
+    menu_box.append(&separator1);
+    
+    // Save file button with keyboard shortcut hint
+    let save_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let save_btn_label = gtk::Label::new(Some("Save"));
+    save_btn_label.set_halign(gtk::Align::Start);
+    save_btn_label.set_hexpand(true);
+    let save_shortcut = shortcut_hint(Action::Save);
+    
+    save_button.append(&save_btn_label);
+    save_button.append(&save_shortcut);
+    
+    let save_button_wrapper = gtk::Button::new();
+    save_button_wrapper.set_child(Some(&save_button));
+    save_button_wrapper.set_has_frame(false);
+    save_button_wrapper.set_hexpand(true);
+    
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    save_button_wrapper.connect_clicked(move |_| {
+        save_shown_tab(&window_ref, &text_view_ref, &state_ref, || {});
+    });
+    menu_box.append(&save_button_wrapper);
+    
+    // Save As button with keyboard shortcut hint
+    let save_as_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let save_as_btn_label = gtk::Label::new(Some("Save as..."));
+    save_as_btn_label.set_halign(gtk::Align::Start);
+    save_as_btn_label.set_hexpand(true);
+    let save_as_shortcut = shortcut_hint(Action::SaveAs);
+    
+    save_as_button.append(&save_as_btn_label);
+    save_as_button.append(&save_as_shortcut);
+    
+    let save_as_button_wrapper = gtk::Button::new();
+    save_as_button_wrapper.set_child(Some(&save_as_button));
+    save_as_button_wrapper.set_has_frame(false);
+    save_as_button_wrapper.set_hexpand(true);
+    
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    save_as_button_wrapper.connect_clicked(move |_| {
+        save_shown_tab_as(&window_ref, &text_view_ref, &state_ref, || {});
+    });
+    menu_box.append(&save_as_button_wrapper);
+    
+    // Revert button with keyboard shortcut hint
+    let revert_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let revert_btn_label = gtk::Label::new(Some("Revert"));
+    revert_btn_label.set_halign(gtk::Align::Start);
+    revert_btn_label.set_hexpand(true);
+    revert_button.append(&revert_btn_label);
+    revert_button.append(&shortcut_hint(Action::Revert));
+    
+    let revert_button_wrapper = gtk::Button::new();
+    revert_button_wrapper.set_child(Some(&revert_button));
+    revert_button_wrapper.set_has_frame(false);
+    revert_button_wrapper.set_hexpand(true);
+    
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    revert_button_wrapper.connect_clicked(move |_| {
+        revert_shown_tab(&window_ref, &text_view_ref, &state_ref);
+    });
+    menu_box.append(&revert_button_wrapper);
+    
+    // Print and Print Preview buttons
+    let print_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let print_btn_label = gtk::Label::new(Some("Print…"));
+    print_btn_label.set_halign(gtk::Align::Start);
+    print_btn_label.set_hexpand(true);
+    print_button.append(&print_btn_label);
+    print_button.append(&shortcut_hint(Action::Print));
+    
+    let print_button_wrapper = gtk::Button::new();
+    print_button_wrapper.set_child(Some(&print_button));
+    print_button_wrapper.set_has_frame(false);
+    print_button_wrapper.set_hexpand(true);
+    
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    print_button_wrapper.connect_clicked(move |_| {
+        print_shown_tab(&window_ref, &text_view_ref, &state_ref, false);
+    });
+    menu_box.append(&print_button_wrapper);
+    
+    let print_preview_label = gtk::Label::new(Some("Print Preview"));
+    print_preview_label.set_halign(gtk::Align::Start);
+    let print_preview_button = gtk::Button::new();
+    print_preview_button.set_child(Some(&print_preview_label));
+    print_preview_button.set_has_frame(false);
+    print_preview_button.set_hexpand(true);
+    
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    print_preview_button.connect_clicked(move |_| {
+        print_shown_tab(&window_ref, &text_view_ref, &state_ref, true);
+    });
+    menu_box.append(&print_preview_button);
+    
+    // Add separator
+    let separator2 = gtk::Separator::new(gtk::Orientation::Horizontal);
+    separator2.set_margin_top(2);
+    separator2.set_margin_bottom(2);
+    menu_box.append(&separator2);
+    
+    // Close file button with keyboard shortcut hint
+    let close_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let close_btn_label = gtk::Label::new(Some("Close file"));
+    close_btn_label.set_halign(gtk::Align::Start);
+    close_btn_label.set_hexpand(true);
+    let close_shortcut = shortcut_hint(Action::CloseTab);
+    
+    close_button.append(&close_btn_label);
+    close_button.append(&close_shortcut);
+    
+    let close_button_wrapper = gtk::Button::new();
+    close_button_wrapper.set_child(Some(&close_button));
+    close_button_wrapper.set_has_frame(false);
+    close_button_wrapper.set_hexpand(true);
+    
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    close_button_wrapper.connect_clicked(move |_| {
+        let text_view = text_view_ref.clone();
+        let state = state_ref.clone();
+        confirm_close_tab(&text_view_ref, &state_ref, move || clear_shown_tab(&text_view, &state));
+    });
+    menu_box.append(&close_button_wrapper);
+    
+    // Add separator before quit
+    let separator3 = gtk::Separator::new(gtk::Orientation::Horizontal);
+    separator3.set_margin_top(2);
+    separator3.set_margin_bottom(2);
+    menu_box.append(&separator3);
+    
+    // Quit button with keyboard shortcut hint
+    let quit_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let quit_btn_label = gtk::Label::new(Some("Quit"));
+    quit_btn_label.set_halign(gtk::Align::Start);
+    quit_btn_label.set_hexpand(true);
+    let quit_shortcut = shortcut_hint(Action::Quit);
+    
+    quit_button.append(&quit_btn_label);
+    quit_button.append(&quit_shortcut);
+    
+    let quit_button_wrapper = gtk::Button::new();
+    quit_button_wrapper.set_child(Some(&quit_button));
+    quit_button_wrapper.set_has_frame(false);
+    quit_button_wrapper.set_hexpand(true);
+    
+    let app_window = window.clone();
+    quit_button_wrapper.connect_clicked(move |_| {
+        app_window.close();
+    });
+    menu_box.append(&quit_button_wrapper);
+    
+    menu.set_child(Some(&menu_box));
+    file_menu_button.set_popover(Some(&menu));
+    
+    // Add Edit menu button next to File
+    let edit_menu_button = gtk::MenuButton::new();
+    edit_menu_button.set_label("Edit");
+    edit_menu_button.set_css_classes(&["menu-button"]);
+    edit_menu_button.set_has_frame(false);
+    edit_menu_button.set_focus_on_click(false);
+    menu_bar.append(&edit_menu_button);
+
+    // Create Edit popup menu
+    let edit_menu = gtk::PopoverMenu::from_model(None::<&gtk::gio::MenuModel>);
+    let edit_menu_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    edit_menu_box.set_margin_top(2);
+    edit_menu_box.set_margin_bottom(2);
+    edit_menu_box.set_margin_start(2);
+    edit_menu_box.set_margin_end(2);
+
+    // Undo button with keyboard shortcut hint
+    let undo_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let undo_btn_label = gtk::Label::new(Some("Undo"));
+    undo_btn_label.set_halign(gtk::Align::Start);
+    undo_btn_label.set_hexpand(true);
+    let undo_shortcut = shortcut_hint(Action::Undo);
+    
+    undo_button.append(&undo_btn_label);
+    undo_button.append(&undo_shortcut);
+    
+    let undo_button_wrapper = gtk::Button::new();
+    undo_button_wrapper.set_child(Some(&undo_button));
+    undo_button_wrapper.set_has_frame(false);
+    undo_button_wrapper.set_hexpand(true);
+    
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    undo_button_wrapper.connect_clicked(move |_| {
+        step_history(&text_view_ref, &state_ref, false);
+    });
+    edit_menu_box.append(&undo_button_wrapper);
+
+    // Redo button with keyboard shortcut hint
+    let redo_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let redo_btn_label = gtk::Label::new(Some("Redo"));
+    redo_btn_label.set_halign(gtk::Align::Start);
+    redo_btn_label.set_hexpand(true);
+    let redo_shortcut = shortcut_hint(Action::Redo);
+    
+    redo_button.append(&redo_btn_label);
+    redo_button.append(&redo_shortcut);
+    
+    let redo_button_wrapper = gtk::Button::new();
+    redo_button_wrapper.set_child(Some(&redo_button));
+    redo_button_wrapper.set_has_frame(false);
+    redo_button_wrapper.set_hexpand(true);
+    
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    redo_button_wrapper.connect_clicked(move |_| {
+        step_history(&text_view_ref, &state_ref, true);
+    });
+    edit_menu_box.append(&redo_button_wrapper);
+
+    // Line and clipboard commands, with their keyboard shortcut hints
+    for (label, action) in [
+        ("Duplicate Line", Action::DuplicateLine),
+        ("Move Lines Up", Action::MoveLinesUp),
+        ("Move Lines Down", Action::MoveLinesDown),
+        ("Select Line", Action::SelectLine),
+        ("Select Paragraph", Action::SelectParagraph),
+        ("Expand Selection", Action::ExpandSelection),
+        ("Paste from History", Action::PasteFromHistory),
+        ("Swap with Clipboard", Action::SwapWithClipboard),
+        ("Format Markdown Table", Action::FormatTable),
+        ("Toggle Read-Only", Action::ToggleReadOnly),
+    ] {
+        let item = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        let item_label = gtk::Label::new(Some(label));
+        item_label.set_halign(gtk::Align::Start);
+        item_label.set_hexpand(true);
+        item.append(&item_label);
+        item.append(&shortcut_hint(action));
+
+        let item_wrapper = gtk::Button::new();
+        item_wrapper.set_child(Some(&item));
+        item_wrapper.set_has_frame(false);
+        item_wrapper.set_hexpand(true);
+
+        let text_view_ref = text_view.clone();
+        let state_ref = editor_state.clone();
+        let edit_menu_ref = edit_menu.clone();
+        item_wrapper.connect_clicked(move |_| {
+            edit_menu_ref.popdown();
+            if action.edits_text() && !text_view_ref.is_editable() {
+                return;
+            }
+            match action {
+                Action::DuplicateLine => duplicate_lines(&text_view_ref),
+                Action::MoveLinesUp => move_lines(&text_view_ref, false),
+                Action::MoveLinesDown => move_lines(&text_view_ref, true),
+                Action::SelectLine => select_from_mirror(&text_view_ref, &state_ref, |buffer, selection| Some(buffer.line_selection(selection))),
+                Action::SelectParagraph => select_from_mirror(&text_view_ref, &state_ref, |buffer, selection| buffer.paragraph_range(selection.start)),
+                Action::ExpandSelection => select_from_mirror(&text_view_ref, &state_ref, |buffer, selection| Some(buffer.expand_selection(selection))),
+                Action::PasteFromHistory => show_clipboard_history(&text_view_ref),
+                Action::SwapWithClipboard => swap_with_clipboard(&text_view_ref),
+                Action::FormatTable => format_markdown_table(&text_view_ref),
+                _ => toggle_read_only(&text_view_ref, &state_ref),
+            }
+        });
+        edit_menu_box.append(&item_wrapper);
+    }
+
+    // Sorting and the like, in a submenu of their own
+    let line_operations = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let line_operations_label = gtk::Label::new(Some("Line Operations"));
+    line_operations_label.set_halign(gtk::Align::Start);
+    line_operations_label.set_hexpand(true);
+    line_operations.append(&line_operations_label);
+    line_operations.append(&gtk::Label::new(Some("▸")));
+
+    let line_operations_wrapper = gtk::Button::new();
+    line_operations_wrapper.set_child(Some(&line_operations));
+    line_operations_wrapper.set_has_frame(false);
+    line_operations_wrapper.set_hexpand(true);
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    let edit_menu_ref = edit_menu.clone();
+    // Sort options stay as they were left for the rest of the session
+    let sort_options = Rc::new(Cell::new((false, false)));
+    line_operations_wrapper.connect_clicked(move |button| {
+        show_line_operations(button, &edit_menu_ref, &text_view_ref, &state_ref, &sort_options);
+    });
+    edit_menu_box.append(&line_operations_wrapper);
+
+    // Add separator
+    let separator_edit = gtk::Separator::new(gtk::Orientation::Horizontal);
+    separator_edit.set_margin_top(2);
+    separator_edit.set_margin_bottom(2);
+    edit_menu_box.append(&separator_edit);
+
+    // Find button
+    let find_button = gtk::Button::with_label("Find...");
+    find_button.set_has_frame(false);
+    find_button.set_hexpand(true);
+    find_button.set_halign(gtk::Align::Start);
+    edit_menu_box.append(&find_button);
+
+    // Replace button
+    let replace_button = gtk::Button::with_label("Replace...");
+    replace_button.set_has_frame(false);
+    replace_button.set_hexpand(true);
+    replace_button.set_halign(gtk::Align::Start);
+    edit_menu_box.append(&replace_button);
+
+    // Open the selected URL or file, or search the workspace for the selected text
+    let open_selection_button = gtk::Button::with_label("Open Selection");
+    open_selection_button.set_has_frame(false);
+    open_selection_button.set_hexpand(true);
+    open_selection_button.set_halign(gtk::Align::Start);
+    edit_menu_box.append(&open_selection_button);
+
+    // Copy the selected text into a new Untitled tab
+    let selection_to_tab_button = gtk::Button::with_label("Open Selection in New Tab");
+    selection_to_tab_button.set_has_frame(false);
+    selection_to_tab_button.set_hexpand(true);
+    selection_to_tab_button.set_halign(gtk::Align::Start);
+    edit_menu_box.append(&selection_to_tab_button);
+
+    // Compile and run the selected Rust code, showing its output in a panel
+    let run_selection_item = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let run_selection_label = gtk::Label::new(Some("Run Selection as Rust"));
+    run_selection_label.set_halign(gtk::Align::Start);
+    run_selection_label.set_hexpand(true);
+    run_selection_item.append(&run_selection_label);
+    run_selection_item.append(&shortcut_hint(Action::RunSelection));
+    let run_selection_button = gtk::Button::new();
+    run_selection_button.set_child(Some(&run_selection_item));
+    run_selection_button.set_has_frame(false);
+    run_selection_button.set_hexpand(true);
+    edit_menu_box.append(&run_selection_button);
+
+    // Line ending conversion, also offered from the status bar
+    let separator_line_endings = gtk::Separator::new(gtk::Orientation::Horizontal);
+    separator_line_endings.set_margin_top(2);
+    separator_line_endings.set_margin_bottom(2);
+    edit_menu_box.append(&separator_line_endings);
+
+    for line_ending in LineEnding::ALL {
+        let convert_button = gtk::Button::with_label(&format!("Convert to {}", line_ending.description()));
+        convert_button.set_has_frame(false);
+        convert_button.set_hexpand(true);
+        convert_button.set_halign(gtk::Align::Start);
+
+        let text_view_ref = text_view.clone();
+        let state_ref = editor_state.clone();
+        let status_label_ref = status_label.clone();
+        let edit_menu_ref = edit_menu.clone();
+        convert_button.connect_clicked(move |_| {
+            edit_menu_ref.popdown();
+            convert_line_endings(&text_view_ref, &state_ref, &status_label_ref, line_ending);
+        });
+        edit_menu_box.append(&convert_button);
+    }
+
+    edit_menu.set_child(Some(&edit_menu_box));
+    edit_menu_button.set_popover(Some(&edit_menu));
+    
+    // Add View menu button after Edit
+    let view_menu_button = gtk::MenuButton::new();
+    view_menu_button.set_label("View");
+    view_menu_button.set_css_classes(&["menu-button"]);
+    view_menu_button.set_has_frame(false);
+    view_menu_button.set_focus_on_click(false);
+    menu_bar.append(&view_menu_button);
+
+    // Create View popup menu
+    let view_menu = gtk::PopoverMenu::from_model(None::<&gtk::gio::MenuModel>);
+    let view_menu_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    view_menu_box.set_margin_top(2);
+    view_menu_box.set_margin_bottom(2);
+    view_menu_box.set_margin_start(2);
+    view_menu_box.set_margin_end(2);
+
+    // Word Wrap toggle
+    let word_wrap_button = gtk::CheckButton::with_label("Word Wrap");
+    if let Ok(state) = editor_state.try_borrow() {
+        word_wrap_button.set_active(state.settings.word_wrap);
+        if state.settings.word_wrap {
+            text_view.set_wrap_mode(gtk::WrapMode::Word);
+        }
+    }
+    view_menu_box.append(&word_wrap_button);
+    WORD_WRAP_TOGGLE.with(|toggle| *toggle.borrow_mut() = Some(word_wrap_button.clone()));
+
+    // Scrollbar style, remembered across launches
+    let scrollbars_label = gtk::Label::new(Some("Scrollbars"));
+    scrollbars_label.set_halign(gtk::Align::Start);
+    scrollbars_label.set_css_classes(&["dim-label"]);
+    view_menu_box.append(&scrollbars_label);
+
+    let current_scrollbars = editor_state.try_borrow().map(|state| state.persistent.scrollbars).unwrap_or_default();
+    let mut scrollbars_group: Option<gtk::CheckButton> = None;
+    for (mode, label) in [
+        (ScrollbarMode::Always, "Always Visible"),
+        (ScrollbarMode::Overlay, "Overlay"),
+        (ScrollbarMode::Hidden, "Hidden"),
+    ] {
+        let mode_button = gtk::CheckButton::with_label(label);
+        mode_button.set_margin_start(8);
+        mode_button.set_group(scrollbars_group.as_ref());
+        mode_button.set_active(mode == current_scrollbars);
+
+        let state_ref = editor_state.clone();
+        let text_view_ref = text_view.clone();
+        mode_button.connect_toggled(move |button| {
+            if !button.is_active() {
+                return;
+            }
+            if let Ok(mut state) = state_ref.try_borrow_mut() {
+                state.persistent.scrollbars = mode;
+                if let Err(e) = state.persistent.save() {
+                    warn!("Failed to save editor state: {}", e);
+                }
+            }
+            apply_scrollbar_mode(&text_view_ref, mode);
+        });
+        view_menu_box.append(&mode_button);
+        scrollbars_group.get_or_insert(mode_button);
+    }
+
+    // Show Line Numbers toggle
+    let show_line_numbers_button = gtk::CheckButton::with_label("Show Line Numbers");
+    if let Ok(state) = editor_state.try_borrow() {
+        show_line_numbers_button.set_active(state.settings.show_line_numbers);
+    }
+    view_menu_box.append(&show_line_numbers_button);
+
+    // Favorites sidebar toggle, remembered across launches
+    let show_favorites_button = gtk::CheckButton::with_label("Show Favorites");
+    if let Ok(state) = editor_state.try_borrow() {
+        show_favorites_button.set_active(state.persistent.show_favorites);
+    }
+    let state_ref = editor_state.clone();
+    show_favorites_button.connect_toggled(move |button| {
+        set_favorites_sidebar_visible(button.is_active());
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.show_favorites = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+    });
+    view_menu_box.append(&show_favorites_button);
+
+    // Auto-reload toggle, remembered across launches
+    let auto_reload_button = gtk::CheckButton::with_label("Auto-reload Unmodified Files");
+    if let Ok(state) = editor_state.try_borrow() {
+        auto_reload_button.set_active(state.persistent.auto_reload);
+    }
+    let state_ref = editor_state.clone();
+    auto_reload_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.auto_reload = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+    });
+    view_menu_box.append(&auto_reload_button);
+
+    // Save-on-focus-loss toggle, remembered across launches
+    let save_on_focus_loss_button = gtk::CheckButton::with_label("Save on Focus Loss");
+    if let Ok(state) = editor_state.try_borrow() {
+        save_on_focus_loss_button.set_active(state.persistent.save_on_focus_loss);
+    }
+    let state_ref = editor_state.clone();
+    save_on_focus_loss_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.save_on_focus_loss = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+    });
+    view_menu_box.append(&save_on_focus_loss_button);
+
+    // Focus mode toggle, remembered across launches
+    let focus_mode_button = gtk::CheckButton::with_label("Focus Mode");
+    if let Ok(state) = editor_state.try_borrow() {
+        focus_mode_button.set_active(state.persistent.focus_mode);
+    }
+    let state_ref = editor_state.clone();
+    let text_view_ref = text_view.clone();
+    focus_mode_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.focus_mode = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+        update_focus_mode(&text_view_ref.buffer(), button.is_active());
+    });
+    view_menu_box.append(&focus_mode_button);
+
+    // Recovery autosave toggle and interval, remembered across launches
+    let autosave_button = gtk::CheckButton::with_label("Autosave Recovery Copies");
+    let autosave_interval = gtk::SpinButton::with_range(MIN_AUTOSAVE_INTERVAL_SECS as f64, 600.0, 5.0);
+    if let Ok(state) = editor_state.try_borrow() {
+        autosave_button.set_active(state.settings.autosave);
+        autosave_interval.set_value(state.settings.autosave_interval_secs as f64);
+        autosave_interval.set_sensitive(state.settings.autosave);
+    }
+    let state_ref = editor_state.clone();
+    let autosave_interval_ref = autosave_interval.clone();
+    autosave_button.connect_toggled(move |button| {
+        autosave_interval_ref.set_sensitive(button.is_active());
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.autosave = button.is_active();
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+    });
+    let state_ref = editor_state.clone();
+    autosave_interval.connect_value_changed(move |spin| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.settings.autosave_interval_secs = spin.value_as_int() as u32;
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+    });
+    view_menu_box.append(&autosave_button);
+
+    let autosave_interval_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    autosave_interval_row.set_margin_start(8);
+    let autosave_interval_label = gtk::Label::new(Some("Every (seconds)"));
+    autosave_interval_label.set_halign(gtk::Align::Start);
+    autosave_interval_label.set_hexpand(true);
+    autosave_interval_row.append(&autosave_interval_label);
+    autosave_interval_row.append(&autosave_interval);
+    view_menu_box.append(&autosave_interval_row);
+
+    // Inline diagnostics toggle, remembered across launches
+    let inline_diagnostics_button = gtk::CheckButton::with_label("Inline Diagnostics");
+    if let Ok(state) = editor_state.try_borrow() {
+        inline_diagnostics_button.set_active(state.persistent.inline_diagnostics);
+    }
+    let state_ref = editor_state.clone();
+    let text_view_ref = text_view.clone();
+    inline_diagnostics_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.inline_diagnostics = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+        show_inline_diagnostics(&text_view_ref.buffer(), &text_view_ref, &state_ref);
+    });
+    view_menu_box.append(&inline_diagnostics_button);
+
+    // Marks for spaces, tabs and line ends, remembered across launches
+    let whitespace_button = gtk::CheckButton::with_label("Show Whitespace");
+    if let Ok(state) = editor_state.try_borrow() {
+        whitespace_button.set_active(state.persistent.show_whitespace);
+        show_whitespace(state.persistent.show_whitespace);
+    }
+    let state_ref = editor_state.clone();
+    whitespace_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.show_whitespace = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+        show_whitespace(button.is_active());
+    });
+    view_menu_box.append(&whitespace_button);
+
+    // Gutter shading of the lines edited this session, remembered across launches
+    let heat_map_button = gtk::CheckButton::with_label("Edit Heat Map");
+    if let Ok(state) = editor_state.try_borrow() {
+        heat_map_button.set_active(state.persistent.edit_heat_map);
+    }
+    let state_ref = editor_state.clone();
+    let text_view_ref = text_view.clone();
+    heat_map_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.edit_heat_map = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+        queue_gutter_redraw(&text_view_ref, true);
+    });
+    view_menu_box.append(&heat_map_button);
+
+    // Layout of comparison tabs, remembered across launches
+    let side_by_side_button = gtk::CheckButton::with_label("Side-by-Side Diffs");
+    if let Ok(state) = editor_state.try_borrow() {
+        side_by_side_button.set_active(state.persistent.side_by_side_diff);
+    }
+    let state_ref = editor_state.clone();
+    let text_view_ref = text_view.clone();
+    side_by_side_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.persistent.side_by_side_diff = button.is_active();
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+        update_diff_layout(&text_view_ref, &state_ref);
+    });
+    view_menu_box.append(&side_by_side_button);
+
+    let clear_markers_button = gtk::Button::with_label("Clear Edit Markers");
+    clear_markers_button.set_has_frame(false);
+    clear_markers_button.set_hexpand(true);
+    clear_markers_button.set_halign(gtk::Align::Start);
+    let text_view_ref = text_view.clone();
+    let view_menu_ref = view_menu.clone();
+    clear_markers_button.connect_clicked(move |_| {
+        view_menu_ref.popdown();
+        clear_edit_markers(&text_view_ref);
+    });
+    view_menu_box.append(&clear_markers_button);
+
+    // Timeline of the edits made in every tab
+    let recent_edits_button = gtk::Button::with_label("Recent Edits");
+    recent_edits_button.set_has_frame(false);
+    recent_edits_button.set_hexpand(true);
+    recent_edits_button.set_halign(gtk::Align::Start);
+    recent_edits_button.connect_clicked(|_| show_recent_edits());
+    view_menu_box.append(&recent_edits_button);
+
+    // Bookmarked lines of every tab
+    let bookmarks_button = gtk::Button::with_label("Bookmarks");
+    bookmarks_button.set_has_frame(false);
+    bookmarks_button.set_hexpand(true);
+    bookmarks_button.set_halign(gtk::Align::Start);
+    bookmarks_button.connect_clicked(|_| show_bookmarks());
+    view_menu_box.append(&bookmarks_button);
+
+    // Encoding of the selected characters
+    let inspect_bytes_button = gtk::Button::with_label("Inspect Bytes");
+    inspect_bytes_button.set_has_frame(false);
+    inspect_bytes_button.set_hexpand(true);
+    inspect_bytes_button.set_halign(gtk::Align::Start);
+    let text_view_ref = text_view.clone();
+    let view_menu_ref = view_menu.clone();
+    inspect_bytes_button.connect_clicked(move |_| {
+        view_menu_ref.popdown();
+        show_byte_inspector(&text_view_ref.buffer());
+    });
+    view_menu_box.append(&inspect_bytes_button);
+
+    // Repeated words and word frequencies of the text
+    let prose_button = gtk::Button::with_label("Prose Analysis");
+    prose_button.set_has_frame(false);
+    prose_button.set_hexpand(true);
+    prose_button.set_halign(gtk::Align::Start);
+    let text_view_ref = text_view.clone();
+    let view_menu_ref = view_menu.clone();
+    prose_button.connect_clicked(move |_| {
+        view_menu_ref.popdown();
+        show_prose_analysis(&text_view_ref.buffer());
+    });
+    view_menu_box.append(&prose_button);
+
+    // A second editor pane below or beside the first one
+    for (label, orientation) in [
+        ("Split Horizontally", Some(gtk::Orientation::Vertical)),
+        ("Split Vertically", Some(gtk::Orientation::Horizontal)),
+        ("Close Split", None),
+    ] {
+        let split_button = gtk::Button::with_label(label);
+        split_button.set_has_frame(false);
+        split_button.set_hexpand(true);
+        split_button.set_halign(gtk::Align::Start);
+        split_button.connect_clicked(move |_| match orientation {
+            Some(orientation) => split_editor(orientation),
+            None => unsplit_editor(),
+        });
+        view_menu_box.append(&split_button);
+    }
+
+    // Add separator
+    let separator_view1 = gtk::Separator::new(gtk::Orientation::Horizontal);
+    separator_view1.set_margin_top(2);
+    separator_view1.set_margin_bottom(2);
+    view_menu_box.append(&separator_view1);
+
+    // Zoom In button with keyboard shortcut hint
+    let zoom_in_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let zoom_in_label = gtk::Label::new(Some("Zoom In"));
+    zoom_in_label.set_halign(gtk::Align::Start);
+    zoom_in_label.set_hexpand(true);
+    let zoom_in_shortcut = shortcut_hint(Action::ZoomIn);
+
+    zoom_in_button.append(&zoom_in_label);
+    zoom_in_button.append(&zoom_in_shortcut);
+
+    let zoom_in_wrapper = gtk::Button::new();
+    zoom_in_wrapper.set_child(Some(&zoom_in_button));
+    zoom_in_wrapper.set_has_frame(false);
+    zoom_in_wrapper.set_hexpand(true);
+
+    let state_ref = editor_state.clone();
+    let text_view_ref = text_view.clone();
+    zoom_in_wrapper.connect_clicked(move |_| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.zoom_in();
+            apply_zoom(&text_view_ref, &state.settings, state.zoom_level);
+        }
+    });
+    view_menu_box.append(&zoom_in_wrapper);
+
+    // Zoom Out button with keyboard shortcut hint
+    let zoom_out_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let zoom_out_label = gtk::Label::new(Some("Zoom Out"));
+    zoom_out_label.set_halign(gtk::Align::Start);
+    zoom_out_label.set_hexpand(true);
+    let zoom_out_shortcut = shortcut_hint(Action::ZoomOut);
+
+    zoom_out_button.append(&zoom_out_label);
+    zoom_out_button.append(&zoom_out_shortcut);
+
+    let zoom_out_wrapper = gtk::Button::new();
+    zoom_out_wrapper.set_child(Some(&zoom_out_button));
+    zoom_out_wrapper.set_has_frame(false);
+    zoom_out_wrapper.set_hexpand(true);
+
+    let state_ref = editor_state.clone();
+    let text_view_ref = text_view.clone();
+    zoom_out_wrapper.connect_clicked(move |_| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.zoom_out();
+            apply_zoom(&text_view_ref, &state.settings, state.zoom_level);
+        }
+    });
+    view_menu_box.append(&zoom_out_wrapper);
+
+    // Reset Zoom button with keyboard shortcut hint
+    let reset_zoom_button = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let reset_zoom_label = gtk::Label::new(Some("Reset Zoom"));
+    reset_zoom_label.set_halign(gtk::Align::Start);
+    reset_zoom_label.set_hexpand(true);
+    let reset_zoom_shortcut = shortcut_hint(Action::ResetZoom);
+
+    reset_zoom_button.append(&reset_zoom_label);
+    reset_zoom_button.append(&reset_zoom_shortcut);
+
+    let reset_zoom_wrapper = gtk::Button::new();
+    reset_zoom_wrapper.set_child(Some(&reset_zoom_button));
+    reset_zoom_wrapper.set_has_frame(false);
+    reset_zoom_wrapper.set_hexpand(true);
+
+    let state_ref = editor_state.clone();
+    let text_view_ref = text_view.clone();
+    reset_zoom_wrapper.connect_clicked(move |_| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.reset_zoom();
+            apply_zoom(&text_view_ref, &state.settings, state.zoom_level);
+        }
+    });
+    view_menu_box.append(&reset_zoom_wrapper);
+
+    view_menu.set_child(Some(&view_menu_box));
+    view_menu_button.set_popover(Some(&view_menu));
+
+    // Connect word wrap toggle
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    word_wrap_button.connect_toggled(move |button| {
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            // Only brought in line with the shown tab, as after switching tabs
+            if button.is_active() == (state.settings.word_wrap || state.wrap_long_lines) {
+                return;
+            }
+            state.settings.word_wrap = button.is_active();
+            // Turning wrapping off unwraps a tab opened with its long lines wrapped too
+            state.wrap_long_lines &= button.is_active();
+            if let Err(e) = state.settings.save() {
+                warn!("Failed to save settings: {}", e);
+            }
+        }
+        show_wrap_mode(&text_view_ref, &state_ref);
+    });
+
+    // Preferences, at the end of the Edit menu. The toggles it shares with the View menu
+    // are mirrored, so either place can be used.
+    let preferences_button = gtk::Button::with_label("Preferences");
+    preferences_button.set_has_frame(false);
+    preferences_button.set_hexpand(true);
+    preferences_button.set_halign(gtk::Align::Start);
+    let view_toggles = ViewToggles {
+        word_wrap: word_wrap_button.clone(),
+        show_line_numbers: show_line_numbers_button.clone(),
+        autosave: autosave_button.clone(),
+        autosave_interval: autosave_interval.clone(),
+    };
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = editor_state.clone();
+    preferences_button.connect_clicked(move |_| {
+        show_preferences(&window_ref, &text_view_ref, &state_ref, &view_toggles);
+    });
+    edit_menu_box.append(&preferences_button);
+
+    // Add Help menu button
+    let help_menu_button = gtk::MenuButton::new();
+    help_menu_button.set_label("Help");
+    help_menu_button.set_css_classes(&["menu-button"]);
+    help_menu_button.set_has_frame(false);
+    help_menu_button.set_focus_on_click(false);
+    menu_bar.append(&help_menu_button);
+
+    // Create Help popup menu
+    let help_menu = gtk::PopoverMenu::from_model(None::<&gtk::gio::MenuModel>);
+    let help_menu_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    help_menu_box.set_margin_top(2);
+    help_menu_box.set_margin_bottom(2);
+    help_menu_box.set_margin_start(2);
+    help_menu_box.set_margin_end(2);
+
+    // Keyboard Shortcuts button
+    let shortcuts_button = gtk::Button::with_label("Keyboard Shortcuts");
+    shortcuts_button.set_has_frame(false);
+    shortcuts_button.set_hexpand(true);
+    shortcuts_button.set_halign(gtk::Align::Start);
+
+    let window_ref = window.clone();
+    shortcuts_button.connect_clicked(move |_| {
+        show_shortcuts_editor(&window_ref);
+    });
+    help_menu_box.append(&shortcuts_button);
+
+    // About button
+    let about_button = gtk::Button::with_label("About RustEdit");
+    about_button.set_has_frame(false);
+    about_button.set_hexpand(true);
+    about_button.set_halign(gtk::Align::Start);
+
+    let window_ref = window.clone();
+    about_button.connect_clicked(move |_| {
+        let dialog = gtk::AboutDialog::new();
+        dialog.set_modal(true);
+        dialog.set_transient_for(Some(&window_ref));
+        dialog.set_program_name(Some("RustEdit"));
+        dialog.set_version(Some("0.1.0"));
+        dialog.set_comments(Some("A lightweight text editor inspired by COSMIC Edit"));
+        dialog.set_copyright(Some("© 2023 RustEdit Developers"));
+        dialog.set_license_type(gtk::License::Gpl30);
+        
+        dialog.show();
+    });
+    help_menu_box.append(&about_button);
+
+    help_menu.set_child(Some(&help_menu_box));
+    help_menu_button.set_popover(Some(&help_menu));
+    
+    // Create a separator between menu bars and tabs
+    let separator = gtk::Separator::new(gtk::Orientation::Horizontal);
+    separator.set_css_classes(&["menu-separator"]);
+    
+    // Add the menu bar to the main container
+    main_container.append(&menu_bar);
+    main_container.append(&separator);
+    
+    // Create a new separate row for tabs (horizontal box)
+    let tabs_row = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    tabs_row.set_css_classes(&["tabs-row"]);
+    
+    // Add modern tab bar container
+    let tabs_container = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    tabs_container.set_hexpand(true);
+    tabs_container.set_css_classes(&["tab-bar"]);
+    
+    // Create tabs box and store tab buttons in a Vec for tracking
+    let tabs_box = gtk::Box::new(gtk::Orientation::Horizontal, 2);
+    tabs_box.set_hexpand(true);
+    tabs_box.set_css_classes(&["tabs-box"]);
+    
+    // Create tab button with modern styling
+    let tab_button = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    tab_button.set_css_classes(&["tab-button"]);
+    
+    // Get the tab name
+    let tab_name = {
+        if let Ok(state) = editor_state.try_borrow() {
+            state.tab_name.clone()
+        } else {
+            "Untitled".to_string()
+        }
+    };
+    
+    // Create a label for the tab
+    let tab_label = gtk::Label::new(Some(&tab_name));
+    tab_label.set_css_classes(&["tab-label"]);
+    tab_label.set_ellipsize(pango::EllipsizeMode::End);
+    tab_label.set_width_chars(15);
+    tab_label.set_max_width_chars(15);
+    
+    // Lock shown while the tab is read-only
+    let tab_lock = gtk::Image::from_icon_name("changes-prevent-symbolic");
+    tab_lock.set_tooltip_text(Some("Read-only"));
+    tab_lock.set_visible(false);
+    
+    // Create a close button for the tab
+    let close_icon = gtk::Button::new();
+    close_icon.set_css_classes(&["tab-close-button"]);
+    close_icon.set_icon_name("window-close-symbolic");
+    close_icon.set_tooltip_text(Some("Close tab"));
+    
+    // Add elements to tab button
+    tab_button.append(&tab_lock);
+    tab_button.append(&tab_label);
     tab_button.append(&close_icon);
     
-    // Wrap tab button in a clickable button
-    let tab_button_wrapper = gtk::Button::new();
-    tab_button_wrapper.set_css_classes(&["tab-button-wrapper", "active"]);
-    tab_button_wrapper.set_has_frame(false);
-    tab_button_wrapper.set_child(Some(&tab_button));
+    // Wrap tab button in a clickable button
+    let tab_button_wrapper = gtk::Button::new();
+    tab_button_wrapper.set_css_classes(&["tab-button-wrapper", "active"]);
+    tab_button_wrapper.set_has_frame(false);
+    tab_button_wrapper.set_child(Some(&tab_button));
+    tabs.borrow_mut().add_tab(buffer, &tab_label, &tab_lock, &tab_button_wrapper);
+    connect_tab_tooltip(&tab_button_wrapper, buffer, tabs);
+    
+    // Add the tab to tabs box
+    tabs_box.append(&tab_button_wrapper);
+    
+    // Create a "+" button to add new tabs with modern styling
+    let new_tab_button = gtk::Button::new();
+    new_tab_button.set_icon_name("list-add-symbolic");
+    new_tab_button.set_tooltip_text(Some("New Tab"));
+    new_tab_button.set_css_classes(&["new-tab-button"]);
+    
+    // Add the new tab button after the first tab
+    tabs_box.append(&new_tab_button);
+    
+    // Connect the initial tab to activate it when clicked
+    let text_view_ref = text_view.clone();
+    let buffer_clone = buffer.clone();
+    let tab_button_wrapper_clone = tab_button_wrapper.clone();
+    
+    tab_button_wrapper.connect_clicked(move |clicked_button| {
+        // Set this tab as active
+        clicked_button.set_css_classes(&["tab-button-wrapper", "active"]);
+        
+        // Switch to this tab's buffer
+        text_view_ref.set_buffer(Some(&buffer_clone));
+    });
+    
+    // Make the close button for the first tab work
+    let buffer_clone = buffer.clone();
+    let editor_state_ref = editor_state.clone();
+    
+    // Create a gesture controller for the first tab's close button
+    let first_click_controller = gtk::GestureClick::new();
+    first_click_controller.set_button(1); // Left mouse button
+    first_click_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    close_icon.add_controller(first_click_controller.clone());
+    
+    let buffer_clone = buffer.clone();
+    let editor_state_ref = editor_state.clone();
+    let text_view_ref = text_view.clone();
+    
+    first_click_controller.connect_pressed(move |gesture, _, _, _| {
+        debug!("First tab X button clicked");
+        gesture.set_state(gtk::EventSequenceState::Claimed);
+        
+        // Show the first tab's buffer so the state below is the first tab's own
+        text_view_ref.set_buffer(Some(&buffer_clone));
+        
+        // Clear the content of this tab as it's the main tab; we don't actually
+        // remove it as it's the primary one. Unsaved changes are offered for saving first.
+        debug!("Clearing content of first tab (not removing it as it's the primary tab)");
+        let text_view = text_view_ref.clone();
+        let state = editor_state_ref.clone();
+        confirm_close_tab(&text_view_ref, &editor_state_ref, move || clear_shown_tab(&text_view, &state));
+    });
+    
+    // Set up a timer to update the active tab's label when state changes (like when a file is opened)
+    let editor_state_ref = editor_state.clone();
+    let tabs_ref = tabs.clone();
+    
+    let timeout_id = glib::timeout_add_local(Duration::from_millis(500), move || {
+        let active_label = tabs_ref.borrow().active_label();
+        if let (Some(label), Ok(state)) = (active_label, editor_state_ref.try_borrow()) {
+            let modified_marker = if state.is_modified { "*" } else { "" };
+            label.set_text(&format!("{}{}", modified_marker, state.tab_title()));
+        }
+        // Continue the timer
+        glib::ControlFlow::Continue
+    });
+    
+    // Store the timeout ID
+    if let Ok(mut state) = editor_state.try_borrow_mut() {
+        state.timeout_id = Some(timeout_id);
+    }
+    
+    // Add right-click context menu for the first tab
+    let gesture = gtk::GestureClick::new();
+    gesture.set_button(3); // Right mouse button
+    
+    let tab_button_wrapper_ref = tab_button_wrapper.clone();
+    // Create a fresh buffer clone for this closure
+    let buffer_for_context = buffer.clone();
+    let text_view_for_context = text_view.clone();
+    let editor_state_for_context = editor_state.clone();
+    let tabs_for_context = tabs.clone();
+    
+    gesture.connect_pressed(move |_, _, _, _| {
+        let popover = gtk::Popover::new();
+        popover.set_parent(&tab_button_wrapper_ref);
+        
+        let box_container = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        box_container.set_margin_top(5);
+        box_container.set_margin_bottom(5);
+        box_container.set_margin_start(5);
+        box_container.set_margin_end(5);
+        
+        // Clear tab content option
+        let clear_item = gtk::Button::new();
+        clear_item.set_label("Clear Content");
+        clear_item.set_css_classes(&["menu-item"]);
+        clear_item.set_has_frame(false);
+        
+        // Use clone specific to this inner closure
+        let buffer_for_clear = buffer_for_context.clone();
+        let popover_ref = popover.clone();
+        
+        let clear_item_clone = clear_item.clone();
+        clear_item.connect_clicked(move |_| {
+            buffer_for_clear.set_text("");
+            popover_ref.popdown();
+        });
+        
+        box_container.append(&clear_item_clone);
+        
+        // Follow toggle, only meaningful when the tab shows a file
+        let follow_item = gtk::CheckButton::with_label("Follow (tail -f)");
+        let is_shown = text_view_for_context.buffer() == buffer_for_context;
+        if let Ok(state) = editor_state_for_context.try_borrow() {
+            follow_item.set_active(is_shown && state.following);
+            follow_item.set_sensitive(is_shown && state.current_file.is_some());
+        }
+        
+        let buffer_for_follow = buffer_for_context.clone();
+        let text_view_for_follow = text_view_for_context.clone();
+        let state_for_follow = editor_state_for_context.clone();
+        follow_item.connect_toggled(move |button| {
+            set_following(&buffer_for_follow, &text_view_for_follow, &state_for_follow, button.is_active());
+        });
+        
+        box_container.append(&follow_item);
+        
+        if let Some(favorite_item) = favorite_menu_item(&tabs_for_context, &editor_state_for_context, &buffer_for_context, &popover) {
+            box_container.append(&favorite_item);
+        }
+        
+        popover.set_child(Some(&box_container));
+        popover.popup();
+    });
+    
+    tab_button_wrapper.add_controller(gesture);
+    
+    // Connect the + button to create a new tab
+    let tab_bar = TabBar {
+        tabs_box: tabs_box.clone(),
+        new_tab_button: new_tab_button.clone(),
+        first_tab: tab_button_wrapper.clone(),
+        first_buffer: buffer.clone(),
+        text_view: text_view.clone(),
+        editor_state: editor_state.clone(),
+        tabs: tabs.clone(),
+    };
+    
+    let tab_bar_ref = tab_bar.clone();
+    new_tab_button.connect_clicked(move |_| {
+        open_new_tab(&tab_bar_ref, None);
+    });
+    
+    // File -> Compare files: pick two files and show their diff in a new tab
+    let window_ref = window.clone();
+    let tab_bar_ref = tab_bar.clone();
+    compare_button_wrapper.connect_clicked(move |_| {
+        let window = window_ref.clone();
+        let tab_bar = tab_bar_ref.clone();
+        choose_file(&window_ref, &tab_bar_ref.editor_state, "Select First File", move |old_path| {
+            let tab_bar = tab_bar.clone();
+            let state = tab_bar.editor_state.clone();
+            choose_file(&window, &state, "Select Second File", move |new_path| {
+                compare_files(&tab_bar, &old_path, &new_path);
+            });
+        });
+    });
+    
+    // File -> Compare with saved: the shown tab's edits against its file on disk
+    let tab_bar_ref = tab_bar.clone();
+    compare_saved_wrapper.connect_clicked(move |_| {
+        compare_with_saved(&tab_bar_ref);
+    });
+    
+    // Edit -> Open selection
+    let window_ref = window.clone();
+    let tab_bar_ref = tab_bar.clone();
+    let status_label_ref = status_label.clone();
+    let edit_menu_ref = edit_menu.clone();
+    open_selection_button.connect_clicked(move |_| {
+        edit_menu_ref.popdown();
+        open_selection(&window_ref, &tab_bar_ref, &status_label_ref);
+    });
+    
+    // Edit -> Open selection in new tab
+    let tab_bar_ref = tab_bar.clone();
+    let edit_menu_ref = edit_menu.clone();
+    selection_to_tab_button.connect_clicked(move |_| {
+        edit_menu_ref.popdown();
+        open_selection_in_new_tab(&tab_bar_ref);
+    });
+
+    // Edit -> Run selection as Rust
+    let text_view_ref = text_view.clone();
+    let edit_menu_ref = edit_menu.clone();
+    run_selection_button.connect_clicked(move |_| {
+        edit_menu_ref.popdown();
+        run_selection(&text_view_ref);
+    });
+    
+    // File -> Save session / Recent sessions
+    let window_ref = window.clone();
+    let tab_bar_ref = tab_bar.clone();
+    save_session_wrapper.connect_clicked(move |_| {
+        save_session_dialog(&window_ref, &tab_bar_ref);
+    });
+    
+    let window_ref = window.clone();
+    let tab_bar_ref = tab_bar.clone();
+    let status_label_ref = status_label.clone();
+    recent_sessions_wrapper.connect_clicked(move |button| {
+        show_sessions_popover(button, &window_ref, &tab_bar_ref, &status_label_ref);
+    });
+    
+    // Offer the saved sessions once the window is up
+    let window_ref = window.clone();
+    let tab_bar_ref = tab_bar.clone();
+    let status_label_ref = status_label.clone();
+    glib::idle_add_local_once(move || {
+        prompt_for_session(&window_ref, &tab_bar_ref, &status_label_ref);
+    });
+    
+    // Offer work left behind by a crash, then keep recovery copies of unsaved tabs
+    let window_ref = window.clone();
+    let tab_bar_ref = tab_bar.clone();
+    glib::idle_add_local_once(move || {
+        offer_recovery(&window_ref, &tab_bar_ref);
+    });
+    schedule_autosave(tabs);
+
+    // Files from the command line or from another launch of the editor
+    let window_ref = window.clone();
+    let tab_bar_ref = tab_bar.clone();
+    let status_label_ref = status_label.clone();
+    FILE_HANDOFF.with(|handoff| *handoff.borrow_mut() = Some(Box::new(move |paths| {
+        for path in paths {
+            show_path_in_tab(&window_ref, &tab_bar_ref, &status_label_ref, path);
+        }
+        window_ref.present();
+    })));
+    
+    // Keyboard activation of the first tab's close button, with the same prompt as a click
+    let buffer_clone = buffer.clone();
+    let text_view_ref = text_view.clone();
+    let editor_state_ref = editor_state.clone();
+    
+    close_icon.connect_clicked(move |_| {
+        text_view_ref.set_buffer(Some(&buffer_clone));
+        let text_view = text_view_ref.clone();
+        let state = editor_state_ref.clone();
+        confirm_close_tab(&text_view_ref, &editor_state_ref, move || clear_shown_tab(&text_view, &state));
+    });
+    
+    // Connect the initial tab to activate it when clicked
+    let text_view_ref = text_view.clone();
+    let buffer_clone = buffer.clone();
+    
+    tab_button_wrapper.connect_clicked(move |clicked_button| {
+        // Set this tab as active
+        clicked_button.set_css_classes(&["tab-button-wrapper", "active"]);
+        
+        // Switch to this tab's buffer
+        text_view_ref.set_buffer(Some(&buffer_clone));
+    });
+    
+    // Create tabs container with tabs and add button
+    tabs_container.append(&tabs_box);
+    
+    // Add tabs container to tabs row
+    tabs_row.append(&tabs_container);
+    
+    // Add the tabs row to the main container
+    main_container.append(&tabs_row);
+
+    // Return the main container, button references, and find/replace buttons
+    (main_container, new_button_wrapper, open_button_wrapper, save_button_wrapper.clone(), open_recent_wrapper, save_as_button_wrapper, tabs_box, find_button, replace_button, show_line_numbers_button)
+}
+
+fn update_status_bar(status_label: &gtk::Label, buffer: &gtk::TextBuffer, editor_state: &Rc<RefCell<EditorState>>) {
+    if let Ok(state) = editor_state.try_borrow() {
+        let modified = state.is_modified;
+        let (line, column) = get_cursor_position(buffer);
+        
+        let modified_marker = if modified { "*" } else { "" };
+        status_label.set_text(&format!("{}Line: {} Col: {}", modified_marker, line, column));
+    }
+    update_language_selector(editor_state);
+    update_encoding_selector(editor_state);
+    update_line_ending_selector(editor_state);
+    update_indentation_selector(editor_state);
+    apply_language_theme(editor_state);
+}
+
+thread_local! {
+    static LANGUAGE_SELECTOR: RefCell<Option<gtk::DropDown>> = const { RefCell::new(None) };
+}
+
+// Entries of the status bar language selector, in display order
+fn language_choices() -> Vec<&'static str> {
+    let mut choices = vec!["Auto-detect", language::PLAIN_TEXT];
+    choices.extend(language::LANGUAGES.iter().map(|language| language.name));
+    choices
+}
+
+// Status bar drop-down showing the tab's language; picking one overrides detection for that tab
+fn create_language_selector(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) -> gtk::DropDown {
+    let choices = language_choices();
+    let selector = gtk::DropDown::from_strings(&choices);
+    selector.set_css_classes(&["language-selector"]);
+    selector.set_tooltip_text(Some("Highlighting language for this tab"));
+
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    selector.connect_selected_notify(move |selector| {
+        let chosen = choices.get(selector.selected() as usize).copied();
+
+        let changed = match state_ref.try_borrow_mut() {
+            Ok(mut state) => {
+                // Choosing the detected language is the same as auto-detect
+                let detected = state.detected_language().map_or(language::PLAIN_TEXT, |language| language.name);
+                let language_override = chosen
+                    .filter(|name| *name != "Auto-detect" && *name != detected)
+                    .map(str::to_string);
+                let changed = state.language_override != language_override;
+                if changed {
+                    state.remember_language_override(language_override.clone());
+                }
+                state.language_override = language_override;
+                changed
+            }
+            Err(_) => false,
+        };
+
+        if changed {
+            let (highlight, language) = match state_ref.try_borrow() {
+                Ok(state) => (state.syntax_highlighting && !state.large_file_mode, state.language()),
+                Err(_) => (false, None),
+            };
+            if highlight {
+                highlight_shown_buffer(&text_view_ref.buffer(), language, &state_ref);
+            }
+        }
+
+        // Show the language now in effect, which moves "Auto-detect" to the detected one
+        update_language_selector(&state_ref);
+    });
+
+    LANGUAGE_SELECTOR.with(|cell| *cell.borrow_mut() = Some(selector.clone()));
+    selector
+}
+
+fn update_language_selector(state: &Rc<RefCell<EditorState>>) {
+    let name = match state.try_borrow() {
+        Ok(state) => state.language().map_or(language::PLAIN_TEXT, |language| language.name),
+        Err(_) => return,
+    };
+    let Some(index) = language_choices().iter().position(|choice| *choice == name) else {
+        return;
+    };
+
+    // Only touch the selection when it differs, since changing it re-enters the handler above
+    LANGUAGE_SELECTOR.with(|cell| {
+        if let Some(selector) = cell.borrow().as_ref() {
+            if selector.selected() != index as u32 {
+                selector.set_selected(index as u32);
+            }
+        }
+    });
+}
+
+thread_local! {
+    static INDENTATION_SELECTOR: RefCell<Option<gtk::MenuButton>> = const { RefCell::new(None) };
+}
+
+// Status bar button showing how Tab indents, with a popover to change it for the tab's language
+fn create_indentation_selector(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label) -> gtk::MenuButton {
+    let selector = gtk::MenuButton::new();
+    selector.set_css_classes(&["indentation-selector"]);
+    selector.set_tooltip_text(Some("Indentation of this tab's language"));
+
+    let popover = gtk::Popover::new();
+    let choices_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    let choices: [(&str, Option<bool>, Option<u32>); 5] = [
+        ("Indent Using Spaces", Some(true), None),
+        ("Indent Using Tabs", Some(false), None),
+        ("Indent Width: 2", None, Some(2)),
+        ("Indent Width: 4", None, Some(4)),
+        ("Indent Width: 8", None, Some(8)),
+    ];
+    for (label, insert_spaces, indent_width) in choices {
+        let button = gtk::Button::with_label(label);
+        button.set_has_frame(false);
+        button.set_halign(gtk::Align::Start);
+
+        let text_view_ref = text_view.clone();
+        let state_ref = state.clone();
+        let status_label_ref = status_label.clone();
+        let popover_ref = popover.clone();
+        button.connect_clicked(move |_| {
+            popover_ref.popdown();
+            if let Ok(mut state) = state_ref.try_borrow_mut() {
+                let language = state.language().map_or(language::PLAIN_TEXT, |language| language.name);
+                let language_settings = state.settings.languages.entry(language.to_string()).or_default();
+                if insert_spaces.is_some() {
+                    language_settings.insert_spaces = insert_spaces;
+                }
+                if indent_width.is_some() {
+                    language_settings.indent_width = indent_width;
+                }
+                if let Err(e) = state.settings.save() {
+                    warn!("Failed to save settings: {}", e);
+                }
+            }
+            update_status_bar(&status_label_ref, &text_view_ref.buffer(), &state_ref);
+        });
+        choices_box.append(&button);
+    }
+    popover.set_child(Some(&choices_box));
+    selector.set_popover(Some(&popover));
+
+    INDENTATION_SELECTOR.with(|cell| *cell.borrow_mut() = Some(selector.clone()));
+    update_indentation_selector(state);
+    selector
+}
+
+fn update_indentation_selector(state: &Rc<RefCell<EditorState>>) {
+    let label = match state.try_borrow() {
+        Ok(state) => state.indentation().label(),
+        Err(_) => return,
+    };
+    INDENTATION_SELECTOR.with(|cell| {
+        if let Some(selector) = cell.borrow().as_ref() {
+            if selector.label().as_deref() != Some(label.as_str()) {
+                selector.set_label(&label);
+            }
+        }
+    });
+}
+
+// Tab indents and Shift+Tab dedents the lines of the selection, or of the cursor for
+// Shift+Tab. Tab without a multi-line selection inserts one level of indentation.
+fn connect_indentation(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let dedent = key == Key::ISO_Left_Tab || key == Key::Tab && modifiers.contains(gtk::gdk::ModifierType::SHIFT_MASK);
+        if !(dedent || key == Key::Tab)
+            || modifiers.intersects(gtk::gdk::ModifierType::CONTROL_MASK | gtk::gdk::ModifierType::ALT_MASK)
+            || !text_view_ref.is_editable()
+        {
+            return glib::Propagation::Proceed;
+        }
+        let Ok(indentation) = state_ref.try_borrow().map(|state| state.indentation()) else {
+            return glib::Propagation::Proceed;
+        };
+
+        let buffer = text_view_ref.buffer();
+        let (mut start, mut end) = buffer.selection_bounds()
+            .unwrap_or_else(|| {
+                let cursor = buffer.iter_at_mark(&buffer.get_insert());
+                (cursor, cursor)
+            });
+        if !dedent && start.line() == end.line() {
+            let unit = if indentation.insert_spaces {
+                let width = indentation.width as i32;
+                " ".repeat((width - start.line_offset() % width) as usize)
+            } else {
+                "\t".to_string()
+            };
+            buffer.begin_user_action();
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &unit);
+            buffer.end_user_action();
+            return glib::Propagation::Stop;
+        }
+
+        // A selection ending at the start of a line leaves that line alone
+        if end.line() > start.line() && end.starts_line() {
+            end.backward_char();
+        }
+        start.set_line_offset(0);
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+        let lines = buffer.text(&start, &end, false);
+        let changed = if dedent {
+            dedent_lines(&lines, indentation.width as usize)
+        } else {
+            indent_lines(&lines, &indentation.unit())
+        };
+        if changed != lines.as_str() {
+            let first_line = start.line();
+            buffer.begin_user_action();
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &changed);
+            buffer.end_user_action();
+            // Keep the whole lines selected, to indent them again
+            if let Some(first) = buffer.iter_at_line(first_line) {
+                buffer.select_range(&first, &start);
+            }
+        }
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Backspace between a bracket or quote and its partner deletes both, so an empty pair goes
+// in one keystroke, and so does selecting just the opening half of one
+fn connect_pair_deletion(text_view: &gtk::TextView) {
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        if key != Key::BackSpace || !modifiers.is_empty() || !text_view_ref.is_editable() {
+            return glib::Propagation::Proceed;
+        }
+        let buffer = text_view_ref.buffer();
+        let (mut start, mut end) = match buffer.selection_bounds() {
+            Some((start, end)) if end.offset() - start.offset() == 1 => (start, end),
+            Some(_) => return glib::Propagation::Proceed,
+            None => {
+                let cursor = buffer.iter_at_mark(&buffer.get_insert());
+                let mut start = cursor;
+                if !start.backward_char() {
+                    return glib::Propagation::Proceed;
+                }
+                (start, cursor)
+            }
+        };
+        if brackets::closing_partner(start.char()) != Some(end.char()) {
+            return glib::Propagation::Proceed;
+        }
+        end.forward_char();
+        buffer.begin_user_action();
+        buffer.delete(&mut start, &mut end);
+        buffer.end_user_action();
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Smart Home and End, with Shift to select: Home goes to the first character of the line
+// that is not blank, then to the line's start; End to the line's end, then to just after its
+// last character that is not blank. On the inner rows of a wrapped line GTK's own keys apply.
+fn connect_smart_home_end(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let home = match key {
+            Key::Home | Key::KP_Home => true,
+            Key::End | Key::KP_End => false,
+            _ => return glib::Propagation::Proceed,
+        };
+        let extend = modifiers.contains(gtk::gdk::ModifierType::SHIFT_MASK);
+        if !modifiers.difference(gtk::gdk::ModifierType::SHIFT_MASK).is_empty() {
+            return glib::Propagation::Proceed;
+        }
+        let buffer = text_view_ref.buffer();
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let mut row_edge = cursor;
+        let line_edge = if home {
+            text_view_ref.backward_display_line_start(&mut row_edge);
+            let mut line_start = cursor;
+            line_start.set_line_offset(0);
+            line_start
+        } else {
+            text_view_ref.forward_display_line_end(&mut row_edge);
+            let mut line_end = cursor;
+            if !line_end.ends_line() {
+                line_end.forward_to_line_end();
+            }
+            line_end
+        };
+        if row_edge != line_edge {
+            return glib::Propagation::Proceed;
+        }
+
+        let target = {
+            let mut state = state_ref.borrow_mut();
+            if !mirror_in_step(&state, &buffer) {
+                return glib::Propagation::Proceed;
+            }
+            let insert = state.text_buffer.byte_offset(cursor.offset() as usize);
+            let bound = state.text_buffer.byte_offset(buffer.iter_at_mark(&buffer.selection_bound()).offset() as usize);
+            state.text_buffer.set_cursor_position(insert);
+            state.text_buffer.set_selection((bound != insert).then_some(bound..insert));
+            if home {
+                state.text_buffer.move_cursor_home(extend);
+            } else {
+                state.text_buffer.move_cursor_end(extend);
+            }
+            iter_at_mirror_byte(&buffer, &state.text_buffer, state.text_buffer.cursor_position())
+        };
+        if extend {
+            buffer.move_mark(&buffer.get_insert(), &target);
+        } else {
+            buffer.place_cursor(&target);
+        }
+        text_view_ref.scroll_mark_onscreen(&buffer.get_insert());
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Words as the settings define them for the tab's language: double-clicking selects one,
+// Ctrl+Left/Right (with Shift to select) move by them and Ctrl+Backspace/Delete delete them.
+// Without extra word characters GTK's own word handling is left alone.
+fn connect_word_chars(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    // The mirror, synced with the language's word characters, or None when it lags the
+    // buffer or the language adds none
+    fn synced_mirror<'a>(buffer: &gtk::TextBuffer, state: &'a Rc<RefCell<EditorState>>) -> Option<std::cell::RefMut<'a, EditorState>> {
+        let mut state = state.borrow_mut();
+        state.sync_word_chars();
+        (mirror_in_step(&state, buffer) && !state.text_buffer.extra_word_chars().is_empty()).then_some(state)
+    }
+
+    let click = gtk::GestureClick::new();
+    click.set_button(1);
+    click.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    click.connect_pressed(move |gesture, n_press, x, y| {
+        if n_press != 2 {
+            return;
+        }
+        let buffer = text_view_ref.buffer();
+        let (x, y) = text_view_ref.window_to_buffer_coords(gtk::TextWindowType::Widget, x as i32, y as i32);
+        let Some(at) = text_view_ref.iter_at_location(x, y) else {
+            return;
+        };
+        let word = {
+            let Some(state) = synced_mirror(&buffer, &state_ref) else {
+                return;
+            };
+            let range = state.text_buffer.get_word_boundary_at_offset(state.text_buffer.byte_offset(at.offset() as usize));
+            (!range.is_empty()).then(|| (iter_at_mirror_byte(&buffer, &state.text_buffer, range.start), iter_at_mirror_byte(&buffer, &state.text_buffer, range.end)))
+        };
+        if let Some((start, end)) = word {
+            gesture.set_state(gtk::EventSequenceState::Claimed);
+            buffer.select_range(&end, &start);
+        }
+    });
+    text_view.add_controller(click);
+
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let (forward, delete) = match key {
+            Key::Left => (false, false),
+            Key::Right => (true, false),
+            Key::BackSpace => (false, true),
+            Key::Delete | Key::KP_Delete => (true, true),
+            _ => return glib::Propagation::Proceed,
+        };
+        let extend = modifiers.contains(gtk::gdk::ModifierType::SHIFT_MASK);
+        if modifiers.difference(gtk::gdk::ModifierType::SHIFT_MASK) != gtk::gdk::ModifierType::CONTROL_MASK {
+            return glib::Propagation::Proceed;
+        }
+        let buffer = text_view_ref.buffer();
+        if delete {
+            // A selection is deleted whole, as GTK does anyway
+            if extend || buffer.has_selection() || !text_view_ref.is_editable() {
+                return glib::Propagation::Proceed;
+            }
+            let (mut start, mut end) = {
+                let Some(state) = synced_mirror(&buffer, &state_ref) else {
+                    return glib::Propagation::Proceed;
+                };
+                let cursor = state.text_buffer.byte_offset(buffer.iter_at_mark(&buffer.get_insert()).offset() as usize);
+                let range = state.text_buffer.word_deletion_range(cursor, forward);
+                (iter_at_mirror_byte(&buffer, &state.text_buffer, range.start), iter_at_mirror_byte(&buffer, &state.text_buffer, range.end))
+            };
+            buffer.begin_user_action();
+            buffer.delete(&mut start, &mut end);
+            buffer.end_user_action();
+            text_view_ref.scroll_mark_onscreen(&buffer.get_insert());
+            return glib::Propagation::Stop;
+        }
+        let target = {
+            let Some(state) = synced_mirror(&buffer, &state_ref) else {
+                return glib::Propagation::Proceed;
+            };
+            let cursor = state.text_buffer.byte_offset(buffer.iter_at_mark(&buffer.get_insert()).offset() as usize);
+            let target = if forward { state.text_buffer.next_word_end(cursor) } else { state.text_buffer.previous_word_start(cursor) };
+            iter_at_mirror_byte(&buffer, &state.text_buffer, target)
+        };
+        if extend {
+            buffer.move_mark(&buffer.get_insert(), &target);
+        } else {
+            buffer.place_cursor(&target);
+        }
+        text_view_ref.scroll_mark_onscreen(&buffer.get_insert());
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Ctrl+Up/Down (with Shift to select) move to the start of the paragraph before or after
+// the cursor, paragraphs being runs of lines with text between blank lines
+fn connect_paragraph_moves(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let forward = match key {
+            Key::Up | Key::KP_Up => false,
+            Key::Down | Key::KP_Down => true,
+            _ => return glib::Propagation::Proceed,
+        };
+        let extend = modifiers.contains(gtk::gdk::ModifierType::SHIFT_MASK);
+        if modifiers.difference(gtk::gdk::ModifierType::SHIFT_MASK) != gtk::gdk::ModifierType::CONTROL_MASK {
+            return glib::Propagation::Proceed;
+        }
+        let buffer = text_view_ref.buffer();
+        let target = {
+            let state = state_ref.borrow();
+            if !mirror_in_step(&state, &buffer) {
+                return glib::Propagation::Proceed;
+            }
+            let cursor = state.text_buffer.byte_offset(buffer.iter_at_mark(&buffer.get_insert()).offset() as usize);
+            let target = if forward {
+                state.text_buffer.next_paragraph_start(cursor)
+            } else {
+                state.text_buffer.previous_paragraph_start(cursor)
+            };
+            iter_at_mirror_byte(&buffer, &state.text_buffer, target)
+        };
+        if extend {
+            buffer.move_mark(&buffer.get_insert(), &target);
+        } else {
+            buffer.place_cursor(&target);
+        }
+        text_view_ref.scroll_mark_onscreen(&buffer.get_insert());
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+}
+
+// Add `unit` in front of every line that has text
+fn indent_lines(lines: &str, unit: &str) -> String {
+    lines
+        .split('\n')
+        .map(|line| if line.trim().is_empty() { line.to_string() } else { format!("{}{}", unit, line) })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Remove one level of indentation, a tab or up to `width` spaces, from the start of every line
+fn dedent_lines(lines: &str, width: usize) -> String {
+    lines
+        .split('\n')
+        .map(|line| {
+            if let Some(rest) = line.strip_prefix('\t') {
+                return rest;
+            }
+            let spaces = line.len() - line.trim_start_matches(' ').len();
+            &line[spaces.min(width)..]
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+thread_local! {
+    static LINE_ENDING_SELECTOR: RefCell<Option<gtk::MenuButton>> = const { RefCell::new(None) };
+}
+
+// Ctrl+/ comments or uncomments the selected lines, Ctrl+Shift+/ wraps the selection (or the
+// line) in a block comment or unwraps it. Languages with only one kind use it for both.
+fn toggle_comment(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, block: bool) {
+    let Some(language) = state.try_borrow().ok().and_then(|state| state.language()) else {
+        return;
+    };
+    let buffer = text_view.buffer();
+    let (mut start, mut end) = buffer.selection_bounds()
+        .unwrap_or_else(|| {
+            let cursor = buffer.iter_at_mark(&buffer.get_insert());
+            (cursor, cursor)
+        });
+    let selected_text = start != end;
+
+    let whole_lines = !(block && selected_text && language.block_comment.is_some());
+    if whole_lines {
+        // A selection ending at the start of a line leaves that line alone
+        if end.line() > start.line() && end.starts_line() {
+            end.backward_char();
+        }
+        start.set_line_offset(0);
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+    }
+    let text = buffer.text(&start, &end, false);
+    let changed = match (block, language.line_comment, language.block_comment) {
+        (true, _, Some((open, close))) => comments::toggle_block_comment(&text, open, close),
+        (_, Some(open), _) => comments::toggle_line_comments(&text, open, None),
+        (false, None, Some((open, close))) => comments::toggle_line_comments(&text, open, Some(close)),
+        _ => return,
+    };
+    if changed == text.as_str() {
+        return;
+    }
+
+    let start_offset = start.offset();
+    buffer.begin_user_action();
+    buffer.delete(&mut start, &mut end);
+    buffer.insert(&mut start, &changed);
+    buffer.end_user_action();
+    // Keep what was commented selected, to toggle it back
+    if selected_text || whole_lines && changed.contains('\n') {
+        buffer.select_range(&buffer.iter_at_offset(start_offset), &start);
+    }
+}
+
+// Text of the view's buffer and its selection, or cursor, in bytes
+fn text_and_selection(buffer: &gtk::TextBuffer) -> (String, std::ops::Range<usize>) {
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false).to_string();
+    let (start, end) = buffer.selection_bounds().unwrap_or_else(|| {
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        (cursor, cursor)
+    });
+    let selection = byte_offset_of(&text, start.offset())..byte_offset_of(&text, end.offset());
+    (text, selection)
+}
+
+// Make an edit planned by `edit_ops` on the view's buffer, as a single undo step
+fn apply_edit(text_view: &gtk::TextView, text: &str, edit: edit_ops::Edit) {
+    let buffer = text_view.buffer();
+    let mut start = iter_at_byte(&buffer, text, edit.range.start);
+    let mut end = iter_at_byte(&buffer, text, edit.range.end);
+    buffer.begin_user_action();
+    buffer.delete(&mut start, &mut end);
+    buffer.insert(&mut start, &edit.text);
+    buffer.end_user_action();
+
+    let changed = edit.apply(text);
+    buffer.select_range(
+        &iter_at_byte(&buffer, &changed, edit.selection.end),
+        &iter_at_byte(&buffer, &changed, edit.selection.start),
+    );
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+}
+
+// Ctrl+Shift+D: duplicate the selection, or the cursor's line
+fn duplicate_lines(text_view: &gtk::TextView) {
+    if !text_view.is_editable() {
+        return;
+    }
+    let (text, selection) = text_and_selection(&text_view.buffer());
+    let edit = edit_ops::duplicate(&text, selection);
+    apply_edit(text_view, &text, edit);
+}
+
+// Alt+Up and Alt+Down: move the lines the selection touches past the line above or below
+fn move_lines(text_view: &gtk::TextView, down: bool) {
+    if !text_view.is_editable() {
+        return;
+    }
+    let (text, selection) = text_and_selection(&text_view.buffer());
+    if let Some(edit) = edit_ops::move_lines(&text, selection, down) {
+        apply_edit(text_view, &text, edit);
+    }
+}
+
+// Select the range `pick` finds in the mirror for the view's selection, or cursor, with the
+// cursor at its end: Ctrl+L for lines, Ctrl+Shift+L for the paragraph, Ctrl+Shift+Space to
+// expand the selection
+fn select_from_mirror(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, pick: impl FnOnce(&EditorBuffer, std::ops::Range<usize>) -> Option<std::ops::Range<usize>>) {
+    let buffer = text_view.buffer();
+    let (start, end) = {
+        let state = state.borrow();
+        if !mirror_in_step(&state, &buffer) {
+            return;
+        }
+        let (start, end) = buffer.selection_bounds().unwrap_or_else(|| {
+            let cursor = buffer.iter_at_mark(&buffer.get_insert());
+            (cursor, cursor)
+        });
+        let Some(range) = pick(&state.text_buffer, state.text_buffer.byte_offset(start.offset() as usize)..state.text_buffer.byte_offset(end.offset() as usize)) else {
+            return;
+        };
+        (iter_at_mirror_byte(&buffer, &state.text_buffer, range.start), iter_at_mirror_byte(&buffer, &state.text_buffer, range.end))
+    };
+    buffer.select_range(&end, &start);
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+}
+
+// Edit -> Line Operations: sort, deduplicate, reverse or join the lines of the selection,
+// or of the whole text, with whether sorting ignores case and goes by numbers in `sort_options`
+fn show_line_operations(button: &gtk::Button, edit_menu: &gtk::PopoverMenu, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, sort_options: &Rc<Cell<(bool, bool)>>) {
+    let popover = gtk::Popover::new();
+    popover.set_parent(button);
+    popover.set_position(gtk::PositionType::Right);
+    popover.connect_closed(|popover| popover.unparent());
+
+    let operations_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    operations_box.set_margin_top(2);
+    operations_box.set_margin_bottom(2);
+    operations_box.set_margin_start(2);
+    operations_box.set_margin_end(2);
+
+    let (ignore_case, numeric) = sort_options.get();
+    let ignore_case_button = gtk::CheckButton::with_label("Ignore Case");
+    ignore_case_button.set_active(ignore_case);
+    let numeric_button = gtk::CheckButton::with_label("Numeric");
+    numeric_button.set_active(numeric);
+    for check_button in [&ignore_case_button, &numeric_button] {
+        let (ignore_case_button, numeric_button) = (ignore_case_button.clone(), numeric_button.clone());
+        let sort_options = sort_options.clone();
+        check_button.connect_toggled(move |_| sort_options.set((ignore_case_button.is_active(), numeric_button.is_active())));
+    }
+
+    let operations = [
+        ("Sort Ascending", LineOperation::Sort { descending: false, ignore_case, numeric }),
+        ("Sort Descending", LineOperation::Sort { descending: true, ignore_case, numeric }),
+        ("Remove Duplicate Lines", LineOperation::RemoveDuplicates),
+        ("Reverse Lines", LineOperation::Reverse),
+        ("Join Lines", LineOperation::Join),
+    ];
+    for (index, (label, operation)) in operations.into_iter().enumerate() {
+        let operation_button = gtk::Button::with_label(label);
+        operation_button.set_has_frame(false);
+        operation_button.set_hexpand(true);
+        operation_button.set_halign(gtk::Align::Start);
+        let popover_ref = popover.clone();
+        let edit_menu = edit_menu.clone();
+        let text_view = text_view.clone();
+        let state = state.clone();
+        let sort_options = sort_options.clone();
+        operation_button.connect_clicked(move |_| {
+            popover_ref.popdown();
+            edit_menu.popdown();
+            // Sorting goes by the options as they are when it is picked
+            let (ignore_case, numeric) = sort_options.get();
+            let operation = match operation {
+                LineOperation::Sort { descending, .. } => LineOperation::Sort { descending, ignore_case, numeric },
+                operation => operation,
+            };
+            apply_line_operation(&text_view, &state, operation);
+        });
+        operations_box.append(&operation_button);
+        // The sort options go right below the sort commands
+        if index == 1 {
+            operations_box.append(&ignore_case_button);
+            operations_box.append(&numeric_button);
+            let separator = gtk::Separator::new(gtk::Orientation::Horizontal);
+            separator.set_margin_top(2);
+            separator.set_margin_bottom(2);
+            operations_box.append(&separator);
+        }
+    }
+
+    popover.set_child(Some(&operations_box));
+    popover.popup();
+}
+
+// Rewrite lines as one undoable step, leaving the rewritten lines selected
+fn apply_line_operation(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, operation: LineOperation) {
+    if !text_view.is_editable() {
+        return;
+    }
+    let buffer = text_view.buffer();
+    let planned = {
+        let state = state.borrow();
+        if !mirror_in_step(&state, &buffer) {
+            return;
+        }
+        let (start, end) = buffer.selection_bounds().unwrap_or_else(|| {
+            let cursor = buffer.iter_at_mark(&buffer.get_insert());
+            (cursor, cursor)
+        });
+        let selection = state.text_buffer.byte_offset(start.offset() as usize)..state.text_buffer.byte_offset(end.offset() as usize);
+        state.text_buffer.line_operation(selection, operation).map(|(span, replacement)| {
+            let char_span = state.text_buffer.char_index(span.start) as i32..state.text_buffer.char_index(span.end) as i32;
+            (char_span.start, char_span.end, replacement)
+        })
+    };
+    let Some((start, end, replacement)) = planned else {
+        return;
+    };
+
+    buffer.begin_user_action();
+    let mut start_iter = buffer.iter_at_offset(start);
+    let mut end_iter = buffer.iter_at_offset(end);
+    buffer.delete(&mut start_iter, &mut end_iter);
+    buffer.insert(&mut start_iter, &replacement);
+    buffer.end_user_action();
+    let end = start + replacement.chars().count() as i32;
+    buffer.select_range(&buffer.iter_at_offset(end), &buffer.iter_at_offset(start));
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+}
+
+// Switch the shown tab to another line ending style; the text is rewritten when it is saved
+fn convert_line_endings(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label, line_ending: LineEnding) {
+    if let Ok(mut state) = state.try_borrow_mut() {
+        state.line_ending = line_ending;
+        state.refresh_modified();
+    }
+    update_status_bar(status_label, &text_view.buffer(), state);
+}
+
+// Status bar button showing the tab's line endings, with a popover to convert them
+fn create_line_ending_selector(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label) -> gtk::MenuButton {
+    let selector = gtk::MenuButton::new();
+    selector.set_label(LineEnding::Lf.label());
+    selector.set_css_classes(&["line-ending-selector"]);
+    selector.set_tooltip_text(Some("Line endings of this tab"));
+
+    let popover = gtk::Popover::new();
+    let choices_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    for line_ending in LineEnding::ALL {
+        let button = gtk::Button::with_label(&format!("Convert to {}", line_ending.description()));
+        button.set_has_frame(false);
+        button.set_halign(gtk::Align::Start);
+
+        let text_view_ref = text_view.clone();
+        let state_ref = state.clone();
+        let status_label_ref = status_label.clone();
+        let popover_ref = popover.clone();
+        button.connect_clicked(move |_| {
+            popover_ref.popdown();
+            convert_line_endings(&text_view_ref, &state_ref, &status_label_ref, line_ending);
+        });
+        choices_box.append(&button);
+    }
+    popover.set_child(Some(&choices_box));
+    selector.set_popover(Some(&popover));
+
+    LINE_ENDING_SELECTOR.with(|cell| *cell.borrow_mut() = Some(selector.clone()));
+    selector
+}
+
+fn update_line_ending_selector(state: &Rc<RefCell<EditorState>>) {
+    let label = match state.try_borrow() {
+        Ok(state) => state.line_ending.label(),
+        Err(_) => return,
+    };
+    LINE_ENDING_SELECTOR.with(|cell| {
+        if let Some(selector) = cell.borrow().as_ref() {
+            if selector.label().as_deref() != Some(label) {
+                selector.set_label(label);
+            }
+        }
+    });
+}
+
+thread_local! {
+    static ENCODING_SELECTOR: RefCell<Option<gtk::MenuButton>> = const { RefCell::new(None) };
+    static WARNING_BAR: RefCell<Option<(gtk::Box, gtk::Label)>> = const { RefCell::new(None) };
+}
+
+// A byte count in the largest unit that keeps it at one or more
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn encoding_label(encoding: &'static Encoding, bom: bool) -> String {
+    if bom {
+        format!("{} BOM", encoding.name())
+    } else {
+        encoding.name().to_string()
+    }
+}
+
+// Status bar button showing the tab's encoding, with a popover to reopen or save in another one
+fn create_encoding_selector(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label, save_button: &gtk::Button) -> gtk::MenuButton {
+    let selector = gtk::MenuButton::new();
+    selector.set_label(&encoding_label(encoding_rs::UTF_8, false));
+    selector.set_css_classes(&["encoding-selector"]);
+    selector.set_tooltip_text(Some("Character encoding of this tab"));
+
+    let names: Vec<&str> = encoding::CHOICES.iter().map(|encoding| encoding.name()).collect();
+    let choices = gtk::DropDown::from_strings(&names);
+    let bom_check = gtk::CheckButton::with_label("Byte order mark");
+    let reopen_button = gtk::Button::with_label("Reopen");
+    reopen_button.set_tooltip_text(Some("Read the file again in the chosen encoding"));
+    let save_with_button = gtk::Button::with_label("Save");
+    save_with_button.set_tooltip_text(Some("Save the file in the chosen encoding"));
+
+    let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    buttons.set_homogeneous(true);
+    buttons.append(&reopen_button);
+    buttons.append(&save_with_button);
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 8);
+    content.set_margin_start(8);
+    content.set_margin_end(8);
+    content.set_margin_top(8);
+    content.set_margin_bottom(8);
+    content.append(&choices);
+    content.append(&bom_check);
+    content.append(&buttons);
+
+    let popover = gtk::Popover::new();
+    popover.set_child(Some(&content));
+    selector.set_popover(Some(&popover));
+
+    // Only UTF encodings have a byte order mark
+    let bom_check_ref = bom_check.clone();
+    choices.connect_selected_notify(move |choices| {
+        let is_unicode = encoding::CHOICES
+            .get(choices.selected() as usize)
+            .is_some_and(|encoding| *encoding == encoding_rs::UTF_8 || encoding.name().starts_with("UTF-16"));
+        bom_check_ref.set_sensitive(is_unicode);
+    });
+
+    // Start from the tab's current settings each time the popover opens
+    let state_ref = state.clone();
+    let choices_ref = choices.clone();
+    let bom_check_ref = bom_check.clone();
+    let reopen_button_ref = reopen_button.clone();
+    popover.connect_show(move |_| {
+        if let Ok(state) = state_ref.try_borrow() {
+            let index = encoding::CHOICES.iter().position(|encoding| *encoding == state.encoding).unwrap_or(0);
+            choices_ref.set_selected(index as u32);
+            bom_check_ref.set_active(state.write_bom);
+            // Reopening would throw away unsaved edits
+            reopen_button_ref.set_sensitive(state.current_file.is_some() && !state.is_modified);
+        }
+    });
+
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    let status_label_ref = status_label.clone();
+    let choices_ref = choices.clone();
+    let popover_ref = popover.clone();
+    reopen_button.connect_clicked(move |_| {
+        popover_ref.popdown();
+        let Some(&chosen) = encoding::CHOICES.get(choices_ref.selected() as usize) else {
+            return;
+        };
+        let path = state_ref.try_borrow().ok().and_then(|state| state.current_file.clone());
+        if let Some(path) = path {
+            reopen_with_encoding(&window_ref, &text_view_ref, &state_ref, &status_label_ref, path, chosen);
+        }
+    });
+
+    let state_ref = state.clone();
+    let choices_ref = choices.clone();
+    let bom_check_ref = bom_check.clone();
+    let popover_ref = popover.clone();
+    let save_button_ref = save_button.clone();
+    save_with_button.connect_clicked(move |_| {
+        popover_ref.popdown();
+        let Some(&chosen) = encoding::CHOICES.get(choices_ref.selected() as usize) else {
+            return;
+        };
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            state.encoding = chosen;
+            state.write_bom = bom_check_ref.is_active() && bom_check_ref.is_sensitive();
+        }
+        update_encoding_selector(&state_ref);
+        // The regular Save path asks for a file name when the tab has none
+        save_button_ref.emit_clicked();
+    });
+
+    ENCODING_SELECTOR.with(|cell| *cell.borrow_mut() = Some(selector.clone()));
+    selector
+}
+
+fn update_encoding_selector(state: &Rc<RefCell<EditorState>>) {
+    let label = match state.try_borrow() {
+        Ok(state) => encoding_label(state.encoding, state.write_bom),
+        Err(_) => return,
+    };
+    ENCODING_SELECTOR.with(|cell| {
+        if let Some(selector) = cell.borrow().as_ref() {
+            if selector.label().as_deref() != Some(label.as_str()) {
+                selector.set_label(&label);
+            }
+        }
+    });
+}
+
+// File reads and writes still running after this show in the status bar
+const FILE_JOB_INDICATOR_DELAY: Duration = Duration::from_millis(300);
+
+// A file read or write, or a budgeted job such as a `cargo check`, running on a worker thread
+struct FileJob {
+    id: u64,
+    description: String,
+    cancellable: gio::Cancellable,
+    // Running past FILE_JOB_INDICATOR_DELAY, so shown in the status bar
+    slow: bool,
+    // Closing the window waits for file reads and writes, not for budgeted jobs
+    file_io: bool,
+}
+
+// Status bar spinner naming the slow file jobs, with a button cancelling them
+struct FileJobIndicator {
+    container: gtk::Box,
+    spinner: gtk::Spinner,
+    label: gtk::Label,
+}
+
+thread_local! {
+    static FILE_JOBS: RefCell<Vec<FileJob>> = const { RefCell::new(Vec::new()) };
+    static NEXT_FILE_JOB: Cell<u64> = const { Cell::new(0) };
+    static FILE_JOB_INDICATOR: RefCell<Option<FileJobIndicator>> = const { RefCell::new(None) };
+}
+
+fn create_file_job_indicator() -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    container.set_visible(false);
+
+    let spinner = gtk::Spinner::new();
+    container.append(&spinner);
+
+    let label = gtk::Label::new(None);
+    label.set_css_classes(&["status-label"]);
+    label.set_ellipsize(pango::EllipsizeMode::Middle);
+    label.set_max_width_chars(40);
+    container.append(&label);
+
+    let cancel_button = gtk::Button::from_icon_name("process-stop-symbolic");
+    cancel_button.set_has_frame(false);
+    cancel_button.set_tooltip_text(Some("Cancel"));
+    cancel_button.connect_clicked(|_| {
+        FILE_JOBS.with(|jobs| {
+            for job in jobs.borrow().iter().filter(|job| job.slow) {
+                job.cancellable.cancel();
+            }
+        });
+    });
+    container.append(&cancel_button);
+
+    FILE_JOB_INDICATOR.with(|cell| *cell.borrow_mut() = Some(FileJobIndicator {
+        container: container.clone(),
+        spinner,
+        label,
+    }));
+    container
+}
+
+// Name the latest slow job, and how many others there are, or hide the indicator
+fn update_file_job_indicator() {
+    let (latest, others) = FILE_JOBS.with(|jobs| {
+        let jobs = jobs.borrow();
+        let slow: Vec<&FileJob> = jobs.iter().filter(|job| job.slow).collect();
+        (slow.last().map(|job| job.description.clone()), slow.len().saturating_sub(1))
+    });
+    FILE_JOB_INDICATOR.with(|cell| {
+        let cell = cell.borrow();
+        let Some(indicator) = cell.as_ref() else {
+            return;
+        };
+        match latest {
+            Some(description) => {
+                if others > 0 {
+                    indicator.label.set_text(&format!("{} (and {} more)", description, others));
+                } else {
+                    indicator.label.set_text(&description);
+                }
+                indicator.spinner.start();
+                indicator.container.set_visible(true);
+            }
+            None => {
+                indicator.spinner.stop();
+                indicator.container.set_visible(false);
+            }
+        }
+    });
+}
+
+// Run `work` on a worker thread, so slow disks and network mounts do not freeze the window,
+// and hand its result to `done` back on the main thread; `None` if the user cancelled it.
+// A job running past FILE_JOB_INDICATOR_DELAY shows `description` in the status bar, with
+// a button that cancels the cancellable `work` is given.
+fn run_file_job<T: Send + 'static>(
+    description: String,
+    work: impl FnOnce(&gio::Cancellable) -> T + Send + 'static,
+    done: impl FnOnce(Option<T>) + 'static,
+) {
+    start_job(description, None, work, done);
+}
+
+// Like run_file_job, for work that is not file I/O and must not run away: once it has run
+// for `budget` it is cancelled, and the warning bar says so. `work` has to give up when
+// its cancellable is cancelled.
+fn run_budgeted_job<T: Send + 'static>(
+    description: String,
+    budget: Duration,
+    work: impl FnOnce(&gio::Cancellable) -> T + Send + 'static,
+    done: impl FnOnce(Option<T>) + 'static,
+) {
+    start_job(description, Some(budget), work, done);
+}
+
+fn start_job<T: Send + 'static>(
+    description: String,
+    budget: Option<Duration>,
+    work: impl FnOnce(&gio::Cancellable) -> T + Send + 'static,
+    done: impl FnOnce(Option<T>) + 'static,
+) {
+    let id = NEXT_FILE_JOB.with(|next| next.replace(next.get() + 1));
+    let cancellable = gio::Cancellable::new();
+    if let Some(budget) = budget {
+        let description = description.clone();
+        glib::timeout_add_local_once(budget, move || {
+            let running = FILE_JOBS.with(|jobs| jobs.borrow().iter().find(|job| job.id == id).map(|job| job.cancellable.clone()));
+            if let Some(cancellable) = running.filter(|cancellable| !cancellable.is_cancelled()) {
+                cancellable.cancel();
+                let description = description.trim_end_matches('…');
+                warn!("{} ran past its budget of {:?} and was stopped", description, budget);
+                show_warning_bar(&format!("{} took longer than {} seconds and was stopped.", description, budget.as_secs()));
+            }
+        });
+    }
+    FILE_JOBS.with(|jobs| jobs.borrow_mut().push(FileJob {
+        id,
+        description,
+        cancellable: cancellable.clone(),
+        slow: false,
+        file_io: budget.is_none(),
+    }));
+    glib::timeout_add_local_once(FILE_JOB_INDICATOR_DELAY, move || {
+        FILE_JOBS.with(|jobs| {
+            if let Some(job) = jobs.borrow_mut().iter_mut().find(|job| job.id == id) {
+                job.slow = true;
+            }
+        });
+        update_file_job_indicator();
+    });
+
+    glib::spawn_future_local(async move {
+        let worker_cancellable = cancellable.clone();
+        let result = gio::spawn_blocking(move || work(&worker_cancellable)).await;
+        FILE_JOBS.with(|jobs| jobs.borrow_mut().retain(|job| job.id != id));
+        update_file_job_indicator();
+        match result {
+            Ok(result) if !cancellable.is_cancelled() => done(Some(result)),
+            Ok(_) => done(None),
+            Err(_) => {
+                error!("File I/O thread panicked");
+                done(None);
+            }
+        }
+    });
+}
+
+fn file_jobs_running() -> bool {
+    FILE_JOBS.with(|jobs| jobs.borrow().iter().any(|job| job.file_io))
+}
+
+// Dismissable bar above the editor for problems that should not go unnoticed
+fn create_warning_bar() -> gtk::Box {
+    let bar = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    bar.set_css_classes(&["warning-bar"]);
+    bar.set_visible(false);
+
+    let label = gtk::Label::new(None);
+    label.set_halign(gtk::Align::Start);
+    label.set_hexpand(true);
+    label.set_wrap(true);
+    bar.append(&label);
+
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Dismiss"));
+    close_button.connect_clicked(|_| hide_warning_bar());
+    bar.append(&close_button);
+
+    WARNING_BAR.with(|cell| *cell.borrow_mut() = Some((bar.clone(), label)));
+    bar
+}
+
+fn show_warning_bar(message: &str) {
+    WARNING_BAR.with(|cell| {
+        if let Some((bar, label)) = cell.borrow().as_ref() {
+            label.set_text(message);
+            bar.set_visible(true);
+        }
+    });
+}
+
+thread_local! {
+    // Bar above the editor saying why the shown tab is read-only
+    static READ_ONLY_BAR: RefCell<Option<(gtk::Box, gtk::Label)>> = const { RefCell::new(None) };
+}
+
+// Bar shown while the shown tab is read-only, with a button that allows editing it
+fn create_read_only_bar(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) -> gtk::Box {
+    let bar = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    bar.set_css_classes(&["warning-bar"]);
+    bar.set_visible(false);
+
+    let icon = gtk::Image::from_icon_name("changes-prevent-symbolic");
+    bar.append(&icon);
+    let label = gtk::Label::new(None);
+    label.set_halign(gtk::Align::Start);
+    label.set_hexpand(true);
+    label.set_wrap(true);
+    bar.append(&label);
+
+    let edit_button = gtk::Button::with_label("Allow Editing");
+    let text_view = text_view.clone();
+    let state = state.clone();
+    edit_button.connect_clicked(move |_| toggle_read_only(&text_view, &state));
+    bar.append(&edit_button);
+
+    READ_ONLY_BAR.with(|cell| *cell.borrow_mut() = Some((bar.clone(), label)));
+    bar
+}
+
+// Make the shown tab editable or not as its state says, with its lock and the read-only bar
+fn show_read_only(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let Ok((read_only, file)) = state.try_borrow().map(|state| (state.read_only, state.current_file.clone())) else {
+        return;
+    };
+    text_view.set_editable(!read_only);
+    let buffer = text_view.buffer();
+    TABS.with(|tabs| {
+        if let Some(tabs) = tabs.borrow().as_ref() {
+            tabs.borrow().show_lock(&buffer, read_only);
+        }
+    });
+
+    let message = match &file {
+        Some(path) if !FileAccess::current().can_write(path) => {
+            "You do not have permission to change this file. Edits can be saved as administrator or to another file."
+        }
+        _ => "This tab is read-only.",
+    };
+    READ_ONLY_BAR.with(|cell| {
+        if let Some((bar, label)) = cell.borrow().as_ref() {
+            label.set_text(message);
+            bar.set_visible(read_only);
+        }
+    });
+}
+
+// Edit > Toggle Read-Only: stop or allow editing the shown tab
+fn toggle_read_only(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    if let Ok(mut state) = state.try_borrow_mut() {
+        state.read_only = !state.read_only;
+    }
+    show_read_only(text_view, state);
+}
+
+// What went wrong with a file, in terms the user can act on
+fn describe_file_error(error: &anyhow::Error) -> String {
+    let Some(io_error) = error.downcast_ref::<std::io::Error>() else {
+        return format!("{}.", error);
+    };
+    match io_error.kind() {
+        std::io::ErrorKind::PermissionDenied => "You do not have permission to access this file or its folder.".to_string(),
+        std::io::ErrorKind::NotFound => "The file or the folder it is in no longer exists.".to_string(),
+        std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => "There is not enough space left on the disk.".to_string(),
+        std::io::ErrorKind::ReadOnlyFilesystem => "The disk is mounted read-only.".to_string(),
+        std::io::ErrorKind::IsADirectory => "This is a folder, not a file.".to_string(),
+        _ => format!("{}.", io_error),
+    }
+}
+
+// Explain a failed open or save in a dialog. `retry`, `save_as` and `as_administrator`
+// add buttons that run once the dialog is gone; saving as administrator is the default
+// when offered, since retrying will not get past a lack of permission.
+fn show_file_error(
+    window: &gtk::ApplicationWindow,
+    message: &str,
+    error: &anyhow::Error,
+    retry: Option<Rc<dyn Fn()>>,
+    save_as: Option<Rc<dyn Fn()>>,
+    as_administrator: Option<Rc<dyn Fn()>>,
+) {
+    error!("{}: {}", message, error);
+
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Error,
+        gtk::ButtonsType::None,
+        message,
+    );
+    dialog.set_secondary_text(Some(&describe_file_error(error)));
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    if save_as.is_some() {
+        dialog.add_button("Save As…", gtk::ResponseType::Apply);
+    }
+    if retry.is_some() {
+        dialog.add_button("Retry", gtk::ResponseType::Accept);
+        dialog.set_default_response(gtk::ResponseType::Accept);
+    }
+    if as_administrator.is_some() {
+        dialog.add_button("Save as Administrator", gtk::ResponseType::Yes);
+        dialog.set_default_response(gtk::ResponseType::Yes);
+    }
+
+    dialog.connect_response(move |dialog, response| {
+        dialog.destroy();
+        let action = match response {
+            gtk::ResponseType::Accept => retry.as_ref(),
+            gtk::ResponseType::Apply => save_as.as_ref(),
+            gtk::ResponseType::Yes => as_administrator.as_ref(),
+            _ => None,
+        };
+        if let Some(action) = action {
+            action();
+        }
+    });
+    dialog.show();
+}
+
+// Name of the file at `path` as shown in messages
+fn display_file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+fn hide_warning_bar() {
+    WARNING_BAR.with(|cell| {
+        if let Some((bar, _)) = cell.borrow().as_ref() {
+            bar.set_visible(false);
+        }
+    });
+}
+
+// Tell the user when a file's encoding was guessed or bytes were lost while decoding it
+fn show_decoding_warning(path: &Path, decoded: &DecodedText) {
+    let file_name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string());
+    match decoded.detection {
+        Detection::Exact => hide_warning_bar(),
+        Detection::Guessed => show_warning_bar(&format!(
+            "\"{}\" is not valid UTF-8 and was opened as {}. If the text looks wrong, reopen it in another encoding from the status bar.",
+            file_name,
+            decoded.encoding.name(),
+        )),
+        Detection::Lossy => show_warning_bar(&format!(
+            "\"{}\" contains bytes that are not valid {} and were replaced with \u{FFFD}. Saving will keep the replacements.",
+            file_name,
+            decoded.encoding.name(),
+        )),
+    }
+}
+
+// Regex, case and whole-word toggles shared by the Find and Replace dialogs
+struct SearchOptionsRow {
+    regex: gtk::CheckButton,
+    case_sensitive: gtk::CheckButton,
+    whole_word: gtk::CheckButton,
+    error: gtk::Label,
+}
+
+impl SearchOptionsRow {
+    // Attach the toggles to `grid` at `row`, with an error line below them
+    fn attach(grid: &gtk::Grid, row: i32) -> Self {
+        let regex = gtk::CheckButton::with_label("Regular expression");
+        let case_sensitive = gtk::CheckButton::with_label("Match case");
+        let whole_word = gtk::CheckButton::with_label("Whole word");
+        let toggles = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        toggles.append(&regex);
+        toggles.append(&case_sensitive);
+        toggles.append(&whole_word);
+        grid.attach(&toggles, 0, row, 2, 1);
+
+        let error = gtk::Label::new(None);
+        error.set_halign(gtk::Align::Start);
+        error.set_wrap(true);
+        error.set_css_classes(&["error"]);
+        error.set_visible(false);
+        grid.attach(&error, 0, row + 1, 2, 1);
+
+        Self { regex, case_sensitive, whole_word, error }
+    }
+
+    fn query(&self, pattern: &str) -> search::SearchQuery {
+        self.error.set_visible(false);
+        search::SearchQuery {
+            pattern: pattern.to_string(),
+            regex: self.regex.is_active(),
+            case_sensitive: self.case_sensitive.is_active(),
+            whole_word: self.whole_word.is_active(),
+        }
+    }
+
+    fn show_error(&self, error: &regex::Error) {
+        self.error.set_text(&format!("Invalid regular expression: {}", error));
+        self.error.set_visible(true);
+    }
+}
+
+// Byte offset of the character at `char_offset` in `text`
+fn byte_offset_of(text: &str, char_offset: i32) -> usize {
+    text.char_indices()
+        .nth(char_offset.max(0) as usize)
+        .map_or(text.len(), |(byte, _)| byte)
+}
+
+fn iter_at_byte(buffer: &gtk::TextBuffer, text: &str, byte: usize) -> gtk::TextIter {
+    buffer.iter_at_offset(text[..byte].chars().count() as i32)
+}
+
+// Whether the mirror holds the view's text. It lags while an edit is under way, for handlers
+// of signals such as mark-set that run before the changed signal brings it up to date.
+fn mirror_in_step(state: &EditorState, buffer: &gtk::TextBuffer) -> bool {
+    state.text_buffer.char_count() == buffer.char_count() as usize
+}
+
+// Where byte `offset` of the mirror is in the view's buffer, which holds the same text
+fn iter_at_mirror_byte(buffer: &gtk::TextBuffer, mirror: &EditorBuffer, offset: usize) -> gtk::TextIter {
+    buffer.iter_at_offset(mirror.char_index(offset) as i32)
+}
+
+// Character offsets of the ordered, non-overlapping byte ranges of `text`, counted in one pass
+fn char_ranges(text: &str, ranges: &[Range<usize>]) -> Vec<Range<i32>> {
+    let (mut byte, mut chars) = (0, 0);
+    ranges
+        .iter()
+        .map(|range| {
+            let start = chars + text[byte..range.start].chars().count();
+            chars = start + text[range.clone()].chars().count();
+            byte = range.end;
+            start as i32..chars as i32
+        })
+        .collect()
+}
+
+fn get_cursor_position(buffer: &gtk::TextBuffer) -> (u32, u32) {
+    if let Some(mark) = buffer.mark("insert") {
+        let iter = buffer.iter_at_mark(&mark);
+        return ((iter.line() + 1) as u32, (iter.line_offset() + 1) as u32);
+    }
+    (1, 1)
+}
+
+// Longest a syntax highlighting pass may keep the window waiting before it is given up
+const HIGHLIGHT_BUDGET: Duration = Duration::from_secs(2);
+// Tags of the syntax pass, which the highlight cache keeps
+const CACHED_SYNTAX_TAGS: [&str; 5] = ["keyword", "type", "string", "comment", "error"];
+// How long a large saved text has to stay as it is before its tags are cached
+const HIGHLIGHT_CACHE_DELAY: Duration = Duration::from_secs(2);
+
+thread_local! {
+    // Pending write of the shown text's tags to the highlight cache
+    static HIGHLIGHT_CACHE_TIMEOUT: RefCell<Option<glib::SourceId>> = const { RefCell::new(None) };
+}
+
+// Syntax highlighting for the shown tab. A large text that was highlighted before, like a
+// file opened again unchanged, takes its tags from the cache on disk instead of a full pass.
+fn highlight_shown_buffer(buffer: &gtk::TextBuffer, language: Option<&'static language::Language>, state: &Rc<RefCell<EditorState>>) {
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+    let Some(language) = language.filter(|_| text.len() >= highlight_cache::MIN_TEXT_LEN) else {
+        if !apply_syntax_highlighting(buffer, language) {
+            if let Ok(mut state) = state.try_borrow_mut() {
+                stop_highlighting(&mut state);
+            }
+        }
+        return;
+    };
+
+    let hash = content_hash([text.as_str()]);
+    let Some(spans) = highlight_cache::load(hash, text.len(), language.name) else {
+        if apply_syntax_highlighting(buffer, Some(language)) {
+            schedule_highlight_cache_write(buffer, state, hash, text.len(), language.name);
+        } else {
+            if let Ok(mut state) = state.try_borrow_mut() {
+                stop_highlighting(&mut state);
+            }
+        }
+        return;
+    };
+    debug!("Highlighting {} from the cache", language.name);
+    buffer.remove_all_tags(&buffer.start_iter(), &buffer.end_iter());
+    for (name, ranges) in &spans {
+        for range in ranges {
+            buffer.apply_tag_by_name(name, &buffer.iter_at_offset(range.start), &buffer.iter_at_offset(range.end));
+        }
+    }
+    // The panel lists the brackets the cached error tags mark
+    let problems = if language.syntax_checks { brackets::find_unmatched_brackets(&text) } else { Vec::new() };
+    update_bracket_panel(buffer, &problems);
+}
+
+// Turn highlighting off for a tab after a pass ran past HIGHLIGHT_BUDGET, so typing does
+// not wait for the next one
+fn stop_highlighting(state: &mut EditorState) {
+    state.syntax_highlighting = false;
+    warn!("Highlighting {} ran past its budget and was turned off", state.tab_title());
+    show_warning_bar(&format!(
+        "Syntax highlighting took longer than {} seconds and was turned off for this tab.",
+        HIGHLIGHT_BUDGET.as_secs()
+    ));
+}
+
+// Cache the tags of `buffer` once it has shown the saved text with `hash` for a while, so
+// opening the file again can skip the full pass
+fn schedule_highlight_cache_write(buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>, hash: u64, len: usize, language: &'static str) {
+    let buffer = buffer.clone();
+    let state = state.clone();
+    let source_id = glib::timeout_add_local_once(HIGHLIGHT_CACHE_DELAY, move || {
+        HIGHLIGHT_CACHE_TIMEOUT.with(|timeout| timeout.borrow_mut().take());
+        let saved = state.try_borrow().is_ok_and(|state| !state.is_modified && state.last_saved_hash == hash);
+        if !saved || content_hash([buffer.text(&buffer.start_iter(), &buffer.end_iter(), false).as_str()]) != hash {
+            return;
+        }
+        let tag_table = buffer.tag_table();
+        let spans: Vec<(String, Vec<Range<i32>>)> = CACHED_SYNTAX_TAGS
+            .iter()
+            .filter_map(|name| Some((name.to_string(), tag_offsets(&buffer, &tag_table.lookup(name)?))))
+            .collect();
+        std::thread::spawn(move || {
+            if let Err(e) = highlight_cache::store(hash, len, language, spans) {
+                warn!("Could not cache highlighting: {}", e);
+            }
+        });
+    });
+    HIGHLIGHT_CACHE_TIMEOUT.with(|timeout| {
+        if let Some(previous) = timeout.borrow_mut().replace(source_id) {
+            previous.remove();
+        }
+    });
+}
+
+// Character ranges `tag` covers in `buffer`
+fn tag_offsets(buffer: &gtk::TextBuffer, tag: &gtk::TextTag) -> Vec<Range<i32>> {
+    let mut ranges = Vec::new();
+    let mut iter = buffer.start_iter();
+    while iter.starts_tag(Some(tag)) || iter.forward_to_tag_toggle(Some(tag)) {
+        let start = iter.offset();
+        iter.forward_to_tag_toggle(Some(tag));
+        ranges.push(start..iter.offset());
+        if iter.is_end() {
+            break;
+        }
+    }
+    ranges
+}
+
+// Highlight the whole of `buffer` in `language`. Gives up after HIGHLIGHT_BUDGET, leaving
+// the text without tags, and returns false then.
+fn apply_syntax_highlighting(buffer: &gtk::TextBuffer, language: Option<&language::Language>) -> bool {
+    // Clear existing tags
+    buffer.remove_all_tags(&buffer.start_iter(), &buffer.end_iter());
+    let deadline = Instant::now() + HIGHLIGHT_BUDGET;
+    let give_up = || {
+        buffer.remove_all_tags(&buffer.start_iter(), &buffer.end_iter());
+        false
+    };
+    
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+    let content = text.as_str();
     
-    // Add the tab to tabs box
-    tabs_box.append(&tab_button_wrapper);
+    // Plain text gets no highlighting and no syntax checks
+    let Some(language) = language else {
+        update_bracket_panel(buffer, &[]);
+        return true;
+    };
     
-    // Create a "+" button to add new tabs with modern styling
-    let new_tab_button = gtk::Button::new();
-    new_tab_button.set_icon_name("list-add-symbolic");
-    new_tab_button.set_tooltip_text(Some("New Tab"));
-    new_tab_button.set_css_classes(&["new-tab-button"]);
+    // Apply keyword highlighting
+    for keyword in language.keywords {
+        let mut start_search = buffer.start_iter();
+        while let Some((match_start, match_end)) = start_search.forward_search(
+            keyword,
+            gtk::TextSearchFlags::CASE_INSENSITIVE,
+            None,
+        ) {
+            if Instant::now() > deadline {
+                return give_up();
+            }
+            // Only highlight if it's a whole word
+            if is_word_boundary(&match_start, true) && is_word_boundary(&match_end, false) {
+                buffer.apply_tag_by_name("keyword", &match_start, &match_end);
+            }
+            start_search = match_end;
+        }
+    }
     
-    // Add the new tab button after the first tab
-    tabs_box.append(&new_tab_button);
+    // Apply type highlighting
+    for type_name in language.types {
+        let mut start_search = buffer.start_iter();
+        while let Some((match_start, match_end)) = start_search.forward_search(
+            type_name,
+            gtk::TextSearchFlags::CASE_INSENSITIVE,
+            None,
+        ) {
+            if Instant::now() > deadline {
+                return give_up();
+            }
+            // Only highlight if it's a whole word
+            if is_word_boundary(&match_start, true) && is_word_boundary(&match_end, false) {
+                buffer.apply_tag_by_name("type", &match_start, &match_end);
+            }
+            start_search = match_end;
+        }
+    }
     
-    // Connect the initial tab to activate it when clicked
+    // Highlight strings, which end at the same quote character they started with
+    let mut open_quote: Option<char> = None;
+    let mut string_start = buffer.start_iter();
+    
+    let mut start_search = buffer.start_iter();
+    while !start_search.is_end() {
+        let ch = start_search.char();
+        start_search.forward_char();
+        if ch == '\n' && Instant::now() > deadline {
+            return give_up();
+        }
+        
+        match open_quote {
+            // Skip whatever an escaping backslash protects
+            Some(_) if ch == '\\' => {
+                start_search.forward_char();
+            }
+            Some(quote) if ch == quote => {
+                buffer.apply_tag_by_name("string", &string_start, &start_search);
+                open_quote = None;
+            }
+            None if language.string_quotes.contains(&ch) => {
+                string_start = start_search;
+                string_start.backward_char();
+                open_quote = Some(ch);
+            }
+            _ => {}
+        }
+    }
+    
+    // Highlight line comments
+    if let Some(marker) = language.line_comment {
+        let mut start_search = buffer.start_iter();
+        while let Some((comment_start, _)) = start_search.forward_search(
+            marker,
+            gtk::TextSearchFlags::CASE_INSENSITIVE,
+            None,
+        ) {
+            if Instant::now() > deadline {
+                return give_up();
+            }
+            let mut line_end = comment_start;
+            line_end.forward_to_line_end();
+            
+            buffer.apply_tag_by_name("comment", &comment_start, &line_end);
+            start_search = line_end;
+        }
+    }
+    
+    // Block comments
+    if let Some((open, close)) = language.block_comment {
+        let mut start_search = buffer.start_iter();
+        while let Some((block_start, _)) = start_search.forward_search(
+            open,
+            gtk::TextSearchFlags::CASE_INSENSITIVE,
+            None,
+        ) {
+            if Instant::now() > deadline {
+                return give_up();
+            }
+            if let Some((_, block_end)) = block_start.forward_search(
+                close,
+                gtk::TextSearchFlags::CASE_INSENSITIVE,
+                None,
+            ) {
+                buffer.apply_tag_by_name("comment", &block_start, &block_end);
+                start_search = block_end;
+            } else {
+                break;
+            }
+        }
+    }
+    
+    // Detect simple syntax errors
+    if language.syntax_checks {
+        check_for_errors(buffer, content);
+    } else {
+        update_bracket_panel(buffer, &[]);
+    }
+    true
+}
+
+fn is_word_boundary(iter: &gtk::TextIter, is_start: bool) -> bool {
+    if is_start {
+        iter.starts_word() || iter.starts_line() || {
+            let mut temp = iter.clone();
+            if temp.backward_char() {
+                !temp.char().is_alphanumeric()
+            } else {
+                true
+            }
+        }
+    } else {
+        iter.ends_word() || iter.ends_line() || !iter.char().is_alphanumeric()
+    }
+}
+
+// Bracket at the cursor, or else just before it, and its partner as found by the buffer
+// layer: `Some(None)` for a bracket without a partner
+fn bracket_at_cursor(buffer: &gtk::TextBuffer, state: &EditorState) -> Option<(gtk::TextIter, Option<gtk::TextIter>)> {
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut before = cursor;
+    let candidates = if before.backward_char() { vec![cursor, before] } else { vec![cursor] };
+    candidates.into_iter().find_map(|bracket| {
+        let offset = state.text_buffer.byte_offset(bracket.offset() as usize);
+        let partner = state.text_buffer.matching_bracket(offset)?;
+        Some((bracket, partner.map(|partner| iter_at_mirror_byte(buffer, &state.text_buffer, partner))))
+    })
+}
+
+// Highlight the bracket pair at the cursor, or flag the bracket there if it has no partner
+fn highlight_matching_bracket(buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>) {
+    let (start, end) = buffer.bounds();
+    buffer.remove_tag_by_name("bracket-match", &start, &end);
+    buffer.remove_tag_by_name("bracket-unmatched", &start, &end);
+
+    let Ok(state) = state.try_borrow() else {
+        return;
+    };
+    // Plain text has no brackets to pair
+    if !state.syntax_highlighting || state.large_file_mode || state.language().is_none() || !mirror_in_step(&state, buffer) {
+        return;
+    }
+    let Some((bracket, partner)) = bracket_at_cursor(buffer, &state) else {
+        return;
+    };
+    let tag_one = |tag: &str, at: &gtk::TextIter| {
+        let mut after = *at;
+        after.forward_char();
+        buffer.apply_tag_by_name(tag, at, &after);
+    };
+    match partner {
+        Some(partner) => {
+            tag_one("bracket-match", &bracket);
+            tag_one("bracket-match", &partner);
+        }
+        None => tag_one("bracket-unmatched", &bracket),
+    }
+}
+
+// Tint the spaces and tabs ending lines, if the settings ask for it
+fn highlight_trailing_whitespace(buffer: &gtk::TextBuffer, state: &Rc<RefCell<EditorState>>) {
+    let (start, end) = buffer.bounds();
+    buffer.remove_tag_by_name("trailing-whitespace", &start, &end);
+    if !state.try_borrow().is_ok_and(|state| state.settings.show_trailing_whitespace && !state.large_file_mode) {
+        return;
+    }
+
+    let text = buffer.text(&start, &end, false);
+    for range in char_ranges(&text, &edit_ops::trailing_whitespace(&text)) {
+        buffer.apply_tag_by_name("trailing-whitespace", &buffer.iter_at_offset(range.start), &buffer.iter_at_offset(range.end));
+    }
+}
+
+// Ctrl+Alt+- and Ctrl+Alt+=: go back or forward through the caret history of the shown tab
+fn step_caret_history(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, forward: bool) {
+    let buffer = text_view.buffer();
+    let mut history = std::mem::take(&mut state.borrow_mut().caret_history);
+    let target = history.step(&buffer, forward);
+    state.borrow_mut().caret_history = history;
+    if let Some(target) = target {
+        buffer.place_cursor(&target);
+        text_view.scroll_to_mark(&buffer.get_insert(), 0.1, false, 0.0, 0.0);
+    }
+}
+
+// Ctrl+M: put the cursor at the partner of the bracket at the cursor
+fn jump_to_matching_bracket(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let buffer = text_view.buffer();
+    let partner = bracket_at_cursor(&buffer, &state.borrow()).and_then(|(_, partner)| partner);
+    if let Some(partner) = partner {
+        buffer.place_cursor(&partner);
+        text_view.scroll_mark_onscreen(&buffer.get_insert());
+    }
+}
+
+fn check_for_errors(buffer: &gtk::TextBuffer, content: &str) {
+    // Unbalanced brackets are underlined and listed in the bracket panel
+    let problems = brackets::find_unmatched_brackets(content);
+    for problem in &problems {
+        highlight_error_at_position(buffer, problem.line, problem.column);
+    }
+    update_bracket_panel(buffer, &problems);
+    
+    // Check for missing semicolons
+    for (line_idx, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && 
+           !trimmed.ends_with(';') && 
+           !trimmed.ends_with('{') && 
+           !trimmed.ends_with('}') && 
+           !trimmed.starts_with("//") &&
+           !trimmed.starts_with("pub fn") &&
+           !trimmed.starts_with("fn") &&
+           !trimmed.contains("->") {
+            // Potential missing semicolon
+            if let Some(iter) = buffer.iter_at_line_offset(line_idx as i32, 0) {
+                let mut end = iter.clone();
+                if end.forward_to_line_end() {
+                    // Skip if it's inside a comment or string
+                    let text = buffer.text(&iter, &end, false);
+                    if !text.contains("//") && !text.contains("/*") && !is_inside_string(&text) {
+                        buffer.apply_tag_by_name("error", &iter, &end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_inside_string(text: &str) -> bool {
+    let mut in_string = false;
+    let mut escaped = false;
+    
+    for ch in text.chars() {
+        if ch == '\\' {
+            escaped = !escaped;
+        } else if ch == '"' && !escaped {
+            in_string = !in_string;
+        } else {
+            escaped = false;
+        }
+    }
+    
+    in_string
+}
+
+// Tag the character at a 0-based line and column
+fn highlight_error_at_position(buffer: &gtk::TextBuffer, line: usize, col: usize) {
+    if let Some(pos) = buffer.iter_at_line_offset(line as i32, col as i32) {
+        let mut end = pos;
+        if end.forward_char() {
+            buffer.apply_tag_by_name("error", &pos, &end);
+        }
+    }
+}
+
+// Buffer the problems were found in and the problems, by row
+type BracketPositions = Rc<RefCell<(Option<gtk::TextBuffer>, Vec<BracketProblem>)>>;
+
+struct BracketPanel {
+    container: gtk::Box,
+    list: gtk::ListBox,
+    problems: BracketPositions,
+}
+
+thread_local! {
+    static BRACKET_PANEL: RefCell<Option<BracketPanel>> = const { RefCell::new(None) };
+    // Fills in the panel when the first problem is found
+    static BRACKET_PANEL_BUILDER: RefCell<Option<Box<dyn FnOnce() -> BracketPanel>>> = const { RefCell::new(None) };
+}
+
+// Panel under the editor listing unbalanced brackets; hidden while there are none, and
+// only filled in once there are some
+fn create_bracket_panel(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    container.set_css_classes(&["bracket-panel"]);
+    container.set_visible(false);
+
+    let container_ref = container.clone();
+    let (text_view, state) = (text_view.clone(), state.clone());
+    BRACKET_PANEL_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_bracket_panel(&container_ref, &text_view, &state)
+    })));
+    container
+}
+
+fn build_bracket_panel(container: &gtk::Box, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) -> BracketPanel {
+    load_on_demand_css();
+
+    let heading = gtk::Label::new(Some("Bracket problems"));
+    heading.set_halign(gtk::Align::Start);
+    heading.set_css_classes(&["heading"]);
+    container.append(&heading);
+
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::None);
+    let scrolled = gtk::ScrolledWindow::new();
+    scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled.set_propagate_natural_height(true);
+    scrolled.set_max_content_height(120);
+    scrolled.set_child(Some(&list));
+    container.append(&scrolled);
+
+    let problems: BracketPositions = Rc::new(RefCell::new((None, Vec::new())));
+
+    // Activating an entry jumps to the bracket
+    let problems_ref = problems.clone();
+    let text_view_ref = text_view.clone();
+    list.connect_row_activated(move |_, row| {
+        let problems = problems_ref.borrow();
+        let (Some(buffer), Some(problem)) = (&problems.0, problems.1.get(row.index() as usize)) else {
+            return;
+        };
+        if text_view_ref.buffer() != *buffer {
+            return;
+        }
+        place_cursor_at_line_column(buffer, problem.line as i32, problem.column as i32);
+        text_view_ref.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.3);
+        text_view_ref.grab_focus();
+    });
+
+    // Right-clicking an entry offers to copy it with the code around it
+    let right_click = gtk::GestureClick::new();
+    right_click.set_button(3);
+    let list_ref = list.clone();
+    let problems_ref = problems.clone();
+    let (text_view_ref, state_ref) = (text_view.clone(), state.clone());
+    right_click.connect_pressed(move |_, _, _, y| {
+        let Some(row) = list_ref.row_at_y(y as i32) else {
+            return;
+        };
+        let popover = gtk::Popover::new();
+        popover.set_parent(&row);
+        let copy_item = gtk::Button::with_label("Copy as Report");
+        copy_item.set_css_classes(&["menu-item"]);
+        copy_item.set_has_frame(false);
+        popover.set_child(Some(&copy_item));
+
+        let (problems, text_view, state, popover_ref) = (problems_ref.clone(), text_view_ref.clone(), state_ref.clone(), popover.clone());
+        let index = row.index() as usize;
+        copy_item.connect_clicked(move |_| {
+            popover_ref.popdown();
+            let problems = problems.borrow();
+            let (Some(buffer), Some(problem)) = (&problems.0, problems.1.get(index)) else {
+                return;
+            };
+            // The tab's name is only known while it is shown
+            let location = match state.try_borrow() {
+                Ok(state) if text_view.buffer() == *buffer => state.current_file.as_ref().map_or_else(|| state.tab_name.clone(), |path| path.display().to_string()),
+                _ => "Untitled".to_string(),
+            };
+            let (start, end) = buffer.bounds();
+            let report = diagnostics::format_report(&location, problem.line, problem.column, &problem.description(), &buffer.text(&start, &end, false));
+            text_view.clipboard().set_text(&report);
+            remember_clipboard_text(&report);
+        });
+        popover.connect_closed(|popover| popover.unparent());
+        popover.popup();
+    });
+    list.add_controller(right_click);
+
+    BracketPanel { container: container.clone(), list, problems }
+}
+
+struct ByteInspector {
+    container: gtk::Box,
+    summary: gtk::Label,
+    code_points: gtk::Label,
+    utf8: gtk::Label,
+    utf16: gtk::Label,
+}
+
+thread_local! {
+    static BYTE_INSPECTOR: RefCell<Option<ByteInspector>> = const { RefCell::new(None) };
+    static BYTE_INSPECTOR_BUILDER: RefCell<Option<Box<dyn FnOnce() -> ByteInspector>>> = const { RefCell::new(None) };
+}
+
+// Panel under the editor spelling out the code points, UTF-8 bytes and UTF-16 code units
+// of the selection, or of the character at the cursor without one
+fn create_byte_inspector() -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    container.set_css_classes(&["byte-inspector"]);
+    container.set_visible(false);
+
+    let container_ref = container.clone();
+    BYTE_INSPECTOR_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_byte_inspector(&container_ref)
+    })));
+    container
+}
+
+fn build_byte_inspector(container: &gtk::Box) -> ByteInspector {
+    load_on_demand_css();
+
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let heading = gtk::Label::new(Some("Inspect Bytes"));
+    heading.set_halign(gtk::Align::Start);
+    heading.set_css_classes(&["heading"]);
+    let summary = gtk::Label::new(None);
+    summary.set_halign(gtk::Align::Start);
+    summary.set_hexpand(true);
+    summary.set_css_classes(&["dim-label"]);
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Close panel"));
+    header.append(&heading);
+    header.append(&summary);
+    header.append(&close_button);
+    container.append(&header);
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(2);
+    grid.set_column_spacing(12);
+    let mut values = Vec::new();
+    for (row, name) in ["Code points", "UTF-8", "UTF-16"].into_iter().enumerate() {
+        let label = gtk::Label::new(Some(name));
+        label.set_halign(gtk::Align::Start);
+        label.set_valign(gtk::Align::Start);
+        let value = gtk::Label::new(None);
+        value.set_halign(gtk::Align::Start);
+        value.set_hexpand(true);
+        value.set_wrap(true);
+        value.set_wrap_mode(pango::WrapMode::WordChar);
+        value.set_xalign(0.0);
+        value.set_selectable(true);
+        value.set_css_classes(&["byte-inspector-value"]);
+        grid.attach(&label, 0, row as i32, 1, 1);
+        grid.attach(&value, 1, row as i32, 1, 1);
+        values.push(value);
+    }
+    container.append(&grid);
+
+    let container_ref = container.clone();
+    close_button.connect_clicked(move |_| container_ref.set_visible(false));
+
+    let [code_points, utf8, utf16]: [gtk::Label; 3] = values.try_into().expect("one value per row");
+    ByteInspector { container: container.clone(), summary, code_points, utf8, utf16 }
+}
+
+// View > Inspect Bytes: open the panel on the selection of `buffer`
+fn show_byte_inspector(buffer: &gtk::TextBuffer) {
+    if let Some(build) = BYTE_INSPECTOR_BUILDER.with(|builder| builder.borrow_mut().take()) {
+        let inspector = build();
+        BYTE_INSPECTOR.with(|cell| *cell.borrow_mut() = Some(inspector));
+    }
+    BYTE_INSPECTOR.with(|inspector| {
+        if let Some(inspector) = inspector.borrow().as_ref() {
+            inspector.container.set_visible(true);
+        }
+    });
+    update_byte_inspector(buffer);
+}
+
+// Show the selection of `buffer` in the panel, if it is open
+fn update_byte_inspector(buffer: &gtk::TextBuffer) {
+    BYTE_INSPECTOR.with(|inspector| {
+        let inspector = inspector.borrow();
+        let Some(inspector) = inspector.as_ref().filter(|inspector| inspector.container.is_visible()) else {
+            return;
+        };
+        let (start, end) = buffer.selection_bounds().unwrap_or_else(|| {
+            let cursor = buffer.iter_at_mark(&buffer.get_insert());
+            let mut next = cursor;
+            next.forward_char();
+            (cursor, next)
+        });
+        // Past the characters spelled out only the counts are needed
+        let mut spelled_end = start;
+        spelled_end.forward_chars(inspect::MAX_INSPECTED_CHARS as i32 + 1);
+        let spelled_end = if spelled_end < end { spelled_end } else { end };
+        let spelled = buffer.text(&start, &spelled_end, true);
+        let inspection = inspect::inspect(&spelled);
+
+        let chars = end.offset() - start.offset();
+        if spelled_end == end {
+            inspector.summary.set_text(&format!(
+                "{} characters, {} UTF-8 bytes, {} UTF-16 code units",
+                chars, inspection.utf8_len, inspection.utf16_len
+            ));
+        } else {
+            inspector.summary.set_text(&format!("{} characters, the first {} shown", chars, inspect::MAX_INSPECTED_CHARS));
+        }
+        inspector.code_points.set_text(&inspection.code_points);
+        inspector.utf8.set_text(&inspection.utf8);
+        inspector.utf16.set_text(&inspection.utf16);
+    });
+}
+
+struct OutputPanel {
+    container: gtk::Box,
+    summary: gtk::Label,
+    text: gtk::TextView,
+}
+
+thread_local! {
+    static OUTPUT_PANEL: RefCell<Option<OutputPanel>> = const { RefCell::new(None) };
+    static OUTPUT_PANEL_BUILDER: RefCell<Option<Box<dyn FnOnce() -> OutputPanel>>> = const { RefCell::new(None) };
+}
+
+// Panel under the editor showing what a command printed, such as a snippet that was run
+fn create_output_panel() -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 4);
+    container.set_css_classes(&["output-panel"]);
+    container.set_visible(false);
+
+    let container_ref = container.clone();
+    OUTPUT_PANEL_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_output_panel(&container_ref)
+    })));
+    container
+}
+
+fn build_output_panel(container: &gtk::Box) -> OutputPanel {
+    load_on_demand_css();
+
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let heading = gtk::Label::new(Some("Output"));
+    heading.set_halign(gtk::Align::Start);
+    heading.set_css_classes(&["heading"]);
+    let summary = gtk::Label::new(None);
+    summary.set_halign(gtk::Align::Start);
+    summary.set_hexpand(true);
+    summary.set_css_classes(&["dim-label"]);
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Close panel"));
+    header.append(&heading);
+    header.append(&summary);
+    header.append(&close_button);
+    container.append(&header);
+
+    let text = gtk::TextView::new();
+    text.set_editable(false);
+    text.set_monospace(true);
+    text.set_wrap_mode(gtk::WrapMode::WordChar);
+    text.set_css_classes(&["output-panel-text"]);
+    let scroll = gtk::ScrolledWindow::new();
+    scroll.set_min_content_height(160);
+    scroll.set_child(Some(&text));
+    container.append(&scroll);
+
+    let container_ref = container.clone();
+    close_button.connect_clicked(move |_| container_ref.set_visible(false));
+
+    OutputPanel { container: container.clone(), summary, text }
+}
+
+// Open the output panel on `output`, with `summary` beside its heading
+fn show_output(summary: &str, output: &str) {
+    if let Some(build) = OUTPUT_PANEL_BUILDER.with(|builder| builder.borrow_mut().take()) {
+        let panel = build();
+        OUTPUT_PANEL.with(|cell| *cell.borrow_mut() = Some(panel));
+    }
+    OUTPUT_PANEL.with(|panel| {
+        if let Some(panel) = panel.borrow().as_ref() {
+            panel.summary.set_text(summary);
+            panel.text.buffer().set_text(output);
+            panel.container.set_visible(true);
+        }
+    });
+}
+
+// Edit > Run Selection as Rust: compile the selected code with rustc, in a main function
+// unless it has one, run it, and show what the compiler and the program printed
+fn run_selection(text_view: &gtk::TextView) {
+    let buffer = text_view.buffer();
+    let Some((start, end)) = buffer.selection_bounds() else {
+        show_output("Nothing to run", "Select Rust code to compile and run it.");
+        return;
+    };
+    let snippet = buffer.text(&start, &end, false).to_string();
+    show_output("Compiling…", "");
+
+    glib::spawn_future_local(async move {
+        let result = gio::spawn_blocking(move || snippet::run(&snippet)).await;
+        let output = match result {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => {
+                show_output("Could not run the selection", &format!("{:#}", e));
+                return;
+            }
+            Err(_) => {
+                error!("Snippet thread panicked");
+                return;
+            }
+        };
+
+        let mut text = output.compiler.clone();
+        let Some(run) = output.run else {
+            show_output("Did not compile", &text);
+            return;
+        };
+        for stream in [&run.stdout, &run.stderr] {
+            if !stream.is_empty() {
+                if !text.is_empty() && !text.ends_with("\n\n") {
+                    text.push('\n');
+                }
+                text.push_str(stream);
+            }
+        }
+        let summary = match run.exit_code {
+            _ if run.timed_out => format!("Stopped after {} s", snippet::RUN_TIMEOUT.as_secs()),
+            Some(0) => format!("Ran in {:.2} s", run.duration.as_secs_f64()),
+            Some(code) => format!("Exited with code {} after {:.2} s", code, run.duration.as_secs_f64()),
+            None => "Ended by a signal".to_string(),
+        };
+        show_output(&summary, &text);
+    });
+}
+
+// Most words listed in the frequency report
+const MAX_FREQUENCY_ROWS: usize = 100;
+
+// Buffer analyzed and the character ranges of its repeated words, by row
+type RepeatPositions = Rc<RefCell<(Option<gtk::TextBuffer>, Vec<Range<i32>>)>>;
+
+struct ProsePanel {
+    container: gtk::Box,
+    summary: gtk::Label,
+    repeats: gtk::ListBox,
+    frequencies: gtk::ListBox,
+    positions: RepeatPositions,
+}
+
+thread_local! {
+    static PROSE_PANEL: RefCell<Option<ProsePanel>> = const { RefCell::new(None) };
+    static PROSE_PANEL_BUILDER: RefCell<Option<Box<dyn FnOnce() -> ProsePanel>>> = const { RefCell::new(None) };
+}
+
+// Panel under the editor proofreading prose: words repeated right after themselves, which
+// are also marked in the text, and how often each word is used
+fn create_prose_panel(text_view: &gtk::TextView) -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 4);
+    container.set_css_classes(&["prose-panel"]);
+    container.set_visible(false);
+
+    let container_ref = container.clone();
+    let text_view = text_view.clone();
+    PROSE_PANEL_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_prose_panel(&container_ref, &text_view)
+    })));
+    container
+}
+
+fn build_prose_panel(container: &gtk::Box, text_view: &gtk::TextView) -> ProsePanel {
+    load_on_demand_css();
+
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let heading = gtk::Label::new(Some("Prose Analysis"));
+    heading.set_halign(gtk::Align::Start);
+    heading.set_css_classes(&["heading"]);
+    let summary = gtk::Label::new(None);
+    summary.set_halign(gtk::Align::Start);
+    summary.set_hexpand(true);
+    summary.set_css_classes(&["dim-label"]);
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Close panel"));
+    header.append(&heading);
+    header.append(&summary);
+    header.append(&close_button);
+    container.append(&header);
+
+    // Repeated words beside the word frequencies
+    let columns = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    columns.set_homogeneous(true);
+    let mut lists = Vec::new();
+    for title in ["Repeated words", "Word frequency"] {
+        let column = gtk::Box::new(gtk::Orientation::Vertical, 2);
+        let label = gtk::Label::new(Some(title));
+        label.set_halign(gtk::Align::Start);
+        label.set_css_classes(&["prose-panel-title"]);
+        column.append(&label);
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::None);
+        let scrolled = gtk::ScrolledWindow::new();
+        scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        scrolled.set_min_content_height(140);
+        scrolled.set_child(Some(&list));
+        column.append(&scrolled);
+        columns.append(&column);
+        lists.push(list);
+    }
+    container.append(&columns);
+    let [repeats, frequencies]: [gtk::ListBox; 2] = lists.try_into().expect("one list per column");
+
+    let positions: RepeatPositions = Rc::new(RefCell::new((None, Vec::new())));
+
+    // Activating a repeat selects it in the text
+    let positions_ref = positions.clone();
+    let text_view_ref = text_view.clone();
+    repeats.connect_row_activated(move |_, row| {
+        let positions = positions_ref.borrow();
+        let (Some(buffer), Some(range)) = (&positions.0, positions.1.get(row.index() as usize)) else {
+            return;
+        };
+        if text_view_ref.buffer() != *buffer {
+            return;
+        }
+        buffer.select_range(&buffer.iter_at_offset(range.end), &buffer.iter_at_offset(range.start));
+        text_view_ref.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.3);
+        text_view_ref.grab_focus();
+    });
+
+    // Closing the panel takes the marks out of the text
+    let container_ref = container.clone();
+    let positions_ref = positions.clone();
+    close_button.connect_clicked(move |_| {
+        container_ref.set_visible(false);
+        if let Some(buffer) = positions_ref.borrow_mut().0.take() {
+            let (start, end) = buffer.bounds();
+            buffer.remove_tag_by_name("repeated-word", &start, &end);
+        }
+    });
+
+    ProsePanel { container: container.clone(), summary, repeats, frequencies, positions }
+}
+
+// View > Prose Analysis: open the panel on the text of `buffer`
+fn show_prose_analysis(buffer: &gtk::TextBuffer) {
+    if let Some(build) = PROSE_PANEL_BUILDER.with(|builder| builder.borrow_mut().take()) {
+        let panel = build();
+        PROSE_PANEL.with(|cell| *cell.borrow_mut() = Some(panel));
+    }
+    PROSE_PANEL.with(|panel| {
+        if let Some(panel) = panel.borrow().as_ref() {
+            // Marks of a tab analyzed before stay out of this one's way
+            if let Some(previous) = panel.positions.borrow_mut().0.replace(buffer.clone()) {
+                let (start, end) = previous.bounds();
+                previous.remove_tag_by_name("repeated-word", &start, &end);
+            }
+            panel.container.set_visible(true);
+        }
+    });
+    update_prose_analysis(buffer);
+}
+
+// Analyze `buffer` again after an edit, if the open panel shows it, and mark its repeated words
+fn update_prose_analysis(buffer: &gtk::TextBuffer) {
+    PROSE_PANEL.with(|panel| {
+        let panel = panel.borrow();
+        let Some(panel) = panel.as_ref().filter(|panel| panel.container.is_visible()) else {
+            return;
+        };
+        if panel.positions.borrow().0.as_ref() != Some(buffer) {
+            return;
+        }
+
+        let (start, end) = buffer.bounds();
+        let text = buffer.text(&start, &end, false);
+        let report = prose::analyze(&text);
+        let repeats = char_ranges(&text, &report.repeats);
+
+        buffer.remove_tag_by_name("repeated-word", &start, &end);
+        for range in &repeats {
+            buffer.apply_tag_by_name("repeated-word", &buffer.iter_at_offset(range.start), &buffer.iter_at_offset(range.end));
+        }
+
+        panel.summary.set_text(&format!(
+            "{} words, {} different, {} repeated",
+            report.words,
+            report.frequencies.len(),
+            repeats.len()
+        ));
+
+        while let Some(child) = panel.repeats.first_child() {
+            panel.repeats.remove(&child);
+        }
+        for (range, chars) in report.repeats.iter().zip(&repeats) {
+            let line = buffer.iter_at_offset(chars.start).line() + 1;
+            let words = text[range.clone()].split_whitespace().collect::<Vec<_>>().join(" ");
+            let label = gtk::Label::new(Some(&format!("Line {}: {}", line, words)));
+            label.set_halign(gtk::Align::Start);
+            panel.repeats.append(&label);
+        }
+
+        while let Some(child) = panel.frequencies.first_child() {
+            panel.frequencies.remove(&child);
+        }
+        for (word, count) in report.frequencies.iter().take(MAX_FREQUENCY_ROWS) {
+            let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+            let word_label = gtk::Label::new(Some(word));
+            word_label.set_halign(gtk::Align::Start);
+            word_label.set_hexpand(true);
+            word_label.set_ellipsize(pango::EllipsizeMode::End);
+            let count_label = gtk::Label::new(Some(&count.to_string()));
+            count_label.set_css_classes(&["dim-label"]);
+            row.append(&word_label);
+            row.append(&count_label);
+            panel.frequencies.append(&row);
+        }
+
+        panel.positions.borrow_mut().1 = repeats;
+    });
+}
+
+fn update_bracket_panel(buffer: &gtk::TextBuffer, problems: &[BracketProblem]) {
+    if !problems.is_empty() {
+        if let Some(build) = BRACKET_PANEL_BUILDER.with(|builder| builder.borrow_mut().take()) {
+            let panel = build();
+            BRACKET_PANEL.with(|cell| *cell.borrow_mut() = Some(panel));
+        }
+    }
+    BRACKET_PANEL.with(|panel| {
+        let panel = panel.borrow();
+        let Some(panel) = panel.as_ref() else {
+            return;
+        };
+
+        while let Some(child) = panel.list.first_child() {
+            panel.list.remove(&child);
+        }
+        for problem in problems {
+            let label = gtk::Label::new(Some(&format!(
+                "Line {}, column {}: {}",
+                problem.line + 1,
+                problem.column + 1,
+                problem.description()
+            )));
+            label.set_halign(gtk::Align::Start);
+            panel.list.append(&label);
+        }
+
+        *panel.problems.borrow_mut() = (Some(buffer.clone()), problems.to_vec());
+        panel.container.set_visible(!problems.is_empty());
+    });
+}
+
+// Give the tab's button a tooltip describing the tab, built afresh each time it is shown
+fn connect_tab_tooltip(button: &gtk::Button, buffer: &gtk::TextBuffer, tabs: &Rc<RefCell<TabManager>>) {
+    button.set_has_tooltip(true);
+    let (buffer, tabs) = (buffer.clone(), tabs.clone());
+    button.connect_query_tooltip(move |_, _, _, _, tooltip| {
+        let Some(text) = tabs.try_borrow().ok().and_then(|tabs| tabs.tooltip_for(&buffer)) else {
+            return false;
+        };
+        tooltip.set_text(Some(&text));
+        true
+    });
+}
+
+// Tab context menu entry that stars or unstars the tab's file; None for tabs without a file
+fn favorite_menu_item(tabs: &Rc<RefCell<TabManager>>, state: &Rc<RefCell<EditorState>>, buffer: &gtk::TextBuffer, popover: &gtk::Popover) -> Option<gtk::Button> {
+    let path = tabs.borrow().file_for(buffer)?;
+    let is_favorite = state.try_borrow().ok()?.persistent.favorites.contains(&path);
+
+    let item = gtk::Button::new();
+    item.set_label(if is_favorite { "Remove from Favorites" } else { "Add to Favorites" });
+    item.set_css_classes(&["menu-item"]);
+    item.set_has_frame(false);
+
+    let state = state.clone();
+    let popover = popover.clone();
+    item.connect_clicked(move |_| {
+        if let Ok(mut state) = state.try_borrow_mut() {
+            state.persistent.toggle_favorite(&path);
+            if let Err(e) = state.persistent.save() {
+                warn!("Failed to save editor state: {}", e);
+            }
+        }
+        update_favorites_sidebar(&state);
+        popover.popdown();
+    });
+    Some(item)
+}
+
+struct FavoritesSidebar {
+    container: gtk::Box,
+    list: gtk::ListBox,
+    paths: Rc<RefCell<Vec<PathBuf>>>,
+}
+
+thread_local! {
+    static FAVORITES_SIDEBAR: RefCell<Option<FavoritesSidebar>> = const { RefCell::new(None) };
+}
+
+// Sidebar on the left of the editor listing starred files
+fn create_favorites_sidebar(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label) -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    container.set_css_classes(&["favorites-sidebar"]);
+    container.set_width_request(200);
+    container.set_visible(state.try_borrow().map(|state| state.persistent.show_favorites).unwrap_or(false));
+
+    let heading = gtk::Label::new(Some("Favorites"));
+    heading.set_halign(gtk::Align::Start);
+    heading.set_css_classes(&["heading"]);
+    container.append(&heading);
+
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::None);
+    let scrolled = gtk::ScrolledWindow::new();
+    scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled.set_vexpand(true);
+    scrolled.set_child(Some(&list));
+    container.append(&scrolled);
+
+    // Activating a favorite opens it in the shown tab, like Open recent does
+    let paths: Rc<RefCell<Vec<PathBuf>>> = Rc::new(RefCell::new(Vec::new()));
+    let paths_ref = paths.clone();
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    let status_label_ref = status_label.clone();
+    list.connect_row_activated(move |_, row| {
+        let Some(path) = paths_ref.borrow().get(row.index() as usize).cloned() else {
+            return;
+        };
+        open_path_in_view(&window_ref, &text_view_ref.buffer(), &text_view_ref, &state_ref, &status_label_ref, path);
+    });
+
+    FAVORITES_SIDEBAR.with(|sidebar| *sidebar.borrow_mut() = Some(FavoritesSidebar {
+        container: container.clone(),
+        list,
+        paths,
+    }));
+    update_favorites_sidebar(state);
+    container
+}
+
+fn update_favorites_sidebar(state: &Rc<RefCell<EditorState>>) {
+    let favorites = match state.try_borrow() {
+        Ok(state) => state.persistent.favorites.clone(),
+        Err(_) => return,
+    };
+
+    FAVORITES_SIDEBAR.with(|sidebar| {
+        let sidebar = sidebar.borrow();
+        let Some(sidebar) = sidebar.as_ref() else {
+            return;
+        };
+
+        while let Some(child) = sidebar.list.first_child() {
+            sidebar.list.remove(&child);
+        }
+        if favorites.is_empty() {
+            let hint = gtk::Label::new(Some("Star a file from its tab's menu"));
+            hint.set_css_classes(&["dim-label"]);
+            let row = gtk::ListBoxRow::new();
+            row.set_child(Some(&hint));
+            row.set_activatable(false);
+            sidebar.list.append(&row);
+        }
+        for path in &favorites {
+            let name = path.file_name().map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.to_string_lossy().to_string());
+            let label = gtk::Label::new(Some(&name));
+            label.set_halign(gtk::Align::Start);
+            label.set_ellipsize(pango::EllipsizeMode::End);
+            label.set_tooltip_text(Some(&path.to_string_lossy()));
+            sidebar.list.append(&label);
+        }
+        *sidebar.paths.borrow_mut() = favorites;
+    });
+}
+
+fn set_favorites_sidebar_visible(visible: bool) {
+    FAVORITES_SIDEBAR.with(|sidebar| {
+        if let Some(sidebar) = sidebar.borrow().as_ref() {
+            sidebar.container.set_visible(visible);
+        }
+    });
+}
+
+// Stop streaming results into the Find in Files panel after this many matches
+const MAX_FIND_IN_FILES_MATCHES: usize = 2000;
+
+// Where a Find in Files row jumps to; file header rows have no target
+type FindTargets = Rc<RefCell<Vec<Option<(PathBuf, usize, usize)>>>>;
+
+struct FindInFilesPanel {
+    container: gtk::Box,
+    entry: gtk::SearchEntry,
+}
+
+thread_local! {
+    static FIND_IN_FILES_PANEL: RefCell<Option<FindInFilesPanel>> = const { RefCell::new(None) };
+    // Fills in the panel the first time it is opened
+    static FIND_IN_FILES_BUILDER: RefCell<Option<Box<dyn FnOnce() -> FindInFilesPanel>>> = const { RefCell::new(None) };
+}
+
+// Side panel that searches a directory recursively and lists matches grouped by file.
+// Only the empty container is made here; most sessions never open it.
+fn create_find_in_files_panel(window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label) -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    container.set_css_classes(&["find-in-files-panel"]);
+    container.set_width_request(340);
+    container.set_visible(false);
+
+    let container_ref = container.clone();
+    let window = window.clone();
+    let text_view = text_view.clone();
+    let state = state.clone();
+    let status_label = status_label.clone();
+    FIND_IN_FILES_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_find_in_files_panel(&container_ref, &window, &text_view, &state, &status_label)
+    })));
+    container
+}
+
+fn build_find_in_files_panel(container: &gtk::Box, window: &gtk::ApplicationWindow, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label) -> FindInFilesPanel {
+    load_on_demand_css();
+
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let heading = gtk::Label::new(Some("Find in Files"));
+    heading.set_halign(gtk::Align::Start);
+    heading.set_hexpand(true);
+    heading.set_css_classes(&["heading"]);
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Close panel"));
+    header.append(&heading);
+    header.append(&close_button);
+    container.append(&header);
+
+    let entry = gtk::SearchEntry::new();
+    entry.set_placeholder_text(Some("Search text, Enter to run"));
+    container.append(&entry);
+
+    // Search the workspace by default, or wherever the user points the folder button
+    let directory = Rc::new(RefCell::new(
+        state.try_borrow().ok().and_then(|state| state.workspace_root.clone())
+            .or_else(|| env::current_dir().ok())
+            .unwrap_or_else(glib::home_dir),
+    ));
+    let folder_button = gtk::Button::new();
+    folder_button.set_hexpand(true);
+    let show_directory = |button: &gtk::Button, dir: &Path| {
+        let name = dir.file_name().map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| dir.to_string_lossy().to_string());
+        button.set_label(&name);
+        button.set_tooltip_text(Some(&dir.to_string_lossy()));
+    };
+    show_directory(&folder_button, &directory.borrow());
+
+    let regex_toggle = gtk::CheckButton::with_label("Regex");
+    let case_toggle = gtk::CheckButton::with_label("Match case");
+    let options_row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    options_row.append(&folder_button);
+    options_row.append(&regex_toggle);
+    options_row.append(&case_toggle);
+    container.append(&options_row);
+
+    let summary = gtk::Label::new(None);
+    summary.set_halign(gtk::Align::Start);
+    summary.set_css_classes(&["dim-label"]);
+    container.append(&summary);
+
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::None);
+    let scrolled = gtk::ScrolledWindow::new();
+    scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled.set_vexpand(true);
+    scrolled.set_child(Some(&list));
+    container.append(&scrolled);
+
+    let container_ref = container.clone();
+    close_button.connect_clicked(move |_| container_ref.set_visible(false));
+
+    let window_ref = window.clone();
+    let directory_ref = directory.clone();
+    folder_button.connect_clicked(move |button| {
+        let dialog = gtk::FileChooserNative::builder()
+            .title("Search in Folder")
+            .action(gtk::FileChooserAction::SelectFolder)
+            .accept_label("Select")
+            .cancel_label("Cancel")
+            .transient_for(&window_ref)
+            .modal(true)
+            .build();
+        let _ = dialog.set_current_folder(Some(&gio::File::for_path(&*directory_ref.borrow())));
+
+        let button = button.clone();
+        let directory = directory_ref.clone();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                    show_directory(&button, &path);
+                    *directory.borrow_mut() = path;
+                }
+            }
+            dialog.destroy();
+        });
+        dialog.show();
+    });
+
+    // Each search gets a cancel flag; starting a new one cancels the previous one
+    let targets: FindTargets = Rc::new(RefCell::new(Vec::new()));
+    let cancel: Rc<RefCell<Arc<AtomicBool>>> = Rc::new(RefCell::new(Arc::new(AtomicBool::new(false))));
+    let list_ref = list.clone();
+    let targets_ref = targets.clone();
+    entry.connect_activate(move |entry| {
+        cancel.borrow().store(true, Ordering::Relaxed);
+        while let Some(child) = list_ref.first_child() {
+            list_ref.remove(&child);
+        }
+        targets_ref.borrow_mut().clear();
+
+        let options = find_in_files::SearchOptions {
+            pattern: entry.text().to_string(),
+            regex: regex_toggle.is_active(),
+            case_sensitive: case_toggle.is_active(),
+        };
+        if options.pattern.is_empty() {
+            summary.set_text("");
+            return;
+        }
+        let matcher = match find_in_files::build_matcher(&options) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                summary.set_text(&format!("Invalid pattern: {}", e));
+                return;
+            }
+        };
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        *cancel.borrow_mut() = cancelled.clone();
+        summary.set_text("Searching…");
+
+        // Files are searched on a worker thread and streamed back as they are found
+        let (sender, receiver) = mpsc::channel();
+        let root = directory.borrow().clone();
+        let worker_cancelled = cancelled.clone();
+        std::thread::spawn(move || {
+            let mut total = 0;
+            find_in_files::search_directory(&root, &matcher, |found| {
+                total += found.matches.len();
+                !worker_cancelled.load(Ordering::Relaxed)
+                    && sender.send(found).is_ok()
+                    && total < MAX_FIND_IN_FILES_MATCHES
+            });
+        });
+
+        let list = list_ref.clone();
+        let targets = targets_ref.clone();
+        let summary = summary.clone();
+        let root = directory.borrow().clone();
+        let (mut files, mut matches) = (0, 0);
+        glib::timeout_add_local(Duration::from_millis(50), move || {
+            if cancelled.load(Ordering::Relaxed) {
+                return glib::ControlFlow::Break;
+            }
+            loop {
+                match receiver.try_recv() {
+                    Ok(found) => {
+                        files += 1;
+                        matches += found.matches.len();
+                        append_find_in_files_results(&list, &targets, &root, found);
+                    }
+                    Err(mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                    Err(mpsc::TryRecvError::Disconnected) => break,
+                }
+            }
+            let limit_note = if matches >= MAX_FIND_IN_FILES_MATCHES { " (stopped at the limit)" } else { "" };
+            summary.set_text(&format!("{} matches in {} files{}", matches, files, limit_note));
+            glib::ControlFlow::Break
+        });
+    });
+
+    // Activating a match opens its file and moves the cursor there
+    let window_ref = window.clone();
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    let status_label_ref = status_label.clone();
+    list.connect_row_activated(move |_, row| {
+        let Some(Some((path, line, column))) = targets.borrow().get(row.index() as usize).cloned() else {
+            return;
+        };
+        let buffer = text_view_ref.buffer();
+        let state = state_ref.clone();
+        let text_view = text_view_ref.clone();
+        let path_ref = path.clone();
+        let jump = move || {
+            // Files with very long lines load after a dialog, so only jump once the file is shown
+            let is_open = state.try_borrow().map(|state| state.current_file.as_deref() == Some(path_ref.as_path())).unwrap_or(false);
+            if is_open {
+                place_cursor_at_line_column(&buffer, line as i32, column as i32);
+                text_view.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.3);
+                text_view.grab_focus();
+            }
+        };
+        let is_open = state_ref.try_borrow().map(|state| state.current_file.as_deref() == Some(path.as_path())).unwrap_or(false);
+        if is_open {
+            jump();
+        } else {
+            open_path_in_view_then(&window_ref, &text_view_ref.buffer(), &text_view_ref, &state_ref, &status_label_ref, path, jump);
+        }
+    });
+
+    FindInFilesPanel {
+        container: container.clone(),
+        entry,
+    }
+}
+
+fn append_find_in_files_results(list: &gtk::ListBox, targets: &FindTargets, root: &Path, found: find_in_files::FileMatches) {
+    let relative = found.path.strip_prefix(root).unwrap_or(&found.path);
+    let header = gtk::Label::new(Some(&format!("{} ({})", relative.display(), found.matches.len())));
+    header.set_halign(gtk::Align::Start);
+    header.set_css_classes(&["find-in-files-file"]);
+    let header_row = gtk::ListBoxRow::new();
+    header_row.set_child(Some(&header));
+    header_row.set_activatable(false);
+    list.append(&header_row);
+    targets.borrow_mut().push(None);
+
+    for line_match in found.matches {
+        let label = gtk::Label::new(Some(&format!("{}: {}", line_match.line + 1, line_match.preview)));
+        label.set_halign(gtk::Align::Start);
+        label.set_ellipsize(pango::EllipsizeMode::End);
+        label.set_margin_start(12);
+        list.append(&label);
+        targets.borrow_mut().push(Some((found.path.clone(), line_match.line, line_match.column)));
+    }
+}
+
+// Ctrl+Shift+F: show the panel and focus its search entry
+fn show_find_in_files() {
+    if let Some(build) = FIND_IN_FILES_BUILDER.with(|builder| builder.borrow_mut().take()) {
+        let panel = build();
+        FIND_IN_FILES_PANEL.with(|cell| *cell.borrow_mut() = Some(panel));
+    }
+    FIND_IN_FILES_PANEL.with(|panel| {
+        if let Some(panel) = panel.borrow().as_ref() {
+            panel.container.set_visible(true);
+            panel.entry.grab_focus();
+        }
+    });
+}
+
+// Most edits kept in the Recent Edits timeline
+const MAX_RECENT_EDITS: usize = 200;
+// Typing or deleting within this long of the previous edit, where it left off, extends it
+const RECENT_EDIT_MERGE_WINDOW: Duration = Duration::from_secs(2);
+// Characters of an edit shown in its timeline row
+const RECENT_EDIT_EXCERPT_CHARS: usize = 40;
+
+// One entry of the Recent Edits timeline. Its marks move with later edits, so jumping to
+// it still lands on the edited text.
+struct RecentEdit {
+    time: String,
+    last_change: Instant,
+    tab_name: String,
+    inserted: bool,
+    text: String,
+    buffer: gtk::TextBuffer,
+    start: gtk::TextMark,
+    end: gtk::TextMark,
+    // Still shown in the edit heat map, until its markers are cleared
+    heat_marked: bool,
+}
+
+impl RecentEdit {
+    // First line of the edit, shortened to fit its row
+    fn excerpt(&self) -> String {
+        let sign = if self.inserted { '+' } else { '−' };
+        let first = self.text.lines().next().unwrap_or("").trim();
+        let mut excerpt: String = first.chars().take(RECENT_EDIT_EXCERPT_CHARS).collect();
+        if excerpt.is_empty() {
+            excerpt.push_str(if self.text.contains('\n') { "line break" } else { "whitespace" });
+        } else if excerpt.chars().count() < self.text.trim().chars().count() {
+            excerpt.push('…');
+        }
+        format!("{} {}", sign, excerpt)
+    }
+}
+
+// The Recent Edits panel once it has been opened; rows are listed newest first
+struct RecentEditsPanel {
+    container: gtk::Box,
+    list: gtk::ListBox,
+}
+
+thread_local! {
+    // Edits made by the user in any tab, oldest first
+    static RECENT_EDITS: RefCell<VecDeque<RecentEdit>> = const { RefCell::new(VecDeque::new()) };
+    // Offset, text and kind of the edit the buffer is about to make, recorded once it is made
+    static PENDING_EDIT: RefCell<Option<(i32, String, bool)>> = const { RefCell::new(None) };
+    static RECENT_EDITS_PANEL: RefCell<Option<RecentEditsPanel>> = const { RefCell::new(None) };
+    // Fills in the panel the first time it is opened
+    static RECENT_EDITS_BUILDER: RefCell<Option<Box<dyn FnOnce() -> RecentEditsPanel>>> = const { RefCell::new(None) };
+    static RECENT_EDITS_REFRESH_QUEUED: Cell<bool> = const { Cell::new(false) };
+}
+
+// Record the user's edits of `buffer` for the timeline. Only changes made inside a user
+// action count, so loading files, undo and reloads stay out of it.
+fn connect_recent_edits(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let user_action_depth = Rc::new(Cell::new(0u32));
+    let depth = user_action_depth.clone();
+    buffer.connect_begin_user_action(move |_| depth.set(depth.get() + 1));
+    let depth = user_action_depth.clone();
+    buffer.connect_end_user_action(move |_| depth.set(depth.get().saturating_sub(1)));
+
+    // The buffer cannot be touched (not even by adding marks) before it makes the change,
+    // so the edit is only noted here and recorded from the changed signal
+    let depth = user_action_depth.clone();
+    buffer.connect_insert_text(move |_, location, text| {
+        if depth.get() > 0 {
+            PENDING_EDIT.with(|pending| *pending.borrow_mut() = Some((location.offset(), text.to_string(), true)));
+        }
+    });
+    let depth = user_action_depth;
+    buffer.connect_delete_range(move |buf, start, end| {
+        if depth.get() > 0 {
+            let text = buf.text(start, end, true).to_string();
+            PENDING_EDIT.with(|pending| *pending.borrow_mut() = Some((start.offset(), text, false)));
+        }
+    });
+
+    let text_view = text_view.clone();
+    let state = state.clone();
+    buffer.connect_changed(move |buf| {
+        let Some((offset, text, inserted)) = PENDING_EDIT.with(|pending| pending.borrow_mut().take()) else {
+            return;
+        };
+        // The shared state names the tab that is shown
+        let (tab_name, heat_map) = match state.try_borrow() {
+            Ok(state) if text_view.buffer() == *buf => (state.tab_name.clone(), state.persistent.edit_heat_map),
+            _ => (String::new(), false),
+        };
+        record_recent_edit(buf, &tab_name, offset, text, inserted);
+        if heat_map {
+            queue_gutter_redraw(&text_view, true);
+        }
+    });
+}
+
+// Lines of `buffer` in the edit heat map, with how recent their latest edit is: 1.0 for
+// the newest edit, down to a fraction of that for the oldest
+fn edit_heat(buffer: &gtk::TextBuffer) -> HashMap<i32, f64> {
+    RECENT_EDITS.with(|edits| {
+        let edits = edits.borrow();
+        let marked: Vec<&RecentEdit> = edits.iter().filter(|edit| edit.buffer == *buffer && edit.heat_marked).collect();
+        let mut heat = HashMap::new();
+        for (index, edit) in marked.iter().enumerate() {
+            let recency = (index + 1) as f64 / marked.len() as f64;
+            let first = buffer.iter_at_mark(&edit.start).line();
+            let last = buffer.iter_at_mark(&edit.end).line();
+            for line in first..=last {
+                heat.insert(line, recency);
+            }
+        }
+        heat
+    })
+}
+
+// View -> Clear edit markers: start the shown tab's heat map afresh. The edits stay in the
+// Recent Edits timeline.
+fn clear_edit_markers(text_view: &gtk::TextView) {
+    let buffer = text_view.buffer();
+    RECENT_EDITS.with(|edits| {
+        for edit in edits.borrow_mut().iter_mut().filter(|edit| edit.buffer == buffer) {
+            edit.heat_marked = false;
+        }
+    });
+    queue_gutter_redraw(text_view, true);
+}
+
+fn record_recent_edit(buffer: &gtk::TextBuffer, tab_name: &str, offset: i32, text: String, inserted: bool) {
+    RECENT_EDITS.with(|edits| {
+        let mut edits = edits.borrow_mut();
+
+        // Extend the previous edit when this one continues it: typing on at its end, or
+        // deleting forward or backspacing from where it stopped
+        if let Some(last) = edits.back_mut() {
+            if last.buffer == *buffer && last.inserted == inserted && last.last_change.elapsed() < RECENT_EDIT_MERGE_WINDOW {
+                let start = buffer.iter_at_mark(&last.start).offset();
+                let end = buffer.iter_at_mark(&last.end).offset();
+                let length = text.chars().count() as i32;
+                let merged = if inserted && offset + length == end || !inserted && offset == start {
+                    last.text.push_str(&text);
+                    true
+                } else if !inserted && offset + length == start {
+                    last.text.insert_str(0, &text);
+                    true
+                } else {
+                    false
+                };
+                if merged {
+                    last.last_change = Instant::now();
+                    last.heat_marked = true;
+                    return;
+                }
+            }
+        }
+
+        let start_iter = buffer.iter_at_offset(offset);
+        let end_iter = if inserted {
+            buffer.iter_at_offset(offset + text.chars().count() as i32)
+        } else {
+            start_iter
+        };
+        let time = glib::DateTime::now_local()
+            .and_then(|now| now.format("%H:%M:%S"))
+            .map(|time| time.to_string())
+            .unwrap_or_default();
+        edits.push_back(RecentEdit {
+            time,
+            last_change: Instant::now(),
+            tab_name: tab_name.to_string(),
+            inserted,
+            text,
+            buffer: buffer.clone(),
+            start: buffer.create_mark(None, &start_iter, true),
+            end: buffer.create_mark(None, &end_iter, false),
+            heat_marked: true,
+        });
+        while edits.len() > MAX_RECENT_EDITS {
+            if let Some(dropped) = edits.pop_front() {
+                dropped.buffer.delete_mark(&dropped.start);
+                dropped.buffer.delete_mark(&dropped.end);
+            }
+        }
+    });
+    queue_recent_edits_refresh();
+}
+
+// Refresh the panel once the current burst of edits is over, if it is shown
+fn queue_recent_edits_refresh() {
+    if RECENT_EDITS_REFRESH_QUEUED.with(|queued| queued.replace(true)) {
+        return;
+    }
+    glib::idle_add_local_once(|| {
+        RECENT_EDITS_REFRESH_QUEUED.with(|queued| queued.set(false));
+        refresh_recent_edits();
+    });
+}
+
+fn refresh_recent_edits() {
+    RECENT_EDITS_PANEL.with(|panel| {
+        let Some(panel) = panel.borrow().as_ref().map(|panel| panel.list.clone()) else {
+            return;
+        };
+        if !panel.is_visible() {
+            return;
+        }
+        while let Some(child) = panel.first_child() {
+            panel.remove(&child);
+        }
+        RECENT_EDITS.with(|edits| {
+            for edit in edits.borrow().iter().rev() {
+                let heading = gtk::Label::new(Some(&format!("{}  {}", edit.time, edit.tab_name)));
+                heading.set_halign(gtk::Align::Start);
+                heading.set_css_classes(&["dim-label"]);
+                let excerpt = gtk::Label::new(Some(&edit.excerpt()));
+                excerpt.set_halign(gtk::Align::Start);
+                excerpt.set_ellipsize(pango::EllipsizeMode::End);
+                excerpt.set_css_classes(&["recent-edit-excerpt"]);
+                let row = gtk::Box::new(gtk::Orientation::Vertical, 2);
+                row.append(&heading);
+                row.append(&excerpt);
+                panel.append(&row);
+            }
+        });
+    });
+}
+
+// Side panel listing the recent edits of every tab. Only the empty container is made here.
+fn create_recent_edits_panel(text_view: &gtk::TextView, tabs: &Rc<RefCell<TabManager>>) -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    container.set_css_classes(&["recent-edits-panel"]);
+    container.set_width_request(280);
+    container.set_visible(false);
+
+    let container_ref = container.clone();
+    let text_view = text_view.clone();
+    let tabs = tabs.clone();
+    RECENT_EDITS_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_recent_edits_panel(&container_ref, &text_view, &tabs)
+    })));
+    container
+}
+
+fn build_recent_edits_panel(container: &gtk::Box, text_view: &gtk::TextView, tabs: &Rc<RefCell<TabManager>>) -> RecentEditsPanel {
+    load_on_demand_css();
+
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let heading = gtk::Label::new(Some("Recent Edits"));
+    heading.set_halign(gtk::Align::Start);
+    heading.set_hexpand(true);
+    heading.set_css_classes(&["heading"]);
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Close panel"));
+    header.append(&heading);
+    header.append(&close_button);
+    container.append(&header);
+
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::None);
+    let scrolled = gtk::ScrolledWindow::new();
+    scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled.set_vexpand(true);
+    scrolled.set_child(Some(&list));
+    container.append(&scrolled);
+
+    let container_ref = container.clone();
+    close_button.connect_clicked(move |_| container_ref.set_visible(false));
+
+    // Activating an edit shows its tab and selects what was typed there
+    let text_view = text_view.clone();
+    let tabs = tabs.clone();
+    list.connect_row_activated(move |_, row| {
+        let target = RECENT_EDITS.with(|edits| {
+            let edits = edits.borrow();
+            let index = edits.len().checked_sub(row.index() as usize + 1)?;
+            let edit = edits.get(index)?;
+            Some((edit.buffer.clone(), edit.start.clone(), edit.end.clone()))
+        });
+        let Some((buffer, start, end)) = target else {
+            return;
+        };
+        // The edit's tab may have been closed since
+        let Some(tab_button) = tabs.borrow().button_for(&buffer) else {
+            return;
+        };
+        if text_view.buffer() != buffer {
+            tab_button.emit_clicked();
+        }
+        buffer.select_range(&buffer.iter_at_mark(&end), &buffer.iter_at_mark(&start));
+        text_view.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.3);
+        text_view.grab_focus();
+    });
+
+    RecentEditsPanel {
+        container: container.clone(),
+        list,
+    }
+}
+
+// View > Recent Edits: show the timeline, newest edit first
+fn show_recent_edits() {
+    if let Some(build) = RECENT_EDITS_BUILDER.with(|builder| builder.borrow_mut().take()) {
+        let panel = build();
+        RECENT_EDITS_PANEL.with(|cell| *cell.borrow_mut() = Some(panel));
+    }
+    RECENT_EDITS_PANEL.with(|panel| {
+        if let Some(panel) = panel.borrow().as_ref() {
+            panel.container.set_visible(true);
+        }
+    });
+    refresh_recent_edits();
+}
+
+// A bookmarked line, kept on its text by a mark at the line's start
+struct Bookmark {
+    buffer: gtk::TextBuffer,
+    mark: gtk::TextMark,
+}
+
+// The Bookmarks panel once it has been opened, with the bookmark each row jumps to
+struct BookmarksPanel {
+    container: gtk::Box,
+    list: gtk::ListBox,
+    targets: Rc<RefCell<Vec<(gtk::TextBuffer, gtk::TextMark)>>>,
+}
+
+thread_local! {
+    // Bookmarks of every tab
+    static BOOKMARKS: RefCell<Vec<Bookmark>> = const { RefCell::new(Vec::new()) };
+    static BOOKMARKS_PANEL: RefCell<Option<BookmarksPanel>> = const { RefCell::new(None) };
+    // Fills in the panel the first time it is opened
+    static BOOKMARKS_BUILDER: RefCell<Option<Box<dyn FnOnce() -> BookmarksPanel>>> = const { RefCell::new(None) };
+}
+
+// Bookmarked lines of `buffer`, top to bottom. Deleting the text between two bookmarks
+// leaves both on one line, which then counts once.
+fn bookmark_lines(buffer: &gtk::TextBuffer) -> Vec<i32> {
+    let mut lines: Vec<i32> = BOOKMARKS.with(|bookmarks| {
+        bookmarks.borrow()
+            .iter()
+            .filter(|bookmark| bookmark.buffer == *buffer)
+            .map(|bookmark| buffer.iter_at_mark(&bookmark.mark).line())
+            .collect()
+    });
+    lines.sort_unstable();
+    lines.dedup();
+    lines
+}
+
+// Replace the bookmarks of `buffer` with ones on `lines`, leaving out lines past its end
+fn set_bookmark_lines(buffer: &gtk::TextBuffer, lines: &[i32]) {
+    BOOKMARKS.with(|bookmarks| {
+        let mut bookmarks = bookmarks.borrow_mut();
+        bookmarks.retain(|bookmark| {
+            if bookmark.buffer != *buffer {
+                return true;
+            }
+            buffer.delete_mark(&bookmark.mark);
+            false
+        });
+        for &line in lines {
+            if let Some(line_start) = buffer.iter_at_line(line) {
+                let mark = buffer.create_mark(None, &line_start, true);
+                bookmarks.push(Bookmark { buffer: buffer.clone(), mark });
+            }
+        }
+    });
+}
+
+// Ctrl+F2: bookmark the cursor's line, or take its bookmark away
+fn toggle_bookmark(text_view: &gtk::TextView) {
+    let buffer = text_view.buffer();
+    let line = buffer.iter_at_mark(&buffer.get_insert()).line();
+    let mut lines = bookmark_lines(&buffer);
+    match lines.binary_search(&line) {
+        Ok(index) => {
+            lines.remove(index);
+        }
+        Err(index) => lines.insert(index, line),
+    }
+    set_bookmark_lines(&buffer, &lines);
+    queue_gutter_redraw(text_view, true);
+    refresh_bookmarks();
+}
+
+// F2 and Shift+F2: put the cursor on the next or previous bookmarked line of the shown
+// tab, going round from the last to the first
+fn step_bookmark(text_view: &gtk::TextView, forward: bool) {
+    step_to_line(text_view, &bookmark_lines(&text_view.buffer()), forward);
+}
+
+// Move the cursor to the first of the ordered `lines` after its line, or the last one before
+// it, going round at the end
+fn step_to_line(text_view: &gtk::TextView, lines: &[i32], forward: bool) {
+    let buffer = text_view.buffer();
+    let line = buffer.iter_at_mark(&buffer.get_insert()).line();
+    let target = if forward {
+        lines.iter().find(|&&candidate| candidate > line).or(lines.first())
+    } else {
+        lines.iter().rev().find(|&&candidate| candidate < line).or(lines.last())
+    };
+    if let Some(line_start) = target.and_then(|&target| buffer.iter_at_line(target)) {
+        buffer.place_cursor(&line_start);
+        text_view.scroll_to_mark(&buffer.get_insert(), 0.1, false, 0.0, 0.0);
+    }
+}
+
+// List the bookmarks of every tab in tab order, if the panel is shown. Bookmarks of tabs
+// closed since are dropped.
+fn refresh_bookmarks() {
+    let Some(summaries) = TABS.with(|tabs| tabs.borrow().as_ref().map(|tabs| tabs.borrow().summaries())) else {
+        return;
+    };
+    BOOKMARKS.with(|bookmarks| {
+        bookmarks.borrow_mut().retain(|bookmark| {
+            let open = summaries.iter().any(|tab| tab.buffer == bookmark.buffer);
+            if !open {
+                bookmark.buffer.delete_mark(&bookmark.mark);
+            }
+            open
+        });
+    });
+
+    BOOKMARKS_PANEL.with(|panel| {
+        let panel = panel.borrow();
+        let Some(panel) = panel.as_ref().filter(|panel| panel.container.is_visible()) else {
+            return;
+        };
+        while let Some(child) = panel.list.first_child() {
+            panel.list.remove(&child);
+        }
+        let mut targets = panel.targets.borrow_mut();
+        for (buffer, mark) in targets.drain(..) {
+            buffer.delete_mark(&mark);
+        }
+        for tab in &summaries {
+            for line in bookmark_lines(&tab.buffer) {
+                let Some(line_start) = tab.buffer.iter_at_line(line) else {
+                    continue;
+                };
+                let mut line_end = line_start;
+                if !line_end.ends_line() {
+                    line_end.forward_to_line_end();
+                }
+                let heading = gtk::Label::new(Some(&format!("{}  line {}", tab.name, line + 1)));
+                heading.set_halign(gtk::Align::Start);
+                heading.set_css_classes(&["dim-label"]);
+                let excerpt = gtk::Label::new(Some(tab.buffer.text(&line_start, &line_end, false).trim()));
+                excerpt.set_halign(gtk::Align::Start);
+                excerpt.set_ellipsize(pango::EllipsizeMode::End);
+                excerpt.set_css_classes(&["bookmark-excerpt"]);
+                let row = gtk::Box::new(gtk::Orientation::Vertical, 2);
+                row.append(&heading);
+                row.append(&excerpt);
+                panel.list.append(&row);
+                targets.push((tab.buffer.clone(), tab.buffer.create_mark(None, &line_start, true)));
+            }
+        }
+    });
+}
+
+// Side panel listing the bookmarks of every tab. Only the empty container is made here.
+fn create_bookmarks_panel(text_view: &gtk::TextView) -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    container.set_css_classes(&["bookmarks-panel"]);
+    container.set_width_request(280);
+    container.set_visible(false);
+
+    let container_ref = container.clone();
+    let text_view = text_view.clone();
+    BOOKMARKS_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_bookmarks_panel(&container_ref, &text_view)
+    })));
+    container
+}
+
+fn build_bookmarks_panel(container: &gtk::Box, text_view: &gtk::TextView) -> BookmarksPanel {
+    load_on_demand_css();
+
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let heading = gtk::Label::new(Some("Bookmarks"));
+    heading.set_halign(gtk::Align::Start);
+    heading.set_hexpand(true);
+    heading.set_css_classes(&["heading"]);
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Close panel"));
+    header.append(&heading);
+    header.append(&close_button);
+    container.append(&header);
+
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::None);
+    let scrolled = gtk::ScrolledWindow::new();
+    scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled.set_vexpand(true);
+    scrolled.set_child(Some(&list));
+    container.append(&scrolled);
+
+    let container_ref = container.clone();
+    close_button.connect_clicked(move |_| container_ref.set_visible(false));
+
+    // Activating a bookmark shows its tab with the cursor on the bookmarked line
+    let targets: Rc<RefCell<Vec<(gtk::TextBuffer, gtk::TextMark)>>> = Rc::new(RefCell::new(Vec::new()));
+    let targets_ref = targets.clone();
+    let text_view = text_view.clone();
+    list.connect_row_activated(move |_, row| {
+        let Some((buffer, mark)) = targets_ref.borrow().get(row.index() as usize).cloned() else {
+            return;
+        };
+        if !show_tab_of(&text_view, &buffer) {
+            return;
+        }
+        buffer.place_cursor(&buffer.iter_at_mark(&mark));
+        text_view.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.3);
+        text_view.grab_focus();
+    });
+
+    BookmarksPanel {
+        container: container.clone(),
+        list,
+        targets,
+    }
+}
+
+// View > Bookmarks: show the bookmarks of all tabs
+fn show_bookmarks() {
+    if let Some(build) = BOOKMARKS_BUILDER.with(|builder| builder.borrow_mut().take()) {
+        let panel = build();
+        BOOKMARKS_PANEL.with(|cell| *cell.borrow_mut() = Some(panel));
+    }
+    BOOKMARKS_PANEL.with(|panel| {
+        if let Some(panel) = panel.borrow().as_ref() {
+            panel.container.set_visible(true);
+        }
+    });
+    refresh_bookmarks();
+}
+
+// One match of a Find All, kept on its text while the buffer is edited
+struct SearchResult {
+    start: gtk::TextMark,
+    end: gtk::TextMark,
+}
+
+struct SearchResultsPanel {
+    container: gtk::Box,
+    expander: gtk::Expander,
+    list: gtk::ListBox,
+}
+
+thread_local! {
+    // Results of the last Find All and the buffer they are in
+    static SEARCH_RESULTS: RefCell<(Option<gtk::TextBuffer>, Vec<SearchResult>)> = const { RefCell::new((None, Vec::new())) };
+    static SEARCH_RESULTS_PANEL: RefCell<Option<SearchResultsPanel>> = const { RefCell::new(None) };
+    static SEARCH_RESULTS_BUILDER: RefCell<Option<Box<dyn FnOnce() -> SearchResultsPanel>>> = const { RefCell::new(None) };
+    // Overview ruler beside the editor, marking where the results are in the whole file
+    static SEARCH_RULER: RefCell<Option<gtk::DrawingArea>> = const { RefCell::new(None) };
+}
+
+fn create_search_results_panel(text_view: &gtk::TextView) -> gtk::Box {
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    container.set_css_classes(&["search-results-panel"]);
+    container.set_visible(false);
+
+    let container_ref = container.clone();
+    let text_view = text_view.clone();
+    SEARCH_RESULTS_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_search_results_panel(&container_ref, &text_view)
+    })));
+    container
+}
+
+fn build_search_results_panel(container: &gtk::Box, text_view: &gtk::TextView) -> SearchResultsPanel {
+    load_on_demand_css();
+
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let expander = gtk::Expander::new(None);
+    expander.set_hexpand(true);
+    expander.set_expanded(true);
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Close results"));
+    close_button.set_valign(gtk::Align::Start);
+    header.append(&expander);
+    header.append(&close_button);
+    container.append(&header);
+
+    // Up/Down move through the results and Enter jumps to one
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::Browse);
+    let scrolled = gtk::ScrolledWindow::new();
+    scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled.set_propagate_natural_height(true);
+    scrolled.set_max_content_height(160);
+    scrolled.set_child(Some(&list));
+    expander.set_child(Some(&scrolled));
+
+    close_button.connect_clicked(|_| clear_search_results());
+
     let text_view_ref = text_view.clone();
-    let buffer_clone = buffer.clone();
-    let tab_button_wrapper_clone = tab_button_wrapper.clone();
-    
-    tab_button_wrapper.connect_clicked(move |clicked_button| {
-        // Set this tab as active
-        clicked_button.set_css_classes(&["tab-button-wrapper", "active"]);
-        
-        // Switch to this tab's buffer
-        text_view_ref.set_buffer(Some(&buffer_clone));
+    list.connect_row_activated(move |_, row| {
+        jump_to_search_result(&text_view_ref, row.index() as usize);
     });
-    
-    // Make the close button for the first tab work
-    let buffer_clone = buffer.clone();
-    let editor_state_ref = editor_state.clone();
-    
-    // Create a gesture controller for the first tab's close button
-    let first_click_controller = gtk::GestureClick::new();
-    first_click_controller.set_button(1); // Left mouse button
-    first_click_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
-    close_icon.add_controller(first_click_controller.clone());
-    
-    let buffer_clone = buffer.clone();
-    let editor_state_ref = editor_state.clone();
+
+    SearchResultsPanel {
+        container: container.clone(),
+        expander,
+        list,
+    }
+}
+
+// Narrow strip beside the editor; each result is a tick at its place in the file, and
+// clicking the strip jumps to the nearest result
+fn create_search_ruler(text_view: &gtk::TextView) -> gtk::DrawingArea {
+    let ruler = gtk::DrawingArea::new();
+    ruler.set_content_width(12);
+    ruler.set_vexpand(true);
+    ruler.set_css_classes(&["search-ruler"]);
+    ruler.set_visible(false);
+    ruler.set_tooltip_text(Some("Search results in this file"));
+
     let text_view_ref = text_view.clone();
-    
-    first_click_controller.connect_pressed(move |gesture, _, _, _| {
-        debug!("First tab X button clicked");
+    ruler.set_draw_func(move |_, cr, width, height| {
+        let buffer = text_view_ref.buffer();
+        let lines = search_result_lines(&buffer);
+        if lines.is_empty() {
+            return;
+        }
+        let color = CURRENT_THEME.with(|theme| {
+            gtk::gdk::RGBA::parse(theme.borrow().search_match.as_str()).unwrap_or(gtk::gdk::RGBA::WHITE)
+        });
+        cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, color.alpha() as f64);
+        let line_count = buffer.line_count().max(1) as f64;
+        for line in lines {
+            let y = (line as f64 + 0.5) / line_count * height as f64;
+            cr.rectangle(2.0, (y - 1.5).max(0.0), (width - 4).max(1) as f64, 3.0);
+        }
+        let _ = cr.fill();
+    });
+
+    let click = gtk::GestureClick::new();
+    let text_view_ref = text_view.clone();
+    let ruler_ref = ruler.clone();
+    click.connect_pressed(move |_, _, _, y| {
+        let buffer = text_view_ref.buffer();
+        let target = y / ruler_ref.height().max(1) as f64 * buffer.line_count() as f64;
+        let nearest = search_result_lines(&buffer)
+            .into_iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| (*a as f64 - target).abs().total_cmp(&(*b as f64 - target).abs()))
+            .map(|(index, _)| index);
+        if let Some(index) = nearest {
+            jump_to_search_result(&text_view_ref, index);
+        }
+    });
+    ruler.add_controller(click);
+
+    SEARCH_RULER.with(|cell| *cell.borrow_mut() = Some(ruler.clone()));
+    ruler
+}
+
+fn queue_search_ruler_redraw() {
+    SEARCH_RULER.with(|ruler| {
+        if let Some(ruler) = ruler.borrow().as_ref() {
+            if ruler.is_visible() {
+                ruler.queue_draw();
+            }
+        }
+    });
+}
+
+// Current line of every result, if the results are in `buffer`
+fn search_result_lines(buffer: &gtk::TextBuffer) -> Vec<i32> {
+    SEARCH_RESULTS.with(|results| {
+        let results = results.borrow();
+        if results.0.as_ref() != Some(buffer) {
+            return Vec::new();
+        }
+        results.1.iter().map(|result| buffer.iter_at_mark(&result.start).line()).collect()
+    })
+}
+
+fn jump_to_search_result(text_view: &gtk::TextView, index: usize) {
+    let buffer = text_view.buffer();
+    let target = SEARCH_RESULTS.with(|results| {
+        let results = results.borrow();
+        if results.0.as_ref() != Some(&buffer) {
+            return None;
+        }
+        results.1.get(index).map(|result| (buffer.iter_at_mark(&result.start), buffer.iter_at_mark(&result.end)))
+    });
+    let Some((start, end)) = target else {
+        return;
+    };
+    buffer.select_range(&start, &end);
+    text_view.scroll_to_mark(&buffer.get_insert(), 0.1, true, 0.0, 0.3);
+
+    // Keep the panel's selection in step with ruler clicks
+    SEARCH_RESULTS_PANEL.with(|panel| {
+        if let Some(panel) = panel.borrow().as_ref() {
+            if let Some(row) = panel.list.row_at_index(index as i32) {
+                panel.list.select_row(Some(&row));
+            }
+        }
+    });
+}
+
+// Find All: list every match of `ranges` (byte ranges of `text`, the buffer's text) with
+// its line, and mark them in the ruler
+fn show_search_results(text_view: &gtk::TextView, pattern: &str, text: &str, ranges: &[Range<usize>]) {
+    clear_search_results();
+    if let Some(build) = SEARCH_RESULTS_BUILDER.with(|builder| builder.borrow_mut().take()) {
+        let panel = build();
+        SEARCH_RESULTS_PANEL.with(|cell| *cell.borrow_mut() = Some(panel));
+    }
+
+    let buffer = text_view.buffer();
+    let results: Vec<SearchResult> = ranges
+        .iter()
+        .map(|range| SearchResult {
+            start: buffer.create_mark(None, &iter_at_byte(&buffer, text, range.start), true),
+            end: buffer.create_mark(None, &iter_at_byte(&buffer, text, range.end), false),
+        })
+        .collect();
+
+    SEARCH_RESULTS_PANEL.with(|panel| {
+        let panel = panel.borrow();
+        let Some(panel) = panel.as_ref() else {
+            return;
+        };
+        let noun = if ranges.len() == 1 { "match" } else { "matches" };
+        panel.expander.set_label(Some(&format!("{} {} for \u{201c}{}\u{201d}", ranges.len(), noun, pattern)));
+        for range in ranges {
+            let line_start = text[..range.start].rfind('\n').map_or(0, |index| index + 1);
+            let line_end = text[range.start..].find('\n').map_or(text.len(), |index| range.start + index);
+            let line_number = text[..range.start].matches('\n').count() + 1;
+            let context: String = text[line_start..line_end].trim().chars().take(120).collect();
+
+            let row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            let number = gtk::Label::new(Some(&line_number.to_string()));
+            number.set_css_classes(&["dim-label"]);
+            number.set_width_chars(6);
+            number.set_xalign(1.0);
+            let context = gtk::Label::new(Some(&context));
+            context.set_css_classes(&["search-result-context"]);
+            context.set_halign(gtk::Align::Start);
+            context.set_ellipsize(pango::EllipsizeMode::End);
+            row.append(&number);
+            row.append(&context);
+            panel.list.append(&row);
+        }
+        panel.container.set_visible(true);
+        panel.expander.set_expanded(true);
+        if let Some(first) = panel.list.row_at_index(0) {
+            panel.list.select_row(Some(&first));
+            first.grab_focus();
+        }
+    });
+
+    SEARCH_RESULTS.with(|cell| *cell.borrow_mut() = (Some(buffer), results));
+    SEARCH_RULER.with(|ruler| {
+        if let Some(ruler) = ruler.borrow().as_ref() {
+            ruler.set_visible(!ranges.is_empty());
+            ruler.queue_draw();
+        }
+    });
+}
+
+// Drop the results of the last Find All, with their marks, list and ruler
+fn clear_search_results() {
+    let (buffer, results) = SEARCH_RESULTS.with(|cell| cell.take());
+    if let Some(buffer) = buffer {
+        for result in results {
+            buffer.delete_mark(&result.start);
+            buffer.delete_mark(&result.end);
+        }
+    }
+    SEARCH_RESULTS_PANEL.with(|panel| {
+        if let Some(panel) = panel.borrow().as_ref() {
+            while let Some(child) = panel.list.first_child() {
+                panel.list.remove(&child);
+            }
+            panel.container.set_visible(false);
+        }
+    });
+    SEARCH_RULER.with(|ruler| {
+        if let Some(ruler) = ruler.borrow().as_ref() {
+            ruler.set_visible(false);
+        }
+    });
+}
+
+thread_local! {
+    // Draws the block selection of the shown tab over the text
+    static BLOCK_OVERLAY: RefCell<Option<gtk::DrawingArea>> = const { RefCell::new(None) };
+    // Text of the last block copied, so pasting it goes in column-wise
+    static BLOCK_CLIPBOARD: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Column selection: Alt+drag or Shift+Alt+arrows select a block, typing and Backspace/Delete
+// act on every line of it, and cut/copy/paste work column-wise. GTK only knows ranges, so
+// the block lives in the tab's EditorBuffer and is drawn by an overlay of the view.
+fn connect_block_selection(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let overlay = gtk::DrawingArea::new();
+    overlay.set_can_target(false);
+    overlay.set_visible(false);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    overlay.set_draw_func(move |_, cr, _, _| {
+        let rects = block_rects(&text_view_ref, &state_ref);
+        let Some(origin) = rects.iter().copied().reduce(|union, rect| union.union(&rect)) else {
+            return;
+        };
+        let color = CURRENT_THEME.with(|theme| {
+            gtk::gdk::RGBA::parse(theme.borrow().block_selection.as_str()).unwrap_or(gtk::gdk::RGBA::BLUE)
+        });
+        cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, color.alpha() as f64);
+        for rect in rects {
+            cr.rectangle((rect.x() - origin.x()) as f64, (rect.y() - origin.y()) as f64, rect.width() as f64, rect.height() as f64);
+        }
+        let _ = cr.fill();
+    });
+    text_view.add_overlay(&overlay, 0, 0);
+    BLOCK_OVERLAY.with(|cell| *cell.borrow_mut() = Some(overlay));
+
+    // Alt+drag selects a block; any other press drops it
+    let drag = gtk::GestureDrag::new();
+    drag.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let dragging = Rc::new(Cell::new(false));
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    let dragging_ref = dragging.clone();
+    drag.connect_drag_begin(move |gesture, x, y| {
+        if !gesture.current_event_state().contains(gtk::gdk::ModifierType::ALT_MASK) {
+            if state_ref.borrow().text_buffer.block_selection().is_some() {
+                set_block_selection(&text_view_ref, &state_ref, None);
+            }
+            return;
+        }
         gesture.set_state(gtk::EventSequenceState::Claimed);
-        
-        // Ask if they want to close the tab if content is modified
-        if let Ok(state) = editor_state_ref.lock() {
-            if state.is_modified {
-                debug!("First tab has modified content, just clearing instead of closing");
-                buffer_clone.set_text("");
-                return;
+        dragging_ref.set(true);
+        text_view_ref.grab_focus();
+        let corner = block_position_at(&text_view_ref, x, y);
+        set_block_selection(&text_view_ref, &state_ref, Some(BlockSelection { anchor: corner, head: corner }));
+    });
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    let dragging_ref = dragging.clone();
+    drag.connect_drag_update(move |gesture, dx, dy| {
+        let Some((x, y)) = gesture.start_point().filter(|_| dragging_ref.get()) else {
+            return;
+        };
+        let Some(block) = state_ref.borrow().text_buffer.block_selection() else {
+            return;
+        };
+        let head = block_position_at(&text_view_ref, x + dx, y + dy);
+        if head != block.head {
+            set_block_selection(&text_view_ref, &state_ref, Some(BlockSelection { anchor: block.anchor, head }));
+        }
+    });
+    drag.connect_drag_end(move |_, _, _| dragging.set(false));
+    text_view.add_controller(drag);
+
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let text_view_ref = text_view.clone();
+    let state_ref = state.clone();
+    key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+        let control = modifiers.contains(gtk::gdk::ModifierType::CONTROL_MASK);
+        let alt = modifiers.contains(gtk::gdk::ModifierType::ALT_MASK);
+        let shift = modifiers.contains(gtk::gdk::ModifierType::SHIFT_MASK);
+        let block = state_ref.borrow().text_buffer.block_selection();
+
+        // Shift+Alt+arrows grow the block from the cursor
+        if shift && alt && !control && matches!(key, Key::Up | Key::Down | Key::Left | Key::Right) {
+            let buffer = text_view_ref.buffer();
+            let block = block.unwrap_or_else(|| {
+                let cursor = buffer.iter_at_mark(&buffer.get_insert());
+                let corner = (cursor.line() as usize, grapheme_column(&cursor));
+                BlockSelection { anchor: corner, head: corner }
+            });
+            let (line, column) = block.head;
+            let last_line = (buffer.line_count() - 1).max(0) as usize;
+            let head = match key {
+                Key::Up => (line.saturating_sub(1), column),
+                Key::Down => ((line + 1).min(last_line), column),
+                Key::Left => (line, column.saturating_sub(1)),
+                _ => (line, column + 1),
+            };
+            set_block_selection(&text_view_ref, &state_ref, Some(BlockSelection { anchor: block.anchor, head }));
+            return glib::Propagation::Stop;
+        }
+
+        let shortcut = |letter: Key| control && !alt && key.to_lower() == letter;
+        if shortcut(Key::v) {
+            paste_block(&text_view_ref, &state_ref);
+            return glib::Propagation::Stop;
+        }
+        if block.is_none() || is_modifier_key(key) {
+            return glib::Propagation::Proceed;
+        }
+
+        if shortcut(Key::c) || shortcut(Key::x) {
+            let text = state_ref.borrow().text_buffer.block_text();
+            text_view_ref.clipboard().set_text(&text);
+            remember_clipboard_text(&text);
+            BLOCK_CLIPBOARD.with(|clipboard| *clipboard.borrow_mut() = Some(text));
+            if shortcut(Key::x) {
+                edit_block(&text_view_ref, &state_ref, |buffer| buffer.replace_block(""));
             }
+            return glib::Propagation::Stop;
         }
-        
-        debug!("Clearing content of first tab (not removing it as it's the primary tab)");
-        // Just clear the content of this tab as it's the main tab
-        // We don't actually remove this tab as it's the primary one
-        buffer_clone.set_text("");
-        
-        // Reset any file association
-        if let Ok(mut state) = editor_state_ref.lock() {
-            state.current_file = None;
-            state.is_modified = false;
-            state.update_tab_name();
+        if control || alt {
+            return glib::Propagation::Proceed;
         }
-        
-        // Ensure we're showing the first tab's buffer
-        text_view_ref.set_buffer(Some(&buffer_clone));
+        match key {
+            Key::Escape => set_block_selection(&text_view_ref, &state_ref, None),
+            Key::BackSpace => edit_block(&text_view_ref, &state_ref, |buffer| buffer.delete_block(false)),
+            Key::Delete | Key::KP_Delete => edit_block(&text_view_ref, &state_ref, |buffer| buffer.delete_block(true)),
+            _ => match key.to_unicode().filter(|ch| !ch.is_control() || *ch == '\t') {
+                Some(typed) => {
+                    let typed = typed.to_string();
+                    edit_block(&text_view_ref, &state_ref, |buffer| buffer.replace_block(&typed));
+                }
+                // Moving the cursor leaves block mode
+                None => {
+                    set_block_selection(&text_view_ref, &state_ref, None);
+                    return glib::Propagation::Proceed;
+                }
+            },
+        }
+        glib::Propagation::Stop
     });
-    
-    // Set up a timer to update the tab label when state changes (like when a file is opened)
-    let editor_state_ref = editor_state.clone();
-    let tab_label_ref = tab_label.clone();
-    
-    let timeout_id = glib::timeout_add_local(Duration::from_millis(500), move || {
-        if let Ok(state) = editor_state_ref.lock() {
-            tab_label_ref.set_text(&state.tab_name);
+    text_view.add_controller(key_controller);
+}
+
+// Select `block` in the shown tab, putting the view's cursor at its head
+fn set_block_selection(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, block: Option<BlockSelection>) {
+    state.borrow_mut().text_buffer.set_block_selection(block);
+    if let Some(block) = block {
+        let buffer = text_view.buffer();
+        if let Some(mut head) = buffer.iter_at_line(block.head.0 as i32) {
+            for _ in 0..block.head.1 {
+                if head.ends_line() {
+                    break;
+                }
+                head.forward_cursor_position();
+            }
+            buffer.place_cursor(&head);
+            text_view.scroll_mark_onscreen(&buffer.get_insert());
         }
-        // Continue the timer
-        glib::ControlFlow::Continue
+    }
+    update_block_overlay(text_view, state);
+}
+
+// Apply a block edit planned on a copy of the tab's EditorBuffer to the view's buffer, where
+// the usual mirroring brings it back into the state, then restore the block
+fn edit_block(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, edit: impl FnOnce(&mut EditorBuffer) -> Option<(Range<usize>, String)>) {
+    let planned = {
+        let state = state.borrow();
+        let original = &state.text_buffer;
+        let mut planned = original.clone();
+        edit(&mut planned).map(|(span, replacement)| {
+            let start = original.char_index(span.start) as i32;
+            let end = original.char_index(span.end) as i32;
+            let cursor = planned.char_index(planned.cursor_position()) as i32;
+            (start, end, replacement, cursor, planned.block_selection())
+        })
+    };
+    let Some((start, end, replacement, cursor, block)) = planned else {
+        return;
+    };
+
+    let buffer = text_view.buffer();
+    buffer.begin_user_action();
+    let mut start = buffer.iter_at_offset(start);
+    let mut end = buffer.iter_at_offset(end);
+    buffer.delete(&mut start, &mut end);
+    buffer.insert(&mut start, &replacement);
+    buffer.end_user_action();
+    buffer.place_cursor(&buffer.iter_at_offset(cursor));
+
+    state.borrow_mut().text_buffer.set_block_selection(block);
+    update_block_overlay(text_view, state);
+}
+
+// Ctrl+V: a block copied here goes in column-wise at the cursor or block, anything else
+// is pasted as usual
+fn paste_block(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let text_view = text_view.clone();
+    let state = state.clone();
+    text_view.clipboard().read_text_async(None::<&gtk::gio::Cancellable>, move |result| {
+        let Ok(Some(text)) = result else {
+            return;
+        };
+        let copied_block = BLOCK_CLIPBOARD.with(|clipboard| clipboard.borrow().as_deref() == Some(text.as_str()));
+        let block = state.borrow().text_buffer.block_selection();
+        if !copied_block && block.is_none() {
+            text_view.emit_paste_clipboard();
+            return;
+        }
+        if block.is_none() {
+            let buffer = text_view.buffer();
+            let cursor = buffer.iter_at_mark(&buffer.get_insert());
+            let corner = (cursor.line() as usize, grapheme_column(&cursor));
+            state.borrow_mut().text_buffer.set_block_selection(Some(BlockSelection { anchor: corner, head: corner }));
+        }
+        edit_block(&text_view, &state, |buffer| buffer.replace_block(&text));
     });
-    
-    // Store the timeout ID
-    if let Ok(mut state) = editor_state.lock() {
-        state.timeout_id = Some(timeout_id);
+}
+
+// Most texts the clipboard ring keeps
+const CLIPBOARD_RING_SIZE: usize = 20;
+
+thread_local! {
+    // Texts copied, cut or pasted over lately, most recent first
+    static CLIPBOARD_RING: RefCell<VecDeque<String>> = const { RefCell::new(VecDeque::new()) };
+}
+
+// Put `text` at the front of the clipboard ring
+fn remember_clipboard_text(text: &str) {
+    if text.is_empty() {
+        return;
     }
-    
-    // Add right-click context menu for the first tab
-    let gesture = gtk::GestureClick::new();
-    gesture.set_button(3); // Right mouse button
-    
-    let tab_button_wrapper_ref = tab_button_wrapper.clone();
-    // Create a fresh buffer clone for this closure
-    let buffer_for_context = buffer.clone();
-    
-    gesture.connect_pressed(move |_, _, _, _| {
-        let popover = gtk::Popover::new();
-        popover.set_parent(&tab_button_wrapper_ref);
-        
-        let box_container = gtk::Box::new(gtk::Orientation::Vertical, 5);
-        box_container.set_margin_top(5);
-        box_container.set_margin_bottom(5);
-        box_container.set_margin_start(5);
-        box_container.set_margin_end(5);
-        
-        // Clear tab content option
-        let clear_item = gtk::Button::new();
-        clear_item.set_label("Clear Content");
-        clear_item.set_css_classes(&["menu-item"]);
-        clear_item.set_has_frame(false);
-        
-        // Use clone specific to this inner closure
-        let buffer_for_clear = buffer_for_context.clone();
-        let popover_ref = popover.clone();
-        
-        let clear_item_clone = clear_item.clone();
-        clear_item.connect_clicked(move |_| {
-            buffer_for_clear.set_text("");
-            popover_ref.popdown();
-        });
-        
-        box_container.append(&clear_item_clone);
-        
-        popover.set_child(Some(&box_container));
-        popover.popup();
+    CLIPBOARD_RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        ring.retain(|kept| kept != text);
+        ring.push_front(text.to_string());
+        ring.truncate(CLIPBOARD_RING_SIZE);
     });
-    
-    tab_button_wrapper.add_controller(gesture);
-    
-    // Connect the + button to create a new tab
-    let tabs_box_ref = tabs_box.clone();
-    let new_tab_button_ref = new_tab_button.clone();
-    let editor_state_ref = editor_state.clone();
-    let text_view_ref = text_view.clone();
-    let tab_button_wrapper_ref = tab_button_wrapper.clone();
-    // Create a fresh owned buffer for the new tab handler
-    let buffer_for_new_tab = buffer.clone();
-    
-    new_tab_button.connect_clicked(move |_| {
-        // Create a new buffer with syntax highlighting
-        let tag_table = create_tag_table();
-        let new_buffer = TextBuffer::new(Some(&tag_table));
-        
-        // Generate tab ID
-        let tab_id = {
-            if let Ok(mut state) = editor_state_ref.lock() {
-                state.active_tab_id += 1;
-                state.active_tab_id
-            } else {
-                0
+}
+
+fn selected_text(buffer: &gtk::TextBuffer) -> Option<String> {
+    let (start, end) = buffer.selection_bounds()?;
+    Some(buffer.text(&start, &end, false).to_string())
+}
+
+// Keep what is copied or cut in the clipboard ring, and the selection a paste replaces, so
+// that text can be pasted again from the history
+fn connect_clipboard_ring(text_view: &gtk::TextView) {
+    let remember_selection = |text_view: &gtk::TextView| {
+        if let Some(text) = selected_text(&text_view.buffer()) {
+            remember_clipboard_text(&text);
+        }
+    };
+    text_view.connect_copy_clipboard(remember_selection);
+    text_view.connect_cut_clipboard(remember_selection);
+    text_view.connect_paste_clipboard(remember_selection);
+}
+
+// With the setting on, also keep text other applications put on the clipboard while the
+// window is focused, and what they left there when it gets the focus back
+fn connect_external_clipboard(window: &gtk::ApplicationWindow, state: &Rc<RefCell<EditorState>>) {
+    let clipboard = window.clipboard();
+    let remember_external = {
+        let (window, state) = (window.clone(), state.clone());
+        move |clipboard: &gtk::gdk::Clipboard| {
+            let enabled = state.try_borrow().is_ok_and(|state| state.settings.clipboard_history_external);
+            // The editor's own copies are already in the ring
+            if !enabled || !window.is_active() || clipboard.is_local() {
+                return;
             }
-        };
-        
-        // Create new tab with initial opacity of 0
-        let new_tab_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
-        new_tab_box.set_css_classes(&["tab-button"]);
-        new_tab_box.set_opacity(0.0);
-        create_tab_transition(&new_tab_box);
-        
-        let new_tab_label = gtk::Label::new(Some(&format!("Untitled {}", tab_id)));
-        new_tab_label.set_css_classes(&["tab-label"]);
-        new_tab_label.set_ellipsize(pango::EllipsizeMode::End);
-        new_tab_label.set_width_chars(15);
-        new_tab_label.set_max_width_chars(15);
-        
-        let new_close_icon = gtk::Button::new();
-        new_close_icon.set_css_classes(&["tab-close-button"]);
-        new_close_icon.set_icon_name("window-close-symbolic");
-        new_close_icon.set_tooltip_text(Some("Close tab"));
-        
-        new_tab_box.append(&new_tab_label);
-        new_tab_box.append(&new_close_icon);
-        
-        let new_tab_wrapper = gtk::Button::new();
-        new_tab_wrapper.set_css_classes(&["tab-button-wrapper"]);
-        new_tab_wrapper.set_has_frame(false);
-        new_tab_wrapper.set_child(Some(&new_tab_box));
-        
-        // Add the tab to the box first
-        tabs_box_ref.remove(&new_tab_button_ref);
-        tabs_box_ref.append(&new_tab_wrapper);
-        tabs_box_ref.append(&new_tab_button_ref);
-        
-        // Use a timeout to trigger the fade-in
-        glib::timeout_add_local(Duration::from_millis(50), move || {
-            new_tab_box.set_opacity(1.0);
-            glib::ControlFlow::Break
-        });
-        
-        // Connect close button - we need a fresh buffer for each tab
-        let tabs_box_ref_clone = tabs_box_ref.clone();
-        let new_tab_wrapper_clone = new_tab_wrapper.clone();
-        let text_view_ref_clone = text_view_ref.clone();
-        // Create a fresh buffer clone specific to this closure
-        let buffer_for_close = buffer_for_new_tab.clone();
-        let tab_button_wrapper_ref_clone = tab_button_wrapper_ref.clone();
-        
-        // CRITICAL: Create separate click controller for close button to ensure clicks are captured
-        let click_controller = gtk::GestureClick::new();
-        click_controller.set_button(1); // Left mouse button
-        click_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
-        new_close_icon.add_controller(click_controller.clone());
-        
-        let tabs_box_ref_clone = tabs_box_ref.clone();
-        let new_tab_wrapper_clone = new_tab_wrapper.clone();
-        let text_view_ref_clone = text_view_ref.clone();
-        let buffer_for_close = buffer_for_new_tab.clone();
-        let tab_button_wrapper_ref_clone = tab_button_wrapper_ref.clone();
-        
-        click_controller.connect_pressed(move |gesture, _, _, _| {
-            debug!("Tab X button clicked");
-            gesture.set_state(gtk::EventSequenceState::Claimed);
-            
-            // Check if this is the active tab
-            let is_active = new_tab_wrapper_clone.css_classes().iter().any(|class| class == "active");
-            debug!("Is active tab: {}", is_active);
-            
-            // Create fade-out transition
-            create_tab_transition(&new_tab_wrapper_clone);
-            
-            // Start the fade-out
-            new_tab_wrapper_clone.set_opacity(0.0);
-            
-            // Clone all the necessary variables for the inner closure
-            let tabs_box_ref_inner = tabs_box_ref_clone.clone();
-            let new_tab_wrapper_inner = new_tab_wrapper_clone.clone();
-            let text_view_ref_inner = text_view_ref_clone.clone();
-            let buffer_for_close_inner = buffer_for_close.clone();
-            let tab_button_wrapper_ref_inner = tab_button_wrapper_ref_clone.clone();
-            let is_active_inner = is_active;
-            
-            glib::timeout_add_local(Duration::from_millis(150), move || {
-                // Remove the tab after animation completes
-                tabs_box_ref_inner.remove(&new_tab_wrapper_inner);
-                
-                // Check if the tab was actually removed
-                if new_tab_wrapper_inner.parent().is_some() {
-                    warn!("Tab wasn't removed properly, it still has a parent");
-                } else {
-                    debug!("Tab was successfully removed");
-                }
-                
-                // If this was the active tab, switch back to the first tab
-                if is_active_inner {
-                    debug!("Switching back to first tab since active tab was closed");
-                    text_view_ref_inner.set_buffer(Some(&buffer_for_close_inner));
-                    tab_button_wrapper_ref_inner.set_css_classes(&["tab-button-wrapper", "active"]);
+            clipboard.read_text_async(gio::Cancellable::NONE, |text: Result<Option<glib::GString>, glib::Error>| {
+                if let Ok(Some(text)) = text {
+                    remember_clipboard_text(&text);
                 }
-                
-                glib::ControlFlow::Break
             });
-        });
-        
-        // Connect tab button to switch to this tab
-        let new_buffer_clone = new_buffer.clone();
-        let text_view_ref_clone = text_view_ref.clone();
-        let tab_button_wrapper_clone = tab_button_wrapper_ref.clone();
-        
-        new_tab_wrapper.connect_clicked(move |clicked_button| {
-            // Set all tabs to inactive (simplified approach)
-            if let Some(parent) = clicked_button.parent() {
-                if let Some(box_parent) = parent.downcast_ref::<gtk::Box>() {
-                    // Find all buttons in the tabs box and set them to inactive
-                    let n_children = box_parent.first_child()
-                        .map(|_| {
-                            let mut count = 0;
-                            let mut child = box_parent.first_child();
-                            while let Some(widget) = child {
-                                count += 1;
-                                child = widget.next_sibling();
-                            }
-                            count
-                        })
-                        .unwrap_or(0);
-
-                    let mut child = box_parent.first_child();
-                    for _ in 0..n_children {
-                        if let Some(widget) = child.clone() {
-                            if let Some(button) = widget.downcast_ref::<gtk::Button>() {
-                                // Don't compare pointers, just set all to inactive
-                                button.set_css_classes(&["tab-button-wrapper"]);
-                            }
-                            child = widget.next_sibling();
-                        }
-                    }
-                }
+        }
+    };
+    let remember_ref = remember_external.clone();
+    clipboard.connect_changed(move |clipboard| remember_ref(clipboard));
+    window.connect_is_active_notify(move |window| remember_external(&window.clipboard()));
+}
+
+// Replace the selection, or insert at the cursor, with `text` as one undo step, leaving
+// `text` selected
+fn replace_selection(buffer: &gtk::TextBuffer, text: &str) {
+    buffer.begin_user_action();
+    buffer.delete_selection(true, true);
+    let start = buffer.iter_at_mark(&buffer.get_insert()).offset();
+    buffer.insert_at_cursor(text);
+    buffer.end_user_action();
+    let end = buffer.iter_at_mark(&buffer.get_insert());
+    buffer.select_range(&end, &buffer.iter_at_offset(start));
+}
+
+// Ctrl+Shift+V: pick an earlier text of the clipboard ring and paste it, making it the
+// clipboard's text again
+fn show_clipboard_history(text_view: &gtk::TextView) {
+    let texts: Vec<String> = CLIPBOARD_RING.with(|ring| ring.borrow().iter().cloned().collect());
+    let items = texts.iter()
+        .map(|text| {
+            let lines = text.lines().count();
+            picker::PickerItem {
+                label: text.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or_default().to_string(),
+                detail: if lines > 1 { format!("{} lines", lines) } else { format!("{} characters", text.chars().count()) },
+                rank: 0,
             }
-            
-            // Set this tab as active
-            clicked_button.set_css_classes(&["tab-button-wrapper", "active"]);
-            // Set old tab to inactive
-            tab_button_wrapper_clone.set_css_classes(&["tab-button-wrapper"]);
-            
-            // Set this tab as active
-            clicked_button.set_css_classes(&["tab-button-wrapper", "active"]);
-            
-            // Switch to this tab's buffer
-            text_view_ref_clone.set_buffer(Some(&new_buffer_clone));
-        });
-        
-        // Add right-click context menu for the new tab
-        let right_click = gtk::GestureClick::new();
-        right_click.set_button(3); // Right mouse button
-        
-        let new_tab_wrapper_ref = new_tab_wrapper.clone();
-        let tabs_box_ref_clone = tabs_box_ref.clone();
-        let text_view_ref_clone = text_view_ref.clone();
-        // Create separate buffer clones to avoid lifetime issues
-        let buffer_for_menu = buffer_for_new_tab.clone();
-        let tab_button_wrapper_ref_clone = tab_button_wrapper_ref.clone();
-        let new_buffer_for_menu = new_buffer.clone();
-        
-        right_click.connect_pressed(move |_, _, _, _| {
-            let popover = gtk::Popover::new();
-            popover.set_parent(&new_tab_wrapper_ref);
-            
-            let box_container = gtk::Box::new(gtk::Orientation::Vertical, 5);
-            box_container.set_margin_top(5);
-            box_container.set_margin_bottom(5);
-            box_container.set_margin_start(5);
-            box_container.set_margin_end(5);
-            
-            // Close tab option
-            let close_item = gtk::Button::new();
-            close_item.set_label("Close Tab");
-            close_item.set_css_classes(&["menu-item"]);
-            close_item.set_has_frame(false);
-            
-            // Create fresh clones for this inner closure
-            let tabs_box_for_close = tabs_box_ref_clone.clone();
-            let new_tab_wrapper_for_close = new_tab_wrapper_ref.clone();
-            let text_view_for_close = text_view_ref_clone.clone();
-            let buffer_for_close = buffer_for_menu.clone();
-            let tab_button_wrapper_for_close = tab_button_wrapper_ref_clone.clone();
-            let popover_for_close = popover.clone();
-            
-            let close_item_clone = close_item.clone();
-            close_item.connect_clicked(move |_| {
-                // Check if this is the active tab
-                let is_active = new_tab_wrapper_for_close.css_classes().iter().any(|class| class == "active");
-                
-                // Remove this tab
-                tabs_box_for_close.remove(&new_tab_wrapper_for_close);
-                
-                // If this was the active tab, switch back to the first tab
-                if is_active {
-                    text_view_for_close.set_buffer(Some(&buffer_for_close));
-                    tab_button_wrapper_for_close.set_css_classes(&["tab-button-wrapper", "active"]);
-                }
-                
-                // Close the popover
-                popover_for_close.popdown();
-            });
-            
-            // Clear tab content option
-            let clear_item = gtk::Button::new();
-            clear_item.set_label("Clear Content");
-            clear_item.set_css_classes(&["menu-item"]);
-            clear_item.set_has_frame(false);
-            
-            // Create fresh clone for this inner closure
-            let new_buffer_clear = new_buffer_for_menu.clone();
-            let popover_clear = popover.clone();
-            
-            let clear_item_clone = clear_item.clone();
-            clear_item.connect_clicked(move |_| {
-                new_buffer_clear.set_text("");
-                popover_clear.popdown();
-            });
-            
-            box_container.append(&close_item_clone);
-            box_container.append(&clear_item_clone);
-            
-            popover.set_child(Some(&box_container));
-            popover.popup();
-        });
-        
-        new_tab_wrapper.add_controller(right_click);
-        
-        // Move the + button to the end
-        tabs_box_ref.remove(&new_tab_button_ref);
-        tabs_box_ref.append(&new_tab_wrapper);
-        tabs_box_ref.append(&new_tab_button_ref);
-        
-        // Simulate a click on the new tab to activate it
-        new_tab_wrapper.emit_clicked();
+        })
+        .collect();
+
+    let text_view_ref = text_view.clone();
+    picker::show_fuzzy_picker(text_view, "Paste from history...", items, move |index| {
+        let text = &texts[index];
+        let buffer = text_view_ref.buffer();
+        if let Some(replaced) = selected_text(&buffer) {
+            remember_clipboard_text(&replaced);
+        }
+        replace_selection(&buffer, text);
+        buffer.place_cursor(&buffer.iter_at_mark(&buffer.get_insert()));
+        text_view_ref.clipboard().set_text(text);
+        remember_clipboard_text(text);
+        text_view_ref.scroll_mark_onscreen(&buffer.get_insert());
+        text_view_ref.grab_focus();
     });
-    
-    // Make the close button for the first tab work
-    let buffer_clone = buffer.clone();
-    
-    close_icon.connect_clicked(move |_| {
-        // Just clear the content of this tab
-        buffer_clone.set_text("");
+}
+
+// Exchange the selection and the clipboard's text. The pasted text stays selected, so
+// swapping again puts everything back.
+fn swap_with_clipboard(text_view: &gtk::TextView) {
+    let buffer = text_view.buffer();
+    let Some(selection) = selected_text(&buffer) else {
+        return;
+    };
+    let text_view = text_view.clone();
+    text_view.clipboard().read_text_async(None::<&gtk::gio::Cancellable>, move |result| {
+        let Ok(Some(text)) = result else {
+            return;
+        };
+        replace_selection(&text_view.buffer(), &text);
+        text_view.clipboard().set_text(&selection);
+        remember_clipboard_text(&selection);
     });
-    
-    // Connect the initial tab to activate it when clicked
+}
+
+// Column of `iter` in graphemes, as block selections count them
+fn grapheme_column(iter: &gtk::TextIter) -> usize {
+    let mut position = *iter;
+    position.set_line_offset(0);
+    let mut column = 0;
+    while position < *iter && position.forward_cursor_position() {
+        column += 1;
+    }
+    column
+}
+
+// Width of a character in the view's font, for columns past the end of a line
+fn view_char_width(text_view: &gtk::TextView) -> i32 {
+    let metrics = text_view.pango_context().metrics(None, None);
+    (metrics.approximate_char_width() / pango::SCALE).max(1)
+}
+
+// Line and column of the block corner at a point of the view, counting columns past the
+// end of the line by the font's character width
+fn block_position_at(text_view: &gtk::TextView, x: f64, y: f64) -> (usize, usize) {
+    let (x, y) = text_view.window_to_buffer_coords(gtk::TextWindowType::Widget, x as i32, y as i32);
+    let Some(iter) = text_view.iter_at_location(x, y) else {
+        let buffer = text_view.buffer();
+        return ((buffer.line_count() - 1).max(0) as usize, 0);
+    };
+    let mut column = grapheme_column(&iter);
+    if iter.ends_line() {
+        let end_x = text_view.iter_location(&iter).x();
+        let char_width = view_char_width(text_view);
+        if x > end_x {
+            column += ((x - end_x + char_width / 2) / char_width) as usize;
+        }
+    }
+    (iter.line() as usize, column)
+}
+
+// Rectangles of the shown tab's block selection in buffer coordinates, one per line
+fn block_rects(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) -> Vec<gtk::gdk::Rectangle> {
+    let Some(block) = state.try_borrow().ok().and_then(|state| state.text_buffer.block_selection()) else {
+        return Vec::new();
+    };
+    let buffer = text_view.buffer();
+    let char_width = view_char_width(text_view);
+    let columns = block.columns();
+    let x_at = |line_start: &gtk::TextIter, column: usize| {
+        let mut iter = *line_start;
+        let mut reached = 0;
+        while reached < column && !iter.ends_line() && iter.forward_cursor_position() {
+            reached += 1;
+        }
+        text_view.iter_location(&iter).x() + (column - reached) as i32 * char_width
+    };
+
+    block.lines()
+        .filter_map(|line| buffer.iter_at_line(line as i32))
+        .map(|line_start| {
+            let (y, height) = text_view.line_yrange(&line_start);
+            let left = x_at(&line_start, columns.start);
+            // A block without columns is a caret on each line
+            let width = if columns.is_empty() { 2 } else { x_at(&line_start, columns.end) - left };
+            gtk::gdk::Rectangle::new(left, y, width, height)
+        })
+        .collect()
+}
+
+thread_local! {
+    // Overlays marking the whitespace of each editor pane, and whether they are shown
+    static WHITESPACE_OVERLAYS: RefCell<Vec<gtk::DrawingArea>> = const { RefCell::new(Vec::new()) };
+    static SHOW_WHITESPACE: Cell<bool> = const { Cell::new(false) };
+}
+
+// View -> Show Whitespace: spaces, tabs and line ends are marked by an overlay kept over
+// the visible part of `text_view`, so only what is on screen is ever looked at
+fn connect_whitespace_marks(text_view: &gtk::TextView) {
+    let overlay = gtk::DrawingArea::new();
+    overlay.set_can_target(false);
+    overlay.set_visible(SHOW_WHITESPACE.with(Cell::get));
     let text_view_ref = text_view.clone();
-    let buffer_clone = buffer.clone();
-    
-    tab_button_wrapper.connect_clicked(move |clicked_button| {
-        // Set this tab as active
-        clicked_button.set_css_classes(&["tab-button-wrapper", "active"]);
-        
-        // Switch to this tab's buffer
-        text_view_ref.set_buffer(Some(&buffer_clone));
-    });
-    
-    // Create tabs container with tabs and add button
-    tabs_container.append(&tabs_box);
-    
-    // Add tabs container to tabs row
-    tabs_row.append(&tabs_container);
-    
-    // Add the tabs row to the main container
-    main_container.append(&tabs_row);
+    overlay.set_draw_func(move |_, cr, _, _| {
+        let text_view = &text_view_ref;
+        let color = CURRENT_THEME.with(|theme| {
+            gtk::gdk::RGBA::parse(theme.borrow().whitespace.as_str()).unwrap_or(gtk::gdk::RGBA::WHITE)
+        });
+        cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, color.alpha() as f64);
+        let font = text_view.pango_context().font_description()
+            .unwrap_or_else(|| pango::FontDescription::from_string("Monospace 9"));
+        let marks = ["·", "→", "¶"].map(|mark| {
+            let layout = pangocairo::functions::create_layout(cr);
+            layout.set_font_description(Some(&font));
+            layout.set_text(mark);
+            layout
+        });
+        let [space, tab, line_end] = &marks;
+
+        let visible = text_view.visible_rect();
+        let (left, right, bottom) = (visible.x(), visible.x() + visible.width(), visible.y() + visible.height());
+        let draw = |layout: &pango::Layout, at: &gtk::TextIter, centered: bool| {
+            let location = text_view.iter_location(at);
+            let (width, height) = layout.pixel_size();
+            let x = if centered { location.x() + (location.width() - width) / 2 } else { location.x() + 1 };
+            cr.move_to((x - left) as f64, (location.y() + (location.height() - height) / 2 - visible.y()) as f64);
+            pangocairo::functions::show_layout(cr, layout);
+        };
+
+        let Some(mut line_start) = text_view.iter_at_location(left, visible.y()) else {
+            return;
+        };
+        line_start.set_line_offset(0);
+        loop {
+            let (top, height) = text_view.line_yrange(&line_start);
+            if top > bottom {
+                break;
+            }
+            // Only the characters within the visible columns and rows of the line
+            let first_row = top.max(visible.y());
+            let last_row = (top + height - 1).min(bottom);
+            let (Some(mut at), Some(last)) = (text_view.iter_at_location(left, first_row), text_view.iter_at_location(right, last_row)) else {
+                break;
+            };
+            while at < last && !at.ends_line() {
+                match at.char() {
+                    ' ' => draw(space, &at, true),
+                    '\t' => draw(tab, &at, false),
+                    _ => {}
+                }
+                at.forward_char();
+            }
+            if at.ends_line() && !at.is_end() {
+                draw(line_end, &at, false);
+            }
+            if !line_start.forward_line() {
+                break;
+            }
+        }
+    });
+    text_view.add_overlay(&overlay, 0, 0);
 
-    // Return the main container, button references, and find/replace buttons
-    (main_container, new_button_wrapper, open_button_wrapper, save_button_wrapper.clone(), open_recent_wrapper, save_as_button_wrapper, tabs_box, find_button, replace_button, show_line_numbers_button)
+    // Follow the visible part of the text as it scrolls, resizes or shows another tab
+    let place = {
+        let text_view = text_view.clone();
+        let overlay = overlay.clone();
+        move || {
+            if !overlay.is_visible() {
+                return;
+            }
+            let visible = text_view.visible_rect();
+            overlay.set_size_request(visible.width(), visible.height());
+            text_view.move_overlay(&overlay, visible.x(), visible.y());
+            overlay.queue_draw();
+        }
+    };
+    let place = Rc::new(place);
+    for adjustment in [text_view.vadjustment(), text_view.hadjustment()].into_iter().flatten() {
+        let place_ref = place.clone();
+        adjustment.connect_value_changed(move |_| place_ref());
+        let place_ref = place.clone();
+        adjustment.connect_changed(move |_| place_ref());
+    }
+    let place_ref = place.clone();
+    text_view.connect_buffer_notify(move |_| place_ref());
+    let place_ref = place.clone();
+    overlay.connect_visible_notify(move |_| place_ref());
+    WHITESPACE_OVERLAYS.with(|overlays| overlays.borrow_mut().push(overlay));
 }
 
-fn update_status_bar(status_label: &gtk::Label, buffer: &gtk::TextBuffer, editor_state: &Arc<Mutex<EditorState>>) {
-    if let Ok(state) = editor_state.lock() {
-        let modified = state.is_modified;
-        let (line, column) = get_cursor_position(buffer);
-        
-        let modified_marker = if modified { "*" } else { "" };
-        status_label.set_text(&format!("{}Line: {} Col: {}", modified_marker, line, column));
-    }
+// Show or hide the whitespace marks of every pane
+fn show_whitespace(visible: bool) {
+    SHOW_WHITESPACE.with(|shown| shown.set(visible));
+    WHITESPACE_OVERLAYS.with(|overlays| {
+        for overlay in overlays.borrow().iter() {
+            overlay.set_visible(visible);
+        }
+    });
 }
 
-fn get_cursor_position(buffer: &gtk::TextBuffer) -> (u32, u32) {
-    if let Some(mark) = buffer.mark("insert") {
-        let iter = buffer.iter_at_mark(&mark);
-        return ((iter.line() + 1) as u32, (iter.line_offset() + 1) as u32);
+// Redraw the whitespace marks after an edit
+fn queue_whitespace_redraw() {
+    if !SHOW_WHITESPACE.with(Cell::get) {
+        return;
     }
-    (1, 1)
+    WHITESPACE_OVERLAYS.with(|overlays| {
+        for overlay in overlays.borrow().iter() {
+            overlay.queue_draw();
+        }
+    });
 }
 
-fn apply_syntax_highlighting(buffer: &gtk::TextBuffer) {
-    // Clear existing tags
-    buffer.remove_all_tags(&buffer.start_iter(), &buffer.end_iter());
-    
-    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
-    let content = text.as_str();
-    
-    // Rust keywords
-    let keywords = [
-        "as", "break", "const", "continue", "crate", "else", "enum", "extern",
-        "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
-        "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
-        "super", "trait", "true", "type", "unsafe", "use", "where", "while", "async",
-        "await", "dyn", "abstract", "become", "box", "do", "final", "macro", "override",
-        "priv", "typeof", "unsized", "virtual", "yield"
-    ];
-    
-    // Rust types
-    let types = [
-        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize",
-        "u8", "u16", "u32", "u64", "u128", "usize", "str", "String", "Vec"
-    ];
-    
-    // Apply keyword highlighting
-    for keyword in keywords {
-        let mut start_search = buffer.start_iter();
-        while let Some((match_start, match_end)) = start_search.forward_search(
-            keyword,
-            gtk::TextSearchFlags::CASE_INSENSITIVE,
-            None,
-        ) {
-            // Only highlight if it's a whole word
-            if is_word_boundary(&match_start, true) && is_word_boundary(&match_end, false) {
-                buffer.apply_tag_by_name("keyword", &match_start, &match_end);
-            }
-            start_search = match_end;
+// Applies the wrap column settings at a zoom level to one editor pane
+type WrapColumnUpdate = Rc<dyn Fn(&Settings, f64)>;
+
+thread_local! {
+    static WRAP_COLUMN_VIEWS: RefCell<Vec<WrapColumnUpdate>> = const { RefCell::new(Vec::new()) };
+}
+
+// Narrowest right margin of the text, also kept when wrapping at a column
+const MIN_RIGHT_MARGIN: i32 = 10;
+
+// Word wrap at a column: the right margin of `text_view` grows until the text is the
+// column's width, and with the right margin setting a line is drawn at that column
+fn connect_wrap_column(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let ruler = gtk::DrawingArea::new();
+    ruler.set_can_target(false);
+    ruler.set_visible(false);
+    ruler.set_draw_func(|_, cr, width, height| {
+        let color = CURRENT_THEME.with(|theme| {
+            gtk::gdk::RGBA::parse(theme.borrow().right_margin.as_str()).unwrap_or(gtk::gdk::RGBA::BLACK)
+        });
+        cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, color.alpha() as f64);
+        cr.rectangle(0.0, 0.0, width as f64, height as f64);
+        let _ = cr.fill();
+    });
+    text_view.add_overlay(&ruler, 0, 0);
+
+    let text_view_ref = text_view.clone();
+    let update: WrapColumnUpdate = Rc::new(move |settings: &Settings, zoom_level: f64| {
+        let text_view = &text_view_ref;
+        let column = settings.wrap_column as i32;
+        let layout = text_view.create_pango_layout(Some("0"));
+        layout.set_font_description(Some(&text_font(settings, zoom_level)));
+        let visible = text_view.visible_rect();
+        let text_start = text_view.iter_location(&text_view.buffer().start_iter()).x();
+        let column_x = text_start + column * layout.pixel_size().0;
+
+        let right_margin = if column > 0 && text_view.wrap_mode() != gtk::WrapMode::None {
+            (visible.width() - column_x).max(MIN_RIGHT_MARGIN)
+        } else {
+            MIN_RIGHT_MARGIN
+        };
+        if text_view.right_margin() != right_margin {
+            text_view.set_right_margin(right_margin);
         }
-    }
-    
-    // Apply type highlighting
-    for type_name in types {
-        let mut start_search = buffer.start_iter();
-        while let Some((match_start, match_end)) = start_search.forward_search(
-            type_name,
-            gtk::TextSearchFlags::CASE_INSENSITIVE,
-            None,
-        ) {
-            // Only highlight if it's a whole word
-            if is_word_boundary(&match_start, true) && is_word_boundary(&match_end, false) {
-                buffer.apply_tag_by_name("type", &match_start, &match_end);
-            }
-            start_search = match_end;
+
+        ruler.set_visible(column > 0 && settings.show_right_margin);
+        if ruler.is_visible() {
+            ruler.set_size_request(1, visible.height());
+            text_view.move_overlay(&ruler, column_x, visible.y());
         }
-    }
-    
-    // Highlight strings
-    let mut in_string = false;
-    let mut string_start = buffer.start_iter();
-    
-    let mut start_search = buffer.start_iter();
-    while !start_search.is_end() {
-        let ch = start_search.char();
-        
-        if ch == '"' && (!in_string || start_search.backward_char() && start_search.char() != '\\') {
-            start_search.forward_char();
-            if !in_string {
-                string_start = start_search.clone();
-                in_string = true;
-            } else {
-                buffer.apply_tag_by_name("string", &string_start, &start_search);
-                in_string = false;
+    });
+
+    // Follow resizing, scrolling and switching wrapping on or off
+    let apply = {
+        let update = update.clone();
+        let state = state.clone();
+        move || {
+            if let Ok(state) = state.try_borrow() {
+                update(&state.settings, state.zoom_level);
             }
-        } else {
-            start_search.forward_char();
         }
+    };
+    let apply = Rc::new(apply);
+    for adjustment in [text_view.vadjustment(), text_view.hadjustment()].into_iter().flatten() {
+        let apply_ref = apply.clone();
+        adjustment.connect_value_changed(move |_| apply_ref());
+        let apply_ref = apply.clone();
+        adjustment.connect_changed(move |_| apply_ref());
     }
-    
-    // Highlight comments (// and /* */)
-    let mut start_search = buffer.start_iter();
-    while let Some((comment_start, _)) = start_search.forward_search(
-        "//",
-        gtk::TextSearchFlags::CASE_INSENSITIVE,
-        None,
-    ) {
-        let mut line_end = comment_start.clone();
-        line_end.forward_to_line_end();
-        
-        buffer.apply_tag_by_name("comment", &comment_start, &line_end);
-        start_search = line_end;
-    }
-    
-    // Block comments /* */
-    let mut start_search = buffer.start_iter();
-    while let Some((block_start, _)) = start_search.forward_search(
-        "/*",
-        gtk::TextSearchFlags::CASE_INSENSITIVE,
-        None,
-    ) {
-        if let Some((block_end, _)) = block_start.forward_search(
-            "*/",
-            gtk::TextSearchFlags::CASE_INSENSITIVE,
-            None,
-        ) {
-            buffer.apply_tag_by_name("comment", &block_start, &block_end);
-            start_search = block_end;
-        } else {
-            break;
+    let apply_ref = apply.clone();
+    text_view.connect_wrap_mode_notify(move |_| apply_ref());
+    WRAP_COLUMN_VIEWS.with(|views| views.borrow_mut().push(update));
+}
+
+thread_local! {
+    // The View menu's Word Wrap toggle, kept showing whether the shown tab wraps
+    static WORD_WRAP_TOGGLE: RefCell<Option<gtk::CheckButton>> = const { RefCell::new(None) };
+}
+
+// Wrap the shown tab at words when the Word Wrap setting is on, or anywhere in a line for a
+// tab opened with its long lines wrapped, and show that on the Word Wrap toggle
+fn show_wrap_mode(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    let Ok((word_wrap, wrap_long_lines)) = state.try_borrow().map(|state| (state.settings.word_wrap, state.wrap_long_lines)) else {
+        return;
+    };
+    let wrap_mode = if wrap_long_lines {
+        gtk::WrapMode::WordChar
+    } else if word_wrap {
+        gtk::WrapMode::Word
+    } else {
+        gtk::WrapMode::None
+    };
+    text_view.set_wrap_mode(wrap_mode);
+    WORD_WRAP_TOGGLE.with(|toggle| {
+        if let Some(toggle) = toggle.borrow().as_ref() {
+            toggle.set_active(word_wrap || wrap_long_lines);
         }
+    });
+}
+
+// Apply changed wrap column settings to every pane
+fn update_wrap_columns(settings: &Settings, zoom_level: f64) {
+    let views = WRAP_COLUMN_VIEWS.with(|views| views.borrow().clone());
+    for update in views {
+        update(settings, zoom_level);
     }
-    
-    // Detect simple syntax errors
-    check_for_errors(buffer, content);
 }
 
-fn is_word_boundary(iter: &gtk::TextIter, is_start: bool) -> bool {
-    if is_start {
-        iter.starts_word() || iter.starts_line() || {
-            let mut temp = iter.clone();
-            if temp.backward_char() {
-                !temp.char().is_alphanumeric()
-            } else {
-                true
-            }
+// Move and size the overlay to the block selection, hiding it without one
+fn update_block_overlay(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>) {
+    BLOCK_OVERLAY.with(|overlay| {
+        let overlay = overlay.borrow();
+        let Some(overlay) = overlay.as_ref() else {
+            return;
+        };
+        let rects = block_rects(text_view, state);
+        let Some(bounds) = rects.into_iter().reduce(|union, rect| union.union(&rect)) else {
+            overlay.set_visible(false);
+            return;
+        };
+        overlay.set_size_request(bounds.width(), bounds.height());
+        text_view.move_overlay(overlay, bounds.x(), bounds.y());
+        overlay.set_visible(true);
+        overlay.queue_draw();
+    });
+}
+
+// Second editor pane, showing the shown tab at another place or any other open tab
+struct SplitPane {
+    paned: gtk::Paned,
+    container: gtk::Box,
+    view: gtk::TextView,
+}
+
+thread_local! {
+    static SPLIT_PANE: RefCell<Option<SplitPane>> = const { RefCell::new(None) };
+    // Fills in the second pane the first time the editor is split
+    static SPLIT_PANE_BUILDER: RefCell<Option<Box<dyn FnOnce() -> SplitPane>>> = const { RefCell::new(None) };
+    // The editor pane that last lost focus, and where it left its buffer's cursor
+    static LAST_PANE_LEFT: RefCell<Option<(gtk::TextView, gtk::TextMark)>> = const { RefCell::new(None) };
+}
+
+// Holds the editor and, once split, a second pane next to or below it
+fn create_editor_panes(scroll: &gtk::ScrolledWindow, text_view: &gtk::TextView, tabs: &Rc<RefCell<TabManager>>) -> gtk::Paned {
+    let paned = gtk::Paned::new(gtk::Orientation::Horizontal);
+    paned.set_hexpand(true);
+    paned.set_vexpand(true);
+    paned.set_start_child(Some(scroll));
+    paned.set_shrink_start_child(false);
+    paned.set_shrink_end_child(false);
+    track_pane_cursor(text_view);
+
+    let paned_ref = paned.clone();
+    let text_view = text_view.clone();
+    let tabs = tabs.clone();
+    SPLIT_PANE_BUILDER.with(|builder| *builder.borrow_mut() = Some(Box::new(move || {
+        build_split_pane(&paned_ref, &text_view, &tabs)
+    })));
+    paned
+}
+
+fn build_split_pane(paned: &gtk::Paned, text_view: &gtk::TextView, tabs: &Rc<RefCell<TabManager>>) -> SplitPane {
+    let view = gtk::TextView::with_buffer(&text_view.buffer());
+    view.set_monospace(true);
+    text_view.bind_property("wrap-mode", &view, "wrap-mode").sync_create().build();
+    view.set_left_margin(10);
+    view.set_right_margin(MIN_RIGHT_MARGIN);
+    view.set_top_margin(10);
+    view.set_bottom_margin(10);
+    view.set_pixels_above_lines(2);
+    view.set_pixels_below_lines(2);
+    view.set_css_classes(&["dark-mode"]);
+    if let Some(tabs) = text_view.tabs() {
+        view.set_tabs(&tabs);
+    }
+    ZOOM_PROVIDER.with(|cell| {
+        if let Some(provider) = cell.borrow().as_ref() {
+            view.style_context().add_provider(provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
         }
-    } else {
-        iter.ends_word() || iter.ends_line() || !iter.char().is_alphanumeric()
+    });
+    track_pane_cursor(&view);
+    connect_abbreviations(&view, None);
+    connect_pair_deletion(&view);
+    connect_whitespace_marks(&view);
+    connect_clipboard_ring(&view);
+    let state = tabs.borrow().state.clone();
+    connect_wrap_column(&view, &state);
+
+    let scroll = gtk::ScrolledWindow::new();
+    scroll.set_vexpand(true);
+    scroll.set_hexpand(true);
+    scroll.set_css_classes(&["editor-scroll"]);
+    scroll.set_child(Some(&view));
+
+    // Header choosing which tab the pane shows
+    let tab_button = gtk::MenuButton::new();
+    tab_button.set_hexpand(true);
+    tab_button.set_halign(gtk::Align::Start);
+    tab_button.set_has_frame(false);
+    tab_button.set_tooltip_text(Some("Tab shown in this pane"));
+    let tab_list = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    let popover = gtk::Popover::new();
+    popover.set_child(Some(&tab_list));
+    tab_button.set_popover(Some(&popover));
+    let close_button = gtk::Button::from_icon_name("window-close-symbolic");
+    close_button.set_has_frame(false);
+    close_button.set_tooltip_text(Some("Close split"));
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    header.append(&tab_button);
+    header.append(&close_button);
+
+    let container = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    container.append(&header);
+    container.append(&scroll);
+    close_button.connect_clicked(|_| unsplit_editor());
+
+    // Name the tab shown, and list the open tabs each time the list opens
+    let show_tab_name = {
+        let tabs = tabs.clone();
+        let tab_button = tab_button.clone();
+        move |buffer: &gtk::TextBuffer| {
+            let name = tabs.borrow().summaries().into_iter()
+                .find(|summary| summary.buffer == *buffer)
+                .map(|summary| summary.name)
+                .unwrap_or_default();
+            tab_button.set_label(&name);
+        }
+    };
+    show_tab_name(&view.buffer());
+    let tabs_ref = tabs.clone();
+    let view_ref = view.clone();
+    let popover_ref = popover.clone();
+    popover.connect_show(move |_| {
+        while let Some(child) = tab_list.first_child() {
+            tab_list.remove(&child);
+        }
+        for summary in tabs_ref.borrow().summaries() {
+            let modified_marker = if summary.modified { "*" } else { "" };
+            let item = gtk::Button::with_label(&format!("{}{}", modified_marker, summary.name));
+            item.set_has_frame(false);
+            item.set_halign(gtk::Align::Start);
+            let view = view_ref.clone();
+            let popover = popover_ref.clone();
+            item.connect_clicked(move |_| {
+                popover.popdown();
+                view.set_buffer(Some(&summary.buffer));
+                view.grab_focus();
+            });
+            tab_list.append(&item);
+        }
+    });
+
+    // Edits of a tab that only this pane shows go to that tab's parked state, since the
+    // editing signals of the shown tab ignore other buffers
+    let connected_buffers: RefCell<Vec<gtk::TextBuffer>> = RefCell::new(Vec::new());
+    let tabs_ref = tabs.clone();
+    let main_view = text_view.clone();
+    view.connect_buffer_notify(move |view| {
+        let buffer = view.buffer();
+        show_tab_name(&buffer);
+        if connected_buffers.borrow().contains(&buffer) {
+            return;
+        }
+        connected_buffers.borrow_mut().push(buffer.clone());
+        let tabs = tabs_ref.clone();
+        let main_view = main_view.clone();
+        let view = view.clone();
+        let pending_edit = connect_pending_edits(&buffer);
+        buffer.connect_changed(move |buf| {
+            let edit = pending_edit.borrow_mut().take();
+            if main_view.buffer() == *buf || view.buffer() != *buf {
+                return;
+            }
+            let mirrored = tabs.borrow_mut().mirror_parked_edit(buf, edit);
+            if let Some(language) = mirrored {
+                if !apply_syntax_highlighting(buf, language) {
+                    tabs.borrow_mut().update_state(buf, stop_highlighting);
+                }
+            }
+            queue_whitespace_redraw();
+        });
+    });
+
+    // When the tab the pane shows is closed, fall back to the shown tab. Closing finishes
+    // after the editor has switched away, hence the idle.
+    let tabs_ref = tabs.clone();
+    let view_ref = view.clone();
+    text_view.connect_buffer_notify(move |main_view| {
+        let tabs = tabs_ref.clone();
+        let view = view_ref.clone();
+        let main_view = main_view.clone();
+        glib::idle_add_local_once(move || {
+            if tabs.borrow().button_for(&view.buffer()).is_none() {
+                view.set_buffer(Some(&main_view.buffer()));
+            }
+        });
+    });
+
+    SplitPane {
+        paned: paned.clone(),
+        container,
+        view,
     }
 }
 
-fn check_for_errors(buffer: &gtk::TextBuffer, content: &str) {
-    // Pattern for unmatched brackets/parentheses
-    let brackets: Vec<(char, char)> = vec![
-        ('(', ')'),
-        ('{', '}'),
-        ('[', ']'),
-    ];
-    
-    // Check for unmatched brackets
-    for (open_bracket, close_bracket) in brackets {
-        let mut stack: Vec<(usize, usize)> = Vec::new();  // (line, col) positions
-        let mut line = 0;
-        let mut col = 0;
-        
-        for ch in content.chars() {
-            if ch == '\n' {
-                line += 1;
-                col = 0;
-            } else {
-                col += 1;
-                
-                if ch == open_bracket {
-                    stack.push((line, col));
-                } else if ch == close_bracket {
-                    if stack.is_empty() {
-                        // Unmatched closing bracket
-                        highlight_error_at_position(buffer, line, col);
-                    } else {
-                        stack.pop();
+// Give each editor pane its own cursor on a shared buffer. GTK keeps one cursor per
+// buffer, so a pane puts back where it left its cursor when focus comes back from the
+// other pane, unless something else moved the cursor in between.
+fn track_pane_cursor(view: &gtk::TextView) {
+    let saved: Rc<RefCell<Option<(gtk::TextMark, gtk::TextMark)>>> = Rc::new(RefCell::new(None));
+    let focus = gtk::EventControllerFocus::new();
+
+    let saved_ref = saved.clone();
+    let view_ref = view.clone();
+    focus.connect_leave(move |_| {
+        let buffer = view_ref.buffer();
+        let insert = buffer.iter_at_mark(&buffer.get_insert());
+        let bound = buffer.iter_at_mark(&buffer.selection_bound());
+        let mut saved = saved_ref.borrow_mut();
+        match saved.as_ref() {
+            Some((insert_mark, bound_mark)) if insert_mark.buffer().as_ref() == Some(&buffer) => {
+                buffer.move_mark(insert_mark, &insert);
+                buffer.move_mark(bound_mark, &bound);
+            }
+            _ => {
+                if let Some((insert_mark, bound_mark)) = saved.take() {
+                    if let Some(old_buffer) = insert_mark.buffer() {
+                        old_buffer.delete_mark(&insert_mark);
+                        old_buffer.delete_mark(&bound_mark);
                     }
                 }
+                *saved = Some((buffer.create_mark(None, &insert, true), buffer.create_mark(None, &bound, true)));
             }
         }
-        
-        // Unmatched opening brackets
-        for (line, col) in stack {
-            highlight_error_at_position(buffer, line, col);
+        let left_at = buffer.create_mark(None, &insert, true);
+        let previous = LAST_PANE_LEFT.with(|last| last.borrow_mut().replace((view_ref.clone(), left_at)));
+        if let Some((_, mark)) = previous {
+            if let Some(buffer) = mark.buffer() {
+                buffer.delete_mark(&mark);
+            }
         }
-    }
-    
-    // Check for missing semicolons
-    for (line_idx, line) in content.lines().enumerate() {
-        let trimmed = line.trim();
-        if !trimmed.is_empty() && 
-           !trimmed.ends_with(';') && 
-           !trimmed.ends_with('{') && 
-           !trimmed.ends_with('}') && 
-           !trimmed.starts_with("//") &&
-           !trimmed.starts_with("pub fn") &&
-           !trimmed.starts_with("fn") &&
-           !trimmed.contains("->") {
-            // Potential missing semicolon
-            if let Some(iter) = buffer.iter_at_line_offset(line_idx as i32, 0) {
-                let mut end = iter.clone();
-                if end.forward_to_line_end() {
-                    // Skip if it's inside a comment or string
-                    let text = buffer.text(&iter, &end, false);
-                    if !text.contains("//") && !text.contains("/*") && !is_inside_string(&text) {
-                        buffer.apply_tag_by_name("error", &iter, &end);
-                    }
-                }
+    });
+
+    let view_ref = view.clone();
+    focus.connect_enter(move |_| {
+        let Some((other, left_at)) = LAST_PANE_LEFT.with(|last| last.borrow().clone()) else {
+            return;
+        };
+        let buffer = view_ref.buffer();
+        if other == view_ref || other.buffer() != buffer || left_at.buffer().as_ref() != Some(&buffer) {
+            return;
+        }
+        if buffer.iter_at_mark(&buffer.get_insert()) != buffer.iter_at_mark(&left_at) {
+            return;
+        }
+        if let Some((insert_mark, bound_mark)) = saved.borrow().as_ref() {
+            if insert_mark.buffer().as_ref() == Some(&buffer) {
+                buffer.select_range(&buffer.iter_at_mark(insert_mark), &buffer.iter_at_mark(bound_mark));
             }
         }
+    });
+    view.add_controller(focus);
+}
+
+// View > Split: show a second pane beside (vertical split) or below (horizontal split)
+// the editor, on the shown tab
+fn split_editor(orientation: gtk::Orientation) {
+    if let Some(build) = SPLIT_PANE_BUILDER.with(|builder| builder.borrow_mut().take()) {
+        let pane = build();
+        SPLIT_PANE.with(|cell| *cell.borrow_mut() = Some(pane));
     }
+    SPLIT_PANE.with(|pane| {
+        let Some(pane) = pane.borrow().as_ref().map(|pane| (pane.paned.clone(), pane.container.clone(), pane.view.clone())) else {
+            return;
+        };
+        let (paned, container, view) = pane;
+        paned.set_orientation(orientation);
+        if paned.end_child().is_none() {
+            paned.set_end_child(Some(&container));
+        }
+        let size = if orientation == gtk::Orientation::Horizontal { paned.width() } else { paned.height() };
+        paned.set_position(size / 2);
+        view.grab_focus();
+    });
 }
 
-fn is_inside_string(text: &str) -> bool {
-    let mut in_string = false;
-    let mut escaped = false;
-    
-    for ch in text.chars() {
-        if ch == '\\' {
-            escaped = !escaped;
-        } else if ch == '"' && !escaped {
-            in_string = !in_string;
+fn unsplit_editor() {
+    SPLIT_PANE.with(|pane| {
+        if let Some(pane) = pane.borrow().as_ref() {
+            pane.paned.set_end_child(None::<&gtk::Widget>);
+            if let Some(main_view) = pane.paned.start_child().and_then(|scroll| scroll.first_child()) {
+                main_view.grab_focus();
+            }
+        }
+    });
+}
+
+// F6: move the focus to the other editor pane, if the editor is split
+fn focus_other_pane(text_view: &gtk::TextView) {
+    SPLIT_PANE.with(|pane| {
+        let Some(pane) = pane.borrow().as_ref().map(|pane| (pane.paned.clone(), pane.view.clone())) else {
+            return;
+        };
+        let (paned, view) = pane;
+        if paned.end_child().is_none() {
+            return;
+        }
+        if view.has_focus() {
+            text_view.grab_focus();
         } else {
-            escaped = false;
+            view.grab_focus();
         }
-    }
+    });
+}
+
+thread_local! {
+    // Zoom is applied on every tab switch, so one provider is reloaded instead of stacking new ones
+    static ZOOM_PROVIDER: RefCell<Option<gtk::CssProvider>> = const { RefCell::new(None) };
+}
+
+// Set the editor's scrollbars from `mode`; the horizontal one is only offered while
+// lines are not wrapped, and then only appears once they overflow
+fn apply_scrollbar_mode(text_view: &gtk::TextView, mode: ScrollbarMode) {
+    let Some(scroll) = text_view.ancestor(gtk::ScrolledWindow::static_type()).and_downcast::<gtk::ScrolledWindow>() else {
+        return;
+    };
+    let (vertical, overlay) = match mode {
+        ScrollbarMode::Always => (gtk::PolicyType::Always, false),
+        ScrollbarMode::Overlay => (gtk::PolicyType::Automatic, true),
+        ScrollbarMode::Hidden => (gtk::PolicyType::External, false),
+    };
+    let horizontal = if text_view.wrap_mode() != gtk::WrapMode::None {
+        gtk::PolicyType::Never
+    } else if mode == ScrollbarMode::Hidden {
+        gtk::PolicyType::External
+    } else {
+        gtk::PolicyType::Automatic
+    };
+    scroll.set_overlay_scrolling(overlay);
+    scroll.set_policy(horizontal, vertical);
+}
+
+// Show `text_view` in the configured font, at the zoom level of the tab it currently displays
+// The font the text is shown in at `zoom_level`
+fn text_font(settings: &Settings, zoom_level: f64) -> pango::FontDescription {
+    let mut font = pango::FontDescription::new();
+    font.set_family(&settings.font_family);
+    font.set_absolute_size((settings.font_size as f64 * zoom_level).round() * pango::SCALE as f64);
+    font
+}
+
+fn apply_zoom(text_view: &gtk::TextView, settings: &Settings, zoom_level: f64) {
+    let font_size = (settings.font_size as f64 * zoom_level).round();
+    let css = format!(
+        "textview {{ font-family: \"{}\"; font-size: {}px; line-height: 1.4; }}",
+        settings.font_family.replace('"', ""),
+        font_size
+    );
     
-    in_string
+    ZOOM_PROVIDER.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let provider = cell.get_or_insert_with(|| {
+            let provider = gtk::CssProvider::new();
+            text_view.style_context().add_provider(&provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
+            provider
+        });
+        provider.load_from_data(&css);
+    });
+
+    // Tab stops are measured in the font the CSS above selects, which the view may not
+    // have picked up yet
+    let layout = text_view.create_pango_layout(Some(&" ".repeat(settings.tab_width.max(1) as usize)));
+    layout.set_font_description(Some(&text_font(settings, zoom_level)));
+    let mut tabs = pango::TabArray::new(1, true);
+    tabs.set_tab(0, pango::TabAlign::Left, layout.pixel_size().0);
+    text_view.set_tabs(&tabs);
+    SPLIT_PANE.with(|pane| {
+        if let Some(pane) = pane.borrow().as_ref() {
+            pane.view.set_tabs(&tabs);
+        }
+    });
+    // Columns got wider or narrower
+    update_wrap_columns(settings, zoom_level);
+}
+
+thread_local! {
+    // The theme the window is drawn in, and its name
+    static CURRENT_THEME: RefCell<theme::Theme> = RefCell::new(theme::Theme::default());
+    static CURRENT_THEME_NAME: RefCell<String> = const { RefCell::new(String::new()) };
+    // Style sheet of the window, reloaded when the theme changes
+    static STYLE_PROVIDER: RefCell<Option<gtk::CssProvider>> = const { RefCell::new(None) };
+    // Whether the rules of the panels built on demand are part of the style sheet yet
+    static ON_DEMAND_CSS_LOADED: Cell<bool> = const { Cell::new(false) };
+}
+
+// Load the style sheet in the current theme's colors
+fn reload_style_sheet() {
+    let css = CURRENT_THEME.with(|theme| theme.borrow().css(ON_DEMAND_CSS_LOADED.with(Cell::get)));
+    STYLE_PROVIDER.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let provider = cell.get_or_insert_with(|| {
+            let provider = gtk::CssProvider::new();
+            if let Some(display) = gtk::gdk::Display::default() {
+                gtk::style_context_add_provider_for_display(
+                    &display,
+                    &provider,
+                    gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+                );
+            }
+            provider
+        });
+        provider.load_from_data(&css);
+    });
 }
 
-fn highlight_error_at_position(buffer: &gtk::TextBuffer, line: usize, col: usize) {
-    if let Some(iter) = buffer.iter_at_line_offset(line as i32, 0) {
-        let mut pos = iter.clone();
-        if pos.forward_chars(col as i32) {
-            let mut end = pos.clone();
-            if end.forward_char() {
-                buffer.apply_tag_by_name("error", &pos, &end);
-            }
+// Add the rules of the panels that are built on first use; the first call reloads the style sheet
+fn load_on_demand_css() {
+    if ON_DEMAND_CSS_LOADED.with(|loaded| loaded.replace(true)) {
+        return;
+    }
+    reload_style_sheet();
+}
+
+// Draw the window, the syntax colors and the gutter in `theme`
+fn apply_theme(theme: &theme::Theme) {
+    CURRENT_THEME.with(|current| *current.borrow_mut() = theme.clone());
+    reload_style_sheet();
+    if let Some(settings) = gtk::Settings::default() {
+        settings.set_gtk_application_prefer_dark_theme(theme.dark);
+    }
+
+    // Every buffer shares the one tag table
+    color_tags(&shared_tag_table(), theme);
+    LINE_NUMBERS.with(|gutter| {
+        if let Some(gutter) = gutter.borrow().as_ref() {
+            gutter.queue_draw();
         }
-    }
+    });
 }
 
-fn apply_zoom(text_view: &gtk::TextView, zoom_level: f64) {
-    let provider = gtk::CssProvider::new();
-    let css = format!(
-        "textview {{ font-family: 'Monospace'; font-size: {}px; line-height: 1.4; }}",
-        (13.0 * zoom_level).round()
-    );
-    
-    provider.load_from_data(&css);
-    
-    let context = text_view.style_context();
-    context.add_provider(&provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
+// Switch to the theme of the shown tab's language when it is not the one shown already
+fn apply_language_theme(state: &Rc<RefCell<EditorState>>) {
+    let Ok(name) = state.try_borrow().map(|state| state.theme_name().to_string()) else {
+        return;
+    };
+    if CURRENT_THEME_NAME.with(|current| *current.borrow() == name) {
+        return;
+    }
+    apply_theme(&theme::Theme::load(&name));
+    CURRENT_THEME_NAME.with(|current| *current.borrow_mut() = name);
 }
 
 // In the beginning of the main function or after TextBuffer creation
-fn highlight_current_line(buffer: &gtk::TextBuffer, _text_view: &gtk::TextView) {
-    // Create provider for current line highlight
-    let provider = gtk::CssProvider::new();
-    provider.load_from_data(".line-highlight { background-color: rgba(255, 255, 255, 0.04); }");
-    
-    let display = gtk::gdk::Display::default().unwrap();
-    gtk::style_context_add_provider_for_display(
-        &display,
-        &provider,
-        gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
-    );
-    
+fn highlight_current_line(buffer: &gtk::TextBuffer, _text_view: &gtk::TextView, theme: &theme::Theme) {
     // Get the tag table
     let tag_table = buffer.tag_table();
     
-    // Create tag for line highlight if needed
+    // Create tag for line highlight if needed, colored by the theme
     if tag_table.lookup("line-highlight").is_none() {
         let tag = gtk::TextTag::builder()
             .name("line-highlight")
-            .background_rgba(&gtk::gdk::RGBA::new(0.15, 0.15, 0.15, 1.0))
+            .background(theme.current_line.as_str())
             .build();
         tag_table.add(&tag);
     }
@@ -1995,34 +11652,237 @@ fn update_highlight_line(buffer: &gtk::TextBuffer, iter: &gtk::TextIter) {
     buffer.apply_tag_by_name("line-highlight", &line_start, &line_end);
 }
 
+// In focus mode dim every line outside the paragraph holding the cursor, where
+// paragraphs are separated by blank lines; otherwise clear the dimming
+fn update_focus_mode(buffer: &gtk::TextBuffer, enabled: bool) {
+    let (start, end) = buffer.bounds();
+    buffer.remove_tag_by_name("focus-dim", &start, &end);
+    if !enabled {
+        return;
+    }
+
+    let is_blank = |line: i32| {
+        let Some(line_start) = buffer.iter_at_line(line) else {
+            return true;
+        };
+        let mut line_end = line_start;
+        if !line_end.ends_line() {
+            line_end.forward_to_line_end();
+        }
+        line_start.text(&line_end).trim().is_empty()
+    };
+
+    let cursor_line = buffer.iter_at_mark(&buffer.get_insert()).line();
+    let (mut first, mut last) = (cursor_line, cursor_line);
+    if !is_blank(cursor_line) {
+        while first > 0 && !is_blank(first - 1) {
+            first -= 1;
+        }
+        while last + 1 < buffer.line_count() && !is_blank(last + 1) {
+            last += 1;
+        }
+    }
+
+    if let Some(paragraph_start) = buffer.iter_at_line(first) {
+        buffer.apply_tag_by_name("focus-dim", &start, &paragraph_start);
+    }
+    if let Some(mut paragraph_end) = buffer.iter_at_line(last) {
+        if !paragraph_end.ends_line() {
+            paragraph_end.forward_to_line_end();
+        }
+        buffer.apply_tag_by_name("focus-dim", &paragraph_end, &end);
+    }
+}
+
+// Undo, or redo when `redo` is set. The state is updated and let go of before the view's
+// buffer changes, since the buffer's changed handler needs it as well.
+fn step_history(text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, redo: bool) {
+    if !text_view.is_editable() {
+        return;
+    }
+    let text = {
+        let mut state = state.borrow_mut();
+        let text = if redo { state.redo() } else { state.undo() };
+        state.restoring_history = text.is_some();
+        text
+    };
+    // The mirror follows the view's edits as it takes the text
+    if let Some(text) = text {
+        text_view.buffer().set_text(&text);
+        state.borrow_mut().restoring_history = false;
+    }
+}
+
+// Keep a tab's EditorState, highlighting, status bar and completion in step with its buffer
+fn connect_buffer_signals(buffer: &gtk::TextBuffer, text_view: &gtk::TextView, state: &Rc<RefCell<EditorState>>, status_label: &gtk::Label, completion: &Rc<CompletionPopup>) {
+    connect_recent_edits(buffer, text_view, state);
+
+    // Changes made within one user action, such as a delete and insert, are one undo step:
+    // nesting of the user actions, and whether the one under way has its step yet
+    let user_action_depth = Rc::new(Cell::new(0u32));
+    let undo_step_taken = Rc::new(Cell::new(false));
+    let depth = user_action_depth.clone();
+    let step_taken = undo_step_taken.clone();
+    buffer.connect_begin_user_action(move |_| {
+        if depth.get() == 0 {
+            step_taken.set(false);
+        }
+        depth.set(depth.get() + 1);
+    });
+    let depth = user_action_depth.clone();
+    buffer.connect_end_user_action(move |_| depth.set(depth.get().saturating_sub(1)));
+
+    // Mirror edits into the editor state and refresh highlighting and the status bar
+    let pending_edit = connect_pending_edits(buffer);
+    let state_ref = state.clone();
+    let status_label_ref = status_label.clone();
+    let text_view_ref = text_view.clone();
+    let completion_ref = completion.clone();
+    buffer.connect_changed(move |buf| {
+        // Taken in any case, so that it is not mistaken for the next edit
+        let edit = pending_edit.borrow_mut().take();
+        // The shared state belongs to the tab that is shown
+        if text_view_ref.buffer() != *buf {
+            return;
+        }
+        
+        let mut highlight = true;
+        let mut language = None;
+        let mut focus_mode = false;
+        if let Ok(mut state) = state_ref.try_borrow_mut() {
+            highlight = state.syntax_highlighting && !state.large_file_mode;
+            focus_mode = state.persistent.focus_mode;
+            
+            let new_undo_step = user_action_depth.get() == 0 || !undo_step_taken.replace(true);
+            state.mirror_buffer_edit(buf, edit, new_undo_step);
+            language = state.language();
+        }
+        update_status_bar(&status_label_ref, buf, &state_ref);
+        
+        // Apply syntax highlighting, unless the file was opened in large-file mode
+        // or the tab shows generated content with its own tags
+        if highlight {
+            highlight_shown_buffer(buf, language, &state_ref);
+            schedule_semantic_tokens(buf, &state_ref);
+        }
+        
+        // Highlighting clears the dimming, and edits can grow or split the paragraph
+        if focus_mode {
+            update_focus_mode(buf, true);
+        }
+
+        // Line numbers only change when lines are added or removed
+        queue_gutter_redraw(&text_view_ref, false);
+
+        // Keep inline diagnostics at the end of their (possibly edited) lines
+        let text_view = text_view_ref.clone();
+        let completion = completion_ref.clone();
+        let state = state_ref.clone();
+        glib::idle_add_local_once(move || {
+            layout_inline_diagnostics(&text_view);
+            update_completion(&completion, &text_view, &state, false);
+            // Other edits drop the block selection
+            update_block_overlay(&text_view, &state);
+            queue_search_ruler_redraw();
+            queue_whitespace_redraw();
+            // Highlighting cleared the bracket pair and trailing whitespace
+            highlight_matching_bracket(&text_view.buffer(), &state);
+            highlight_trailing_whitespace(&text_view.buffer(), &state);
+            update_prose_analysis(&text_view.buffer());
+        });
+    });
+
+    let state_ref = state.clone();
+    let status_label_ref = status_label.clone();
+    let text_view_ref = text_view.clone();
+    let completion = completion.clone();
+    buffer.connect_mark_set(move |buf, _, mark| {
+        if text_view_ref.buffer() != *buf {
+            return;
+        }
+        update_status_bar(&status_label_ref, buf, &state_ref);
+
+        if *mark == buf.get_insert() {
+            let focus_mode = state_ref.try_borrow().map(|state| state.persistent.focus_mode).unwrap_or(false);
+            if focus_mode {
+                update_focus_mode(buf, true);
+            }
+            // Taken out of the state, since moving its marks emits this signal again
+            if let Ok(mut history) = state_ref.try_borrow_mut().map(|mut state| std::mem::take(&mut state.caret_history)) {
+                history.caret_moved(buf, &buf.iter_at_mark(mark));
+                if let Ok(mut state) = state_ref.try_borrow_mut() {
+                    state.caret_history = history;
+                }
+            }
+            // The cursor's line number is highlighted
+            queue_gutter_redraw(&text_view_ref, false);
+            highlight_matching_bracket(buf, &state_ref);
+        }
+        if *mark == buf.get_insert() || *mark == buf.selection_bound() {
+            update_byte_inspector(buf);
+        }
+
+        // Moving the cursor elsewhere closes or refreshes an open completion
+        if completion.is_visible() && *mark == buf.get_insert() {
+            update_completion(&completion, &text_view_ref, &state_ref, false);
+        }
+    });
+}
+
 fn main() -> Result<()> {
     // Force Wayland backend for GTK
     env::set_var("GDK_BACKEND", "wayland");
     
-    env_logger::init();
+    // --profile-startup is ours; GTK rejects options it does not know
+    let (profile_flags, args): (Vec<String>, Vec<String>) = env::args()
+        .partition(|arg| arg == startup::PROFILE_FLAG);
+    let profile_startup = !profile_flags.is_empty();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if profile_startup {
+        logger.filter(Some(startup::LOG_TARGET), log::LevelFilter::Info);
+    }
+    logger.init();
     info!("Starting application with GTK");
+    let profile = Rc::new(StartupProfile::new(profile_startup));
 
     // Initialize GTK
     gtk::init().expect("Failed to initialize GTK");
+    profile.phase("GTK initialized");
 
+    // One instance per session: launching again hands its files to the running editor
     let app = gtk::Application::builder()
         .application_id("com.example.rustedit")
+        .flags(gio::ApplicationFlags::HANDLES_OPEN)
         .build();
 
-    let editor_state = Arc::new(Mutex::new(EditorState::new()));
+    let editor_state = Rc::new(RefCell::new(EditorState::new()));
+    profile.phase("settings and state loaded");
 
     app.connect_activate(move |app| {
         debug!("Application activated");
+        // Launched again without files: raise the window there is
+        if let Some(window) = app.active_window() {
+            window.present();
+            return;
+        }
         
-        // Create GTK window and text view first
+        // Create GTK window and text view first, at the size it had when last closed
+        let (window_width, window_height, window_maximized) = match editor_state.try_borrow() {
+            Ok(state) => (state.persistent.window_width, state.persistent.window_height, state.persistent.window_maximized),
+            Err(_) => (1280, 720, false),
+        };
         let window = gtk::ApplicationWindow::builder()
             .application(app)
             .title("RustEdit")
-            .default_width(1280)
-            .default_height(720)
+            .default_width(window_width)
+            .default_height(window_height)
             .css_classes(["dark"])
             .build();
-
+        if window_maximized {
+            window.maximize();
+        }
+        
         // Set proper visual appearance
         window.add_css_class("dark");
         
@@ -2031,7 +11891,7 @@ fn main() -> Result<()> {
         window.set_child(Some(&vbox));
         
         // Create text buffer with syntax highlighting
-        let tag_table = create_tag_table();
+        let tag_table = shared_tag_table();
         let buffer = TextBuffer::new(Some(&tag_table));
         
         // Create status bar
@@ -2045,6 +11905,7 @@ fn main() -> Result<()> {
         let status_label = gtk::Label::new(Some("Line: 1 Col: 1"));
         status_label.set_halign(gtk::Align::Start);
         status_label.set_css_classes(&["status-label"]);
+        status_label.set_hexpand(true);
         status_bar.append(&status_label);
         
         // Create scroll window for text view
@@ -2060,7 +11921,7 @@ fn main() -> Result<()> {
         text_view.set_monospace(true);
         text_view.set_wrap_mode(gtk::WrapMode::None);
         text_view.set_left_margin(10);
-        text_view.set_right_margin(10);
+        text_view.set_right_margin(MIN_RIGHT_MARGIN);
         text_view.set_top_margin(10);
         text_view.set_bottom_margin(10);
         text_view.set_cursor_visible(true);
@@ -2074,20 +11935,90 @@ fn main() -> Result<()> {
         // Set dark mode for the text view
         text_view.set_css_classes(&["dark-mode"]);
         
+        profile.phase("window and text view");
+        
+        // Every tab gets its own editor state, swapped in when its buffer is shown
+        let tabs = Rc::new(RefCell::new(TabManager::new(editor_state.clone())));
+        TABS.with(|cell| *cell.borrow_mut() = Some(tabs.clone()));
+        
+        // Closing the window first offers to save every tab with unsaved changes, then
+        // remembers the window layout for the next launch
+        let state_ref = editor_state.clone();
+        let tabs_ref = tabs.clone();
+        let text_view_ref = text_view.clone();
+        let quit_confirmed = Rc::new(Cell::new(false));
+        window.connect_close_request(move |window| {
+            // Wait for files still being read or written; slow ones can be cancelled from
+            // the status bar
+            if file_jobs_running() {
+                let window = window.clone();
+                glib::timeout_add_local(Duration::from_millis(100), move || {
+                    if file_jobs_running() {
+                        return glib::ControlFlow::Continue;
+                    }
+                    window.close();
+                    glib::ControlFlow::Break
+                });
+                return glib::Propagation::Stop;
+            }
+            if !quit_confirmed.get() {
+                let modified_tabs: Vec<gtk::Button> = tabs_ref.borrow().summaries()
+                    .into_iter()
+                    .filter(|tab| tab.modified)
+                    .map(|tab| tab.button)
+                    .collect();
+                if !modified_tabs.is_empty() {
+                    let window = window.clone();
+                    let quit_confirmed = quit_confirmed.clone();
+                    confirm_close_tabs(&text_view_ref, &state_ref, modified_tabs, Rc::new(move || {
+                        quit_confirmed.set(true);
+                        window.close();
+                    }));
+                    return glib::Propagation::Stop;
+                }
+            }
+            
+            let positions: Vec<(PathBuf, FilePosition)> = tabs_ref.borrow().summaries()
+                .into_iter()
+                .filter_map(|tab| Some((tab.file?, file_position(&tab.buffer))))
+                .collect();
+            if let Ok(mut state) = state_ref.try_borrow_mut() {
+                for (path, position) in positions {
+                    state.persistent.remember_file_position(&path, position);
+                }
+                // The default size follows the unmaximized size as the window is resized
+                let (width, height) = window.default_size();
+                state.persistent.window_width = width;
+                state.persistent.window_height = height;
+                state.persistent.window_maximized = window.is_maximized();
+                if let Err(e) = state.persistent.save() {
+                    warn!("Failed to save editor state: {}", e);
+                }
+            }
+            // A clean exit leaves nothing to recover
+            recovery::remove_all();
+            glib::Propagation::Proceed
+        });
+        
         // Create menu bar and add it to the vbox - note that menu_bar is now the main_container with both menu and tabs
         let (menu_container, new_button, open_button, save_button, _open_recent_button, save_as_button, _tabs_box, find_button, replace_button, show_line_numbers_button) = 
-            create_menu_bar(&window, &buffer, editor_state.clone(), status_label.clone(), &text_view);
+            create_menu_bar(&window, &buffer, editor_state.clone(), &tabs, status_label.clone(), &text_view);
+        profile.phase("menu bar");
         vbox.append(&menu_container);
         
         // Set up find and replace button handlers now that text_view is available
-        let buffer_ref = buffer.clone();
         let window_ref = window.clone();
         let text_view_ref = text_view.clone();
         
+        // Caret, selection and current line colors come from the theme
+        let theme_name = editor_state.try_borrow().map(|state| state.theme_name().to_string()).unwrap_or_default();
+        let theme = theme::Theme::load(&theme_name);
+        CURRENT_THEME_NAME.with(|current| *current.borrow_mut() = theme_name);
+        
         // Set up current line highlighting
         let buffer_for_highlight = buffer.clone();
         let text_view_for_highlight = text_view.clone();
-        highlight_current_line(&buffer_for_highlight, &text_view_for_highlight);
+        highlight_current_line(&buffer_for_highlight, &text_view_for_highlight, &theme);
         
         find_button.connect_clicked(move |_| {
             // Create a dialog for find
@@ -2096,6 +12027,7 @@ fn main() -> Result<()> {
                 Some(&window_ref),
                 gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
                 &[
+                    ("Find All", gtk::ResponseType::Apply),
                     ("Find", gtk::ResponseType::Accept),
                     ("Cancel", gtk::ResponseType::Cancel),
                 ],
@@ -2121,31 +12053,49 @@ fn main() -> Result<()> {
             
             grid.attach(&find_label, 0, 0, 1, 1);
             grid.attach(&find_entry, 1, 0, 1, 1);
+            let options = SearchOptionsRow::attach(&grid, 1);
             
             content_area.append(&grid);
             dialog.show();
             
-            // Get the buffer for searching
-            let buffer = buffer_ref.clone();
+            // Search the active tab's buffer
+            let buffer = text_view_ref.buffer();
             let text_view = text_view_ref.clone();
             
             dialog.connect_response(move |dialog, response| {
-                if response == gtk::ResponseType::Accept {
+                if response == gtk::ResponseType::Apply {
                     let search_text = find_entry.text();
-                    if !search_text.is_empty() {
-                        // Get the cursor position or start of buffer
-                        let mut start_iter = buffer.start_iter();
-                        if let Some(mark) = buffer.mark("insert") {
-                            start_iter = buffer.iter_at_mark(&mark);
+                    if search_text.is_empty() {
+                        return;
+                    }
+                    let searcher = match options.query(&search_text).compile() {
+                        Ok(searcher) => searcher,
+                        Err(e) => {
+                            options.show_error(&e);
+                            return;
                         }
+                    };
+                    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+                    show_search_results(&text_view, &search_text, &text, &searcher.find_all(&text));
+                } else if response == gtk::ResponseType::Accept {
+                    let search_text = find_entry.text();
+                    if !search_text.is_empty() {
+                        // Keep the dialog open so a bad pattern can be fixed
+                        let searcher = match options.query(&search_text).compile() {
+                            Ok(searcher) => searcher,
+                            Err(e) => {
+                                options.show_error(&e);
+                                return;
+                            }
+                        };
                         
-                        // Search for text
-                        if let Some((match_start, match_end)) = start_iter.forward_search(
-                            &search_text,
-                            gtk::TextSearchFlags::CASE_INSENSITIVE,
-                            None,
-                        ) {
+                        // Search from the cursor
+                        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+                        let from = byte_offset_of(&text, buffer.iter_at_mark(&buffer.get_insert()).offset());
+                        if let Some(range) = searcher.find_from(&text, from) {
                             // Select the found text
+                            let match_start = iter_at_byte(&buffer, &text, range.start);
+                            let match_end = iter_at_byte(&buffer, &text, range.end);
                             buffer.select_range(&match_start, &match_end);
                             
                             // Scroll to the selection
@@ -2159,7 +12109,6 @@ fn main() -> Result<()> {
             });
         });
         
-        let buffer_ref = buffer.clone();
         let window_ref = window.clone();
         let text_view_ref = text_view.clone();
         
@@ -2204,12 +12153,13 @@ fn main() -> Result<()> {
             grid.attach(&find_entry, 1, 0, 1, 1);
             grid.attach(&replace_label, 0, 1, 1, 1);
             grid.attach(&replace_entry, 1, 1, 1, 1);
+            let options = SearchOptionsRow::attach(&grid, 2);
             
             content_area.append(&grid);
             dialog.show();
             
-            // Get the buffer for searching and replacing
-            let buffer = buffer_ref.clone();
+            // Search and replace in the active tab's buffer
+            let buffer = text_view_ref.buffer();
             let text_view = text_view_ref.clone();
             let window_ref = window_ref.clone();
             
@@ -2217,23 +12167,29 @@ fn main() -> Result<()> {
                 let search_text = find_entry.text();
                 let replace_text = replace_entry.text();
                 
-                if response == gtk::ResponseType::Accept && !search_text.is_empty() {
-                    // Get the cursor position or start of buffer
-                    let mut start_iter = buffer.start_iter();
-                    if let Some(mark) = buffer.mark("insert") {
-                        start_iter = buffer.iter_at_mark(&mark);
+                let searcher = if search_text.is_empty() || response == gtk::ResponseType::Cancel {
+                    None
+                } else {
+                    // Keep the dialog open so a bad pattern can be fixed
+                    match options.query(&search_text).compile() {
+                        Ok(searcher) => Some(searcher),
+                        Err(e) => {
+                            options.show_error(&e);
+                            return;
+                        }
                     }
-                    
-                    // Search for text
-                    if let Some((mut match_start, mut match_end)) = start_iter.forward_search(
-                        &search_text,
-                        gtk::TextSearchFlags::CASE_INSENSITIVE,
-                        None,
-                    ) {
-                        // Replace the found text
+                };
+                let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+                
+                if let (gtk::ResponseType::Accept, Some(searcher)) = (response, &searcher) {
+                    // Replace the next match after the cursor
+                    let from = byte_offset_of(&text, buffer.iter_at_mark(&buffer.get_insert()).offset());
+                    if let Some((range, replacement)) = searcher.replace_next(&text, from, &replace_text) {
+                        let mut match_start = iter_at_byte(&buffer, &text, range.start);
+                        let mut match_end = iter_at_byte(&buffer, &text, range.end);
                         buffer.begin_user_action();
                         buffer.delete(&mut match_start, &mut match_end);
-                        buffer.insert(&mut match_start, &replace_text);
+                        buffer.insert(&mut match_start, &replacement);
                         buffer.end_user_action();
                         
                         // Move cursor to the end of the replaced text
@@ -2244,24 +12200,17 @@ fn main() -> Result<()> {
                             text_view.scroll_to_mark(&mark, 0.1, false, 0.0, 0.5);
                         }
                     }
-                } else if response == gtk::ResponseType::Apply && !search_text.is_empty() {
-                    // Replace all occurrences
-                    let mut start_iter = buffer.start_iter();
-                    let mut count = 0;
+                } else if let (gtk::ResponseType::Apply, Some(searcher)) = (response, &searcher) {
+                    // Replace all occurrences, last first so earlier offsets stay valid
+                    let replacements = searcher.replace_all(&text, &replace_text);
+                    let count = replacements.len();
                     
                     buffer.begin_user_action();
-                    while let Some((mut match_start, mut match_end)) = start_iter.forward_search(
-                        &search_text,
-                        gtk::TextSearchFlags::CASE_INSENSITIVE,
-                        None,
-                    ) {
-                        // Replace the found text
+                    for (range, replacement) in replacements.iter().rev() {
+                        let mut match_start = iter_at_byte(&buffer, &text, range.start);
+                        let mut match_end = iter_at_byte(&buffer, &text, range.end);
                         buffer.delete(&mut match_start, &mut match_end);
-                        buffer.insert(&mut match_start, &replace_text);
-                        
-                        // Move start_iter to continue searching
-                        start_iter = match_start;
-                        count += 1;
+                        buffer.insert(&mut match_start, replacement);
                     }
                     buffer.end_user_action();
                     
@@ -2286,439 +12235,342 @@ fn main() -> Result<()> {
             });
         });
         
-        // Apply CSS to ensure dark styling
-        let provider = gtk::CssProvider::new();
-        provider.load_from_data(
-            "
-            window {
-                background-color: #1e1e1e;
-            }
-            headerbar {
-                background-color: #1e1e1e;
-                border-bottom: none;
-                padding: 0;
-                min-height: 0;
-            }
-            headerbar button {
-                margin: 0;
-                padding: 2px;
-                background: none;
-                border: none;
-                color: #e0e0e0;
-            }
-            headerbar button:hover {
-                background-color: rgba(255, 255, 255, 0.1);
-            }
-            .dark-mode {
-                background-color: #1e1e1e;
-                color: #e0e0e0;
-                caret-color: #ffffff;
-            }
-            .line-numbers {
-                background-color: #1e1e1e;
-                color: #707070;
-                border-right: 1px solid #303030;
-                margin: 0;
-                padding: 6px 0 0 0;
-            }
-            .text-box {
-                background-color: #1e1e1e;
-                margin: 0;
-                padding: 0;
-            }
-            textview {
-                font-family: 'Monospace';
-                font-size: 12px;
-                padding: 0;
-                background-color: #1e1e1e;
-            }
-            textview text {
-                background-color: #1e1e1e;
-                color: #e0e0e0;
-            }
-            scrolledwindow {
-                border: none;
-                background-color: #1e1e1e;
-                padding: 0;
-                margin: 0;
-            }
-            .error-line {
-                background-color: rgba(255, 0, 0, 0.2);
-            }
-            .error-text {
-                text-decoration: underline;
-                text-decoration-color: #ff3333;
-                text-decoration-style: wavy;
-            }
-            .main-menu-container {
-                background-color: #1e1e1e;
-            }
-            .menu-bar {
-                background-color: #1e1e1e;
-                padding: 0 4px;
-                border-bottom: none;
-            }
-            .menu-button {
-                background: none;
-                color: #e0e0e0;
-                margin-right: 1px;
-                margin-top: 0;
-                margin-bottom: 0;
-                font-size: 0.95em;
-                min-height: 18px;
-                padding: 1px 1px;
-                border: none;
-                border-radius: 2px;
-                box-shadow: none;
-                outline: none;
-                font-weight: normal;
-                width: min-content;
-                min-width: min-content;
-            }
-            .menu-button:hover {
-                background-color: rgba(255, 255, 255, 0.05);
-            }
-            .menu-button:active, 
-            .menu-button:checked,
-            .menu-button:focus {
-                outline: none;
-                box-shadow: none;
-                background-color: rgba(255, 255, 255, 0.05);
-            }
-            menubutton {
-                padding: 0;
-                margin: 0;
-                min-height: 0;
-                min-width: 0;
-                width: min-content;
-                outline: none;
-                box-shadow: none;
-                background: none;
-            }
-            menubutton > box {
-                min-height: 0;
-                padding: 0;
-                margin: 0;
-                width: min-content;
-            }
-            menubutton:focus, menubutton:active {
-                outline: none;
-                box-shadow: none;
-            }
-            menubutton > arrow {
-                -gtk-icon-size: 0;
-                min-height: 0;
-                min-width: 0;
-                padding: 0;
-                margin: 0;
-                opacity: 0;
-            }
-            menubutton button {
-                border: none !important;
-                outline: none !important;
-                box-shadow: none !important;
-                background: none !important;
-            }
-            
-            menubutton > button:focus,
-            menubutton > button:active,
-            menubutton > button:checked {
-                outline: none !important;
-                border: none !important;
-                box-shadow: none !important;
-            }
-            .text-button {
-                background: none;
-                color: #e0e0e0;
-                margin-right: 12px;
-                margin-top: 2px;
-                margin-bottom: 2px;
-                font-size: 0.95em;
-                min-height: 18px;
-                padding: 2px 8px;
-                border: 1px solid rgba(255, 255, 255, 0.15);
-                border-radius: 4px;
-                box-shadow: none;
-            }
-            .text-button:hover {
-                background-color: rgba(255, 255, 255, 0.05);
-                border-color: rgba(255, 255, 255, 0.2);
-            }
-            .text-button:active, 
-            .text-button:checked,
-            .text-button:focus {
-                background-color: rgba(255, 255, 255, 0.05);
-                border-color: rgba(255, 255, 255, 0.2);
-                box-shadow: none;
-                outline: none;
-            }
-            .menu-separator {
-                margin: 0;
-                background-color: #303030;
-            }
-            .shortcut-label {
-                opacity: 0.7;
-                font-size: 0.9em;
-            }
-            .tabs-row {
-                background-color: #1e1e1e;
-                padding: 1px 0 1px 35px; 
-                border-bottom: 1px solid #202020;
-            }
-            .tab-bar {
-                background-color: #1e1e1e;
-                padding: 0;
-            }
-            .tabs-box {
-                padding: 0;
-            }
-            .tab-button {
-                background-color: #252525;
-                padding: 2px 6px;
-                border-radius: 2px;
-                margin-right: 1px;
-                border: none;
-                color: #d0d0d0;
-                min-width: 0;
-                width: auto;
-                transition: background-color 150ms ease-out;
-            }
-            .tab-button-wrapper {
-                background: none;
-                border-radius: 2px;
-                margin: 0 1px 0 0;
-                min-height: 0;
-                min-width: 0;
-                width: auto;
-                transition: all 150ms ease-out;
-            }
-            .tab-button-wrapper:checked .tab-button,
-            .tab-button-wrapper:active .tab-button {
-                background-color: #303030;
-                box-shadow: none;
-            }
-            .tab-label {
-                color: #e0e0e0;
-                font-size: 0.95em;
-                padding: 0;
-                margin: 0;
-                min-width: 0;
-                width: auto;
-            }
-            .tab-close-button {
-                padding: 0;
-                min-height: 12px;
-                min-width: 12px;
-                border-radius: 2px;
-                background: none;
-                opacity: 0.7;
-                transition: all 150ms ease-out;
-            }
-            .tab-close-button:hover {
-                background-color: rgba(255, 0, 0, 0.2);
-                opacity: 1;
-            }
-            .new-tab-button {
-                padding: 2px;
-                min-height: 20px;
-                min-width: 20px;
-                margin: 1px 2px 0 4px;
-                border-radius: 3px;
-                background: rgba(255, 255, 255, 0.03);
-                color: #d0d0d0;
-                border: none;
-                position: relative;
-                top: 1px;
-                transition: all 150ms ease-out;
-            }
-            .new-tab-button:hover {
-                background-color: rgba(255, 255, 255, 0.08);
-            }
-            .tab-button-wrapper.active .tab-button {
-                background-color: #3a3a3a;
-                box-shadow: none;
-                transition: background-color 150ms ease-out;
-            }
-            .tab-button-wrapper.active {
-                background-color: transparent;
-                transition: all 150ms ease-out;
-            }
-            button {
-                min-height: 0;
-                min-width: 0;
-            }
-            popover, 
-            popover contents {
-                background-color: #252525;
-                border: none;
-                border-radius: 3px;
-                box-shadow: 0 3px 6px rgba(0, 0, 0, 0.4);
-                margin: 0;
-                padding: 1px;
-            }
-            popover box {
-                padding: 0;
-                margin: 0;
-                spacing: 2px;
-            }
-            popover button {
-                border: none;
-                background: none;
-                box-shadow: none;
-                outline: none;
-                padding: 3px 6px;
-                color: #e0e0e0;
-                min-height: 24px;
-                min-width: 0;
-                width: auto;
-                border-radius: 4px;
-            }
-            
-            popover button:not(:hover) {
-                background-color: transparent;
-            }
-            
-            popover button:hover {
-                background-color: rgba(255, 255, 255, 0.1);
-            }
-            
-            popover.menu {
-                padding: 0;
-                margin: 0;
-            }
-            .status-bar {
-                background-color: #252525;
-                border-top: 1px solid rgba(255, 255, 255, 0.1);
-                padding: 2px 8px;
-            }
-            .status-label {
-                color: #b0b0b0;
-                font-size: 0.9em;
-            }
-            .tab-button-wrapper.active .tab-button {
-                background-color: #3a3a3a;
-                box-shadow: none;
-            }
-            .tab-button-wrapper.active {
-                background-color: transparent;
-            }
-            "
-        );
-        
-        let display = gtk::gdk::Display::default().unwrap();
-        gtk::style_context_add_provider_for_display(
-            &display,
-            &provider,
-            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
-        );
-
-        // Create a box for text view and line numbers with better layout
-        let text_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
-        text_box.set_hexpand(true);
-        text_box.set_vexpand(true);
-        text_box.set_css_classes(&["text-box"]);
+        // Window style sheet in the theme's colors
+        apply_theme(&theme);
+        profile.phase("style sheets");
 
-        // Create line number display
+        // Line number gutter, in the view's left border so it scrolls with the text
         let line_numbers = gtk::DrawingArea::new();
-        line_numbers.set_width_request(30);
         line_numbers.set_hexpand(false);
         line_numbers.set_vexpand(true);
-        line_numbers.set_content_width(30);
-
-        // Add a CSS class for styling the line numbers
         line_numbers.set_css_classes(&["line-numbers"]);
 
-        // Set reference to buffer for drawing line numbers
-        let buffer_for_draw = buffer.clone();
+        // Set reference to the view for drawing line numbers of whichever buffer it shows
         let text_view_for_draw = text_view.clone();
+        let state_for_draw = editor_state.clone();
+
+        // Lines are numbered where the view actually laid them out, so wrapped lines,
+        // zoom and line spacing stay in step with the text
+        line_numbers.set_draw_func(move |area, cr, width, height| {
+            GUTTER_PAINTED.with(|painted| painted.set(Some(gutter_view_key(&text_view_for_draw))));
 
-        // Set up the drawing function for line numbers
-        line_numbers.set_draw_func(move |_, cr, width, height| {
-            // Set dark background for line numbers
-            cr.set_source_rgb(0.12, 0.12, 0.12);  // Darker background to match theme
+            // Gutter colors of the theme; the cursor's line is numbered in the text color
+            let (background, foreground, current, edit_marker, bookmark, git_added, git_modified, git_deleted) = CURRENT_THEME.with(|theme| {
+                let theme = theme.borrow();
+                let parse = |color: &str| gtk::gdk::RGBA::parse(color).unwrap_or(gtk::gdk::RGBA::BLACK);
+                (
+                    parse(&theme.gutter_background), parse(&theme.gutter_foreground), parse(&theme.foreground), parse(&theme.edit_marker),
+                    parse(&theme.bookmark), parse(&theme.git_added), parse(&theme.git_modified), parse(&theme.git_deleted),
+                )
+            });
+            let set_color = |color: &gtk::gdk::RGBA| {
+                cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, color.alpha() as f64);
+            };
+            set_color(&background);
             cr.rectangle(0.0, 0.0, width as f64, height as f64);
             cr.fill().expect("Failed to fill background");
-            
-            // Use light gray text for line numbers
-            cr.set_source_rgb(0.5, 0.5, 0.5);  // More subtle color for line numbers
-            
+
+            // Numbers in the view's font, so they scale with zoom
             let layout = pangocairo::functions::create_layout(cr);
-            let font_desc = pango::FontDescription::from_string("Monospace 9");
-            layout.set_font_description(Some(&font_desc));
-            
-            // Get visible range and adjustment values
-            let vadj = text_view_for_draw.vadjustment().unwrap();
-            let scroll_pos = vadj.value();
-            let line_height = 18.0; // Approximate line height
-            
-            // Calculate first visible line
-            let start_line = (scroll_pos / line_height).floor() as i32;
-            let visible_lines = (height as f64 / line_height).ceil() as i32 + 1;
-            let line_count = buffer_for_draw.line_count();
-            
-            // Draw visible line numbers
-            for i in 0..visible_lines {
-                let line_num = start_line + i;
-                if line_num < line_count {
-                    // Calculate y position with offset for scrolling
-                    let y = (i as f64 * line_height) - (scroll_pos % line_height);
-                    
-                    layout.set_text(&format!("{:>3}", line_num + 1));
-                    cr.move_to(4.0, y);  // Added a bit more padding
+            let font = text_view_for_draw.pango_context().font_description()
+                .unwrap_or_else(|| pango::FontDescription::from_string("Monospace 9"));
+            layout.set_font_description(Some(&font));
+
+            // Wide enough for the longest number and a lightbulb
+            let buffer = text_view_for_draw.buffer();
+            let digits = buffer.line_count().to_string().len().max(3);
+            layout.set_text(&"0".repeat(digits));
+            let digits_width = layout.pixel_size().0;
+            layout.set_text("💡");
+            let bulb_width = layout.pixel_size().0;
+            let wanted_width = digits_width + bulb_width + 3 * GUTTER_PADDING;
+            if area.content_width() != wanted_width {
+                area.set_content_width(wanted_width);
+            }
+
+            let (fix_lines, heat_map) = state_for_draw.try_borrow()
+                .map(|state| (quick_fix_lines(&state), state.persistent.edit_heat_map))
+                .unwrap_or_default();
+            let heat = if heat_map { edit_heat(&buffer) } else { HashMap::new() };
+            let bookmarks = bookmark_lines(&buffer);
+            let git_hunks = git_hunks(&buffer);
+            let cursor_line = buffer.iter_at_mark(&buffer.get_insert()).line();
+
+            let visible = text_view_for_draw.visible_rect();
+            let Some(mut line_start) = text_view_for_draw.iter_at_location(0, visible.y()) else {
+                return;
+            };
+            line_start.set_line_offset(0);
+            loop {
+                let (top, line_height) = text_view_for_draw.line_yrange(&line_start);
+                if top > visible.y() + visible.height() {
+                    break;
+                }
+                // Centered on the first display line of wrapped lines
+                let (_, y) = text_view_for_draw.buffer_to_window_coords(gtk::TextWindowType::Left, 0, top);
+                let first_row_height = text_view_for_draw.iter_location(&line_start).height();
+                let line = line_start.line();
+
+                // Heat map shading, fading with the age of the line's latest edit
+                if let Some(recency) = heat.get(&line) {
+                    let alpha = 0.15 + 0.55 * recency;
+                    cr.set_source_rgba(edit_marker.red() as f64, edit_marker.green() as f64, edit_marker.blue() as f64, alpha);
+                    cr.rectangle(0.0, y as f64, width as f64, line_height as f64);
+                    cr.fill().expect("Failed to fill edit marker");
+                }
+
+                // Bars for lines changed since the last commit, wedges where lines were removed
+                for hunk in &git_hunks {
+                    match hunk.kind {
+                        git::HunkKind::Added | git::HunkKind::Modified if hunk.lines.contains(&(line as usize)) => {
+                            set_color(if hunk.kind == git::HunkKind::Added { &git_added } else { &git_modified });
+                            cr.rectangle(0.0, y as f64, GIT_MARKER_WIDTH, line_height as f64);
+                            cr.fill().expect("Failed to fill git marker");
+                        }
+                        git::HunkKind::Deleted if hunk.lines.start == line as usize || (hunk.lines.start > line as usize && line + 1 == buffer.line_count()) => {
+                            let wedge_y = if hunk.lines.start == line as usize { y } else { y + line_height } as f64;
+                            cr.move_to(0.0, wedge_y - 2.0 * GIT_MARKER_WIDTH);
+                            cr.line_to(2.0 * GIT_MARKER_WIDTH, wedge_y);
+                            cr.line_to(0.0, wedge_y + 2.0 * GIT_MARKER_WIDTH);
+                            cr.close_path();
+                            set_color(&git_deleted);
+                            cr.fill().expect("Failed to fill git marker");
+                        }
+                        _ => {}
+                    }
+                }
+
+                layout.set_text(&(line + 1).to_string());
+                let (number_width, number_height) = layout.pixel_size();
+                let text_y = (y + (first_row_height - number_height) / 2) as f64;
+                set_color(if line == cursor_line { &current } else { &foreground });
+                cr.move_to((GUTTER_PADDING + digits_width - number_width) as f64, text_y);
+                pangocairo::functions::show_layout(cr, &layout);
+
+                // Ribbon for bookmarked lines, where a lightbulb would go
+                if bookmarks.binary_search(&line).is_ok() {
+                    let left = (2 * GUTTER_PADDING + digits_width) as f64 + bulb_width as f64 * 0.2;
+                    let right = left + bulb_width as f64 * 0.6;
+                    let (top, bottom) = ((y + 2) as f64, (y + first_row_height - 2) as f64);
+                    cr.move_to(left, top);
+                    cr.line_to(right, top);
+                    cr.line_to(right, bottom);
+                    cr.line_to((left + right) / 2.0, bottom - (right - left) / 2.0);
+                    cr.line_to(left, bottom);
+                    cr.close_path();
+                    set_color(&bookmark);
+                    cr.fill().expect("Failed to fill bookmark");
+                }
+
+                // Lightbulb for lines with quick fixes
+                if fix_lines.contains(&(line as usize)) {
+                    layout.set_text("💡");
+                    cr.move_to((2 * GUTTER_PADDING + digits_width) as f64, text_y);
                     pangocairo::functions::show_layout(cr, &layout);
                 }
+
+                // Rows a wrapped line continues on are marked with an arrow
+                if line_height > first_row_height {
+                    set_color(&foreground);
+                    layout.set_text("↪");
+                    let arrow_width = layout.pixel_size().0;
+                    let mut row = line_start;
+                    while text_view_for_draw.forward_display_line(&mut row) && row.line() == line && !row.starts_line() {
+                        let row_location = text_view_for_draw.iter_location(&row);
+                        if row_location.y() > visible.y() + visible.height() {
+                            break;
+                        }
+                        let (_, row_y) = text_view_for_draw.buffer_to_window_coords(gtk::TextWindowType::Left, 0, row_location.y());
+                        cr.move_to((GUTTER_PADDING + digits_width - arrow_width) as f64, (row_y + (row_location.height() - number_height) / 2) as f64);
+                        pangocairo::functions::show_layout(cr, &layout);
+                    }
+                }
+
+                if !line_start.forward_line() {
+                    break;
+                }
             }
         });
+        text_view.set_gutter(gtk::TextWindowType::Left, Some(&line_numbers));
+
+        // The view scrolls itself, which also gives it the adjustments the gutter follows
+        scroll.set_child(Some(&text_view));
 
-        // Handle adjustments to redraw line numbers when scrolling
+        // Redraw line numbers when scrolling, and when relayout (zoom, wrapping) moves lines
         if let Some(vadj) = text_view.vadjustment() {
-            let line_numbers_clone = line_numbers.clone();
+            let text_view_ref = text_view.clone();
             vadj.connect_value_changed(move |_| {
-                line_numbers_clone.queue_draw();
+                queue_gutter_redraw(&text_view_ref, false);
+            });
+            let text_view_ref = text_view.clone();
+            vadj.connect_changed(move |_| {
+                queue_gutter_redraw(&text_view_ref, false);
             });
         }
 
-        // Create text source view with line numbers
-        text_box.append(&line_numbers);
-        text_box.append(&text_view);
+        // Clicking a lightbulb in the gutter offers that line's quick fixes
+        let gutter_click = gtk::GestureClick::new();
+        let line_numbers_ref = line_numbers.clone();
+        let text_view_ref = text_view.clone();
+        let buffer_ref = buffer.clone();
+        let state_ref = editor_state.clone();
+        gutter_click.connect_pressed(move |_, _, _, y| {
+            if text_view_ref.buffer() != buffer_ref {
+                return;
+            }
+            let (_, buffer_y) = text_view_ref.window_to_buffer_coords(gtk::TextWindowType::Left, 0, y as i32);
+            let (line_start, _) = text_view_ref.line_at_y(buffer_y);
+            let (top, height) = text_view_ref.line_yrange(&line_start);
+            let (_, top) = text_view_ref.buffer_to_window_coords(gtk::TextWindowType::Left, 0, top);
+            let rect = gtk::gdk::Rectangle::new(0, top, line_numbers_ref.width(), height);
+            show_quick_fixes(&line_numbers_ref, &rect, &buffer_ref, &state_ref, line_start.line() as usize);
+        });
+        line_numbers.add_controller(gutter_click);
+
+        // Right-clicking a git marker offers to revert its hunk
+        let gutter_right_click = gtk::GestureClick::new();
+        gutter_right_click.set_button(gtk::gdk::BUTTON_SECONDARY);
+        let line_numbers_ref = line_numbers.clone();
+        let text_view_ref = text_view.clone();
+        gutter_right_click.connect_pressed(move |_, _, _, y| {
+            let (_, buffer_y) = text_view_ref.window_to_buffer_coords(gtk::TextWindowType::Left, 0, y as i32);
+            let (line_start, _) = text_view_ref.line_at_y(buffer_y);
+            let (top, height) = text_view_ref.line_yrange(&line_start);
+            let (_, top) = text_view_ref.buffer_to_window_coords(gtk::TextWindowType::Left, 0, top);
+            let rect = gtk::gdk::Rectangle::new(0, top, line_numbers_ref.width(), height);
+            show_git_hunk_menu(&line_numbers_ref, &rect, &text_view_ref.buffer(), line_start.line() as usize);
+        });
+        line_numbers.add_controller(gutter_right_click);
+        LINE_NUMBERS.with(|gutter| *gutter.borrow_mut() = Some(line_numbers.clone()));
         
-        // Add the text box to the scroll window
-        scroll.set_child(Some(&text_box));
+        // Scrollbars follow the setting, and the horizontal one whether lines wrap
+        let state_ref = editor_state.clone();
+        text_view.connect_wrap_mode_notify(move |view| {
+            let mode = state_ref.try_borrow().map(|state| state.persistent.scrollbars).unwrap_or_default();
+            apply_scrollbar_mode(view, mode);
+        });
+        let scrollbars = editor_state.try_borrow().map(|state| state.persistent.scrollbars).unwrap_or_default();
+        apply_scrollbar_mode(&text_view, scrollbars);
+        
+        // Font and tab width come from the settings
+        if let Ok(state) = editor_state.try_borrow() {
+            apply_zoom(&text_view, &state.settings, state.zoom_level);
+        }
         
-        // Ensure the scroll window is added to the vbox
-        vbox.append(&scroll);
+        // Ensure the scroll window is added to the vbox, with the Find in Files panel beside it
+        // Encoding problems and failed saves are reported above the editor
+        vbox.append(&create_warning_bar());
+        vbox.append(&create_read_only_bar(&text_view, &editor_state));
 
+        let editor_row = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        editor_row.append(&create_favorites_sidebar(&window, &text_view, &editor_state, &status_label));
+        let editor_panes = create_editor_panes(&scroll, &text_view, &tabs);
+        editor_row.append(&editor_panes);
+        editor_row.append(&create_search_ruler(&text_view));
+        editor_row.append(&create_find_in_files_panel(&window, &text_view, &editor_state, &status_label));
+        editor_row.append(&create_recent_edits_panel(&text_view, &tabs));
+        editor_row.append(&create_bookmarks_panel(&text_view));
+        vbox.append(&editor_row);
+
+        // Unbalanced brackets and Find All results are listed between the editor and the status bar
+        vbox.append(&create_bracket_panel(&text_view, &editor_state));
+        vbox.append(&create_search_results_panel(&text_view));
+        vbox.append(&create_byte_inspector());
+        vbox.append(&create_output_panel());
+        vbox.append(&create_prose_panel(&text_view));
+
+        // Indentation, per-tab line endings, encoding and language override at the right end of the status bar
+        status_bar.append(&create_git_status_label());
+        status_bar.append(&create_file_job_indicator());
+        status_bar.append(&create_indentation_selector(&text_view, &editor_state, &status_label));
+        status_bar.append(&create_line_ending_selector(&text_view, &editor_state, &status_label));
+        status_bar.append(&create_encoding_selector(&window, &text_view, &editor_state, &status_label, &save_button));
+        status_bar.append(&create_language_selector(&text_view, &editor_state));
+        
         // Add status bar to vbox
         vbox.append(&status_bar);
+        profile.phase("gutter, panels and status bar");
+        
+        // Completion popup for file paths typed inside string literals, code, and words in prose
+        let completion = CompletionPopup::new(&text_view);
+        // Block selection goes first, so typing into a block is not expanded or replaced
+        connect_block_selection(&text_view, &editor_state);
+        connect_abbreviations(&text_view, Some(completion.clone()));
+        connect_smart_typography(&text_view, &editor_state);
+        connect_markdown_lists(&text_view, &editor_state);
+        connect_markdown_tables(&text_view, &editor_state);
+        connect_indentation(&text_view, &editor_state);
+        connect_pair_deletion(&text_view);
+        connect_word_chars(&text_view, &editor_state);
+        connect_smart_home_end(&text_view, &editor_state);
+        connect_paragraph_moves(&text_view, &editor_state);
+        connect_whitespace_marks(&text_view);
+        connect_clipboard_ring(&text_view);
+        connect_external_clipboard(&window, &editor_state);
+        connect_wrap_column(&text_view, &editor_state);
+
+        connect_buffer_signals(&buffer, &text_view, &editor_state, &status_label, &completion);
+        let focus_mode = editor_state.try_borrow().map(|state| state.persistent.focus_mode).unwrap_or(false);
+        update_focus_mode(&buffer, focus_mode);
         
-        // Update status bar when cursor position changes
+        // Leaving the window saves the shown tab when save-on-focus-loss is enabled, and
+        // coming back picks up commits and checkouts made elsewhere
         let state_ref = editor_state.clone();
-        let status_label_ref = status_label.clone();
-        buffer.connect_changed(move |buf| {
-            let text = buf.text(&buf.start_iter(), &buf.end_iter(), false);
-            let text_str = text.as_str();
-            
-            if let Ok(mut state) = state_ref.lock() {
-                state.is_modified = true;
-                
-                // Only push to undo stack if content actually changed
-                if state.text_buffer.text() != text_str {
-                    // Store current text before modifying it
-                    let current_text = state.text_buffer.text().to_string();
-                    state.push_to_undo_stack(&current_text);
-                    state.text_buffer.set_text(text_str);
-                }
+        let text_view_ref = text_view.clone();
+        window.connect_is_active_notify(move |window| {
+            if !window.is_active() {
+                save_on_focus_loss(&text_view_ref.buffer(), &state_ref);
+            } else {
+                let path = state_ref.try_borrow().ok().and_then(|state| state.current_file.clone());
+                refresh_git(&text_view_ref.buffer(), &text_view_ref, path.as_deref());
             }
-            update_status_bar(&status_label_ref, buf, &state_ref);
-            
-            // Apply syntax highlighting
-            apply_syntax_highlighting(buf);
         });
         
+        // Switching tabs swaps in that tab's editor state; each buffer gets the
+        // editing signals the first time it is shown
+        let connected_buffers = RefCell::new(vec![buffer.clone()]);
+        let tabs_for_switch = tabs.clone();
         let state_ref = editor_state.clone();
         let status_label_ref = status_label.clone();
-        buffer.connect_mark_set(move |buf, _, _| {
-            update_status_bar(&status_label_ref, buf, &state_ref);
+        let completion_ref = completion.clone();
+        text_view.connect_buffer_notify(move |view| {
+            // The tab being left still owns the shared state at this point
+            let previous_buffer = tabs_for_switch.borrow().active_buffer();
+            if let Some(previous_buffer) = previous_buffer {
+                save_on_focus_loss(&previous_buffer, &state_ref);
+            }
+            
+            let buffer = view.buffer();
+            tabs_for_switch.borrow_mut().activate(&buffer);
+            // Any warning shown was about the tab being left
+            hide_warning_bar();
+            
+            if !connected_buffers.borrow().contains(&buffer) {
+                connect_buffer_signals(&buffer, view, &state_ref, &status_label_ref, &completion_ref);
+                connected_buffers.borrow_mut().push(buffer.clone());
+            }
+            
+            update_status_bar(&status_label_ref, &buffer, &state_ref);
+            if let Ok(state) = state_ref.try_borrow() {
+                apply_zoom(view, &state.settings, state.zoom_level);
+            }
+            show_read_only(view, &state_ref);
+            show_wrap_mode(view, &state_ref);
+            let path = state_ref.try_borrow().ok().and_then(|state| state.current_file.clone());
+            refresh_git(&buffer, view, path.as_deref());
+            
+            // Focus mode may have been toggled while another tab was shown
+            let focus_mode = state_ref.try_borrow().map(|state| state.persistent.focus_mode).unwrap_or(false);
+            update_focus_mode(&buffer, focus_mode);
+            
+            // The diff layout may have been switched while another tab was shown
+            update_diff_layout(view, &state_ref);
+            
+            // Inline diagnostics only show on the buffer they were placed in
+            layout_inline_diagnostics(view);
+            update_block_overlay(view, &state_ref);
+            queue_search_ruler_redraw();
+            queue_gutter_redraw(view, true);
         });
         
         // Set up keyboard shortcuts with additional zoom functionality
@@ -2730,125 +12582,184 @@ fn main() -> Result<()> {
         let state_ref = editor_state.clone();
         let text_view_ref = text_view.clone();
         let window_ref = window.clone();  // Create a separate clone for the closure
+        let tabs_ref = tabs.clone();
         
-        key_controller.connect_key_pressed(move |_, key, _keycode, state| {
-            let ctrl = state.contains(gtk::gdk::ModifierType::CONTROL_MASK);
-            let shift = state.contains(gtk::gdk::ModifierType::SHIFT_MASK);
-            
-            if ctrl {
-                match key {
-                    gtk::gdk::Key::s => {
-                        if shift {
-                            // Ctrl+Shift+S - Save As
-                            save_as_button_ref.emit_clicked();
-                        } else {
-                            // Ctrl+S - Save
-                            save_button_ref.emit_clicked();
-                        }
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::o => {
-                        // Ctrl+O - Open
-                        open_button_ref.emit_clicked();
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::t => {
-                        // Ctrl+T - New File (changed from n to t to match COSMIC)
-                        new_button_ref.emit_clicked();
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::w => {
-                        // Ctrl+W - Close File
-                        buffer.set_text("");
-                        if let Ok(mut state) = state_ref.lock() {
-                            state.text_buffer.set_text("");
-                            state.current_file = None;
-                            state.is_modified = false;
-                            state.update_tab_name();
-                        }
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::q => {
-                        // Ctrl+Q - Quit
-                        window_ref.close();  // Use window_ref instead of window
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::plus | gtk::gdk::Key::equal => {
-                        // Ctrl+Plus or Ctrl+= - Zoom In
-                        if let Ok(mut state) = state_ref.lock() {
-                            state.zoom_in();
-                            apply_zoom(&text_view_ref, state.zoom_level);
-                        }
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::minus => {
-                        // Ctrl+Minus - Zoom Out
-                        if let Ok(mut state) = state_ref.lock() {
-                            state.zoom_out();
-                            apply_zoom(&text_view_ref, state.zoom_level);
-                        }
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::_0 => {
-                        // Ctrl+0 - Reset Zoom
-                        if let Ok(mut state) = state_ref.lock() {
-                            state.reset_zoom();
-                            apply_zoom(&text_view_ref, state.zoom_level);
-                        }
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::z => {
-                        // Ctrl+Z - Undo
-                        if let Ok(mut state) = state_ref.lock() {
-                            if let Some(previous_text) = state.undo() {
-                                buffer.set_text(&previous_text);
-                                state.text_buffer.set_text(&previous_text);
-                            }
-                        }
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::y => {
-                        // Ctrl+Y - Redo
-                        if let Ok(mut state) = state_ref.lock() {
-                            if let Some(next_text) = state.redo() {
-                                buffer.set_text(&next_text);
-                                state.text_buffer.set_text(&next_text);
-                            }
-                        }
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::f => {
-                        // Ctrl+F - Find
-                        find_button.emit_clicked();
-                        return glib::Propagation::Stop;
-                    },
-                    gtk::gdk::Key::h => {
-                        // Ctrl+H - Replace
-                        replace_button.emit_clicked();
-                        return glib::Propagation::Stop;
-                    },
-                    _ => {}
-                }
+        let run_action = Rc::new(move |action: Action| {
+            if action.edits_text() && !text_view_ref.is_editable() {
+                return glib::Propagation::Stop;
+            }
+            match action {
+                Action::NextSymbol => jump_to_symbol(&text_view_ref, &state_ref, true),
+                Action::PreviousSymbol => jump_to_symbol(&text_view_ref, &state_ref, false),
+                Action::Save => save_button_ref.emit_clicked(),
+                Action::SaveAs => save_as_button_ref.emit_clicked(),
+                Action::Revert => revert_shown_tab(&window_ref, &text_view_ref, &state_ref),
+                Action::Print => print_shown_tab(&window_ref, &text_view_ref, &state_ref, false),
+                Action::TabOverview => show_tab_overview(&text_view_ref, &tabs_ref),
+                Action::GoToSymbol => show_symbol_search(&text_view_ref, &state_ref),
+                Action::GoToLine => show_go_to_line(&text_view_ref),
+                Action::Open => open_button_ref.emit_clicked(),
+                Action::QuickOpen => show_quick_open(&text_view_ref, &state_ref),
+                Action::QuickFix => {
+                    // Without fixes on the line the key does what it would otherwise do
+                    if !show_quick_fixes_at_cursor(&text_view_ref, &buffer, &state_ref) {
+                        return glib::Propagation::Proceed;
+                    }
+                },
+                Action::NewTab => new_button_ref.emit_clicked(),
+                Action::CloseTab => {
+                    let text_view = text_view_ref.clone();
+                    let state = state_ref.clone();
+                    confirm_close_tab(&text_view_ref, &state_ref, move || clear_shown_tab(&text_view, &state));
+                },
+                Action::Quit => window_ref.close(),
+                Action::ZoomIn => {
+                    if let Ok(mut state) = state_ref.try_borrow_mut() {
+                        state.zoom_in();
+                        apply_zoom(&text_view_ref, &state.settings, state.zoom_level);
+                    }
+                },
+                Action::ZoomOut => {
+                    if let Ok(mut state) = state_ref.try_borrow_mut() {
+                        state.zoom_out();
+                        apply_zoom(&text_view_ref, &state.settings, state.zoom_level);
+                    }
+                },
+                Action::ResetZoom => {
+                    if let Ok(mut state) = state_ref.try_borrow_mut() {
+                        state.reset_zoom();
+                        apply_zoom(&text_view_ref, &state.settings, state.zoom_level);
+                    }
+                },
+                Action::Undo => {
+                    step_history(&text_view_ref, &state_ref, false);
+                },
+                Action::Redo => {
+                    step_history(&text_view_ref, &state_ref, true);
+                },
+                Action::DuplicateLine => duplicate_lines(&text_view_ref),
+                Action::MoveLinesUp => move_lines(&text_view_ref, false),
+                Action::MoveLinesDown => move_lines(&text_view_ref, true),
+                Action::SelectLine => select_from_mirror(&text_view_ref, &state_ref, |buffer, selection| Some(buffer.line_selection(selection))),
+                Action::SelectParagraph => select_from_mirror(&text_view_ref, &state_ref, |buffer, selection| buffer.paragraph_range(selection.start)),
+                Action::ExpandSelection => select_from_mirror(&text_view_ref, &state_ref, |buffer, selection| Some(buffer.expand_selection(selection))),
+                Action::ShowCompletions => update_completion(&completion, &text_view_ref, &state_ref, true),
+                Action::PasteFromHistory => show_clipboard_history(&text_view_ref),
+                Action::SwapWithClipboard => swap_with_clipboard(&text_view_ref),
+                Action::FormatTable => format_markdown_table(&text_view_ref),
+                Action::ToggleReadOnly => toggle_read_only(&text_view_ref, &state_ref),
+                Action::RunSelection => run_selection(&text_view_ref),
+                Action::FindInFiles => show_find_in_files(),
+                Action::Find => find_button.emit_clicked(),
+                Action::Replace => replace_button.emit_clicked(),
+                Action::FocusOtherPane => focus_other_pane(&text_view_ref),
+                Action::JumpToBracket => jump_to_matching_bracket(&text_view_ref, &state_ref),
+                Action::ToggleLineComment => toggle_comment(&text_view_ref, &state_ref, false),
+                Action::ToggleBlockComment => toggle_comment(&text_view_ref, &state_ref, true),
+                Action::CaretBack => step_caret_history(&text_view_ref, &state_ref, false),
+                Action::CaretForward => step_caret_history(&text_view_ref, &state_ref, true),
+                Action::ToggleBookmark => toggle_bookmark(&text_view_ref),
+                Action::NextBookmark => step_bookmark(&text_view_ref, true),
+                Action::NextChange => step_change(&text_view_ref, true),
+                Action::PreviousChange => step_change(&text_view_ref, false),
+                Action::PreviousBookmark => step_bookmark(&text_view_ref, false),
+                Action::MoveTabLeft => move_shown_tab(&text_view_ref, &tabs_ref, |index| index.saturating_sub(1)),
+                Action::MoveTabRight => move_shown_tab(&text_view_ref, &tabs_ref, |index| index + 1),
+                Action::MoveTabToPosition => show_tab_position_picker(&text_view_ref, &tabs_ref),
+            }
+            glib::Propagation::Stop
+        });
+        let run = run_action.clone();
+        key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+            let binding = KeyBinding::new(key, modifiers);
+            match KEYMAP.with(|keymap| keymap.borrow().action_for(&binding)) {
+                Some(action) => run(action),
+                None => glib::Propagation::Proceed,
             }
-            
-            glib::Propagation::Proceed
         });
         window.add_controller(key_controller);
 
+        // The text views have bindings of their own for some keys, such as Ctrl+Shift+Page Up,
+        // and would handle them before the window; the actions on those keys go first
+        let view_key_controller = gtk::EventControllerKey::new();
+        view_key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+        view_key_controller.connect_key_pressed(move |_, key, _keycode, modifiers| {
+            let binding = KeyBinding::new(key, modifiers);
+            match KEYMAP.with(|keymap| keymap.borrow().action_for(&binding)) {
+                Some(action) if action.overrides_text_view() => run_action(action),
+                _ => glib::Propagation::Proceed,
+            }
+        });
+        editor_panes.add_controller(view_key_controller);
+        profile.phase("signal handlers");
+
+        // Preview file paths and images when the pointer rests on them
+        let hover_controller = gtk::EventControllerMotion::new();
+        let text_view_ref = text_view.clone();
+        let state_ref = editor_state.clone();
+        hover_controller.connect_motion(move |_, x, y| {
+            schedule_hover_preview(&text_view_ref, &state_ref, x, y);
+        });
+        hover_controller.connect_leave(|_| cancel_hover_preview());
+        text_view.add_controller(hover_controller);
+
         // Show the GTK window
         window.show();
+        profile.phase("window shown");
+        let profile_ref = profile.clone();
+        window.add_tick_callback(move |_, _| {
+            profile_ref.phase("first frame clock tick");
+            glib::ControlFlow::Break
+        });
 
         // Add this to the main function after creating text_view and line_numbers
+        line_numbers.set_visible(show_line_numbers_button.is_active());
         let line_numbers_ref = line_numbers.clone();
+        let state_ref = editor_state.clone();
         show_line_numbers_button.connect_toggled(move |button| {
             if button.is_active() {
                 line_numbers_ref.set_visible(true);
             } else {
                 line_numbers_ref.set_visible(false);
             }
+            if let Ok(mut state) = state_ref.try_borrow_mut() {
+                state.settings.show_line_numbers = button.is_active();
+                if let Err(e) = state.settings.save() {
+                    warn!("Failed to save settings: {}", e);
+                }
+            }
         });
     });
 
-    app.run();
+    // Files to open, from this launch or handed over by another one: open them in the
+    // window there is, building it first if this launch brought it up
+    app.connect_open(|app, files, _hint| {
+        if app.active_window().is_none() {
+            app.activate();
+        }
+        open_handed_over_files(files.iter().filter_map(|file| file.path()).collect());
+    });
+
+    app.run_with_args(&args);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassigning_a_shortcut_shows_the_new_keymap() {
+        // Saving the keymap writes to the config directory, so point it somewhere harmless
+        env::set_var("XDG_CONFIG_HOME", env::temp_dir().join("rustedit-test-config"));
+        let binding = KeyBinding::new(Key::s, gtk::gdk::ModifierType::CONTROL_MASK);
+        let other = KEYMAP.with(|keymap| keymap.borrow().action_for(&binding)).expect("Ctrl+S is bound by default");
+        let action = Action::ALL.into_iter().find(|&action| action != other).unwrap();
+
+        change_keymap(|keymap| {
+            keymap.unbind(&binding);
+            keymap.set(action, vec![binding]);
+        }, &[]);
+        KEYMAP.with(|keymap| assert_eq!(keymap.borrow().action_for(&binding), Some(action)));
+    }
+}
@@ -0,0 +1,291 @@
+use std::ops::Range;
+
+/// What Enter does on a Markdown line that is a list item or part of a quote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListEnter {
+    /// Start the new line with `prefix`, such as `- `, `3. ` or `> `
+    Continue { prefix: String },
+    /// The item is empty: take its prefix, the first `prefix_len` bytes, off the line
+    /// instead of starting another
+    End { prefix_len: usize },
+}
+
+// The start of a list item or quote line
+struct Prefix {
+    // Bytes of the indentation and quote markers before a list marker
+    lead_len: usize,
+    // Bytes of the whole prefix, up to the item's text
+    len: usize,
+    // Number and delimiter of an ordered item
+    ordered: Option<(u64, char)>,
+    // Prefix of the item after this one
+    next: String,
+}
+
+// End of the indentation and quote markers starting `line`, and whether there were any quote markers
+fn lead_end(line: &str) -> (usize, bool) {
+    let mut end = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let mut quoted = false;
+    while line[end..].starts_with('>') {
+        quoted = true;
+        end += 1;
+        end = line.len() - line[end..].trim_start_matches([' ', '\t']).len();
+    }
+    (end, quoted)
+}
+
+fn parse_prefix(line: &str) -> Option<Prefix> {
+    let (lead_len, quoted) = lead_end(line);
+    let rest = &line[lead_len..];
+
+    // `-`, `*` or `+`, or up to nine digits and `.` or `)`, then a space
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (marker_len, ordered) = match rest.chars().next() {
+        Some('-' | '*' | '+') => (1, None),
+        Some(_) if (1..=9).contains(&digits) => match rest[digits..].chars().next() {
+            Some(delimiter @ ('.' | ')')) => (digits + 1, Some((rest[..digits].parse().ok()?, delimiter))),
+            _ => (0, None),
+        },
+        _ => (0, None),
+    };
+    let after_marker = &rest[marker_len..];
+    let spacing_len = after_marker.len() - after_marker.trim_start_matches([' ', '\t']).len();
+    if marker_len == 0 || spacing_len == 0 {
+        return quoted.then(|| Prefix { lead_len, len: lead_len, ordered: None, next: line[..lead_len].to_string() });
+    }
+
+    // A task's box starts out unchecked on the next item
+    let after_spacing = &after_marker[spacing_len..];
+    let task_len = ["[ ] ", "[x] ", "[X] "].iter().find(|task| after_spacing.starts_with(*task)).map_or(0, |task| task.len());
+    let marker = match ordered {
+        Some((number, delimiter)) => format!("{}{}", number + 1, delimiter),
+        None => rest[..1].to_string(),
+    };
+    let next = format!(
+        "{}{}{}{}",
+        &line[..lead_len],
+        marker,
+        &after_marker[..spacing_len],
+        if task_len > 0 { "[ ] " } else { "" }
+    );
+    Some(Prefix { lead_len, len: lead_len + marker_len + spacing_len + task_len, ordered, next })
+}
+
+/// What Enter does on `line` with the cursor `cursor` bytes into it, or `None` when the line
+/// is not a list item or quote, or the cursor is still in its prefix
+pub fn list_enter(line: &str, cursor: usize) -> Option<ListEnter> {
+    let prefix = parse_prefix(line)?;
+    if cursor < prefix.len {
+        return None;
+    }
+    if line[prefix.len..].trim().is_empty() {
+        Some(ListEnter::End { prefix_len: prefix.len })
+    } else {
+        Some(ListEnter::Continue { prefix: prefix.next })
+    }
+}
+
+/// Numbers to change in `following`, the lines after a new ordered item with `prefix`, so
+/// the rest of its list counts on from it. Items nested deeper are passed over; a blank
+/// line or anything else ends the list. Gives byte ranges in `following` and their new numbers.
+pub fn renumber(prefix: &str, following: &str) -> Vec<(Range<usize>, String)> {
+    let Some(Prefix { lead_len, ordered: Some((mut number, delimiter)), .. }) = parse_prefix(prefix) else {
+        return Vec::new();
+    };
+    let lead = &prefix[..lead_len];
+
+    let mut changes = Vec::new();
+    let mut line_start = 0;
+    for line in following.split_inclusive('\n') {
+        let start = line_start;
+        line_start += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.trim().is_empty() {
+            break;
+        }
+        match parse_prefix(line) {
+            Some(Prefix { lead_len, ordered: Some((found, found_delimiter)), .. })
+                if &line[..lead_len] == lead && found_delimiter == delimiter =>
+            {
+                number += 1;
+                if found != number {
+                    let digits = line[lead_len..].find(delimiter).unwrap_or_default();
+                    changes.push((start + lead_len..start + lead_len + digits, number.to_string()));
+                }
+            }
+            _ => {
+                let (line_lead_len, _) = lead_end(line);
+                if !(line_lead_len > lead.len() && line.starts_with(lead)) {
+                    break;
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// How a table column lines up its cells, from the colons of its delimiter row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+/// A Markdown table: a header row, a delimiter row of dashes, then the body rows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    indent: String,
+    // Trimmed cells of the header and body rows; the delimiter row is not among them
+    rows: Vec<Vec<String>>,
+    aligns: Vec<Align>,
+}
+
+// Cells of a table line, split at pipes that are not escaped, without the outer pipes
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = if line.ends_with('|') && !line.ends_with("\\|") { &line[..line.len() - 1] } else { line };
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut escaped = false;
+    for c in line.chars() {
+        if c == '|' && !escaped {
+            cells.push(cell.trim().to_string());
+            cell.clear();
+        } else {
+            cell.push(c);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+// The alignments of a delimiter row such as `| :--- | ---: |`, or `None` if it is not one
+fn parse_delimiter_row(line: &str) -> Option<Vec<Align>> {
+    split_row(line)
+        .iter()
+        .map(|cell| {
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => Align::Center,
+                (true, false) => Align::Left,
+                (false, true) => Align::Right,
+                (false, false) => Align::None,
+            })
+        })
+        .collect()
+}
+
+/// The lines of the table holding line `line` of `lines`: the run of lines with a pipe in
+/// them around it, whose second line is a delimiter row
+pub fn table_lines(lines: &[&str], line: usize) -> Option<Range<usize>> {
+    let in_table = |index: usize| lines.get(index).is_some_and(|line| line.contains('|') && !line.trim().is_empty());
+    if !in_table(line) {
+        return None;
+    }
+    let mut start = line;
+    while start > 0 && in_table(start - 1) {
+        start -= 1;
+    }
+    let mut end = line + 1;
+    while in_table(end) {
+        end += 1;
+    }
+    (end - start >= 2 && parse_delimiter_row(lines[start + 1]).is_some()).then_some(start..end)
+}
+
+/// Index of the cell holding byte `column` of a table line
+pub fn cell_at(line: &str, column: usize) -> usize {
+    let before = &line[..column.min(line.len())];
+    let pipes = before
+        .char_indices()
+        .filter(|&(i, c)| c == '|' && !before[..i].ends_with('\\'))
+        .count();
+    let leading_pipe = line.trim_start().starts_with('|');
+    if leading_pipe { pipes.saturating_sub(1) } else { pipes }
+}
+
+/// Byte offset where the text of cell `cell` starts in a line formatted by `Table::format`
+pub fn cell_start(line: &str, cell: usize) -> usize {
+    let pipe = line
+        .char_indices()
+        .filter(|&(i, c)| c == '|' && !line[..i].ends_with('\\'))
+        .nth(cell)
+        .map_or(line.len(), |(i, _)| i);
+    (pipe + 2).min(line.len())
+}
+
+impl Table {
+    /// Read the table in `lines`, which start with its header and delimiter rows
+    pub fn parse(lines: &[&str]) -> Option<Self> {
+        let aligns = parse_delimiter_row(lines.get(1)?)?;
+        let indent = lines[0].len() - lines[0].trim_start().len();
+        let rows = lines.iter().enumerate().filter(|&(index, _)| index != 1).map(|(_, line)| split_row(line)).collect();
+        Some(Self { indent: lines[0][..indent].to_string(), rows, aligns })
+    }
+
+    pub fn columns(&self) -> usize {
+        self.rows.iter().map(Vec::len).chain([self.aligns.len()]).max().unwrap_or(0)
+    }
+
+    /// Lines of the table, the delimiter row among them
+    pub fn line_count(&self) -> usize {
+        self.rows.len() + 1
+    }
+
+    /// Add an empty row at the end
+    pub fn push_row(&mut self) {
+        self.rows.push(vec![String::new(); self.columns()]);
+    }
+
+    /// The table's lines with the pipes lined up, each cell padded to the width of its column
+    pub fn format(&self) -> Vec<String> {
+        let columns = self.columns();
+        let align = |column: usize| self.aligns.get(column).copied().unwrap_or(Align::None);
+        let widths: Vec<usize> = (0..columns)
+            .map(|column| {
+                let widest = self.rows.iter().filter_map(|row| row.get(column)).map(|cell| cell.chars().count()).max().unwrap_or(0);
+                widest.max(3)
+            })
+            .collect();
+
+        let format_row = |row: &[String]| {
+            let cells: Vec<String> = (0..columns)
+                .map(|column| {
+                    let cell = row.get(column).map_or("", String::as_str);
+                    let padding = widths[column] - cell.chars().count();
+                    let (left, right) = match align(column) {
+                        Align::Right => (padding, 0),
+                        Align::Center => (padding / 2, padding - padding / 2),
+                        Align::None | Align::Left => (0, padding),
+                    };
+                    format!("{}{}{}", " ".repeat(left), cell, " ".repeat(right))
+                })
+                .collect();
+            format!("{}| {} |", self.indent, cells.join(" | "))
+        };
+
+        let delimiter: Vec<String> = (0..columns)
+            .map(|column| {
+                let width = widths[column];
+                match align(column) {
+                    Align::None => "-".repeat(width),
+                    Align::Left => format!(":{}", "-".repeat(width - 1)),
+                    Align::Right => format!("{}:", "-".repeat(width - 1)),
+                    Align::Center => format!(":{}:", "-".repeat(width - 2)),
+                }
+            })
+            .collect();
+
+        let mut lines = vec![format_row(&self.rows[0])];
+        lines.push(format!("{}| {} |", self.indent, delimiter.join(" | ")));
+        lines.extend(self.rows[1..].iter().map(|row| format_row(row)));
+        lines
+    }
+}
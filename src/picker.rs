@@ -0,0 +1,195 @@
+use crate::fuzzy::fuzzy_score;
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Rows shown at most; typing more narrows the rest down
+const MAX_ROWS: usize = 500;
+
+/// One entry in a fuzzy picker: `label` is what gets matched, `detail` is shown dimmed beside it.
+/// `rank` is added to the match score, so entries the caller favors come first among similar matches.
+pub struct PickerItem {
+    pub label: String,
+    pub detail: String,
+    pub rank: i64,
+}
+
+/// A picker that is shown, which can take more items while the user types
+#[derive(Clone)]
+pub struct FuzzyPicker {
+    popover: gtk::Popover,
+    items: Rc<RefCell<Vec<PickerItem>>>,
+    refresh: Rc<dyn Fn()>,
+}
+
+impl FuzzyPicker {
+    /// Add `items` after the ones the picker has, filtering them by what is typed
+    pub fn extend(&self, items: Vec<PickerItem>) {
+        if items.is_empty() {
+            return;
+        }
+        self.items.borrow_mut().extend(items);
+        (self.refresh)();
+    }
+
+    /// Whether the picker is still shown; it cannot be shown again once closed
+    pub fn is_open(&self) -> bool {
+        self.popover.parent().is_some()
+    }
+}
+
+/// Show a popover with a search entry over a fuzzy-filtered list of `items`.
+/// `on_pick` receives the index (into `items`, then any added later) of the entry the user chose.
+pub fn show_fuzzy_picker<W, F>(parent: &W, placeholder: &str, items: Vec<PickerItem>, on_pick: F) -> FuzzyPicker
+where
+    W: IsA<gtk::Widget>,
+    F: Fn(usize) + 'static,
+{
+    let popover = gtk::Popover::new();
+    popover.set_parent(parent);
+    popover.set_position(gtk::PositionType::Bottom);
+    popover.set_pointing_to(Some(&gtk::gdk::Rectangle::new(parent.width() / 2, 0, 1, 1)));
+    popover.connect_closed(|popover| popover.unparent());
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 4);
+    content.set_margin_top(4);
+    content.set_margin_bottom(4);
+    content.set_margin_start(4);
+    content.set_margin_end(4);
+
+    let entry = gtk::SearchEntry::new();
+    entry.set_placeholder_text(Some(placeholder));
+    content.append(&entry);
+
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::Single);
+
+    let scrolled = gtk::ScrolledWindow::new();
+    scrolled.set_min_content_width(420);
+    scrolled.set_min_content_height(320);
+    scrolled.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled.set_child(Some(&list));
+    content.append(&scrolled);
+
+    popover.set_child(Some(&content));
+
+    // Indices into `items` for the rows currently shown, in display order
+    let visible: Rc<RefCell<Vec<usize>>> = Rc::new(RefCell::new(Vec::new()));
+    let items = Rc::new(RefCell::new(items));
+
+    // `keep_selection` keeps a row the user moved to selected when items are added
+    let populate = {
+        let list = list.clone();
+        let visible = visible.clone();
+        let items = items.clone();
+        Rc::new(move |filter: &str, keep_selection: bool| {
+            let selected = list.selected_row()
+                .filter(|row| keep_selection && row.index() > 0)
+                .and_then(|row| visible.borrow().get(row.index() as usize).copied());
+            while let Some(child) = list.first_child() {
+                list.remove(&child);
+            }
+
+            let items = items.borrow();
+            let mut matches: Vec<(i64, usize)> = items
+                .iter()
+                .enumerate()
+                .filter_map(|(index, item)| fuzzy_score(filter, &item.label).map(|score| (score + item.rank, index)))
+                .collect();
+            // Best score first, original order among ties
+            matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+            matches.truncate(MAX_ROWS);
+
+            let mut shown = visible.borrow_mut();
+            shown.clear();
+            for (_, index) in matches {
+                let item = &items[index];
+                let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                let label = gtk::Label::new(Some(&item.label));
+                label.set_halign(gtk::Align::Start);
+                label.set_hexpand(true);
+                label.set_ellipsize(pango::EllipsizeMode::End);
+                let detail = gtk::Label::new(Some(&item.detail));
+                detail.set_css_classes(&["dim-label", "shortcut-label"]);
+                row_box.append(&label);
+                row_box.append(&detail);
+                list.append(&row_box);
+                shown.push(index);
+            }
+
+            let row = selected.and_then(|selected| shown.iter().position(|&index| index == selected)).unwrap_or(0);
+            if let Some(row) = list.row_at_index(row as i32) {
+                list.select_row(Some(&row));
+            }
+        })
+    };
+    populate("", false);
+
+    let populate_ref = populate.clone();
+    entry.connect_search_changed(move |entry| {
+        populate_ref(&entry.text(), false);
+    });
+    let entry_ref = entry.clone();
+    let refresh: Rc<dyn Fn()> = Rc::new(move || populate(&entry_ref.text(), true));
+
+    let on_pick = Rc::new(on_pick);
+    let pick = {
+        let popover = popover.clone();
+        let visible = visible.clone();
+        move |row: &gtk::ListBoxRow| {
+            let index = visible.borrow().get(row.index() as usize).copied();
+            popover.popdown();
+            if let Some(index) = index {
+                on_pick(index);
+            }
+        }
+    };
+    let pick = Rc::new(pick);
+
+    let pick_row = pick.clone();
+    list.connect_row_activated(move |_, row| pick_row(row));
+
+    let list_ref = list.clone();
+    entry.connect_activate(move |_| {
+        if let Some(row) = list_ref.selected_row() {
+            pick(&row);
+        }
+    });
+
+    // Arrow keys move the selection while focus stays in the entry
+    let key_controller = gtk::EventControllerKey::new();
+    let list_ref = list.clone();
+    let popover_ref = popover.clone();
+    key_controller.connect_key_pressed(move |_, key, _, _| {
+        let step = match key {
+            gtk::gdk::Key::Down => 1,
+            gtk::gdk::Key::Up => -1,
+            gtk::gdk::Key::Escape => {
+                popover_ref.popdown();
+                return glib::Propagation::Stop;
+            }
+            _ => return glib::Propagation::Proceed,
+        };
+        let current = list_ref.selected_row().map(|row| row.index()).unwrap_or(-1);
+        if let Some(row) = list_ref.row_at_index((current + step).max(0)) {
+            list_ref.select_row(Some(&row));
+            if let Some(adj) = list_ref.adjustment() {
+                let bounds = row.compute_bounds(&list_ref);
+                if let Some(bounds) = bounds {
+                    let (top, bottom) = (bounds.y() as f64, (bounds.y() + bounds.height()) as f64);
+                    if top < adj.value() {
+                        adj.set_value(top);
+                    } else if bottom > adj.value() + adj.page_size() {
+                        adj.set_value(bottom - adj.page_size());
+                    }
+                }
+            }
+        }
+        glib::Propagation::Stop
+    });
+    entry.add_controller(key_controller);
+
+    popover.popup();
+    entry.grab_focus();
+    FuzzyPicker { popover, items, refresh }
+}
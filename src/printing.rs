@@ -0,0 +1,234 @@
+use crate::settings::Settings;
+use crate::theme::Theme;
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Size of the printed text, in points
+const PRINT_FONT_SIZE: f64 = 9.0;
+// Space between the line numbers and the text, and around the header and footer, in points
+const GAP: f64 = 8.0;
+
+thread_local! {
+    // Printer and paper the user chose last, offered again by the next print
+    static PRINT_SETTINGS: RefCell<Option<gtk::PrintSettings>> = const { RefCell::new(None) };
+}
+
+// One line of the text with its syntax colors
+struct PrintLine {
+    text: String,
+    attributes: pango::AttrList,
+}
+
+// Text as it goes onto the pages, and the lines each page holds once it is laid out
+struct PrintJob {
+    title: String,
+    font: pango::FontDescription,
+    tab_width: u32,
+    lines: Vec<PrintLine>,
+    pages: Vec<Range<usize>>,
+}
+
+// Colors of the syntax tags on paper: the theme's own when it is light, otherwise the
+// built-in light ones, since dark themes' colors are meant for a dark background
+fn tag_colors(theme: &Theme) -> Vec<(&'static str, pango::AttrColor)> {
+    let theme = if theme.dark { Theme::light() } else { theme.clone() };
+    [
+        ("keyword", &theme.keyword),
+        ("function", &theme.function),
+        ("type", &theme.type_name),
+        ("string", &theme.string),
+        ("number", &theme.number),
+        ("comment", &theme.comment),
+        ("error", &theme.error),
+        ("semantic-parameter", &theme.parameter),
+        ("semantic-variable", &theme.variable),
+        ("semantic-field", &theme.field),
+    ]
+    .into_iter()
+    .filter_map(|(name, color)| {
+        let color = gtk::gdk::RGBA::parse(color.as_str()).ok()?;
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 65535.0) as u16;
+        Some((name, pango::AttrColor::new_foreground(channel(color.red()), channel(color.green()), channel(color.blue()))))
+    })
+    .collect()
+}
+
+// The lines of `buffer` with the colors of the syntax tags on them
+fn colored_lines(buffer: &gtk::TextBuffer, theme: &Theme) -> Vec<PrintLine> {
+    let colors = tag_colors(theme);
+    (0..buffer.line_count())
+        .filter_map(|line| {
+            let start = buffer.iter_at_line(line)?;
+            let mut end = start;
+            if !end.ends_line() {
+                end.forward_to_line_end();
+            }
+            let attributes = pango::AttrList::new();
+            let mut text = String::new();
+            let mut run_start = start;
+            while run_start < end {
+                let mut run_end = run_start;
+                run_end.forward_to_tag_toggle(None::<&gtk::TextTag>);
+                if run_end > end {
+                    run_end = end;
+                }
+                let run = buffer.text(&run_start, &run_end, false);
+                // Tags come lowest priority first, so the last colored one wins
+                let color = run_start.tags().iter().rev().find_map(|tag| {
+                    let name = tag.name()?;
+                    colors.iter().find(|(tag_name, _)| *tag_name == name.as_str()).map(|(_, color)| color.clone())
+                });
+                if let Some(mut color) = color {
+                    color.set_start_index(text.len() as u32);
+                    color.set_end_index((text.len() + run.len()) as u32);
+                    attributes.insert(color);
+                }
+                text.push_str(&run);
+                run_start = run_end;
+            }
+            Some(PrintLine { text, attributes })
+        })
+        .collect()
+}
+
+impl PrintJob {
+    // Layout of line `line` in the width left beside the line numbers
+    fn layout(&self, context: &gtk::PrintContext, line: &PrintLine, width: f64) -> pango::Layout {
+        let layout = context.create_pango_layout();
+        layout.set_font_description(Some(&self.font));
+        layout.set_width((width * pango::SCALE as f64) as i32);
+        layout.set_wrap(pango::WrapMode::WordChar);
+        let mut tabs = pango::TabArray::new(1, false);
+        let space = context.create_pango_layout();
+        space.set_font_description(Some(&self.font));
+        space.set_text(&" ".repeat(self.tab_width.max(1) as usize));
+        tabs.set_tab(0, pango::TabAlign::Left, space.size().0);
+        layout.set_tabs(Some(&tabs));
+        layout.set_text(&line.text);
+        layout.set_attributes(Some(&line.attributes));
+        layout
+    }
+
+    // Width of the line number column
+    fn gutter_width(&self, context: &gtk::PrintContext) -> f64 {
+        let layout = context.create_pango_layout();
+        layout.set_font_description(Some(&self.font));
+        layout.set_text(&self.lines.len().max(1).to_string());
+        layout.size().0 as f64 / pango::SCALE as f64 + GAP
+    }
+
+    // Height of one line of header or footer text
+    fn line_height(&self, context: &gtk::PrintContext) -> f64 {
+        let layout = context.create_pango_layout();
+        layout.set_font_description(Some(&self.font));
+        layout.set_text("0");
+        layout.size().1 as f64 / pango::SCALE as f64
+    }
+
+    // Split the lines over pages, a line that wraps staying on one page
+    fn paginate(&mut self, context: &gtk::PrintContext) {
+        let text_width = context.width() - self.gutter_width(context);
+        let room = context.height() - 2.0 * (self.line_height(context) + GAP);
+        let mut pages = Vec::new();
+        let (mut first, mut used) = (0, 0.0);
+        for (index, line) in self.lines.iter().enumerate() {
+            let height = self.layout(context, line, text_width).size().1 as f64 / pango::SCALE as f64;
+            if used + height > room && index > first {
+                pages.push(first..index);
+                first = index;
+                used = 0.0;
+            }
+            used += height;
+        }
+        pages.push(first..self.lines.len());
+        self.pages = pages;
+    }
+
+    fn draw_page(&self, context: &gtk::PrintContext, page: usize) {
+        let Some(lines) = self.pages.get(page) else {
+            return;
+        };
+        let cr = context.cairo_context();
+        let (width, height) = (context.width(), context.height());
+        let line_height = self.line_height(context);
+        let gutter = self.gutter_width(context);
+
+        // File name above, page number below, each set off by a rule
+        let text_layout = |text: &str| {
+            let layout = context.create_pango_layout();
+            layout.set_font_description(Some(&self.font));
+            layout.set_text(text);
+            layout
+        };
+        cr.set_source_rgb(0.0, 0.0, 0.0);
+        let header = text_layout(&self.title);
+        header.set_width((width * pango::SCALE as f64) as i32);
+        header.set_ellipsize(pango::EllipsizeMode::Start);
+        cr.move_to(0.0, 0.0);
+        pangocairo::functions::show_layout(&cr, &header);
+        let footer = text_layout(&format!("Page {} of {}", page + 1, self.pages.len()));
+        footer.set_width((width * pango::SCALE as f64) as i32);
+        footer.set_alignment(pango::Alignment::Center);
+        cr.move_to(0.0, height - line_height);
+        pangocairo::functions::show_layout(&cr, &footer);
+        cr.set_line_width(0.5);
+        for y in [line_height + GAP / 2.0, height - line_height - GAP / 2.0] {
+            cr.move_to(0.0, y);
+            cr.line_to(width, y);
+        }
+        let _ = cr.stroke();
+
+        let mut y = line_height + GAP;
+        for index in lines.clone() {
+            let number = text_layout(&(index + 1).to_string());
+            number.set_width(((gutter - GAP) * pango::SCALE as f64) as i32);
+            number.set_alignment(pango::Alignment::Right);
+            cr.set_source_rgb(0.5, 0.5, 0.5);
+            cr.move_to(0.0, y);
+            pangocairo::functions::show_layout(&cr, &number);
+
+            let layout = self.layout(context, &self.lines[index], width - gutter);
+            cr.set_source_rgb(0.0, 0.0, 0.0);
+            cr.move_to(gutter, y);
+            pangocairo::functions::show_layout(&cr, &layout);
+            y += layout.size().1 as f64 / pango::SCALE as f64;
+        }
+    }
+}
+
+/// Print `buffer` in the editor's font with its syntax colors and line numbers, under a
+/// header with `title` and over a footer with the page number. With `preview` the pages
+/// are shown instead. Blocks until the print dialog or preview is closed.
+pub fn print_buffer(window: &impl IsA<gtk::Window>, buffer: &gtk::TextBuffer, title: &str, settings: &Settings, theme: &Theme, preview: bool) -> Result<(), glib::Error> {
+    let mut font = pango::FontDescription::new();
+    font.set_family(&settings.font_family);
+    font.set_size((PRINT_FONT_SIZE * pango::SCALE as f64) as i32);
+    let job = Rc::new(RefCell::new(PrintJob {
+        title: title.to_string(),
+        font,
+        tab_width: settings.tab_width,
+        lines: colored_lines(buffer, theme),
+        pages: Vec::new(),
+    }));
+
+    let operation = gtk::PrintOperation::new();
+    operation.set_job_name(title);
+    operation.set_unit(gtk::Unit::Points);
+    PRINT_SETTINGS.with(|settings| operation.set_print_settings(settings.borrow().as_ref()));
+
+    let job_ref = job.clone();
+    operation.connect_begin_print(move |operation, context| {
+        let mut job = job_ref.borrow_mut();
+        job.paginate(context);
+        operation.set_n_pages(job.pages.len() as i32);
+    });
+    operation.connect_draw_page(move |_, context, page| job.borrow().draw_page(context, page as usize));
+
+    let action = if preview { gtk::PrintOperationAction::Preview } else { gtk::PrintOperationAction::PrintDialog };
+    if operation.run(action, Some(window))? == gtk::PrintOperationResult::Apply {
+        PRINT_SETTINGS.with(|settings| *settings.borrow_mut() = operation.print_settings());
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Words of a text and how often each is used, for proofreading prose
+#[derive(Debug, Clone, Default)]
+pub struct ProseReport {
+    pub words: usize,
+    /// Words in lowercase with their counts, most used first
+    pub frequencies: Vec<(String, usize)>,
+    /// Byte ranges of words repeated right after themselves, such as "the the", each
+    /// spanning the whole run of repeats
+    pub repeats: Vec<Range<usize>>,
+}
+
+// An apostrophe between two letters belongs to the word, as in "don't"
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '\u{2019}'
+}
+
+/// Byte ranges of the words of `text`: runs of letters and digits
+fn words(text: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next_is_word = chars.peek().is_some_and(|&(_, next)| next.is_alphanumeric());
+        let in_word = c.is_alphanumeric() || (start.is_some() && is_apostrophe(c) && next_is_word);
+        match (in_word, start) {
+            (true, None) => start = Some(i),
+            (false, Some(word_start)) => {
+                words.push(word_start..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(word_start) = start {
+        words.push(word_start..text.len());
+    }
+    words
+}
+
+/// Analyze `text`. Repeats are words with a letter in them, compared ignoring case, with
+/// nothing but spaces and at most one line break between them, so within a paragraph.
+pub fn analyze(text: &str) -> ProseReport {
+    let words = words(text);
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for word in &words {
+        *counts.entry(text[word.clone()].to_lowercase()).or_default() += 1;
+    }
+    let mut frequencies: Vec<(String, usize)> = counts.into_iter().collect();
+    frequencies.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let mut repeats: Vec<Range<usize>> = Vec::new();
+    for pair in words.windows(2) {
+        let (first, second) = (&text[pair[0].clone()], &text[pair[1].clone()]);
+        let gap = &text[pair[0].end..pair[1].start];
+        let repeated = first.chars().any(char::is_alphabetic)
+            && first.to_lowercase() == second.to_lowercase()
+            && gap.chars().all(char::is_whitespace)
+            && gap.matches('\n').count() <= 1;
+        if !repeated {
+            continue;
+        }
+        // A third repeat extends the run of the first two
+        match repeats.last_mut() {
+            Some(run) if run.end == pair[0].end => run.end = pair[1].end,
+            _ => repeats.push(pair[0].start..pair[1].end),
+        }
+    }
+
+    ProseReport { words: words.len(), frequencies, repeats }
+}
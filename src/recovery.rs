@@ -0,0 +1,100 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Unsaved content of one tab, written periodically so it survives a crash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryFile {
+    /// File the tab was showing, if it had one
+    pub path: Option<PathBuf>,
+    pub tab_name: String,
+    pub text: String,
+}
+
+fn recovery_dir() -> PathBuf {
+    glib::user_data_dir().join("rustedit").join("recovery")
+}
+
+// Files are named after the writing process so that a running editor's copies are not
+// mistaken for leftovers
+fn recovery_file(tab_id: usize) -> PathBuf {
+    recovery_dir().join(format!("{}-{}.json", process::id(), tab_id))
+}
+
+/// Write the recovery copy of the tab with `tab_id`, replacing the previous one
+pub fn write(tab_id: usize, file: &RecoveryFile) -> Result<()> {
+    let dir = recovery_dir();
+    fs::create_dir_all(&dir)?;
+
+    // Write next to the target and rename, so a crash mid-write keeps the last good copy
+    let path = recovery_file(tab_id);
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, serde_json::to_string(file)?)?;
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+/// Drop the recovery copy of the tab with `tab_id`, if there is one
+pub fn remove(tab_id: usize) {
+    discard(&recovery_file(tab_id));
+}
+
+/// Drop every recovery copy written by this process, for a clean exit
+pub fn remove_all() {
+    let prefix = format!("{}-", process::id());
+    let Ok(entries) = fs::read_dir(recovery_dir()) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            discard(&entry.path());
+        }
+    }
+}
+
+/// Recovery copies left behind by editor processes that are no longer running
+pub fn leftovers() -> Vec<(PathBuf, RecoveryFile)> {
+    let Ok(entries) = fs::read_dir(recovery_dir()) else {
+        return Vec::new();
+    };
+
+    let mut found: Vec<(PathBuf, RecoveryFile)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .filter(|path| writer_pid(path).is_some_and(|pid| !is_running(pid)))
+        .filter_map(|path| {
+            let content = fs::read_to_string(&path).ok()?;
+            match serde_json::from_str(&content) {
+                Ok(file) => Some((path, file)),
+                Err(e) => {
+                    warn!("Ignoring unreadable recovery file {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found
+}
+
+/// Delete a recovery copy once it has been restored or declined
+pub fn discard(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove recovery file {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn writer_pid(path: &Path) -> Option<u32> {
+    path.file_stem()?.to_str()?.split('-').next()?.parse().ok()
+}
+
+// Without /proc every other process counts as gone
+fn is_running(pid: u32) -> bool {
+    pid == process::id() || Path::new("/proc").join(pid.to_string()).exists()
+}
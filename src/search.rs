@@ -0,0 +1,194 @@
+use regex::{Captures, Regex, RegexBuilder};
+use std::ops::Range;
+
+/// What the Find and Replace dialogs look for
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub pattern: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+}
+
+/// A compiled `SearchQuery`. Ranges are byte offsets into the searched text.
+#[derive(Debug)]
+pub struct Searcher {
+    regex: Regex,
+    expand_replacement: bool,
+}
+
+impl SearchQuery {
+    pub fn compile(&self) -> Result<Searcher, regex::Error> {
+        let pattern = if self.regex {
+            self.pattern.clone()
+        } else {
+            regex::escape(&self.pattern)
+        };
+        let pattern = if self.whole_word {
+            format!(r"\b(?:{})\b", pattern)
+        } else {
+            pattern
+        };
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .build()?;
+        Ok(Searcher {
+            regex,
+            expand_replacement: self.regex,
+        })
+    }
+}
+
+impl Searcher {
+    /// First non-empty match starting at or after `from`
+    pub fn find_from(&self, text: &str, from: usize) -> Option<Range<usize>> {
+        let mut start = from;
+        loop {
+            let found = self.regex.find_at(text, start)?;
+            if !found.is_empty() {
+                return Some(found.range());
+            }
+            // Step over the empty match by one character
+            start = found.end() + text[found.end()..].chars().next()?.len_utf8();
+        }
+    }
+
+    /// Every non-empty match, in document order
+    pub fn find_all(&self, text: &str) -> Vec<Range<usize>> {
+        self.regex
+            .find_iter(text)
+            .filter(|found| !found.is_empty())
+            .map(|found| found.range())
+            .collect()
+    }
+
+    /// The next match at or after `from` together with the text that replaces it.
+    /// In regex mode `$1`, `${name}` and `$$` in `replacement` are expanded.
+    pub fn replace_next(&self, text: &str, from: usize, replacement: &str) -> Option<(Range<usize>, String)> {
+        let range = self.find_from(text, from)?;
+        let captures = self.regex.captures_at(text, range.start)?;
+        Some((range, self.expand(&captures, replacement)))
+    }
+
+    /// Every non-empty match with its replacement, in document order
+    pub fn replace_all(&self, text: &str, replacement: &str) -> Vec<(Range<usize>, String)> {
+        self.regex
+            .captures_iter(text)
+            .filter_map(|captures| {
+                let found = captures.get(0)?;
+                if found.is_empty() {
+                    return None;
+                }
+                Some((found.range(), self.expand(&captures, replacement)))
+            })
+            .collect()
+    }
+
+    fn expand(&self, captures: &Captures, replacement: &str) -> String {
+        if self.expand_replacement {
+            let mut expanded = String::new();
+            captures.expand(replacement, &mut expanded);
+            expanded
+        } else {
+            // Literal mode keeps `$` as typed
+            replacement.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pattern: &str) -> SearchQuery {
+        SearchQuery {
+            pattern: pattern.to_string(),
+            ..SearchQuery::default()
+        }
+    }
+
+    #[test]
+    fn literal_search_escapes_regex_syntax() {
+        let searcher = query("a.b").compile().unwrap();
+        assert_eq!(searcher.find_from("axb a.b", 0), Some(4..7));
+    }
+
+    #[test]
+    fn search_is_case_insensitive_unless_asked() {
+        let text = "Foo foo";
+        assert_eq!(query("foo").compile().unwrap().find_from(text, 0), Some(0..3));
+
+        let case_sensitive = SearchQuery { case_sensitive: true, ..query("foo") };
+        assert_eq!(case_sensitive.compile().unwrap().find_from(text, 0), Some(4..7));
+    }
+
+    #[test]
+    fn whole_word_skips_partial_matches() {
+        let whole_word = SearchQuery { whole_word: true, ..query("cat") };
+        let searcher = whole_word.compile().unwrap();
+        assert_eq!(searcher.find_from("concat cat", 0), Some(7..10));
+    }
+
+    #[test]
+    fn find_from_starts_at_offset() {
+        let searcher = query("ab").compile().unwrap();
+        assert_eq!(searcher.find_from("ab ab", 1), Some(3..5));
+        assert_eq!(searcher.find_from("ab ab", 4), None);
+
+        // Overlapping occurrences are still found from inside an earlier one
+        let searcher = query("aa").compile().unwrap();
+        assert_eq!(searcher.find_from("aaa", 1), Some(1..3));
+    }
+
+    #[test]
+    fn find_all_lists_every_match() {
+        let searcher = query("ab").compile().unwrap();
+        assert_eq!(searcher.find_all("ab xab\nab"), vec![0..2, 4..6, 7..9]);
+
+        let regex = SearchQuery { regex: true, ..query("x*") };
+        assert_eq!(regex.compile().unwrap().find_all("axxbx"), vec![1..3, 4..5]);
+    }
+
+    #[test]
+    fn regex_replacement_expands_capture_groups() {
+        let regex = SearchQuery { regex: true, ..query(r"(\w+)=(\d+)") };
+        let searcher = regex.compile().unwrap();
+        assert_eq!(
+            searcher.replace_next("x=1, y=2", 0, "$2=$1"),
+            Some((0..3, "1=x".to_string()))
+        );
+        assert_eq!(
+            searcher.replace_all("x=1, y=2", "${2}:$1"),
+            vec![(0..3, "1:x".to_string()), (5..8, "2:y".to_string())]
+        );
+    }
+
+    #[test]
+    fn literal_replacement_keeps_dollar_signs() {
+        let searcher = query("price").compile().unwrap();
+        assert_eq!(searcher.replace_all("price", "$1"), vec![(0..5, "$1".to_string())]);
+    }
+
+    #[test]
+    fn empty_matches_are_ignored() {
+        let regex = SearchQuery { regex: true, ..query("x*") };
+        let searcher = regex.compile().unwrap();
+        assert_eq!(searcher.find_from("abxxc", 0), Some(2..4));
+        assert_eq!(searcher.replace_all("abxxc", "y"), vec![(2..4, "y".to_string())]);
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let regex = SearchQuery { regex: true, ..query("(") };
+        assert!(regex.compile().is_err());
+    }
+
+    #[test]
+    fn anchors_match_per_line() {
+        let regex = SearchQuery { regex: true, ..query("^b") };
+        let searcher = regex.compile().unwrap();
+        assert_eq!(searcher.find_from("a\nb", 0), Some(2..3));
+    }
+}
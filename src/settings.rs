@@ -0,0 +1,205 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use crate::dictionary::DEFAULT_DICTIONARY;
+use crate::theme::Theme;
+
+/// Preferences chosen by the user, stored as TOML in the XDG config directory.
+/// Unlike `PersistentState`, this is meant to be edited by hand as well.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub font_family: String,
+    /// Font size in pixels at 100% zoom
+    pub font_size: u32,
+    /// Width of a tab character, in spaces
+    pub tab_width: u32,
+    /// Name of the color theme
+    pub theme: String,
+    /// Periodically keep recovery copies of modified tabs
+    pub autosave: bool,
+    pub autosave_interval_secs: u32,
+    pub word_wrap: bool,
+    /// Column word wrap breaks lines at, or 0 to wrap at the edge of the window
+    pub wrap_column: u32,
+    /// Draw a line down the text at the wrap column
+    pub show_right_margin: bool,
+    pub show_line_numbers: bool,
+    /// Curly quotes, dashes and sentence capitals while typing Markdown or plain text
+    pub smart_typography: bool,
+    /// Suggest completions from the language server, or the open tabs' words, while typing code
+    pub code_completion: bool,
+    /// Suggest words from a dictionary while typing Markdown or plain text
+    pub prose_completion: bool,
+    /// Name of the dictionary prose completion draws from
+    pub dictionary: String,
+    /// Indent with spaces rather than tab characters
+    pub insert_spaces: bool,
+    /// Columns one level of indentation takes
+    pub indent_width: u32,
+    /// Characters besides letters, digits and '_' that double-clicking and moving by
+    /// words treat as part of a word
+    pub word_chars: String,
+    /// Tint spaces and tabs at the ends of lines
+    pub show_trailing_whitespace: bool,
+    /// Remove spaces and tabs at the ends of lines when saving
+    pub trim_trailing_whitespace: bool,
+    /// End the file with a line break when saving
+    pub ensure_final_newline: bool,
+    /// Keep text copied in other applications in the paste history too, while the editor
+    /// is the focused window
+    pub clipboard_history_external: bool,
+    /// Indentation, whitespace, word characters and theme of particular languages, by language name, as in `[languages.Python]`
+    pub languages: BTreeMap<String, LanguageSettings>,
+}
+
+/// Settings of one language, overriding the general ones where given
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanguageSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insert_spaces: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indent_width: Option<u32>,
+    /// Theme the window switches to while a tab in this language is shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trim_trailing_whitespace: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ensure_final_newline: Option<bool>,
+    /// Word characters of the language, such as `"$"` for Shell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub word_chars: Option<String>,
+}
+
+/// How the Tab key indents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Indentation {
+    pub insert_spaces: bool,
+    pub width: u32,
+}
+
+impl Indentation {
+    /// Text of one level of indentation
+    pub fn unit(&self) -> String {
+        if self.insert_spaces {
+            " ".repeat(self.width.max(1) as usize)
+        } else {
+            "\t".to_string()
+        }
+    }
+
+    /// Form shown in the status bar
+    pub fn label(&self) -> String {
+        if self.insert_spaces {
+            format!("Spaces: {}", self.width)
+        } else {
+            format!("Tabs: {}", self.width)
+        }
+    }
+}
+
+/// How the text is tidied when saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveCleanup {
+    pub trim_trailing_whitespace: bool,
+    pub ensure_final_newline: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            font_family: "Monospace".to_string(),
+            font_size: 13,
+            tab_width: 4,
+            theme: Theme::default_name().to_string(),
+            autosave: true,
+            autosave_interval_secs: 30,
+            word_wrap: false,
+            wrap_column: 0,
+            show_right_margin: false,
+            show_line_numbers: true,
+            smart_typography: false,
+            code_completion: true,
+            prose_completion: false,
+            dictionary: DEFAULT_DICTIONARY.to_string(),
+            insert_spaces: false,
+            indent_width: 4,
+            word_chars: String::new(),
+            show_trailing_whitespace: false,
+            trim_trailing_whitespace: false,
+            ensure_final_newline: false,
+            clipboard_history_external: false,
+            languages: [("Python", 4), ("Rust", 4), ("YAML", 2), ("JSON", 2)]
+                .into_iter()
+                .map(|(language, width)| {
+                    let settings = LanguageSettings { insert_spaces: Some(true), indent_width: Some(width), ..Default::default() };
+                    (language.to_string(), settings)
+                })
+                // Two trailing spaces end a line in Markdown
+                .chain([("Markdown".to_string(), LanguageSettings { trim_trailing_whitespace: Some(false), ..Default::default() })])
+                .collect(),
+        }
+    }
+}
+
+impl Settings {
+    /// Indentation for files in `language`, given by name
+    pub fn indentation(&self, language: &str) -> Indentation {
+        let overrides = self.languages.get(language);
+        Indentation {
+            insert_spaces: overrides.and_then(|settings| settings.insert_spaces).unwrap_or(self.insert_spaces),
+            width: overrides.and_then(|settings| settings.indent_width).unwrap_or(self.indent_width).max(1),
+        }
+    }
+
+    /// Name of the theme for files in `language`, given by name
+    pub fn theme_for(&self, language: &str) -> &str {
+        self.languages.get(language).and_then(|settings| settings.theme.as_deref()).unwrap_or(&self.theme)
+    }
+
+    /// Characters besides letters, digits and '_' that are part of words in `language`
+    pub fn word_chars(&self, language: &str) -> &str {
+        self.languages.get(language).and_then(|settings| settings.word_chars.as_deref()).unwrap_or(&self.word_chars)
+    }
+
+    /// How files in `language` are tidied when saved
+    pub fn save_cleanup(&self, language: &str) -> SaveCleanup {
+        let overrides = self.languages.get(language);
+        SaveCleanup {
+            trim_trailing_whitespace: overrides
+                .and_then(|settings| settings.trim_trailing_whitespace)
+                .unwrap_or(self.trim_trailing_whitespace),
+            ensure_final_newline: overrides.and_then(|settings| settings.ensure_final_newline).unwrap_or(self.ensure_final_newline),
+        }
+    }
+
+    fn settings_file() -> PathBuf {
+        glib::user_config_dir().join("rustedit").join("settings.toml")
+    }
+
+    pub fn load() -> Self {
+        let path = Self::settings_file();
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable settings {}: {}", path.display(), e);
+                Self::default()
+            }),
+            // Nothing changed from the defaults yet
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::settings_file();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
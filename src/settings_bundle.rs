@@ -0,0 +1,112 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Layout of bundles this version writes; bundles of a later layout are refused
+const BUNDLE_FORMAT: u32 = 1;
+
+// Files of the config directory that make up the configuration
+const CONFIG_FILES: &[&str] = &["settings.toml", "keybindings.toml", "abbreviations.toml", "theme.json"];
+// Directories of it holding one file per theme or dictionary, with their extensions
+const CONFIG_DIRS: &[(&str, &[&str])] = &[("themes", &["toml", "json"]), ("dictionaries", &["txt"])];
+
+/// The user's settings, key bindings, abbreviations, themes and dictionaries in one JSON
+/// file, to carry them to another machine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    pub format: u32,
+    /// Version of the editor that wrote the bundle
+    pub editor_version: String,
+    /// Content of each file, by its path in the config directory such as `themes/Solarized.toml`
+    pub files: BTreeMap<String, String>,
+}
+
+fn config_dir() -> PathBuf {
+    glib::user_config_dir().join("rustedit")
+}
+
+// Whether `name` is a path the bundle may hold, so importing only ever writes configuration
+fn is_config_file(name: &str) -> bool {
+    if CONFIG_FILES.contains(&name) {
+        return true;
+    }
+    let Some((dir, file)) = name.split_once('/') else {
+        return false;
+    };
+    let Some((_, extensions)) = CONFIG_DIRS.iter().find(|(config_dir, _)| *config_dir == dir) else {
+        return false;
+    };
+    let file = Path::new(file);
+    !name.contains('\\')
+        && file.components().count() == 1
+        && file.file_stem().is_some_and(|stem| !stem.to_string_lossy().starts_with('.'))
+        && file.extension().is_some_and(|extension| extensions.iter().any(|wanted| extension == *wanted))
+}
+
+impl SettingsBundle {
+    /// Gather the configuration files there are
+    pub fn collect() -> Self {
+        let dir = config_dir();
+        let mut names: Vec<String> = CONFIG_FILES.iter().map(|name| name.to_string()).collect();
+        for (subdir, _) in CONFIG_DIRS {
+            let Ok(entries) = fs::read_dir(dir.join(subdir)) else {
+                continue;
+            };
+            names.extend(
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| format!("{}/{}", subdir, entry.file_name().to_string_lossy()))
+                    .filter(|name| is_config_file(name)),
+            );
+        }
+
+        let files = names
+            .into_iter()
+            .filter_map(|name| Some((name.clone(), fs::read_to_string(dir.join(&name)).ok()?)))
+            .collect();
+        Self {
+            format: BUNDLE_FORMAT,
+            editor_version: env!("CARGO_PKG_VERSION").to_string(),
+            files,
+        }
+    }
+
+    /// Write the configuration to `path`, returning how many files went in
+    pub fn export(path: &Path) -> Result<usize> {
+        let bundle = Self::collect();
+        fs::write(path, serde_json::to_string_pretty(&bundle)?)?;
+        Ok(bundle.files.len())
+    }
+
+    /// Read the bundle at `path`, checking it before anything is written
+    pub fn read(path: &Path) -> Result<Self> {
+        let bundle: Self = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if bundle.format > BUNDLE_FORMAT {
+            bail!(
+                "The bundle was exported by a newer version of the editor ({}); update this one ({}) to import it",
+                bundle.editor_version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+        if let Some(name) = bundle.files.keys().find(|name| !is_config_file(name)) {
+            bail!("The bundle holds '{}', which is not a configuration file", name);
+        }
+        Ok(bundle)
+    }
+
+    /// Put the bundle's files in the config directory, replacing those of the same name.
+    /// Files the bundle does not have are kept.
+    pub fn install(&self) -> Result<()> {
+        let dir = config_dir();
+        for (name, content) in &self.files {
+            let path = dir.join(name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, content)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a snippet may run before it is stopped
+pub const RUN_TIMEOUT: Duration = Duration::from_secs(10);
+// Most output kept from each of the program's streams
+const MAX_OUTPUT: u64 = 1 << 20;
+
+// Source of the names of the directories snippets are built in
+static NEXT_SNIPPET: AtomicUsize = AtomicUsize::new(0);
+
+/// What came of compiling a snippet and running it
+#[derive(Debug, Clone)]
+pub struct SnippetOutput {
+    /// rustc's errors and warnings
+    pub compiler: String,
+    /// The run, unless the snippet did not compile
+    pub run: Option<SnippetRun>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SnippetRun {
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code; `None` when it was ended by a signal or stopped after `RUN_TIMEOUT`
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub duration: Duration,
+}
+
+/// The snippet as a program: as it is if it has a `main` function of its own, otherwise
+/// as the body of one
+pub fn wrap_in_main(snippet: &str) -> String {
+    let words: Vec<&str> = snippet.split(|c: char| !(c.is_alphanumeric() || c == '_')).filter(|word| !word.is_empty()).collect();
+    if words.windows(2).any(|pair| pair == ["fn", "main"]) {
+        return snippet.to_string();
+    }
+    let body: String = snippet
+        .lines()
+        .map(|line| if line.trim().is_empty() { "\n".to_string() } else { format!("    {}\n", line) })
+        .collect();
+    format!("fn main() {{\n{}}}\n", body)
+}
+
+/// Compile the snippet with rustc in a directory of its own under the temp directory and
+/// run it there, stopping it after `RUN_TIMEOUT`. The directory is removed afterwards.
+pub fn run(snippet: &str) -> Result<SnippetOutput> {
+    let dir = std::env::temp_dir().join(format!(
+        "rustedit-snippet-{}-{}",
+        process::id(),
+        NEXT_SNIPPET.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir)?;
+    let output = compile_and_run(&dir, &wrap_in_main(snippet));
+    let _ = fs::remove_dir_all(&dir);
+    output
+}
+
+fn compile_and_run(dir: &Path, program: &str) -> Result<SnippetOutput> {
+    let source = dir.join("snippet.rs");
+    let binary = dir.join("snippet");
+    fs::write(&source, program)?;
+
+    let compiled = Command::new("rustc")
+        .args(["--edition", "2021", "-o"])
+        .arg(&binary)
+        .arg(&source)
+        .current_dir(dir)
+        .output()
+        .context("Could not start rustc; is a Rust toolchain installed?")?;
+    let compiler = String::from_utf8_lossy(&compiled.stderr).into_owned();
+    if !compiled.status.success() {
+        return Ok(SnippetOutput { compiler, run: None });
+    }
+
+    let started = Instant::now();
+    let mut child = Command::new(&binary)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read on threads of their own, so a program filling one pipe does not stall
+    let read_all = |stream: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            if let Some(stream) = stream {
+                let _ = stream.take(MAX_OUTPUT).read_to_end(&mut bytes);
+            }
+            String::from_utf8_lossy(&bytes).into_owned()
+        })
+    };
+    let stdout = read_all(child.stdout.take().map(|stream| Box::new(stream) as Box<dyn Read + Send>));
+    let stderr = read_all(child.stderr.take().map(|stream| Box::new(stream) as Box<dyn Read + Send>));
+
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= RUN_TIMEOUT {
+            timed_out = true;
+            let _ = child.kill();
+            break child.wait()?;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let duration = started.elapsed();
+
+    Ok(SnippetOutput {
+        compiler,
+        run: Some(SnippetRun {
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+            exit_code: status.code(),
+            timed_out,
+            duration,
+        }),
+    })
+}
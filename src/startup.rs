@@ -0,0 +1,44 @@
+use log::info;
+use std::cell::Cell;
+use std::time::Instant;
+
+/// Command line flag that turns on startup timing
+pub const PROFILE_FLAG: &str = "--profile-startup";
+
+/// Log target of the timings, enabled at info level when profiling
+pub const LOG_TARGET: &str = "startup";
+
+/// Times the phases of startup. Each call to `phase` logs how long it took since the
+/// previous one and since launch; without `--profile-startup` nothing is logged.
+pub struct StartupProfile {
+    enabled: bool,
+    start: Instant,
+    last: Cell<Instant>,
+}
+
+impl StartupProfile {
+    pub fn new(enabled: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            enabled,
+            start: now,
+            last: Cell::new(now),
+        }
+    }
+
+    /// Mark the end of the phase called `name`
+    pub fn phase(&self, name: &str) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        info!(
+            target: LOG_TARGET,
+            "{:<28} {:>8.1} ms  (total {:.1} ms)",
+            name,
+            (now - self.last.get()).as_secs_f64() * 1000.0,
+            (now - self.start).as_secs_f64() * 1000.0,
+        );
+        self.last.set(now);
+    }
+}
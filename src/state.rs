@@ -0,0 +1,220 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Most files the recent list keeps besides the pinned ones
+const MAX_RECENT_FILES: usize = 10;
+// Most files whose cursor and bookmarks are kept
+const MAX_FILE_POSITIONS: usize = 200;
+
+/// A named set of open files and the workspace they belong to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub name: String,
+    pub workspace_root: Option<PathBuf>,
+    pub files: Vec<PathBuf>,
+    /// Zoom of each file's tab; files missing here open at 100%
+    #[serde(default)]
+    pub zoom_levels: HashMap<PathBuf, f64>,
+}
+
+/// A file of the recent list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentFile {
+    pub path: PathBuf,
+    /// When the file was last opened or saved, in seconds since the Unix epoch
+    pub last_used: u64,
+    /// Pinned files are listed first and survive clearing the list
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// Where a file was left, restored when it is opened again
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePosition {
+    /// Line and character column of the cursor, both from 0
+    pub cursor: (i32, i32),
+    /// Lines with a bookmark, from 0
+    #[serde(default)]
+    pub bookmarks: Vec<i32>,
+    /// When the file was left, in seconds since the Unix epoch
+    #[serde(default)]
+    pub last_used: u64,
+}
+
+/// How the editor's scrollbars are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollbarMode {
+    /// Permanent scrollbars beside the text
+    Always,
+    /// Thin scrollbars drawn over the text while scrolling
+    #[default]
+    Overlay,
+    /// No scrollbars; the text still scrolls
+    Hidden,
+}
+
+/// Editor state that survives restarts, stored as JSON in the XDG state directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistentState {
+    pub last_directory: Option<PathBuf>,
+    pub last_filter: Option<String>,
+    pub auto_reload: bool,
+    pub inline_diagnostics: bool,
+    pub save_on_focus_loss: bool,
+    pub window_width: i32,
+    pub window_height: i32,
+    pub window_maximized: bool,
+    pub scrollbars: ScrollbarMode,
+    /// Dim everything but the paragraph holding the cursor
+    pub focus_mode: bool,
+    /// Shade the gutter beside lines edited this session, darker for more recent edits
+    pub edit_heat_map: bool,
+    /// Show comparisons as the old and new text side by side instead of one unified listing
+    pub side_by_side_diff: bool,
+    /// Mark spaces, tabs and line ends in the text
+    pub show_whitespace: bool,
+    /// Saved sessions, most recently used first
+    pub sessions: Vec<Session>,
+    pub prompt_for_session: bool,
+    /// Starred files; unlike the recent list these are never pruned
+    pub favorites: Vec<PathBuf>,
+    pub show_favorites: bool,
+    /// Files opened or saved lately, most recent first
+    pub recent_files: Vec<RecentFile>,
+    /// Language picked in the status bar for a file, used instead of the detected one
+    /// whenever the file is opened
+    pub language_overrides: HashMap<PathBuf, String>,
+    /// Where each file was left when its tab closed or the editor quit
+    pub file_positions: HashMap<PathBuf, FilePosition>,
+}
+
+impl Default for PersistentState {
+    fn default() -> Self {
+        Self {
+            last_directory: None,
+            last_filter: None,
+            auto_reload: true,
+            inline_diagnostics: true,
+            save_on_focus_loss: false,
+            window_width: 1280,
+            window_height: 720,
+            window_maximized: false,
+            scrollbars: ScrollbarMode::default(),
+            focus_mode: false,
+            edit_heat_map: false,
+            side_by_side_diff: false,
+            show_whitespace: false,
+            sessions: Vec::new(),
+            prompt_for_session: false,
+            favorites: Vec::new(),
+            show_favorites: false,
+            recent_files: Vec::new(),
+            language_overrides: HashMap::new(),
+            file_positions: HashMap::new(),
+        }
+    }
+}
+
+impl PersistentState {
+    fn state_file() -> PathBuf {
+        glib::user_state_dir().join("rustedit").join("state.json")
+    }
+
+    pub fn load() -> Self {
+        let path = Self::state_file();
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable state file {}: {}", path.display(), e);
+                Self::default()
+            }),
+            // A missing state file just means this is the first launch
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Store `session`, replacing one with the same name, and move it to the front
+    pub fn remember_session(&mut self, session: Session) {
+        self.sessions.retain(|existing| existing.name != session.name);
+        self.sessions.insert(0, session);
+    }
+
+    /// Star `path`, or unstar it if it already is a favorite
+    pub fn toggle_favorite(&mut self, path: &Path) {
+        if self.favorites.iter().any(|favorite| favorite == path) {
+            self.favorites.retain(|favorite| favorite != path);
+        } else {
+            self.favorites.push(path.to_path_buf());
+        }
+    }
+
+    /// Move `path` to the front of the recent list, keeping whether it is pinned, and drop
+    /// the oldest unpinned files past the limit
+    pub fn add_recent_file(&mut self, path: &Path) {
+        let pinned = self.recent_files.iter().any(|recent| recent.path == path && recent.pinned);
+        self.recent_files.retain(|recent| recent.path != path);
+        self.recent_files.insert(0, RecentFile { path: path.to_path_buf(), last_used: now(), pinned });
+
+        let mut unpinned = 0;
+        self.recent_files.retain(|recent| {
+            if !recent.pinned {
+                unpinned += 1;
+            }
+            recent.pinned || unpinned <= MAX_RECENT_FILES
+        });
+    }
+
+    pub fn remove_recent_file(&mut self, path: &Path) {
+        self.recent_files.retain(|recent| recent.path != path);
+    }
+
+    /// Pin `path` in the recent list, or unpin it if it already is pinned
+    pub fn toggle_pinned(&mut self, path: &Path) {
+        if let Some(recent) = self.recent_files.iter_mut().find(|recent| recent.path == path) {
+            recent.pinned = !recent.pinned;
+        }
+    }
+
+    /// Forget the recent files that are not pinned
+    pub fn clear_recent_files(&mut self) {
+        self.recent_files.retain(|recent| recent.pinned);
+    }
+
+    /// The recent list as it is shown: pinned files first, each group most recent first
+    pub fn recent_files(&self) -> Vec<RecentFile> {
+        let mut recent_files = self.recent_files.clone();
+        recent_files.sort_by_key(|recent| !recent.pinned);
+        recent_files
+    }
+
+    /// Keep where `path` was left, dropping the file left longest ago past the limit
+    pub fn remember_file_position(&mut self, path: &Path, position: FilePosition) {
+        self.file_positions.insert(path.to_path_buf(), FilePosition { last_used: now(), ..position });
+        if self.file_positions.len() > MAX_FILE_POSITIONS {
+            let oldest = self.file_positions.iter().min_by_key(|(_, position)| position.last_used).map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                self.file_positions.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::state_file();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
@@ -0,0 +1,454 @@
+/// Kinds of items the outline knows about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Struct,
+    Enum,
+    Trait,
+    Impl,
+    Module,
+    Constant,
+    TypeAlias,
+    Macro,
+    Class,
+    Interface,
+    Heading,
+}
+
+impl SymbolKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            SymbolKind::Function => "fn",
+            SymbolKind::Struct => "struct",
+            SymbolKind::Enum => "enum",
+            SymbolKind::Trait => "trait",
+            SymbolKind::Impl => "impl",
+            SymbolKind::Module => "mod",
+            SymbolKind::Constant => "const",
+            SymbolKind::TypeAlias => "type",
+            SymbolKind::Macro => "macro",
+            SymbolKind::Class => "class",
+            SymbolKind::Interface => "interface",
+            SymbolKind::Heading => "heading",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// 0-based line of the item
+    pub line: usize,
+    /// Column (in characters) where the item starts
+    pub column: usize,
+    /// Brace nesting depth, 0 for top-level items
+    pub depth: usize,
+}
+
+/// Extract the symbols of `text` in the language named `language`: Python classes and
+/// functions, JavaScript and TypeScript declarations and class methods, or Markdown
+/// headings. Anything else is scanned as Rust, which also finds C's structs and enums.
+pub fn extract_symbols_for(text: &str, language: Option<&str>) -> Vec<Symbol> {
+    match language {
+        Some("Python") => extract_python_symbols(text),
+        Some("JavaScript" | "TypeScript") => extract_script_symbols(text),
+        Some("Markdown") => extract_headings(text),
+        _ => extract_symbols(text),
+    }
+}
+
+/// Extract item definitions from Rust source with a line-based scan.
+/// This is not a parser, but it understands enough (comments, strings,
+/// visibility and qualifiers) to build an outline of ordinary code.
+pub fn extract_symbols(text: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let mut depth = 0usize;
+    let mut in_block_comment = false;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if !in_block_comment {
+            if let Some((kind, name)) = parse_item(trimmed) {
+                symbols.push(Symbol {
+                    name,
+                    kind,
+                    line: line_index,
+                    column: line.chars().count() - trimmed.chars().count(),
+                    depth,
+                });
+            }
+        }
+        depth = track_depth(line, depth, &mut in_block_comment);
+    }
+
+    symbols
+}
+
+fn parse_item(line: &str) -> Option<(SymbolKind, String)> {
+    if line.starts_with("//") || line.starts_with('#') {
+        return None;
+    }
+
+    let mut rest = strip_visibility(line);
+    // Qualifiers that may precede the item keyword; `const` only counts as one in `const fn`
+    while let Some(stripped) = ["async ", "unsafe ", "default ", "extern \"C\" ", "extern ", "const "]
+        .iter()
+        .filter(|qualifier| **qualifier != "const " || is_const_fn(rest))
+        .find_map(|qualifier| rest.strip_prefix(qualifier))
+    {
+        rest = stripped.trim_start();
+    }
+
+    let keywords = [
+        ("fn ", SymbolKind::Function),
+        ("struct ", SymbolKind::Struct),
+        ("union ", SymbolKind::Struct),
+        ("enum ", SymbolKind::Enum),
+        ("trait ", SymbolKind::Trait),
+        ("mod ", SymbolKind::Module),
+        ("const ", SymbolKind::Constant),
+        ("static ", SymbolKind::Constant),
+        ("type ", SymbolKind::TypeAlias),
+        ("macro_rules! ", SymbolKind::Macro),
+    ];
+    for (keyword, kind) in keywords {
+        if let Some(after) = rest.strip_prefix(keyword) {
+            let after = after.strip_prefix("mut ").unwrap_or(after);
+            return identifier(after).map(|name| (kind, name));
+        }
+    }
+
+    if rest.starts_with("impl ") || rest.starts_with("impl<") {
+        // Use the whole header, e.g. `impl Display for Foo`
+        let header = rest.split(['{', ';']).next().unwrap_or(rest);
+        let header = header.split(" where").next().unwrap_or(header).trim();
+        return Some((SymbolKind::Impl, header.to_string()));
+    }
+
+    None
+}
+
+fn is_const_fn(text: &str) -> bool {
+    text.strip_prefix("const ")
+        .map(|rest| rest.trim_start().starts_with("fn ") || rest.trim_start().starts_with("unsafe "))
+        .unwrap_or(false)
+}
+
+fn strip_visibility(line: &str) -> &str {
+    if let Some(rest) = line.strip_prefix("pub(") {
+        if let Some(close) = rest.find(')') {
+            return rest[close + 1..].trim_start();
+        }
+    }
+    line.strip_prefix("pub ").map(str::trim_start).unwrap_or(line)
+}
+
+fn identifier(text: &str) -> Option<String> {
+    let name: String = text
+        .trim_start()
+        .chars()
+        .take_while(|ch| ch.is_alphanumeric() || *ch == '_')
+        .collect();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+// Update the brace depth after `line`, skipping braces inside strings, chars and comments
+fn track_depth(line: &str, mut depth: usize, in_block_comment: &mut bool) -> usize {
+    let mut chars = line.chars().peekable();
+    let mut in_string = false;
+    while let Some(ch) = chars.next() {
+        if *in_block_comment {
+            if ch == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                *in_block_comment = false;
+            }
+            continue;
+        }
+        if in_string {
+            match ch {
+                '\\' => {
+                    chars.next();
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' => in_string = true,
+            '\'' => {
+                // Skip char literals like '{' but leave lifetimes alone
+                let mut lookahead = chars.clone();
+                let literal = match lookahead.next() {
+                    Some('\\') => lookahead.nth(1) == Some('\''),
+                    Some(_) => lookahead.next() == Some('\''),
+                    None => false,
+                };
+                if literal {
+                    chars = lookahead;
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => break,
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                *in_block_comment = true;
+            }
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    depth
+}
+
+// `def` and `class` lines; nesting follows the indentation. Lines inside triple-quoted
+// strings are skipped.
+fn extract_python_symbols(text: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    // Indentation of the definitions enclosing the current line
+    let mut enclosing: Vec<usize> = Vec::new();
+    let mut in_docstring = false;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let quotes = line.matches("\"\"\"").count() + line.matches("'''").count();
+        let was_in_docstring = in_docstring;
+        in_docstring ^= quotes % 2 == 1;
+        if was_in_docstring || trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let column = line.chars().count() - trimmed.chars().count();
+        while enclosing.last().is_some_and(|&indent| indent >= column) {
+            enclosing.pop();
+        }
+        let rest = trimmed.strip_prefix("async ").unwrap_or(trimmed);
+        let item = [("def ", SymbolKind::Function), ("class ", SymbolKind::Class)]
+            .into_iter()
+            .find_map(|(keyword, kind)| Some((kind, identifier(rest.strip_prefix(keyword)?)?)));
+        if let Some((kind, name)) = item {
+            symbols.push(Symbol { name, kind, line: line_index, column, depth: enclosing.len() });
+            enclosing.push(column);
+        }
+    }
+    symbols
+}
+
+// Declarations at the start of a line: functions, classes, interfaces, enums, types,
+// namespaces and arrow functions assigned to a name, and the methods of classes
+fn extract_script_symbols(text: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let mut depth = 0usize;
+    let mut in_block_comment = false;
+    // Brace depth of the bodies of the classes around the current line
+    let mut class_bodies: Vec<usize> = Vec::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        while class_bodies.last().is_some_and(|&body| body > depth) {
+            class_bodies.pop();
+        }
+        let in_class_body = class_bodies.last() == Some(&depth);
+        if !in_block_comment && !trimmed.starts_with("//") && !trimmed.starts_with('*') {
+            let item = if in_class_body { parse_method(trimmed) } else { parse_declaration(trimmed) };
+            if let Some((kind, name)) = item {
+                if kind == SymbolKind::Class {
+                    class_bodies.push(depth + 1);
+                }
+                symbols.push(Symbol {
+                    name,
+                    kind,
+                    line: line_index,
+                    column: line.chars().count() - trimmed.chars().count(),
+                    depth,
+                });
+            }
+        }
+        depth = track_depth(line, depth, &mut in_block_comment);
+    }
+    symbols
+}
+
+fn parse_declaration(line: &str) -> Option<(SymbolKind, String)> {
+    let mut rest = line;
+    while let Some(stripped) = ["export ", "default ", "declare ", "abstract ", "async "]
+        .iter()
+        .find_map(|qualifier| rest.strip_prefix(qualifier))
+    {
+        rest = stripped.trim_start();
+    }
+
+    let keywords = [
+        ("function* ", SymbolKind::Function),
+        ("function ", SymbolKind::Function),
+        ("class ", SymbolKind::Class),
+        ("interface ", SymbolKind::Interface),
+        ("enum ", SymbolKind::Enum),
+        ("type ", SymbolKind::TypeAlias),
+        ("namespace ", SymbolKind::Module),
+    ];
+    for (keyword, kind) in keywords {
+        if let Some(after) = rest.strip_prefix(keyword) {
+            return identifier(after).map(|name| (kind, name));
+        }
+    }
+
+    // `const name = (...) => ...` and `const name = function ...`
+    let after = ["const ", "let ", "var "].iter().find_map(|keyword| rest.strip_prefix(keyword))?;
+    let name = identifier(after)?;
+    let (_, value) = after.split_once('=')?;
+    let value = value.trim_start();
+    let value = value.strip_prefix("async").map(str::trim_start).unwrap_or(value);
+    (value.starts_with("function") || value.contains("=>")).then_some((SymbolKind::Function, name))
+}
+
+// A method header in a class body, such as `static async load(path) {`
+fn parse_method(line: &str) -> Option<(SymbolKind, String)> {
+    let mut rest = line;
+    while let Some(stripped) = ["public ", "private ", "protected ", "static ", "readonly ", "async ", "get ", "set ", "*"]
+        .iter()
+        .find_map(|qualifier| rest.strip_prefix(qualifier))
+    {
+        rest = stripped.trim_start();
+    }
+    let name = identifier(rest)?;
+    let after = rest[name.len()..].trim_start();
+    let control_flow = ["if", "for", "while", "switch", "catch", "return", "function"].contains(&name.as_str());
+    (!control_flow && (after.starts_with('(') || after.starts_with('<')) && line.trim_end().ends_with('{'))
+        .then_some((SymbolKind::Function, name))
+}
+
+// ATX headings (`#` to `######`) outside fenced code blocks, nested by level
+fn extract_headings(text: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    let mut in_fence = false;
+    for (line_index, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let level = trimmed.len() - trimmed.trim_start_matches('#').len();
+        let title = trimmed[level..].trim().trim_end_matches('#').trim_end();
+        if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') && !title.is_empty() {
+            symbols.push(Symbol {
+                name: title.to_string(),
+                kind: SymbolKind::Heading,
+                line: line_index,
+                column: line.chars().count() - trimmed.chars().count(),
+                depth: level - 1,
+            });
+        }
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SymbolKind::*;
+
+    fn outline(text: &str, language: &str) -> Vec<(SymbolKind, String, usize, usize)> {
+        extract_symbols_for(text, Some(language))
+            .into_iter()
+            .map(|symbol| (symbol.kind, symbol.name, symbol.line, symbol.depth))
+            .collect()
+    }
+
+    fn item(kind: SymbolKind, name: &str, line: usize, depth: usize) -> (SymbolKind, String, usize, usize) {
+        (kind, name.to_string(), line, depth)
+    }
+
+    #[test]
+    fn outlines_rust_items() {
+        let text = concat!(
+            "// fn commented() {}\n",
+            "pub struct Point {\n",
+            "    x: i32,\n",
+            "}\n",
+            "\n",
+            "impl Display for Point {\n",
+            "    pub(crate) const fn origin() -> Self {\n",
+            "        let s = \"{\";\n",
+            "    }\n",
+            "}\n",
+            "macro_rules! point {}\n",
+        );
+        assert_eq!(outline(text, "Rust"), [
+            item(Struct, "Point", 1, 0),
+            item(Impl, "impl Display for Point", 5, 0),
+            item(Function, "origin", 6, 1),
+            item(Macro, "point", 10, 0),
+        ]);
+    }
+
+    #[test]
+    fn outlines_python_definitions() {
+        let text = concat!(
+            "class Parser:\n",
+            "    \"\"\"\n",
+            "    def not_a_method(self):\n",
+            "    \"\"\"\n",
+            "    async def parse(self):\n",
+            "        pass\n",
+            "\n",
+            "# def commented():\n",
+            "def main():\n",
+            "    pass\n",
+        );
+        assert_eq!(outline(text, "Python"), [
+            item(Class, "Parser", 0, 0),
+            item(Function, "parse", 4, 1),
+            item(Function, "main", 8, 0),
+        ]);
+    }
+
+    #[test]
+    fn outlines_script_declarations_and_methods() {
+        let text = concat!(
+            "export default class Store {\n",
+            "  static async load(path) {\n",
+            "    if (path) {\n",
+            "    }\n",
+            "  }\n",
+            "}\n",
+            "// function commented() {}\n",
+            "const handler = async (event) => {};\n",
+            "interface Options {}\n",
+        );
+        assert_eq!(outline(text, "JavaScript"), [
+            item(Class, "Store", 0, 0),
+            item(Function, "load", 1, 1),
+            item(Function, "handler", 7, 0),
+            item(Interface, "Options", 8, 0),
+        ]);
+        assert_eq!(outline(text, "TypeScript"), outline(text, "JavaScript"));
+    }
+
+    #[test]
+    fn outlines_markdown_headings() {
+        let text = concat!(
+            "# Title\n",
+            "\n",
+            "```\n",
+            "# not a heading\n",
+            "```\n",
+            "## Usage ##\n",
+            "#hashtag\n",
+            "###### Deep\n",
+        );
+        assert_eq!(outline(text, "Markdown"), [
+            item(Heading, "Title", 0, 0),
+            item(Heading, "Usage", 5, 1),
+            item(Heading, "Deep", 7, 5),
+        ]);
+    }
+}
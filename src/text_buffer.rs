@@ -1,24 +1,68 @@
-use std::ops::Range;
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ops::{Range, RangeInclusive};
+use ropey::Rope;
 use unicode_segmentation::UnicodeSegmentation;
-use xi_unicode::LineBreakIterator;
+use crate::brackets::BracketPairs;
 
+/// A rectangular selection between two corners given as (line, column), with columns
+/// counted in graphemes. Every line of the block selects the same columns, as far as the
+/// line reaches; with both corners in one column it is a caret on each line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockSelection {
+    pub anchor: (usize, usize),
+    pub head: (usize, usize),
+}
+
+impl BlockSelection {
+    pub fn lines(&self) -> RangeInclusive<usize> {
+        self.anchor.0.min(self.head.0)..=self.anchor.0.max(self.head.0)
+    }
+
+    pub fn columns(&self) -> Range<usize> {
+        self.anchor.1.min(self.head.1)..self.anchor.1.max(self.head.1)
+    }
+}
+
+/// What Edit → Line Operations does to the lines of the selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOperation {
+    /// Sort by text, optionally ignoring case, or by the number each line starts with
+    Sort { descending: bool, ignore_case: bool, numeric: bool },
+    /// Keep only the first of lines that are the same
+    RemoveDuplicates,
+    Reverse,
+    /// Make one line of them, their text separated by single spaces
+    Join,
+}
+
+// Content lives in a rope, so edits and line lookups are O(log n) even for huge files.
+// All offsets in the public API are byte offsets, as they were with the String buffer.
 #[derive(Debug, Clone)]
 pub struct TextBuffer {
-    content: String,
-    line_breaks: Vec<usize>,
+    content: Rope,
+    flattened: OnceCell<String>,  // Contiguous copy handed out by `text()`, rebuilt after edits
+    bracket_pairs: OnceCell<BracketPairs>,  // Scanned on demand, like `flattened`
     cursor_position: usize,
     selection: Option<Range<usize>>,
+    // Replaces `selection` while a block is selected
+    block_selection: Option<BlockSelection>,
     preferred_column: Option<usize>,  // For maintaining cursor column during vertical movement
+    extra_word_chars: String,  // Count as word characters besides letters, digits and '_'
 }
 
 impl TextBuffer {
     pub fn new() -> Self {
         Self {
-            content: String::new(),
-            line_breaks: vec![0],
+            content: Rope::new(),
+            flattened: OnceCell::new(),
+            bracket_pairs: OnceCell::new(),
             cursor_position: 0,
             selection: None,
+            block_selection: None,
             preferred_column: None,
+            extra_word_chars: String::new(),
         }
     }
 
@@ -29,37 +73,117 @@ impl TextBuffer {
     }
 
     pub fn set_text(&mut self, text: &str) {
-        self.content = text.to_string();
-        self.update_line_breaks();
+        self.content = Rope::from_str(text);
+        self.content_changed();
         self.cursor_position = 0;
-        self.selection = None;
-        self.preferred_column = None;
+        self.drop_selection();
+    }
+
+    // Drop what was derived from the old content
+    fn content_changed(&mut self) {
+        self.flattened = OnceCell::new();
+        self.bracket_pairs = OnceCell::new();
     }
 
     pub fn text(&self) -> &str {
+        self.flattened.get_or_init(|| self.content.to_string())
+    }
+
+    /// The content itself, cheap to clone and keep, as undo does
+    pub fn rope(&self) -> &Rope {
         &self.content
     }
 
+    /// Whether the text is `text`, without flattening it
+    pub fn content_eq(&self, text: &str) -> bool {
+        self.len() == text.len() && self.content == text
+    }
+
+    fn len(&self) -> usize {
+        self.content.len_bytes()
+    }
+
+    pub fn char_count(&self) -> usize {
+        self.content.len_chars()
+    }
+
+    /// Character index of a byte offset, as GTK counts positions
+    pub fn char_index(&self, offset: usize) -> usize {
+        self.content.byte_to_char(offset.min(self.len()))
+    }
+
+    /// Byte offset of a character index
+    pub fn byte_offset(&self, char_index: usize) -> usize {
+        self.content.char_to_byte(char_index.min(self.char_count()))
+    }
+
+    /// Insert `text` at character `char_index`, following an edit the view made. A cursor
+    /// at or after it moves along; the selection is dropped.
+    pub fn insert_at_char(&mut self, char_index: usize, text: &str) {
+        let char_index = char_index.min(self.char_count());
+        let offset = self.content.char_to_byte(char_index);
+        self.content.insert(char_index, text);
+        self.content_changed();
+        if self.cursor_position >= offset {
+            self.cursor_position += text.len();
+        }
+        self.drop_selection();
+    }
+
+    /// Remove the characters in `range`, following an edit the view made
+    pub fn remove_chars(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.char_count());
+        let start = range.start.min(end);
+        let (start_offset, end_offset) = (self.content.char_to_byte(start), self.content.char_to_byte(end));
+        self.content.remove(start..end);
+        self.content_changed();
+        if self.cursor_position >= end_offset {
+            self.cursor_position -= end_offset - start_offset;
+        } else if self.cursor_position > start_offset {
+            self.cursor_position = start_offset;
+        }
+        self.drop_selection();
+    }
+
+    fn drop_selection(&mut self) {
+        self.selection = None;
+        self.block_selection = None;
+        self.preferred_column = None;
+    }
+
     pub fn insert(&mut self, text: &str) {
+        if self.block_selection.is_some() {
+            self.replace_block(text);
+            return;
+        }
         if let Some(range) = self.selection.take() {
+            self.cursor_position = range.start;
             self.delete_range(range);
         }
-        self.content.insert_str(self.cursor_position, text);
+        let char_index = self.content.byte_to_char(self.cursor_position);
+        self.content.insert(char_index, text);
+        self.content_changed();
         self.cursor_position += text.len();
-        self.update_line_breaks();
         self.preferred_column = None;
     }
 
     pub fn delete_backward(&mut self) {
+        if self.block_selection.is_some() {
+            self.delete_block(false);
+            return;
+        }
         if let Some(range) = self.selection.take() {
+            self.cursor_position = range.start;
             self.delete_range(range);
         } else if self.cursor_position > 0 {
-            let prev_char_boundary = self.content
+            // Graphemes never span lines except "\r\n", which ends the previous line
+            let line_start = self.content.line_to_byte(self.content.byte_to_line(self.cursor_position - 1));
+            let before = self.slice_to_string(line_start..self.cursor_position);
+            let prev_char_boundary = before
                 .grapheme_indices(true)
-                .take_while(|(i, _)| *i < self.cursor_position)
-                .last()
-                .map(|(i, _)| i)
-                .unwrap_or(0);
+                .next_back()
+                .map(|(i, _)| line_start + i)
+                .unwrap_or(line_start);
             self.delete_range(prev_char_boundary..self.cursor_position);
             self.cursor_position = prev_char_boundary;
         }
@@ -67,25 +191,120 @@ impl TextBuffer {
     }
 
     pub fn delete_forward(&mut self) {
+        if self.block_selection.is_some() {
+            self.delete_block(true);
+            return;
+        }
         if let Some(range) = self.selection.take() {
+            self.cursor_position = range.start;
             self.delete_range(range);
-        } else if self.cursor_position < self.content.len() {
-            let next_char_boundary = self.content
-                .grapheme_indices(true)
-                .find(|(i, _)| *i > self.cursor_position)
-                .map(|(i, _)| i)
-                .unwrap_or(self.content.len());
+        } else if self.cursor_position < self.len() {
+            let line_end = self.line_range(self.line_at_offset(self.cursor_position))
+                .map(|range| range.end)
+                .unwrap_or(self.len());
+            let after = self.slice_to_string(self.cursor_position..line_end);
+            let next_char_boundary = after
+                .graphemes(true)
+                .next()
+                .map(|grapheme| self.cursor_position + grapheme.len())
+                .unwrap_or(line_end);
             self.delete_range(self.cursor_position..next_char_boundary);
         }
         self.preferred_column = None;
     }
 
+    /// Ctrl+Backspace: delete the selection, or back to the start of the word before the
+    /// cursor without leaving its line; at the start of a line, the line break before it
+    pub fn delete_word_backward(&mut self) {
+        self.delete_word(false);
+    }
+
+    /// Ctrl+Delete: delete the selection, or up to the end of the word after the cursor
+    /// without leaving its line; at the end of a line, the line break after it
+    pub fn delete_word_forward(&mut self) {
+        self.delete_word(true);
+    }
+
+    fn delete_word(&mut self, forward: bool) {
+        if self.block_selection.is_some() {
+            self.delete_block(forward);
+            return;
+        }
+        let range = self.selection.take().unwrap_or_else(|| self.word_deletion_range(self.cursor_position, forward));
+        self.cursor_position = range.start;
+        self.delete_range(range);
+        self.preferred_column = None;
+    }
+
     pub fn move_cursor(&mut self, offset: isize, extend_selection: bool) {
         let new_position = if offset < 0 {
             self.cursor_position.saturating_sub(offset.unsigned_abs())
         } else {
             self.cursor_position.saturating_add(offset as usize)
-        }.min(self.content.len());
+        };
+        self.move_cursor_to(new_position, extend_selection);
+    }
+
+    /// Home: to the first character of the cursor's line that is not a space or tab, or to
+    /// the start of the line when the cursor is already there or the line has only blanks
+    pub fn move_cursor_home(&mut self, extend_selection: bool) {
+        let (line_start, content) = self.cursor_line();
+        let text_start = content.len() - content.trim_start_matches([' ', '\t']).len();
+        let first_non_blank = if text_start == content.len() { line_start } else { line_start + text_start };
+        let target = if self.cursor_position == first_non_blank { line_start } else { first_non_blank };
+        self.move_cursor_to(target, extend_selection);
+    }
+
+    /// End: to the end of the cursor's line, or to just after its last character that is not
+    /// a space or tab when the cursor is already there and the line has one
+    pub fn move_cursor_end(&mut self, extend_selection: bool) {
+        let (line_start, content) = self.cursor_line();
+        let line_end = line_start + content.len();
+        let text_end = content.trim_end_matches([' ', '\t']).len();
+        let target = if self.cursor_position == line_end && text_end > 0 { line_start + text_end } else { line_end };
+        self.move_cursor_to(target, extend_selection);
+    }
+
+    /// Ctrl+Left/Right: to the start of the word before the cursor or the end of the word
+    /// after it, crossing lines
+    pub fn move_cursor_by_word(&mut self, forward: bool, extend_selection: bool) {
+        let target = if forward {
+            self.next_word_end(self.cursor_position)
+        } else {
+            self.previous_word_start(self.cursor_position)
+        };
+        self.move_cursor_to(target, extend_selection);
+    }
+
+    /// Ctrl+Up/Down: to the start of the paragraph before or after the cursor, or of the one
+    /// it is in when going back from inside it
+    pub fn move_cursor_by_paragraph(&mut self, forward: bool, extend_selection: bool) {
+        let target = if forward {
+            self.next_paragraph_start(self.cursor_position)
+        } else {
+            self.previous_paragraph_start(self.cursor_position)
+        };
+        self.move_cursor_to(target, extend_selection);
+    }
+
+    /// Put the cursor at `offset` without touching the selection, as when the view moved it
+    pub fn set_cursor_position(&mut self, offset: usize) {
+        self.cursor_position = offset.min(self.len());
+        self.preferred_column = None;
+    }
+
+    // Start of the cursor's line and its text without the line break
+    fn cursor_line(&self) -> (usize, String) {
+        let line_range = self.line_range(self.line_at_offset(self.cursor_position)).unwrap_or(self.len()..self.len());
+        let content = self.slice_to_string(line_range.clone());
+        (line_range.start, content.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    // Move the cursor to `new_position`, growing or shrinking the selection from its anchor
+    // when extending, otherwise dropping it
+    fn move_cursor_to(&mut self, new_position: usize, extend_selection: bool) {
+        let new_position = new_position.min(self.len());
+        self.block_selection = None;
 
         if extend_selection {
             let current_selection = self.selection.clone();
@@ -113,27 +332,26 @@ impl TextBuffer {
 
         // Find target position
         let new_position = if let Some(line_range) = self.line_range(target_line) {
-            let line_text = &self.content[line_range.clone()];
-            let mut column = 0;
+            let line_text = self.slice_to_string(line_range.clone());
             let mut target_pos = line_range.start;
 
-            for (idx, _) in line_text.grapheme_indices(true) {
-                if column >= preferred_column {
+            for (column, (idx, grapheme)) in line_text.grapheme_indices(true).enumerate() {
+                if column >= preferred_column || grapheme.ends_with('\n') {
                     break;
                 }
-                target_pos = line_range.start + idx;
-                column += 1;
+                target_pos = line_range.start + idx + grapheme.len();
             }
             target_pos
         } else {
             if lines < 0 {
                 0
             } else {
-                self.content.len()
+                self.len()
             }
         };
 
         // Update selection if needed
+        self.block_selection = None;
         if extend_selection {
             let current_selection = self.selection.clone();
             self.selection = Some(match current_selection {
@@ -148,21 +366,14 @@ impl TextBuffer {
     }
 
     fn delete_range(&mut self, range: Range<usize>) {
-        self.content.drain(range.clone());
-        self.update_line_breaks();
+        let start = self.content.byte_to_char(range.start);
+        let end = self.content.byte_to_char(range.end);
+        self.content.remove(start..end);
+        self.content_changed();
     }
 
-    fn update_line_breaks(&mut self) {
-        self.line_breaks = vec![0];
-        let mut iter = LineBreakIterator::new(&self.content);
-        while let Some((idx, _)) = iter.next() {
-            if idx > 0 {
-                self.line_breaks.push(idx);
-            }
-        }
-        if !self.content.is_empty() && *self.line_breaks.last().unwrap() != self.content.len() {
-            self.line_breaks.push(self.content.len());
-        }
+    fn slice_to_string(&self, range: Range<usize>) -> String {
+        self.content.byte_slice(range).to_string()
     }
 
     pub fn cursor_position(&self) -> usize {
@@ -174,63 +385,951 @@ impl TextBuffer {
     }
 
     pub fn line_count(&self) -> usize {
-        self.line_breaks.len()
+        self.content.len_lines()
     }
 
+    // Byte range of a line, including its line ending
     pub fn line_range(&self, line_index: usize) -> Option<Range<usize>> {
-        if line_index >= self.line_breaks.len() {
+        if line_index >= self.line_count() {
             return None;
         }
-        let start = self.line_breaks[line_index];
-        let end = self.line_breaks.get(line_index + 1).copied().unwrap_or(self.content.len());
+        let start = self.content.line_to_byte(line_index);
+        let end = self.content.line_to_byte(line_index + 1);
         Some(start..end)
     }
 
-    pub fn line_at_offset(&self, offset: usize) -> usize {
-        match self.line_breaks.binary_search(&offset) {
-            Ok(idx) => idx,
-            Err(idx) => idx.saturating_sub(1),
+    /// Text of a line without its line ending
+    pub fn line_content(&self, line_index: usize) -> String {
+        match self.line_range(line_index) {
+            Some(range) => self.slice_to_string(range.start..self.line_content_end(line_index)),
+            None => String::new(),
         }
     }
 
+    pub fn line_at_offset(&self, offset: usize) -> usize {
+        self.content.byte_to_line(offset.min(self.len()))
+    }
+
     pub fn column_at_offset(&self, offset: usize) -> usize {
-        let line_start = self.line_breaks[self.line_at_offset(offset)];
-        self.content[line_start..offset].graphemes(true).count()
+        let offset = offset.min(self.len());
+        let line_start = self.content.line_to_byte(self.line_at_offset(offset));
+        self.slice_to_string(line_start..offset).graphemes(true).count()
     }
 
     pub fn get_word_boundary_at_offset(&self, offset: usize) -> Range<usize> {
-        let mut start = offset;
-        let mut end = offset;
+        // Words never contain line breaks, so only the offset's line needs scanning
+        let Some(line_range) = self.line_range(self.line_at_offset(offset)) else {
+            return offset..offset;
+        };
+        let line_text = self.slice_to_string(line_range.clone());
+        let relative = offset - line_range.start;
+        let mut start = relative;
+        let mut end = relative;
 
         // Find word start
-        for (idx, _) in self.content[..offset].grapheme_indices(true).rev() {
-            if !self.is_word_char(self.content[idx..].chars().next().unwrap()) {
+        for (idx, grapheme) in line_text[..relative].grapheme_indices(true).rev() {
+            if !grapheme.chars().next().is_some_and(|c| self.is_word_char(c)) {
                 break;
             }
             start = idx;
         }
 
         // Find word end
-        for (idx, _) in self.content[offset..].grapheme_indices(true) {
-            let abs_idx = offset + idx;
-            if !self.is_word_char(self.content[abs_idx..].chars().next().unwrap()) {
+        for (idx, grapheme) in line_text[relative..].grapheme_indices(true) {
+            if !grapheme.chars().next().is_some_and(|c| self.is_word_char(c)) {
                 break;
             }
-            end = abs_idx + 1;
+            end = relative + idx + grapheme.len();
         }
 
+        line_range.start + start..line_range.start + end
+    }
+
+    /// Byte offset of the bracket paired with the one at `offset`: `Some(None)` when it has
+    /// no partner, and `None` when there is no bracket at `offset` outside strings and comments
+    pub fn matching_bracket(&self, offset: usize) -> Option<Option<usize>> {
+        let partner = self.bracket_pairs().partner(self.char_position(offset))?;
+        Some(partner.map(|position| self.offset_of_char_position(position)))
+    }
+
+    fn bracket_pairs(&self) -> &BracketPairs {
+        self.bracket_pairs.get_or_init(|| BracketPairs::new(self.text()))
+    }
+
+    // Line and character column of a byte offset, as brackets are located
+    fn char_position(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.len());
+        let line = self.line_at_offset(offset);
+        let column = self.content.byte_to_char(offset) - self.content.line_to_char(line);
+        (line, column)
+    }
+
+    fn offset_of_char_position(&self, (line, column): (usize, usize)) -> usize {
+        self.content.char_to_byte(self.content.line_to_char(line) + column)
+    }
+
+    /// Ctrl+L: the whole lines `selection` touches, their line breaks included. Once it
+    /// covers whole lines, its end is at the start of the next line, so that line is added.
+    pub fn line_selection(&self, selection: Range<usize>) -> Range<usize> {
+        let start = self.content.line_to_byte(self.line_at_offset(selection.start));
+        let end = self.line_range(self.line_at_offset(selection.end)).map_or(self.len(), |range| range.end);
         start..end
     }
 
+    /// The paragraph around `offset`, with the line break after it, or `None` on a blank line
+    pub fn paragraph_range(&self, offset: usize) -> Option<Range<usize>> {
+        let line = self.line_at_offset(offset);
+        if self.is_blank_line(line) {
+            return None;
+        }
+        let mut first = line;
+        while first > 0 && !self.is_blank_line(first - 1) {
+            first -= 1;
+        }
+        let mut last = line;
+        while last + 1 < self.line_count() && !self.is_blank_line(last + 1) {
+            last += 1;
+        }
+        Some(self.content.line_to_byte(first)..self.line_range(last).map_or(self.len(), |range| range.end))
+    }
+
+    /// The next larger piece of text around `selection`: the word, a string, the text
+    /// between brackets, then with the brackets, the line's text, the whole line, the
+    /// paragraph and finally everything. Whichever of these is smallest wins, so brackets
+    /// on one line come before the line and ones spanning lines after it. Until there is a
+    /// syntax tree these are found in the text itself.
+    pub fn expand_selection(&self, selection: Range<usize>) -> Range<usize> {
+        let mut candidates = vec![self.get_word_boundary_at_offset(selection.start)];
+        candidates.extend(self.strings_around(selection.clone()));
+        let pairs = self.bracket_pairs().enclosing(self.char_position(selection.start), self.char_position(selection.end));
+        for (open, close) in pairs {
+            let (open, close) = (self.offset_of_char_position(open), self.offset_of_char_position(close));
+            candidates.push(open + 1..close);
+            candidates.push(open..close + 1);
+        }
+        let line = self.line_at_offset(selection.start);
+        let line_start = self.content.line_to_byte(line);
+        let content = self.slice_to_string(line_start..self.line_content_end(line));
+        let indent = content.len() - content.trim_start().len();
+        candidates.push(line_start + indent..line_start + content.trim_end().len());
+        candidates.push(self.line_selection(selection.start..selection.start));
+        candidates.extend(self.paragraph_range(selection.start));
+        candidates.push(0..self.len());
+
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.start <= selection.start && candidate.end >= selection.end && candidate.len() > selection.len())
+            .min_by_key(|candidate| candidate.len())
+            .unwrap_or(selection)
+    }
+
+    // Double-quoted strings on the line of `selection` that hold it, each with and without
+    // its quotes. Escaped quotes do not end a string.
+    fn strings_around(&self, selection: Range<usize>) -> Vec<Range<usize>> {
+        let line = self.line_at_offset(selection.start);
+        let line_start = self.content.line_to_byte(line);
+        let content = self.slice_to_string(line_start..self.line_content_end(line));
+        let mut strings = Vec::new();
+        let mut open = None;
+        let mut escaped = false;
+        for (index, c) in content.char_indices() {
+            match c {
+                '\\' if open.is_some() => {
+                    escaped = !escaped;
+                    continue;
+                }
+                '"' if !escaped => match open.take() {
+                    Some(start) => {
+                        let (start, end) = (line_start + start, line_start + index);
+                        if start < selection.start && end >= selection.end {
+                            strings.push(start + 1..end);
+                            strings.push(start..end + 1);
+                        }
+                    }
+                    None => open = Some(index),
+                },
+                _ => {}
+            }
+            escaped = false;
+        }
+        strings
+    }
+
+    /// Where the word at or after `offset` ends, crossing lines; the end of the text if no
+    /// word follows
+    pub fn next_word_end(&self, offset: usize) -> usize {
+        // Line breaks end words, so the lines can be scanned one at a time
+        let offset = offset.min(self.len());
+        let mut in_word = false;
+        for line in self.line_at_offset(offset)..self.line_count() {
+            let Some(range) = self.line_range(line) else {
+                break;
+            };
+            let start = range.start.max(offset);
+            for (idx, grapheme) in self.slice_to_string(start..range.end).grapheme_indices(true) {
+                let is_word = grapheme.chars().next().is_some_and(|c| self.is_word_char(c));
+                if in_word && !is_word {
+                    return start + idx;
+                }
+                in_word |= is_word;
+            }
+        }
+        self.len()
+    }
+
+    /// Where the word at or before `offset` starts, crossing lines; the start of the text if
+    /// no word comes before
+    pub fn previous_word_start(&self, offset: usize) -> usize {
+        let offset = offset.min(self.len());
+        for line in (0..=self.line_at_offset(offset)).rev() {
+            let line_start = self.content.line_to_byte(line);
+            let end = self.line_range(line).map_or(offset, |range| range.end.min(offset));
+            let mut word_start = None;
+            for (idx, grapheme) in self.slice_to_string(line_start..end).grapheme_indices(true).rev() {
+                let is_word = grapheme.chars().next().is_some_and(|c| self.is_word_char(c));
+                if is_word {
+                    word_start = Some(line_start + idx);
+                } else if word_start.is_some() {
+                    break;
+                }
+            }
+            // The line break before the line ends the word there
+            if let Some(start) = word_start {
+                return start;
+            }
+        }
+        0
+    }
+
+    /// What Ctrl+Backspace (or Ctrl+Delete when `forward`) removes at `offset`: back to the
+    /// previous word's start or on to the next word's end, but no further than the line's
+    /// edge. At the edge itself it is the line break, "\r\n" included.
+    pub fn word_deletion_range(&self, offset: usize, forward: bool) -> Range<usize> {
+        let offset = offset.min(self.len());
+        let line = self.line_at_offset(offset);
+        let line_start = self.content.line_to_byte(line);
+        let content_end = self.line_content_end(line);
+        if forward {
+            if offset >= content_end {
+                let next_line_start = self.line_range(line).map_or(self.len(), |range| range.end);
+                return offset..next_line_start;
+            }
+            offset..self.next_word_end(offset).min(content_end)
+        } else {
+            if offset == line_start {
+                let previous_content_end = if line > 0 { self.line_content_end(line - 1) } else { offset };
+                return previous_content_end..offset;
+            }
+            self.previous_word_start(offset).max(line_start)..offset
+        }
+    }
+
+    /// Where the first paragraph after `offset` starts; the end of the text if none follows.
+    /// Paragraphs are runs of lines with text, between blank lines.
+    pub fn next_paragraph_start(&self, offset: usize) -> usize {
+        let first_line = self.line_at_offset(offset) + 1;
+        (first_line..self.line_count())
+            .find(|&line| self.starts_paragraph(line))
+            .map_or(self.len(), |line| self.content.line_to_byte(line))
+    }
+
+    /// Where the last paragraph starting before `offset` starts; the start of the text if
+    /// none does
+    pub fn previous_paragraph_start(&self, offset: usize) -> usize {
+        let offset = offset.min(self.len());
+        let line = self.line_at_offset(offset);
+        (0..=line)
+            .rev()
+            .map(|line| (line, self.content.line_to_byte(line)))
+            .find(|&(line, start)| start < offset && self.starts_paragraph(line))
+            .map_or(0, |(_, start)| start)
+    }
+
+    // A line with text after a blank line or at the top
+    fn starts_paragraph(&self, line: usize) -> bool {
+        !self.is_blank_line(line) && (line == 0 || self.is_blank_line(line - 1))
+    }
+
+    fn is_blank_line(&self, line: usize) -> bool {
+        self.line_range(line).is_none_or(|range| self.slice_to_string(range).trim().is_empty())
+    }
+
+    /// Characters that count as part of a word besides letters, digits and '_', such as
+    /// `-` for CSS
+    pub fn set_extra_word_chars(&mut self, chars: &str) {
+        if self.extra_word_chars != chars {
+            self.extra_word_chars = chars.to_string();
+        }
+    }
+
+    pub fn extra_word_chars(&self) -> &str {
+        &self.extra_word_chars
+    }
+
     fn is_word_char(&self, c: char) -> bool {
-        c.is_alphanumeric() || c == '_'
+        c.is_alphanumeric() || c == '_' || self.extra_word_chars.contains(c)
     }
 
     pub fn set_selection(&mut self, range: Option<Range<usize>>) {
         self.selection = range;
+        self.block_selection = None;
+    }
+
+    /// Select a block instead of a range; the cursor goes to the block's head
+    pub fn set_block_selection(&mut self, block: Option<BlockSelection>) {
+        self.block_selection = block;
+        if let Some(block) = block {
+            self.selection = None;
+            self.cursor_position = self.offset_at_column(block.head.0, block.head.1);
+            self.preferred_column = None;
+        }
+    }
+
+    pub fn block_selection(&self) -> Option<BlockSelection> {
+        self.block_selection
+    }
+
+    /// Byte range the block covers on each of its lines, top to bottom. Lines that end
+    /// left of the block give an empty range at their end.
+    pub fn block_ranges(&self) -> Vec<Range<usize>> {
+        let Some(block) = self.block_selection else {
+            return Vec::new();
+        };
+        let columns = block.columns();
+        block.lines()
+            .filter(|&line| line < self.line_count())
+            .map(|line| self.offset_at_column(line, columns.start)..self.offset_at_column(line, columns.end))
+            .collect()
+    }
+
+    /// Text of the block, one line per line of the block
+    pub fn block_text(&self) -> String {
+        self.block_ranges()
+            .into_iter()
+            .map(|range| self.slice_to_string(range))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Replace the block on every line with `text`, padding lines that end left of it.
+    /// Text of several lines is pasted column-wise instead: its lines go on consecutive
+    /// lines from the top of the block, adding lines past the end if needed. Returns the
+    /// byte range that changed and the text that replaced it, to repeat the edit elsewhere.
+    pub fn replace_block(&mut self, text: &str) -> Option<(Range<usize>, String)> {
+        let block = self.block_selection?;
+        let columns = block.columns();
+        let first_line = *block.lines().start();
+        let pieces: Vec<&str> = if text.contains('\n') {
+            text.strip_suffix('\n')
+                .unwrap_or(text)
+                .split('\n')
+                .map(|line| line.strip_suffix('\r').unwrap_or(line))
+                .collect()
+        } else {
+            vec![text; block.lines().count()]
+        };
+
+        let edit = self.rewrite_lines(first_line, pieces.len(), |row, line| {
+            let (start, missing) = column_to_byte(line, columns.start);
+            let (end, _) = column_to_byte(line, columns.end);
+            let padding = if pieces[row].is_empty() { 0 } else { missing };
+            format!("{}{}{}{}", &line[..start], " ".repeat(padding), pieces[row], &line[end..])
+        });
+
+        if text.contains('\n') {
+            // A pasted block leaves the cursor at the end of its last line
+            let last_line = first_line + pieces.len() - 1;
+            self.block_selection = None;
+            self.cursor_position = self.offset_at_column(last_line, columns.start + pieces[pieces.len() - 1].graphemes(true).count());
+        } else {
+            let column = columns.start + text.graphemes(true).count();
+            self.set_block_selection(Some(BlockSelection {
+                anchor: (block.anchor.0, column),
+                head: (block.head.0, column),
+            }));
+        }
+        Some(edit)
+    }
+
+    /// Delete the block, or with a caret on each line, the grapheme before it (or after
+    /// it when `forward`). Returns the edit like `replace_block`.
+    pub fn delete_block(&mut self, forward: bool) -> Option<(Range<usize>, String)> {
+        let block = self.block_selection?;
+        let columns = block.columns();
+        if !columns.is_empty() {
+            return self.replace_block("");
+        }
+        let column = columns.start;
+        if !forward && column == 0 {
+            return None;
+        }
+        let deleted = if forward { column..column + 1 } else { column - 1..column };
+        let first_line = *block.lines().start();
+        let edit = self.rewrite_lines(first_line, block.lines().count(), |_, line| {
+            let (start, _) = column_to_byte(line, deleted.start);
+            let (end, _) = column_to_byte(line, deleted.end);
+            format!("{}{}", &line[..start], &line[end..])
+        });
+        self.set_block_selection(Some(BlockSelection {
+            anchor: (block.anchor.0, deleted.start),
+            head: (block.head.0, deleted.start),
+        }));
+        Some(edit)
+    }
+
+    // Byte offset of a grapheme column on a line, or of the line's end if it is shorter
+    fn offset_at_column(&self, line: usize, column: usize) -> usize {
+        let Some(range) = self.line_range(line) else {
+            return self.len();
+        };
+        let content = self.slice_to_string(range.start..self.line_content_end(line));
+        range.start + column_to_byte(&content, column).0
+    }
+
+    // End of a line's text, before its line ending
+    fn line_content_end(&self, line: usize) -> usize {
+        let Some(range) = self.line_range(line) else {
+            return self.len();
+        };
+        let text = self.slice_to_string(range.clone());
+        let ending = if text.ends_with("\r\n") {
+            2
+        } else if text.ends_with(['\n', '\r']) {
+            1
+        } else {
+            0
+        };
+        range.end - ending
+    }
+
+    // Rewrite the text of `rows` lines from `first_line`, keeping their line endings, with
+    // lines past the end added. Returns the replaced byte range and its new text.
+    fn rewrite_lines(&mut self, first_line: usize, rows: usize, mut rewrite: impl FnMut(usize, &str) -> String) -> (Range<usize>, String) {
+        let existing = rows.min(self.line_count().saturating_sub(first_line)).max(1);
+        let last_line = first_line + existing - 1;
+        let span = self.content.line_to_byte(first_line.min(self.line_count() - 1))..self.line_content_end(last_line);
+
+        let mut replacement = String::new();
+        for row in 0..rows {
+            let line = first_line + row;
+            if row > 0 {
+                if row < existing {
+                    replacement.push_str(&self.slice_to_string(self.line_content_end(line - 1)..self.content.line_to_byte(line)));
+                } else {
+                    replacement.push('\n');
+                }
+            }
+            let content = if row < existing {
+                self.slice_to_string(self.content.line_to_byte(line)..self.line_content_end(line))
+            } else {
+                String::new()
+            };
+            replacement.push_str(&rewrite(row, &content));
+        }
+
+        self.delete_range(span.clone());
+        let char_index = self.content.byte_to_char(span.start);
+        self.content.insert(char_index, &replacement);
+        self.content_changed();
+        (span, replacement)
+    }
+
+    /// Apply `operation` to the whole lines `selection` touches, or to every line when it
+    /// is empty. A selection ending at the start of a line leaves that line out, and so does
+    /// the empty line after a final line break; joining a single line joins the next one
+    /// to it. Returns the byte range to replace and its new text, or `None` when the lines
+    /// would stay as they are.
+    pub fn line_operation(&self, selection: Range<usize>, operation: LineOperation) -> Option<(Range<usize>, String)> {
+        let last_line = self.line_count() - 1;
+        let (first, mut last) = if selection.is_empty() {
+            (0, last_line)
+        } else {
+            let first = self.line_at_offset(selection.start);
+            let last = self.line_at_offset(selection.end);
+            if last > first && self.content.line_to_byte(last) == selection.end { (first, last - 1) } else { (first, last) }
+        };
+        let ends_with_break = last_line > 0 && self.line_content_end(last_line) == self.content.line_to_byte(last_line);
+        if last == last_line && last > first && ends_with_break {
+            last -= 1;
+        }
+        if operation == LineOperation::Join && first == last {
+            if (last + 1 == last_line && ends_with_break) || last == last_line {
+                return None;
+            }
+            last += 1;
+        }
+
+        let span = self.content.line_to_byte(first)..self.line_content_end(last);
+        let original = self.slice_to_string(span.clone());
+        let separator = if original.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut lines: Vec<&str> = original.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+        let replacement = match operation {
+            LineOperation::Sort { descending, ignore_case, numeric } => {
+                lines.sort_by(|a, b| {
+                    let mut ordering = Ordering::Equal;
+                    if numeric {
+                        ordering = match (leading_number(a), leading_number(b)) {
+                            (Some(a), Some(b)) => a.total_cmp(&b),
+                            // Lines without a number come first
+                            (a, b) => a.is_some().cmp(&b.is_some()),
+                        };
+                    }
+                    if ignore_case {
+                        ordering = ordering.then_with(|| a.to_lowercase().cmp(&b.to_lowercase()));
+                    }
+                    let ordering = ordering.then_with(|| a.cmp(b));
+                    if descending { ordering.reverse() } else { ordering }
+                });
+                lines.join(separator)
+            }
+            LineOperation::RemoveDuplicates => {
+                let mut seen = HashSet::new();
+                lines.retain(|line| seen.insert(*line));
+                lines.join(separator)
+            }
+            LineOperation::Reverse => {
+                lines.reverse();
+                lines.join(separator)
+            }
+            LineOperation::Join => {
+                // The first line keeps its indentation
+                let indent = &lines[0][..lines[0].len() - lines[0].trim_start().len()];
+                let pieces: Vec<&str> = lines.iter().map(|line| line.trim()).filter(|line| !line.is_empty()).collect();
+                format!("{}{}", indent, pieces.join(" "))
+            }
+        };
+        (replacement != original).then_some((span, replacement))
     }
 
     pub fn get_selection(&self) -> Option<Range<usize>> {
         self.selection.clone()
     }
-} 
\ No newline at end of file
+}
+
+// Byte offset of grapheme `column` in `line`, and how many columns short of it the line ends
+fn column_to_byte(line: &str, column: usize) -> (usize, usize) {
+    let mut count = 0;
+    for (index, _) in line.grapheme_indices(true) {
+        if count == column {
+            return (index, 0);
+        }
+        count += 1;
+    }
+    (line.len(), column - count)
+}
+
+// The number `line` starts with after its indentation, for sorting numerically
+fn leading_number(line: &str) -> Option<f64> {
+    let line = line.trim_start();
+    let end = line
+        .char_indices()
+        .take_while(|&(index, c)| c.is_ascii_digit() || c == '.' || (index == 0 && matches!(c, '-' | '+')))
+        .count();
+    // The longest prefix that is a number, so "1.5.2" sorts as 1.5
+    (1..=end).rev().find_map(|len| line[..len].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_at(text: &str, cursor: usize) -> TextBuffer {
+        let mut buffer = TextBuffer::from_str(text);
+        buffer.set_cursor_position(cursor);
+        buffer
+    }
+
+    #[test]
+    fn word_moves_keep_combining_marks_with_their_letter() {
+        // "café" with the accent as a combining mark, then "au"
+        let text = "cafe\u{301} au";
+        let mut buffer = buffer_at(text, 0);
+        buffer.move_cursor_by_word(true, false);
+        assert_eq!(buffer.cursor_position(), "cafe\u{301}".len());
+        buffer.move_cursor_by_word(true, false);
+        assert_eq!(buffer.cursor_position(), text.len());
+        buffer.move_cursor_by_word(false, false);
+        assert_eq!(buffer.cursor_position(), "cafe\u{301} ".len());
+        buffer.move_cursor_by_word(false, false);
+        assert_eq!(buffer.cursor_position(), 0);
+    }
+
+    #[test]
+    fn word_moves_pass_over_emoji_as_one_grapheme() {
+        // A family emoji joined with zero width joiners is not a word, so it is skipped whole
+        let family = "\u{1F469}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let text = format!("{} ünï {}", family, family);
+        let mut buffer = buffer_at(&text, 0);
+        buffer.move_cursor_by_word(true, false);
+        assert_eq!(buffer.cursor_position(), format!("{} ünï", family).len());
+        buffer.move_cursor_by_word(true, false);
+        assert_eq!(buffer.cursor_position(), text.len());
+        buffer.move_cursor_by_word(false, false);
+        assert_eq!(buffer.cursor_position(), format!("{} ", family).len());
+    }
+
+    #[test]
+    fn word_moves_extend_the_selection_from_its_anchor() {
+        let mut buffer = buffer_at("один два три", 0);
+        buffer.move_cursor_by_word(true, true);
+        buffer.move_cursor_by_word(true, true);
+        assert_eq!(buffer.selection(), Some(0.."один два".len()));
+        buffer.move_cursor_by_word(false, true);
+        assert_eq!(buffer.selection(), Some(0.."один ".len()));
+        buffer.move_cursor_by_word(true, false);
+        assert_eq!(buffer.selection(), None);
+    }
+
+    #[test]
+    fn word_moves_cross_lines() {
+        let text = "漢字\r\n\r\n  かな";
+        let mut buffer = buffer_at(text, "漢字".len());
+        buffer.move_cursor_by_word(true, false);
+        assert_eq!(buffer.cursor_position(), text.len());
+        buffer.move_cursor_by_word(false, false);
+        assert_eq!(buffer.cursor_position(), "漢字\r\n\r\n  ".len());
+        buffer.move_cursor_by_word(false, false);
+        assert_eq!(buffer.cursor_position(), 0);
+    }
+
+    #[test]
+    fn paragraph_moves_stop_at_the_first_line_of_each_paragraph() {
+        let text = "ä one\nä two\n\n \t\nb one\n\nc one";
+        let b = text.find("b one").unwrap();
+        let c = text.find("c one").unwrap();
+        let mut buffer = buffer_at(text, 3);
+        buffer.move_cursor_by_paragraph(true, false);
+        assert_eq!(buffer.cursor_position(), b);
+        buffer.move_cursor_by_paragraph(true, false);
+        assert_eq!(buffer.cursor_position(), c);
+        buffer.move_cursor_by_paragraph(true, false);
+        assert_eq!(buffer.cursor_position(), text.len());
+        buffer.move_cursor_by_paragraph(false, false);
+        assert_eq!(buffer.cursor_position(), c);
+        buffer.move_cursor_by_paragraph(false, false);
+        assert_eq!(buffer.cursor_position(), b);
+        buffer.move_cursor_by_paragraph(false, false);
+        assert_eq!(buffer.cursor_position(), 0);
+    }
+
+    #[test]
+    fn paragraph_move_back_from_inside_goes_to_its_start() {
+        let text = "a\n\nb one\nb two";
+        let mut buffer = buffer_at(text, text.len());
+        buffer.move_cursor_by_paragraph(false, false);
+        assert_eq!(buffer.cursor_position(), text.find("b one").unwrap());
+    }
+
+    #[test]
+    fn word_deletion_stops_at_the_line_start() {
+        let mut buffer = buffer_at("naïve\n  wörd", "naïve\n  wö".len());
+        buffer.delete_word_backward();
+        assert_eq!(buffer.text(), "naïve\n  rd");
+        buffer.delete_word_backward();
+        assert_eq!(buffer.text(), "naïve\nrd");
+        buffer.delete_word_backward();
+        assert_eq!(buffer.text(), "naïverd");
+        assert_eq!(buffer.cursor_position(), "naïve".len());
+    }
+
+    #[test]
+    fn word_deletion_removes_crlf_whole() {
+        let mut buffer = buffer_at("é\r\nx", "é".len());
+        buffer.delete_word_forward();
+        assert_eq!(buffer.text(), "éx");
+        let mut buffer = buffer_at("é\r\nx", "é\r\n".len());
+        buffer.delete_word_backward();
+        assert_eq!(buffer.text(), "éx");
+        assert_eq!(buffer.cursor_position(), "é".len());
+    }
+
+    #[test]
+    fn word_deletion_forward_keeps_graphemes_whole() {
+        let text = "e\u{301}e\u{301} \u{1F44D}\u{1F3FD} end";
+        let mut buffer = buffer_at(text, 0);
+        buffer.delete_word_forward();
+        assert_eq!(buffer.text(), " \u{1F44D}\u{1F3FD} end");
+        buffer.delete_word_forward();
+        assert_eq!(buffer.text(), "");
+    }
+
+    #[test]
+    fn line_selection_grows_a_line_at_a_time() {
+        let buffer = TextBuffer::from_str("one\ntwo\nthree");
+        assert_eq!(buffer.line_selection(5..5), 4..8);
+        assert_eq!(buffer.line_selection(4..8), 4..13);
+        assert_eq!(buffer.line_selection(4..13), 4..13);
+        assert_eq!(buffer.line_selection(1..6), 0..8);
+    }
+
+    #[test]
+    fn paragraph_range_covers_the_lines_between_blank_ones() {
+        let text = "a\n\nb one\nb two\n \nc";
+        let buffer = TextBuffer::from_str(text);
+        let b = text.find("b one").unwrap();
+        assert_eq!(buffer.paragraph_range(b + 7), Some(b..text.find(" \nc").unwrap()));
+        assert_eq!(buffer.paragraph_range(2), None);
+        assert_eq!(buffer.paragraph_range(text.len()), Some(text.len() - 1..text.len()));
+    }
+
+    #[test]
+    fn expand_selection_steps_out_from_the_word() {
+        let text = "fn main() {\n    call(\"hé llo\", x);\n}\n";
+        let buffer = TextBuffer::from_str(text);
+        let start = text.find("llo").unwrap() + 1;
+        let steps: Vec<&str> = std::iter::successors(Some(start..start), |selection| {
+            let expanded = buffer.expand_selection(selection.clone());
+            (expanded != *selection).then_some(expanded)
+        })
+        .skip(1)
+        .map(|selection| &text[selection])
+        .collect();
+        assert_eq!(
+            steps,
+            [
+                "llo",
+                "hé llo",
+                "\"hé llo\"",
+                "\"hé llo\", x",
+                "(\"hé llo\", x)",
+                "call(\"hé llo\", x);",
+                "    call(\"hé llo\", x);\n",
+                "\n    call(\"hé llo\", x);\n",
+                "{\n    call(\"hé llo\", x);\n}",
+                text,
+            ]
+        );
+    }
+
+    #[test]
+    fn word_deletion_takes_the_selection_instead() {
+        let mut buffer = buffer_at("alpha beta", 0);
+        buffer.set_selection(Some(2..8));
+        buffer.delete_word_backward();
+        assert_eq!(buffer.text(), "alta");
+        assert_eq!(buffer.cursor_position(), 2);
+    }
+
+    fn apply(text: &str, selection: Range<usize>, operation: LineOperation) -> String {
+        let buffer = TextBuffer::from_str(text);
+        let mut text = text.to_string();
+        if let Some((range, replacement)) = buffer.line_operation(selection, operation) {
+            text.replace_range(range, &replacement);
+        }
+        text
+    }
+
+    const SORT: LineOperation = LineOperation::Sort { descending: false, ignore_case: false, numeric: false };
+
+    #[test]
+    fn sorts_every_line_without_a_selection() {
+        assert_eq!(apply("pear\nApple\nbanana\n", 0..0, SORT), "Apple\nbanana\npear\n");
+        let descending = LineOperation::Sort { descending: true, ignore_case: true, numeric: false };
+        assert_eq!(apply("pear\nApple\nbanana", 0..0, descending), "pear\nbanana\nApple");
+    }
+
+    #[test]
+    fn sorts_numbers_by_value() {
+        let numeric = LineOperation::Sort { descending: false, ignore_case: false, numeric: true };
+        assert_eq!(apply("10 ten\n9 nine\n-1.5 less\nnone\n  2 two\n", 0..0, numeric), "none\n-1.5 less\n  2 two\n9 nine\n10 ten\n");
+        assert_eq!(apply("10\n9\n", 0..0, SORT), "10\n9\n");
+    }
+
+    #[test]
+    fn works_on_the_lines_the_selection_touches() {
+        let text = "c\nb\na\nz\n";
+        // From inside "b" to the start of "z", which is left out
+        assert_eq!(apply(text, 2..6, SORT), "c\na\nb\nz\n");
+        assert_eq!(apply(text, 2..6, LineOperation::Reverse), "c\na\nb\nz\n");
+        assert_eq!(apply(text, 0..3, LineOperation::Reverse), "b\nc\na\nz\n");
+        // One line on its own stays as it is
+        assert_eq!(TextBuffer::from_str(text).line_operation(0..1, SORT), None);
+    }
+
+    #[test]
+    fn removes_duplicates_keeping_the_first() {
+        assert_eq!(apply("b\na\nb\r\na\nc", 0..0, LineOperation::RemoveDuplicates), "b\r\na\r\nc");
+        assert_eq!(TextBuffer::from_str("a\nb\n").line_operation(0..0, LineOperation::RemoveDuplicates), None);
+    }
+
+    #[test]
+    fn joins_lines_with_single_spaces() {
+        assert_eq!(apply("  one  \n\n   two\nthree\n", 0..0, LineOperation::Join), "  one two three\n");
+        // A single line takes the next one
+        assert_eq!(apply("a\n  b\nc", 0..1, LineOperation::Join), "a b\nc");
+        assert_eq!(TextBuffer::from_str("a\nb\n").line_operation(2..3, LineOperation::Join), None);
+    }
+
+    #[test]
+    fn crlf_line_breaks_count_once() {
+        let buffer = TextBuffer::from_str("one\r\ntwo\r\n\r\nthree");
+        assert_eq!(buffer.line_count(), 4);
+        assert_eq!(buffer.line_range(0), Some(0..5));
+        assert_eq!(buffer.line_range(2), Some(10..12));
+        assert_eq!(buffer.line_at_offset(4), 0);
+        assert_eq!(buffer.line_at_offset(5), 1);
+        assert_eq!(buffer.line_content(0), "one");
+        assert_eq!(buffer.line_content(3), "three");
+        assert_eq!(buffer.line_content(4), "");
+    }
+
+    #[test]
+    fn character_indices_map_to_byte_offsets() {
+        let mut buffer = TextBuffer::from_str("héllo wörld");
+        assert_eq!(buffer.char_count(), 11);
+        assert_eq!(buffer.byte_offset(2), 3);
+        assert_eq!(buffer.char_index(3), 2);
+        assert_eq!(buffer.byte_offset(99), "héllo wörld".len());
+
+        buffer.insert_at_char(7, "🙂");
+        assert!(buffer.content_eq("héllo w🙂örld"));
+        buffer.remove_chars(1..2);
+        assert!(buffer.content_eq("hllo w🙂örld"));
+        assert!(!buffer.content_eq("hllo w🙂örle"));
+        assert_eq!(buffer.char_index(buffer.text().find('ö').unwrap()), 7);
+    }
+
+    #[test]
+    fn lines_follow_inserts_and_deletes() {
+        let mut buffer = TextBuffer::from_str("one\ntwo\nthree\n");
+        buffer.insert_at_char(4, "new\nlines\n");
+        assert_eq!(buffer.line_count(), 6);
+        assert_eq!(buffer.line_range(3), Some(14..18));
+        assert_eq!(buffer.line_at_offset(15), 3);
+
+        buffer.remove_chars(4..14);
+        assert!(buffer.content_eq("one\ntwo\nthree\n"));
+        assert_eq!(buffer.line_count(), 4);
+        assert_eq!(buffer.line_range(1), Some(4..8));
+        assert_eq!(buffer.line_at_offset(9), 2);
+    }
+
+    #[test]
+    fn the_cursor_follows_edits_before_it() {
+        let mut buffer = buffer_at("ab\ncd", 4);
+        buffer.set_selection(Some(3..4));
+        buffer.insert_at_char(0, "é");
+        assert_eq!(buffer.cursor_position(), 6);
+        assert_eq!(buffer.selection(), None);
+        buffer.remove_chars(0..3);
+        assert_eq!(buffer.cursor_position(), 2);
+        // Removing the text around it leaves the cursor where it was
+        buffer.remove_chars(1..3);
+        assert_eq!(buffer.cursor_position(), 1);
+    }
+
+    #[test]
+    fn home_toggles_between_the_text_and_the_line_start() {
+        let mut buffer = buffer_at("fn main() {\n    let x = 1;\n}", 20);
+        buffer.move_cursor_home(false);
+        assert_eq!(buffer.cursor_position(), 16);
+        buffer.move_cursor_home(false);
+        assert_eq!(buffer.cursor_position(), 12);
+        buffer.move_cursor_home(false);
+        assert_eq!(buffer.cursor_position(), 16);
+    }
+
+    #[test]
+    fn end_toggles_between_the_line_end_and_the_text_end() {
+        let mut buffer = buffer_at("  foo   \nbar", 0);
+        buffer.move_cursor_end(false);
+        assert_eq!(buffer.cursor_position(), 8);
+        buffer.move_cursor_end(false);
+        assert_eq!(buffer.cursor_position(), 5);
+        buffer.move_cursor_end(false);
+        assert_eq!(buffer.cursor_position(), 8);
+    }
+
+    #[test]
+    fn home_and_end_extend_the_selection_from_its_anchor() {
+        // The selection runs from the anchor to the cursor, backwards here
+        let anchor_and_cursor = |buffer: &TextBuffer| buffer.selection().map(|range| (range.start, range.end));
+        let mut buffer = buffer_at("    let x = 1;", 8);
+        buffer.move_cursor_home(true);
+        assert_eq!(anchor_and_cursor(&buffer), Some((8, 4)));
+        buffer.move_cursor_home(true);
+        assert_eq!(anchor_and_cursor(&buffer), Some((8, 0)));
+        buffer.move_cursor_end(true);
+        assert_eq!(buffer.selection(), Some(8..14));
+        buffer.move_cursor_home(false);
+        assert_eq!(buffer.selection(), None);
+    }
+
+    #[test]
+    fn home_and_end_stop_before_crlf() {
+        let mut buffer = buffer_at("  ab  \r\n  cd", 3);
+        buffer.move_cursor_end(false);
+        assert_eq!(buffer.cursor_position(), 6);
+        buffer.move_cursor_end(false);
+        assert_eq!(buffer.cursor_position(), 4);
+        buffer.set_cursor_position(11);
+        buffer.move_cursor_home(false);
+        assert_eq!(buffer.cursor_position(), 10);
+        buffer.move_cursor_end(false);
+        assert_eq!(buffer.cursor_position(), 12);
+    }
+
+    #[test]
+    fn home_and_end_on_a_line_of_blanks_go_to_its_edges() {
+        let mut buffer = buffer_at("x\n \t \ny", 2);
+        buffer.move_cursor_home(false);
+        assert_eq!(buffer.cursor_position(), 2);
+        buffer.move_cursor_end(false);
+        assert_eq!(buffer.cursor_position(), 5);
+        buffer.move_cursor_end(false);
+        assert_eq!(buffer.cursor_position(), 5);
+        buffer.move_cursor_home(false);
+        assert_eq!(buffer.cursor_position(), 2);
+    }
+
+    fn block_at(text: &str, anchor: (usize, usize), head: (usize, usize)) -> TextBuffer {
+        let mut buffer = TextBuffer::from_str(text);
+        buffer.set_block_selection(Some(BlockSelection { anchor, head }));
+        buffer
+    }
+
+    #[test]
+    fn typing_in_a_block_pads_lines_that_end_left_of_it() {
+        let mut buffer = block_at("abcdef\nab\nabcdef", (0, 4), (2, 4));
+        buffer.replace_block("X");
+        assert_eq!(buffer.text(), "abcdXef\nab  X\nabcdXef");
+        assert_eq!(buffer.block_selection(), Some(BlockSelection { anchor: (0, 5), head: (2, 5) }));
+    }
+
+    #[test]
+    fn pasting_lines_column_wise_adds_lines_past_the_end() {
+        let mut buffer = block_at("ab\ncd", (1, 1), (1, 1));
+        buffer.replace_block("1\n2\n3\n");
+        assert_eq!(buffer.text(), "ab\nc1d\n 2\n 3");
+        assert_eq!(buffer.block_selection(), None);
+        assert_eq!(buffer.cursor_position(), buffer.text().len());
+    }
+
+    #[test]
+    fn block_text_leaves_out_crlf() {
+        let buffer = block_at("abcd\r\nefgh\r\nij", (0, 1), (2, 3));
+        assert_eq!(buffer.block_text(), "bc\nfg\nj");
+    }
+
+    #[test]
+    fn deleting_at_a_caret_block_in_the_first_column() {
+        let mut buffer = block_at("ab\ncd", (0, 0), (1, 0));
+        assert_eq!(buffer.delete_block(false), None);
+        assert_eq!(buffer.text(), "ab\ncd");
+        buffer.delete_block(true);
+        assert_eq!(buffer.text(), "b\nd");
+        assert_eq!(buffer.block_selection(), Some(BlockSelection { anchor: (0, 0), head: (1, 0) }));
+    }
+
+    #[test]
+    fn matching_brackets_after_multi_byte_text() {
+        let buffer = TextBuffer::from_str("é(ü)\nö{ '(' }\nü]");
+        assert_eq!(buffer.matching_bracket(2), Some(Some(5)));
+        assert_eq!(buffer.matching_bracket(5), Some(Some(2)));
+        assert_eq!(buffer.matching_bracket(9), Some(Some(15)));
+        // The one in the character literal is not a bracket
+        assert_eq!(buffer.matching_bracket(12), None);
+        assert_eq!(buffer.matching_bracket(19), Some(None));
+        assert_eq!(buffer.matching_bracket(0), None);
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::{bail, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The built-in dark colors
+pub const DEFAULT_THEME: &str = "Dark";
+/// The built-in light colors
+pub const LIGHT_THEME: &str = "Light";
+/// The colors of `theme.json`, from before themes had names
+pub const CUSTOM_THEME: &str = "Custom";
+
+// Style sheets written against the theme's colors, named `@editor_<field>`
+const WINDOW_STYLE: &str = include_str!("style.css");
+const PANEL_STYLE: &str = include_str!("panels.css");
+
+/// Colors of the editor, as CSS color values.
+///
+/// Every `.toml` or `.json` file in the `themes` directory of the config directory is a
+/// theme named after the file. Colors a theme file leaves out are taken from the dark
+/// theme, or from the light one if the file sets `base = "light"`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    /// Whether GTK should use its dark variant for widgets the style sheet leaves alone
+    pub dark: bool,
+
+    pub background: String,
+    pub foreground: String,
+    /// Tabs, popovers and the status bar
+    pub surface: String,
+    /// The tab being shown
+    pub surface_active: String,
+    pub border: String,
+    /// Secondary text
+    pub muted: String,
+    pub warning_background: String,
+    pub warning_foreground: String,
+
+    pub caret: String,
+    /// Selection colors; unset keeps the GTK theme's selection
+    pub selection_background: Option<String>,
+    pub selection_foreground: Option<String>,
+    /// Columns of a block selection, drawn over the text
+    pub block_selection: String,
+    /// Background of the line holding the cursor
+    pub current_line: String,
+    pub gutter_background: String,
+    pub gutter_foreground: String,
+    /// Gutter shading beside the latest edits of the edit heat map; older ones fade
+    pub edit_marker: String,
+    /// Text outside the current paragraph in focus mode
+    pub focus_dim: String,
+    /// Background of the bracket matching the one at the cursor
+    pub bracket_match: String,
+    /// Background of spaces and tabs ending a line, when they are shown
+    pub trailing_whitespace: String,
+    /// Marks drawn for spaces, tabs and line ends when whitespace is shown
+    pub whitespace: String,
+    /// Line down the text at the wrap column
+    pub right_margin: String,
+
+    pub keyword: String,
+    pub function: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub string: String,
+    pub number: String,
+    pub comment: String,
+    pub error: String,
+    pub parameter: String,
+    pub variable: String,
+    pub field: String,
+
+    pub diff_added: String,
+    pub diff_removed: String,
+    pub diff_added_inline: String,
+    pub diff_removed_inline: String,
+
+    pub diagnostic_error: String,
+    pub diagnostic_warning: String,
+    pub diagnostic_note: String,
+
+    /// Marks of search results in the overview ruler
+    pub search_match: String,
+    /// Marker beside bookmarked lines in the gutter
+    pub bookmark: String,
+    /// Gutter bars of lines added, changed and removed since the last commit
+    pub git_added: String,
+    pub git_modified: String,
+    pub git_deleted: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            dark: true,
+            background: "#1e1e1e".to_string(),
+            foreground: "#e0e0e0".to_string(),
+            surface: "#252525".to_string(),
+            surface_active: "#3a3a3a".to_string(),
+            border: "#303030".to_string(),
+            muted: "#b0b0b0".to_string(),
+            warning_background: "#4d3800".to_string(),
+            warning_foreground: "#f0e0b0".to_string(),
+            caret: "#ffffff".to_string(),
+            selection_background: None,
+            selection_foreground: None,
+            block_selection: "rgba(38, 79, 120, 0.7)".to_string(),
+            current_line: "#262626".to_string(),
+            gutter_background: "#1f1f1f".to_string(),
+            gutter_foreground: "#808080".to_string(),
+            edit_marker: "#c5862b".to_string(),
+            focus_dim: "rgba(224, 224, 224, 0.3)".to_string(),
+            bracket_match: "#3b514d".to_string(),
+            trailing_whitespace: "rgba(244, 71, 71, 0.2)".to_string(),
+            whitespace: "rgba(224, 224, 224, 0.25)".to_string(),
+            right_margin: "#333333".to_string(),
+            keyword: "#569CD6".to_string(),
+            function: "#DCDCAA".to_string(),
+            type_name: "#4EC9B0".to_string(),
+            string: "#CE9178".to_string(),
+            number: "#B5CEA8".to_string(),
+            comment: "#6A9955".to_string(),
+            error: "#F44747".to_string(),
+            parameter: "#9CDCFE".to_string(),
+            variable: "#9CDCFE".to_string(),
+            field: "#C586C0".to_string(),
+            diff_added: "#1e3a28".to_string(),
+            diff_removed: "#3f1d1d".to_string(),
+            diff_added_inline: "#2f6b3f".to_string(),
+            diff_removed_inline: "#7a2e2e".to_string(),
+            diagnostic_error: "#f48771".to_string(),
+            diagnostic_warning: "#cca700".to_string(),
+            diagnostic_note: "#75beff".to_string(),
+            search_match: "#d7ba7d".to_string(),
+            bookmark: "#4fa3e0".to_string(),
+            git_added: "#487e02".to_string(),
+            git_modified: "#1b81a8".to_string(),
+            git_deleted: "#f14c4c".to_string(),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            dark: false,
+            background: "#ffffff".to_string(),
+            foreground: "#1e1e1e".to_string(),
+            surface: "#f3f3f3".to_string(),
+            surface_active: "#dcdcdc".to_string(),
+            border: "#d4d4d4".to_string(),
+            muted: "#616161".to_string(),
+            warning_background: "#fff4ce".to_string(),
+            warning_foreground: "#5c4400".to_string(),
+            caret: "#000000".to_string(),
+            selection_background: None,
+            selection_foreground: None,
+            block_selection: "rgba(173, 214, 255, 0.7)".to_string(),
+            current_line: "#f2f2f2".to_string(),
+            gutter_background: "#f7f7f7".to_string(),
+            gutter_foreground: "#8a8a8a".to_string(),
+            edit_marker: "#e8a33d".to_string(),
+            focus_dim: "rgba(30, 30, 30, 0.3)".to_string(),
+            bracket_match: "#c9e8e0".to_string(),
+            trailing_whitespace: "rgba(229, 20, 0, 0.12)".to_string(),
+            whitespace: "rgba(30, 30, 30, 0.25)".to_string(),
+            right_margin: "#e0e0e0".to_string(),
+            keyword: "#0000FF".to_string(),
+            function: "#795E26".to_string(),
+            type_name: "#267F99".to_string(),
+            string: "#A31515".to_string(),
+            number: "#098658".to_string(),
+            comment: "#008000".to_string(),
+            error: "#E51400".to_string(),
+            parameter: "#001080".to_string(),
+            variable: "#001080".to_string(),
+            field: "#AF00DB".to_string(),
+            diff_added: "#e6ffec".to_string(),
+            diff_removed: "#ffebe9".to_string(),
+            diff_added_inline: "#abf2bc".to_string(),
+            diff_removed_inline: "#ffc0c0".to_string(),
+            diagnostic_error: "#c72e0f".to_string(),
+            diagnostic_warning: "#946c00".to_string(),
+            diagnostic_note: "#0069c2".to_string(),
+            search_match: "#b58900".to_string(),
+            bookmark: "#1a73e8".to_string(),
+            git_added: "#48985d".to_string(),
+            git_modified: "#2090d3".to_string(),
+            git_deleted: "#e51400".to_string(),
+        }
+    }
+
+    fn config_dir() -> PathBuf {
+        glib::user_config_dir().join("rustedit")
+    }
+
+    fn custom_theme_file() -> PathBuf {
+        Self::config_dir().join("theme.json")
+    }
+
+    // Theme files of the user by name, skipping any that would hide a built-in theme
+    fn user_themes() -> Vec<(String, PathBuf)> {
+        let Ok(entries) = fs::read_dir(Self::config_dir().join("themes")) else {
+            return Vec::new();
+        };
+        let mut themes: Vec<(String, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "toml" || extension == "json"))
+            .filter_map(|path| Some((path.file_stem()?.to_str()?.to_string(), path)))
+            .filter(|(name, _)| ![DEFAULT_THEME, LIGHT_THEME, CUSTOM_THEME].contains(&name.as_str()))
+            .collect();
+        themes.sort();
+        themes.dedup_by(|a, b| a.0 == b.0);
+        themes
+    }
+
+    /// Themes that can be chosen: the built-in ones, then the user's
+    pub fn names() -> Vec<String> {
+        let mut names = vec![DEFAULT_THEME.to_string(), LIGHT_THEME.to_string()];
+        names.extend(Self::user_themes().into_iter().map(|(name, _)| name));
+        if Self::custom_theme_file().exists() {
+            names.push(CUSTOM_THEME.to_string());
+        }
+        names
+    }
+
+    /// The theme used until one is chosen: the custom one if there is a `theme.json`
+    pub fn default_name() -> &'static str {
+        if Self::custom_theme_file().exists() {
+            CUSTOM_THEME
+        } else {
+            DEFAULT_THEME
+        }
+    }
+
+    /// The theme called `name`, falling back to the default one
+    pub fn load(name: &str) -> Self {
+        let path = match name {
+            LIGHT_THEME => return Self::light(),
+            CUSTOM_THEME => Self::custom_theme_file(),
+            _ => match Self::user_themes().into_iter().find(|(theme, _)| theme == name) {
+                Some((_, path)) => path,
+                // The built-in dark theme, or a theme file that was deleted
+                None => return Self::default(),
+            },
+        };
+        Self::from_file(&path).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable theme {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let value: serde_json::Value = if path.extension().is_some_and(|extension| extension == "toml") {
+            toml::from_str(&content)?
+        } else {
+            serde_json::from_str(&content)?
+        };
+        let serde_json::Value::Object(mut colors) = value else {
+            bail!("expected a table of colors");
+        };
+
+        // Lay the file's colors over those of its base theme
+        let base = match colors.remove("base").as_ref().and_then(|base| base.as_str()) {
+            Some("light") => Self::light(),
+            _ => Self::dark(),
+        };
+        let serde_json::Value::Object(mut merged) = serde_json::to_value(base)? else {
+            bail!("theme did not serialize to a table");
+        };
+        merged.extend(colors);
+        Ok(serde_json::from_value(serde_json::Value::Object(merged))?)
+    }
+
+    /// Style sheet of the window in this theme's colors, including the rules of the panels
+    /// built on demand once `with_panels` is set
+    pub fn css(&self, with_panels: bool) -> String {
+        let colors = [
+            ("background", &self.background),
+            ("foreground", &self.foreground),
+            ("surface", &self.surface),
+            ("surface_active", &self.surface_active),
+            ("border", &self.border),
+            ("muted", &self.muted),
+            ("warning_background", &self.warning_background),
+            ("warning_foreground", &self.warning_foreground),
+            ("gutter_foreground", &self.gutter_foreground),
+            ("error", &self.error),
+            ("keyword", &self.keyword),
+            ("function", &self.function),
+            ("type", &self.type_name),
+            ("variable", &self.variable),
+            ("field", &self.field),
+            ("diagnostic_error", &self.diagnostic_error),
+            ("diagnostic_warning", &self.diagnostic_warning),
+            ("diagnostic_note", &self.diagnostic_note),
+        ];
+        let mut css: String = colors
+            .iter()
+            .map(|(name, color)| format!("@define-color editor_{} {};\n", name, color))
+            .collect();
+        css.push_str(WINDOW_STYLE);
+        if with_panels {
+            css.push_str(PANEL_STYLE);
+        }
+
+        // Caret and selection last, so they win over the rules above
+        css.push_str(&format!("textview text {{ caret-color: {}; }}\n", self.caret));
+        let mut selection = String::new();
+        if let Some(background) = &self.selection_background {
+            selection.push_str(&format!(" background-color: {};", background));
+        }
+        if let Some(foreground) = &self.selection_foreground {
+            selection.push_str(&format!(" color: {};", foreground));
+        }
+        if !selection.is_empty() {
+            css.push_str(&format!("textview text selection {{{} }}\n", selection));
+        }
+        css
+    }
+}
@@ -0,0 +1,77 @@
+// Words ending in a period that do not end a sentence
+const ABBREVIATIONS: &[&str] = &["e.g.", "i.e.", "etc.", "vs.", "cf.", "Mr.", "Mrs.", "Ms.", "Dr.", "St."];
+
+/// What to put in the text instead of a typed character: `delete_before` characters
+/// before the cursor are removed and `insert` goes in their place
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replacement {
+    pub delete_before: usize,
+    pub insert: String,
+}
+
+/// The typographic form of `typed`, given the text before the cursor, or `None` to type
+/// it as it is:
+///
+/// - straight quotes become opening or closing curly quotes, and `'` after a letter an
+///   apostrophe
+/// - a second `-` turns the first into an em dash
+/// - the first letter of a sentence or paragraph is capitalized
+pub fn replacement(before: &str, typed: char) -> Option<Replacement> {
+    let previous = before.chars().next_back();
+    match typed {
+        '"' => Some(Replacement {
+            delete_before: 0,
+            insert: if opens_quote(previous) { "\u{201c}" } else { "\u{201d}" }.to_string(),
+        }),
+        '\'' => Some(Replacement {
+            delete_before: 0,
+            insert: if opens_quote(previous) { "\u{2018}" } else { "\u{2019}" }.to_string(),
+        }),
+        '-' if previous == Some('-') => Some(Replacement {
+            delete_before: 1,
+            insert: "\u{2014}".to_string(),
+        }),
+        _ if typed.is_lowercase() && starts_sentence(before) => Some(Replacement {
+            delete_before: 0,
+            insert: typed.to_uppercase().collect(),
+        }),
+        _ => None,
+    }
+}
+
+/// Whether the cursor is in Markdown code, where text is typed as it is: a fenced block,
+/// or a code span on the cursor's line
+pub fn in_markdown_code(before: &str) -> bool {
+    let fences = before.lines().filter(|line| line.trim_start().starts_with("```")).count();
+    let line = before.rsplit('\n').next().unwrap_or_default();
+    fences % 2 == 1 || line.matches('`').count() % 2 == 1
+}
+
+// A quote opens at the start of a line, after a space or an opening bracket or quote
+fn opens_quote(previous: Option<char>) -> bool {
+    match previous {
+        None => true,
+        Some(ch) => ch.is_whitespace() || "([{\u{2014}\u{2013}\u{201c}\u{2018}".contains(ch),
+    }
+}
+
+// The text ends with the end of a sentence and a space or line break, or with a blank line
+fn starts_sentence(before: &str) -> bool {
+    let text = before.trim_end_matches([' ', '\t']);
+    if text.trim().is_empty() || text.ends_with("\n\n") {
+        return true;
+    }
+    // Mid-word, or straight after the mark as in `3.5`
+    if text.len() == before.len() && !text.ends_with('\n') {
+        return false;
+    }
+    // Closing quotes and brackets may follow the sentence's last mark
+    let sentence = text
+        .trim_end_matches('\n')
+        .trim_end_matches(['"', '\'', ')', ']', '\u{201d}', '\u{2019}']);
+    if !sentence.ends_with(['.', '!', '?']) {
+        return false;
+    }
+    let last_word = sentence.rsplit(char::is_whitespace).next().unwrap_or_default();
+    !ABBREVIATIONS.contains(&last_word)
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+/// Words shorter than this are quicker typed than picked from a list
+const MIN_WORD_CHARS: usize = 3;
+
+/// The words of a text with how often each occurs. Kept up to date by removing the lines an
+/// edit is about to change and adding them back once it is made, so words never straddle
+/// the edges of what is added or removed.
+#[derive(Debug, Default)]
+pub struct WordIndex {
+    counts: HashMap<String, usize>,
+}
+
+// Identifiers: runs of letters, digits and '_' that do not start with a digit
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| word.chars().count() >= MIN_WORD_CHARS && !word.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+impl WordIndex {
+    pub fn new(text: &str) -> Self {
+        let mut index = Self::default();
+        index.add(text);
+        index
+    }
+
+    /// Count the words of `text`, whole lines that are now in the text
+    pub fn add(&mut self, text: &str) {
+        for word in words(text) {
+            *self.counts.entry(word.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Stop counting the words of `text`, whole lines that are no longer in the text
+    pub fn remove(&mut self, text: &str) {
+        for word in words(text) {
+            if let Some(count) = self.counts.get_mut(word) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(word);
+                }
+            }
+        }
+    }
+
+    /// Words that start with `prefix`, ignoring case, and are longer than it, with how
+    /// often each occurs, the most frequent first
+    pub fn complete(&self, prefix: &str) -> Vec<(&str, usize)> {
+        let prefix = prefix.to_lowercase();
+        let mut words: Vec<(&str, usize)> = self.counts
+            .iter()
+            .filter(|(word, _)| word.len() > prefix.len() && word.to_lowercase().starts_with(&prefix))
+            .map(|(word, &count)| (word.as_str(), count))
+            .collect();
+        words.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        words
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_by_frequency_ignoring_case() {
+        let index = WordIndex::new("let value = validate(value);\nlet Valid = 3;\n");
+        assert_eq!(index.complete("val"), [("value", 2), ("Valid", 1), ("validate", 1)]);
+        assert_eq!(index.complete("value"), []);
+    }
+
+    #[test]
+    fn skips_short_words_and_numbers() {
+        let index = WordIndex::new("a ab abc 123abc _ab");
+        assert_eq!(index.complete(""), [("_ab", 1), ("abc", 1)]);
+    }
+
+    #[test]
+    fn follows_lines_being_replaced() {
+        let mut index = WordIndex::new("first line\nsecond line\n");
+        // "second line" becomes "second lines"
+        index.remove("second line");
+        index.add("second lines");
+        assert_eq!(index.complete("lin"), [("line", 1), ("lines", 1)]);
+        index.remove("first line");
+        assert_eq!(index.complete("lin"), [("lines", 1)]);
+        assert_eq!(index.complete("fir"), []);
+    }
+}